@@ -0,0 +1,36 @@
+use active_call::media::encryption::{decrypt, parse_key};
+use anyhow::Result;
+use clap::Parser;
+
+/// Decrypt a recording written with `recording.encryption_key` enabled
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Encrypted recording file (.wav.enc)
+    #[arg(short, long)]
+    input: String,
+
+    /// Output file, defaults to the input without the .enc suffix
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Key in hex or base64, same as `recording.encryption_key`
+    #[arg(short, long)]
+    key: String,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let key = parse_key(&args.key)?;
+    let data = std::fs::read(&args.input)?;
+    let plain = decrypt(&data, &key)?;
+    let output = args.output.unwrap_or_else(|| {
+        args.input
+            .strip_suffix(".enc")
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("{}.dec", args.input))
+    });
+    std::fs::write(&output, plain)?;
+    println!("Decrypted recording written to {}", output);
+    Ok(())
+}
@@ -1,9 +1,9 @@
 use crate::{
-    call::{ActiveCallRef, sip::Invitation},
-    callrecord::{
-        CallRecordFormatter, CallRecordManagerBuilder, CallRecordSender, DefaultCallRecordFormatter,
-    },
-    config::Config,
+    call::{ActiveCallRef, rate_limit::CallRateLimiter, sip::Invitation},
+    callrecord::{CallRecord, CallRecordFormatter, CallRecordManagerBuilder, CallRecordSender},
+    config::{CallRecordConfig, Config, RELOADABLE_KEYS},
+    event_webhook::EventWebhook,
+    hooks::{CallHookContext, CallHooks, CallRecordSaveError},
     locator::RewriteTargetLocator,
     useragent::{
         RegisterOption,
@@ -17,7 +17,9 @@ use crate::{
 
 use crate::media::{cache::set_cache_dir, engine::StreamEngine};
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use chrono::{DateTime, Local};
+use futures::StreamExt;
 use humantime::parse_duration;
 use rsip::prelude::HeadersExt;
 use rsipstack::transaction::{
@@ -26,6 +28,7 @@ use rsipstack::transaction::{
 };
 use rsipstack::{dialog::dialog_layer::DialogLayer, transaction::endpoint::MessageInspector};
 use std::collections::HashSet;
+use std::future::Future;
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
@@ -37,7 +40,13 @@ use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
 pub struct AppStateInner {
+    /// The config the server started with, reloads don't change it
+    #[deprecated(note = "use `config()`, which follows config reloads")]
     pub config: Arc<Config>,
+    /// Use `config()`, replaced as a whole when the config file is reloaded
+    live_config: ArcSwap<Config>,
+    /// Backend config of the built-in call record manager, swapped on reload
+    callrecord_config: Option<Arc<ArcSwap<CallRecordConfig>>>,
     pub token: CancellationToken,
     pub stream_engine: Arc<StreamEngine>,
     pub callrecord_sender: Option<CallRecordSender>,
@@ -54,7 +63,13 @@ pub struct AppStateInner {
     pub active_calls: Arc<std::sync::Mutex<HashMap<String, ActiveCallRef>>>,
     pub total_calls: AtomicU64,
     pub total_failed_calls: AtomicU64,
+    /// New calls that had to wait for, or were refused, admission
+    pub total_throttled_calls: AtomicU64,
+    pub call_limiter: Option<CallRateLimiter>,
     pub uptime: DateTime<Local>,
+    pub hooks: CallHooks,
+    /// Set when `event_webhook` is configured
+    pub event_webhook: Option<Arc<EventWebhook>>,
 }
 
 pub type AppState = Arc<AppStateInner>;
@@ -71,11 +86,113 @@ pub struct AppStateBuilder {
     pub message_inspector: Option<Box<dyn MessageInspector>>,
     pub target_locator: Option<Box<dyn TargetLocator>>,
     pub transport_inspector: Option<Box<dyn TransportEventInspector>>,
+    pub hooks: CallHooks,
 }
 
 impl AppStateInner {
+    /// The running config. Hold on to the returned snapshot for the duration of
+    /// one operation so a reload can't change it halfway through.
+    pub fn config(&self) -> Arc<Config> {
+        self.live_config.load_full()
+    }
+
+    /// Apply a reloaded config file. Settings in `RELOADABLE_KEYS` take effect
+    /// for new calls; other changes are logged as needing a restart and ignored.
+    pub fn reload_config(&self, reloaded: Config) {
+        let current = self.config();
+        let changed = match current.changed_keys(&reloaded) {
+            Ok(changed) => changed,
+            Err(e) => {
+                warn!(
+                    "failed to compare reloaded config, keeping the running one: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let (reloadable, restart): (Vec<String>, Vec<String>) = changed
+            .into_iter()
+            .partition(|key| RELOADABLE_KEYS.contains(&key.as_str()));
+        if !restart.is_empty() {
+            warn!(keys = ?restart, "config changes need a restart to take effect");
+        }
+        if reloadable.is_empty() {
+            return;
+        }
+
+        let mut next = current.with_reloadable(&reloaded);
+        match (&self.callrecord_config, &next.callrecord) {
+            (Some(handle), Some(callrecord)) => handle.store(Arc::new(callrecord.clone())),
+            (None, None) => {}
+            // The call record manager is only started at boot
+            _ => {
+                warn!("enabling or disabling callrecord needs a restart to take effect");
+                next.callrecord = current.callrecord.clone();
+            }
+        }
+        if reloadable.iter().any(|key| key == "tts_concurrency") {
+            crate::synthesis::limiter::set_concurrency_limits(
+                &next.tts_concurrency.clone().unwrap_or_default(),
+            );
+        }
+        self.live_config.store(Arc::new(next));
+        info!(keys = ?reloadable, "config reloaded");
+    }
+
+    /// Reload the config whenever the file at `path` changes
+    fn start_config_watcher(self: &Arc<Self>, path: &str) {
+        let updates = match Config::watch(path) {
+            Ok(updates) => updates,
+            Err(e) => {
+                warn!(path, "failed to watch config file: {}", e);
+                return;
+            }
+        };
+        let state = Arc::downgrade(self);
+        let token = self.token.child_token();
+        crate::spawn(async move {
+            let mut updates = std::pin::pin!(updates);
+            loop {
+                let reloaded = select! {
+                    _ = token.cancelled() => break,
+                    reloaded = updates.next() => reloaded,
+                };
+                let (Some(reloaded), Some(state)) = (reloaded, state.upgrade()) else {
+                    break;
+                };
+                state.reload_config(reloaded);
+            }
+        });
+    }
+
+    /// Wait for the call rate limiter before setting up a new call. Errors when
+    /// the call would wait longer than `call_admission_timeout`.
+    pub async fn admit_call(&self) -> Result<Duration> {
+        let Some(limiter) = self.call_limiter.as_ref() else {
+            return Ok(Duration::ZERO);
+        };
+        let result = limiter.admit().await;
+        match &result {
+            Ok(waited) if waited.is_zero() => {}
+            Ok(waited) => {
+                self.total_throttled_calls
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                info!(
+                    waited_ms = waited.as_millis() as u64,
+                    "call admission throttled"
+                );
+            }
+            Err(e) => {
+                self.total_throttled_calls
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                warn!("call refused: {}", e);
+            }
+        }
+        result
+    }
+
     pub fn get_dump_events_file(&self, session_id: &String) -> String {
-        let recorder_root = self.config.recorder_path();
+        let recorder_root = self.config().recorder_path();
         let root = Path::new(&recorder_root);
         if !root.exists() {
             match std::fs::create_dir_all(root) {
@@ -97,7 +214,8 @@ impl AppStateInner {
     }
 
     pub fn get_recorder_file(&self, session_id: &String) -> String {
-        let recorder_root = self.config.recorder_path();
+        let config = self.config();
+        let recorder_root = config.recorder_path();
         let root = Path::new(&recorder_root);
         if !root.exists() {
             match std::fs::create_dir_all(root) {
@@ -113,7 +231,7 @@ impl AppStateInner {
                 }
             }
         }
-        let desired_ext = self.config.recorder_format().extension();
+        let desired_ext = config.recorder_format().extension();
         let mut filename = session_id.clone();
         if !filename
             .to_lowercase()
@@ -124,6 +242,47 @@ impl AppStateInner {
         root.join(filename).to_string_lossy().to_string()
     }
 
+    /// Periodically remove local recordings and CDRs older than `max_age`,
+    /// skipping files that belong to active calls.
+    fn start_retention_janitor(&self, max_age: Duration) {
+        let config = self.config();
+        let mut roots = vec![config.recorder_path()];
+        if let Some(CallRecordConfig::Local { root, .. }) = &config.callrecord {
+            roots.push(root.clone());
+        }
+        let active_calls = self.active_calls.clone();
+        let token = self.token.child_token();
+        crate::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            loop {
+                select! {
+                    _ = token.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let active: HashSet<String> =
+                    active_calls.lock().unwrap().keys().cloned().collect();
+                let roots = roots.clone();
+                let removed = tokio::task::spawn_blocking(move || {
+                    roots
+                        .iter()
+                        .map(|root| {
+                            crate::callrecord::retention::remove_expired_files(
+                                Path::new(root),
+                                max_age,
+                                &active,
+                            )
+                        })
+                        .sum::<usize>()
+                })
+                .await
+                .unwrap_or_default();
+                if removed > 0 {
+                    info!(removed, "retention janitor removed expired files");
+                }
+            }
+        });
+    }
+
     pub async fn serve(self: Arc<Self>) -> Result<()> {
         let incoming_txs = self.endpoint.incoming_transactions()?;
         let token = self.token.child_token();
@@ -159,7 +318,7 @@ impl AppStateInner {
         // Wait for registration to stop, if not stopped within 50 seconds,
         // force stop it.
         let timeout = self
-            .config
+            .config()
             .graceful_shutdown
             .map(|_| Duration::from_secs(50));
 
@@ -215,12 +374,13 @@ impl AppStateInner {
             let (state_sender, state_receiver) = dialog_layer.new_dialog_state_channel();
             match tx.original.method {
                 rsip::Method::Invite | rsip::Method::Ack => {
+                    let config = self.config();
                     let invitation_handler = match self.create_invitation_handler {
                         Some(ref create_invitation_handler) => {
-                            create_invitation_handler(self.config.handler.as_ref()).ok()
+                            create_invitation_handler(config.handler.as_ref()).ok()
                         }
                         _ => default_create_invite_handler(
-                            self.config.handler.as_ref(),
+                            config.handler.as_ref(),
                             Some(self.clone()),
                         ),
                     };
@@ -299,7 +459,7 @@ impl AppStateInner {
                     ));
 
                     let accept_timeout = self
-                        .config
+                        .config()
                         .accept_timeout
                         .as_ref()
                         .and_then(|t| parse_duration(t).ok())
@@ -320,8 +480,21 @@ impl AppStateInner {
                     let routing_state = self.routing_state.clone();
                     let dialog_for_reject = dialog.clone();
                     let guard_ref = guard.clone();
+                    let app_state = self.clone();
                     crate::spawn(async move {
                         let invite_loop = async {
+                            if let Err(e) = app_state.admit_call().await {
+                                info!(id = dialog_id_str, "rejecting invite: {}", e);
+                                dialog_for_reject
+                                    .reject(
+                                        Some(rsip::StatusCode::ServiceUnavailable),
+                                        Some(e.to_string()),
+                                    )
+                                    .ok();
+                                token.cancel();
+                                guard_ref.drop_async().await;
+                                return;
+                            }
                             match invitation_handler
                                 .on_invite(
                                     dialog_id_str.clone(),
@@ -384,7 +557,7 @@ impl AppStateInner {
 
     pub async fn start_registration(&self) -> Result<usize> {
         let mut count = 0;
-        if let Some(register_users) = &self.config.register_users {
+        if let Some(register_users) = &self.config().register_users {
             for option in register_users.iter() {
                 match self.register(option.clone()).await {
                     Ok(_) => {
@@ -399,6 +572,34 @@ impl AppStateInner {
         Ok(count)
     }
 
+    /// Registration state of the trunk serving `callee`. Returns `None` when no
+    /// enabled `register_users` entry matches the callee host.
+    pub fn trunk_registered_for_callee(&self, callee: &str) -> Option<bool> {
+        let callee_host = Self::sip_host(callee)?;
+        let config = self.config();
+        let option = config.register_users.as_ref()?.iter().find(|option| {
+            !option.disabled.unwrap_or(false)
+                && Self::sip_host(&option.server).as_deref() == Some(callee_host.as_str())
+        })?;
+        let registered = self
+            .alive_users
+            .read()
+            .map(|users| users.contains(&option.aor()))
+            .unwrap_or(false);
+        Some(registered)
+    }
+
+    fn sip_host(uri: &str) -> Option<String> {
+        let uri = if uri.starts_with("sip:") || uri.starts_with("sips:") {
+            uri.to_string()
+        } else {
+            format!("sip:{}", uri)
+        };
+        rsip::Uri::try_from(uri.as_str())
+            .ok()
+            .map(|uri| uri.host_with_port.host.to_string())
+    }
+
     pub fn find_credentials_for_callee(&self, callee: &str) -> Option<UserCredential> {
         let callee_uri = callee
             .strip_prefix("sip:")
@@ -424,7 +625,7 @@ impl AppStateInner {
         };
 
         // Look through registered users to find one matching this domain
-        if let Some(register_users) = &self.config.register_users {
+        if let Some(register_users) = &self.config().register_users {
             for option in register_users.iter() {
                 let mut server = option.server.clone();
                 if !server.starts_with("sip:") && !server.starts_with("sips:") {
@@ -482,7 +683,7 @@ impl AppStateInner {
         &self,
         callee_ip: &std::net::IpAddr,
     ) -> Option<crate::useragent::registration::UserCredential> {
-        if let Some(register_users) = &self.config.register_users {
+        if let Some(register_users) = &self.config().register_users {
             for option in register_users.iter() {
                 let mut server = option.server.clone();
                 if !server.starts_with("sip:") && !server.starts_with("sips:") {
@@ -632,6 +833,7 @@ impl AppStateBuilder {
             message_inspector: None,
             target_locator: None,
             transport_inspector: None,
+            hooks: CallHooks::default(),
         }
     }
 
@@ -650,6 +852,14 @@ impl AppStateBuilder {
         self
     }
 
+    /// Format call records with `formatter`, e.g. `CsvCallRecordFormatter`.
+    /// Without one, records are stored under the `callrecord` root of the
+    /// running config, which follows reloads
+    pub fn with_callrecord_formatter(mut self, formatter: Arc<dyn CallRecordFormatter>) -> Self {
+        self.callrecord_formatter = Some(formatter);
+        self
+    }
+
     pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
         self.cancel_token = Some(token);
         self
@@ -677,12 +887,66 @@ impl AppStateBuilder {
         self
     }
 
+    pub fn on_call_created<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CallHookContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_call_created = Some(CallHooks::wrap(hook));
+        self
+    }
+
+    pub fn on_answered<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CallHookContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_answered = Some(CallHooks::wrap(hook));
+        self
+    }
+
+    pub fn on_hangup<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CallHookContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_hangup = Some(CallHooks::wrap(hook));
+        self
+    }
+
+    /// Only fired by the built-in call record manager, not with a custom `callrecord_sender`
+    pub fn on_cdr_saved<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CallRecord) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_cdr_saved = Some(CallHooks::wrap(hook));
+        self
+    }
+
+    /// Fired when the built-in call record manager fails to save a record, e.g.
+    /// to alert on CDRs lost after the upload retries ran out
+    pub fn on_cdr_save_error<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(CallRecordSaveError) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_cdr_save_error = Some(CallHooks::wrap(hook));
+        self
+    }
+
     pub async fn build(self) -> Result<AppState> {
         let config: Arc<Config> = Arc::new(self.config.unwrap_or_default());
         let token = self
             .cancel_token
             .unwrap_or_else(|| CancellationToken::new());
         let _ = set_cache_dir(&config.media_cache_path);
+        if let Some(limits) = &config.tts_concurrency {
+            crate::synthesis::limiter::set_concurrency_limits(limits);
+        }
+        if let Some(http_client) = &config.http_client {
+            crate::net_tool::configure_http_client(http_client)?;
+        }
 
         let local_ip = if !config.addr.is_empty() {
             std::net::IpAddr::from_str(config.addr.as_str())?
@@ -734,28 +998,38 @@ impl AppStateBuilder {
 
         let stream_engine = self.stream_engine.unwrap_or_default();
 
-        let callrecord_formatter = if let Some(formatter) = self.callrecord_formatter {
-            formatter
-        } else {
-            let formatter = if let Some(ref callrecord) = config.callrecord {
-                DefaultCallRecordFormatter::new_with_config(callrecord)
-            } else {
-                DefaultCallRecordFormatter::default()
-            };
-            Arc::new(formatter)
-        };
+        // `cdr_saved` is only known to the built-in call record manager
+        let event_webhook = config.event_webhook.clone().map(|webhook| {
+            let report_cdr = self.callrecord_sender.is_none() && config.callrecord.is_some();
+            Arc::new(EventWebhook::new(webhook, report_cdr, token.child_token()))
+        });
+        if let Some(webhook) = &event_webhook {
+            match webhook.resume_pending().await {
+                Ok(0) => {}
+                Ok(count) => info!(count, "resuming pending event webhooks"),
+                Err(e) => warn!("failed to resume pending event webhooks: {}", e),
+            }
+        }
 
+        let mut callrecord_config = None;
         let callrecord_sender = if let Some(sender) = self.callrecord_sender {
             Some(sender)
         } else if let Some(ref callrecord) = config.callrecord {
-            let builder = CallRecordManagerBuilder::new()
+            let mut builder = CallRecordManagerBuilder::new()
                 .with_cancel_token(token.child_token())
                 .with_config(callrecord.clone())
                 .with_max_concurrent(32)
-                .with_formatter(callrecord_formatter.clone());
+                .with_on_saved(self.hooks.on_cdr_saved.clone())
+                .with_on_save_error(self.hooks.on_cdr_save_error.clone())
+                .with_event_webhook(event_webhook.clone())
+                .with_enrichment(config.cdr_enrichment.clone());
+            if let Some(formatter) = self.callrecord_formatter {
+                builder = builder.with_formatter(formatter);
+            }
 
             let mut callrecord_manager = builder.build();
             let sender = callrecord_manager.sender.clone();
+            callrecord_config = Some(callrecord_manager.config_handle());
             crate::spawn(async move {
                 callrecord_manager.serve().await;
             });
@@ -764,8 +1038,24 @@ impl AppStateBuilder {
             None
         };
 
+        let call_limiter = config
+            .max_calls_per_sec
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| {
+                let burst = config.call_burst.unwrap_or(rate.ceil() as u32);
+                let max_wait = config
+                    .call_admission_timeout
+                    .as_ref()
+                    .and_then(|t| parse_duration(t).ok())
+                    .unwrap_or_else(|| Duration::from_secs(5));
+                CallRateLimiter::new(rate, burst, max_wait)
+            });
+
+        #[allow(deprecated)]
         let app_state = Arc::new(AppStateInner {
-            config,
+            config: config.clone(),
+            live_config: ArcSwap::new(config),
+            callrecord_config,
             token,
             stream_engine,
             callrecord_sender,
@@ -781,9 +1071,20 @@ impl AppStateBuilder {
             active_calls: Arc::new(std::sync::Mutex::new(HashMap::new())),
             total_calls: AtomicU64::new(0),
             total_failed_calls: AtomicU64::new(0),
+            total_throttled_calls: AtomicU64::new(0),
+            call_limiter,
             uptime: Local::now(),
+            hooks: self.hooks,
+            event_webhook,
         });
 
+        if let Some(days) = app_state.config().local_retention_days {
+            app_state.start_retention_janitor(Duration::from_secs(days * 24 * 3600));
+        }
+        if let Some(path) = self.config_path {
+            app_state.start_config_watcher(&path);
+        }
+
         Ok(app_state)
     }
 }
@@ -1,14 +1,22 @@
-use super::Command;
+use super::{Command, DtmfMethod};
 use crate::{
-    CallOption, ReferOption,
+    AnswerSupervision, CallOption, ConsentMode, HoldAsrMode, PlaybackPolicy,
+    RecordingConsentOutcome, ReferOption,
     event::{EventReceiver, EventSender, SessionEvent},
     media::{
-        TrackId,
-        ambiance::AmbianceProcessor,
+        INTERNAL_SAMPLERATE, TrackId,
+        ambiance::{AmbianceProcessor, AmbianceSource},
+        asr_processor::AsrProcessor,
         engine::StreamEngine,
-        negotiate::strip_ipv6_candidates,
+        loudness::LoudnessProcessor,
+        negotiate::{
+            audio_payload_type, audio_payload_types, restrict_audio_codec, sdp_origin_version,
+            set_sdp_direction, set_sdp_origin_version, sip_sdp_filter, strip_ipv6_candidates,
+        },
         processor::SubscribeProcessor,
-        recorder::RecorderOption,
+        quality::{CodecAdaptation, QualityMonitor, QualityStats, QualitySummary},
+        recorder::{RecordedFile, RecorderOption},
+        resolve_internal_samplerate,
         stream::{MediaStream, MediaStreamBuilder},
         track::{
             Track, TrackConfig,
@@ -18,6 +26,7 @@ use crate::{
             tts::SynthesisHandle,
             websocket::{WebsocketBytesReceiver, WebsocketTrack},
         },
+        watermark::WatermarkProcessor,
     },
     synthesis::{SynthesisCommand, SynthesisOption},
     transcription::TranscriptionOption,
@@ -26,15 +35,25 @@ use crate::{
     app::AppState,
     call::{
         CommandReceiver, CommandSender,
-        sip::{DialogStateReceiverGuard, Invitation, InviteDialogStates},
+        sip::{
+            DialAttempt, DialogStateReceiverGuard, Invitation, InviteDialogStates, allows_update,
+        },
     },
-    callrecord::{CallRecord, CallRecordEvent, CallRecordEventType, CallRecordHangupReason},
+    callrecord::{
+        CallRecord, CallRecordEvent, CallRecordEventType, CallRecordHangupMessage,
+        CallRecordHangupReason,
+    },
+    config::Config,
+    hooks::CallHookContext,
     useragent::invitation::PendingDialog,
 };
 use anyhow::Result;
 use audio_codec::CodecType;
 use chrono::{DateTime, Utc};
-use rsipstack::dialog::{invitation::InviteOption, server_dialog::ServerInviteDialog};
+use futures::{FutureExt, StreamExt, stream::FuturesUnordered};
+use rsipstack::dialog::{
+    DialogId, dialog::Dialog, invitation::InviteOption, server_dialog::ServerInviteDialog,
+};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
 use tokio::{fs::File, select, sync::Mutex, sync::RwLock, time::sleep};
@@ -208,6 +227,286 @@ mod tests {
 
         Ok(())
     }
+
+    async fn setup_playback_policy_call(
+        policy: PlaybackPolicy,
+    ) -> Result<(Arc<ActiveCall>, mpsc::UnboundedReceiver<SynthesisCommand>)> {
+        let mut config = Config::default();
+        config.udp_port = 0;
+        config.media_cache_path = "/tmp/mediacache".to_string();
+        let app_state = AppStateBuilder::new()
+            .with_config(config)
+            .with_stream_engine(Arc::new(StreamEngine::default()))
+            .build()
+            .await?;
+
+        let active_call = Arc::new(ActiveCall::new(
+            ActiveCallType::Sip,
+            CancellationToken::new(),
+            "test-playback-policy".to_string(),
+            app_state.invitation.clone(),
+            app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+            None,
+        ));
+
+        let mut tts_opt = crate::synthesis::SynthesisOption::default();
+        tts_opt.provider = Some(crate::synthesis::SynthesisType::Aliyun);
+        let mut option = crate::CallOption::default();
+        option.tts = Some(tts_opt);
+        option.playback_policy = Some(policy);
+
+        // First playback is in progress
+        let (tx, rx) = mpsc::unbounded_channel();
+        {
+            let mut state = active_call.call_state.write().await;
+            state.option = Some(option);
+            state.tts_handle = Some(SynthesisHandle::new(tx, Some("play_1".to_string()), 111));
+            state.current_play_id = Some("play_1".to_string());
+            state.playing = true;
+        }
+        Ok((active_call, rx))
+    }
+
+    fn tts_command(text: &str, play_id: &str) -> Command {
+        Command::Tts {
+            text: text.to_string(),
+            speaker: None,
+            play_id: Some(play_id.to_string()),
+            auto_hangup: None,
+            streaming: None,
+            end_of_stream: None,
+            option: None,
+            wait_input_timeout: None,
+            base64: None,
+            cache_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_playback_policy_reject() -> Result<()> {
+        let (active_call, _rx) = setup_playback_policy_call(PlaybackPolicy::Reject).await?;
+        let mut events = active_call.event_sender.subscribe();
+
+        active_call
+            .dispatch(tts_command("second", "play_2"))
+            .await?;
+
+        let state = active_call.call_state.read().await;
+        assert_eq!(state.current_play_id.as_deref(), Some("play_1"));
+        assert_eq!(state.tts_handle.as_ref().map(|h| h.ssrc), Some(111));
+        drop(state);
+        match events.try_recv() {
+            Ok(SessionEvent::Error { sender, code, .. }) => {
+                assert_eq!(sender, "playback");
+                assert_eq!(code, Some(409));
+            }
+            other => panic!("expected playback error event, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_playback_policy_replace() -> Result<()> {
+        let (active_call, _rx) = setup_playback_policy_call(PlaybackPolicy::Replace).await?;
+
+        active_call
+            .dispatch(tts_command("second", "play_2"))
+            .await?;
+
+        let state = active_call.call_state.read().await;
+        assert_eq!(state.current_play_id.as_deref(), Some("play_2"));
+        assert_ne!(state.tts_handle.as_ref().map(|h| h.ssrc), Some(111));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_playback_policy_queue() -> Result<()> {
+        let (active_call, mut rx) = setup_playback_policy_call(PlaybackPolicy::Queue).await?;
+
+        active_call
+            .dispatch(tts_command("second", "play_2"))
+            .await?;
+        {
+            let state = active_call.call_state.read().await;
+            assert_eq!(state.current_play_id.as_deref(), Some("play_1"));
+            assert_eq!(state.playback_queue.len(), 1);
+        }
+        assert!(
+            rx.try_recv().is_err(),
+            "queued text must not reach the active stream"
+        );
+
+        // More text for the active playback is still streamed immediately
+        active_call
+            .dispatch(tts_command("first, continued", "play_1"))
+            .await?;
+        assert_eq!(rx.try_recv()?.text, "first, continued");
+
+        // First playback ends, the queued one starts
+        {
+            let mut state = active_call.call_state.write().await;
+            state.playing = false;
+            state.current_play_id = None;
+            state.tts_handle = None;
+        }
+        assert!(active_call.play_next_queued().await);
+        let state = active_call.call_state.read().await;
+        assert_eq!(state.current_play_id.as_deref(), Some("play_2"));
+        assert!(state.playback_queue.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reject_outbound_when_trunk_not_registered() -> Result<()> {
+        let mut config = Config::default();
+        config.udp_port = 0;
+        config.registration_admission = Some(true);
+        config.register_users = Some(vec![crate::useragent::RegisterOption {
+            server: "127.0.0.1:25060".to_string(),
+            username: "1001".to_string(),
+            display_name: None,
+            disabled: None,
+            credential: None,
+        }]);
+        let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+        assert_eq!(
+            app_state.trunk_registered_for_callee("sip:2000@127.0.0.1:25060"),
+            Some(false)
+        );
+        assert_eq!(
+            app_state.trunk_registered_for_callee("sip:2000@10.0.0.1"),
+            None
+        );
+
+        let active_call = Arc::new(ActiveCall::new(
+            ActiveCallType::Sip,
+            CancellationToken::new(),
+            "test-admission".to_string(),
+            app_state.invitation.clone(),
+            app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+            None,
+        ));
+
+        let option = crate::CallOption {
+            callee: Some("sip:2000@127.0.0.1:25060".to_string()),
+            ..Default::default()
+        };
+        let result =
+            tokio::time::timeout(Duration::from_secs(2), active_call.do_invite(option)).await;
+        assert!(matches!(result, Ok(Err(_))), "invite should fail fast");
+        {
+            let state = active_call.call_state.read().await;
+            assert_eq!(
+                state.hangup_reason,
+                Some(CallRecordHangupReason::ServerUnavailable)
+            );
+        }
+
+        app_state
+            .alive_users
+            .write()
+            .unwrap()
+            .insert("1001@127.0.0.1:25060".to_string());
+        assert_eq!(
+            app_state.trunk_registered_for_callee("sip:2000@127.0.0.1:25060"),
+            Some(true)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge_option_keeps_on_answer_url() -> Result<()> {
+        let mut config = Config::default();
+        config.udp_port = 0;
+        let app_state = AppStateBuilder::new().with_config(config).build().await?;
+        let active_call = ActiveCall::new(
+            ActiveCallType::Sip,
+            CancellationToken::new(),
+            "test-merge-option".to_string(),
+            app_state.invitation.clone(),
+            app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+            None,
+        );
+        active_call.call_state.write().await.option = Some(crate::CallOption {
+            on_answer_url: Some("http://crm.local/screen-pop".to_string()),
+            ..Default::default()
+        });
+
+        // An accept re-sends the option without the playbook's on_answer_url
+        let merged = active_call
+            .call_state
+            .read()
+            .await
+            .merge_option(crate::CallOption::default());
+        assert_eq!(
+            merged.on_answer_url.as_deref(),
+            Some("http://crm.local/screen-pop")
+        );
+
+        let merged = active_call
+            .call_state
+            .read()
+            .await
+            .merge_option(crate::CallOption {
+                on_answer_url: Some("http://crm.local/other".to_string()),
+                ..Default::default()
+            });
+        assert_eq!(
+            merged.on_answer_url.as_deref(),
+            Some("http://crm.local/other")
+        );
+        Ok(())
+    }
+}
+
+/// Drop the ASR processors older than `generation` from the track
+async fn retire_asr(media_stream: &MediaStream, track_id: &TrackId, generation: u64) {
+    media_stream
+        .retain_processor::<AsrProcessor>(track_id, |p| p.generation >= generation)
+        .await
+        .ok();
+}
+
+/// Extra headers to send with BYE, taken from the call's SIP options
+fn sip_hangup_headers(option: &CallOption) -> Option<Vec<rsip::Header>> {
+    option
+        .sip
+        .as_ref()
+        .and_then(|s| s.hangup_headers.as_ref())
+        .map(|headers_map| {
+            headers_map
+                .iter()
+                .map(|(k, v)| rsip::Header::Other(k.clone(), v.clone()))
+                .collect::<Vec<rsip::Header>>()
+        })
+}
+
+/// How long to wait before retrying an offer refused with 491 Request
+/// Pending. RFC 3261 §14.1 has the owner of the Call-ID wait 2.1-4s and the
+/// other side 0-2s, so the two retries don't collide again
+fn glare_backoff(owns_call_id: bool) -> Duration {
+    let (min_ms, max_ms) = if owns_call_id {
+        (2100, 4000)
+    } else {
+        (0, 2000)
+    };
+    Duration::from_millis(min_ms + rand::random::<u64>() % (max_ms - min_ms + 1))
 }
 
 #[derive(Deserialize)]
@@ -219,6 +518,18 @@ pub struct CallParams {
     #[serde(rename = "ping")]
     pub ping_interval: Option<u32>,
     pub server_side_track: Option<String>,
+    /// Wire encoding for commands and events: "json" (default) or "msgpack"
+    pub encoding: Option<String>,
+    /// Listen to the mixed audio of this session instead of starting a call
+    pub monitor: Option<String>,
+    /// Join this session as a supervisor instead of starting a call
+    pub barge: Option<String>,
+    /// Monitor or barge token, when not sent as an `Authorization` header
+    pub token: Option<String>,
+    /// Name of the barging supervisor, noted in the call record
+    pub supervisor: Option<String>,
+    /// Only send these events to the client, comma separated, e.g. "asrFinal,hangup"
+    pub events: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
@@ -256,6 +567,37 @@ pub struct ActiveCallState {
     pub audio_receiver: Option<WebsocketBytesReceiver>,
     pub ready_to_answer: Option<(String, Option<Box<dyn Track>>, ServerInviteDialog)>,
     pub pending_asr_resume: Option<(u32, TranscriptionOption)>,
+    pub quality: Option<QualitySummary>,
+    /// A server-side playback (tts/file) is in progress
+    pub playing: bool,
+    /// Playbacks waiting for the current one under `PlaybackPolicy::Queue`
+    pub playback_queue: std::collections::VecDeque<Command>,
+    /// Outcome of each target tried by a dial sequence
+    pub hangup_messages: Vec<CallRecordHangupMessage>,
+    /// Generation of the current ASR processor, bumped by every ASR swap
+    pub asr_generation: u64,
+    /// Dialog of the connected SIP call
+    pub sip_dialog_id: Option<DialogId>,
+    /// The SIP peer listed UPDATE in its Allow header
+    pub peer_allows_update: bool,
+    /// Last SDP we sent to the SIP peer, the base of hold/resume offers
+    pub local_sdp: Option<String>,
+    /// Our offer to the SIP peer is awaiting its answer, offers from the peer
+    /// are refused with 491 meanwhile
+    pub sip_offer_pending: bool,
+    /// Watches link quality when `codecFallback` is enabled
+    pub quality_monitor: Option<QualityMonitor>,
+    /// Codec change made because of poor link quality
+    pub codec_adaptation: Option<CodecAdaptation>,
+    /// How ASR was put on hold, undone when the call resumes
+    pub asr_on_hold: Option<HoldAsrMode>,
+    /// Recording held back until the caller's `recordingConsent` allows it
+    pub pending_recorder: Option<RecorderOption>,
+    /// Files the recorder finished writing, known once the media stream is
+    /// cleaned up
+    pub recordings: Option<Vec<RecordedFile>>,
+    /// Switches the file of the ambiance playing under the server-side track
+    pub ambiance_source: Option<AmbianceSource>,
 }
 
 pub type ActiveCallRef = Arc<ActiveCall>;
@@ -282,7 +624,7 @@ pub struct ActiveCallGuard {
 }
 
 impl ActiveCallGuard {
-    pub fn new(call: ActiveCallRef) -> Self {
+    pub async fn new(call: ActiveCallRef) -> Self {
         let active_calls = {
             call.app_state
                 .total_calls
@@ -291,6 +633,11 @@ impl ActiveCallGuard {
             calls.insert(call.session_id.clone(), call.clone());
             calls.len()
         };
+        let ctx = call.hook_context(None).await;
+        if let Some(webhook) = &call.app_state.event_webhook {
+            webhook.send(&call.session_id, "created", ctx.webhook_data());
+        }
+        call.app_state.hooks.call_created(ctx);
         Self { call, active_calls }
     }
 }
@@ -445,6 +792,7 @@ impl ActiveCall {
 
     async fn process(&self) -> Result<()> {
         let mut event_receiver = self.event_sender.subscribe();
+        let consent_event_receiver = self.event_sender.subscribe();
 
         let input_timeout_expire = Arc::new(Mutex::new((0u64, 0u32)));
         let input_timeout_expire_ref = input_timeout_expire.clone();
@@ -470,8 +818,18 @@ impl ActiveCall {
         };
         let server_side_track_id = self.server_side_track_id.clone();
         let event_hook_loop = async move {
+            let mut answered = false;
             while let Ok(event) = event_receiver.recv().await {
                 match event {
+                    SessionEvent::Answer { refer, .. } if !answered && refer != Some(true) => {
+                        answered = true;
+                        let ctx = self.hook_context(None).await;
+                        if let Some(webhook) = &self.app_state.event_webhook {
+                            webhook.send(&self.session_id, "answered", ctx.webhook_data());
+                        }
+                        self.app_state.hooks.answered(ctx);
+                        self.notify_answer_url().await;
+                    }
                     SessionEvent::Speaking { .. }
                     | SessionEvent::Dtmf { .. }
                     | SessionEvent::AsrDelta { .. }
@@ -501,6 +859,7 @@ impl ActiveCall {
                                 continue;
                             }
                             state.current_play_id = None;
+                            state.playing = false;
                             (
                                 state.moh.clone(),
                                 state.auto_hangup.clone(),
@@ -531,6 +890,10 @@ impl ActiveCall {
                             }
                         }
 
+                        if self.play_next_queued().await {
+                            continue;
+                        }
+
                         if let Some(timeout) = wait_timeout_val {
                             let expire = if timeout > 0 {
                                 (crate::media::get_timestamp(), timeout)
@@ -551,6 +914,77 @@ impl ActiveCall {
                             self.do_interrupt(true).await.ok();
                         }
                     }
+                    SessionEvent::QualityStats {
+                        jitter_ms,
+                        loss_pct,
+                        rtt_ms,
+                        ..
+                    } => {
+                        let stats = QualityStats {
+                            jitter_ms,
+                            loss_pct,
+                            rtt_ms,
+                        };
+                        let fallback_codec = {
+                            let mut state = self.call_state.write().await;
+                            state
+                                .quality
+                                .get_or_insert_with(QualitySummary::default)
+                                .update(&stats);
+                            let fallback =
+                                state.option.as_ref().and_then(|o| o.codec_fallback.clone());
+                            fallback.and_then(|fallback| {
+                                let monitor = state.quality_monitor.get_or_insert_with(|| {
+                                    QualityMonitor::new(
+                                        fallback.max_loss_pct.unwrap_or(10.0),
+                                        fallback.max_jitter_ms.unwrap_or(60.0),
+                                        Duration::from_secs(fallback.sustain_secs.unwrap_or(10)),
+                                    )
+                                });
+                                monitor
+                                    .observe(&stats, std::time::Instant::now())
+                                    .then(|| fallback.codec.unwrap_or_else(|| "pcmu".to_string()))
+                            })
+                        };
+                        let Some(codec) = fallback_codec else {
+                            continue;
+                        };
+                        // The re-INVITE can take seconds, so it runs beside the event loop
+                        let call = self
+                            .app_state
+                            .active_calls
+                            .lock()
+                            .unwrap()
+                            .get(&self.session_id)
+                            .cloned();
+                        let Some(call) = call else {
+                            warn!(
+                                session_id = self.session_id,
+                                codec, "codec fallback skipped, call is not registered"
+                            );
+                            continue;
+                        };
+                        crate::spawn(async move {
+                            let result = select! {
+                                _ = call.cancel_token.cancelled() => return,
+                                result = call.do_codec_fallback(&codec, stats) => result,
+                            };
+                            if let Err(e) = result {
+                                warn!(
+                                    session_id = call.session_id,
+                                    codec, "codec fallback failed: {}", e
+                                );
+                            }
+                        });
+                    }
+                    SessionEvent::Hold { on_hold, .. } => {
+                        if let Err(e) = self.apply_hold_asr(on_hold).await {
+                            warn!(
+                                session_id = self.session_id,
+                                on_hold, "failed to apply hold to asr: {}", e
+                            );
+                        }
+                    }
                     SessionEvent::Inactivity { track_id, .. } => {
                         info!(
                             session_id = self.session_id,
@@ -560,6 +994,15 @@ impl ActiveCall {
                             .await
                             .ok();
                     }
+                    SessionEvent::MediaTimeout { track_id, .. } => {
+                        warn!(
+                            session_id = self.session_id,
+                            track_id, "no media received after answer, hanging up"
+                        );
+                        self.do_hangup(Some(CallRecordHangupReason::ServerUnavailable), None, None)
+                            .await
+                            .ok();
+                    }
                     SessionEvent::Hangup { refer, .. } => {
                         // Check if we need to resume ASR after refer hangup
                         if refer == Some(true) {
@@ -675,11 +1118,218 @@ impl ActiveCall {
             _ = event_hook_loop => {
                 info!(session_id = self.session_id, "event loop done");
             }
+            _ = self.recording_consent_loop(consent_event_receiver) => {}
         }
         Ok(())
     }
 
+    /// Run the `recordingConsent` flow once the call is answered and start the
+    /// held-back recording if the outcome allows it. Never returns, so it can
+    /// run alongside the other call loops.
+    async fn recording_consent_loop(&self, mut event_receiver: EventReceiver) {
+        if let Some(outcome) = self.collect_recording_consent(&mut event_receiver).await {
+            info!(
+                session_id = self.session_id,
+                ?outcome,
+                "recording consent collected"
+            );
+            let recorder = {
+                let mut state = self.call_state.write().await;
+                let mode = state
+                    .option
+                    .as_ref()
+                    .and_then(|o| o.recording_consent.as_ref())
+                    .map(|consent| consent.consent_mode)
+                    .unwrap_or_default();
+                state.set_recording_consent(mode, outcome);
+                state.pending_recorder.take()
+            };
+            if let Some(recorder) = recorder.filter(|_| outcome.allows_recording()) {
+                self.media_stream.attach_recorder(recorder).await;
+            }
+        }
+        drop(event_receiver);
+        std::future::pending::<()>().await
+    }
+
+    /// Wait for the answer, play the consent prompt and collect the caller's
+    /// decision. `None` when no recording waits for consent or the call ends first
+    async fn collect_recording_consent(
+        &self,
+        event_receiver: &mut EventReceiver,
+    ) -> Option<RecordingConsentOutcome> {
+        use tokio::sync::broadcast::error::RecvError;
+        const PLAY_ID: &str = "recording-consent";
+
+        loop {
+            match event_receiver.recv().await {
+                Ok(SessionEvent::Answer { refer, .. }) if refer != Some(true) => break,
+                Ok(SessionEvent::Hangup { refer, .. }) if refer != Some(true) => return None,
+                Err(RecvError::Closed) => return None,
+                _ => {}
+            }
+        }
+        let consent = {
+            let state = self.call_state.read().await;
+            state.pending_recorder.as_ref()?;
+            state
+                .option
+                .as_ref()
+                .and_then(|o| o.recording_consent.clone())?
+        };
+
+        let mut prompt_done = true;
+        if let Some(url) = consent.prompt.clone() {
+            match self
+                .enqueue_command(Command::Play {
+                    url,
+                    play_id: Some(PLAY_ID.to_string()),
+                    auto_hangup: None,
+                    wait_input_timeout: None,
+                })
+                .await
+            {
+                Ok(_) => prompt_done = false,
+                Err(e) => warn!(
+                    session_id = self.session_id,
+                    "failed to play recording consent prompt: {}", e
+                ),
+            }
+        }
+
+        let accept_digit = consent.accept_digit.unwrap_or_else(|| "1".to_string());
+        let timeout = Duration::from_secs(consent.timeout_secs.unwrap_or(10));
+        let mut deadline = None;
+        loop {
+            if prompt_done {
+                if consent.consent_mode != ConsentMode::Explicit {
+                    return Some(RecordingConsentOutcome::Announced);
+                }
+                deadline.get_or_insert_with(|| tokio::time::Instant::now() + timeout);
+            }
+            let event = match deadline {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline, event_receiver.recv()).await {
+                        Ok(event) => event,
+                        Err(_) => return Some(RecordingConsentOutcome::TimedOut),
+                    }
+                }
+                None => event_receiver.recv().await,
+            };
+            match event {
+                Ok(SessionEvent::TrackEnd { play_id, .. })
+                    if play_id.as_deref() == Some(PLAY_ID) =>
+                {
+                    prompt_done = true;
+                }
+                Ok(SessionEvent::Dtmf { digit, .. })
+                    if consent.consent_mode == ConsentMode::Explicit =>
+                {
+                    return Some(if digit == accept_digit {
+                        RecordingConsentOutcome::Granted
+                    } else {
+                        RecordingConsentOutcome::Declined
+                    });
+                }
+                Ok(SessionEvent::Hangup { refer, .. }) if refer != Some(true) => return None,
+                Err(RecvError::Closed) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Apply the call's playback policy to Play/Tts commands arriving while another
+    /// playback is active. Returns the command when it should run now.
+    async fn check_playback_policy(&self, command: Command) -> Result<Option<Command>> {
+        let (play_id, is_tts) = match &command {
+            Command::Tts { play_id, .. } => (play_id.clone(), true),
+            Command::Play { url, play_id, .. } => (play_id.clone().or(Some(url.clone())), false),
+            _ => return Ok(Some(command)),
+        };
+        let policy = {
+            let mut state = self.call_state.write().await;
+            let policy = match state.option.as_ref().and_then(|o| o.playback_policy) {
+                Some(policy) => policy,
+                None => return Ok(Some(command)),
+            };
+            // More text for the current tts stream is not a new playback
+            let continuation = is_tts
+                && state.tts_handle.is_some()
+                && (play_id.is_none() || state.current_play_id == play_id);
+            if !state.playing || continuation {
+                return Ok(Some(command));
+            }
+            if policy == PlaybackPolicy::Queue {
+                debug!(session_id = self.session_id, ?play_id, "playback queued");
+                state.playback_queue.push_back(command);
+                return Ok(None);
+            }
+            policy
+        };
+
+        if policy == PlaybackPolicy::Reject {
+            warn!(
+                session_id = self.session_id,
+                ?play_id,
+                "playback rejected, another playback is active"
+            );
+            self.event_sender
+                .send(SessionEvent::Error {
+                    track_id: self.server_side_track_id.clone(),
+                    timestamp: crate::media::get_timestamp(),
+                    sender: "playback".to_string(),
+                    error: format!(
+                        "playback {:?} rejected, another playback is active",
+                        play_id
+                    ),
+                    code: Some(409),
+                })
+                .ok();
+            return Ok(None);
+        }
+        self.do_interrupt(false).await?;
+        Ok(Some(command))
+    }
+
+    /// Start the next queued playback, returns false when the queue is empty.
+    async fn play_next_queued(&self) -> bool {
+        let next = self.call_state.write().await.playback_queue.pop_front();
+        match next {
+            Some(command) => {
+                if let Err(e) = self.dispatch(command).await {
+                    warn!(
+                        session_id = self.session_id,
+                        "failed to play queued playback: {}", e
+                    );
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
     async fn dispatch(&self, command: Command) -> Result<()> {
+        let rejected = match &command {
+            Command::Play { url, .. } => self.app_state.config().check_play_path(url).err(),
+            _ => None,
+        };
+        if let Some(e) = rejected {
+            warn!(session_id = self.session_id, "{}", e);
+            self.event_sender
+                .send(SessionEvent::Error {
+                    track_id: self.server_side_track_id.clone(),
+                    timestamp: crate::media::get_timestamp(),
+                    sender: "play".to_string(),
+                    error: e.to_string(),
+                    code: Some(403),
+                })
+                .ok();
+            return Ok(());
+        }
+        let command = match self.check_playback_policy(command).await? {
+            Some(command) => command,
+            None => return Ok(()),
+        };
         match command {
             Command::Invite { option } => self.do_invite(option).await,
             Command::Accept { option } => self.do_accept(option).await,
@@ -745,6 +1395,9 @@ impl ActiveCall {
             } => self.do_refer(caller, callee, options).await,
             Command::Mute { track_id } => self.do_mute(track_id).await,
             Command::Unmute { track_id } => self.do_unmute(track_id).await,
+            Command::Hold {} => self.do_hold(true).await,
+            Command::Unhold {} => self.do_hold(false).await,
+            Command::Renegotiate {} => self.do_renegotiate().await,
             Command::Pause {} => self.do_pause().await,
             Command::Resume {} => self.do_resume().await,
             Command::Interrupt {
@@ -752,11 +1405,24 @@ impl ActiveCall {
                 fade_out_ms: _,
             } => self.do_interrupt(passage.unwrap_or_default()).await,
             Command::History { speaker, text } => self.do_history(speaker, text).await,
+            Command::SwapAsr {
+                option,
+                overlap_timeout,
+            } => self.do_swap_asr(option, overlap_timeout).await,
+            Command::SendData { label, data } => self.do_send_data(label, data).await,
+            Command::SendDtmf {
+                digits,
+                method,
+                duration_ms,
+                gap_ms,
+            } => self.do_send_dtmf(digits, method.unwrap_or_default(), duration_ms, gap_ms),
+            Command::SetAmbiance { path } => self.do_set_ambiance(path).await,
         }
     }
 
     fn build_record_option(&self, option: &CallOption) -> Option<RecorderOption> {
         if let Some(recorder_option) = &option.recorder {
+            let config = self.app_state.config();
             let mut recorder_file = recorder_option.recorder_file.clone();
             if recorder_file.contains("{id}") {
                 recorder_file = recorder_file.replace("{id}", &self.session_id);
@@ -786,9 +1452,7 @@ impl ActiveCall {
             } else {
                 recorder_option.ptime
             };
-            let requested_format = recorder_option
-                .format
-                .unwrap_or(self.app_state.config.recorder_format());
+            let requested_format = recorder_option.format.unwrap_or(config.recorder_format());
             let format = requested_format.effective();
             if requested_format != format {
                 warn!(
@@ -802,6 +1466,15 @@ impl ActiveCall {
                 samplerate: recorder_samplerate,
                 ptime: recorder_ptime,
                 format: Some(format),
+                on_format_change: recorder_option
+                    .on_format_change
+                    .or(config.recording.as_ref().and_then(|r| r.on_format_change)),
+                encryption_key: config
+                    .recording
+                    .as_ref()
+                    .and_then(|r| r.encryption_key.clone()),
+                stereo: recorder_option.stereo,
+                outbound_track_id: Some(self.server_side_track_id.clone()),
             };
             recorder_config.ensure_path_extension(format);
             Some(recorder_config)
@@ -819,7 +1492,26 @@ impl ActiveCall {
 
         option.check_default();
         if let Some(opt) = self.build_record_option(&option) {
-            self.media_stream.update_recorder_option(opt).await;
+            let consent_mode = option
+                .recording_consent
+                .as_ref()
+                .map(|consent| consent.consent_mode);
+            match consent_mode {
+                None => self.media_stream.update_recorder_option(opt).await,
+                Some(ConsentMode::None) => {
+                    self.call_state.write().await.set_recording_consent(
+                        ConsentMode::None,
+                        RecordingConsentOutcome::NotRequired,
+                    );
+                    self.media_stream.update_recorder_option(opt).await;
+                }
+                Some(mode) => {
+                    // Started by the consent flow once the call is answered
+                    let mut state = self.call_state.write().await;
+                    state.set_recording_consent(mode, RecordingConsentOutcome::Pending);
+                    state.pending_recorder = Some(opt);
+                }
+            }
         }
 
         if let Some(opt) = &option.media_pass {
@@ -922,6 +1614,7 @@ impl ActiveCall {
                 Ok(_) => {
                     {
                         let mut state = self.call_state.write().await;
+                        state.local_sdp = Some(answer.clone());
                         state.answer = Some(answer);
                         state.answer_time = Some(Utc::now());
                     }
@@ -973,6 +1666,9 @@ impl ActiveCall {
             let _ = self.invite_or_accept(option, "ringing".to_string()).await?;
         }
 
+        // 18x are sent unreliably even to callers supporting 100rel. The
+        // dialog answers PRACKs without reporting them, so a reliable 18x
+        // could not be retransmitted until PRACKed, nor the 200 OK wait for it
         let state = self.call_state.read().await;
         if let Some((answer, _, dialog)) = state.ready_to_answer.as_ref() {
             let (headers, body) = if early_media.unwrap_or_default() || ringtone.is_some() {
@@ -1076,6 +1772,7 @@ impl ActiveCall {
             state.wait_input_timeout = wait_input_timeout;
 
             state.current_play_id = play_id.clone();
+            state.playing = true;
             (changed, target_ssrc)
         };
 
@@ -1140,6 +1837,7 @@ impl ActiveCall {
                 _ => None,
             };
             state.wait_input_timeout = wait_input_timeout;
+            state.playing = true;
         }
 
         self.update_track_wrapper(Box::new(file_track), play_id)
@@ -1159,11 +1857,99 @@ impl ActiveCall {
             .map_err(Into::into)
     }
 
-    async fn do_interrupt(&self, graceful: bool) -> Result<()> {
+    /// Start the new recognizer next to the current one and retire the old one
+    /// once the new one delivers its first result, so no speech goes untranscribed
+    async fn do_swap_asr(
+        &self,
+        mut option: TranscriptionOption,
+        overlap_timeout: Option<u64>,
+    ) -> Result<()> {
+        option.samplerate.get_or_insert(INTERNAL_SAMPLERATE);
+        let generation = {
+            let mut state = self.call_state.write().await;
+            state.asr_generation += 1;
+            if let Some(call_option) = state.option.as_mut() {
+                call_option.asr = Some(option.clone());
+            }
+            state.asr_generation
+        };
+
+        // Events of the new recognizer go through a relay that spots its first result
+        let (asr_sender, mut asr_receiver) = tokio::sync::broadcast::channel(128);
+        let samplerate = option.samplerate;
+        let asr_client = self
+            .app_state
+            .stream_engine
+            .create_asr_client(
+                self.session_id.clone(),
+                self.cancel_token.child_token(),
+                option,
+                asr_sender,
+            )
+            .await?;
+        let processor = AsrProcessor::new(asr_client, samplerate).with_generation(generation);
+        // The old recognizer stops hearing the caller right away, so the two
+        // never transcribe the same audio. It stays open to deliver the result
+        // of speech it already has until it is retired below
+        self.media_stream
+            .update_processor::<AsrProcessor>(&self.session_id, |p| {
+                if p.generation < generation {
+                    p.paused = true;
+                }
+            })
+            .await?;
+        self.media_stream
+            .append_processor(&self.session_id, Box::new(processor))
+            .await?;
+        info!(session_id = self.session_id, generation, "swapping asr");
+
+        let session_id = self.session_id.clone();
+        let media_stream = self.media_stream.clone();
+        let event_sender = self.event_sender.clone();
+        let cancel_token = self.cancel_token.clone();
+        let overlap_timeout = Duration::from_secs(overlap_timeout.unwrap_or(10));
+        crate::spawn(async move {
+            let overlap = sleep(overlap_timeout);
+            tokio::pin!(overlap);
+            let mut swapped = false;
+            loop {
+                select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = &mut overlap, if !swapped => {
+                        swapped = true;
+                        info!(session_id, generation, "no result from new asr within overlap, removing old asr");
+                        retire_asr(&media_stream, &session_id, generation).await;
+                    }
+                    event = asr_receiver.recv() => match event {
+                        Ok(event) => {
+                            if !swapped
+                                && matches!(
+                                    event,
+                                    SessionEvent::AsrDelta { .. } | SessionEvent::AsrFinal { .. }
+                                )
+                            {
+                                swapped = true;
+                                info!(session_id, generation, "new asr is producing results, removing old asr");
+                                retire_asr(&media_stream, &session_id, generation).await;
+                            }
+                            event_sender.send(event).ok();
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn do_interrupt(&self, graceful: bool) -> Result<()> {
         {
             let mut state = self.call_state.write().await;
             state.tts_handle = None;
             state.moh = None;
+            state.playing = false;
+            state.playback_queue.clear();
         }
         self.media_stream
             .remove_track(&self.server_side_track_id, graceful)
@@ -1224,21 +2010,26 @@ impl ActiveCall {
             }
         }
 
-        // Set hangup reason based on initiator and reason
+        // Set hangup reason based on initiator and reason. Hangups requested by
+        // the agent (LLM tools, DTMF actions) are attributed to the system
         let hangup_reason = match initiator.as_deref() {
             Some("caller") => CallRecordHangupReason::ByCaller,
             Some("callee") => CallRecordHangupReason::ByCallee,
             Some("system") => CallRecordHangupReason::Autohangup,
+            Some("ai") | Some("agent") => CallRecordHangupReason::BySystem,
             _ => reason.unwrap_or(CallRecordHangupReason::BySystem),
         };
 
-        self.media_stream
-            .stop(Some(hangup_reason.to_string()), initiator);
-
+        // Record the reason before stopping the media, so the dialog teardown
+        // triggered by the stop can't claim the hangup for the remote party.
+        // If the remote side already hung up, its reason is kept
         self.call_state
             .write()
             .await
-            .set_hangup_reason(hangup_reason);
+            .set_hangup_reason(hangup_reason.clone());
+
+        self.media_stream
+            .stop(Some(hangup_reason.to_string()), initiator);
         Ok(())
     }
 
@@ -1322,7 +2113,7 @@ impl ActiveCall {
 
         let headers = invite_option.headers.get_or_insert_with(|| Vec::new());
 
-        {
+        let original_callee = {
             let cs = self.call_state.read().await;
             if let Some(opt) = cs.option.as_ref() {
                 if let Some(callee) = opt.callee.as_ref() {
@@ -1338,13 +2129,41 @@ impl ActiveCall {
                     ));
                 }
             }
-        }
+            // Inbound calls only carry the called number in the call variables
+            cs.option
+                .as_ref()
+                .and_then(|o| o.callee.clone())
+                .or_else(|| {
+                    cs.extras
+                        .as_ref()
+                        .and_then(|e| e.get(crate::playbook::BUILTIN_CALLEE))
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string())
+                })
+        };
 
         headers.push(rsip::Header::Other(
             "X-Referred-Id".to_string(),
             self.session_id.clone(),
         ));
 
+        let diversion = refer_option
+            .as_ref()
+            .and_then(|o| o.diversion.clone())
+            .or_else(|| self.app_state.config().refer_diversion.clone())
+            .filter(|o| o.is_enabled());
+        if let (Some(diversion), Some(original)) = (diversion, original_callee) {
+            match diversion.headers(&original, &callee) {
+                Ok(diversion_headers) => headers.extend(diversion_headers),
+                Err(e) => {
+                    warn!(
+                        session_id = self.session_id,
+                        original, "skipping diversion headers: {}", e
+                    );
+                }
+            }
+        }
+
         let ssrc = rand::random::<u32>();
         let refer_call_state = Arc::new(RwLock::new(ActiveCallState {
             start_time: Utc::now(),
@@ -1398,6 +2217,7 @@ impl ActiveCall {
                 &call_option,
                 moh,
                 auto_hangup_requested,
+                None,
             ),
         )
         .await;
@@ -1462,6 +2282,68 @@ impl ActiveCall {
         Ok(())
     }
 
+    async fn do_send_data(&self, label: String, data: String) -> Result<()> {
+        self.media_stream
+            .send_data(&self.session_id, &label, &data)
+            .await
+    }
+
+    /// Play the digits in the background, the tones take a while and the
+    /// call keeps handling commands meanwhile
+    fn do_send_dtmf(
+        &self,
+        digits: String,
+        method: DtmfMethod,
+        duration_ms: Option<u32>,
+        gap_ms: Option<u32>,
+    ) -> Result<()> {
+        info!(session_id = self.session_id, digits, ?method, "send dtmf");
+        let media_stream = self.media_stream.clone();
+        let track_id = self.session_id.clone();
+        crate::spawn(async move {
+            let result = match method {
+                DtmfMethod::Inband => {
+                    media_stream
+                        .send_dtmf_inband(
+                            &track_id,
+                            &digits,
+                            duration_ms.unwrap_or(100),
+                            gap_ms.unwrap_or(70),
+                        )
+                        .await
+                }
+            };
+            if let Err(e) = result {
+                warn!(session_id = track_id, "failed to send dtmf: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Switch the ambiance to `path`. The playing ambiance moves over at the
+    /// end of its current file, and later playback tracks start with `path`
+    async fn do_set_ambiance(&self, path: String) -> Result<()> {
+        let source = {
+            let mut cs = self.call_state.write().await;
+            let ambiance = cs
+                .option
+                .get_or_insert_with(Default::default)
+                .ambiance
+                .get_or_insert_with(Default::default);
+            ambiance.path = Some(path.clone());
+            ambiance.playlist = None;
+            cs.ambiance_source.clone()
+        };
+        match source {
+            Some(source) => source.set_source(&path).await?,
+            None => info!(
+                session_id = self.session_id,
+                path, "no ambiance playing, the next playback starts with it"
+            ),
+        }
+        Ok(())
+    }
+
     async fn do_mute(&self, track_id: Option<String>) -> Result<()> {
         self.media_stream.mute_track(track_id).await;
         Ok(())
@@ -1472,12 +2354,416 @@ impl ActiveCall {
         Ok(())
     }
 
+    /// Send an offer built from the last local SDP to the SIP peer, returning
+    /// its answer. The offer goes out as an UPDATE when the peer allows it,
+    /// otherwise as a re-INVITE. Each retry rebuilds the offer from the local
+    /// SDP as it is then, since the peer's own offer may have changed it while
+    /// we backed off, with a higher `o=` version than any offer sent before.
+    async fn send_sip_offer(
+        &self,
+        purpose: &str,
+        build_offer: impl Fn(&str) -> String,
+    ) -> Result<String> {
+        let (dialog_id, peer_allows_update) = {
+            let cs = self.call_state.read().await;
+            (cs.sip_dialog_id.clone(), cs.peer_allows_update)
+        };
+        let Some(dialog_id) = dialog_id else {
+            return Err(anyhow::anyhow!("{} requires a connected sip call", purpose));
+        };
+        let Some(dialog) = self.invitation.dialog_layer.get_dialog(&dialog_id) else {
+            return Err(anyhow::anyhow!("sip dialog {} not found", dialog_id));
+        };
+
+        let owns_call_id = matches!(dialog, Dialog::ClientInvite(_));
+        let mut glare_retries = self.app_state.config().reinvite_glare_retries.unwrap_or(2);
+
+        let mut use_update = peer_allows_update;
+        let mut sent_version = None;
+        let (response, local_sdp, offer) = loop {
+            // Set before sending so the dialog handler recognizes our own re-INVITE
+            let (local_sdp, offer) = {
+                let mut cs = self.call_state.write().await;
+                let Some(local_sdp) = cs.local_sdp.clone() else {
+                    return Err(anyhow::anyhow!("{} requires a connected sip call", purpose));
+                };
+                let mut offer = build_offer(&local_sdp);
+                if let (Some(sent), Some(version)) = (sent_version, sdp_origin_version(&offer)) {
+                    if version <= sent {
+                        offer = set_sdp_origin_version(&offer, sent + 1);
+                    }
+                }
+                sent_version = sdp_origin_version(&offer);
+                cs.local_sdp = Some(offer.clone());
+                cs.sip_offer_pending = true;
+                (local_sdp, offer)
+            };
+            let headers = Some(vec![rsip::Header::ContentType(
+                "application/sdp".to_string().into(),
+            )]);
+            let body = Some(offer.as_bytes().to_vec());
+            let result = match (&dialog, use_update) {
+                (Dialog::ClientInvite(d), true) => d.update(headers, body).await,
+                (Dialog::ClientInvite(d), false) => d.reinvite(headers, body).await,
+                (Dialog::ServerInvite(d), true) => d.update(headers, body).await,
+                (Dialog::ServerInvite(d), false) => d.reinvite(headers, body).await,
+                _ => {
+                    let mut cs = self.call_state.write().await;
+                    cs.local_sdp = Some(local_sdp);
+                    cs.sip_offer_pending = false;
+                    return Err(anyhow::anyhow!("sip dialog {} is not a call", dialog_id));
+                }
+            };
+            match result {
+                Ok(Some(resp))
+                    if use_update
+                        && matches!(
+                            resp.status_code,
+                            rsip::StatusCode::MethodNotAllowed | rsip::StatusCode::NotImplemented
+                        ) =>
+                {
+                    info!(
+                        session_id = self.session_id,
+                        status = %resp.status_code,
+                        "peer rejected UPDATE, falling back to re-INVITE"
+                    );
+                    self.restore_local_sdp(&offer, local_sdp).await;
+                    use_update = false;
+                }
+                Ok(Some(resp))
+                    if resp.status_code == rsip::StatusCode::RequestPending
+                        && glare_retries > 0 =>
+                {
+                    glare_retries -= 1;
+                    let backoff = glare_backoff(owns_call_id);
+                    info!(
+                        session_id = self.session_id,
+                        purpose,
+                        backoff_ms = backoff.as_millis() as u64,
+                        "sip offer glare, retrying after back-off"
+                    );
+                    // The peer's own offer may go through while we wait
+                    self.restore_local_sdp(&offer, local_sdp).await;
+                    self.call_state.write().await.sip_offer_pending = false;
+                    tokio::time::sleep(backoff).await;
+                }
+                other => break (other, local_sdp, offer),
+            }
+        };
+        self.call_state.write().await.sip_offer_pending = false;
+
+        match response {
+            Ok(Some(resp)) if resp.status_code == rsip::StatusCode::OK => {
+                debug!(
+                    session_id = self.session_id,
+                    purpose,
+                    method = if use_update { "UPDATE" } else { "INVITE" },
+                    "sip offer accepted"
+                );
+                Ok(String::from_utf8_lossy(resp.body()).to_string())
+            }
+            other => {
+                self.restore_local_sdp(&offer, local_sdp).await;
+                let reason = match other {
+                    Ok(Some(resp)) => resp.status_code.to_string(),
+                    Ok(None) => "dialog not confirmed".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                Err(anyhow::anyhow!("{} offer failed: {}", purpose, reason))
+            }
+        }
+    }
+
+    /// Put back the local SDP an offer was built from once the offer failed,
+    /// unless an answer to the peer's own offer replaced it meanwhile
+    async fn restore_local_sdp(&self, offer: &str, local_sdp: String) {
+        let mut cs = self.call_state.write().await;
+        if cs.local_sdp.as_deref() == Some(offer) {
+            cs.local_sdp = Some(local_sdp);
+        }
+    }
+
+    /// Put the SIP peer on hold (`a=sendonly`) or resume it.
+    async fn do_hold(&self, on_hold: bool) -> Result<()> {
+        let answer = self
+            .send_sip_offer("hold", |local_sdp| {
+                set_sdp_direction(local_sdp, if on_hold { "sendonly" } else { "sendrecv" })
+            })
+            .await?;
+        info!(session_id = self.session_id, on_hold, "hold offer accepted");
+
+        if !answer.trim().is_empty() {
+            if let Err(e) = self
+                .media_stream
+                .update_remote_description(&self.session_id, &answer)
+                .await
+            {
+                warn!(
+                    session_id = self.session_id,
+                    "failed to apply hold answer: {}", e
+                );
+            }
+        }
+        if on_hold {
+            self.media_stream
+                .hold_track(Some(self.session_id.clone()))
+                .await;
+        } else {
+            self.media_stream
+                .resume_track(Some(self.session_id.clone()))
+                .await;
+        }
+        self.event_sender
+            .send(SessionEvent::Hold {
+                track_id: self.session_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                on_hold,
+            })
+            .ok();
+        Ok(())
+    }
+
+    /// Offer the local SDP to the SIP peer again as a new version, e.g. to
+    /// refresh the session after a network change, and apply its answer
+    async fn do_renegotiate(&self) -> Result<()> {
+        let answer = self
+            .send_sip_offer("renegotiate", |local_sdp| {
+                let version = sdp_origin_version(local_sdp).unwrap_or_default();
+                set_sdp_origin_version(local_sdp, version + 1)
+            })
+            .await?;
+        info!(session_id = self.session_id, "renegotiation accepted");
+        if !answer.trim().is_empty() {
+            self.media_stream
+                .update_remote_description(&self.session_id, &answer)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Pause or tear down ASR when the call goes on hold according to the
+    /// call's `holdAsr` mode, and bring it back when the call resumes.
+    async fn apply_hold_asr(&self, on_hold: bool) -> Result<()> {
+        let (mode, asr_option, generation) = {
+            let mut state = self.call_state.write().await;
+            let asr_option = state.option.as_ref().and_then(|o| o.asr.clone());
+            let mode = if on_hold {
+                if state.asr_on_hold.is_some() || asr_option.is_none() {
+                    return Ok(());
+                }
+                let mode = state
+                    .option
+                    .as_ref()
+                    .and_then(|o| o.hold_asr)
+                    .unwrap_or_default();
+                state.asr_on_hold = Some(mode);
+                mode
+            } else {
+                match state.asr_on_hold.take() {
+                    Some(mode) => mode,
+                    None => return Ok(()),
+                }
+            };
+            (mode, asr_option, state.asr_generation)
+        };
+
+        match mode {
+            HoldAsrMode::Silence => Ok(()),
+            HoldAsrMode::PauseAsr => {
+                info!(
+                    session_id = self.session_id,
+                    on_hold, "pausing asr for hold"
+                );
+                // A recognizer being retired by `swapAsr` stays muted
+                self.media_stream
+                    .update_processor::<AsrProcessor>(&self.session_id, |p| {
+                        if p.generation >= generation {
+                            p.paused = on_hold;
+                        }
+                    })
+                    .await
+            }
+            HoldAsrMode::TeardownAsr if on_hold => {
+                info!(session_id = self.session_id, "closing asr for hold");
+                self.media_stream
+                    .remove_processor::<AsrProcessor>(&self.session_id)
+                    .await
+            }
+            HoldAsrMode::TeardownAsr => {
+                let Some(mut option) = asr_option else {
+                    return Ok(());
+                };
+                info!(session_id = self.session_id, "restarting asr after hold");
+                option.samplerate.get_or_insert(INTERNAL_SAMPLERATE);
+                let samplerate = option.samplerate;
+                let asr_client = self
+                    .app_state
+                    .stream_engine
+                    .create_asr_client(
+                        self.session_id.clone(),
+                        self.cancel_token.child_token(),
+                        option,
+                        self.event_sender.clone(),
+                    )
+                    .await?;
+                let processor =
+                    AsrProcessor::new(asr_client, samplerate).with_generation(generation);
+                self.media_stream
+                    .append_processor(&self.session_id, Box::new(processor))
+                    .await
+            }
+        }
+    }
+
+    /// Renegotiate the SIP call to `codec` after sustained poor link quality,
+    /// recording the change for the call record.
+    async fn do_codec_fallback(&self, codec: &str, stats: QualityStats) -> Result<()> {
+        let target = CodecType::try_from(codec)?;
+        let payload_type = target.payload_type();
+        let current = {
+            let cs = self.call_state.read().await;
+            if !cs
+                .local_sdp
+                .as_deref()
+                .is_some_and(|sdp| audio_payload_types(sdp).contains(&payload_type))
+            {
+                return Err(anyhow::anyhow!("{} was not offered on this call", codec));
+            }
+            cs.answer.as_deref().and_then(audio_payload_type)
+        };
+        if current == Some(payload_type) {
+            debug!(
+                session_id = self.session_id,
+                codec, "already using fallback codec"
+            );
+            return Ok(());
+        }
+
+        let answer = self
+            .send_sip_offer("codec fallback", |local_sdp| {
+                restrict_audio_codec(local_sdp, payload_type)
+            })
+            .await?;
+        if !answer.trim().is_empty() {
+            self.media_stream
+                .update_remote_description(&self.session_id, &answer)
+                .await?;
+        }
+
+        let codec_name = |pt: u8| {
+            CodecType::try_from(pt)
+                .map(|c| c.mime_type().trim_start_matches("audio/").to_string())
+                .unwrap_or_else(|_| pt.to_string())
+        };
+        let adaptation = CodecAdaptation {
+            timestamp: crate::media::get_timestamp(),
+            from: current.map(codec_name),
+            to: codec_name(payload_type),
+            stats,
+        };
+        warn!(
+            session_id = self.session_id,
+            from = ?adaptation.from,
+            to = %adaptation.to,
+            loss_pct = stats.loss_pct,
+            jitter_ms = stats.jitter_ms,
+            "link quality degraded, renegotiated codec"
+        );
+        self.event_sender
+            .send(SessionEvent::Metrics {
+                timestamp: adaptation.timestamp,
+                key: "codec_fallback".to_string(),
+                duration: 0,
+                data: serde_json::json!({
+                    "from": adaptation.from,
+                    "to": adaptation.to,
+                    "lossPct": stats.loss_pct,
+                    "jitterMs": stats.jitter_ms,
+                }),
+            })
+            .ok();
+        self.call_state.write().await.codec_adaptation = Some(adaptation);
+        Ok(())
+    }
+
     pub async fn cleanup(&self) -> Result<()> {
         self.call_state.write().await.tts_handle = None;
-        self.media_stream.cleanup().await.ok();
+        let recordings = self.media_stream.cleanup().await.ok().flatten();
+        self.call_state.write().await.recordings = recordings;
         Ok(())
     }
 
+    pub async fn hook_context(
+        &self,
+        hangup_reason: Option<CallRecordHangupReason>,
+    ) -> CallHookContext {
+        let state = self.call_state.read().await;
+        self.hook_context_for(state.option.as_ref(), hangup_reason)
+    }
+
+    /// Hook context of the call with the caller and callee of `option`
+    fn hook_context_for(
+        &self,
+        option: Option<&CallOption>,
+        hangup_reason: Option<CallRecordHangupReason>,
+    ) -> CallHookContext {
+        CallHookContext {
+            session_id: self.session_id.clone(),
+            call_type: self.call_type.clone(),
+            caller: option.and_then(|o| o.caller.clone()),
+            callee: option.and_then(|o| o.callee.clone()),
+            hangup_reason,
+        }
+    }
+
+    /// POST the screen-pop payload to the call's `onAnswerUrl`, falling back to
+    /// the global `on_answer_url`. Sent on its own task so answering never waits
+    /// for the callback
+    async fn notify_answer_url(&self) {
+        let (url, payload) = {
+            let state = self.call_state.read().await;
+            let option = state.option.as_ref();
+            let Some(url) = option
+                .and_then(|o| o.on_answer_url.clone())
+                .or_else(|| self.app_state.config().on_answer_url.clone())
+            else {
+                return;
+            };
+            let payload = serde_json::json!({
+                "call_id": self.session_id,
+                "caller": option.and_then(|o| o.caller.clone()),
+                "callee": option.and_then(|o| o.callee.clone()),
+                "answer_time": state.answer_time.unwrap_or_else(Utc::now),
+                "extras": state.extras.clone().unwrap_or_default(),
+            });
+            (url, payload)
+        };
+        let session_id = self.session_id.clone();
+        crate::spawn(async move {
+            match crate::net_tool::with_request_timeout(
+                crate::net_tool::http_client().post(&url).json(&payload),
+            )
+            .send()
+            .await
+            {
+                Ok(response) if response.status().is_success() => {
+                    debug!(session_id, url, "on_answer_url notified");
+                }
+                Ok(response) => {
+                    warn!(
+                        session_id,
+                        url,
+                        status = response.status().as_u16(),
+                        "on_answer_url rejected the callback"
+                    );
+                }
+                Err(e) => {
+                    warn!(session_id, url, "failed to call on_answer_url: {}", e);
+                }
+            }
+        });
+    }
+
     pub fn get_callrecord(&self) -> Option<CallRecord> {
         self.call_state.try_read().ok().map(|call_state| {
             call_state.build_callrecord(
@@ -1555,11 +2841,32 @@ impl ActiveCall {
         }
     }
 
+    /// Server `codec_fmtp` with the call's entries taking precedence, keyed
+    /// by lowercase codec name
+    fn codec_fmtp(
+        config: &Config,
+        call_fmtp: Option<&HashMap<String, String>>,
+    ) -> HashMap<String, String> {
+        config
+            .codec_fmtp
+            .iter()
+            .chain(call_fmtp)
+            .flatten()
+            .map(|(name, fmtp)| (name.to_lowercase(), fmtp.clone()))
+            .collect()
+    }
+
     pub async fn create_rtp_track(&self, track_id: TrackId, ssrc: u32) -> Result<RtcTrack> {
+        let config = self.app_state.config();
         let mut rtc_config = RtcTrackConfig::default();
         rtc_config.mode = rustrtc::TransportMode::Rtp;
+        let call_fmtp = {
+            let state = self.call_state.read().await;
+            state.option.as_ref().and_then(|o| o.codec_fmtp.clone())
+        };
+        rtc_config.codec_fmtp = Self::codec_fmtp(&config, call_fmtp.as_ref());
 
-        if let Some(codecs) = &self.app_state.config.codecs {
+        if let Some(codecs) = &config.codecs {
             let mut codec_types = Vec::new();
             for c in codecs {
                 match c.to_lowercase().as_str() {
@@ -1585,20 +2892,17 @@ impl ActiveCall {
             rtc_config.preferred_codec = Some(self.track_config.codec.clone());
         }
 
-        rtc_config.rtp_port_range = self
-            .app_state
-            .config
-            .rtp_start_port
-            .zip(self.app_state.config.rtp_end_port);
+        rtc_config.rtp_port_range = config.rtp_start_port.zip(config.rtp_end_port);
 
-        if let Some(ref external_ip) = self.app_state.config.external_ip {
+        if let Some(ref external_ip) = config.external_ip {
             rtc_config.external_ip = Some(external_ip.clone());
         }
-        if let Some(ref bind_ip) = self.app_state.config.rtp_bind_ip {
+        if let Some(ref bind_ip) = config.rtp_bind_ip {
             rtc_config.bind_ip = Some(bind_ip.clone());
         }
 
-        rtc_config.enable_latching = self.app_state.config.enable_rtp_latching;
+        rtc_config.enable_latching = config.enable_rtp_latching;
+        rtc_config.sdp_filter = config.sip_sdp_filter.clone().unwrap_or_else(sip_sdp_filter);
 
         let mut track = RtcTrack::new(
             self.cancel_token.child_token(),
@@ -1659,45 +2963,222 @@ impl ActiveCall {
                     }
                 }
 
-                // Auto-inject credentials from registered users if not already provided
-                let mut option = option.clone();
-                if option.sip.is_none()
-                    || option
-                        .sip
-                        .as_ref()
-                        .and_then(|s| s.username.as_ref())
-                        .is_none()
+                if self
+                    .app_state
+                    .config()
+                    .registration_admission
+                    .unwrap_or(false)
                 {
                     if let Some(callee) = &option.callee {
-                        if let Some(cred) = self.app_state.find_credentials_for_callee(callee) {
-                            if option.sip.is_none() {
-                                option.sip = Some(crate::SipOption {
-                                    username: Some(cred.username.clone()),
-                                    password: Some(cred.password.clone()),
-                                    realm: cred.realm.clone(),
-                                    ..Default::default()
-                                });
+                        if self.app_state.trunk_registered_for_callee(callee) == Some(false) {
+                            warn!(
+                                session_id = self.session_id,
+                                callee, "trunk not registered, rejecting outbound call"
+                            );
+                            {
+                                let mut state = self.call_state.write().await;
+                                state.last_status_code = 503;
+                                state.set_hangup_reason(CallRecordHangupReason::ServerUnavailable);
                             }
+                            self.event_sender
+                                .send(SessionEvent::Reject {
+                                    track_id: self.session_id.clone(),
+                                    timestamp: crate::media::get_timestamp(),
+                                    reason: "trunk not registered".to_string(),
+                                    code: Some(503),
+                                    refer: Some(false),
+                                })
+                                .ok();
+                            return Err(anyhow::anyhow!("trunk for {} is not registered", callee));
                         }
                     }
                 }
 
+                return self.dial_outbound(option).await;
+            }
+            ActiveCallType::B2bua => {
+                if let Some(dialog_id) = self
+                    .invitation
+                    .find_dialog_id_by_session_id(&self.session_id)
+                {
+                    if let Some(pending_dialog) = self.invitation.get_pending_call(&dialog_id) {
+                        return self
+                            .prepare_incoming_sip_track(
+                                self.cancel_token.clone(),
+                                self.call_state.clone(),
+                                &self.session_id,
+                                pending_dialog,
+                                hangup_headers,
+                            )
+                            .await;
+                    }
+                }
+
+                warn!(
+                    session_id = self.session_id,
+                    "no pending dialog found for B2BUA call"
+                );
+                return Err(anyhow::anyhow!(
+                    "no pending dialog found for session_id: {}",
+                    self.session_id
+                ));
+            }
+        };
+        match track {
+            Some(track) => {
+                self.finish_caller_stack(&option, Some(track)).await?;
+            }
+            None => {
+                warn!(session_id = self.session_id, "no track created for caller");
+                return Err(anyhow::anyhow!("no track created for caller"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Auto-inject credentials from registered users if not already provided
+    fn inject_credentials(&self, option: &mut CallOption) {
+        if option
+            .sip
+            .as_ref()
+            .and_then(|s| s.username.as_ref())
+            .is_some()
+        {
+            return;
+        }
+        if let Some(callee) = &option.callee {
+            if let Some(cred) = self.app_state.find_credentials_for_callee(callee) {
+                if option.sip.is_none() {
+                    option.sip = Some(crate::SipOption {
+                        username: Some(cred.username.clone()),
+                        password: Some(cred.password.clone()),
+                        realm: cred.realm.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+    }
+
+    /// Dial an outbound SIP call, trying each target of `dial_sequence` in turn
+    /// until one answers. A target failing with one of the `invite_retry` codes
+    /// is dialed again first. Every attempt is recorded in the CDR hangup messages.
+    async fn dial_outbound(&self, option: &CallOption) -> Result<()> {
+        if let Some(targets) = option.dial_fork.as_ref().filter(|t| !t.is_empty()) {
+            return self.dial_fork(option, targets).await;
+        }
+        let targets = match &option.dial_sequence {
+            Some(sequence) if !sequence.is_empty() => {
+                sequence.iter().cloned().map(Some).collect::<Vec<_>>()
+            }
+            _ => vec![option.callee.clone()],
+        };
+        let (retry_codes, max_retries, retry_delay) = match &option.invite_retry {
+            Some(retry) => (retry.retry_codes(), retry.max_retries(), retry.delay()),
+            None => (Vec::new(), 0, Duration::ZERO),
+        };
+        let max_retries = if retry_codes.is_empty() {
+            0
+        } else {
+            max_retries
+        };
+        let retry_codes: Arc<[u16]> = retry_codes.into();
+        let record_attempts = targets.len() > 1 || max_retries > 0;
+        let ring_timeout = option.ring_timeout.map(Duration::from_secs);
+        let mut start_time = None;
+        let mut attempt = 0;
+
+        for (index, target) in targets.iter().enumerate() {
+            let is_last_target = index + 1 == targets.len();
+            let mut retries = 0;
+            loop {
+                let can_retry = retries < max_retries;
+                let is_last = is_last_target && !can_retry;
+                let mut option = option.clone();
+                option.callee = target.clone();
+
+                self.inject_credentials(&mut option);
                 let mut invite_option = option.build_invite_option()?;
-                invite_option.call_id = Some(self.session_id.clone());
-
-                match self
-                    .create_outgoing_sip_track(
-                        self.cancel_token.clone(),
-                        self.call_state.clone(),
-                        &self.session_id,
-                        invite_option,
-                        &option,
-                        None,
-                        false,
-                    )
-                    .await
+                invite_option.call_id = Some(self.attempt_call_id(attempt));
+                attempt += 1;
+
+                // Only the last leg owns the call token, earlier legs may fail without ending the call
+                let cancel_token = if is_last {
+                    self.cancel_token.clone()
+                } else {
+                    self.cancel_token.child_token()
+                };
+                let dial_attempt = if is_last {
+                    None
+                } else if is_last_target {
+                    Some(DialAttempt::retrying(retry_codes.clone()))
+                } else {
+                    Some(DialAttempt::new(false))
+                };
+                let invite = self.create_outgoing_sip_track(
+                    cancel_token.clone(),
+                    self.call_state.clone(),
+                    &self.session_id,
+                    invite_option,
+                    &option,
+                    None,
+                    false,
+                    dial_attempt.clone(),
+                );
+                // Dropping the pending invite on timeout sends CANCEL to the target
+                let (result, timed_out) = match ring_timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, invite).await {
+                        Ok(result) => (result, false),
+                        Err(_) => (
+                            Err(rsipstack::Error::Error("ring timeout".to_string())),
+                            true,
+                        ),
+                    },
+                    None => (invite.await, false),
+                };
+
+                let (code, reason) = match &result {
+                    Ok(_) => (200, None),
+                    Err(_) if timed_out => (408, Some("ring timeout".to_string())),
+                    Err(rsipstack::Error::DialogError(reason, _, code)) => {
+                        (code.code(), Some(reason.clone()))
+                    }
+                    Err(e) => (500, Some(e.to_string())),
+                };
+                let retry =
+                    result.is_err() && !timed_out && can_retry && retry_codes.contains(&code);
                 {
+                    let mut state = self.call_state.write().await;
+                    let first_start = *start_time.get_or_insert(state.start_time);
+                    state.start_time = first_start;
+                    if record_attempts {
+                        state.hangup_messages.push(CallRecordHangupMessage {
+                            code,
+                            reason: reason.clone(),
+                            target: target.clone(),
+                        });
+                    }
+                    if result.is_ok() {
+                        if let Some(o) = state.option.as_mut() {
+                            o.callee = target.clone();
+                        }
+                    } else if timed_out && is_last_target && state.hangup_reason.is_none() {
+                        state.last_status_code = code;
+                        state.set_hangup_reason(CallRecordHangupReason::NoAnswer);
+                    }
+                }
+
+                match result {
                     Ok(answer) => {
+                        if let Some(dial_attempt) = dial_attempt {
+                            // The answered leg now carries the call
+                            dial_attempt.connected();
+                            let call_token = self.cancel_token.clone();
+                            crate::spawn(async move {
+                                cancel_token.cancelled().await;
+                                call_token.cancel();
+                            });
+                        }
                         self.event_sender
                             .send(SessionEvent::Answer {
                                 timestamp: crate::media::get_timestamp(),
@@ -1708,11 +3189,43 @@ impl ActiveCall {
                             .ok();
                         return Ok(());
                     }
+                    Err(e) if retry => {
+                        retries += 1;
+                        info!(
+                            session_id = self.session_id,
+                            target = ?target,
+                            code,
+                            retries,
+                            "dial attempt failed, retrying in {:?}: {}",
+                            retry_delay,
+                            e
+                        );
+                        tokio::select! {
+                            _ = self.cancel_token.cancelled() => {
+                                return Err(anyhow::anyhow!("call cancelled while retrying"));
+                            }
+                            _ = tokio::time::sleep(retry_delay) => {}
+                        }
+                    }
+                    Err(e) if !is_last_target => {
+                        info!(
+                            session_id = self.session_id,
+                            target = ?target,
+                            code,
+                            "dial attempt failed, trying next target: {}",
+                            e
+                        );
+                        break;
+                    }
                     Err(e) => {
                         warn!(
                             session_id = self.session_id,
                             "failed to create sip track: {}", e
                         );
+                        if dial_attempt.is_some() {
+                            // The leg ran on a child token, end the call ourselves
+                            self.cancel_token.cancel();
+                        }
                         match &e {
                             rsipstack::Error::DialogError(reason, _, code) => {
                                 self.event_sender
@@ -1730,44 +3243,237 @@ impl ActiveCall {
                         return Err(e.into());
                     }
                 }
-            }
-            ActiveCallType::B2bua => {
-                if let Some(dialog_id) = self
-                    .invitation
-                    .find_dialog_id_by_session_id(&self.session_id)
-                {
-                    if let Some(pending_dialog) = self.invitation.get_pending_call(&dialog_id) {
-                        return self
-                            .prepare_incoming_sip_track(
-                                self.cancel_token.clone(),
-                                self.call_state.clone(),
-                                &self.session_id,
-                                pending_dialog,
-                                hangup_headers,
-                            )
-                            .await;
+            }
+        }
+        Err(anyhow::anyhow!("no outbound target to dial"))
+    }
+
+    /// Call-ID of an outbound INVITE. The first one uses the session id, each
+    /// later attempt or forked leg is a new call and gets its own
+    fn attempt_call_id(&self, attempt: usize) -> String {
+        match attempt {
+            0 => self.session_id.clone(),
+            n => format!("{}-{}", self.session_id, n),
+        }
+    }
+
+    /// Ring every `dial_fork` target at once and connect the first one to answer.
+    /// Legs still ringing are cancelled and a leg answering in the same instant as
+    /// the winner is released with BYE. Every leg is recorded in the CDR hangup messages.
+    async fn dial_fork(&self, option: &CallOption, targets: &[String]) -> Result<()> {
+        struct ForkLeg {
+            target: String,
+            option: CallOption,
+            offer: String,
+            track: Option<RtcTrack>,
+            cancel_token: CancellationToken,
+            dial_attempt: DialAttempt,
+            outcome: Option<(u16, Option<String>)>,
+        }
+
+        let ssrc = {
+            let mut cs = self.call_state.write().await;
+            cs.start_time = Utc::now();
+            cs.ssrc
+        };
+
+        let mut legs = Vec::with_capacity(targets.len());
+        let mut pending = FuturesUnordered::new();
+        for (index, target) in targets.iter().enumerate() {
+            let mut leg_option = option.clone();
+            leg_option.callee = Some(target.clone());
+            self.inject_credentials(&mut leg_option);
+
+            let mut invite_option = leg_option.build_invite_option()?;
+            invite_option.call_id = Some(self.attempt_call_id(index));
+            let track = self.create_rtp_track(self.session_id.clone(), ssrc).await?;
+            let offer = track.local_description().await?;
+            invite_option.offer = Some(offer.clone().into());
+            self.fill_local_contact(&mut invite_option);
+            self.fill_outbound_proxy(&mut invite_option)?;
+            invite_option.support_prack = self.use_100rel(Some(&leg_option));
+
+            let cancel_token = self.cancel_token.child_token();
+            let dial_attempt = DialAttempt::new(true);
+            info!(session_id = self.session_id, target, "forking invite");
+            pending.push(
+                self.invite_with_dialog(
+                    cancel_token.clone(),
+                    self.call_state.clone(),
+                    &self.session_id,
+                    invite_option,
+                    sip_hangup_headers(&leg_option),
+                    Some(dial_attempt.clone()),
+                )
+                .map(move |result| (index, result)),
+            );
+            legs.push(ForkLeg {
+                target: target.clone(),
+                option: leg_option,
+                offer,
+                track: Some(track),
+                cancel_token,
+                dial_attempt,
+                outcome: None,
+            });
+        }
+
+        let failure = |result: Result<(DialogId, Option<Vec<u8>>), rsipstack::Error>| match result {
+            Ok(_) => (488, Some("No answer received".to_string())),
+            Err(rsipstack::Error::DialogError(reason, _, code)) => (code.code(), Some(reason)),
+            Err(e) => (500, Some(e.to_string())),
+        };
+
+        let ring_timeout = option.ring_timeout.map(Duration::from_secs);
+        let ring_deadline = async {
+            match ring_timeout {
+                Some(timeout) => sleep(timeout).await,
+                None => std::future::pending::<()>().await,
+            }
+        };
+        tokio::pin!(ring_deadline);
+
+        let mut winner = None;
+        let mut timed_out = false;
+        while winner.is_none() {
+            select! {
+                _ = &mut ring_deadline => {
+                    timed_out = true;
+                    break;
+                }
+                next = pending.next() => match next {
+                    Some((index, Ok((dialog_id, Some(answer))))) => {
+                        winner = Some((index, dialog_id, answer));
                     }
-                }
+                    Some((index, result)) => legs[index].outcome = Some(failure(result)),
+                    None => break,
+                },
+            }
+        }
 
-                warn!(
-                    session_id = self.session_id,
-                    "no pending dialog found for B2BUA call"
-                );
-                return Err(anyhow::anyhow!(
-                    "no pending dialog found for session_id: {}",
-                    self.session_id
-                ));
+        // A leg that answered in the same instant loses the race and is released below
+        while let Some(Some((index, result))) = pending.next().now_or_never() {
+            legs[index].outcome = Some(match result {
+                Ok((_, Some(_))) => (200, Some("answered after another leg".to_string())),
+                other => failure(other),
+            });
+        }
+        // Dropping the pending invites cancels the legs still ringing
+        drop(pending);
+
+        let winner_index = winner.as_ref().map(|(index, ..)| *index);
+        {
+            let mut cs = self.call_state.write().await;
+            for (index, leg) in legs.iter().enumerate() {
+                let (code, reason) = match &leg.outcome {
+                    _ if Some(index) == winner_index => (200, None),
+                    Some(outcome) => outcome.clone(),
+                    None if timed_out => (408, Some("ring timeout".to_string())),
+                    None => (487, Some("cancelled".to_string())),
+                };
+                cs.hangup_messages.push(CallRecordHangupMessage {
+                    code,
+                    reason,
+                    target: Some(leg.target.clone()),
+                });
+            }
+        }
+        for (index, leg) in legs.iter().enumerate() {
+            if Some(index) != winner_index {
+                leg.cancel_token.cancel();
+            }
+        }
+
+        let Some((index, dialog_id, answer)) = winner else {
+            let (code, reason) = legs
+                .iter()
+                .rev()
+                .find_map(|leg| leg.outcome.clone())
+                .filter(|_| !timed_out)
+                .unwrap_or((408, Some("ring timeout".to_string())));
+            {
+                let mut cs = self.call_state.write().await;
+                cs.last_status_code = code;
+                if timed_out {
+                    cs.set_hangup_reason(CallRecordHangupReason::NoAnswer);
+                }
             }
+            let reason = reason.unwrap_or_default();
+            warn!(
+                session_id = self.session_id,
+                code, "no forked leg answered: {}", reason
+            );
+            self.event_sender
+                .send(SessionEvent::Reject {
+                    track_id: self.session_id.clone(),
+                    timestamp: crate::media::get_timestamp(),
+                    reason: reason.clone(),
+                    code: Some(code as u32),
+                    refer: Some(false),
+                })
+                .ok();
+            return Err(anyhow::anyhow!("no forked leg answered: {}", reason));
         };
-        match track {
-            Some(track) => {
-                self.finish_caller_stack(&option, Some(track)).await?;
+
+        let leg = &mut legs[index];
+        leg.dial_attempt.connected();
+        info!(
+            session_id = self.session_id,
+            target = leg.target,
+            "forked leg answered"
+        );
+        // The winning leg now carries the call
+        let call_token = self.cancel_token.clone();
+        let leg_token = leg.cancel_token.clone();
+        crate::spawn(async move {
+            leg_token.cancelled().await;
+            call_token.cancel();
+        });
+
+        let answer = String::from_utf8_lossy(&answer).to_string();
+        let media_supervised = {
+            let mut cs = self.call_state.write().await;
+            let media_supervised = cs.answer_supervision() == AnswerSupervision::Media;
+            cs.session_id = dialog_id.to_string();
+            if cs.answer_time.is_none() && !media_supervised {
+                cs.answer_time = Some(Utc::now());
             }
-            None => {
-                warn!(session_id = self.session_id, "no track created for caller");
-                return Err(anyhow::anyhow!("no track created for caller"));
+            cs.last_status_code = 200;
+            cs.answer = Some(answer.clone());
+            cs.sip_dialog_id = Some(dialog_id.clone());
+            cs.local_sdp = Some(leg.offer.clone());
+            if let Some(o) = cs.option.as_mut() {
+                o.callee = Some(leg.target.clone());
+                o.offer = Some(leg.offer.clone());
             }
+            media_supervised
+        };
+        if media_supervised {
+            super::sip::supervise_media_answer(
+                self.call_state.clone(),
+                &self.media_stream,
+                self.session_id.clone(),
+                self.cancel_token.clone(),
+            )
+            .await;
+        }
+        if let Some(track) = leg.track.take() {
+            self.setup_track_with_stream(&leg.option, Box::new(track))
+                .await?;
         }
+        self.media_stream
+            .update_remote_description(&self.session_id, &answer)
+            .await
+            .ok();
+
+        self.event_sender
+            .send(SessionEvent::Answer {
+                timestamp: crate::media::get_timestamp(),
+                track_id: self.session_id.clone(),
+                sdp: answer,
+                refer: Some(false),
+            })
+            .ok();
         Ok(())
     }
 
@@ -1810,13 +3516,38 @@ impl ActiveCall {
         option: &CallOption,
         mut track: Box<dyn Track>,
     ) -> Result<()> {
+        let mut option = option.clone();
+        if let Some(requested) = option.internal_samplerate {
+            let codec = track
+                .negotiated_codec()
+                .unwrap_or_else(|| track.config().codec);
+            option.internal_samplerate = resolve_internal_samplerate(requested, codec);
+            match option.internal_samplerate {
+                Some(samplerate) => {
+                    info!(
+                        session_id = self.session_id,
+                        track_id = track.id(),
+                        samplerate,
+                        ?codec,
+                        "using internal sample rate"
+                    );
+                    let chain = track.processor_chain();
+                    chain.set_sample_rate(samplerate);
+                    chain.set_follow_codec(requested == 0);
+                }
+                None => warn!(
+                    session_id = self.session_id,
+                    requested, "unsupported internal sample rate, using default"
+                ),
+            }
+        }
         let processors = match StreamEngine::create_processors(
             self.app_state.stream_engine.clone(),
             track.as_ref(),
             self.cancel_token.child_token(),
             self.event_sender.clone(),
             self.media_stream.packet_sender.clone(),
-            option,
+            &option,
         )
         .await
         {
@@ -1840,7 +3571,8 @@ impl ActiveCall {
     }
 
     pub async fn update_track_wrapper(&self, mut track: Box<dyn Track>, play_id: Option<String>) {
-        let (ambiance_opt, subscribe) = {
+        let config = self.app_state.config();
+        let (ambiance_opt, loudness_opt, watermark_opt, elapsed_ms, subscribe) = {
             let state = self.call_state.read().await;
             let mut opt = state
                 .option
@@ -1848,22 +3580,49 @@ impl ActiveCall {
                 .and_then(|o| o.ambiance.clone())
                 .unwrap_or_default();
 
-            if let Some(global) = &self.app_state.config.ambiance {
+            if let Some(global) = &config.ambiance {
                 opt.merge(global);
             }
 
+            let mut loudness_opt = state
+                .option
+                .as_ref()
+                .and_then(|o| o.output_loudness.clone());
+            if let Some(global) = &config.output_loudness {
+                loudness_opt
+                    .get_or_insert_with(Default::default)
+                    .merge(global);
+            }
+
+            let mut watermark_opt = state.option.as_ref().and_then(|o| o.watermark.clone());
+            if let Some(global) = &config.watermark {
+                watermark_opt
+                    .get_or_insert_with(Default::default)
+                    .merge(global);
+            }
+
+            let elapsed_ms = (Utc::now() - state.answer_time.unwrap_or(state.start_time))
+                .num_milliseconds()
+                .max(0) as u64;
+
             let subscribe = state
                 .option
                 .as_ref()
                 .and_then(|o| o.subscribe)
                 .unwrap_or_default();
 
-            (opt, subscribe)
+            (opt, loudness_opt, watermark_opt, elapsed_ms, subscribe)
         };
-        if track.id() == &self.server_side_track_id && ambiance_opt.path.is_some() {
+        if track.id() == &self.server_side_track_id {
+            if let Some(loudness_opt) = loudness_opt.filter(|o| o.is_enabled()) {
+                track.append_processor(Box::new(LoudnessProcessor::new(&loudness_opt)));
+            }
+        }
+        if track.id() == &self.server_side_track_id && ambiance_opt.sources().next().is_some() {
             match AmbianceProcessor::new(ambiance_opt).await {
                 Ok(ambiance) => {
                     info!(session_id = self.session_id, "loaded ambiance processor");
+                    self.call_state.write().await.ambiance_source = Some(ambiance.source());
                     track.append_processor(Box::new(ambiance));
                 }
                 Err(e) => {
@@ -1871,6 +3630,13 @@ impl ActiveCall {
                 }
             }
         }
+        // After ambiance, so the mark is in the mix the caller hears
+        if track.id() == &self.server_side_track_id {
+            if let Some(watermark_opt) = watermark_opt.filter(|o| o.is_enabled()) {
+                let watermark = WatermarkProcessor::new(&watermark_opt).starting_at(elapsed_ms);
+                track.append_processor(Box::new(watermark));
+            }
+        }
 
         if subscribe && self.call_type != ActiveCallType::WebSocket {
             let (track_index, sub_track_id) = if track.id() == &self.server_side_track_id {
@@ -1924,6 +3690,7 @@ impl ActiveCall {
     }
 
     pub(super) async fn create_webrtc_track(&self) -> Result<Box<dyn Track>> {
+        let config = self.app_state.config();
         let (ssrc, option) = {
             let call_state = self.call_state.read().await;
             (
@@ -1934,9 +3701,11 @@ impl ActiveCall {
 
         let mut rtc_config = RtcTrackConfig::default();
         rtc_config.mode = rustrtc::TransportMode::WebRtc; // WebRTC
-        rtc_config.ice_servers = self.app_state.config.ice_servers.clone();
+        rtc_config.ice_servers = config.ice_servers.clone();
+        rtc_config.data_channel = option.data_channel.unwrap_or(false);
+        rtc_config.codec_fmtp = Self::codec_fmtp(&config, option.codec_fmtp.as_ref());
 
-        if let Some(codecs) = &self.app_state.config.codecs {
+        if let Some(codecs) = &config.codecs {
             let mut codec_types = Vec::new();
             for c in codecs {
                 match c.to_lowercase().as_str() {
@@ -1958,10 +3727,10 @@ impl ActiveCall {
             }
         }
 
-        if let Some(ref external_ip) = self.app_state.config.external_ip {
+        if let Some(ref external_ip) = config.external_ip {
             rtc_config.external_ip = Some(external_ip.clone());
         }
-        if let Some(ref bind_ip) = self.app_state.config.rtp_bind_ip {
+        if let Some(ref bind_ip) = config.rtp_bind_ip {
             rtc_config.bind_ip = Some(bind_ip.clone());
         }
 
@@ -2012,6 +3781,7 @@ impl ActiveCall {
         call_option: &CallOption,
         moh: Option<String>,
         auto_hangup: bool,
+        dial_attempt: Option<DialAttempt>,
     ) -> Result<String, rsipstack::Error> {
         let ssrc = call_state_ref.read().await.ssrc;
         let rtp_track = self
@@ -2031,31 +3801,15 @@ impl ActiveCall {
             if let Some(o) = cs.option.as_mut() {
                 o.offer = offer.clone();
             }
+            cs.local_sdp = offer.clone();
             cs.start_time = Utc::now();
         };
 
         invite_option.offer = offer.clone().map(|s| s.into());
-
-        // Set contact to local SIP endpoint address if not already set explicitly
-        // Check if contact is still default (no scheme set) or if host is localhost-like
-        let needs_contact = invite_option.contact.scheme.is_none()
-            || invite_option
-                .contact
-                .host_with_port
-                .to_string()
-                .starts_with("127.0.0.1");
-
-        if needs_contact {
-            if let Some(addr) = self.invitation.dialog_layer.endpoint.get_addrs().first() {
-                invite_option.contact = rsip::Uri {
-                    scheme: Some(rsip::Scheme::Sip),
-                    auth: None,
-                    host_with_port: addr.addr.clone(),
-                    params: vec![],
-                    headers: vec![],
-                };
-            }
-        }
+        self.fill_local_contact(&mut invite_option);
+        self.fill_outbound_proxy(&mut invite_option)
+            .map_err(|e| rsipstack::Error::Error(e.to_string()))?;
+        invite_option.support_prack = self.use_100rel(Some(call_option));
 
         let mut rtp_track_to_setup = Some(Box::new(rtp_track) as Box<dyn Track>);
 
@@ -2101,45 +3855,15 @@ impl ActiveCall {
             offer.as_ref().map(|s| s.as_str()).unwrap_or("<NO OFFER>")
         );
 
-        let (dlg_state_sender, dlg_state_receiver) =
-            self.invitation.dialog_layer.new_dialog_state_channel();
-
-        let states = InviteDialogStates {
-            is_client: true,
-            session_id: self.session_id.clone(),
-            track_id: track_id.clone(),
-            event_sender: self.event_sender.clone(),
-            media_stream: self.media_stream.clone(),
-            call_state: call_state_ref.clone(),
-            cancel_token,
-            terminated_reason: None,
-            has_early_media: false,
-        };
-
-        let hangup_headers = call_option
-            .sip
-            .as_ref()
-            .and_then(|s| s.hangup_headers.as_ref())
-            .map(|headers_map| {
-                headers_map
-                    .iter()
-                    .map(|(k, v)| rsip::Header::Other(k.clone(), v.clone()))
-                    .collect::<Vec<rsip::Header>>()
-            });
-
-        let mut client_dialog_handler = DialogStateReceiverGuard::new(
-            self.invitation.dialog_layer.clone(),
-            dlg_state_receiver,
-            hangup_headers,
-        );
-
-        crate::spawn(async move {
-            client_dialog_handler.process_dialog(states).await;
-        });
-
         let (dialog_id, answer) = self
-            .invitation
-            .invite(invite_option, dlg_state_sender)
+            .invite_with_dialog(
+                cancel_token,
+                call_state_ref.clone(),
+                track_id,
+                invite_option,
+                sip_hangup_headers(call_option),
+                dial_attempt,
+            )
             .await?;
 
         self.call_state.write().await.moh = None;
@@ -2188,6 +3912,95 @@ impl ActiveCall {
         Ok(answer)
     }
 
+    /// Route the INVITE through the global `outbound_proxy` unless the call
+    /// already set its own.
+    fn fill_outbound_proxy(&self, invite_option: &mut InviteOption) -> Result<()> {
+        let config = self.app_state.config();
+        let Some(proxy) = &config.outbound_proxy else {
+            return Ok(());
+        };
+        let headers = invite_option.headers.get_or_insert_with(Vec::new);
+        if !headers.iter().any(|h| matches!(h, rsip::Header::Route(_))) {
+            headers.push(crate::outbound_proxy_route(proxy)?);
+        }
+        Ok(())
+    }
+
+    /// Whether reliable provisional responses are used, the call's
+    /// `sip.enable_100rel` overriding the global `enable_100rel`
+    fn use_100rel(&self, option: Option<&CallOption>) -> bool {
+        option
+            .and_then(|option| option.sip.as_ref())
+            .and_then(|sip| sip.enable_100rel)
+            .or(self.app_state.config().enable_100rel)
+            .unwrap_or(false)
+    }
+
+    /// Set contact to local SIP endpoint address if not already set explicitly.
+    /// Check if contact is still default (no scheme set) or if host is localhost-like
+    fn fill_local_contact(&self, invite_option: &mut InviteOption) {
+        let needs_contact = invite_option.contact.scheme.is_none()
+            || invite_option
+                .contact
+                .host_with_port
+                .to_string()
+                .starts_with("127.0.0.1");
+
+        if needs_contact {
+            if let Some(addr) = self.invitation.dialog_layer.endpoint.get_addrs().first() {
+                invite_option.contact = rsip::Uri {
+                    scheme: Some(rsip::Scheme::Sip),
+                    auth: None,
+                    host_with_port: addr.addr.clone(),
+                    params: vec![],
+                    headers: vec![],
+                };
+            }
+        }
+    }
+
+    /// Send the INVITE with a dialog state handler attached, returning the
+    /// dialog id and the answer body once the invite transaction completes
+    async fn invite_with_dialog(
+        &self,
+        cancel_token: CancellationToken,
+        call_state_ref: ActiveCallStateRef,
+        track_id: &String,
+        invite_option: InviteOption,
+        hangup_headers: Option<Vec<rsip::Header>>,
+        dial_attempt: Option<DialAttempt>,
+    ) -> Result<(DialogId, Option<Vec<u8>>), rsipstack::Error> {
+        let (dlg_state_sender, dlg_state_receiver) =
+            self.invitation.dialog_layer.new_dialog_state_channel();
+
+        let states = InviteDialogStates {
+            is_client: true,
+            session_id: self.session_id.clone(),
+            track_id: track_id.clone(),
+            event_sender: self.event_sender.clone(),
+            media_stream: self.media_stream.clone(),
+            call_state: call_state_ref,
+            cancel_token,
+            terminated_reason: None,
+            has_early_media: false,
+            dial_attempt,
+        };
+
+        let mut client_dialog_handler = DialogStateReceiverGuard::new(
+            self.invitation.dialog_layer.clone(),
+            dlg_state_receiver,
+            hangup_headers,
+        );
+
+        crate::spawn(async move {
+            client_dialog_handler.process_dialog(states).await;
+        });
+
+        self.invitation
+            .invite(invite_option, dlg_state_sender)
+            .await
+    }
+
     /// Detect if SDP is WebRTC format
     pub fn is_webrtc_sdp(sdp: &str) -> bool {
         (sdp.contains("a=ice-ufrag:") || sdp.contains("a=ice-pwd:"))
@@ -2200,6 +4013,7 @@ impl ActiveCall {
         option: &CallOption,
         offer: String,
     ) -> Result<(String, Box<dyn Track>)> {
+        let config = self.app_state.config();
         let offer = match option.enable_ipv6 {
             Some(false) | None => strip_ipv6_candidates(&offer),
             _ => offer.clone(),
@@ -2210,14 +4024,16 @@ impl ActiveCall {
         let mut media_track = if Self::is_webrtc_sdp(&offer) {
             let mut rtc_config = RtcTrackConfig::default();
             rtc_config.mode = rustrtc::TransportMode::WebRtc;
-            rtc_config.ice_servers = self.app_state.config.ice_servers.clone();
-            if let Some(ref external_ip) = self.app_state.config.external_ip {
+            rtc_config.ice_servers = config.ice_servers.clone();
+            rtc_config.data_channel = option.data_channel.unwrap_or(false);
+            rtc_config.codec_fmtp = Self::codec_fmtp(&config, option.codec_fmtp.as_ref());
+            if let Some(ref external_ip) = config.external_ip {
                 rtc_config.external_ip = Some(external_ip.clone());
             }
-            if let Some(ref bind_ip) = self.app_state.config.rtp_bind_ip {
+            if let Some(ref bind_ip) = config.rtp_bind_ip {
                 rtc_config.bind_ip = Some(bind_ip.clone());
             }
-            rtc_config.enable_latching = self.app_state.config.enable_rtp_latching;
+            rtc_config.enable_latching = config.enable_rtp_latching;
 
             let webrtc_track = RtcTrack::new(
                 self.cancel_token.child_token(),
@@ -2264,10 +4080,12 @@ impl ActiveCall {
             cancel_token,
             terminated_reason: None,
             has_early_media: false,
+            dial_attempt: None,
         };
 
         let initial_request = pending_dialog.dialog.initial_request();
         let offer = String::from_utf8_lossy(&initial_request.body).to_string();
+        call_state_ref.write().await.peer_allows_update = allows_update(&initial_request.headers);
 
         let (ssrc, option) = {
             let call_state = call_state_ref.read().await;
@@ -2306,20 +4124,40 @@ impl ActiveCall {
 impl Drop for ActiveCall {
     fn drop(&mut self) {
         info!(session_id = self.session_id, "dropping active call");
+        let record = self.get_callrecord();
+        // Taken from the record, a lock can't be awaited here
+        let ctx = self.hook_context_for(
+            record.as_ref().and_then(|r| r.option.as_ref()),
+            record.as_ref().and_then(|r| r.hangup_reason.clone()),
+        );
+        let webhook_data = ctx.webhook_data();
+        self.app_state.hooks.hangup(ctx);
+        let mut cdr_pending = false;
         if let Some(sender) = self.app_state.callrecord_sender.as_ref() {
-            if let Some(record) = self.get_callrecord() {
-                if let Err(e) = sender.send(record) {
-                    warn!(
+            if let Some(record) = record {
+                match sender.send(record) {
+                    Ok(_) => cdr_pending = true,
+                    Err(e) => warn!(
                         session_id = self.session_id,
                         "failed to send call record: {}", e
-                    );
+                    ),
                 }
             }
         }
+        if let Some(webhook) = &self.app_state.event_webhook {
+            webhook.hangup(&self.session_id, webhook_data, cdr_pending);
+        }
     }
 }
 
 impl ActiveCallState {
+    pub fn answer_supervision(&self) -> AnswerSupervision {
+        self.option
+            .as_ref()
+            .and_then(|o| o.answer_supervision)
+            .unwrap_or_default()
+    }
+
     pub fn merge_option(&self, mut option: CallOption) -> CallOption {
         if let Some(existing) = &self.option {
             if option.asr.is_none() {
@@ -2334,6 +4172,9 @@ impl ActiveCallState {
             if option.denoise.is_none() {
                 option.denoise = existing.denoise;
             }
+            if option.noise_gate.is_none() {
+                option.noise_gate = existing.noise_gate.clone();
+            }
             if option.recorder.is_none() {
                 option.recorder = existing.recorder.clone();
             }
@@ -2346,10 +4187,56 @@ impl ActiveCallState {
             if option.ambiance.is_none() {
                 option.ambiance = existing.ambiance.clone();
             }
+            if option.output_loudness.is_none() {
+                option.output_loudness = existing.output_loudness.clone();
+            }
+            if option.watermark.is_none() {
+                option.watermark = existing.watermark.clone();
+            }
+            if option.on_answer_url.is_none() {
+                option.on_answer_url = existing.on_answer_url.clone();
+            }
         }
         option
     }
 
+    /// Note a supervisor joining the call in the call record extras
+    pub fn add_barge(&mut self, supervisor: Option<&str>) {
+        let barges = self
+            .extras
+            .get_or_insert_with(HashMap::new)
+            .entry("barges".to_string())
+            .or_insert_with(|| serde_json::json!([]));
+        if let Some(barges) = barges.as_array_mut() {
+            barges.push(serde_json::json!({
+                "supervisor": supervisor,
+                "joinedAt": Utc::now(),
+            }));
+        }
+    }
+
+    /// Note the latest barge ending
+    pub fn end_barge(&mut self) {
+        let last = self
+            .extras
+            .as_mut()
+            .and_then(|extras| extras.get_mut("barges"))
+            .and_then(|barges| barges.as_array_mut())
+            .and_then(|barges| barges.last_mut())
+            .and_then(|barge| barge.as_object_mut());
+        if let Some(barge) = last {
+            barge.insert("leftAt".to_string(), serde_json::json!(Utc::now()));
+        }
+    }
+
+    /// Note the consent mode and outcome in the call record extras
+    pub fn set_recording_consent(&mut self, mode: ConsentMode, outcome: RecordingConsentOutcome) {
+        self.extras.get_or_insert_with(HashMap::new).insert(
+            "recordingConsent".to_string(),
+            serde_json::json!({ "consentMode": mode, "outcome": outcome }),
+        );
+    }
+
     pub fn set_hangup_reason(&mut self, reason: CallRecordHangupReason) {
         if self.hangup_reason.is_none() {
             self.hangup_reason = Some(reason);
@@ -2389,20 +4276,34 @@ impl ActiveCallState {
     ) -> CallRecord {
         let option = self.option.clone().unwrap_or_default();
         let recorder = if option.recorder.is_some() {
-            let recorder_file = app_state.get_recorder_file(&session_id);
-            if std::path::Path::new(&recorder_file).exists() {
-                let file_size = std::fs::metadata(&recorder_file)
-                    .map(|m| m.len())
-                    .unwrap_or(0);
-                vec![crate::callrecord::CallRecordMedia {
-                    track_id: session_id.clone(),
-                    path: recorder_file,
-                    size: file_size,
-                    extra: None,
-                }]
-            } else {
-                vec![]
-            }
+            // Without a finished recorder, list what it has written so far
+            let files = self.recordings.clone().unwrap_or_else(|| {
+                RecordedFile::list(Path::new(&app_state.get_recorder_file(&session_id)))
+            });
+            files
+                .into_iter()
+                .filter_map(|file| {
+                    let mut extra = HashMap::new();
+                    if file.encrypted {
+                        extra.insert("encrypted".to_string(), serde_json::json!(true));
+                        extra.insert(
+                            "cipher".to_string(),
+                            serde_json::json!(crate::media::encryption::CIPHER_NAME),
+                        );
+                    }
+                    // Recordings split on a codec change continue in numbered segments
+                    if let Some(segment) = file.segment {
+                        extra.insert("segment".to_string(), serde_json::json!(segment));
+                    }
+                    let file_size = std::fs::metadata(&file.path).ok()?.len();
+                    Some(crate::callrecord::CallRecordMedia {
+                        track_id: session_id.clone(),
+                        path: file.path,
+                        size: file_size,
+                        extra: (!extra.is_empty()).then_some(extra),
+                    })
+                })
+                .collect()
         } else {
             vec![]
         };
@@ -2429,7 +4330,28 @@ impl ActiveCallState {
         let caller = option.caller.clone().unwrap_or_default();
         let callee = option.callee.clone().unwrap_or_default();
 
-        CallRecord {
+        let tenant_keys = app_state
+            .config()
+            .cdr_tenant_keys
+            .clone()
+            .unwrap_or_else(crate::config::default_cdr_tenant_keys);
+        let tenant = self.tenant(&option, &tenant_keys);
+
+        let mut extras = self.extras.clone();
+        if let Some(quality) = &self.quality {
+            extras.get_or_insert_with(HashMap::new).insert(
+                "quality".to_string(),
+                serde_json::to_value(quality).unwrap_or_default(),
+            );
+        }
+        if let Some(adaptation) = &self.codec_adaptation {
+            extras.get_or_insert_with(HashMap::new).insert(
+                "codecAdaptation".to_string(),
+                serde_json::to_value(adaptation).unwrap_or_default(),
+            );
+        }
+
+        let mut record = CallRecord {
             option: Some(option),
             call_id: session_id,
             call_type,
@@ -2440,12 +4362,29 @@ impl ActiveCallState {
             caller,
             callee,
             hangup_reason: self.hangup_reason.clone(),
-            hangup_messages: Vec::new(),
+            hangup_messages: self.hangup_messages.clone(),
             status_code: self.last_status_code,
-            extras: self.extras.clone(),
+            extras,
+            tenant,
             dump_event_file,
             recorder,
             refer_callrecord,
-        }
+            ..Default::default()
+        };
+        record.update_durations();
+        record
+    }
+
+    /// First non-empty value of `keys` in the call extras or the option `extra`
+    fn tenant(&self, option: &CallOption, keys: &[String]) -> Option<String> {
+        keys.iter().find_map(|key| {
+            let value = match self.extras.as_ref().and_then(|e| e.get(key)) {
+                Some(serde_json::Value::String(s)) => Some(s.clone()),
+                Some(serde_json::Value::Null) | None => None,
+                Some(other) => Some(other.to_string()),
+            }
+            .or_else(|| option.extra.as_ref().and_then(|e| e.get(key).cloned()));
+            value.filter(|v| !v.is_empty())
+        })
     }
 }
@@ -1,4 +1,7 @@
-use crate::{CallOption, ReferOption, media::recorder::RecorderOption, synthesis::SynthesisOption};
+use crate::{
+    CallOption, ReferOption, media::recorder::RecorderOption, synthesis::SynthesisOption,
+    transcription::TranscriptionOption,
+};
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::{
@@ -7,6 +10,7 @@ use std::{
 };
 
 pub mod active_call;
+pub mod rate_limit;
 pub mod sip;
 pub use active_call::ActiveCall;
 pub use active_call::ActiveCallRef;
@@ -88,10 +92,51 @@ pub enum Command {
     Unmute {
         track_id: Option<String>,
     },
+    /// Put the SIP peer on hold, via UPDATE when it allows it, else re-INVITE
+    Hold {},
+    Unhold {},
+    /// Offer the current media to the SIP peer again, the same way as hold
+    Renegotiate {},
     History {
         speaker: String,
         text: String,
     },
+    /// Switch the caller's ASR to another provider/model/language mid-call.
+    /// The old recognizer stops receiving audio at once and is removed when the new one produces
+    /// its first result.
+    SwapAsr {
+        option: TranscriptionOption,
+        /// Max seconds to keep the old recognizer running while waiting for the new one, default 10
+        overlap_timeout: Option<u64>,
+    },
+    /// Send a text message on a data channel of the caller's WebRTC track
+    SendData {
+        label: String,
+        data: String,
+    },
+    /// Send DTMF digits (`0-9`, `*`, `#`, `A-D`) to the caller
+    SendDtmf {
+        digits: String,
+        method: Option<DtmfMethod>,
+        /// Length of each tone in milliseconds, default 100
+        duration_ms: Option<u32>,
+        /// Silence between tones in milliseconds, default 70
+        gap_ms: Option<u32>,
+    },
+    /// Play another file as the call's background ambiance, looping. It takes
+    /// over from the file playing now once that one ends
+    SetAmbiance {
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DtmfMethod {
+    /// Tones played in the call audio, generated at the rate of the caller's
+    /// codec
+    #[default]
+    Inband,
 }
 
 /// Routing state for managing stateful load balancing
@@ -0,0 +1,67 @@
+use anyhow::{Result, anyhow};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket limiting how fast new calls are admitted. Calls beyond the
+/// burst wait for their turn, and are refused when the wait would exceed
+/// `max_wait`.
+pub struct CallRateLimiter {
+    rate: f64,
+    burst: f64,
+    max_wait: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+impl CallRateLimiter {
+    pub fn new(calls_per_sec: f64, burst: u32, max_wait: Duration) -> Self {
+        let burst = burst.max(1) as f64;
+        Self {
+            rate: calls_per_sec,
+            burst,
+            max_wait,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Reserve the next admission slot, returning how long to wait for it
+    fn reserve(&self) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.updated = now;
+
+        let wait = if bucket.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+        };
+        if wait > self.max_wait {
+            return None;
+        }
+        // Waiting callers keep their slot, so the balance may go negative
+        bucket.tokens -= 1.0;
+        Some(wait)
+    }
+
+    /// Wait until a new call may be set up, returning how long it waited
+    pub async fn admit(&self) -> Result<Duration> {
+        let wait = self
+            .reserve()
+            .ok_or_else(|| anyhow!("call rate limit exceeded"))?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(wait)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::AnswerSupervision;
 use crate::call::active_call::ActiveCallStateRef;
 use crate::callrecord::CallRecordHangupReason;
 use crate::event::EventSender;
@@ -15,6 +16,8 @@ use rsipstack::dialog::invitation::InviteOption;
 use rsipstack::rsip_ext::RsipResponseExt;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast::error::RecvError;
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
@@ -84,6 +87,99 @@ impl Drop for DialogStateReceiverGuard {
     }
 }
 
+/// Whether the peer listed UPDATE (RFC 3311) in the Allow header of its INVITE or 200 OK
+pub(super) fn allows_update(headers: &rsip::Headers) -> bool {
+    headers.iter().any(|header| {
+        let header = header.to_string().to_ascii_lowercase();
+        header
+            .strip_prefix("allow:")
+            .is_some_and(|methods| methods.split(',').any(|m| m.trim() == "update"))
+    })
+}
+
+/// Outbound leg that is one of several attempts for the same call
+#[derive(Clone)]
+pub(super) struct DialAttempt {
+    /// Cleared by the dialer once this leg connects the call. Until then the
+    /// leg ending is reported by the dialer, not as a hangup of the call.
+    pub pending: Arc<AtomicBool>,
+    /// Forked legs don't own the media track until they win
+    pub forked: bool,
+    /// Set on the last target's leg when its INVITE may be retried: only
+    /// these codes are left to the dialer, any other ends the call
+    pub retry_codes: Option<Arc<[u16]>>,
+}
+
+impl DialAttempt {
+    pub fn new(forked: bool) -> Self {
+        Self {
+            pending: Arc::new(AtomicBool::new(true)),
+            forked,
+            retry_codes: None,
+        }
+    }
+
+    pub fn retrying(retry_codes: Arc<[u16]>) -> Self {
+        Self {
+            retry_codes: Some(retry_codes),
+            ..Self::new(false)
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Whether the leg ending with `code` is reported by the dialer
+    fn defers(&self, code: u16) -> bool {
+        self.is_pending()
+            && self
+                .retry_codes
+                .as_ref()
+                .is_none_or(|codes| codes.contains(&code))
+    }
+
+    pub fn connected(&self) {
+        self.pending.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Record the answer time on the first inbound audio frame of `track_id`,
+/// for calls using `media` answer supervision
+pub(super) async fn supervise_media_answer(
+    call_state: ActiveCallStateRef,
+    media_stream: &MediaStream,
+    track_id: TrackId,
+    cancel_token: CancellationToken,
+) {
+    let mut frames = media_stream.subscribe_monitor().await;
+    crate::spawn(async move {
+        let first_frame = async {
+            loop {
+                match frames.recv().await {
+                    Ok(frame) if frame.track_id == track_id => return true,
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return false,
+                }
+            }
+        };
+        let received = tokio::select! {
+            _ = cancel_token.cancelled() => false,
+            received = first_frame => received,
+        };
+        if received {
+            let mut cs = call_state.write().await;
+            if cs.answer_time.is_none() {
+                info!(
+                    session_id = cs.session_id,
+                    "first media received, call answered"
+                );
+                cs.answer_time = Some(Utc::now());
+            }
+        }
+    });
+}
+
 pub(super) struct InviteDialogStates {
     pub is_client: bool,
     pub session_id: String,
@@ -94,18 +190,41 @@ pub(super) struct InviteDialogStates {
     pub media_stream: Arc<MediaStream>,
     pub terminated_reason: Option<TerminatedReason>,
     pub has_early_media: bool,
+    pub dial_attempt: Option<DialAttempt>,
+}
+
+/// Who ended a dialog that terminated on its own
+fn terminated_hangup_reason(reason: Option<&TerminatedReason>) -> CallRecordHangupReason {
+    match reason {
+        Some(TerminatedReason::UacCancel) => CallRecordHangupReason::Canceled,
+        Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy) => {
+            CallRecordHangupReason::ByCaller
+        }
+        Some(TerminatedReason::UasBye) | Some(TerminatedReason::UasBusy) => {
+            CallRecordHangupReason::ByCallee
+        }
+        Some(TerminatedReason::UasDecline) => CallRecordHangupReason::ByCallee,
+        Some(TerminatedReason::UacOther(_)) => CallRecordHangupReason::ByCaller,
+        Some(TerminatedReason::UasOther(_)) => CallRecordHangupReason::ByCallee,
+        _ => CallRecordHangupReason::BySystem,
+    }
+}
+
+/// The dialog reports our own re-INVITE once the peer accepted it, its From
+/// carries our tag where an offer from the peer carries theirs
+fn is_own_request(dialog_id: &DialogId, req: &rsip::Request) -> bool {
+    use rsip::prelude::HeadersExt;
+    req.from_header()
+        .and_then(|from| from.tag())
+        .ok()
+        .flatten()
+        .is_some_and(|tag| dialog_id.local_tag.as_str() == tag.value())
 }
 
 impl InviteDialogStates {
     pub(super) fn on_terminated(&mut self) {
-        let mut call_state_ref = match self.call_state.try_write() {
-            Ok(cs) => cs,
-            Err(_) => {
-                return;
-            }
-        };
         let reason = &self.terminated_reason;
-        call_state_ref.last_status_code = match reason {
+        let status_code = match reason {
             Some(TerminatedReason::UacCancel) => 487,
             Some(TerminatedReason::UacBye) => 200,
             Some(TerminatedReason::UacBusy) => 486,
@@ -116,30 +235,41 @@ impl InviteDialogStates {
             Some(TerminatedReason::UasOther(code)) => code.code(),
             _ => 500, // Default to internal server error
         };
-
-        if call_state_ref.hangup_reason.is_none() {
-            call_state_ref.hangup_reason.replace(match reason {
-                Some(TerminatedReason::UacCancel) => CallRecordHangupReason::Canceled,
-                Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy) => {
-                    CallRecordHangupReason::ByCaller
-                }
-                Some(TerminatedReason::UasBye) | Some(TerminatedReason::UasBusy) => {
-                    CallRecordHangupReason::ByCallee
-                }
-                Some(TerminatedReason::UasDecline) => CallRecordHangupReason::ByCallee,
-                Some(TerminatedReason::UacOther(_)) => CallRecordHangupReason::ByCaller,
-                Some(TerminatedReason::UasOther(_)) => CallRecordHangupReason::ByCallee,
-                _ => CallRecordHangupReason::BySystem,
-            });
+        if self
+            .dial_attempt
+            .as_ref()
+            .is_some_and(|a| a.defers(status_code))
+        {
+            return;
+        }
+        let mut call_state_ref = match self.call_state.try_write() {
+            Ok(cs) => cs,
+            Err(_) => {
+                return;
+            }
         };
-        let initiator = match reason {
-            Some(TerminatedReason::UacCancel) => "caller".to_string(),
-            Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy) => {
+        call_state_ref.last_status_code = status_code;
+
+        call_state_ref.set_hangup_reason(terminated_hangup_reason(reason.as_ref()));
+        // A hangup recorded by us before the dialog ended was ours, whatever
+        // the dialog reports
+        let initiator = match (&call_state_ref.hangup_reason, reason) {
+            (
+                Some(CallRecordHangupReason::BySystem)
+                | Some(CallRecordHangupReason::Autohangup)
+                | Some(CallRecordHangupReason::InactivityTimeout),
+                _,
+            ) => "system".to_string(),
+            (_, Some(TerminatedReason::UacCancel)) => "caller".to_string(),
+            (_, Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy)) => {
                 "caller".to_string()
             }
-            Some(TerminatedReason::UasBye)
-            | Some(TerminatedReason::UasBusy)
-            | Some(TerminatedReason::UasDecline) => "callee".to_string(),
+            (
+                _,
+                Some(TerminatedReason::UasBye)
+                | Some(TerminatedReason::UasBusy)
+                | Some(TerminatedReason::UasDecline),
+            ) => "callee".to_string(),
             _ => "system".to_string(),
         };
         self.event_sender
@@ -206,7 +336,11 @@ impl DialogStateReceiverGuard {
                             refer: Some(refer),
                         })?;
 
-                    if has_sdp {
+                    let forked = states
+                        .dial_attempt
+                        .as_ref()
+                        .is_some_and(|a| a.forked && a.is_pending());
+                    if has_sdp && !forked {
                         states.has_early_media = true;
                         states
                             .media_stream
@@ -216,11 +350,37 @@ impl DialogStateReceiverGuard {
                 }
                 DialogState::Confirmed(dialog_id, msg) => {
                     info!(session_id=states.session_id, %dialog_id, has_early_media=%states.has_early_media, "dialog confirmed");
+                    if states
+                        .dial_attempt
+                        .as_ref()
+                        .is_some_and(|a| a.forked && a.is_pending())
                     {
+                        // The dialer connects the winning fork and releases the others
+                        continue;
+                    }
+                    let media_supervised = {
                         let mut cs = states.call_state.write().await;
+                        let media_supervised =
+                            states.is_client && cs.answer_supervision() == AnswerSupervision::Media;
                         cs.session_id = dialog_id.to_string();
-                        cs.answer_time.replace(Utc::now());
+                        if !media_supervised {
+                            cs.answer_time.replace(Utc::now());
+                        }
                         cs.last_status_code = 200;
+                        cs.sip_dialog_id = Some(dialog_id.clone());
+                        if states.is_client {
+                            cs.peer_allows_update = allows_update(&msg.headers);
+                        }
+                        media_supervised
+                    };
+                    if media_supervised {
+                        supervise_media_answer(
+                            states.call_state.clone(),
+                            &states.media_stream,
+                            states.track_id.clone(),
+                            states.cancel_token.clone(),
+                        )
+                        .await;
                     }
                     if states.is_client {
                         let answer = String::from_utf8_lossy(msg.body());
@@ -282,6 +442,20 @@ impl DialogStateReceiverGuard {
                     tx_handle.reply(rsip::StatusCode::OK).await.ok();
                 }
                 DialogState::Updated(dialog_id, _req, tx_handle) => {
+                    if is_own_request(&dialog_id, &_req) {
+                        // Our own re-INVITE was accepted, the dialer already applied the answer
+                        continue;
+                    }
+                    let offer_pending = states.call_state.read().await.sip_offer_pending;
+                    if offer_pending
+                        && (_req.method == rsip::Method::Invite
+                            || _req.method == rsip::Method::Update)
+                    {
+                        // Glare: the peer sent an offer while ours is outstanding (RFC 3261 §14.2)
+                        info!(session_id = states.session_id, %dialog_id, method = %_req.method, "refusing offer while our own is pending");
+                        tx_handle.reply(rsip::StatusCode::RequestPending).await.ok();
+                        continue;
+                    }
                     info!(session_id = states.session_id, %dialog_id, "dialog update received");
                     let mut answer_sdp = None;
                     if let Some(sdp_body) = _req.body().get(..) {
@@ -375,6 +549,7 @@ impl DialogStateReceiverGuard {
                     }
 
                     if let Some(sdp) = answer_sdp {
+                        states.call_state.write().await.local_sdp = Some(sdp.clone());
                         tx_handle
                             .respond(
                                 rsip::StatusCode::OK,
@@ -400,6 +575,17 @@ impl DialogStateReceiverGuard {
                         ?reason,
                         "dialog terminated"
                     );
+                    // Record the side that sent the BYE, unless a local hangup
+                    // already set the reason: the first reason recorded is kept
+                    if matches!(reason, TerminatedReason::UacBye | TerminatedReason::UasBye)
+                        && !states.dial_attempt.as_ref().is_some_and(|a| a.is_pending())
+                    {
+                        states
+                            .call_state
+                            .write()
+                            .await
+                            .set_hangup_reason(terminated_hangup_reason(Some(&reason)));
+                    }
                     states.terminated_reason = Some(reason.clone());
                     return Ok(());
                 }
@@ -0,0 +1,151 @@
+use super::{CallRecord, CallRecordFormatter, CallRecordMedia, DefaultCallRecordFormatter};
+use crate::config::CallRecordConfig;
+use anyhow::Result;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+
+const CSV_HEADER: &str = "call_id,caller,callee,start_time,answer_time,end_time,status_code,hangup_reason,duration_seconds\n";
+
+/// Writes each call record as one CSV row, appended to a daily
+/// `<root>/<YYYYMMDD>.csv` file by the local backend, or uploaded on its own
+/// as `<root>/<YYYYMMDD>/<call_id>.csv` to S3. Recordings and event dumps keep
+/// the default per-call paths.
+pub struct CsvCallRecordFormatter {
+    pub root: String,
+}
+
+impl Default for CsvCallRecordFormatter {
+    fn default() -> Self {
+        Self {
+            root: "./config/cdr".to_string(),
+        }
+    }
+}
+
+impl CsvCallRecordFormatter {
+    pub fn new_with_config(config: &CallRecordConfig) -> Self {
+        Self {
+            root: DefaultCallRecordFormatter::new_with_config(config).root,
+        }
+    }
+
+    fn media_formatter(&self) -> DefaultCallRecordFormatter {
+        DefaultCallRecordFormatter {
+            root: self.root.clone(),
+        }
+    }
+}
+
+/// Quote `value` when it holds a separator, quote or line break
+fn escape_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl CallRecordFormatter for CsvCallRecordFormatter {
+    /// One row; `duration_seconds` is the talk time from answer to hangup, 0
+    /// for unanswered calls
+    fn format(&self, record: &CallRecord) -> Result<String> {
+        let duration_seconds = record
+            .billable_duration()
+            .map(|d| d.num_seconds())
+            .unwrap_or(0);
+        let fields = [
+            escape_field(&record.call_id),
+            escape_field(&record.caller),
+            escape_field(&record.callee),
+            format_time(&record.start_time),
+            record
+                .answer_time
+                .as_ref()
+                .map(format_time)
+                .unwrap_or_default(),
+            format_time(&record.end_time),
+            record.status_code.to_string(),
+            record
+                .hangup_reason
+                .as_ref()
+                .map(|reason| escape_field(&reason.to_string()))
+                .unwrap_or_default(),
+            duration_seconds.to_string(),
+        ];
+        Ok(format!("{}\n", fields.join(",")))
+    }
+
+    fn format_file_name(&self, record: &CallRecord) -> String {
+        let date = record.start_time.with_timezone(&Local).format("%Y%m%d");
+        let trimmed_root = self.root.trim_end_matches('/');
+        if trimmed_root.is_empty() {
+            format!("{}.csv", date)
+        } else {
+            format!("{}/{}.csv", trimmed_root, date)
+        }
+    }
+
+    fn append_header(&self) -> Option<String> {
+        Some(CSV_HEADER.to_string())
+    }
+
+    fn format_dump_events_path(&self, record: &CallRecord) -> String {
+        self.media_formatter().format_dump_events_path(record)
+    }
+
+    fn format_media_path(&self, record: &CallRecord, media: &CallRecordMedia) -> String {
+        self.media_formatter().format_media_path(record, media)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callrecord::CallRecordHangupReason;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_csv_row_escapes_fields() {
+        let start_time = Utc.with_ymd_and_hms(2024, 5, 1, 8, 0, 0).unwrap();
+        let record = CallRecord {
+            call_id: "call-1".to_string(),
+            caller: "\"Smith, John\" <sip:1001@pbx>".to_string(),
+            callee: "sip:2002@pbx".to_string(),
+            start_time,
+            answer_time: Some(start_time + chrono::Duration::seconds(5)),
+            end_time: start_time + chrono::Duration::seconds(65),
+            status_code: 200,
+            hangup_reason: Some(CallRecordHangupReason::Other(
+                "transfer, then hangup".to_string(),
+            )),
+            ..Default::default()
+        };
+
+        let row = CsvCallRecordFormatter::default().format(&record).unwrap();
+        assert_eq!(
+            row,
+            "call-1,\"\"\"Smith, John\"\" <sip:1001@pbx>\",sip:2002@pbx,\
+             2024-05-01T08:00:00.000Z,2024-05-01T08:00:05.000Z,2024-05-01T08:01:05.000Z,\
+             200,\"transfer, then hangup\",60\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_unanswered_call_has_no_duration() {
+        let record = CallRecord {
+            call_id: "call-2".to_string(),
+            status_code: 486,
+            ..Default::default()
+        };
+        let row = CsvCallRecordFormatter::default().format(&record).unwrap();
+        let fields: Vec<&str> = row.trim_end().split(',').collect();
+        assert_eq!(fields.len(), 9);
+        assert_eq!(fields[4], "");
+        assert_eq!(fields[6], "486");
+        assert_eq!(fields[7], "");
+        assert_eq!(fields[8], "0");
+    }
+}
@@ -0,0 +1,136 @@
+use age::{Encryptor, x25519};
+use anyhow::{Result, anyhow};
+use object_store::{ObjectStore, ObjectStoreExt, WriteMultipart, path::Path as ObjectPath};
+use std::{
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::io::AsyncReadExt;
+
+/// Suffix appended to the object path of age-encrypted uploads
+pub const AGE_EXTENSION: &str = "age";
+
+/// Plaintext read per step while streaming a file through the encryptor
+const READ_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Parse an age x25519 recipient, e.g. `age1...`
+pub fn parse_recipient(recipient: &str) -> Result<x25519::Recipient> {
+    x25519::Recipient::from_str(recipient.trim())
+        .map_err(|e| anyhow!("invalid age recipient: {}", e))
+}
+
+/// Encrypt a small buffer, such as the call record JSON, to `recipient`
+pub fn encrypt(data: &[u8], recipient: &x25519::Recipient) -> Result<Vec<u8>> {
+    age::encrypt(recipient, data).map_err(|e| anyhow!("failed to encrypt call record: {}", e))
+}
+
+/// Ciphertext written by the encryptor, drained into the upload after each chunk
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stream the file at `path` through age encryption into a multipart upload
+/// at `location`, so only one chunk is held in memory at a time. Returns the
+/// number of encrypted bytes written.
+pub async fn upload_encrypted_file(
+    object_store: &dyn ObjectStore,
+    location: &ObjectPath,
+    path: &str,
+    recipient: &x25519::Recipient,
+) -> Result<usize> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let encryptor = Encryptor::with_recipients(std::iter::once(recipient as &dyn age::Recipient))
+        .map_err(|e| anyhow!("failed to encrypt {}: {}", path, e))?;
+    let output = SharedBuffer::default();
+    let mut writer = encryptor.wrap_output(output.clone())?;
+    let mut upload = WriteMultipart::new(object_store.put_multipart(location).await?);
+
+    let mut buf = vec![0u8; READ_CHUNK_SIZE];
+    let mut written = 0;
+    let result: Result<()> = async {
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buf[..n])?;
+            upload.wait_for_capacity(4).await?;
+            let chunk = output.take();
+            written += chunk.len();
+            upload.write(&chunk);
+        }
+        writer.finish()?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        upload.abort().await.ok();
+        return Err(e);
+    }
+    let chunk = output.take();
+    written += chunk.len();
+    upload.write(&chunk);
+    upload.finish().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_encrypted_buffer_decrypts_with_private_key() {
+        let identity = x25519::Identity::generate();
+        let recipient = parse_recipient(&identity.to_public().to_string()).unwrap();
+
+        let encrypted = encrypt(b"{\"callId\":\"call-1\"}", &recipient).unwrap();
+        assert!(!encrypted.windows(6).any(|w| w == b"callId"));
+        assert_eq!(
+            age::decrypt(&identity, &encrypted).unwrap(),
+            b"{\"callId\":\"call-1\"}"
+        );
+
+        let other = x25519::Identity::generate();
+        assert!(age::decrypt(&other, &encrypted).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_encrypted_file_streams_to_store() {
+        let identity = x25519::Identity::generate();
+        let recipient = identity.to_public();
+        // Spans several read chunks
+        let content: Vec<u8> = (0..READ_CHUNK_SIZE * 2 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&content).unwrap();
+
+        let store = InMemory::new();
+        let location = ObjectPath::from("media/call-1.wav.age");
+        let size =
+            upload_encrypted_file(&store, &location, file.path().to_str().unwrap(), &recipient)
+                .await
+                .unwrap();
+        let encrypted = store.get(&location).await.unwrap().bytes().await.unwrap();
+        assert_eq!(size, encrypted.len());
+        assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), content);
+    }
+}
@@ -0,0 +1,52 @@
+use super::CallRecord;
+use crate::config::CdrEnrichmentConfig;
+use anyhow::{Result, anyhow};
+use serde_json::{Map, Value};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+/// Merge the fields `config.url` returns for the record's caller into its
+/// `extras`. Fields already set on the record are kept. Errors are logged and
+/// leave the record untouched
+pub async fn enrich_record(config: &CdrEnrichmentConfig, record: &mut CallRecord) {
+    match lookup(config, record).await {
+        Ok(fields) => {
+            debug!(
+                call_id = record.call_id,
+                fields = fields.len(),
+                "CDR enriched"
+            );
+            let extras = record.extras.get_or_insert_with(Default::default);
+            for (key, value) in fields {
+                extras.entry(key).or_insert(value);
+            }
+        }
+        Err(e) => {
+            warn!(call_id = record.call_id, "CDR enrichment failed: {}", e);
+        }
+    }
+}
+
+async fn lookup(config: &CdrEnrichmentConfig, record: &CallRecord) -> Result<Map<String, Value>> {
+    let timeout_ms = config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+    let mut request = crate::net_tool::http_client()
+        .get(&config.url)
+        .query(&[
+            ("caller", record.caller.as_str()),
+            ("callee", record.callee.as_str()),
+            ("call_id", record.call_id.as_str()),
+        ])
+        .timeout(Duration::from_millis(timeout_ms));
+    if let Some(headers) = &config.headers {
+        for (key, value) in headers {
+            request = request.header(key, value);
+        }
+    }
+    let response = request.send().await?.error_for_status()?;
+    match response.json::<Value>().await? {
+        Value::Object(fields) => Ok(fields),
+        other => Err(anyhow!("expected a JSON object, got {}", other)),
+    }
+}
@@ -1,30 +1,88 @@
 use crate::CallOption;
 use crate::{
     call::ActiveCallType,
-    config::{CallRecordConfig, S3Vendor},
+    config::{CallRecordConfig, CdrEnrichmentConfig, EncryptionConfig, S3SseConfig, S3Vendor},
+    event_webhook::EventWebhook,
+    hooks::{CallRecordSaveError, FnCallRecordErrorHook, FnCallRecordHook},
 };
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use chrono::{DateTime, Local, Utc};
+use flate2::{Compression, write::GzEncoder};
 use futures::stream::{FuturesUnordered, StreamExt};
 use object_store::PutPayload;
 use object_store::{
-    ObjectStore, ObjectStoreExt, aws::AmazonS3Builder, azure::MicrosoftAzureBuilder,
-    gcp::GoogleCloudStorageBuilder, path::Path as ObjectPath,
+    ObjectStore, ObjectStoreExt,
+    aws::{AmazonS3Builder, AmazonS3ConfigKey, S3EncryptionConfigKey},
+    azure::MicrosoftAzureBuilder,
+    gcp::GoogleCloudStorageBuilder,
+    path::Path as ObjectPath,
 };
 use reqwest;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap, future::Future, path::Path, pin::Pin, str::FromStr, sync::Arc,
-    time::Instant,
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
 };
 use tokio::{fs::File, io::AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+pub mod csv;
+pub mod encryption;
+pub mod enrichment;
+pub mod retention;
+
+pub use csv::CsvCallRecordFormatter;
+
 pub type CallRecordSender = tokio::sync::mpsc::UnboundedSender<CallRecord>;
 pub type CallRecordReceiver = tokio::sync::mpsc::UnboundedReceiver<CallRecord>;
 
+/// Media files of one record uploaded at the same time to S3-like storage
+const DEFAULT_MEDIA_UPLOAD_CONCURRENCY: usize = 4;
+/// Upload attempts for an S3 object whose stored size doesn't match
+const VERIFY_UPLOAD_ATTEMPTS: u32 = 3;
+/// Longest wait between HTTP upload retries, however many have failed
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+/// SHA-256 of the call record JSON, sent with HTTP uploads and echoed back by
+/// collectors that verify what they received
+pub const CALLLOG_CHECKSUM_HEADER: &str = "x-calllog-sha256";
+
+/// Locks of the files records are appended to, so concurrent saves neither
+/// both write the header nor interleave their rows
+static APPEND_LOCKS: LazyLock<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+fn append_lock(file_name: &str) -> Arc<tokio::sync::Mutex<()>> {
+    APPEND_LOCKS
+        .lock()
+        .unwrap()
+        .entry(file_name.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Object name of one record of a formatter that appends records to a shared
+/// file: object stores can't append, so `root/20240501.csv` becomes
+/// `root/20240501/<call_id>.csv`
+fn record_object_name(file_name: &str, call_id: &str) -> String {
+    let (dir, name) = match file_name.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), file_name),
+    };
+    match name.split_once('.') {
+        Some((stem, ext)) => format!("{}{}/{}.{}", dir, stem, call_id, ext),
+        None => format!("{}{}/{}", dir, name, call_id),
+    }
+}
+
 pub type FnSaveCallRecord = Arc<
     Box<
         dyn Fn(
@@ -74,9 +132,11 @@ impl CallRecordEvent {
     }
 }
 
+/// The `Serialize` impl below refreshes `duration_ms` and `billable_ms`, so
+/// they always match the timestamps being written
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", remote = "Self")]
 pub struct CallRecord {
     pub call_type: ActiveCallType,
     pub option: Option<CallOption>,
@@ -85,6 +145,10 @@ pub struct CallRecord {
     pub ring_time: Option<DateTime<Utc>>,
     pub answer_time: Option<DateTime<Utc>>,
     pub end_time: DateTime<Utc>,
+    /// [`CallRecord::duration`] in milliseconds, recomputed when serialized
+    pub duration_ms: Option<i64>,
+    /// [`CallRecord::billable_duration`] in milliseconds, absent for unanswered calls
+    pub billable_ms: Option<i64>,
     pub caller: String,
     pub callee: String,
     pub status_code: u16,
@@ -96,10 +160,51 @@ pub struct CallRecord {
     #[serde(default)]
     pub recorder: Vec<CallRecordMedia>,
     pub extras: Option<HashMap<String, serde_json::Value>>,
+    /// Tenant/account the call is attributed to, see `Config::cdr_tenant_keys`
+    pub tenant: Option<String>,
     pub dump_event_file: Option<String>,
     pub refer_callrecord: Option<Box<CallRecord>>,
 }
 
+impl CallRecord {
+    /// Time from call start to end
+    pub fn duration(&self) -> chrono::Duration {
+        (self.end_time - self.start_time).max(chrono::Duration::zero())
+    }
+
+    /// Talk time from answer to end, `None` for unanswered calls. Clamped to
+    /// zero when clock skew puts the answer after the end
+    pub fn billable_duration(&self) -> Option<chrono::Duration> {
+        self.answer_time
+            .map(|answer_time| (self.end_time - answer_time).max(chrono::Duration::zero()))
+    }
+
+    /// Fill `duration_ms` and `billable_ms` from the call timestamps
+    pub fn update_durations(&mut self) {
+        self.duration_ms = Some(self.duration().num_milliseconds());
+        self.billable_ms = self.billable_duration().map(|d| d.num_milliseconds());
+    }
+}
+
+impl Serialize for CallRecord {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let duration_ms = Some(self.duration().num_milliseconds());
+        let billable_ms = self.billable_duration().map(|d| d.num_milliseconds());
+        if self.duration_ms == duration_ms && self.billable_ms == billable_ms {
+            return CallRecord::serialize(self, serializer);
+        }
+        let mut record = self.clone();
+        record.update_durations();
+        CallRecord::serialize(&record, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CallRecord {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        CallRecord::deserialize(deserializer)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CallRecordMedia {
@@ -193,11 +298,37 @@ pub fn default_cdr_file_name(record: &CallRecord) -> String {
     )
 }
 
+/// Serialized call record bytes as stored, gzipped when `compress` is set
+pub fn encode_call_record(content: String, compress: bool) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    if !compress {
+        return Ok(content.into_bytes());
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content.as_bytes())?;
+    Ok(encoder.finish()?)
+}
+
 pub trait CallRecordFormatter: Send + Sync {
     fn format(&self, record: &CallRecord) -> Result<String> {
         Ok(serde_json::to_string(record)?)
     }
     fn format_file_name(&self, record: &CallRecord) -> String;
+    /// Name the call record is stored under, `.gz` appended when it is compressed
+    fn format_stored_file_name(&self, record: &CallRecord, compress: bool) -> String {
+        let file_name = self.format_file_name(record);
+        if compress {
+            format!("{}.gz", file_name)
+        } else {
+            file_name
+        }
+    }
+    /// When set, the local backend appends records sharing a file name to
+    /// that file, writing this header first when the file is created
+    fn append_header(&self) -> Option<String> {
+        None
+    }
     fn format_dump_events_path(&self, record: &CallRecord) -> String;
     fn format_media_path(&self, record: &CallRecord, media: &CallRecordMedia) -> String;
 }
@@ -217,7 +348,7 @@ impl Default for DefaultCallRecordFormatter {
 impl DefaultCallRecordFormatter {
     pub fn new_with_config(config: &CallRecordConfig) -> Self {
         let root = match config {
-            CallRecordConfig::Local { root } => root.clone(),
+            CallRecordConfig::Local { root, .. } => root.clone(),
             CallRecordConfig::S3 { root, .. } => root.clone(),
             _ => "./config/cdr".to_string(),
         };
@@ -268,6 +399,55 @@ impl CallRecordFormatter for DefaultCallRecordFormatter {
     }
 }
 
+/// Apply the server-side encryption settings of `sse` to an S3 builder
+pub fn apply_s3_sse(
+    builder: AmazonS3Builder,
+    sse: Option<&S3SseConfig>,
+) -> Result<AmazonS3Builder> {
+    let Some(sse) = sse else {
+        return Ok(builder);
+    };
+    let builder = match (sse.algorithm.as_str(), sse.kms_key_id.as_ref()) {
+        ("AES256", _) => builder.with_config(
+            AmazonS3ConfigKey::Encryption(S3EncryptionConfigKey::ServerSideEncryption),
+            "AES256",
+        ),
+        ("aws:kms", Some(key_id)) => builder.with_sse_kms_encryption(key_id),
+        ("aws:kms:dsse", Some(key_id)) => builder.with_dsse_kms_encryption(key_id),
+        ("aws:kms", None) | ("aws:kms:dsse", None) => builder.with_config(
+            AmazonS3ConfigKey::Encryption(S3EncryptionConfigKey::ServerSideEncryption),
+            sse.algorithm.as_str(),
+        ),
+        (other, _) => {
+            return Err(anyhow::anyhow!(
+                "unsupported S3 server-side encryption algorithm: {}",
+                other
+            ));
+        }
+    };
+    Ok(match sse.bucket_key_enabled {
+        Some(enabled) => builder.with_bucket_key(enabled),
+        None => builder,
+    })
+}
+
+/// Confirm the object stored at `location` holds `expected` bytes
+async fn verify_object_size(
+    object_store: &dyn ObjectStore,
+    location: &ObjectPath,
+    expected: usize,
+) -> Result<()> {
+    let meta = object_store.head(location).await?;
+    if meta.size != expected as u64 {
+        return Err(anyhow::anyhow!(
+            "stored size {} does not match uploaded size {}",
+            meta.size,
+            expected
+        ));
+    }
+    Ok(())
+}
+
 pub fn build_object_store_from_s3(
     vendor: &S3Vendor,
     bucket: &str,
@@ -275,7 +455,14 @@ pub fn build_object_store_from_s3(
     access_key: &str,
     secret_key: &str,
     endpoint: &str,
+    sse: Option<&S3SseConfig>,
 ) -> Result<Arc<dyn ObjectStore>> {
+    if sse.is_some() && matches!(vendor, S3Vendor::GCP | S3Vendor::Azure) {
+        warn!(
+            ?vendor,
+            "S3 server-side encryption settings are ignored for this vendor"
+        );
+    }
     let store: Arc<dyn ObjectStore> = match vendor {
         S3Vendor::AWS => {
             let builder = AmazonS3Builder::new()
@@ -283,6 +470,7 @@ pub fn build_object_store_from_s3(
                 .with_region(region)
                 .with_access_key_id(access_key)
                 .with_secret_access_key(secret_key);
+            let builder = apply_s3_sse(builder, sse)?;
 
             let instance = if !endpoint.is_empty() {
                 builder.with_endpoint(endpoint).build()?
@@ -307,14 +495,14 @@ pub fn build_object_store_from_s3(
             Arc::new(instance)
         }
         S3Vendor::Aliyun | S3Vendor::Tencent | S3Vendor::Minio | S3Vendor::DigitalOcean => {
-            let instance = AmazonS3Builder::new()
+            let builder = AmazonS3Builder::new()
                 .with_bucket_name(bucket)
                 .with_region(region)
                 .with_access_key_id(access_key)
                 .with_secret_access_key(secret_key)
                 .with_endpoint(endpoint)
-                .with_virtual_hosted_style_request(false)
-                .build()?;
+                .with_virtual_hosted_style_request(false);
+            let instance = apply_s3_sse(builder, sse)?.build()?;
             Arc::new(instance)
         }
     };
@@ -325,11 +513,16 @@ pub fn build_object_store_from_s3(
 pub struct CallRecordManager {
     pub max_concurrent: usize,
     pub sender: CallRecordSender,
-    config: Arc<CallRecordConfig>,
+    config: Arc<ArcSwap<CallRecordConfig>>,
     cancel_token: CancellationToken,
     receiver: CallRecordReceiver,
     saver_fn: FnSaveCallRecord,
-    formatter: Arc<dyn CallRecordFormatter>,
+    /// When unset, a `DefaultCallRecordFormatter` follows the current config
+    formatter: Option<Arc<dyn CallRecordFormatter>>,
+    on_saved: Option<FnCallRecordHook>,
+    on_save_error: Option<FnCallRecordErrorHook>,
+    event_webhook: Option<Arc<EventWebhook>>,
+    enrichment: Option<Arc<CdrEnrichmentConfig>>,
 }
 
 pub struct CallRecordManagerBuilder {
@@ -338,6 +531,10 @@ pub struct CallRecordManagerBuilder {
     pub max_concurrent: Option<usize>,
     saver_fn: Option<FnSaveCallRecord>,
     formatter: Option<Arc<dyn CallRecordFormatter>>,
+    on_saved: Option<FnCallRecordHook>,
+    on_save_error: Option<FnCallRecordErrorHook>,
+    event_webhook: Option<Arc<EventWebhook>>,
+    enrichment: Option<CdrEnrichmentConfig>,
 }
 
 impl CallRecordManagerBuilder {
@@ -348,6 +545,10 @@ impl CallRecordManagerBuilder {
             max_concurrent: None,
             saver_fn: None,
             formatter: None,
+            on_saved: None,
+            on_save_error: None,
+            event_webhook: None,
+            enrichment: None,
         }
     }
 
@@ -371,6 +572,30 @@ impl CallRecordManagerBuilder {
         self
     }
 
+    /// Called with the record after it has been saved successfully
+    pub fn with_on_saved(mut self, hook: Option<FnCallRecordHook>) -> Self {
+        self.on_saved = hook;
+        self
+    }
+
+    /// Called once for each record the saver failed to save
+    pub fn with_on_save_error(mut self, hook: Option<FnCallRecordErrorHook>) -> Self {
+        self.on_save_error = hook;
+        self
+    }
+
+    /// Reports `cdr_saved` for each saved record
+    pub fn with_event_webhook(mut self, event_webhook: Option<Arc<EventWebhook>>) -> Self {
+        self.event_webhook = event_webhook;
+        self
+    }
+
+    /// Merges the fields of an external lookup into each record before it is saved
+    pub fn with_enrichment(mut self, enrichment: Option<CdrEnrichmentConfig>) -> Self {
+        self.enrichment = enrichment;
+        self
+    }
+
     pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
         self.max_concurrent = Some(max_concurrent);
         self
@@ -383,13 +608,10 @@ impl CallRecordManagerBuilder {
         let saver_fn = self
             .saver_fn
             .unwrap_or_else(|| Arc::new(Box::new(CallRecordManager::default_saver)));
-        let formatter = self
-            .formatter
-            .unwrap_or_else(|| Arc::new(DefaultCallRecordFormatter::default()));
         let max_concurrent = self.max_concurrent.unwrap_or(64);
 
         match config.as_ref() {
-            CallRecordConfig::Local { root } => {
+            CallRecordConfig::Local { root, .. } => {
                 if !Path::new(&root).exists() {
                     match std::fs::create_dir_all(&root) {
                         Ok(_) => {
@@ -409,16 +631,20 @@ impl CallRecordManagerBuilder {
             cancel_token,
             sender,
             receiver,
-            config,
+            config: Arc::new(ArcSwap::new(config)),
             saver_fn,
-            formatter,
+            formatter: self.formatter,
+            on_saved: self.on_saved,
+            on_save_error: self.on_save_error,
+            event_webhook: self.event_webhook,
+            enrichment: self.enrichment.map(Arc::new),
         }
     }
 }
 
 impl CallRecordManager {
     fn default_saver(
-        _cancel_token: CancellationToken,
+        cancel_token: CancellationToken,
         formatter: Arc<dyn CallRecordFormatter>,
         config: Arc<CallRecordConfig>,
         record: CallRecord,
@@ -427,8 +653,8 @@ impl CallRecordManager {
             let mut record = record;
             let start_time = Instant::now();
             let result = match config.as_ref() {
-                CallRecordConfig::Local { .. } => {
-                    Self::save_local_record(formatter.clone(), &mut record).await
+                CallRecordConfig::Local { compress, .. } => {
+                    Self::save_local_record(formatter.clone(), compress, &mut record).await
                 }
                 CallRecordConfig::S3 {
                     vendor,
@@ -439,6 +665,11 @@ impl CallRecordManager {
                     endpoint,
                     with_media,
                     keep_media_copy,
+                    sse,
+                    compress,
+                    encryption,
+                    media_upload_concurrency,
+                    verify_upload,
                     ..
                 } => {
                     Self::save_with_s3_like(
@@ -451,6 +682,11 @@ impl CallRecordManager {
                         endpoint,
                         with_media,
                         keep_media_copy,
+                        sse,
+                        compress,
+                        encryption,
+                        media_upload_concurrency,
+                        verify_upload,
                         &record,
                     )
                     .await
@@ -460,6 +696,9 @@ impl CallRecordManager {
                     headers,
                     with_media,
                     keep_media_copy,
+                    max_retries,
+                    retry_backoff_ms,
+                    verify_upload,
                 } => {
                     Self::save_with_http(
                         formatter.clone(),
@@ -467,6 +706,10 @@ impl CallRecordManager {
                         headers,
                         with_media,
                         keep_media_copy,
+                        max_retries,
+                        retry_backoff_ms,
+                        verify_upload,
+                        &cancel_token,
                         &record,
                     )
                     .await
@@ -490,12 +733,14 @@ impl CallRecordManager {
         })
     }
 
-    async fn save_local_record(
+    pub async fn save_local_record(
         formatter: Arc<dyn CallRecordFormatter>,
+        compress: &Option<bool>,
         record: &mut CallRecord,
     ) -> Result<String> {
-        let file_content = formatter.format(record)?;
-        let file_name = formatter.format_file_name(record);
+        let compress = compress.unwrap_or(false);
+        let mut content = formatter.format(record)?;
+        let file_name = formatter.format_stored_file_name(record, compress);
 
         // Ensure parent directory exists
         if let Some(parent) = Path::new(&file_name).parent() {
@@ -504,23 +749,139 @@ impl CallRecordManager {
             })?;
         }
 
-        let mut file = File::create(&file_name).await.map_err(|e| {
+        let header = formatter.append_header();
+        let lock = header.as_ref().map(|_| append_lock(&file_name));
+        let _guard = match &lock {
+            Some(lock) => Some(lock.lock().await),
+            None => None,
+        };
+        let file = match header {
+            Some(_) => {
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&file_name)
+                    .await
+            }
+            None => File::create(&file_name).await,
+        };
+        let mut file = file.map_err(|e| {
             anyhow::anyhow!("Failed to create call record file {}: {}", file_name, e)
         })?;
-        file.write_all(file_content.as_bytes()).await?;
+        let is_new = file.metadata().await?.len() == 0;
+        if let Some(header) = header.filter(|_| is_new) {
+            content = header + &content;
+        }
+        let file_content = encode_call_record(content, compress)?;
+        file.write_all(&file_content).await?;
         file.flush().await?;
         Ok(file_name.to_string())
     }
 
+    /// Upload the call record as a multipart form, retrying with exponential
+    /// backoff on connection errors, 408, 429 and 5xx responses.
     pub async fn save_with_http(
         formatter: Arc<dyn CallRecordFormatter>,
         url: &String,
         headers: &Option<HashMap<String, String>>,
         with_media: &Option<bool>,
         keep_media_copy: &Option<bool>,
+        max_retries: &Option<u32>,
+        retry_backoff_ms: &Option<u64>,
+        verify_upload: &Option<bool>,
+        cancel_token: &CancellationToken,
         record: &CallRecord,
     ) -> Result<String> {
-        let client = reqwest::Client::new();
+        let client = crate::net_tool::http_client();
+        let checksum = match verify_upload {
+            Some(true) => Some(hex::encode(Sha256::digest(formatter.format(record)?))),
+            _ => None,
+        };
+        let max_retries = max_retries.unwrap_or(0);
+        let mut backoff = Duration::from_millis(retry_backoff_ms.unwrap_or(500));
+        let mut attempt = 0;
+        let response_text = loop {
+            // The multipart body is consumed by each request, so it is rebuilt
+            let form = Self::build_http_form(formatter.clone(), with_media, record).await?;
+            let mut request =
+                crate::net_tool::with_request_timeout(client.post(url).multipart(form));
+            if let Some(headers_map) = headers {
+                for (key, value) in headers_map {
+                    request = request.header(key, value);
+                }
+            }
+            if let Some(checksum) = &checksum {
+                request = request.header(CALLLOG_CHECKSUM_HEADER, checksum);
+            }
+            let (error, retryable) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    let echoed = response
+                        .headers()
+                        .get(CALLLOG_CHECKSUM_HEADER)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.to_ascii_lowercase());
+                    match (&checksum, echoed) {
+                        (Some(expected), Some(echoed)) if *expected != echoed => (
+                            anyhow::anyhow!(
+                                "HTTP upload checksum mismatch: sent {}, collector got {}",
+                                expected,
+                                echoed
+                            ),
+                            true,
+                        ),
+                        _ => break response.text().await.unwrap_or_default(),
+                    }
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT;
+                    let error = anyhow::anyhow!(
+                        "HTTP upload failed with status: {} - {}",
+                        status,
+                        response.text().await.unwrap_or_default()
+                    );
+                    (error, retryable)
+                }
+                Err(e) => (e.into(), true),
+            };
+            if !retryable || attempt >= max_retries {
+                return Err(error);
+            }
+            attempt += 1;
+            warn!(
+                call_id = record.call_id,
+                attempt,
+                ?backoff,
+                "HTTP upload failed, retrying: {}",
+                error
+            );
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    return Err(error.context("HTTP upload retry cancelled"));
+                }
+                _ = tokio::time::sleep(backoff) => {}
+            }
+            backoff = backoff.saturating_mul(2).min(MAX_RETRY_BACKOFF);
+        };
+
+        if keep_media_copy.unwrap_or(false) {
+            for media in &record.recorder {
+                let p = Path::new(&media.path);
+                if p.exists() {
+                    tokio::fs::remove_file(p).await.ok();
+                }
+            }
+        }
+        Ok(format!("HTTP upload successful: {}", response_text))
+    }
+
+    async fn build_http_form(
+        formatter: Arc<dyn CallRecordFormatter>,
+        with_media: &Option<bool>,
+        record: &CallRecord,
+    ) -> Result<reqwest::multipart::Form> {
         // Serialize call record to JSON
         let call_log_json = formatter.format(record)?;
         // Create multipart form
@@ -579,32 +940,7 @@ impl CallRecordManager {
                 }
             }
         }
-        let mut request = client.post(url).multipart(form);
-        if let Some(headers_map) = headers {
-            for (key, value) in headers_map {
-                request = request.header(key, value);
-            }
-        }
-        let response = request.send().await?;
-        if response.status().is_success() {
-            let response_text = response.text().await.unwrap_or_default();
-
-            if keep_media_copy.unwrap_or(false) {
-                for media in &record.recorder {
-                    let p = Path::new(&media.path);
-                    if p.exists() {
-                        tokio::fs::remove_file(p).await.ok();
-                    }
-                }
-            }
-            Ok(format!("HTTP upload successful: {}", response_text))
-        } else {
-            Err(anyhow::anyhow!(
-                "HTTP upload failed with status: {} - {}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            ))
-        }
+        Ok(form)
     }
 
     pub async fn save_with_s3_like(
@@ -617,85 +953,186 @@ impl CallRecordManager {
         endpoint: &String,
         with_media: &Option<bool>,
         keep_media_copy: &Option<bool>,
+        sse: &Option<S3SseConfig>,
+        compress: &Option<bool>,
+        encryption: &Option<EncryptionConfig>,
+        media_upload_concurrency: &Option<usize>,
+        verify_upload: &Option<bool>,
+        record: &CallRecord,
+    ) -> Result<String> {
+        let object_store = build_object_store_from_s3(
+            vendor,
+            bucket,
+            region,
+            access_key,
+            secret_key,
+            endpoint,
+            sse.as_ref(),
+        )?;
+        Self::save_to_object_store(
+            object_store,
+            endpoint,
+            formatter,
+            with_media,
+            keep_media_copy,
+            compress,
+            encryption,
+            media_upload_concurrency,
+            verify_upload,
+            record,
+        )
+        .await
+    }
+
+    /// Upload a record and its media to `object_store`, returning the record's
+    /// location under `endpoint`
+    pub async fn save_to_object_store(
+        object_store: Arc<dyn ObjectStore>,
+        endpoint: &str,
+        formatter: Arc<dyn CallRecordFormatter>,
+        with_media: &Option<bool>,
+        keep_media_copy: &Option<bool>,
+        compress: &Option<bool>,
+        encryption: &Option<EncryptionConfig>,
+        media_upload_concurrency: &Option<usize>,
+        verify_upload: &Option<bool>,
         record: &CallRecord,
     ) -> Result<String> {
         let start_time = Instant::now();
-        let object_store =
-            build_object_store_from_s3(vendor, bucket, region, access_key, secret_key, endpoint)?;
+
+        let recipient = encryption
+            .as_ref()
+            .map(|e| encryption::parse_recipient(&e.recipient))
+            .transpose()?;
+        // Object path of an upload, with `.age` appended when encrypting
+        let object_path = |path: String| match recipient {
+            Some(_) => ObjectPath::from(format!("{}.{}", path, encryption::AGE_EXTENSION)),
+            None => ObjectPath::from(path),
+        };
 
         // Serialize call record to JSON
-        let call_log_json = formatter.format(record)?;
-        // Upload call log JSON
-        let filename = formatter.format_file_name(record);
+        let compress = compress.unwrap_or(false);
+        let mut content = formatter.format(record)?;
+        let filename = formatter.format_stored_file_name(record, compress);
         let local_files = vec![filename.clone()];
-        let json_path = ObjectPath::from(filename);
-        let buf_size = call_log_json.len();
-        match object_store
-            .put(&json_path, PutPayload::from(call_log_json))
-            .await
-        {
-            Ok(_) => {
-                info!(
-                    elapsed = start_time.elapsed().as_secs_f64(),
-                    %json_path,
-                    buf_size,
-                    "upload call record"
-                );
+        // Each record of an appending formatter is its own object, with the header
+        let object_name = match formatter.append_header() {
+            Some(header) => {
+                content = header + &content;
+                record_object_name(&filename, &record.call_id)
             }
-            Err(e) => {
-                warn!(
-                   %json_path,
-                    "failed to upload call record: {}", e
-                );
+            None => filename,
+        };
+        let mut call_log_json = encode_call_record(content, compress)?;
+        if let Some(recipient) = &recipient {
+            call_log_json = encryption::encrypt(&call_log_json, recipient)?;
+        }
+        // Upload call log JSON
+        let json_path = object_path(object_name);
+        let buf_size = call_log_json.len();
+        let verify_upload = verify_upload.unwrap_or(false);
+        let payload = PutPayload::from(call_log_json);
+        let mut attempt = 1;
+        loop {
+            let result = match object_store.put(&json_path, payload.clone()).await {
+                Ok(_) if verify_upload => {
+                    verify_object_size(object_store.as_ref(), &json_path, buf_size).await
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(()) => {
+                    info!(
+                        elapsed = start_time.elapsed().as_secs_f64(),
+                        %json_path,
+                        buf_size,
+                        "upload call record"
+                    );
+                    break;
+                }
+                Err(e) if verify_upload && attempt < VERIFY_UPLOAD_ATTEMPTS => {
+                    warn!(%json_path, attempt, "call record upload not verified, retrying: {}", e);
+                    attempt += 1;
+                }
+                // Keep the local files so the record can be uploaded again
+                Err(e) if verify_upload => {
+                    return Err(e.context(format!("failed to verify upload of {}", json_path)));
+                }
+                Err(e) => {
+                    warn!(
+                       %json_path,
+                        "failed to upload call record: {}", e
+                    );
+                    break;
+                }
             }
         }
         // Upload media files if with_media is true
+        let mut failed_media = vec![];
         if with_media.unwrap_or(false) {
             let mut media_files = vec![];
             for media in &record.recorder {
                 if Path::new(&media.path).exists() {
-                    let media_path = ObjectPath::from(formatter.format_media_path(record, media));
+                    let media_path = object_path(formatter.format_media_path(record, media));
                     media_files.push((media.path.clone(), media_path));
                 }
             }
             if let Some(dump_events_file) = &record.dump_event_file {
                 if Path::new(&dump_events_file).exists() {
-                    let dump_events_path =
-                        ObjectPath::from(formatter.format_dump_events_path(record));
+                    let dump_events_path = object_path(formatter.format_dump_events_path(record));
                     media_files.push((dump_events_file.clone(), dump_events_path));
                 }
             }
+            let concurrency = media_upload_concurrency
+                .unwrap_or(DEFAULT_MEDIA_UPLOAD_CONCURRENCY)
+                .max(1);
+            let store = object_store.as_ref();
+            let media_recipient = recipient.as_ref();
+            let mut uploads = FuturesUnordered::new();
+            let mut results = vec![];
             for (path, media_path) in &media_files {
-                let start_time = Instant::now();
-                let file_content = match tokio::fs::read(path).await {
-                    Ok(file_content) => file_content,
-                    Err(e) => {
-                        warn!("failed to read media file {}: {}", path, e);
-                        continue;
-                    }
-                };
-                let buf_size = file_content.len();
-                match object_store
-                    .put(media_path, PutPayload::from(file_content))
-                    .await
-                {
-                    Ok(_) => {
-                        info!(
-                            elapsed = start_time.elapsed().as_secs_f64(),
-                            %media_path,
-                            buf_size,
-                            "upload media file"
-                        );
-                    }
-                    Err(e) => {
-                        warn!(%media_path,"failed to upload media file: {}", e);
-                    }
+                if uploads.len() >= concurrency {
+                    results.extend(uploads.next().await);
+                }
+                uploads.push(async move {
+                    let result = Self::upload_media_file(
+                        store,
+                        path,
+                        media_path,
+                        media_recipient,
+                        verify_upload,
+                    )
+                    .await;
+                    (path, result)
+                });
+            }
+            while let Some(result) = uploads.next().await {
+                results.push(result);
+            }
+            for (path, result) in results {
+                if let Err(e) = result {
+                    warn!(path, "media upload failed: {:#}", e);
+                    failed_media.push(path.clone());
                 }
             }
+            // Keep the local files so the record can be uploaded again
+            if verify_upload && !failed_media.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} of {} media uploads failed for {}",
+                    failed_media.len(),
+                    media_files.len(),
+                    json_path
+                ));
+            }
         }
-        // Optionally delete local media files if keep_media_copy is false
+        // Optionally delete local media files if keep_media_copy is false,
+        // keeping those that failed to upload
         if !keep_media_copy.unwrap_or(false) {
             for media in &record.recorder {
+                if failed_media.contains(&media.path) {
+                    continue;
+                }
                 let p = Path::new(&media.path);
                 if p.exists() {
                     tokio::fs::remove_file(p).await.ok();
@@ -716,6 +1153,77 @@ impl CallRecordManager {
         ))
     }
 
+    /// Upload one media file. Encrypted uploads are always checked against the
+    /// bytes written. With `verify`, plaintext uploads are re-sent while the
+    /// stored size is off.
+    async fn upload_media_file(
+        object_store: &dyn ObjectStore,
+        path: &str,
+        media_path: &ObjectPath,
+        recipient: Option<&age::x25519::Recipient>,
+        verify: bool,
+    ) -> Result<()> {
+        let start_time = Instant::now();
+        if let Some(recipient) = recipient {
+            let buf_size =
+                encryption::upload_encrypted_file(object_store, media_path, path, recipient)
+                    .await
+                    .map_err(|e| e.context(format!("failed to upload encrypted {}", media_path)))?;
+            if buf_size == 0 {
+                return Err(anyhow::anyhow!(
+                    "encrypted upload of {} is empty",
+                    media_path
+                ));
+            }
+            verify_object_size(object_store, media_path, buf_size)
+                .await
+                .map_err(|e| e.context(format!("failed to verify upload of {}", media_path)))?;
+            info!(
+                elapsed = start_time.elapsed().as_secs_f64(),
+                %media_path,
+                buf_size,
+                "upload encrypted media file"
+            );
+            return Ok(());
+        }
+        let file_content = tokio::fs::read(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to read media file {}: {}", path, e))?;
+        let buf_size = file_content.len();
+        let payload = PutPayload::from(file_content);
+        let mut attempt = 1;
+        loop {
+            let result = match object_store.put(media_path, payload.clone()).await {
+                Ok(_) if verify => verify_object_size(object_store, media_path, buf_size).await,
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(()) => {
+                    info!(
+                        elapsed = start_time.elapsed().as_secs_f64(),
+                        %media_path,
+                        buf_size,
+                        "upload media file"
+                    );
+                    return Ok(());
+                }
+                Err(e) if verify && attempt < VERIFY_UPLOAD_ATTEMPTS => {
+                    warn!(%media_path, attempt, "media upload not verified, retrying: {}", e);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e.context(format!("failed to upload {}", media_path)));
+                }
+            }
+        }
+    }
+
+    /// Handle to replace the backend config used for records saved from now on
+    pub fn config_handle(&self) -> Arc<ArcSwap<CallRecordConfig>> {
+        self.config.clone()
+    }
+
     pub async fn serve(&mut self) {
         let token = self.cancel_token.clone();
         info!("CallRecordManager serving");
@@ -742,14 +1250,46 @@ impl CallRecordManager {
             for record in buffer {
                 let cancel_token_ref = self.cancel_token.clone();
                 let save_fn_ref = self.saver_fn.clone();
-                let config_ref = self.config.clone();
-                let formatter_ref = self.formatter.clone();
+                let config_ref = self.config.load_full();
+                let formatter_ref = self.formatter.clone().unwrap_or_else(|| {
+                    Arc::new(DefaultCallRecordFormatter::new_with_config(&config_ref))
+                });
+                let on_saved = self.on_saved.clone();
+                let on_save_error = self.on_save_error.clone();
+                let event_webhook = self.event_webhook.clone();
+                let enrichment_config = self.enrichment.clone();
 
                 futures.push(async move {
-                    if let Err(e) =
-                        save_fn_ref(cancel_token_ref, formatter_ref, config_ref, record).await
-                    {
-                        warn!("Failed to save call record: {}", e);
+                    let mut record = record;
+                    if let Some(config) = &enrichment_config {
+                        enrichment::enrich_record(config, &mut record).await;
+                    }
+                    let saved_record =
+                        (on_saved.is_some() || event_webhook.is_some()).then(|| record.clone());
+                    let call_id = record.call_id.clone();
+                    let backend = config_ref.backend();
+                    match save_fn_ref(cancel_token_ref, formatter_ref, config_ref, record).await {
+                        Ok(_) => {
+                            if let (Some(webhook), Some(record)) = (&event_webhook, &saved_record) {
+                                webhook.cdr_saved(record);
+                            }
+                            if let (Some(hook), Some(record)) = (on_saved, saved_record) {
+                                crate::spawn(hook(record));
+                            }
+                        }
+                        Err(e) => {
+                            warn!(call_id, backend, "Failed to save call record: {}", e);
+                            if let Some(webhook) = &event_webhook {
+                                webhook.cdr_failed(&call_id);
+                            }
+                            if let Some(hook) = on_save_error {
+                                crate::spawn(hook(CallRecordSaveError {
+                                    call_id,
+                                    backend: backend.to_string(),
+                                    error: e.to_string(),
+                                }));
+                            }
+                        }
                     }
                 });
             }
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tracing::{info, warn};
+
+/// Recursively remove files under `root` last modified more than `max_age` ago.
+/// Files whose name contains one of the `active` session ids are kept, so
+/// recordings and event dumps of in-progress calls are never touched.
+/// Directories left empty are removed too, except today's date directory and
+/// those modified after the cutoff, which a writer may be about to fill.
+/// Returns the number of removed files.
+pub fn remove_expired_files(root: &Path, max_age: Duration, active: &HashSet<String>) -> usize {
+    let Some(cutoff) = SystemTime::now().checked_sub(max_age) else {
+        return 0;
+    };
+    let today = chrono::Local::now().format("%Y%m%d").to_string();
+    remove_expired_in(root, cutoff, &today, active)
+}
+
+fn remove_expired_in(
+    dir: &Path,
+    cutoff: SystemTime,
+    today: &str,
+    active: &HashSet<String>,
+) -> usize {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let metadata = match entry.metadata() {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        let expired = metadata
+            .modified()
+            .map(|modified| modified < cutoff)
+            .unwrap_or(false);
+        if metadata.is_dir() {
+            removed += remove_expired_in(&path, cutoff, today, active);
+            // Drop date directories left empty by the cleanup. The age is
+            // taken before the sweep, since removing files touches it
+            if expired
+                && entry.file_name() != today
+                && std::fs::read_dir(&path)
+                    .map(|mut d| d.next().is_none())
+                    .unwrap_or(false)
+            {
+                std::fs::remove_dir(&path).ok();
+            }
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if active
+            .iter()
+            .any(|session_id| name.contains(session_id.as_str()))
+        {
+            continue;
+        }
+        if !expired {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(_) => {
+                info!(path = %path.display(), "removed expired file");
+                removed += 1;
+            }
+            Err(e) => warn!(path = %path.display(), "failed to remove expired file: {}", e),
+        }
+    }
+    removed
+}
@@ -1,10 +1,39 @@
-use crate::media::{ambiance::AmbianceOption, recorder::RecorderFormat};
+use crate::media::{
+    ambiance::AmbianceOption,
+    loudness::LoudnessOption,
+    recorder::{FormatChangePolicy, RecorderFormat},
+    watermark::WatermarkOption,
+};
+use crate::playbook::InterruptionConfig;
 use crate::useragent::RegisterOption;
 use anyhow::{Error, Result};
 use clap::Parser;
+use futures::Stream;
+use notify::{RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use rustrtc::IceServer;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+/// Top-level keys a config reload applies to new calls. Changes to any other
+/// key only take effect after a restart.
+pub const RELOADABLE_KEYS: &[&str] = &[
+    "rtp_start_port",
+    "rtp_end_port",
+    "ice_servers",
+    "recording",
+    "callrecord",
+    "on_answer_url",
+    "play_allowed_roots",
+    "tts_concurrency",
+];
+
+/// Editors save in several writes, wait for them to settle before re-reading
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -79,7 +108,7 @@ fn default_sip_port() -> u16 {
 }
 
 fn default_config_rtp_start_port() -> Option<u16> {
-    Some(12000)
+    Some(26000)
 }
 
 fn default_config_rtp_end_port() -> Option<u16> {
@@ -118,6 +147,116 @@ fn default_codecs() -> Option<Vec<String>> {
     Some(codecs)
 }
 
+/// Supervisor barge settings, see `Config::barge`
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct BargeConfig {
+    /// Token supervisors must present, as `Authorization: Bearer <token>` or
+    /// the `token` query parameter. Barge is refused while it is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Call lifecycle events POSTed to `url` as
+/// `{event, call_id, timestamp, data}`. Each call has its own queue, so its
+/// events arrive in order; a failed delivery is retried with a doubling delay,
+/// and once the retries are used up the event is appended to `dead_letter_path`
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct EventWebhookConfig {
+    pub url: String,
+    /// Events to send out of `created`, `answered`, `hangup` and `cdr_saved`,
+    /// all of them when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<String>>,
+    /// Retries after the first attempt, default 3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    /// Delay before the first retry, default 1000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay_ms: Option<u64>,
+    /// JSON lines file of undelivered events, default `./config/webhook_dead_letter.jsonl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter_path: Option<String>,
+    /// JSON lines file of events still queued at shutdown, delivered again on
+    /// the next start, default `./config/webhook_pending.jsonl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_path: Option<String>,
+}
+
+impl EventWebhookConfig {
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(3)
+    }
+
+    pub fn retry_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_delay_ms.unwrap_or(1000))
+    }
+
+    pub fn dead_letter_path(&self) -> String {
+        self.dead_letter_path
+            .clone()
+            .unwrap_or_else(|| "./config/webhook_dead_letter.jsonl".to_string())
+    }
+
+    pub fn pending_path(&self) -> String {
+        self.pending_path
+            .clone()
+            .unwrap_or_else(|| "./config/webhook_pending.jsonl".to_string())
+    }
+
+    pub fn wants(&self, event: &str) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.iter().any(|e| e == event))
+    }
+}
+
+/// External lookup merging CRM fields into each CDR's `extras` before it is
+/// saved. `url` is requested with GET and the `caller`, `callee` and `call_id`
+/// query parameters; the fields of a JSON object reply are merged in. A
+/// failed or slow lookup is logged and the CDR saved without them
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct CdrEnrichmentConfig {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+    /// Time allowed for the lookup, default 2000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Settings of the HTTP client shared by outbound requests: CDR uploads, LLM,
+/// RAG, webhooks and HTTP based TTS
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct HttpClientConfig {
+    /// Proxy for all requests, e.g. `http://proxy.corp:3128`. When unset the
+    /// `HTTP_PROXY`/`HTTPS_PROXY` environment variables apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Comma separated hosts bypassing `proxy`, e.g. `localhost,.internal`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+    /// Total time allowed for a one-shot request, including reading the
+    /// response. Streaming LLM and TTS responses are not limited by it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// PEM bundle of extra CA certificates trusted besides the system roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_max_idle_per_host: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// `true` speaks HTTP/2 only, `false` HTTP/1.1 only, unset negotiates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2: Option<bool>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct RecordingPolicy {
@@ -135,6 +274,12 @@ pub struct RecordingPolicy {
     pub path: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub format: Option<RecorderFormat>,
+    /// Default handling of a mid-call codec change, `resample` or `split`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_format_change: Option<FormatChangePolicy>,
+    /// AES-256 key (hex or base64) used to encrypt recordings at rest as `.enc` files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption_key: Option<String>,
 }
 
 impl RecordingPolicy {
@@ -186,12 +331,46 @@ pub struct Config {
     #[serde(default = "default_config_useragent")]
     pub useragent: Option<String>,
     pub register_users: Option<Vec<RegisterOption>>,
+    /// Reject outbound calls routed to a `register_users` trunk that is not currently registered
+    pub registration_admission: Option<bool>,
     #[serde(default = "default_graceful_shutdown")]
     pub graceful_shutdown: Option<bool>,
     pub handler: Option<InviteHandlerConfig>,
+    /// What to do when the playbook assigned to a call can't be loaded
+    pub missing_playbook_action: Option<MissingPlaybookAction>,
     pub accept_timeout: Option<String>,
+    /// How long to keep forwarding events to the client after the call ends, e.g. "500ms"
+    pub hangup_grace_period: Option<String>,
+    /// Admit at most this many new calls per second, SIP INVITEs and websocket
+    /// calls alike. Unlimited when unset
+    pub max_calls_per_sec: Option<f64>,
+    /// Calls admitted at once before `max_calls_per_sec` applies, defaults to
+    /// one second worth of calls
+    pub call_burst: Option<u32>,
+    /// How long a call may wait for admission before it is refused, e.g. "5s"
+    pub call_admission_timeout: Option<String>,
+    /// Allow supervisors to listen to live calls by opening `/call?monitor=<session_id>`
+    pub enable_monitor: Option<bool>,
+    /// Token monitors must present, as `Authorization: Bearer <token>` or the
+    /// `token` query parameter. Monitoring stays off without it
+    pub monitor_token: Option<String>,
+    /// Allow supervisors to join live calls, speaking to both parties, by
+    /// opening `/call?barge=<session_id>`
+    pub barge: Option<BargeConfig>,
+    /// Directories that local files played by the `play` command must reside
+    /// under. Unset allows any path; remote URLs are not affected
+    pub play_allowed_roots: Option<Vec<String>>,
+    /// Audio frames buffered per call while its track catches up. The oldest
+    /// frames are dropped beyond this, defaults to 1500 (about 30s)
+    pub max_audio_buffer_frames: Option<usize>,
+    /// Delete local recordings and CDR files older than this many days
+    pub local_retention_days: Option<u64>,
     #[serde(default = "default_codecs")]
     pub codecs: Option<Vec<String>>,
+    /// `a=fmtp` parameters per codec name offered and answered in SDP, e.g.
+    /// `opus = "maxaveragebitrate=24000;useinbandfec=1"`. Our encoder follows
+    /// the peer's parameters, not these
+    pub codec_fmtp: Option<HashMap<String, String>>,
     pub external_ip: Option<String>,
     #[serde(default = "default_config_rtp_start_port")]
     pub rtp_start_port: Option<u16>,
@@ -200,11 +379,45 @@ pub struct Config {
     #[serde(default = "default_config_rtp_latching")]
     pub enable_rtp_latching: Option<bool>,
     pub rtp_bind_ip: Option<String>,
+    /// Proxy/SBC outbound INVITEs are sent to, with a loose `Route` header so the
+    /// Request-URI keeps the real target, e.g. `sip:sbc.example.com:5060`
+    pub outbound_proxy: Option<String>,
+    /// Reliable provisional responses (100rel/PRACK, RFC 3262): offer `100rel`
+    /// on outbound INVITEs and PRACK the reliable 18x. Inbound calls don't use it
+    pub enable_100rel: Option<bool>,
+    /// How many times a hold/resume offer refused with 491 Request Pending
+    /// (re-INVITE glare, RFC 3261 §14.1) is retried after a random back-off,
+    /// default 2. 0 gives up on the first 491
+    pub reinvite_glare_retries: Option<u32>,
+    /// Diversion/History-Info headers naming the originally called number on
+    /// transfers, for refers that don't set their own `diversion`
+    pub refer_diversion: Option<crate::DiversionOption>,
+    /// SDP attributes stripped from offers/answers sent on SIP. Defaults to the
+    /// WebRTC-only set (extmap, rtcp-fb, msid, ssrc...), an empty list keeps them all
+    pub sip_sdp_filter: Option<Vec<String>>,
 
     pub callrecord: Option<CallRecordConfig>,
+    /// Call variables whose value tags the CDR `tenant`, first match wins. Looked up
+    /// in the call extras (e.g. captured SIP headers) and the call option `extra`.
+    /// Defaults to `tenant`, then `account`
+    pub cdr_tenant_keys: Option<Vec<String>>,
+    /// Look up CRM fields for each CDR before it is saved
+    pub cdr_enrichment: Option<CdrEnrichmentConfig>,
+    /// URL POSTed `{call_id, caller, callee, answer_time, extras}` as soon as a
+    /// call is answered, e.g. for a CRM screen-pop. Overridden per call/playbook
+    pub on_answer_url: Option<String>,
+    pub event_webhook: Option<EventWebhookConfig>,
     #[serde(default = "default_config_media_cache_path")]
     pub media_cache_path: String,
+    /// Max in-flight TTS requests per provider across all calls, e.g. `aliyun = 10`
+    pub tts_concurrency: Option<HashMap<String, usize>>,
+    pub http_client: Option<HttpClientConfig>,
     pub ambiance: Option<AmbianceOption>,
+    pub output_loudness: Option<LoudnessOption>,
+    /// Watermark for calls that don't set their own `watermark`
+    pub watermark: Option<WatermarkOption>,
+    /// Barge-in settings for playbooks without an `interruption` section
+    pub interruption: Option<InterruptionConfig>,
     pub ice_servers: Option<Vec<IceServer>>,
     #[serde(default)]
     pub recording: Option<RecordingPolicy>,
@@ -227,6 +440,18 @@ pub enum InviteHandlerConfig {
     },
 }
 
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum MissingPlaybookAction {
+    /// End the call right away
+    Hangup,
+    /// Play a prompt (e.g. "service unavailable") once answered, then hang up
+    Prompt { prompt: String },
+    /// Run another playbook, defaults to the playbook handler's `default`
+    Fallback { playbook: Option<String> },
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct PlaybookRule {
@@ -247,12 +472,31 @@ pub enum S3Vendor {
     DigitalOcean,
 }
 
+/// Server-side encryption applied to S3 uploads
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct S3SseConfig {
+    /// "AES256", "aws:kms" or "aws:kms:dsse"
+    pub algorithm: String,
+    /// KMS key id or ARN, used with the aws:kms algorithms
+    pub kms_key_id: Option<String>,
+    pub bucket_key_enabled: Option<bool>,
+}
+
+/// Client-side age encryption applied to uploads before they leave the host
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct EncryptionConfig {
+    /// age x25519 public key the uploads are encrypted to, e.g. `age1...`
+    pub recipient: String,
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum CallRecordConfig {
     Local {
         root: String,
+        /// Gzip the call record JSON, saved with a `.gz` suffix
+        compress: Option<bool>,
     },
     S3 {
         vendor: S3Vendor,
@@ -264,15 +508,41 @@ pub enum CallRecordConfig {
         root: String,
         with_media: Option<bool>,
         keep_media_copy: Option<bool>,
+        sse: Option<S3SseConfig>,
+        /// Gzip the call record JSON, uploaded with a `.gz` suffix
+        compress: Option<bool>,
+        /// Encrypt the call record and media before upload, stored with a `.age` suffix
+        encryption: Option<EncryptionConfig>,
+        /// Media files of one record uploaded at the same time (default: 4)
+        media_upload_concurrency: Option<usize>,
+        /// Check each uploaded object's size with a HEAD request, re-uploading on mismatch
+        verify_upload: Option<bool>,
     },
     Http {
         url: String,
         headers: Option<HashMap<String, String>>,
         with_media: Option<bool>,
         keep_media_copy: Option<bool>,
+        /// Retries after a failed upload, 0 gives up on the first failure
+        max_retries: Option<u32>,
+        /// Delay before the first retry, doubled on each one up to 30s (default: 500)
+        retry_backoff_ms: Option<u64>,
+        /// Retry when the collector echoes an `X-Calllog-Sha256` that doesn't match
+        verify_upload: Option<bool>,
     },
 }
 
+impl CallRecordConfig {
+    /// Backend name as written in the `type` field
+    pub fn backend(&self) -> &'static str {
+        match self {
+            Self::Local { .. } => "local",
+            Self::S3 { .. } => "s3",
+            Self::Http { .. } => "http",
+        }
+    }
+}
+
 impl Default for CallRecordConfig {
     fn default() -> Self {
         Self::Local {
@@ -280,6 +550,7 @@ impl Default for CallRecordConfig {
             root: "./config/cdr".to_string(),
             #[cfg(not(target_os = "windows"))]
             root: "./config/cdr".to_string(),
+            compress: None,
         }
     }
 }
@@ -295,25 +566,57 @@ impl Default for Config {
             udp_port: default_sip_port(),
             useragent: None,
             register_users: None,
+            registration_admission: None,
             graceful_shutdown: Some(true),
             handler: None,
+            missing_playbook_action: None,
             accept_timeout: Some("50s".to_string()),
+            hangup_grace_period: None,
+            max_calls_per_sec: None,
+            call_burst: None,
+            call_admission_timeout: None,
+            enable_monitor: None,
+            monitor_token: None,
+            barge: None,
+            play_allowed_roots: None,
+            max_audio_buffer_frames: None,
+            local_retention_days: None,
             media_cache_path: default_config_media_cache_path(),
+            tts_concurrency: None,
+            http_client: None,
             ambiance: None,
+            output_loudness: None,
+            watermark: None,
+            interruption: None,
             callrecord: None,
+            cdr_tenant_keys: None,
+            cdr_enrichment: None,
+            on_answer_url: None,
+            event_webhook: None,
             ice_servers: None,
             codecs: None,
+            codec_fmtp: None,
             external_ip: None,
             rtp_start_port: default_config_rtp_start_port(),
             rtp_end_port: default_config_rtp_end_port(),
             enable_rtp_latching: Some(true),
             rtp_bind_ip: None,
+            outbound_proxy: None,
+            enable_100rel: None,
+            reinvite_glare_retries: None,
+            refer_diversion: None,
+            sip_sdp_filter: None,
             recording: None,
             rewrites: None,
         }
     }
 }
 
+/// Call variables checked for the CDR tenant when `cdr_tenant_keys` is not set
+pub fn default_cdr_tenant_keys() -> Vec<String> {
+    vec!["tenant".to_string(), "account".to_string()]
+}
+
 impl Clone for Config {
     fn clone(&self) -> Self {
         // This is a bit expensive but Config is not cloned often in hot paths
@@ -323,12 +626,296 @@ impl Clone for Config {
     }
 }
 
+/// A problem found by `Config::validate`, naming the offending key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+
+    /// All `errors` on one line, e.g. for a log message
+    pub fn join(errors: &[ConfigError]) -> String {
+        errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// `stun:host[:port]`, `turn:host[:port][?transport=udp]` and their secure variants
+fn is_valid_ice_url(url: &str) -> bool {
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return false;
+    };
+    let host = rest.split('?').next().unwrap_or_default();
+    let host = host.rsplit_once(':').map_or(host, |(host, port)| {
+        if port.parse::<u16>().is_ok() {
+            host
+        } else {
+            ""
+        }
+    });
+    matches!(scheme, "stun" | "stuns" | "turn" | "turns")
+        && !host.is_empty()
+        && !host.contains(['/', ' '])
+}
+
+/// Check that `path` can be created, or written to when it already exists, by
+/// creating a scratch file in its closest existing ancestor. Permission bits
+/// alone miss ACLs, read-only mounts and the user we run as.
+fn check_writable_parent(path: &str) -> Result<(), String> {
+    let dir = Path::new(path)
+        .ancestors()
+        .skip(1)
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }
+        })
+        .find(|dir| dir.exists())
+        .unwrap_or(Path::new("."));
+    match std::fs::metadata(dir) {
+        Ok(meta) if !meta.is_dir() => Err(format!("{} is not a directory", dir.display())),
+        Ok(_) => tempfile::NamedTempFile::new_in(dir)
+            .map(|_| ())
+            .map_err(|e| format!("{} is not writable: {}", dir.display(), e)),
+        Err(e) => Err(format!("{}: {}", dir.display(), e)),
+    }
+}
+
+static RE_ENV_VAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\{([^}:]+)(?::-([^}]*))?\}").unwrap());
+
+/// Expand `${VAR}` and `${VAR:-default}` from the environment. A variable
+/// that is unset and has no default keeps its placeholder.
+pub(crate) fn expand_env_vars(input: &str) -> String {
+    expand_vars(input, &|name| std::env::var(name).ok())
+}
+
+fn expand_vars(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    RE_ENV_VAR
+        .replace_all(input, |caps: &regex::Captures| {
+            match (lookup(&caps[1]), caps.get(2)) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.as_str().to_string(),
+                (None, None) => caps[0].to_string(),
+            }
+        })
+        .to_string()
+}
+
+/// Expand the variables of every string value of a parsed TOML document
+fn expand_toml_vars(value: &mut toml::Value, lookup: &dyn Fn(&str) -> Option<String>) {
+    match value {
+        toml::Value::String(s) => *s = expand_vars(s, lookup),
+        toml::Value::Array(items) => {
+            for item in items {
+                expand_toml_vars(item, lookup);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                expand_toml_vars(item, lookup);
+            }
+        }
+        _ => {}
+    }
+}
+
 impl Config {
+    /// Read and parse the config file, expanding `${VAR}` and
+    /// `${VAR:-default}` environment variables in its string values
     pub fn load(path: &str) -> Result<Self, Error> {
-        let config: Self = toml::from_str(
-            &std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", e, path))?,
-        )?;
-        Ok(config)
+        let content =
+            std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", e, path))?;
+        Self::parse(&content, &|name| std::env::var(name).ok())
+    }
+
+    /// Parse `content`, expanding variables after parsing so comments are
+    /// left alone and values needn't be TOML-escaped
+    fn parse(content: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let mut value = toml::Value::Table(toml::from_str(content)?);
+        expand_toml_vars(&mut value, lookup);
+        Ok(value.try_into()?)
+    }
+
+    /// Watch the config file at `path` and yield it re-parsed after every
+    /// change. Contents that fail to parse or validate are logged and skipped.
+    pub fn watch(path: &str) -> Result<impl Stream<Item = Config> + Send + 'static> {
+        let path = PathBuf::from(path);
+        let file_name = path.file_name().map(|name| name.to_owned());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let touches_file = event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name() == file_name.as_deref());
+                if touches_file && (event.kind.is_modify() || event.kind.is_create()) {
+                    tx.send(()).ok();
+                }
+            })?;
+        // Watch the directory, editors often replace the file with a rename
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(async_stream::stream! {
+            let _watcher = watcher;
+            while rx.recv().await.is_some() {
+                tokio::time::sleep(WATCH_DEBOUNCE).await;
+                while rx.try_recv().is_ok() {}
+                let loaded = Config::load(&path.to_string_lossy()).and_then(|config| {
+                    config
+                        .validate()
+                        .map(|_| config)
+                        .map_err(|errors| anyhow::anyhow!(ConfigError::join(&errors)))
+                });
+                match loaded {
+                    Ok(config) => yield config,
+                    Err(e) => warn!(path = %path.display(), "ignoring invalid config: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Check the settings that would otherwise only fail deep in a call,
+    /// returning every problem found rather than the first one. Run at
+    /// startup and on each reloaded config before it replaces the running one.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        if let Some((start, end)) = self.rtp_start_port.zip(self.rtp_end_port) {
+            if start > end {
+                errors.push(ConfigError::new(
+                    "rtp_start_port",
+                    format!("{} is above rtp_end_port {}", start, end),
+                ));
+            } else if (start..=end).contains(&self.udp_port) {
+                errors.push(ConfigError::new(
+                    "udp_port",
+                    format!(
+                        "{} is inside the RTP port range {}-{}",
+                        self.udp_port, start, end
+                    ),
+                ));
+            }
+        }
+
+        if let Some(CallRecordConfig::S3 {
+            bucket,
+            region,
+            access_key,
+            secret_key,
+            ..
+        }) = &self.callrecord
+        {
+            for (key, value) in [
+                ("bucket", bucket),
+                ("region", region),
+                ("access_key", access_key),
+                ("secret_key", secret_key),
+            ] {
+                if value.trim().is_empty() {
+                    errors.push(ConfigError::new(
+                        format!("callrecord.{}", key),
+                        "must not be empty for the s3 backend",
+                    ));
+                }
+            }
+        }
+
+        for url in self.ice_servers.iter().flatten().flat_map(|s| &s.urls) {
+            if !is_valid_ice_url(url) {
+                errors.push(ConfigError::new(
+                    "ice_servers",
+                    format!(
+                        "invalid url '{}', expected stun:, stuns:, turn: or turns: followed by a host",
+                        url
+                    ),
+                ));
+            }
+        }
+
+        if let Err(message) = check_writable_parent(&self.media_cache_path) {
+            errors.push(ConfigError::new("media_cache_path", message));
+        }
+
+        if let Some(webhook) = &self.event_webhook {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                errors.push(ConfigError::new(
+                    "event_webhook.url",
+                    format!(
+                        "invalid url '{}', expected http:// or https://",
+                        webhook.url
+                    ),
+                ));
+            }
+            if let Err(message) = check_writable_parent(&webhook.dead_letter_path()) {
+                errors.push(ConfigError::new("event_webhook.dead_letter_path", message));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Top-level keys whose value differs between `self` and `other`
+    pub fn changed_keys(&self, other: &Config) -> Result<Vec<String>> {
+        let (toml::Value::Table(current), toml::Value::Table(other)) =
+            (toml::Value::try_from(self)?, toml::Value::try_from(other)?)
+        else {
+            return Err(anyhow::anyhow!("config does not serialize to a table"));
+        };
+        let mut keys: Vec<String> = current
+            .keys()
+            .chain(other.keys())
+            .filter(|key| current.get(*key) != other.get(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Copy of `self` with the settings in `RELOADABLE_KEYS` taken from `reloaded`
+    pub fn with_reloadable(&self, reloaded: &Config) -> Config {
+        let mut config = self.clone();
+        config.rtp_start_port = reloaded.rtp_start_port;
+        config.rtp_end_port = reloaded.rtp_end_port;
+        config.ice_servers = reloaded.ice_servers.clone();
+        config.recording = reloaded.recording.clone();
+        config.callrecord = reloaded.callrecord.clone();
+        config.on_answer_url = reloaded.on_answer_url.clone();
+        config.play_allowed_roots = reloaded.play_allowed_roots.clone();
+        config.tts_concurrency = reloaded.tts_concurrency.clone();
+        config
     }
 
     pub fn recorder_path(&self) -> String {
@@ -345,6 +932,30 @@ impl Config {
             .unwrap_or_default()
     }
 
+    /// Check a `play` url against `play_allowed_roots`. The path is resolved
+    /// first, so `..` segments and symlinks can't escape the allowed roots.
+    pub fn check_play_path(&self, url: &str) -> Result<()> {
+        let Some(roots) = self.play_allowed_roots.as_ref() else {
+            return Ok(());
+        };
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(());
+        }
+        let path = std::fs::canonicalize(url)
+            .map_err(|e| anyhow::anyhow!("play path {} rejected: {}", url, e))?;
+        let allowed = roots
+            .iter()
+            .filter_map(|root| std::fs::canonicalize(root).ok())
+            .any(|root| path.starts_with(root));
+        if !allowed {
+            return Err(anyhow::anyhow!(
+                "play path {} rejected: outside of the allowed roots",
+                url
+            ));
+        }
+        Ok(())
+    }
+
     pub fn ensure_recording_defaults(&mut self) -> bool {
         let mut fallback = false;
 
@@ -455,4 +1066,170 @@ url = "http://example.com/webhook"
             panic!("Expected Webhook handler config");
         }
     }
+
+    #[test]
+    fn test_load_expands_env_vars() {
+        let env = HashMap::from([
+            ("TEST_CONFIG_S3_SECRET", r#"s3"secret\"#),
+            ("TEST_CONFIG_WEBHOOK_TOKEN", "Bearer abc"),
+        ]);
+        let lookup = |name: &str| env.get(name).map(|value| value.to_string());
+        let content = r#"
+http_addr = "0.0.0.0:8080"
+# secret_key = "${TEST_CONFIG_S3_SECRET}" in a comment is left alone
+
+[callrecord]
+type = "s3"
+vendor = "minio"
+bucket = "cdr"
+region = "${TEST_CONFIG_UNSET_REGION:-us-east-1}"
+access_key = "AKIA"
+secret_key = "${TEST_CONFIG_S3_SECRET}"
+endpoint = "http://127.0.0.1:9000"
+root = "cdr/${TEST_CONFIG_UNSET_ROOT}"
+
+[handler]
+type = "webhook"
+url = "http://example.com/webhook"
+headers = [["Authorization", "${TEST_CONFIG_WEBHOOK_TOKEN:-none}"]]
+"#;
+
+        let config = Config::parse(content, &lookup).unwrap();
+        let Some(CallRecordConfig::S3 {
+            secret_key,
+            region,
+            root,
+            ..
+        }) = config.callrecord
+        else {
+            panic!("Expected S3 callrecord config");
+        };
+        // Quotes and backslashes in a value don't need escaping
+        assert_eq!(secret_key, r#"s3"secret\"#);
+        // Unset: the default is used, or the placeholder kept without one
+        assert_eq!(region, "us-east-1");
+        assert_eq!(root, "cdr/${TEST_CONFIG_UNSET_ROOT}");
+        let Some(InviteHandlerConfig::Webhook { headers, .. }) = config.handler else {
+            panic!("Expected Webhook handler config");
+        };
+        assert_eq!(
+            headers.unwrap(),
+            vec![("Authorization".to_string(), "Bearer abc".to_string())]
+        );
+    }
+
+    /// A valid config exercising every validated setting
+    fn validated_config(cache_dir: &Path) -> Config {
+        let toml_config = format!(
+            r#"
+addr = "0.0.0.0"
+udp_port = 5060
+rtp_start_port = 20000
+rtp_end_port = 30000
+media_cache_path = "{}"
+
+[[ice_servers]]
+urls = ["stun:stun.l.google.com:19302", "turn:turn.example.com:3478?transport=udp"]
+
+[callrecord]
+type = "s3"
+vendor = "minio"
+bucket = "cdr"
+region = "us-east-1"
+access_key = "AKIA"
+secret_key = "secret"
+endpoint = "http://127.0.0.1:9000"
+root = "cdr"
+"#,
+            cache_dir.join("mediacache").display()
+        );
+        toml::from_str(&toml_config).unwrap()
+    }
+
+    fn error_keys(config: &Config) -> Vec<String> {
+        config
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.key)
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_accepts_valid_config() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(validated_config(dir.path()).validate(), Ok(()));
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_rejects_reversed_rtp_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = validated_config(dir.path());
+        config.rtp_start_port = Some(40000);
+        assert_eq!(error_keys(&config), vec!["rtp_start_port"]);
+    }
+
+    #[test]
+    fn test_validate_rejects_sip_port_in_rtp_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = validated_config(dir.path());
+        config.udp_port = 25060;
+        assert_eq!(error_keys(&config), vec!["udp_port"]);
+    }
+
+    #[test]
+    fn test_validate_rejects_incomplete_s3() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = validated_config(dir.path());
+        if let Some(CallRecordConfig::S3 {
+            bucket, secret_key, ..
+        }) = config.callrecord.as_mut()
+        {
+            bucket.clear();
+            *secret_key = " ".to_string();
+        }
+        assert_eq!(
+            error_keys(&config),
+            vec!["callrecord.bucket", "callrecord.secret_key"]
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_invalid_ice_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = validated_config(dir.path());
+        config.ice_servers = Some(vec![IceServer::new(vec![
+            "stun.l.google.com:19302".to_string(),
+            "turn:".to_string(),
+            "stun:stun.example.com:port".to_string(),
+        ])]);
+        assert_eq!(
+            error_keys(&config),
+            vec!["ice_servers", "ice_servers", "ice_servers"]
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unwritable_media_cache_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+        let mut config = validated_config(dir.path());
+        config.media_cache_path = file.join("mediacache").to_string_lossy().to_string();
+        assert_eq!(error_keys(&config), vec!["media_cache_path"]);
+    }
+
+    #[test]
+    fn test_validate_collects_every_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = validated_config(dir.path());
+        config.rtp_end_port = Some(10000);
+        config.ice_servers = Some(vec![IceServer::new(vec!["http://example.com".to_string()])]);
+        if let Some(CallRecordConfig::S3 { region, .. }) = config.callrecord.as_mut() {
+            region.clear();
+        }
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors.len(), 3, "errors: {}", ConfigError::join(&errors));
+    }
 }
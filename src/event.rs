@@ -115,6 +115,11 @@ pub enum SessionEvent {
         track_id: String,
         timestamp: u64,
     },
+    /// No inbound media received after answer
+    MediaTimeout {
+        track_id: String,
+        timestamp: u64,
+    },
     Dtmf {
         track_id: String,
         timestamp: u64,
@@ -146,6 +151,11 @@ pub enum SessionEvent {
         total_duration: u32,      // whole tts duration
         current: u32,             // elapsed time since start of tts
     },
+    /// A DTMF collector's prompt was cut short by a key press or speech
+    CollectInterrupted {
+        timestamp: u64,
+        var_name: String,
+    },
     AsrFinal {
         track_id: String,
         timestamp: u64,
@@ -156,6 +166,9 @@ pub enum SessionEvent {
         is_filler: Option<bool>,
         confidence: Option<f32>,
         task_id: Option<String>,
+        /// Transcript as the recognizer returned it, when `formatTranscripts`
+        /// rewrote `text`
+        raw_text: Option<String>,
     },
     AsrDelta {
         track_id: String,
@@ -167,6 +180,18 @@ pub enum SessionEvent {
         is_filler: Option<bool>,
         confidence: Option<f32>,
         task_id: Option<String>,
+        /// Leading part of `text` the provider marked as final; None when unknown
+        stable_text: Option<String>,
+        /// Trailing part of `text` that may still change
+        unstable_text: Option<String>,
+    },
+    /// Periodic link quality derived from RTCP reports
+    QualityStats {
+        track_id: String,
+        timestamp: u64,
+        jitter_ms: f64,
+        loss_pct: f64,
+        rtt_ms: Option<f64>,
     },
     Metrics {
         timestamp: u64,
@@ -193,6 +218,13 @@ pub enum SessionEvent {
         sender: String,
         extra: Option<HashMap<String, String>>,
     },
+    /// Text message received on a WebRTC data channel
+    DataChannel {
+        track_id: String,
+        timestamp: u64,
+        label: String,
+        data: String,
+    },
     Binary {
         track_id: String,
         timestamp: u64,
@@ -204,6 +236,43 @@ pub enum SessionEvent {
     },
 }
 
+impl SessionEvent {
+    /// The `event` tag this event is serialized with
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            SessionEvent::Incoming { .. } => "incoming",
+            SessionEvent::Answer { .. } => "answer",
+            SessionEvent::Reject { .. } => "reject",
+            SessionEvent::Ringing { .. } => "ringing",
+            SessionEvent::Hangup { .. } => "hangup",
+            SessionEvent::AnswerMachineDetection { .. } => "answerMachineDetection",
+            SessionEvent::Interrupt { .. } => "interrupt",
+            SessionEvent::FunctionCall { .. } => "functionCall",
+            SessionEvent::Speaking { .. } => "speaking",
+            SessionEvent::Silence { .. } => "silence",
+            SessionEvent::Eou { .. } => "eou",
+            SessionEvent::Inactivity { .. } => "inactivity",
+            SessionEvent::MediaTimeout { .. } => "mediaTimeout",
+            SessionEvent::Dtmf { .. } => "dtmf",
+            SessionEvent::Hold { .. } => "hold",
+            SessionEvent::TrackStart { .. } => "trackStart",
+            SessionEvent::TrackEnd { .. } => "trackEnd",
+            SessionEvent::Interruption { .. } => "interruption",
+            SessionEvent::CollectInterrupted { .. } => "collectInterrupted",
+            SessionEvent::AsrFinal { .. } => "asrFinal",
+            SessionEvent::AsrDelta { .. } => "asrDelta",
+            SessionEvent::QualityStats { .. } => "qualityStats",
+            SessionEvent::Metrics { .. } => "metrics",
+            SessionEvent::Error { .. } => "error",
+            SessionEvent::AddHistory { .. } => "addHistory",
+            SessionEvent::Other { .. } => "other",
+            SessionEvent::DataChannel { .. } => "dataChannel",
+            SessionEvent::Binary { .. } => "binary",
+            SessionEvent::Ping { .. } => "ping",
+        }
+    }
+}
+
 impl Display for SessionEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
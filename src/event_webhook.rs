@@ -0,0 +1,268 @@
+use crate::{callrecord::CallRecord, config::EventWebhookConfig};
+use chrono::Utc;
+use serde_json::{Value, json};
+use std::{collections::HashMap, path::Path, sync::Arc, sync::Mutex};
+use tokio::{io::AsyncWriteExt, select, sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Delivers `event_webhook` events, one queue per call. A call's queue stays
+/// open from its first event until it is closed, after `hangup`, or after
+/// `cdr_saved` when the call record is reported too, and is drained before
+/// its task ends. Events still queued when `cancel_token` fires are saved to
+/// `pending_path` and delivered again by [`EventWebhook::resume_pending`].
+pub struct EventWebhook {
+    config: EventWebhookConfig,
+    /// Whether the built-in call record manager reports `cdr_saved`, so
+    /// queues stay open after `hangup`
+    report_cdr: bool,
+    queues: Mutex<HashMap<String, mpsc::UnboundedSender<Value>>>,
+    /// Queue tasks, awaited by [`EventWebhook::shutdown`]
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    cancel_token: CancellationToken,
+    /// Held while appending to the dead letter or pending file, so lines
+    /// written by different queues don't interleave
+    file_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl EventWebhook {
+    pub fn new(
+        config: EventWebhookConfig,
+        report_cdr: bool,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        let report_cdr = report_cdr && config.wants("cdr_saved");
+        Self {
+            config,
+            report_cdr,
+            queues: Mutex::new(HashMap::new()),
+            tasks: Mutex::new(Vec::new()),
+            cancel_token,
+            file_lock: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Queue `event` of `call_id` behind the call's earlier events
+    pub fn send(&self, call_id: &str, event: &str, data: Value) {
+        if !self.config.wants(event) {
+            return;
+        }
+        let payload = json!({
+            "event": event,
+            "call_id": call_id,
+            "timestamp": Utc::now(),
+            "data": data,
+        });
+        self.enqueue(call_id, payload);
+    }
+
+    fn enqueue(&self, call_id: &str, payload: Value) {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(call_id.to_string()).or_insert_with(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let task = crate::spawn(run_queue(
+                self.config.clone(),
+                self.cancel_token.clone(),
+                self.file_lock.clone(),
+                receiver,
+            ));
+            let mut tasks = self.tasks.lock().unwrap();
+            tasks.retain(|task| !task.is_finished());
+            tasks.push(task);
+            sender
+        });
+        queue.send(payload).ok();
+    }
+
+    /// Queue the events saved to `pending_path` by the last shutdown again,
+    /// returning how many there were
+    pub async fn resume_pending(&self) -> anyhow::Result<usize> {
+        let path = self.config.pending_path();
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        tokio::fs::remove_file(&path).await?;
+        let mut call_ids = Vec::new();
+        let mut count = 0;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let payload: Value = match serde_json::from_str(line) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(path, "skipping unreadable pending event webhook: {}", e);
+                    continue;
+                }
+            };
+            let call_id = payload["call_id"].as_str().unwrap_or_default().to_string();
+            self.enqueue(&call_id, payload);
+            if !call_ids.contains(&call_id) {
+                call_ids.push(call_id);
+            }
+            count += 1;
+        }
+        // The calls ended before the restart, so no more events will follow
+        for call_id in call_ids {
+            self.close(&call_id);
+        }
+        Ok(count)
+    }
+
+    /// Stop delivering and wait until every queue has saved its undelivered
+    /// events to `pending_path`
+    pub async fn shutdown(&self) {
+        self.cancel_token.cancel();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            task.await.ok();
+        }
+    }
+
+    /// Stop accepting events for `call_id`, the queued ones are still delivered
+    pub fn close(&self, call_id: &str) {
+        self.queues.lock().unwrap().remove(call_id);
+    }
+
+    /// `cdr_pending` tells whether the call record was handed to the call
+    /// record manager, which reports `cdr_saved` later
+    pub(crate) fn hangup(&self, call_id: &str, data: Value, cdr_pending: bool) {
+        self.send(call_id, "hangup", data);
+        if !(self.report_cdr && cdr_pending) {
+            self.close(call_id);
+        }
+    }
+
+    pub(crate) fn cdr_saved(&self, record: &CallRecord) {
+        if self.report_cdr {
+            self.send(&record.call_id, "cdr_saved", json!(record));
+            self.close(&record.call_id);
+        }
+    }
+
+    pub(crate) fn cdr_failed(&self, call_id: &str) {
+        self.close(call_id);
+    }
+}
+
+async fn run_queue(
+    config: EventWebhookConfig,
+    cancel_token: CancellationToken,
+    file_lock: Arc<tokio::sync::Mutex<()>>,
+    mut receiver: mpsc::UnboundedReceiver<Value>,
+) {
+    let mut pending = Vec::new();
+    loop {
+        let payload = select! {
+            _ = cancel_token.cancelled() => break,
+            payload = receiver.recv() => match payload {
+                Some(payload) => payload,
+                None => return,
+            },
+        };
+        if !deliver(&config, &cancel_token, &file_lock, &payload).await {
+            pending.push(payload);
+            break;
+        }
+    }
+    while let Ok(payload) = receiver.try_recv() {
+        pending.push(payload);
+    }
+    if pending.is_empty() {
+        return;
+    }
+    let path = config.pending_path();
+    match append_lines(&path, &pending, &file_lock).await {
+        Ok(_) => debug!(path, count = pending.len(), "saved pending event webhooks"),
+        Err(e) => warn!(path, "failed to save pending event webhooks: {}", e),
+    }
+}
+
+/// POST `payload` until it is accepted or the retries are used up. Network
+/// errors, 429 and 5xx are retried; other statuses go to the dead letter
+/// file right away. Returns false when shut down before either happened
+async fn deliver(
+    config: &EventWebhookConfig,
+    cancel_token: &CancellationToken,
+    file_lock: &tokio::sync::Mutex<()>,
+    payload: &Value,
+) -> bool {
+    let client = crate::net_tool::http_client();
+    let mut delay = config.retry_delay();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let request = crate::net_tool::with_request_timeout(client.post(&config.url).json(payload));
+        let result = select! {
+            _ = cancel_token.cancelled() => return false,
+            result = request.send() => result,
+        };
+        let (error, retryable) = match result {
+            Ok(response) if response.status().is_success() => {
+                debug!(url = config.url, event = ?payload["event"], "event webhook delivered");
+                return true;
+            }
+            Ok(response) => {
+                let status = response.status();
+                (
+                    format!("status {}", status.as_u16()),
+                    status.is_server_error() || status.as_u16() == 429,
+                )
+            }
+            Err(e) => (e.to_string(), true),
+        };
+        if !retryable || attempts > config.max_retries() {
+            warn!(
+                url = config.url,
+                event = ?payload["event"],
+                call_id = ?payload["call_id"],
+                attempts,
+                "event webhook failed: {}",
+                error
+            );
+            let entry = json!({
+                "failed_at": Utc::now(),
+                "url": config.url,
+                "attempts": attempts,
+                "error": error,
+                "payload": payload,
+            });
+            if let Err(e) = append_lines(&config.dead_letter_path(), &[entry], file_lock).await {
+                warn!("failed to write event webhook dead letter: {}", e);
+            }
+            return true;
+        }
+        debug!(
+            url = config.url,
+            attempts, "event webhook failed: {}, retrying in {:?}", error, delay
+        );
+        select! {
+            _ = cancel_token.cancelled() => return false,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        delay *= 2;
+    }
+}
+
+/// Append `entries` to `path` as JSON lines while holding `file_lock`
+async fn append_lines(
+    path: &str,
+    entries: &[Value],
+    file_lock: &tokio::sync::Mutex<()>,
+) -> anyhow::Result<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    let _guard = file_lock.lock().await;
+    if let Some(parent) = Path::new(path).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(content.as_bytes()).await?;
+    Ok(())
+}
@@ -1,16 +1,22 @@
 use crate::{
     app::AppState,
     call::{
-        ActiveCall, ActiveCallType, Command,
+        ActiveCall, ActiveCallRef, ActiveCallType, Command,
         active_call::{ActiveCallGuard, CallParams},
     },
+    config::{InviteHandlerConfig, MissingPlaybookAction},
+    event::{EventReceiver, EventSender},
     handler::playbook,
+    media::audio_queue::{AudioQueueSender, DEFAULT_MAX_AUDIO_BUFFER_FRAMES, audio_queue},
+    media::barge::BARGE_TRACK_ID,
+    media::monitor::{MONITOR_PTIME_MS, MONITOR_SAMPLERATE, MonitorMixer},
     playbook::{Playbook, PlaybookRunner},
 };
 use crate::{event::SessionEvent, media::track::TrackConfig};
 use axum::{
     Json, Router,
     extract::{Path, Query, State, WebSocketUpgrade, ws::Message},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
     response::{IntoResponse, Response},
     routing::get,
 };
@@ -19,8 +25,8 @@ use chrono::Utc;
 use futures::{SinkExt, StreamExt};
 use rustrtc::IceServer;
 use serde_json::json;
-use std::{path::PathBuf, sync::Arc, time::Duration};
-use tokio::{join, select};
+use std::{collections::HashSet, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{join, select, sync::broadcast::error::RecvError};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
@@ -65,24 +71,72 @@ pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     Query(params): Query<CallParams>,
+    headers: HeaderMap,
 ) -> Response {
-    call_handler(ActiveCallType::WebSocket, ws, state, params).await
+    call_handler(ActiveCallType::WebSocket, ws, state, params, headers).await
 }
 
 pub async fn sip_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     Query(params): Query<CallParams>,
+    headers: HeaderMap,
 ) -> Response {
-    call_handler(ActiveCallType::Sip, ws, state, params).await
+    call_handler(ActiveCallType::Sip, ws, state, params, headers).await
 }
 
 pub async fn webrtc_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     Query(params): Query<CallParams>,
+    headers: HeaderMap,
 ) -> Response {
-    call_handler(ActiveCallType::Webrtc, ws, state, params).await
+    call_handler(ActiveCallType::Webrtc, ws, state, params, headers).await
+}
+
+async fn load_pending_playbook(name_or_content: &str) -> anyhow::Result<Playbook> {
+    if name_or_content.trim().starts_with("---") {
+        Playbook::parse(name_or_content)
+    } else {
+        // If path already contains config/playbook, use it as-is; otherwise prepend it
+        let path = if name_or_content.starts_with("config/playbook/") {
+            PathBuf::from(name_or_content)
+        } else {
+            PathBuf::from("config/playbook").join(name_or_content)
+        };
+        Playbook::load(path).await
+    }
+}
+
+/// Play `prompt` once the call is answered and hang up when it finishes
+async fn play_prompt_and_hangup(
+    active_call: ActiveCallRef,
+    mut events: EventReceiver,
+    prompt: String,
+) {
+    let answered = active_call.call_state.read().await.answer_time.is_some();
+    if !answered {
+        loop {
+            match events.recv().await {
+                Ok(SessionEvent::Answer { .. }) => break,
+                Ok(SessionEvent::Hangup { .. }) => return,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(_) => return,
+            }
+        }
+    }
+    let command = Command::Play {
+        url: prompt,
+        play_id: None,
+        auto_hangup: Some(true),
+        wait_input_timeout: None,
+    };
+    if let Err(e) = active_call.enqueue_command(command).await {
+        warn!(
+            session_id = active_call.session_id,
+            "Failed to play missing playbook prompt: {}", e
+        );
+    }
 }
 
 /// Core call handling logic that works with either WebSocket or mpsc channels
@@ -99,7 +153,13 @@ pub async fn call_handler_core(
     event_sender_to_client: tokio::sync::mpsc::UnboundedSender<crate::event::SessionEvent>,
 ) {
     let _cancel_guard = cancel_token.clone().drop_guard();
+    let config = app_state.config();
     let track_config = TrackConfig::default();
+    let (audio_queue_sender, audio_queue_receiver) = audio_queue(
+        config
+            .max_audio_buffer_frames
+            .unwrap_or(DEFAULT_MAX_AUDIO_BUFFER_FRAMES),
+    );
 
     // Check for pending params (extracted headers)
     let extras = {
@@ -114,7 +174,7 @@ pub async fn call_handler_core(
         app_state.invitation.clone(),
         app_state.clone(),
         track_config,
-        Some(audio_receiver),
+        Some(audio_queue_receiver),
         dump_events,
         server_side_track,
         extras,
@@ -125,17 +185,38 @@ pub async fn call_handler_core(
     {
         let mut pending = app_state.pending_playbooks.lock().await;
         if let Some(name_or_content) = pending.remove(&session_id) {
-            let playbook_result = if name_or_content.trim().starts_with("---") {
-                Playbook::parse(&name_or_content)
-            } else {
-                // If path already contains config/playbook, use it as-is; otherwise prepend it
-                let path = if name_or_content.starts_with("config/playbook/") {
-                    PathBuf::from(&name_or_content)
+            let missing_action = config.missing_playbook_action.clone();
+            let mut playbook_result = load_pending_playbook(&name_or_content).await;
+            if let Err(e) = &playbook_result {
+                let display_name = if name_or_content.trim().starts_with("---") {
+                    "custom content"
                 } else {
-                    PathBuf::from("config/playbook").join(&name_or_content)
+                    &name_or_content
                 };
-                Playbook::load(path).await
-            };
+                warn!(
+                    session_id,
+                    "Failed to load playbook {}: {}", display_name, e
+                );
+                let event = SessionEvent::Error {
+                    timestamp: crate::media::get_timestamp(),
+                    track_id: session_id.clone(),
+                    sender: "playbook".to_string(),
+                    error: format!("{}", e),
+                    code: None,
+                };
+                event_sender_to_client.send(event).ok();
+
+                if let Some(MissingPlaybookAction::Fallback { playbook }) = &missing_action {
+                    let fallback = playbook.clone().or_else(|| match &config.handler {
+                        Some(InviteHandlerConfig::Playbook { default, .. }) => default.clone(),
+                        _ => None,
+                    });
+                    if let Some(fallback) = fallback {
+                        info!(session_id, "Falling back to playbook {}", fallback);
+                        playbook_result = load_pending_playbook(&fallback).await;
+                    }
+                }
+            }
 
             match playbook_result {
                 Ok(mut playbook) => {
@@ -164,7 +245,7 @@ pub async fn call_handler_core(
                         }
                     }
 
-                    match PlaybookRunner::new(playbook, active_call.clone()) {
+                    match PlaybookRunner::new(playbook, active_call.clone()).await {
                         Ok(runner) => {
                             crate::spawn(async move {
                                 runner.run().await;
@@ -189,26 +270,20 @@ pub async fn call_handler_core(
                         }
                     }
                 }
-                Err(e) => {
-                    let display_name = if name_or_content.trim().starts_with("---") {
-                        "custom content"
-                    } else {
-                        &name_or_content
-                    };
-                    warn!(
-                        session_id,
-                        "Failed to load playbook {}: {}", display_name, e
-                    );
-                    let event = SessionEvent::Error {
-                        timestamp: crate::media::get_timestamp(),
-                        track_id: session_id.clone(),
-                        sender: "playbook".to_string(),
-                        error: format!("{}", e),
-                        code: None,
-                    };
-                    event_sender_to_client.send(event).ok();
-                    return;
-                }
+                Err(e) => match missing_action {
+                    Some(MissingPlaybookAction::Prompt { prompt }) => {
+                        info!(
+                            session_id,
+                            "Playbook unavailable, playing prompt {}", prompt
+                        );
+                        let events = active_call.event_sender.subscribe();
+                        crate::spawn(play_prompt_and_hangup(active_call.clone(), events, prompt));
+                    }
+                    _ => {
+                        warn!(session_id, "No playbook to run, ending call: {}", e);
+                        return;
+                    }
+                },
             }
         }
     }
@@ -222,6 +297,13 @@ pub async fn call_handler_core(
     };
 
     let mut event_receiver = active_call.event_sender.subscribe();
+    crate::spawn(relay_client_audio(
+        session_id.clone(),
+        audio_receiver,
+        audio_queue_sender,
+        active_call.event_sender.clone(),
+        cancel_token.clone(),
+    ));
     let send_events_loop = async {
         loop {
             match event_receiver.recv().await {
@@ -255,7 +337,7 @@ pub async fn call_handler_core(
         }
     };
 
-    let guard = ActiveCallGuard::new(active_call.clone());
+    let guard = ActiveCallGuard::new(active_call.clone()).await;
     info!(
         session_id,
         active_calls = guard.active_calls,
@@ -278,12 +360,6 @@ pub async fn call_handler_core(
             cancel_token.cancel();
         }
     };
-    // drain events
-    while let Ok(event) = event_receiver.try_recv() {
-        if let Err(_) = event_sender_to_client.send(event) {
-            break;
-        }
-    }
     match r {
         Ok(_) => info!(session_id, "call ended successfully"),
         Err(e) => warn!(session_id, "call ended with error: {}", e),
@@ -299,21 +375,123 @@ pub async fn call_handler_core(
     }
 
     active_call.cleanup().await.ok();
+
+    // Keep forwarding events produced during teardown (final metrics, track end, ...)
+    // for the configured grace period. A zero grace still drains what is already buffered.
+    let grace = config
+        .hangup_grace_period
+        .as_ref()
+        .and_then(|t| humantime::parse_duration(t).ok())
+        .unwrap_or_default();
+    let deadline = tokio::time::Instant::now() + grace;
+    loop {
+        match tokio::time::timeout_at(deadline, event_receiver.recv()).await {
+            Ok(Ok(event)) => {
+                if let Err(_) = event_sender_to_client.send(event) {
+                    break;
+                }
+            }
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(_)) | Err(_) => break,
+        }
+    }
     debug!(session_id, "Call handler core completed");
 }
 
+/// Move audio from the client into the call's bounded queue, reporting frames
+/// dropped because the track fell behind as `audio_dropped` metrics, at most
+/// once per second
+async fn relay_client_audio(
+    session_id: String,
+    mut audio_receiver: tokio::sync::mpsc::UnboundedReceiver<Bytes>,
+    queue: AudioQueueSender,
+    event_sender: EventSender,
+    cancel_token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut reported = 0;
+    loop {
+        select! {
+            frame = audio_receiver.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                queue.push(frame);
+            }
+            _ = ticker.tick() => {
+                let dropped = queue.dropped();
+                if dropped > reported {
+                    warn!(
+                        session_id,
+                        dropped = dropped - reported,
+                        total = dropped,
+                        "audio buffer full, dropped oldest frames"
+                    );
+                    event_sender
+                        .send(SessionEvent::Metrics {
+                            timestamp: crate::media::get_timestamp(),
+                            key: "audio_dropped".to_string(),
+                            duration: 0,
+                            data: json!({
+                                "dropped": dropped - reported,
+                                "total": dropped,
+                                "capacity": queue.capacity(),
+                            }),
+                        })
+                        .ok();
+                    reported = dropped;
+                }
+            }
+            _ = cancel_token.cancelled() => break,
+        }
+    }
+}
+
 pub async fn call_handler(
     call_type: ActiveCallType,
     ws: WebSocketUpgrade,
     app_state: AppState,
     params: CallParams,
+    headers: HeaderMap,
 ) -> Response {
+    let token = bearer_token(&headers).or(params.token.as_deref());
+    if let Some(session_id) = params.monitor {
+        return monitor_handler(ws, app_state, session_id, token).await;
+    }
+    if let Some(session_id) = params.barge {
+        return barge_handler(ws, app_state, session_id, token, params.supervisor).await;
+    }
     let session_id = params
         .id
         .unwrap_or_else(|| format!("s.{}", Uuid::new_v4().to_string()));
+    // Inbound SIP calls were already admitted when their INVITE arrived
+    let admitted_invite = call_type == ActiveCallType::Sip
+        && app_state
+            .invitation
+            .find_dialog_id_by_session_id(&session_id)
+            .is_some();
+    let admission_wait = if admitted_invite {
+        Duration::ZERO
+    } else {
+        match app_state.admit_call().await {
+            Ok(waited) => waited,
+            Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+        }
+    };
     let server_side_track = params.server_side_track.clone();
+    let event_filter = params.events.as_deref().and_then(EventFilter::parse);
     let dump_events = params.dump_events.unwrap_or(true);
     let ping_interval = params.ping_interval.unwrap_or(20);
+    let ws = ws.protocols([WsEncoding::MSGPACK]);
+    let encoding = if ws.selected_protocol().is_some() {
+        WsEncoding::Msgpack
+    } else {
+        params
+            .encoding
+            .as_deref()
+            .map(WsEncoding::from_param)
+            .unwrap_or_default()
+    };
 
     let resp = ws.on_upgrade(move |socket| async move {
         let (mut ws_sender, mut ws_receiver) = socket.split();
@@ -322,6 +500,16 @@ pub async fn call_handler(
         let (event_sender_to_client, mut event_receiver_from_core) =
             tokio::sync::mpsc::unbounded_channel::<crate::event::SessionEvent>();
         let cancel_token = CancellationToken::new();
+        if !admission_wait.is_zero() {
+            event_sender_to_client
+                .send(SessionEvent::Metrics {
+                    timestamp: crate::media::get_timestamp(),
+                    key: "throttled.call".to_string(),
+                    duration: admission_wait.as_millis() as u32,
+                    data: json!({}),
+                })
+                .ok();
+        }
 
         // Start core handler in background
         let session_id_clone = session_id.clone();
@@ -357,6 +545,12 @@ pub async fn call_handler(
                         }
                     }
                     Message::Binary(bin) => {
+                        if let Some(command) = encoding.decode_command(&bin) {
+                            if let Err(_) = command_sender.send(command) {
+                                break;
+                            }
+                            continue;
+                        }
                         audio_sender.send(bin.into()).ok();
                     }
                     Message::Close(_) => {
@@ -370,8 +564,11 @@ pub async fn call_handler(
 
         let send_to_ws_loop = async {
             while let Some(event) = event_receiver_from_core.recv().await {
+                if event_filter.as_ref().is_some_and(|f| !f.allows(&event)) {
+                    continue;
+                }
                 trace!(session_id, %event, "Sending WS message");
-                let message = match event.into_ws_message() {
+                let message = match event.into_ws_message(encoding) {
                     Ok(msg) => msg,
                     Err(e) => {
                         warn!(session_id, error=%e, "Failed to serialize event to WS message");
@@ -402,8 +599,185 @@ pub async fn call_handler(
     resp
 }
 
+fn find_active_call(app_state: &AppState, session_id: &str) -> Option<ActiveCallRef> {
+    app_state
+        .active_calls
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .cloned()
+}
+
+fn call_not_found(session_id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        format!("call {} not found", session_id),
+    )
+        .into_response()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Send the mixed audio of `active_call` as binary PCM frames until the call
+/// ends or the socket fails. Frames of `skip_track_id` are left out of the mix
+async fn send_mixed_audio(
+    ws_sender: &mut futures::stream::SplitSink<axum::extract::ws::WebSocket, Message>,
+    active_call: &ActiveCall,
+    skip_track_id: Option<&str>,
+) {
+    let mut frames = active_call.media_stream.subscribe_monitor().await;
+    let mut mixer = MonitorMixer::new(MONITOR_SAMPLERATE);
+    let frame_len = (MONITOR_SAMPLERATE * MONITOR_PTIME_MS / 1000) as usize;
+    let mut ticker = tokio::time::interval(Duration::from_millis(MONITOR_PTIME_MS as u64));
+    loop {
+        select! {
+            frame = frames.recv() => match frame {
+                Ok(frame) if Some(frame.track_id.as_str()) == skip_track_id => {}
+                Ok(frame) => mixer.push(&frame),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            _ = ticker.tick() => {
+                let data = audio_codec::samples_to_bytes(&mixer.mix(frame_len));
+                if let Err(_) = ws_sender.send(Message::Binary(data.into())).await {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Stream the mixed audio of a live call as binary PCM frames. Monitors are
+/// listen-only: anything they send is dropped and leaving doesn't affect the call.
+async fn monitor_handler(
+    ws: WebSocketUpgrade,
+    app_state: AppState,
+    session_id: String,
+    token: Option<&str>,
+) -> Response {
+    let config = app_state.config();
+    if !config.enable_monitor.unwrap_or(false) {
+        return (StatusCode::FORBIDDEN, "call monitoring is disabled").into_response();
+    }
+    let Some(monitor_token) = config.monitor_token.as_deref() else {
+        warn!("call monitoring is enabled without a monitor_token, refusing");
+        return (
+            StatusCode::FORBIDDEN,
+            "call monitoring needs a monitor_token",
+        )
+            .into_response();
+    };
+    if token != Some(monitor_token) {
+        return (StatusCode::UNAUTHORIZED, "invalid monitor token").into_response();
+    }
+    let Some(active_call) = find_active_call(&app_state, &session_id) else {
+        return call_not_found(&session_id);
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        let (mut ws_sender, mut ws_receiver) = socket.split();
+        info!(session_id, "monitor attached");
+
+        let recv_from_ws_loop = async {
+            while let Some(Ok(message)) = ws_receiver.next().await {
+                match message {
+                    Message::Text(_) | Message::Binary(_) => {
+                        debug!(session_id, "Ignoring message from monitor");
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+        };
+
+        select! {
+            _ = send_mixed_audio(&mut ws_sender, &active_call, None) => {},
+            _ = recv_from_ws_loop => {},
+            _ = active_call.cancel_token.cancelled() => {},
+        }
+        ws_sender.close().await.ok();
+        info!(session_id, "monitor detached");
+    })
+}
+
+/// Join a live call as a supervisor. Like a monitor, the supervisor receives
+/// the mixed audio of the call; the binary PCM frames they send (16-bit mono
+/// at 16000 Hz) are mixed into what the caller and the agent hear. Leaving
+/// removes them from the call, which goes on.
+async fn barge_handler(
+    ws: WebSocketUpgrade,
+    app_state: AppState,
+    session_id: String,
+    token: Option<&str>,
+    supervisor: Option<String>,
+) -> Response {
+    let config = app_state.config();
+    let Some(barge) = config.barge.as_ref() else {
+        return (StatusCode::FORBIDDEN, "call barge is disabled").into_response();
+    };
+    // Barging lets a client speak into any call, so it is never left open
+    let Some(expected) = barge.token.as_deref() else {
+        return (StatusCode::FORBIDDEN, "call barge has no token configured").into_response();
+    };
+    if Some(expected) != token {
+        return (StatusCode::UNAUTHORIZED, "invalid barge token").into_response();
+    }
+    let Some(active_call) = find_active_call(&app_state, &session_id) else {
+        return call_not_found(&session_id);
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        let (mut ws_sender, mut ws_receiver) = socket.split();
+        if let Err(e) = active_call.media_stream.join_barge().await {
+            warn!(session_id, "barge refused: {}", e);
+            ws_sender.close().await.ok();
+            return;
+        }
+        active_call
+            .call_state
+            .write()
+            .await
+            .add_barge(supervisor.as_deref());
+        info!(session_id, supervisor, "supervisor joined");
+
+        let recv_from_ws_loop = async {
+            while let Some(Ok(message)) = ws_receiver.next().await {
+                match message {
+                    Message::Binary(data) => {
+                        let samples = audio_codec::bytes_to_samples(&data);
+                        if let Err(e) = active_call.media_stream.send_barge_audio(samples).await {
+                            warn!(session_id, "failed to send barge audio: {}", e);
+                            break;
+                        }
+                    }
+                    Message::Text(_) => {
+                        debug!(session_id, "Ignoring message from supervisor");
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+        };
+
+        select! {
+            _ = send_mixed_audio(&mut ws_sender, &active_call, Some(BARGE_TRACK_ID)) => {},
+            _ = recv_from_ws_loop => {},
+            _ = active_call.cancel_token.cancelled() => {},
+        }
+        active_call.media_stream.leave_barge().await;
+        active_call.call_state.write().await.end_barge();
+        ws_sender.close().await.ok();
+        info!(session_id, supervisor, "supervisor left");
+    })
+}
+
 pub(crate) async fn get_iceservers(State(state): State<AppState>) -> Response {
-    if let Some(ice_servers) = state.config.ice_servers.as_ref() {
+    if let Some(ice_servers) = state.config().ice_servers.as_ref() {
         return Json(ice_servers).into_response();
     }
     Json(vec![IceServer {
@@ -453,19 +827,80 @@ pub(crate) async fn kill_active_call(
     }
 }
 
+/// Encoding used for commands and events on the call WebSocket.
+/// Negotiated via the `msgpack` subprotocol or the `encoding` query param.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WsEncoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl WsEncoding {
+    pub const MSGPACK: &'static str = "msgpack";
+
+    pub fn from_param(value: &str) -> Self {
+        if value.eq_ignore_ascii_case(Self::MSGPACK) {
+            WsEncoding::Msgpack
+        } else {
+            WsEncoding::Json
+        }
+    }
+
+    /// Binary frames carry audio unless msgpack is negotiated and the frame
+    /// decodes as a msgpack map holding a command.
+    pub fn decode_command(&self, data: &[u8]) -> Option<Command> {
+        match self {
+            WsEncoding::Json => None,
+            WsEncoding::Msgpack => match data.first() {
+                Some(0x80..=0x8f) | Some(0xde) | Some(0xdf) => rmp_serde::from_slice(data).ok(),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Event types a client subscribed to with the `events` call param. Audio
+/// frames and pings are transport, not events, and always pass.
+struct EventFilter(HashSet<String>);
+
+impl EventFilter {
+    fn parse(events: &str) -> Option<Self> {
+        let names: HashSet<String> = events
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+        Some(Self(names))
+    }
+
+    fn allows(&self, event: &SessionEvent) -> bool {
+        matches!(
+            event,
+            SessionEvent::Binary { .. } | SessionEvent::Ping { .. }
+        ) || self.0.contains(&event.event_name().to_ascii_lowercase())
+    }
+}
+
 trait IntoWsMessage {
-    fn into_ws_message(self) -> Result<Message, serde_json::Error>;
+    fn into_ws_message(self, encoding: WsEncoding) -> anyhow::Result<Message>;
 }
 
 impl IntoWsMessage for crate::event::SessionEvent {
-    fn into_ws_message(self) -> Result<Message, serde_json::Error> {
+    fn into_ws_message(self, encoding: WsEncoding) -> anyhow::Result<Message> {
         match self {
             SessionEvent::Binary { data, .. } => Ok(Message::Binary(data.into())),
             SessionEvent::Ping { timestamp, payload } => {
                 let payload = payload.unwrap_or_else(|| timestamp.to_string());
                 Ok(Message::Ping(payload.into()))
             }
-            event => serde_json::to_string(&event).map(|payload| Message::Text(payload.into())),
+            event => match encoding {
+                WsEncoding::Json => Ok(Message::Text(serde_json::to_string(&event)?.into())),
+                WsEncoding::Msgpack => Ok(Message::Binary(rmp_serde::to_vec_named(&event)?.into())),
+            },
         }
     }
 }
@@ -498,4 +933,94 @@ mod tests {
         assert_eq!(extras.get("X-Tenant-ID").unwrap(), &json!("123"));
         assert_eq!(extras.get("Custom-Header").unwrap(), &json!("abc"));
     }
+
+    #[test]
+    fn test_msgpack_command_roundtrip() {
+        let command = Command::Tts {
+            text: "hello".to_string(),
+            speaker: None,
+            play_id: Some("p1".to_string()),
+            auto_hangup: Some(true),
+            streaming: None,
+            end_of_stream: None,
+            option: None,
+            wait_input_timeout: Some(3000),
+            base64: None,
+            cache_key: None,
+        };
+        let packed = rmp_serde::to_vec_named(&command).unwrap();
+        let decoded = WsEncoding::Msgpack
+            .decode_command(&packed)
+            .expect("decode msgpack command");
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&command).unwrap()
+        );
+        assert!(WsEncoding::Json.decode_command(&packed).is_none());
+        // raw audio frames are not mistaken for commands
+        assert!(
+            WsEncoding::Msgpack
+                .decode_command(&[0x81, 0x00, 0x7f])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let filter = EventFilter::parse("asrfinal, hangup").expect("filter");
+        let asr_final = SessionEvent::AsrFinal {
+            track_id: "t1".to_string(),
+            timestamp: 1,
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: "hi".to_string(),
+            is_filler: None,
+            confidence: None,
+            task_id: None,
+            raw_text: None,
+        };
+        let metrics = SessionEvent::Metrics {
+            timestamp: 1,
+            key: "ttfb.asr".to_string(),
+            duration: 10,
+            data: json!({}),
+        };
+        let binary = SessionEvent::Binary {
+            track_id: "t1".to_string(),
+            timestamp: 1,
+            data: vec![0; 4],
+        };
+        assert_eq!(
+            serde_json::to_value(&asr_final).unwrap()["event"],
+            asr_final.event_name()
+        );
+        assert!(filter.allows(&asr_final));
+        assert!(!filter.allows(&metrics));
+        assert!(filter.allows(&binary));
+        assert!(EventFilter::parse(" , ").is_none());
+    }
+
+    #[test]
+    fn test_msgpack_event_roundtrip() {
+        let event = SessionEvent::AsrFinal {
+            track_id: "t1".to_string(),
+            timestamp: 1,
+            index: 2,
+            start_time: Some(10),
+            end_time: None,
+            text: "hi".to_string(),
+            is_filler: None,
+            confidence: Some(0.5),
+            task_id: None,
+            raw_text: None,
+        };
+        let json_value = serde_json::to_value(&event).unwrap();
+        let message = event.into_ws_message(WsEncoding::Msgpack).unwrap();
+        let Message::Binary(data) = message else {
+            panic!("expected binary frame");
+        };
+        let decoded: SessionEvent = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), json_value);
+    }
 }
@@ -0,0 +1,86 @@
+use crate::{
+    call::ActiveCallType,
+    callrecord::{CallRecord, CallRecordHangupReason},
+};
+use std::{future::Future, pin::Pin, sync::Arc};
+
+pub type FnCallHook =
+    Arc<dyn Fn(CallHookContext) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+pub type FnCallRecordHook =
+    Arc<dyn Fn(CallRecord) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+pub type FnCallRecordErrorHook =
+    Arc<dyn Fn(CallRecordSaveError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Call details handed to lifecycle hooks
+#[derive(Debug, Clone)]
+pub struct CallHookContext {
+    pub session_id: String,
+    pub call_type: ActiveCallType,
+    pub caller: Option<String>,
+    pub callee: Option<String>,
+    /// Only set for `on_hangup`
+    pub hangup_reason: Option<CallRecordHangupReason>,
+}
+
+impl CallHookContext {
+    /// `data` of the `event_webhook` lifecycle events
+    pub(crate) fn webhook_data(&self) -> serde_json::Value {
+        serde_json::json!({
+            "call_type": self.call_type,
+            "caller": self.caller,
+            "callee": self.callee,
+            "hangup_reason": self.hangup_reason,
+        })
+    }
+}
+
+/// A call record the call record manager gave up saving
+#[derive(Debug, Clone)]
+pub struct CallRecordSaveError {
+    pub call_id: String,
+    /// Configured backend: `local`, `s3` or `http`
+    pub backend: String,
+    pub error: String,
+}
+
+/// Optional async callbacks for embedders, registered on `AppStateBuilder`.
+/// Hooks run on their own task and never block the call.
+#[derive(Clone, Default)]
+pub struct CallHooks {
+    pub on_call_created: Option<FnCallHook>,
+    pub on_answered: Option<FnCallHook>,
+    pub on_hangup: Option<FnCallHook>,
+    pub on_cdr_saved: Option<FnCallRecordHook>,
+    pub on_cdr_save_error: Option<FnCallRecordErrorHook>,
+}
+
+impl CallHooks {
+    pub fn wrap<T, F, Fut>(
+        f: F,
+    ) -> Arc<dyn Fn(T) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>
+    where
+        T: 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Arc::new(move |arg| Box::pin(f(arg)))
+    }
+
+    fn fire(hook: &Option<FnCallHook>, ctx: CallHookContext) {
+        if let Some(hook) = hook {
+            crate::spawn(hook(ctx));
+        }
+    }
+
+    pub(crate) fn call_created(&self, ctx: CallHookContext) {
+        Self::fire(&self.on_call_created, ctx);
+    }
+
+    pub(crate) fn answered(&self, ctx: CallHookContext) {
+        Self::fire(&self.on_answered, ctx);
+    }
+
+    pub(crate) fn hangup(&self, ctx: CallHookContext) {
+        Self::fire(&self.on_hangup, ctx);
+    }
+}
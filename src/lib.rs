@@ -6,8 +6,9 @@ use std::collections::HashMap;
 
 use crate::{
     media::{
-        ambiance::AmbianceOption, recorder::RecorderOption, track::media_pass::MediaPassOption,
-        vad::VADOption,
+        ambiance::AmbianceOption, loudness::LoudnessOption, noise_gate::NoiseGateOption,
+        recorder::RecorderOption, track::media_pass::MediaPassOption, vad::VADOption,
+        watermark::WatermarkOption,
     },
     synthesis::SynthesisOption,
     transcription::TranscriptionOption,
@@ -18,7 +19,9 @@ pub mod call;
 pub mod callrecord;
 pub mod config;
 pub mod event;
+pub mod event_webhook;
 pub mod handler;
+pub mod hooks;
 pub mod locator;
 pub mod media;
 pub mod net_tool;
@@ -41,6 +44,51 @@ pub struct SipOption {
     pub headers: Option<HashMap<String, String>>,
     pub hangup_headers: Option<HashMap<String, String>>,
     pub extract_headers: Option<Vec<String>>,
+    /// Caller ID for outbound calls, used as the INVITE From URI
+    #[serde(alias = "caller_id")]
+    pub from: Option<String>,
+    pub p_preferred_identity: Option<String>,
+    pub p_asserted_identity: Option<String>,
+    /// Send the INVITE to this proxy with a loose `Route` header, overrides the
+    /// global `outbound_proxy`, e.g. `sip:sbc.example.com:5060;transport=tcp`
+    pub outbound_proxy: Option<String>,
+    /// Offer reliable provisional responses (100rel/PRACK) on outbound INVITEs,
+    /// overrides the global `enable_100rel`
+    pub enable_100rel: Option<bool>,
+}
+
+/// `Route` header sending a request through `proxy` with loose routing, so the
+/// Request-URI keeps the real target. The `sip:` scheme and `lr` are added when missing.
+pub fn outbound_proxy_route(proxy: &str) -> Result<rsip::Header> {
+    let proxy = proxy.trim().trim_start_matches('<').trim_end_matches('>');
+    let uri_str = if proxy.starts_with("sip:") || proxy.starts_with("sips:") {
+        proxy.to_string()
+    } else {
+        format!("sip:{}", proxy)
+    };
+    let mut uri = rsip::Uri::try_from(uri_str.as_str())
+        .map_err(|e| anyhow::anyhow!("invalid outbound proxy '{}': {}", proxy, e))?;
+    if !uri.params.iter().any(|p| matches!(p, rsip::Param::Lr)) {
+        uri.params.push(rsip::Param::Lr);
+    }
+    Ok(rsip::headers::Route::new(format!("<{}>", uri)).into())
+}
+
+/// Normalize a caller ID / identity value into a SIP URI, adding the `sip:` scheme
+/// when missing. Returns an error when the value is not a valid URI with a user part.
+pub fn parse_identity_uri(value: &str) -> Result<rsip::Uri> {
+    let value = value.trim().trim_start_matches('<').trim_end_matches('>');
+    let uri_str = if value.starts_with("sip:") || value.starts_with("sips:") {
+        value.to_string()
+    } else {
+        format!("sip:{}", value)
+    };
+    let uri = rsip::Uri::try_from(uri_str.as_str())
+        .map_err(|e| anyhow::anyhow!("invalid SIP URI '{}': {}", value, e))?;
+    if uri.user().map(|u| u.is_empty()).unwrap_or(true) {
+        return Err(anyhow::anyhow!("SIP URI '{}' has no user part", value));
+    }
+    Ok(uri)
 }
 
 #[skip_serializing_none]
@@ -48,6 +96,8 @@ pub struct SipOption {
 #[serde(rename_all = "camelCase")]
 pub struct CallOption {
     pub denoise: Option<bool>,
+    /// Attenuate the caller's audio while it stays below a level, before VAD and ASR
+    pub noise_gate: Option<NoiseGateOption>,
     pub offer: Option<String>,
     pub callee: Option<String>,
     pub caller: Option<String>,
@@ -60,19 +110,54 @@ pub struct CallOption {
     pub handshake_timeout: Option<u64>,
     pub enable_ipv6: Option<bool>,
     pub inactivity_timeout: Option<u64>, // inactivity timeout in seconds
+    /// Hang up if no inbound audio arrives within this many seconds after answer
+    pub media_timeout_secs: Option<u64>,
+    /// Decode DTMF sent as inband tones into `dtmf` events, for peers without RFC2833
+    pub inband_dtmf: Option<bool>,
+    /// Negotiate a data channel with WebRTC callers for `dataChannel` events and `sendData` commands
+    pub data_channel: Option<bool>,
     pub sip: Option<SipOption>,
     pub extra: Option<HashMap<String, String>>,
     pub codec: Option<String>, // pcmu, pcma, g722, pcm, only for websocket call
+    /// `a=fmtp` parameters per codec name, e.g. `{"opus": "maxaveragebitrate=24000;useinbandfec=1"}`,
+    /// overriding the server's `codec_fmtp` for this call. Only sent in our SDP
+    pub codec_fmtp: Option<HashMap<String, String>>,
     pub ambiance: Option<AmbianceOption>,
+    pub output_loudness: Option<LoudnessOption>,
+    /// Periodic low-level mark mixed into the agent audio, e.g. for regulatory disclosure
+    pub watermark: Option<WatermarkOption>,
     pub eou: Option<EouOption>,
     pub realtime: Option<RealtimeOption>,
     pub subscribe: Option<bool>,
+    /// What to do with a new Play/Tts while another playback is active
+    pub playback_policy: Option<PlaybackPolicy>,
+    /// Internal processing sample rate (8000, 16000 or 48000), 0 follows the negotiated codec
+    pub internal_samplerate: Option<u32>,
+    /// Outbound SIP targets tried in order until one answers, overrides `callee`
+    pub dial_sequence: Option<Vec<String>>,
+    /// Outbound SIP targets rung at the same time, the first to answer wins
+    pub dial_fork: Option<Vec<String>>,
+    /// Seconds to wait for each outbound target to answer before giving up on it
+    pub ring_timeout: Option<u64>,
+    /// Redial an outbound SIP target whose INVITE failed with a transient error
+    pub invite_retry: Option<InviteRetryOption>,
+    /// When an outbound SIP call counts as answered for the CDR `answerTime`
+    pub answer_supervision: Option<AnswerSupervision>,
+    /// Renegotiate a SIP call to a more robust codec when link quality stays poor
+    pub codec_fallback: Option<CodecFallbackOption>,
+    /// What happens to ASR while the call is on hold
+    pub hold_asr: Option<HoldAsrMode>,
+    /// Overrides the global `on_answer_url` screen-pop callback for this call
+    pub on_answer_url: Option<String>,
+    /// Consent required before `recorder` starts, per the caller's jurisdiction
+    pub recording_consent: Option<RecordingConsentOption>,
 }
 
 impl Default for CallOption {
     fn default() -> Self {
         Self {
             denoise: None,
+            noise_gate: None,
             offer: None,
             callee: None,
             caller: None,
@@ -83,14 +168,31 @@ impl Default for CallOption {
             media_pass: None,
             handshake_timeout: None,
             inactivity_timeout: Some(50), // default 50 seconds
+            media_timeout_secs: None,
+            inband_dtmf: None,
+            data_channel: None,
             enable_ipv6: None,
             sip: None,
             extra: None,
             codec: None,
+            codec_fmtp: None,
             ambiance: None,
+            output_loudness: None,
+            watermark: None,
             eou: None,
             realtime: None,
             subscribe: None,
+            playback_policy: None,
+            internal_samplerate: None,
+            dial_sequence: None,
+            dial_fork: None,
+            ring_timeout: None,
+            invite_retry: None,
+            answer_supervision: None,
+            codec_fallback: None,
+            hold_asr: None,
+            on_answer_url: None,
+            recording_consent: None,
         }
     }
 }
@@ -116,7 +218,10 @@ impl CallOption {
         if let Some(callee) = &self.callee {
             invite_option.callee = callee.clone().try_into()?;
         }
-        let caller_uri = if let Some(caller) = &self.caller {
+        let sip_from = self.sip.as_ref().and_then(|sip| sip.from.as_ref());
+        let caller_uri = if let Some(from) = sip_from {
+            parse_identity_uri(from)?.to_string()
+        } else if let Some(caller) = &self.caller {
             // Ensure caller URI has proper sip: scheme
             if caller.starts_with("sip:") || caller.starts_with("sips:") {
                 caller.clone()
@@ -150,6 +255,24 @@ impl CallOption {
                     .map(|(k, v)| rsip::Header::Other(k.clone(), v.clone()))
                     .collect::<Vec<_>>()
             });
+            for (name, value) in [
+                ("P-Preferred-Identity", &sip.p_preferred_identity),
+                ("P-Asserted-Identity", &sip.p_asserted_identity),
+            ] {
+                if let Some(value) = value {
+                    let uri = parse_identity_uri(value)?;
+                    invite_option
+                        .headers
+                        .get_or_insert_with(Vec::new)
+                        .push(rsip::Header::Other(name.to_string(), format!("<{}>", uri)));
+                }
+            }
+            if let Some(proxy) = &sip.outbound_proxy {
+                invite_option
+                    .headers
+                    .get_or_insert_with(Vec::new)
+                    .push(outbound_proxy_route(proxy)?);
+            }
             sip.contact.as_ref().map(|c| match c.clone().try_into() {
                 Ok(u) => {
                     invite_option.contact = u;
@@ -175,6 +298,80 @@ pub struct ReferOption {
     pub call_id: Option<String>,
     /// Pause parent call's ASR during refer call, will resume after refer ends (if auto_hangup is false)
     pub pause_parent_asr: Option<bool>,
+    /// Tell the transfer target which number was originally called, overrides
+    /// the global `refer_diversion`
+    pub diversion: Option<DiversionOption>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum DiversionHeader {
+    /// `Diversion` (RFC 5806)
+    #[default]
+    Diversion,
+    /// `History-Info` (RFC 7044)
+    HistoryInfo,
+    Both,
+}
+
+/// `reason` values of the Diversion header (RFC 5806)
+const DIVERSION_REASONS: &[&str] = &[
+    "unknown",
+    "user-busy",
+    "no-answer",
+    "unavailable",
+    "unconditional",
+    "time-of-day",
+    "do-not-disturb",
+    "deflection",
+    "follow-me",
+    "out-of-service",
+    "away",
+];
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DiversionOption {
+    pub enabled: Option<bool>,
+    pub header: Option<DiversionHeader>,
+    /// `reason` of the Diversion header, default `unconditional`. Only the
+    /// RFC 5806 reasons are accepted
+    pub reason: Option<String>,
+}
+
+impl DiversionOption {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Headers for a call to `original` being transferred to `target`
+    pub fn headers(&self, original: &str, target: &str) -> Result<Vec<rsip::Header>> {
+        let original = parse_identity_uri(original)?;
+        let header = self.header.unwrap_or_default();
+        let mut headers = Vec::new();
+        if header != DiversionHeader::HistoryInfo {
+            let reason = self.reason.as_deref().unwrap_or("unconditional");
+            if !DIVERSION_REASONS.contains(&reason) {
+                return Err(anyhow::anyhow!(
+                    "invalid diversion reason '{}'",
+                    reason.escape_debug()
+                ));
+            }
+            headers.push(rsip::Header::Other(
+                "Diversion".to_string(),
+                format!("<{}>;reason={};counter=1", original, reason),
+            ));
+        }
+        if header != DiversionHeader::Diversion {
+            let target = target.trim().trim_start_matches('<').trim_end_matches('>');
+            headers.push(rsip::Header::Other(
+                "History-Info".to_string(),
+                format!("<{}>;index=1,<{}>;index=1.1;mp=1", original, target),
+            ));
+        }
+        Ok(headers)
+    }
 }
 
 #[skip_serializing_none]
@@ -191,6 +388,138 @@ pub struct EouOption {
     pub extra: Option<HashMap<String, String>>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackPolicy {
+    /// Play after the current playback finishes
+    Queue,
+    /// Interrupt the current playback and play the new one
+    #[default]
+    Replace,
+    /// Drop the new playback and emit an error event
+    Reject,
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CodecFallbackOption {
+    /// Packet loss above this percentage counts as degraded (default: 10)
+    pub max_loss_pct: Option<f64>,
+    /// Jitter above this many milliseconds counts as degraded (default: 60)
+    pub max_jitter_ms: Option<f64>,
+    /// How long quality must stay degraded before renegotiating (default: 10)
+    pub sustain_secs: Option<u64>,
+    /// Codec offered in the renegotiation (default: "pcmu")
+    pub codec: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteRetryOption {
+    /// Final response codes the INVITE is retried on (default: [500, 503]).
+    /// Only 5xx, 408 and 480 are retried, other 4xx never are
+    pub codes: Option<Vec<u16>>,
+    /// Retries per target after the first INVITE (default: 1)
+    pub max_retries: Option<u32>,
+    /// Delay before each retry in milliseconds (default: 500)
+    pub delay_ms: Option<u64>,
+}
+
+impl InviteRetryOption {
+    pub fn retry_codes(&self) -> Vec<u16> {
+        self.codes
+            .clone()
+            .unwrap_or_else(|| vec![500, 503])
+            .into_iter()
+            .filter(|code| matches!(code, 408 | 480 | 500..=599))
+            .collect()
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(1)
+    }
+
+    pub fn delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.delay_ms.unwrap_or(500))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AnswerSupervision {
+    /// Answered on 200 OK
+    #[default]
+    Signaling,
+    /// Answered on the first inbound audio after 200 OK, so media-less
+    /// answers are not billed
+    Media,
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingConsentOption {
+    #[serde(default)]
+    pub consent_mode: ConsentMode,
+    /// Audio played once the call is answered: the recording notice, or the
+    /// consent question in `explicit` mode
+    pub prompt: Option<String>,
+    /// Digit that grants consent in `explicit` mode, any other digit declines (default: "1")
+    pub accept_digit: Option<String>,
+    /// Seconds to wait for a digit after the prompt in `explicit` mode (default: 10)
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentMode {
+    /// Record from the start without a notice
+    #[default]
+    None,
+    /// One-party consent: play the notice, then record
+    Announce,
+    /// All-party consent: record only after the caller agrees
+    Explicit,
+}
+
+/// How the recording consent ended, written to the call record
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingConsentOutcome {
+    /// `none` mode, recorded without asking
+    NotRequired,
+    /// The call ended before the consent flow finished
+    Pending,
+    /// The notice was played and the call recorded
+    Announced,
+    /// The caller pressed the accept digit
+    Granted,
+    /// The caller pressed another digit
+    Declined,
+    /// No digit arrived in time, treated as declined
+    TimedOut,
+}
+
+impl RecordingConsentOutcome {
+    pub fn allows_recording(&self) -> bool {
+        matches!(self, Self::NotRequired | Self::Announced | Self::Granted)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldAsrMode {
+    /// Keep feeding ASR with silence, so the stream is never interrupted
+    #[default]
+    Silence,
+    /// Stop feeding ASR during hold and carry on with the same recognizer
+    PauseAsr,
+    /// Close the recognizer on hold and start a new one on resume
+    TeardownAsr,
+}
+
 #[derive(Debug, Clone, Serialize, Hash, Eq, PartialEq)]
 pub enum RealtimeType {
     #[serde(rename = "openai")]
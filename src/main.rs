@@ -10,7 +10,7 @@ use std::sync::Arc;
 use tokio::signal;
 use tower_http::services::ServeDir;
 use tracing::level_filters::LevelFilter;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::time::LocalTime;
 use tracing_subscriber::layer::SubscriberExt;
@@ -190,6 +190,14 @@ async fn main() -> Result<()> {
 
     let _ = guard_holder; // keep the guard alive
 
+    if let Err(errors) = config.validate() {
+        error!("invalid config, {} error(s):", errors.len());
+        for e in &errors {
+            error!("  - {}", e);
+        }
+        std::process::exit(1);
+    }
+
     info!("Starting active-call service...");
 
     let stream_engine = Arc::new(StreamEngine::default());
@@ -316,5 +324,9 @@ async fn main() -> Result<()> {
             }
         }
     }
+    // Keep the webhook events that were not delivered yet for the next start
+    if let Some(webhook) = &app_state.event_webhook {
+        webhook.shutdown().await;
+    }
     Ok(())
 }
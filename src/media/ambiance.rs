@@ -1,23 +1,32 @@
 use super::processor::Processor;
 use crate::media::{AudioFrame, INTERNAL_SAMPLERATE, Samples};
 use anyhow::Result;
+use arc_swap::ArcSwapOption;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tracing::info;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct AmbianceOption {
     pub path: Option<String>,
+    /// Files played one after the other, looping back to the first. `path`,
+    /// when set, plays first
+    pub playlist: Option<Vec<String>>,
     pub duck_level: Option<f32>,
     pub normal_level: Option<f32>,
     pub transition_speed: Option<f32>,
     pub enabled: Option<bool>,
+    /// Length of the crossfade between the end of the file and its start
+    /// when looping, in milliseconds, default 50. 0 loops with a hard cut
+    pub crossfade_ms: Option<u32>,
 }
 
 impl AmbianceOption {
     pub fn merge(&mut self, other: &AmbianceOption) {
-        if self.path.is_none() {
+        if self.path.is_none() && self.playlist.is_none() {
             self.path = other.path.clone();
+            self.playlist = other.playlist.clone();
         }
         if self.duck_level.is_none() {
             self.duck_level = other.duck_level;
@@ -31,12 +40,83 @@ impl AmbianceOption {
         if self.enabled.is_none() {
             self.enabled = other.enabled;
         }
+        if self.crossfade_ms.is_none() {
+            self.crossfade_ms = other.crossfade_ms;
+        }
+    }
+
+    /// Files to play, `path` followed by the playlist
+    pub fn sources(&self) -> impl Iterator<Item = &String> {
+        self.path.iter().chain(self.playlist.iter().flatten())
     }
 }
 
-pub struct AmbianceProcessor {
+/// Blend the last `len` samples into the first `len`, so playing to the end
+/// and continuing from the returned index has no discontinuity at the seam.
+/// The first pass still starts from the untouched head.
+fn crossfade_loop(samples: &mut [i16], len: usize) -> usize {
+    let len = len.min(samples.len() / 2);
+    let tail_start = samples.len() - len;
+    for i in 0..len {
+        let t = (i + 1) as f32 / (len + 1) as f32;
+        let blended = samples[tail_start + i] as f32 * (1.0 - t) + samples[i] as f32 * t;
+        samples[tail_start + i] = blended.round() as i16;
+    }
+    len
+}
+
+/// A decoded ambiance file, prepared for looping
+struct LoopBuffer {
     samples: Vec<i16>,
+    /// Where playback continues after the end of `samples`
+    loop_start: usize,
+}
+
+impl LoopBuffer {
+    fn new(mut samples: Vec<i16>, crossfade: usize) -> Self {
+        let loop_start = crossfade_loop(&mut samples, crossfade);
+        Self {
+            samples,
+            loop_start,
+        }
+    }
+
+    async fn load(path: &str, crossfade: usize) -> Result<Self> {
+        let samples =
+            crate::media::loader::load_audio_as_pcm(path, INTERNAL_SAMPLERATE, true).await?;
+        info!("Loading ambiance {}: samples={}", path, samples.len());
+        Ok(Self::new(samples, crossfade))
+    }
+}
+
+/// Switches the file an [`AmbianceProcessor`] plays from outside the media
+/// thread. The new file is picked up at the processor's next loop boundary.
+#[derive(Clone)]
+pub struct AmbianceSource {
+    next: Arc<ArcSwapOption<LoopBuffer>>,
+    crossfade: usize,
+}
+
+impl AmbianceSource {
+    /// Load `path` and play it, looping on its own, once the current file ends
+    pub async fn set_source(&self, path: &str) -> Result<()> {
+        let buffer = LoopBuffer::load(path, self.crossfade).await?;
+        self.next.store(Some(Arc::new(buffer)));
+        Ok(())
+    }
+}
+
+pub struct AmbianceProcessor {
+    /// Files played in turn, a single one loops on itself
+    playlist: Vec<Arc<LoopBuffer>>,
+    playlist_index: usize,
+    current: Arc<LoopBuffer>,
     cursor: usize,
+    source: AmbianceSource,
+    /// Last sample played before switching files, faded into the new one
+    switch_from: Option<i16>,
+    switch_pos: usize,
+    last_sample: i16,
     duck_level: f32,
     normal_level: f32,
     enabled: bool,
@@ -48,19 +128,41 @@ pub struct AmbianceProcessor {
 
 impl AmbianceProcessor {
     pub async fn new(option: AmbianceOption) -> Result<Self> {
-        let path = option
-            .path
-            .ok_or_else(|| anyhow::anyhow!("Ambiance path required"))?;
+        let crossfade = Self::crossfade_len(&option);
+        let mut playlist = Vec::new();
+        for path in option.sources() {
+            playlist.push(Arc::new(LoopBuffer::load(path, crossfade).await?));
+        }
+        if playlist.is_empty() {
+            return Err(anyhow::anyhow!("Ambiance path required"));
+        }
+        Ok(Self::with_playlist(playlist, &option))
+    }
 
-        let samples =
-            crate::media::loader::load_audio_as_pcm(&path, INTERNAL_SAMPLERATE, true).await?;
+    fn crossfade_len(option: &AmbianceOption) -> usize {
+        option.crossfade_ms.unwrap_or(50) as usize * INTERNAL_SAMPLERATE as usize / 1000
+    }
 
-        info!("Loading ambiance {}: samples={}", path, samples.len());
+    #[cfg(test)]
+    fn with_samples(samples: Vec<i16>, option: &AmbianceOption) -> Self {
+        let buffer = LoopBuffer::new(samples, Self::crossfade_len(option));
+        Self::with_playlist(vec![Arc::new(buffer)], option)
+    }
 
+    fn with_playlist(playlist: Vec<Arc<LoopBuffer>>, option: &AmbianceOption) -> Self {
         let normal_level = option.normal_level.unwrap_or(0.3);
-        Ok(Self {
-            samples,
+        Self {
+            current: playlist[0].clone(),
+            playlist,
+            playlist_index: 0,
             cursor: 0,
+            source: AmbianceSource {
+                next: Arc::new(ArcSwapOption::empty()),
+                crossfade: Self::crossfade_len(option),
+            },
+            switch_from: None,
+            switch_pos: 0,
+            last_sample: 0,
             duck_level: option.duck_level.unwrap_or(0.1),
             normal_level,
             enabled: option.enabled.unwrap_or(true),
@@ -68,7 +170,7 @@ impl AmbianceProcessor {
             transition_speed: option.transition_speed.unwrap_or(0.01),
             resample_phase: 0,
             resample_step: 1 << 16,
-        })
+        }
     }
 
     pub fn set_enabled(&mut self, enabled: bool) {
@@ -80,20 +182,72 @@ impl AmbianceProcessor {
         self.duck_level = duck;
     }
 
+    /// Handle switching the played file once the processor is in a track
+    pub fn source(&self) -> AmbianceSource {
+        self.source.clone()
+    }
+
+    /// Load `path` and play it instead of the current file or playlist,
+    /// starting at the next loop boundary
+    pub async fn set_source(&self, path: &str) -> Result<()> {
+        self.source.set_source(path).await
+    }
+
+    /// Move on at the end of the current file: to a file set with
+    /// `set_source`, the next one of the playlist, or back to the loop point
+    fn next_loop(&mut self) {
+        let next = match self.source.next.swap(None) {
+            Some(next) => {
+                self.playlist = vec![next.clone()];
+                self.playlist_index = 0;
+                Some(next)
+            }
+            None if self.playlist.len() > 1 => {
+                self.playlist_index = (self.playlist_index + 1) % self.playlist.len();
+                Some(self.playlist[self.playlist_index].clone())
+            }
+            None => None,
+        };
+        match next {
+            Some(next) => {
+                self.current = next;
+                self.cursor = 0;
+                self.switch_from = Some(self.last_sample);
+                self.switch_pos = 0;
+            }
+            None => self.cursor = self.current.loop_start,
+        }
+    }
+
     #[inline]
     fn get_ambient_sample_with_rate(&mut self, target_sample_rate: u32) -> i16 {
-        if self.samples.is_empty() {
+        if self.current.samples.is_empty() {
             return 0;
         }
 
         self.resample_step =
             (((INTERNAL_SAMPLERATE as u64) << 16) / target_sample_rate as u64) as u32;
-        let sample = self.samples[self.cursor];
+        let mut sample = self.current.samples[self.cursor];
+        if let Some(from) = self.switch_from {
+            // Fade from where the previous file stopped into the new one
+            let len = self.source.crossfade.min(self.current.samples.len() / 2);
+            if self.switch_pos < len {
+                let t = (self.switch_pos + 1) as f32 / (len + 1) as f32;
+                sample = (from as f32 * (1.0 - t) + sample as f32 * t).round() as i16;
+                self.switch_pos += 1;
+            } else {
+                self.switch_from = None;
+            }
+        }
+        self.last_sample = sample;
 
         self.resample_phase += self.resample_step;
         while self.resample_phase >= (1 << 16) {
             self.resample_phase -= 1 << 16;
-            self.cursor = (self.cursor + 1) % self.samples.len();
+            self.cursor += 1;
+            if self.cursor >= self.current.samples.len() {
+                self.next_loop();
+            }
         }
 
         sample
@@ -119,7 +273,7 @@ impl AmbianceProcessor {
 
 impl Processor for AmbianceProcessor {
     fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
-        if !self.enabled || self.samples.is_empty() {
+        if !self.enabled || self.current.samples.is_empty() {
             return Ok(());
         }
 
@@ -187,3 +341,89 @@ impl Processor for AmbianceProcessor {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ambiance_loop_has_no_seam() {
+        // 100ms ramp, the end is far from the start
+        let samples: Vec<i16> = (0..1600).map(|i| (i * 6) as i16).collect();
+        let mut processor = AmbianceProcessor::with_samples(samples, &AmbianceOption::default());
+
+        let played: Vec<i16> = (0..1600 * 3)
+            .map(|_| processor.get_ambient_sample_with_rate(INTERNAL_SAMPLERATE))
+            .collect();
+        let max_step = played
+            .windows(2)
+            .map(|w| (w[1] as i32 - w[0] as i32).abs())
+            .max()
+            .unwrap();
+        assert!(max_step <= 12, "max step between samples {}", max_step);
+        assert_eq!(played[0], 0, "first pass starts from the head");
+
+        let mut hard_cut = AmbianceProcessor::with_samples(
+            (0..1600).map(|i| (i * 6) as i16).collect(),
+            &AmbianceOption {
+                crossfade_ms: Some(0),
+                ..Default::default()
+            },
+        );
+        let played: Vec<i16> = (0..1601)
+            .map(|_| hard_cut.get_ambient_sample_with_rate(INTERNAL_SAMPLERATE))
+            .collect();
+        assert_eq!(played[1599] - played[1600], 9594);
+    }
+
+    fn write_wav(path: &std::path::Path, value: i16, len: usize) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: INTERNAL_SAMPLERATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..len {
+            writer.write_sample(value).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ambiance_switch_source_at_loop_boundary() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.wav");
+        let b = dir.path().join("b.wav");
+        write_wav(&a, 1000, 1600);
+        write_wav(&b, -2000, 1600);
+
+        let mut processor = AmbianceProcessor::new(AmbianceOption {
+            path: Some(a.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let head: Vec<i16> = (0..800)
+            .map(|_| processor.get_ambient_sample_with_rate(INTERNAL_SAMPLERATE))
+            .collect();
+        assert!(head.iter().all(|s| *s == 1000));
+
+        processor
+            .source()
+            .set_source(&b.to_string_lossy())
+            .await
+            .unwrap();
+        // The rest of A plays out before B starts
+        let rest: Vec<i16> = (0..800)
+            .map(|_| processor.get_ambient_sample_with_rate(INTERNAL_SAMPLERATE))
+            .collect();
+        assert!(rest.iter().all(|s| *s == 1000));
+
+        let played: Vec<i16> = (0..3200)
+            .map(|_| processor.get_ambient_sample_with_rate(INTERNAL_SAMPLERATE))
+            .collect();
+        assert!(played[0] > -2000 && played[0] < 1000, "faded into B");
+        assert!(played[1600..].iter().all(|s| *s == -2000));
+    }
+}
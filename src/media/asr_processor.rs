@@ -1,22 +1,67 @@
 use super::processor::Processor;
 use crate::{media::AudioFrame, media::Samples, transcription::TranscriptionClient};
 use anyhow::Result;
+use audio_codec::Resampler;
 
 pub struct AsrProcessor {
     pub asr_client: Box<dyn TranscriptionClient>,
+    /// Rate the ASR client was opened with, frames at other rates are resampled
+    pub samplerate: Option<u32>,
+    /// Bumped on every ASR swap so the outgoing processor can be told apart
+    pub generation: u64,
+    /// Frames are dropped instead of sent to the recognizer while paused
+    pub paused: bool,
+    resampler: Option<(u32, Resampler)>,
 }
 
-impl AsrProcessor {}
+impl AsrProcessor {
+    pub fn new(asr_client: Box<dyn TranscriptionClient>, samplerate: Option<u32>) -> Self {
+        Self {
+            asr_client,
+            samplerate,
+            generation: 0,
+            paused: false,
+            resampler: None,
+        }
+    }
+
+    pub fn with_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+}
 
 impl Processor for AsrProcessor {
     fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
         match &frame.samples {
             Samples::PCM { samples } => {
-                if !samples.is_empty() {
-                    self.asr_client
-                        .send_audio(&samples, frame.src_packet.as_ref())?;
-                } else {
+                if samples.is_empty() {
                     tracing::debug!(track_id = %frame.track_id, "AsrProcessor: empty PCM samples");
+                    return Ok(());
+                }
+                match self.samplerate {
+                    Some(target) if frame.sample_rate > 0 && frame.sample_rate != target => {
+                        if !matches!(&self.resampler, Some((rate, _)) if *rate == frame.sample_rate)
+                        {
+                            self.resampler = None;
+                        }
+                        let (_, resampler) = self.resampler.get_or_insert_with(|| {
+                            (
+                                frame.sample_rate,
+                                Resampler::new(frame.sample_rate as usize, target as usize),
+                            )
+                        });
+                        // Source packets are at the wire rate and can't be forwarded as-is
+                        self.asr_client
+                            .send_audio(&resampler.resample(samples), None)?;
+                    }
+                    _ => {
+                        self.asr_client
+                            .send_audio(&samples, frame.src_packet.as_ref())?;
+                    }
                 }
             }
             _ => {
@@ -0,0 +1,111 @@
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+use tokio::sync::Notify;
+
+/// Frames buffered per call when `max_audio_buffer_frames` is unset, about
+/// 30 seconds of 20ms frames
+pub const DEFAULT_MAX_AUDIO_BUFFER_FRAMES: usize = 1500;
+
+struct Shared {
+    frames: Mutex<VecDeque<Bytes>>,
+    capacity: usize,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+/// Bounded audio queue between the client connection and its track. When the
+/// track falls behind, the oldest frames are dropped to make room.
+pub fn audio_queue(capacity: usize) -> (AudioQueueSender, AudioQueueReceiver) {
+    let shared = Arc::new(Shared {
+        frames: Mutex::new(VecDeque::new()),
+        capacity: capacity.max(1),
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        notify: Notify::new(),
+    });
+    (
+        AudioQueueSender {
+            shared: shared.clone(),
+        },
+        AudioQueueReceiver { shared },
+    )
+}
+
+pub struct AudioQueueSender {
+    shared: Arc<Shared>,
+}
+
+impl AudioQueueSender {
+    /// Queue a frame, returning true when an older frame was dropped for it
+    pub fn push(&self, frame: Bytes) -> bool {
+        let dropped = {
+            let mut frames = self.shared.frames.lock().unwrap();
+            let dropped = if frames.len() >= self.shared.capacity {
+                frames.pop_front();
+                true
+            } else {
+                false
+            };
+            frames.push_back(frame);
+            dropped
+        };
+        if dropped {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shared.notify.notify_one();
+        dropped
+    }
+
+    /// Total frames dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+}
+
+impl Drop for AudioQueueSender {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.notify.notify_one();
+    }
+}
+
+pub struct AudioQueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl AudioQueueReceiver {
+    /// Wait for the next frame. Returns None once the sender is gone and the
+    /// queue is drained
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        loop {
+            let frame = self.shared.frames.lock().unwrap().pop_front();
+            if frame.is_some() {
+                return frame;
+            }
+            if self.shared.closed.load(Ordering::Acquire) {
+                let frame = self.shared.frames.lock().unwrap().pop_front();
+                return frame;
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.frames.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
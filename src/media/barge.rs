@@ -0,0 +1,106 @@
+use crate::media::{AudioFrame, Samples, TrackId};
+use audio_codec::{PcmBuf, Resampler};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Track ID of a barging supervisor's audio
+pub const BARGE_TRACK_ID: &str = "barge-track";
+/// Rate of the audio supervisors send, 16-bit mono PCM
+pub const BARGE_SAMPLERATE: u32 = 16000;
+/// A track sent no audio for this long gets the supervisor's frames as they are
+const IDLE_AFTER: Duration = Duration::from_millis(60);
+/// Supervisor audio buffered per track before the oldest samples are dropped
+const MAX_BUFFERED_MS: usize = 200;
+
+#[derive(Default)]
+struct Output {
+    /// Supervisor audio at [`BARGE_SAMPLERATE`] waiting for the next frame sent to the track
+    pending: VecDeque<i16>,
+    last_sent: Option<Instant>,
+    /// Kept across frames so the supervisor's stream is resampled without a
+    /// seam at every frame boundary, rebuilt when the track's rate changes
+    resampler: Option<(u32, Resampler)>,
+}
+
+/// Mixes a barging supervisor's audio into the frames every track of the
+/// call is sent, so the caller and the agent both hear them. A track that is
+/// being sent audio gets the supervisor mixed into it, an idle one gets the
+/// supervisor's frames on their own.
+#[derive(Default)]
+pub struct BargeMixer {
+    outputs: HashMap<TrackId, Output>,
+}
+
+impl BargeMixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue supervisor `samples` for `tracks`. Returns the idle tracks, to
+    /// be sent the supervisor's frame directly
+    pub fn push<'a>(
+        &mut self,
+        tracks: impl IntoIterator<Item = &'a TrackId>,
+        samples: &[i16],
+    ) -> Vec<TrackId> {
+        let now = Instant::now();
+        let max_len = BARGE_SAMPLERATE as usize * MAX_BUFFERED_MS / 1000;
+        let mut idle = Vec::new();
+        for track_id in tracks {
+            if track_id == BARGE_TRACK_ID {
+                continue;
+            }
+            let output = self.outputs.entry(track_id.clone()).or_default();
+            match output.last_sent {
+                Some(last_sent) if now.duration_since(last_sent) < IDLE_AFTER => {
+                    output.pending.extend(samples.iter());
+                    if output.pending.len() > max_len {
+                        let excess = output.pending.len() - max_len;
+                        output.pending.drain(..excess);
+                    }
+                }
+                _ => {
+                    output.pending.clear();
+                    if let Some((_, resampler)) = output.resampler.as_mut() {
+                        resampler.reset();
+                    }
+                    idle.push(track_id.clone());
+                }
+            }
+        }
+        idle
+    }
+
+    /// Mix the supervisor audio queued for `track_id` into `frame`, a PCM
+    /// frame on its way to that track
+    pub fn mix(&mut self, track_id: &TrackId, frame: &mut AudioFrame) {
+        let Samples::PCM { samples } = &mut frame.samples else {
+            return;
+        };
+        let output = self.outputs.entry(track_id.clone()).or_default();
+        output.last_sent = Some(Instant::now());
+        if output.pending.is_empty() || frame.sample_rate == 0 {
+            return;
+        }
+        let channels = frame.channels.max(1) as usize;
+        let wanted =
+            samples.len() / channels * BARGE_SAMPLERATE as usize / frame.sample_rate as usize;
+        let available = wanted.min(output.pending.len());
+        let mut barge: PcmBuf = output.pending.drain(..available).collect();
+        if frame.sample_rate != BARGE_SAMPLERATE {
+            if !matches!(output.resampler, Some((rate, _)) if rate == frame.sample_rate) {
+                let resampler =
+                    Resampler::new(BARGE_SAMPLERATE as usize, frame.sample_rate as usize);
+                output.resampler = Some((frame.sample_rate, resampler));
+            }
+            if let Some((_, resampler)) = output.resampler.as_mut() {
+                barge = resampler.resample(&barge);
+            }
+        }
+        for (out, sample) in samples.chunks_mut(channels).zip(barge) {
+            for out in out {
+                *out = out.saturating_add(sample);
+            }
+        }
+    }
+}
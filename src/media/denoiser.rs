@@ -4,6 +4,7 @@ use audio_codec::Resampler;
 use nnnoiseless::DenoiseState;
 
 pub struct NoiseReducer {
+    input_sample_rate: u32,
     resampler_target: Resampler,
     resampler_source: Resampler,
     denoiser: Box<DenoiseState<'static>>,
@@ -15,6 +16,7 @@ impl NoiseReducer {
         let resampler16k = Resampler::new(input_sample_rate, 48000 as usize);
         let denoiser = DenoiseState::new();
         Self {
+            input_sample_rate: input_sample_rate as u32,
             resampler_target: resampler48k,
             resampler_source: resampler16k,
             denoiser,
@@ -35,6 +37,12 @@ impl Processor for NoiseReducer {
             Samples::PCM { samples } => samples,
             _ => return Ok(()),
         };
+        // The call may process at a different rate than the one we were built for
+        if frame.sample_rate > 0 && frame.sample_rate != self.input_sample_rate {
+            self.input_sample_rate = frame.sample_rate;
+            self.resampler_source = Resampler::new(frame.sample_rate as usize, 48000);
+            self.resampler_target = Resampler::new(48000, frame.sample_rate as usize);
+        }
         let samples = self.resampler_source.resample(samples);
         let input_size = samples.len();
 
@@ -1,4 +1,9 @@
+use super::processor::Processor;
+use crate::event::{EventSender, SessionEvent};
+use crate::media::{AudioFrame, PcmBuf, Samples, get_timestamp};
+use anyhow::Result;
 use std::sync::atomic::{AtomicU8, AtomicU16};
+use tracing::debug;
 // DTMF events as per RFC 4733
 const DTMF_EVENT_0: u8 = 0;
 const DTMF_EVENT_1: u8 = 1;
@@ -136,6 +141,203 @@ impl DtmfDetector {
     }
 }
 
+/// Row and column frequencies of the DTMF keypad
+const DTMF_ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+/// Share of a block's energy each of the two tones needs by default
+pub const DEFAULT_DTMF_THRESHOLD: f32 = 0.25;
+/// Blocks quieter than this mean square are treated as silence
+const MIN_BLOCK_ENERGY: f32 = 1e4;
+/// Consecutive blocks a tone must last before it is reported (~50ms)
+const MIN_TONE_BLOCKS: u32 = 2;
+/// Blocks without the tone that end a keypress, tolerating one dropout
+const MAX_GAP_BLOCKS: u32 = 2;
+
+/// Peak amplitude of each tone of a generated pair
+const DTMF_TONE_AMPLITUDE: f64 = 6000.0;
+
+/// Row and column frequencies of the key `digit`
+fn dtmf_frequencies(digit: char) -> Option<(f32, f32)> {
+    let digit = digit.to_ascii_uppercase();
+    DTMF_KEYS.iter().enumerate().find_map(|(row, keys)| {
+        keys.iter()
+            .position(|key| *key == digit)
+            .map(|col| (DTMF_ROWS[row], DTMF_COLS[col]))
+    })
+}
+
+/// `duration_ms` of the tone pair for `digit`, synthesized at `sample_rate`.
+/// Generate at the rate of the track the tone is sent to, 8kHz for G.711 or
+/// 48kHz for Opus, since resampling a tone smears it enough for gateways to
+/// miss the digit. `None` for a character that isn't a DTMF key
+pub fn generate_dtmf_tone(digit: char, sample_rate: u32, duration_ms: u32) -> Option<PcmBuf> {
+    let (row, col) = dtmf_frequencies(digit)?;
+    let len = sample_rate as usize * duration_ms as usize / 1000;
+    let step = 2.0 * std::f64::consts::PI / sample_rate.max(1) as f64;
+    Some(
+        (0..len)
+            .map(|n| {
+                let phase = n as f64 * step;
+                let sample = (row as f64 * phase).sin() + (col as f64 * phase).sin();
+                (DTMF_TONE_AMPLITUDE * sample) as i16
+            })
+            .collect(),
+    )
+}
+
+/// Goertzel power of `samples` at the frequency `coeff` was computed for
+fn goertzel(samples: &[f32], coeff: f32) -> f32 {
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &x in samples {
+        let s = x + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    s1 * s1 + s2 * s2 - coeff * s1 * s2
+}
+
+/// Decodes inband DTMF tones from PCM frames with a Goertzel filter bank,
+/// for peers that don't send RFC2833 events. A keypress is reported once as
+/// a `Dtmf` event when its tone pair lasts [`MIN_TONE_BLOCKS`] blocks.
+pub struct DtmfDetectProcessor {
+    track_id: String,
+    event_sender: EventSender,
+    /// Share of the block energy, 0.0-0.5, each tone of the pair needs;
+    /// lower values detect quieter or noisier tones
+    threshold: f32,
+    sample_rate: u32,
+    /// Goertzel coefficients for rows then columns at `sample_rate`
+    coeffs: [f32; 8],
+    block: Vec<f32>,
+    block_len: usize,
+    current: Option<char>,
+    tone_blocks: u32,
+    gap_blocks: u32,
+    reported: bool,
+}
+
+impl DtmfDetectProcessor {
+    pub fn new(track_id: String, event_sender: EventSender, threshold: f32) -> Self {
+        Self {
+            track_id,
+            event_sender,
+            threshold,
+            sample_rate: 0,
+            coeffs: [0.0; 8],
+            block: Vec::new(),
+            block_len: 0,
+            current: None,
+            tone_blocks: 0,
+            gap_blocks: 0,
+            reported: false,
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        // 205 samples at 8kHz, the usual block for telephone DTMF
+        self.block_len = (sample_rate as usize * 205 / 8000).max(1);
+        self.block.clear();
+        for (coeff, freq) in self
+            .coeffs
+            .iter_mut()
+            .zip(DTMF_ROWS.iter().chain(DTMF_COLS.iter()))
+        {
+            *coeff = 2.0 * (2.0 * std::f32::consts::PI * freq / sample_rate as f32).cos();
+        }
+    }
+
+    /// The key whose tone pair dominates the block, if any
+    fn detect_block(&self) -> Option<char> {
+        let energy: f32 = self.block.iter().map(|x| x * x).sum();
+        if energy / (self.block.len() as f32) < MIN_BLOCK_ENERGY {
+            return None;
+        }
+        // A pure tone's Goertzel power is energy * len / 2
+        let scale = energy * self.block.len() as f32 / 2.0;
+        let mut powers = [0.0f32; 8];
+        for (power, coeff) in powers.iter_mut().zip(self.coeffs.iter()) {
+            *power = goertzel(&self.block, *coeff) / scale;
+        }
+        let strongest = |powers: &[f32]| {
+            powers
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(i, p)| (i, *p))
+                .unwrap_or((0, 0.0))
+        };
+        let (row, row_power) = strongest(&powers[..4]);
+        let (col, col_power) = strongest(&powers[4..]);
+        if row_power < self.threshold || col_power < self.threshold {
+            return None;
+        }
+        Some(DTMF_KEYS[row][col])
+    }
+
+    fn on_block(&mut self, key: Option<char>) {
+        match key {
+            Some(key) if self.current == Some(key) => {
+                self.tone_blocks += 1;
+                self.gap_blocks = 0;
+            }
+            Some(key) => {
+                self.current = Some(key);
+                self.tone_blocks = 1;
+                self.gap_blocks = 0;
+                self.reported = false;
+            }
+            None => {
+                self.gap_blocks += 1;
+                if self.gap_blocks >= MAX_GAP_BLOCKS {
+                    self.current = None;
+                    self.tone_blocks = 0;
+                    self.reported = false;
+                }
+                return;
+            }
+        }
+        if !self.reported && self.tone_blocks >= MIN_TONE_BLOCKS {
+            self.reported = true;
+            let digit = self.current.unwrap_or_default().to_string();
+            debug!(track_id = self.track_id, digit, "inband dtmf detected");
+            self.event_sender
+                .send(SessionEvent::Dtmf {
+                    track_id: self.track_id.clone(),
+                    timestamp: get_timestamp(),
+                    digit,
+                })
+                .ok();
+        }
+    }
+}
+
+impl Processor for DtmfDetectProcessor {
+    fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        let Samples::PCM { samples } = &frame.samples else {
+            return Ok(());
+        };
+        if frame.sample_rate != self.sample_rate {
+            self.set_sample_rate(frame.sample_rate);
+        }
+        for &sample in samples {
+            self.block.push(sample as f32);
+            if self.block.len() == self.block_len {
+                let key = self.detect_block();
+                self.block.clear();
+                self.on_block(key);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -254,4 +456,130 @@ mod tests {
             "Second press with smaller duration should be recognized as new press"
         );
     }
+
+    /// The two strongest frequencies of `samples`, lowest first
+    fn dominant_frequencies(samples: &[i16], sample_rate: u32) -> (f32, f32) {
+        let mut planner = realfft::RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(samples.len());
+        let mut input: Vec<f32> = samples.iter().map(|s| *s as f32).collect();
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut input, &mut spectrum).unwrap();
+        let bin_hz = sample_rate as f32 / samples.len() as f32;
+        let mut peaks: Vec<(usize, f32)> = spectrum
+            .iter()
+            .enumerate()
+            .map(|(bin, c)| (bin, c.norm()))
+            .collect();
+        peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let first = peaks[0].0 as f32 * bin_hz;
+        // Skip the bins leaking around the strongest peak
+        let second = peaks
+            .iter()
+            .map(|(bin, _)| *bin as f32 * bin_hz)
+            .find(|freq| (freq - first).abs() > 50.0)
+            .unwrap();
+        (first.min(second), first.max(second))
+    }
+
+    #[test]
+    fn test_generated_dtmf_frequencies() {
+        for sample_rate in [8000, 48000] {
+            for (digit, row, col) in [
+                ('1', 697.0, 1209.0),
+                ('0', 941.0, 1336.0),
+                ('#', 941.0, 1477.0),
+                ('d', 941.0, 1633.0),
+            ] {
+                // 1s of tone gives 1Hz bins
+                let tone = generate_dtmf_tone(digit, sample_rate, 1000).unwrap();
+                assert_eq!(tone.len(), sample_rate as usize);
+                let (low, high) = dominant_frequencies(&tone, sample_rate);
+                assert!(
+                    (low - row).abs() <= 1.0 && (high - col).abs() <= 1.0,
+                    "{} at {}Hz: {} / {}",
+                    digit,
+                    sample_rate,
+                    low,
+                    high
+                );
+            }
+        }
+        assert!(generate_dtmf_tone('x', 8000, 100).is_none());
+    }
+
+    #[test]
+    fn test_generated_dtmf_detected() {
+        for sample_rate in [8000u32, 48000] {
+            let event_sender = crate::event::create_event_sender();
+            let mut receiver = event_sender.subscribe();
+            let mut processor = DtmfDetectProcessor::new(
+                "caller".to_string(),
+                event_sender,
+                DEFAULT_DTMF_THRESHOLD,
+            );
+            let mut samples = generate_dtmf_tone('7', sample_rate, 200).unwrap();
+            samples.extend(std::iter::repeat_n(0, sample_rate as usize / 10));
+            for chunk in samples.chunks(sample_rate as usize / 50) {
+                let mut frame = AudioFrame {
+                    track_id: "caller".to_string(),
+                    samples: Samples::PCM {
+                        samples: chunk.to_vec(),
+                    },
+                    sample_rate,
+                    ..Default::default()
+                };
+                processor.process_frame(&mut frame).unwrap();
+            }
+            let digits: Vec<String> = std::iter::from_fn(|| receiver.try_recv().ok())
+                .filter_map(|event| match event {
+                    SessionEvent::Dtmf { digit, .. } => Some(digit),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(digits, vec!["7".to_string()], "at {}Hz", sample_rate);
+        }
+    }
+
+    #[test]
+    fn test_inband_dtmf_long_keypress() {
+        let event_sender = crate::event::create_event_sender();
+        let mut receiver = event_sender.subscribe();
+        let mut processor =
+            DtmfDetectProcessor::new("caller".to_string(), event_sender, DEFAULT_DTMF_THRESHOLD);
+
+        // 400ms of the 697/1209Hz pair for "1", then 100ms of silence, in 20ms frames
+        let sample_rate = 8000;
+        let tone: Vec<i16> = (0..sample_rate * 4 / 10)
+            .map(|n| {
+                let t = n as f32 / sample_rate as f32;
+                let row = (2.0 * std::f32::consts::PI * 697.0 * t).sin();
+                let col = (2.0 * std::f32::consts::PI * 1209.0 * t).sin();
+                (6000.0 * (row + col)) as i16
+            })
+            .chain(std::iter::repeat_n(0, sample_rate / 10))
+            .collect();
+        for chunk in tone.chunks(160) {
+            let mut frame = AudioFrame {
+                track_id: "caller".to_string(),
+                samples: Samples::PCM {
+                    samples: chunk.to_vec(),
+                },
+                sample_rate: sample_rate as u32,
+                ..Default::default()
+            };
+            processor.process_frame(&mut frame).unwrap();
+        }
+
+        let mut digits = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if let SessionEvent::Dtmf {
+                digit, track_id, ..
+            } = event
+            {
+                assert_eq!(track_id, "caller");
+                digits.push(digit);
+            }
+        }
+        assert_eq!(digits, vec!["1".to_string()]);
+    }
 }
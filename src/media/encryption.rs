@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use aws_lc_rs::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use aws_lc_rs::rand::{SecureRandom, SystemRandom};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use std::path::Path;
+
+/// Header of encrypted recording files, followed by the nonce and the AES-256-GCM ciphertext
+const MAGIC: &[u8] = b"ACREC1";
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+pub const CIPHER_NAME: &str = "aes-256-gcm";
+
+/// Parse a 256-bit key given as 64 hex characters or base64.
+pub fn parse_key(key: &str) -> Result<[u8; 32]> {
+    let key = key.trim();
+    let bytes = if key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit()) {
+        hex::decode(key)?
+    } else {
+        BASE64_STANDARD
+            .decode(key)
+            .map_err(|e| anyhow!("invalid recording encryption key: {}", e))?
+    };
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("recording encryption key must be 32 bytes"))
+}
+
+fn build_key(key: &[u8; 32]) -> Result<LessSafeKey> {
+    let unbound = UnboundKey::new(&AES_256_GCM, key)
+        .map_err(|_| anyhow!("invalid recording encryption key"))?;
+    Ok(LessSafeKey::new(unbound))
+}
+
+pub fn encrypt(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>> {
+    let key = build_key(key)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("failed to generate nonce"))?;
+
+    let mut in_out = data.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(MAGIC),
+        &mut in_out,
+    )
+    .map_err(|_| anyhow!("failed to encrypt recording"))?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + in_out.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&in_out);
+    Ok(out)
+}
+
+pub fn decrypt(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>> {
+    if data.len() < MAGIC.len() + NONCE_LEN || !data.starts_with(MAGIC) {
+        return Err(anyhow!("not an encrypted recording"));
+    }
+    let key = build_key(key)?;
+    let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| anyhow!("invalid nonce in encrypted recording"))?;
+    let mut in_out = ciphertext.to_vec();
+    let plain = key
+        .open_in_place(nonce, Aad::from(MAGIC), &mut in_out)
+        .map_err(|_| anyhow!("failed to decrypt recording, wrong key or corrupted file"))?;
+    Ok(plain.to_vec())
+}
+
+/// Encrypt `path` into `<path>.enc` and remove the plaintext file.
+/// Returns the path of the encrypted file.
+pub async fn encrypt_file(path: &Path, key: &[u8; 32]) -> Result<String> {
+    let data = tokio::fs::read(path).await?;
+    let encrypted = encrypt(&data, key)?;
+    let encrypted_path = format!("{}.{}", path.to_string_lossy(), ENCRYPTED_EXTENSION);
+    tokio::fs::write(&encrypted_path, encrypted).await?;
+    tokio::fs::remove_file(path).await?;
+    Ok(encrypted_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_formats() {
+        let hex_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let key = parse_key(hex_key).unwrap();
+        assert_eq!(key[1], 0x11);
+        let b64 = BASE64_STANDARD.encode(key);
+        assert_eq!(parse_key(&b64).unwrap(), key);
+        assert!(parse_key("short").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_recording_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.wav");
+        {
+            let spec = hound::WavSpec {
+                channels: 1,
+                sample_rate: 16000,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+            for i in 0..1600 {
+                writer.write_sample((i % 100) as i16 * 100).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+        let original = std::fs::read(&path).unwrap();
+        let key = [42u8; 32];
+
+        let encrypted_path = encrypt_file(&path, &key).await.unwrap();
+        assert!(encrypted_path.ends_with(".wav.enc"));
+        assert!(!path.exists(), "plaintext recording should be removed");
+
+        let encrypted = std::fs::read(&encrypted_path).unwrap();
+        let contains = |needle: &[u8]| encrypted.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(b"RIFF") && !contains(b"WAVE") && !contains(b"fmt "));
+
+        assert_eq!(decrypt(&encrypted, &key).unwrap(), original);
+    }
+
+    #[test]
+    fn test_decrypt_rejects_wrong_key() {
+        let data = encrypt(b"RIFF....WAVEfmt ", &[7u8; 32]).unwrap();
+        assert!(decrypt(&data, &[8u8; 32]).is_err());
+    }
+}
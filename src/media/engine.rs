@@ -20,6 +20,7 @@ use crate::{
     transcription::{
         AliyunAsrClientBuilder, TencentCloudAsrClientBuilder, TranscriptionClient,
         TranscriptionOption, TranscriptionType,
+        fallback::{FallbackAsr, FallbackAsrClient},
     },
 };
 
@@ -30,7 +31,7 @@ use anyhow::Result;
 use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
-use tracing::debug;
+use tracing::{debug, warn};
 
 pub type FnCreateVadProcessor = fn(
     token: CancellationToken,
@@ -74,7 +75,7 @@ pub type CreateProcessorsHook = Box<
 pub struct StreamEngine {
     vad_creators: HashMap<VadType, FnCreateVadProcessor>,
     eou_creators: HashMap<String, FnCreateEouProcessor>,
-    asr_creators: HashMap<TranscriptionType, FnCreateAsrClient>,
+    asr_creators: HashMap<TranscriptionType, Arc<FnCreateAsrClient>>,
     tts_creators: HashMap<SynthesisType, FnCreateTtsClient>,
     create_processors_hook: Arc<CreateProcessorsHook>,
 }
@@ -103,7 +104,7 @@ impl Default for StreamEngine {
         engine.register_tts(SynthesisType::Aliyun, AliyunTtsClient::create);
         engine.register_tts(SynthesisType::TencentCloud, TencentCloudTtsClient::create);
         engine.register_tts(
-            SynthesisType::Other("tencent_basic".to_string()),
+            SynthesisType::TencentCloudBasic,
             TencentCloudTtsBasicClient::create,
         );
         engine.register_tts(SynthesisType::Deepgram, DeepegramTtsClient::create);
@@ -141,7 +142,7 @@ impl StreamEngine {
         asr_type: TranscriptionType,
         creator: FnCreateAsrClient,
     ) -> &mut Self {
-        self.asr_creators.insert(asr_type, creator);
+        self.asr_creators.insert(asr_type, Arc::new(creator));
         self
     }
 
@@ -190,18 +191,73 @@ impl StreamEngine {
         option: TranscriptionOption,
         event_sender: EventSender,
     ) -> Result<Box<dyn Processor>> {
-        let asr_client = match option.provider {
-            Some(ref provider) => {
-                let creator = self.asr_creators.get(&provider);
-                if let Some(creator) = creator {
-                    creator(track_id, cancel_token, option, event_sender).await?
-                } else {
-                    return Err(anyhow::anyhow!("ASR type not found: {}", provider));
+        let samplerate = option.samplerate;
+        let asr_client = self
+            .create_asr_client(track_id, cancel_token, option, event_sender)
+            .await?;
+        Ok(Box::new(AsrProcessor::new(asr_client, samplerate)))
+    }
+
+    /// Create the recognizer of `option`. With a `fallback` configured, a
+    /// recognizer that fails to start is replaced right away, and one that
+    /// fails mid-call is replaced on its first failed write.
+    pub async fn create_asr_client(
+        &self,
+        track_id: TrackId,
+        cancel_token: CancellationToken,
+        mut option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> Result<Box<dyn TranscriptionClient>> {
+        let fallback = match option.fallback.take() {
+            Some(mut fallback_option) => {
+                // Audio reaches the fallback at the rate the processor resamples to
+                fallback_option.samplerate = option.samplerate;
+                fallback_option.fallback = None;
+                // Its finals reach the same session, so they are formatted alike
+                if fallback_option.format_transcripts.is_none() {
+                    fallback_option.format_transcripts = option.format_transcripts;
                 }
+                Some(FallbackAsr {
+                    creator: self.asr_creator(&fallback_option)?,
+                    track_id: track_id.clone(),
+                    cancel_token: cancel_token.clone(),
+                    option: *fallback_option,
+                    event_sender: event_sender.clone(),
+                })
             }
-            None => return Err(anyhow::anyhow!("ASR type not found: {:?}", option.provider)),
+            None => None,
         };
-        Ok(Box::new(AsrProcessor { asr_client }))
+        let provider = option
+            .provider
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        let primary = match self.asr_creator(&option) {
+            Ok(creator) => creator(track_id, cancel_token, option, event_sender).await,
+            Err(e) => Err(e),
+        };
+        match (primary, fallback) {
+            (Ok(client), Some(fallback)) => {
+                Ok(Box::new(FallbackAsrClient::new(provider, client, fallback)))
+            }
+            (Ok(client), None) => Ok(client),
+            (Err(e), Some(fallback)) => {
+                warn!("failed to start asr {}: {}", provider, e);
+                fallback.start(&provider, &e.to_string()).await
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    fn asr_creator(&self, option: &TranscriptionOption) -> Result<Arc<FnCreateAsrClient>> {
+        match option.provider {
+            Some(ref provider) => self
+                .asr_creators
+                .get(provider)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("ASR type not found: {}", provider)),
+            None => Err(anyhow::anyhow!("ASR type not found: {:?}", option.provider)),
+        }
     }
 
     pub async fn create_tts_client(
@@ -297,14 +353,20 @@ impl StreamEngine {
                 return Ok(processors);
             }
 
+            let samplerate = option.internal_samplerate.unwrap_or(INTERNAL_SAMPLERATE);
             match option.denoise {
                 Some(true) => {
                     debug!(%track_id, "Adding NoiseReducer processor");
-                    let noise_reducer = NoiseReducer::new(INTERNAL_SAMPLERATE as usize);
+                    let noise_reducer = NoiseReducer::new(samplerate as usize);
                     processors.push(Box::new(noise_reducer) as Box<dyn Processor>);
                 }
                 _ => {}
             }
+            if let Some(noise_gate) = option.noise_gate.as_ref().filter(|o| o.is_enabled()) {
+                debug!(%track_id, "Adding NoiseGateProcessor");
+                let noise_gate = crate::media::noise_gate::NoiseGateProcessor::new(noise_gate);
+                processors.push(Box::new(noise_gate) as Box<dyn Processor>);
+            }
             match option.vad {
                 Some(mut option) => {
                     debug!(%track_id, "Adding VadProcessor processor type={:?}", option.r#type);
@@ -321,7 +383,8 @@ impl StreamEngine {
             match option.asr {
                 Some(mut option) => {
                     debug!(%track_id, "Adding AsrProcessor processor provider={:?}", option.provider);
-                    option.samplerate = Some(INTERNAL_SAMPLERATE);
+                    // Narrowband calls are transcribed at 8k, wideband ones are resampled to 16k
+                    option.samplerate = Some(samplerate.min(INTERNAL_SAMPLERATE));
                     let asr_processor = engine
                         .create_asr_processor(
                             track_id.clone(),
@@ -357,6 +420,28 @@ impl StreamEngine {
                 }
                 _ => {}
             }
+            match option.media_timeout_secs {
+                Some(timeout_secs) if timeout_secs > 0 => {
+                    let media_timeout_processor =
+                        crate::media::inactivity::MediaTimeoutProcessor::new(
+                            track_id.clone(),
+                            std::time::Duration::from_secs(timeout_secs),
+                            event_sender.clone(),
+                            cancel_token.child_token(),
+                        );
+                    processors.push(Box::new(media_timeout_processor) as Box<dyn Processor>);
+                }
+                _ => {}
+            }
+            if option.inband_dtmf.unwrap_or(false) {
+                debug!(%track_id, "Adding DtmfDetectProcessor");
+                let dtmf_processor = crate::media::dtmf::DtmfDetectProcessor::new(
+                    track_id.clone(),
+                    event_sender.clone(),
+                    crate::media::dtmf::DEFAULT_DTMF_THRESHOLD,
+                );
+                processors.push(Box::new(dtmf_processor) as Box<dyn Processor>);
+            }
 
             Ok(processors)
         })
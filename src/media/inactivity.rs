@@ -3,8 +3,9 @@ use crate::event::{EventSender, SessionEvent};
 use crate::media::{AudioFrame, get_timestamp};
 use anyhow::Result;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
+use tokio::sync::broadcast;
 use tokio_util::sync::CancellationToken;
 use tracing::info;
 
@@ -54,3 +55,125 @@ impl Processor for InactivityProcessor {
         Ok(())
     }
 }
+
+/// Detects calls that are answered but never receive any inbound audio
+/// (broken media path, NAT). Once the track is answered, sends a
+/// `MediaTimeout` event if no frame arrived within `timeout`.
+pub struct MediaTimeoutProcessor {
+    received: Arc<AtomicBool>,
+}
+
+impl MediaTimeoutProcessor {
+    pub fn new(
+        track_id: String,
+        timeout: Duration,
+        event_sender: EventSender,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        let received = Arc::new(AtomicBool::new(false));
+        let received_clone = received.clone();
+        // Subscribe before returning so an Answer sent right after setup is not missed
+        let mut event_receiver = event_sender.subscribe();
+
+        crate::spawn(async move {
+            let answered = async {
+                loop {
+                    match event_receiver.recv().await {
+                        Ok(SessionEvent::Answer { track_id: id, .. }) if id == track_id => {
+                            return true;
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(_) => return false,
+                    }
+                }
+            };
+            let answered = tokio::select! {
+                _ = cancel_token.cancelled() => false,
+                answered = answered => answered,
+            };
+            if !answered {
+                return;
+            }
+            tokio::select! {
+                _ = cancel_token.cancelled() => return,
+                _ = tokio::time::sleep(timeout) => {}
+            }
+            if !received_clone.load(Ordering::SeqCst) {
+                info!(
+                    track_id,
+                    "no media received after answer, sending media timeout event"
+                );
+                event_sender
+                    .send(SessionEvent::MediaTimeout {
+                        track_id: track_id.clone(),
+                        timestamp: get_timestamp(),
+                    })
+                    .ok();
+            }
+        });
+
+        Self { received }
+    }
+}
+
+impl Processor for MediaTimeoutProcessor {
+    fn process_frame(&mut self, _frame: &mut AudioFrame) -> Result<()> {
+        self.received.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer_event(track_id: &str) -> SessionEvent {
+        SessionEvent::Answer {
+            track_id: track_id.to_string(),
+            timestamp: get_timestamp(),
+            sdp: String::new(),
+            refer: None,
+        }
+    }
+
+    async fn wait_media_timeout(receiver: &mut crate::event::EventReceiver) -> bool {
+        tokio::time::timeout(Duration::from_millis(500), async {
+            loop {
+                if let Ok(SessionEvent::MediaTimeout { .. }) = receiver.recv().await {
+                    return;
+                }
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_media_timeout_without_frames() {
+        let event_sender = crate::event::create_event_sender();
+        let mut receiver = event_sender.subscribe();
+        let _processor = MediaTimeoutProcessor::new(
+            "caller".to_string(),
+            Duration::from_millis(100),
+            event_sender.clone(),
+            CancellationToken::new(),
+        );
+        event_sender.send(answer_event("caller")).ok();
+        assert!(wait_media_timeout(&mut receiver).await);
+    }
+
+    #[tokio::test]
+    async fn test_no_media_timeout_when_frames_arrive() {
+        let event_sender = crate::event::create_event_sender();
+        let mut receiver = event_sender.subscribe();
+        let mut processor = MediaTimeoutProcessor::new(
+            "caller".to_string(),
+            Duration::from_millis(100),
+            event_sender.clone(),
+            CancellationToken::new(),
+        );
+        event_sender.send(answer_event("caller")).ok();
+        processor.process_frame(&mut AudioFrame::default()).unwrap();
+        assert!(!wait_media_timeout(&mut receiver).await);
+    }
+}
@@ -2,7 +2,6 @@ use crate::media::cache;
 use anyhow::{Result, anyhow};
 use audio_codec::Resampler;
 use hound::WavReader;
-use reqwest::Client;
 use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::time::Instant;
@@ -24,8 +23,10 @@ pub async fn download_from_url(url: &str, use_cache: bool) -> Result<File> {
 
     // Download file if not cached
     let start_time = Instant::now();
-    let client = Client::new();
-    let response = client.get(url).send().await?;
+    let client = crate::net_tool::http_client();
+    let response = crate::net_tool::with_request_timeout(client.get(url))
+        .send()
+        .await?;
     let bytes = response.bytes().await?;
     let data = bytes.to_vec();
     let duration = start_time.elapsed();
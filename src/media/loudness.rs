@@ -0,0 +1,153 @@
+use super::processor::Processor;
+use crate::media::{AudioFrame, Samples};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessOption {
+    pub enabled: Option<bool>,
+    /// Target RMS level in dBFS, default -20
+    pub target_dbfs: Option<f32>,
+    /// Upper bound on the applied gain in dB, default 12
+    pub max_gain_db: Option<f32>,
+    /// Frames quieter than this (dBFS) are treated as silence and never boosted, default -50
+    pub noise_floor_dbfs: Option<f32>,
+}
+
+impl LoudnessOption {
+    pub fn merge(&mut self, other: &LoudnessOption) {
+        if self.enabled.is_none() {
+            self.enabled = other.enabled;
+        }
+        if self.target_dbfs.is_none() {
+            self.target_dbfs = other.target_dbfs;
+        }
+        if self.max_gain_db.is_none() {
+            self.max_gain_db = other.max_gain_db;
+        }
+        if self.noise_floor_dbfs.is_none() {
+            self.noise_floor_dbfs = other.noise_floor_dbfs;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+fn dbfs_to_amplitude(dbfs: f32) -> f32 {
+    32768.0 * 10f32.powf(dbfs / 20.0)
+}
+
+/// Normalizes outgoing agent audio (TTS, played files) towards a target RMS level.
+pub struct LoudnessProcessor {
+    target_rms: f32,
+    max_gain: f32,
+    noise_floor_rms: f32,
+    gain: f32,
+    smoothing: f32,
+}
+
+impl LoudnessProcessor {
+    pub fn new(option: &LoudnessOption) -> Self {
+        Self {
+            target_rms: dbfs_to_amplitude(option.target_dbfs.unwrap_or(-20.0)),
+            max_gain: 10f32.powf(option.max_gain_db.unwrap_or(12.0) / 20.0),
+            noise_floor_rms: dbfs_to_amplitude(option.noise_floor_dbfs.unwrap_or(-50.0)),
+            gain: 1.0,
+            smoothing: 0.2,
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    fn rms(samples: &[i16]) -> f32 {
+        if samples.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = samples.iter().map(|s| (*s as f64) * (*s as f64)).sum();
+        (sum / samples.len() as f64).sqrt() as f32
+    }
+}
+
+impl Processor for LoudnessProcessor {
+    fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        let samples = match &mut frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+
+        let rms = Self::rms(samples);
+        // Near-silence keeps the previous gain (capped at unity) so the noise
+        // floor between words is not pumped up.
+        let desired = if rms < self.noise_floor_rms {
+            self.gain.min(1.0)
+        } else {
+            (self.target_rms / rms).min(self.max_gain)
+        };
+        self.gain += (desired - self.gain) * self.smoothing;
+
+        if (self.gain - 1.0).abs() > f32::EPSILON {
+            for sample in samples.iter_mut() {
+                let adjusted = *sample as f32 * self.gain;
+                *sample = adjusted.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_frame(amplitude: f32, offset: usize) -> AudioFrame {
+        let samples = (0..320)
+            .map(|i| {
+                let t = (offset + i) as f32 / 16000.0;
+                (amplitude * (2.0 * std::f32::consts::PI * 440.0 * t).sin()) as i16
+            })
+            .collect();
+        AudioFrame {
+            samples: Samples::PCM { samples },
+            ..Default::default()
+        }
+    }
+
+    fn output_rms(processor: &mut LoudnessProcessor, amplitude: f32) -> f32 {
+        let mut last = 0.0;
+        for n in 0..100 {
+            let mut frame = sine_frame(amplitude, n * 320);
+            processor.process_frame(&mut frame).unwrap();
+            if let Samples::PCM { samples } = &frame.samples {
+                last = LoudnessProcessor::rms(samples);
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn test_loudness_converges_to_target() {
+        let option = LoudnessOption::default();
+        let target = dbfs_to_amplitude(-20.0);
+
+        let mut quiet = LoudnessProcessor::new(&option);
+        let quiet_rms = output_rms(&mut quiet, 2000.0);
+        let mut loud = LoudnessProcessor::new(&option);
+        let loud_rms = output_rms(&mut loud, 20000.0);
+
+        assert!((quiet_rms - target).abs() / target < 0.05, "{}", quiet_rms);
+        assert!((loud_rms - target).abs() / target < 0.05, "{}", loud_rms);
+    }
+
+    #[test]
+    fn test_loudness_does_not_boost_silence() {
+        let mut processor = LoudnessProcessor::new(&LoudnessOption::default());
+        let rms = output_rms(&mut processor, 50.0);
+        assert!(processor.gain() <= 1.0);
+        assert!(rms < 50.0);
+    }
+}
@@ -2,14 +2,23 @@ use serde::{Deserialize, Serialize};
 
 pub mod ambiance;
 pub mod asr_processor;
+pub mod audio_queue;
+pub mod barge;
 pub mod cache;
 pub mod denoiser;
 pub mod dtmf;
+pub mod encryption;
 pub mod engine;
 pub mod inactivity;
 pub mod loader;
+pub mod loudness;
+pub mod monitor;
 pub mod negotiate;
+pub mod noise_gate;
+#[cfg(feature = "opus")]
+pub mod ogg;
 pub mod processor;
+pub mod quality;
 pub mod realtime_processor;
 pub mod recorder;
 pub mod stream;
@@ -18,9 +27,33 @@ mod tests;
 pub mod track;
 pub mod vad;
 pub mod volume_control;
+pub mod watermark;
 pub use audio_codec::PcmBuf;
 pub use audio_codec::Sample;
 pub const INTERNAL_SAMPLERATE: u32 = 16000;
+pub const SUPPORTED_INTERNAL_SAMPLERATES: [u32; 3] = [8000, 16000, 48000];
+
+/// Internal processing rate matching a negotiated codec: narrowband codecs
+/// run at 8k, G.722 at 16k and Opus at 48k.
+pub fn internal_samplerate_for_codec(codec: audio_codec::CodecType) -> u32 {
+    use audio_codec::CodecType;
+    match codec {
+        CodecType::PCMU | CodecType::PCMA | CodecType::G729 => 8000,
+        #[cfg(feature = "opus")]
+        CodecType::Opus => 48000,
+        _ => INTERNAL_SAMPLERATE,
+    }
+}
+
+/// Resolve a requested internal rate, `0` picks the rate matching `codec`.
+/// Unsupported rates yield `None`.
+pub fn resolve_internal_samplerate(requested: u32, codec: audio_codec::CodecType) -> Option<u32> {
+    match requested {
+        0 => Some(internal_samplerate_for_codec(codec)),
+        rate if SUPPORTED_INTERNAL_SAMPLERATES.contains(&rate) => Some(rate),
+        _ => None,
+    }
+}
 pub type TrackId = String;
 pub type PayloadBuf = Vec<u8>;
 
@@ -0,0 +1,97 @@
+use crate::media::{AudioFrame, Samples, TrackId, processor::Processor};
+use anyhow::Result;
+use audio_codec::{PcmBuf, Resampler};
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::broadcast;
+
+/// Rate of the mixed audio sent to monitors, 16-bit mono PCM
+pub const MONITOR_SAMPLERATE: u32 = 16000;
+pub const MONITOR_PTIME_MS: u32 = 20;
+/// Audio buffered per track before the oldest samples are dropped
+const MAX_BUFFERED_MS: usize = 200;
+
+pub type MonitorSender = broadcast::Sender<AudioFrame>;
+
+/// Copies the decoded frames of a track to the call's monitors, if any
+pub struct MonitorProcessor {
+    sender: MonitorSender,
+}
+
+impl MonitorProcessor {
+    pub fn new(sender: MonitorSender) -> Self {
+        Self { sender }
+    }
+}
+
+impl Processor for MonitorProcessor {
+    fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        if self.sender.receiver_count() == 0 {
+            return Ok(());
+        }
+        if matches!(&frame.samples, Samples::PCM { samples } if !samples.is_empty()) {
+            self.sender.send(frame.clone()).ok();
+        }
+        Ok(())
+    }
+}
+
+struct TrackBuffer {
+    sample_rate: u32,
+    resampler: Option<Resampler>,
+    samples: VecDeque<i16>,
+}
+
+/// Mixes the frames of every track of a call into one mono stream
+pub struct MonitorMixer {
+    sample_rate: u32,
+    tracks: HashMap<TrackId, TrackBuffer>,
+}
+
+impl MonitorMixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            tracks: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, frame: &AudioFrame) {
+        let Samples::PCM { samples } = &frame.samples else {
+            return;
+        };
+        let buffer = self
+            .tracks
+            .entry(frame.track_id.clone())
+            .or_insert_with(|| TrackBuffer {
+                sample_rate: 0,
+                resampler: None,
+                samples: VecDeque::new(),
+            });
+        if buffer.sample_rate != frame.sample_rate {
+            buffer.sample_rate = frame.sample_rate;
+            buffer.resampler = (frame.sample_rate > 0 && frame.sample_rate != self.sample_rate)
+                .then(|| Resampler::new(frame.sample_rate as usize, self.sample_rate as usize));
+        }
+        match buffer.resampler.as_mut() {
+            Some(resampler) => buffer.samples.extend(resampler.resample(samples)),
+            None => buffer.samples.extend(samples.iter()),
+        }
+        let max_len = self.sample_rate as usize * MAX_BUFFERED_MS / 1000;
+        if buffer.samples.len() > max_len {
+            let excess = buffer.samples.len() - max_len;
+            buffer.samples.drain(..excess);
+        }
+    }
+
+    /// Take `len` mixed samples, tracks without enough audio contribute silence
+    pub fn mix(&mut self, len: usize) -> PcmBuf {
+        let mut mixed = vec![0i16; len];
+        for buffer in self.tracks.values_mut() {
+            let available = len.min(buffer.samples.len());
+            for (out, sample) in mixed.iter_mut().zip(buffer.samples.drain(..available)) {
+                *out = out.saturating_add(sample);
+            }
+        }
+        mixed
+    }
+}
@@ -1,6 +1,7 @@
 use audio_codec::CodecType;
 use rustrtc::MediaKind;
 use rustrtc::sdp::SessionDescription;
+use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct PeerMedia {
@@ -50,6 +51,29 @@ pub fn parse_rtpmap(rtpmap: &str) -> Result<(u8, CodecType, u32, u16), anyhow::E
     }
 }
 
+/// Format parameters configured for `codec_name` in a codec name to `a=fmtp`
+/// map such as `{"opus": "maxaveragebitrate=24000;useinbandfec=1"}`. Names
+/// match case-insensitively
+pub fn codec_fmtp<'a>(fmtp: &'a HashMap<String, String>, codec_name: &str) -> Option<&'a str> {
+    fmtp.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(codec_name))
+        .map(|(_, params)| params.as_str())
+}
+
+/// Split an `a=fmtp` parameter list (`key=value;key=value`) into its pairs,
+/// keys lowercased. A parameter without a value maps to an empty string
+pub fn parse_fmtp(params: &str) -> HashMap<String, String> {
+    params
+        .split(';')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_string()),
+            None => (param.to_lowercase(), String::new()),
+        })
+        .collect()
+}
+
 pub fn strip_ipv6_candidates(sdp: &str) -> String {
     sdp.lines()
         .filter(|line| !(line.starts_with("a=candidate:") && line.matches(':').count() >= 8))
@@ -58,6 +82,157 @@ pub fn strip_ipv6_candidates(sdp: &str) -> String {
         + "\n"
 }
 
+/// WebRTC-only attributes dropped from the SDP we send on SIP trunks, leaving a
+/// plain RFC 3264 offer/answer that legacy carriers accept
+pub const SIP_SDP_FILTER: &[&str] = &[
+    "extmap",
+    "extmap-allow-mixed",
+    "rtcp-fb",
+    "rtcp-rsize",
+    "msid",
+    "msid-semantic",
+    "ssrc",
+    "ssrc-group",
+    "mid",
+    "group",
+];
+
+pub fn sip_sdp_filter() -> Vec<String> {
+    SIP_SDP_FILTER.iter().map(|a| a.to_string()).collect()
+}
+
+/// Remove every `a=` line whose attribute name is listed in `attributes`
+pub fn filter_sdp_attributes(sdp: &str, attributes: &[String]) -> String {
+    if attributes.is_empty() {
+        return sdp.to_string();
+    }
+    sdp.lines()
+        .filter(|line| {
+            let Some(attr) = line.strip_prefix("a=") else {
+                return true;
+            };
+            let name = attr.split(':').next().unwrap_or_default();
+            !attributes.iter().any(|a| a.eq_ignore_ascii_case(name))
+        })
+        .collect::<Vec<&str>>()
+        .join("\r\n")
+        + "\r\n"
+}
+
+/// Rewrite the media direction of `sdp` (e.g. `sendonly` to put the peer on
+/// hold) and bump the `o=` version so it is a valid new offer (RFC 3264 §8)
+pub fn set_sdp_direction(sdp: &str, direction: &str) -> String {
+    let mut lines = Vec::new();
+    let mut has_direction = false;
+    for line in sdp.lines() {
+        match line {
+            "a=sendrecv" | "a=sendonly" | "a=recvonly" | "a=inactive" => {
+                has_direction = true;
+                lines.push(format!("a={}", direction));
+            }
+            _ if line.starts_with("o=") => {
+                let mut fields: Vec<String> = line.split(' ').map(|f| f.to_string()).collect();
+                if let Some(version) = fields.get_mut(2) {
+                    if let Ok(v) = version.parse::<u64>() {
+                        *version = (v + 1).to_string();
+                    }
+                }
+                lines.push(fields.join(" "));
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    if !has_direction {
+        lines.push(format!("a={}", direction));
+    }
+    lines.join("\r\n") + "\r\n"
+}
+
+/// The session version of the `o=` line, bumped with each new offer
+pub fn sdp_origin_version(sdp: &str) -> Option<u64> {
+    sdp.lines()
+        .find_map(|line| line.strip_prefix("o="))
+        .and_then(|origin| origin.split(' ').nth(2))
+        .and_then(|version| version.parse().ok())
+}
+
+/// Set the session version of the `o=` line to `version`
+pub fn set_sdp_origin_version(sdp: &str, version: u64) -> String {
+    sdp.lines()
+        .map(|line| {
+            if !line.starts_with("o=") {
+                return line.to_string();
+            }
+            let mut fields: Vec<String> = line.split(' ').map(|f| f.to_string()).collect();
+            if let Some(field) = fields.get_mut(2) {
+                *field = version.to_string();
+            }
+            fields.join(" ")
+        })
+        .collect::<Vec<String>>()
+        .join("\r\n")
+        + "\r\n"
+}
+
+/// Payload types listed on the audio media line, in preference order
+pub fn audio_payload_types(sdp: &str) -> Vec<u8> {
+    sdp.lines()
+        .find_map(|line| line.strip_prefix("m=audio "))
+        .map(|m| {
+            m.split_whitespace()
+                .skip(2)
+                .filter_map(|pt| pt.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// First payload type of the audio media line, the codec in use for an answer
+pub fn audio_payload_type(sdp: &str) -> Option<u8> {
+    audio_payload_types(sdp).first().copied()
+}
+
+/// Rewrite an audio offer to carry only `payload_type` (plus telephone-event),
+/// bumping the origin version like [`set_sdp_direction`]
+pub fn restrict_audio_codec(sdp: &str, payload_type: u8) -> String {
+    let dtmf: Vec<String> = sdp
+        .lines()
+        .filter_map(|line| line.strip_prefix("a=rtpmap:"))
+        .filter(|map| map.to_ascii_lowercase().contains("telephone-event"))
+        .filter_map(|map| map.split(' ').next())
+        .map(|pt| pt.to_string())
+        .collect();
+    let keep = |pt: &str| pt == payload_type.to_string() || dtmf.iter().any(|d| d == pt);
+    let mut lines = Vec::new();
+    for line in sdp.lines() {
+        if let Some(media) = line.strip_prefix("m=audio ") {
+            let fields: Vec<&str> = media.split_whitespace().collect();
+            let mut rewritten: Vec<&str> = fields.iter().take(2).copied().collect();
+            rewritten.extend(fields.iter().skip(2).copied().filter(|pt| keep(*pt)));
+            lines.push(format!("m=audio {}", rewritten.join(" ")));
+        } else if let Some(pt) = ["a=rtpmap:", "a=fmtp:", "a=rtcp-fb:"]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix))
+            .and_then(|attr| attr.split(' ').next())
+        {
+            if keep(pt) {
+                lines.push(line.to_string());
+            }
+        } else if line.starts_with("o=") {
+            let mut fields: Vec<String> = line.split(' ').map(|f| f.to_string()).collect();
+            if let Some(version) = fields.get_mut(2) {
+                if let Ok(v) = version.parse::<u64>() {
+                    *version = (v + 1).to_string();
+                }
+            }
+            lines.push(fields.join(" "));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    lines.join("\r\n") + "\r\n"
+}
+
 pub fn prefer_audio_codec(sdp: &SessionDescription) -> Option<CodecType> {
     let mut codecs = select_peer_media(sdp, "audio")?.codecs;
     codecs.sort_by(|a, b| a.cmp(b));
@@ -321,6 +496,91 @@ a=ptime:20"#;
         assert_eq!(codec, Some(CodecType::PCMU));
     }
 
+    #[test]
+    fn test_restrict_audio_codec() {
+        use crate::media::negotiate::{
+            audio_payload_type, audio_payload_types, restrict_audio_codec,
+        };
+        let sdp = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+m=audio 4000 RTP/AVP 9 8 0 101\r\na=rtpmap:9 G722/8000\r\na=rtpmap:8 PCMA/8000\r\n\
+a=rtpmap:0 PCMU/8000\r\na=rtpmap:101 telephone-event/8000\r\na=fmtp:101 0-16\r\na=sendrecv\r\n";
+        assert_eq!(audio_payload_type(sdp), Some(9));
+        assert_eq!(audio_payload_types(sdp), vec![9, 8, 0, 101]);
+
+        let offer = restrict_audio_codec(sdp, 0);
+        assert!(offer.contains("o=- 1 2 IN IP4 127.0.0.1\r\n"));
+        assert!(offer.contains("m=audio 4000 RTP/AVP 0 101\r\n"));
+        assert!(offer.contains("a=rtpmap:0 PCMU/8000\r\n"));
+        assert!(offer.contains("a=fmtp:101 0-16\r\n"));
+        assert!(!offer.contains("G722") && !offer.contains("PCMA"));
+        assert_eq!(audio_payload_type(&offer), Some(0));
+        SessionDescription::parse(rustrtc::sdp::SdpType::Offer, &offer)
+            .expect("restricted SDP should stay valid");
+    }
+
+    #[test]
+    fn test_filter_sdp_attributes_for_sip() {
+        use crate::media::negotiate::{filter_sdp_attributes, sip_sdp_filter};
+        let sdp = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+a=group:BUNDLE 0\r\na=msid-semantic: WMS\r\n\
+m=audio 4000 RTP/AVP 0 101\r\na=mid:0\r\na=rtpmap:0 PCMU/8000\r\n\
+a=rtpmap:101 telephone-event/8000\r\na=fmtp:101 0-16\r\na=rtcp-fb:0 nack\r\n\
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\na=msid:stream track\r\n\
+a=ssrc:1234 cname:abc\r\na=ptime:20\r\na=sendrecv\r\n";
+
+        let filtered = filter_sdp_attributes(sdp, &sip_sdp_filter());
+        let attributes: Vec<&str> = filtered
+            .lines()
+            .filter_map(|l| l.strip_prefix("a="))
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                "rtpmap:0 PCMU/8000",
+                "rtpmap:101 telephone-event/8000",
+                "fmtp:101 0-16",
+                "ptime:20",
+                "sendrecv",
+            ]
+        );
+        assert!(filtered.contains("m=audio 4000 RTP/AVP 0 101\r\n"));
+        SessionDescription::parse(rustrtc::sdp::SdpType::Answer, &filtered)
+            .expect("filtered SDP should stay valid");
+
+        // WebRTC keeps the full set
+        assert_eq!(filter_sdp_attributes(sdp, &[]), sdp);
+    }
+
+    #[test]
+    fn test_set_sdp_direction() {
+        use crate::media::negotiate::{detect_hold_state_from_sdp, set_sdp_direction};
+        let sdp = "v=0\r\no=- 7 3 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+m=audio 4000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=sendrecv\r\n";
+
+        let held = set_sdp_direction(sdp, "sendonly");
+        assert!(held.contains("o=- 7 4 IN IP4 127.0.0.1\r\n"));
+        assert!(held.contains("a=sendonly\r\n"));
+        assert!(!held.contains("a=sendrecv"));
+        assert!(detect_hold_state_from_sdp(&held));
+
+        let resumed = set_sdp_direction(&held, "sendrecv");
+        assert!(resumed.contains("o=- 7 5 IN IP4 127.0.0.1\r\n"));
+        assert!(!detect_hold_state_from_sdp(&resumed));
+
+        let no_direction = sdp.replace("a=sendrecv\r\n", "");
+        assert!(set_sdp_direction(&no_direction, "inactive").ends_with("a=inactive\r\n"));
+    }
+
+    #[test]
+    fn test_sdp_origin_version() {
+        use crate::media::negotiate::{sdp_origin_version, set_sdp_origin_version};
+        let sdp = "v=0\r\no=- 7 3 IN IP4 127.0.0.1\r\ns=-\r\n";
+        assert_eq!(sdp_origin_version(sdp), Some(3));
+        let bumped = set_sdp_origin_version(sdp, 9);
+        assert_eq!(bumped, "v=0\r\no=- 7 9 IN IP4 127.0.0.1\r\ns=-\r\n");
+        assert_eq!(sdp_origin_version("v=0\r\n"), None);
+    }
+
     #[test]
     fn test_answer_intersection() {
         use crate::media::negotiate::intersect_answer;
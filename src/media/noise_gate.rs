@@ -0,0 +1,199 @@
+use super::processor::Processor;
+use crate::media::{AudioFrame, Samples};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NoiseGateOption {
+    pub enabled: Option<bool>,
+    /// Frames at or above this RMS level (dBFS) open the gate, default -50
+    pub threshold_dbfs: Option<f32>,
+    /// How long the level must stay below the close threshold before the gate closes, default 200ms
+    pub hold_ms: Option<u32>,
+    /// The gate closes this many dB below `thresholdDbfs`, so it doesn't chatter around it, default 6
+    pub hysteresis_db: Option<f32>,
+    /// Gain applied while the gate is closed, in dB, default -30
+    pub floor_gain_db: Option<f32>,
+}
+
+impl NoiseGateOption {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+fn dbfs_to_amplitude(dbfs: f32) -> f32 {
+    32768.0 * 10f32.powf(dbfs / 20.0)
+}
+
+/// Attenuates inbound audio while it stays below a threshold, so faint room
+/// noise doesn't reach ASR. The gate opens on the first frame above the
+/// threshold and closes once the level stayed below the threshold minus the
+/// hysteresis for the hold time, fading down to the floor gain over one frame.
+pub struct NoiseGateProcessor {
+    open_rms: f32,
+    close_rms: f32,
+    hold_ms: u32,
+    floor_gain: f32,
+    below_ms: u32,
+    gain: f32,
+}
+
+impl NoiseGateProcessor {
+    pub fn new(option: &NoiseGateOption) -> Self {
+        let threshold_dbfs = option.threshold_dbfs.unwrap_or(-50.0);
+        let hysteresis_db = option.hysteresis_db.unwrap_or(6.0).max(0.0);
+        Self {
+            open_rms: dbfs_to_amplitude(threshold_dbfs),
+            close_rms: dbfs_to_amplitude(threshold_dbfs - hysteresis_db),
+            hold_ms: option.hold_ms.unwrap_or(200),
+            floor_gain: 10f32.powf(option.floor_gain_db.unwrap_or(-30.0).min(0.0) / 20.0),
+            below_ms: 0,
+            gain: 1.0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.gain >= 1.0
+    }
+
+    fn rms(samples: &[i16]) -> f32 {
+        if samples.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = samples.iter().map(|s| (*s as f64) * (*s as f64)).sum();
+        (sum / samples.len() as f64).sqrt() as f32
+    }
+}
+
+impl Processor for NoiseGateProcessor {
+    fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        let samples = match &mut frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        let rms = Self::rms(samples);
+
+        let target = if rms >= self.open_rms {
+            self.below_ms = 0;
+            1.0
+        } else if self.is_open() && rms >= self.close_rms {
+            self.below_ms = 0;
+            1.0
+        } else if self.is_open() {
+            let channels = frame.channels.max(1) as usize;
+            let frame_ms = if frame.sample_rate > 0 {
+                (samples.len() / channels * 1000 / frame.sample_rate as usize) as u32
+            } else {
+                0
+            };
+            self.below_ms = self.below_ms.saturating_add(frame_ms);
+            if self.below_ms >= self.hold_ms {
+                self.floor_gain
+            } else {
+                1.0
+            }
+        } else {
+            self.floor_gain
+        };
+
+        // Opening is immediate so speech onsets are kept, closing fades over
+        // the frame so the gate doesn't click
+        let start = if target > self.gain {
+            target
+        } else {
+            self.gain
+        };
+        self.gain = target;
+        if start >= 1.0 && target >= 1.0 {
+            return Ok(());
+        }
+        let step = (target - start) / samples.len() as f32;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let gain = start + step * i as f32;
+            *sample = (*sample as f32 * gain) as i16;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_frame(amplitude: f32, offset: usize) -> AudioFrame {
+        let samples = (0..320)
+            .map(|i| {
+                let t = (offset + i) as f32 / 16000.0;
+                (amplitude * (2.0 * std::f32::consts::PI * 440.0 * t).sin()) as i16
+            })
+            .collect();
+        AudioFrame {
+            samples: Samples::PCM { samples },
+            sample_rate: 16000,
+            ..Default::default()
+        }
+    }
+
+    fn pcm(frame: &AudioFrame) -> &[i16] {
+        match &frame.samples {
+            Samples::PCM { samples } => samples,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_loud_frame_passes_unchanged() {
+        let mut gate = NoiseGateProcessor::new(&NoiseGateOption::default());
+        for n in 0..20 {
+            let mut frame = sine_frame(10000.0, n * 320);
+            let original = frame.clone();
+            gate.process_frame(&mut frame).unwrap();
+            assert_eq!(pcm(&frame), pcm(&original));
+        }
+        assert!(gate.is_open());
+    }
+
+    #[test]
+    fn test_low_level_attenuated_after_hold() {
+        let option = NoiseGateOption {
+            hold_ms: Some(100),
+            ..Default::default()
+        };
+        let mut gate = NoiseGateProcessor::new(&option);
+        // ~-60 dBFS hiss, below the default -50 threshold
+        let amplitude = 50.0;
+
+        // 20ms frames, the hold runs out on the fifth one
+        for n in 0..4 {
+            let mut frame = sine_frame(amplitude, n * 320);
+            let original = frame.clone();
+            gate.process_frame(&mut frame).unwrap();
+            assert_eq!(pcm(&frame), pcm(&original), "frame {} within hold", n);
+        }
+        for n in 4..10 {
+            let mut frame = sine_frame(amplitude, n * 320);
+            gate.process_frame(&mut frame).unwrap();
+        }
+        assert!(!gate.is_open());
+
+        let mut frame = sine_frame(amplitude, 10 * 320);
+        let input_rms = NoiseGateProcessor::rms(pcm(&frame));
+        gate.process_frame(&mut frame).unwrap();
+        let output_rms = NoiseGateProcessor::rms(pcm(&frame));
+        assert!(output_rms > 0.0, "attenuated, not zeroed");
+        assert!(
+            output_rms < input_rms / 20.0,
+            "{} vs {}",
+            output_rms,
+            input_rms
+        );
+
+        // Speech reopens the gate right away
+        let mut frame = sine_frame(10000.0, 11 * 320);
+        let original = frame.clone();
+        gate.process_frame(&mut frame).unwrap();
+        assert_eq!(pcm(&frame), pcm(&original));
+    }
+}
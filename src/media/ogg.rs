@@ -0,0 +1,180 @@
+//! Minimal Ogg/Opus writer (RFC 7845) used by the recorder: an `OpusHead`
+//! and an `OpusTags` page followed by one page per 20ms Opus packet.
+use anyhow::{Result, anyhow};
+use audio_codec::{PcmBuf, Sample, opus::OpusEncoder};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+/// Granule positions are always counted at 48kHz
+const GRANULE_RATE: u64 = 48000;
+/// Encoder delay skipped by players, in 48kHz samples
+const PRE_SKIP: u16 = 312;
+const FRAME_MS: u32 = 20;
+/// Largest Opus packet
+const MAX_PACKET_SIZE: usize = 1275;
+const HEADER_TYPE_BOS: u8 = 0x02;
+const HEADER_TYPE_EOS: u8 = 0x04;
+
+/// Whether Opus encodes `sample_rate` directly
+pub fn is_supported_rate(sample_rate: u32) -> bool {
+    matches!(sample_rate, 8000 | 12000 | 16000 | 24000 | 48000)
+}
+
+/// Ogg page checksum: CRC-32 with polynomial 0x04c11db7, no reflection
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub struct OggOpusWriter {
+    file: File,
+    encoder: OpusEncoder,
+    sample_rate: u32,
+    channels: u16,
+    serial: u32,
+    sequence: u32,
+    /// Interleaved samples waiting for a full frame
+    pending: PcmBuf,
+    /// Samples per channel written by the caller
+    samples_in: u64,
+    /// Samples per channel encoded, padding included
+    samples_encoded: u64,
+    /// Last packet and its granule, held back to flag it end of stream
+    held: Option<(Vec<u8>, u64)>,
+    packet: Vec<u8>,
+}
+
+impl OggOpusWriter {
+    /// Start an Ogg/Opus stream in `file`, writing both header pages.
+    /// `sample_rate` must be one of the rates Opus encodes directly.
+    pub async fn create(file: File, sample_rate: u32, channels: u16) -> Result<Self> {
+        if !is_supported_rate(sample_rate) {
+            return Err(anyhow!("opus can't encode {}Hz audio", sample_rate));
+        }
+        if !matches!(channels, 1 | 2) {
+            return Err(anyhow!("opus recording needs 1 or 2 channels"));
+        }
+        let mut writer = Self {
+            file,
+            encoder: OpusEncoder::new(sample_rate, channels),
+            sample_rate,
+            channels,
+            serial: rand::random::<u32>(),
+            sequence: 0,
+            pending: Vec::new(),
+            samples_in: 0,
+            samples_encoded: 0,
+            held: None,
+            packet: vec![0u8; MAX_PACKET_SIZE],
+        };
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(channels as u8);
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        let page = writer.page(&head, 0, HEADER_TYPE_BOS);
+        writer.file.write_all(&page).await?;
+
+        let vendor = concat!("active-call ", env!("CARGO_PKG_VERSION"));
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // user comments
+        let page = writer.page(&tags, 0, 0);
+        writer.file.write_all(&page).await?;
+        Ok(writer)
+    }
+
+    fn frame_len(&self) -> usize {
+        (self.sample_rate / 1000 * FRAME_MS) as usize * self.channels as usize
+    }
+
+    /// `samples` counted at 48kHz
+    fn granule(&self, samples: u64) -> u64 {
+        samples * GRANULE_RATE / self.sample_rate as u64
+    }
+
+    /// Append interleaved samples at the writer's rate
+    pub async fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.samples_in += (samples.len() / self.channels as usize) as u64;
+        self.pending.extend_from_slice(samples);
+        self.encode_pending().await
+    }
+
+    async fn encode_pending(&mut self) -> Result<()> {
+        let frame_len = self.frame_len();
+        while self.pending.len() >= frame_len {
+            let frame: PcmBuf = self.pending.drain(..frame_len).collect();
+            let len = self
+                .encoder
+                .encode_into(&frame, &mut self.packet)
+                .ok_or_else(|| anyhow!("opus encoding failed"))?;
+            self.samples_encoded += (frame_len / self.channels as usize) as u64;
+            let packet = self.packet[..len].to_vec();
+            let granule = self.granule(self.samples_encoded);
+            if let Some((held, held_granule)) = self.held.replace((packet, granule)) {
+                let page = self.page(&held, held_granule, 0);
+                self.file.write_all(&page).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pad the last frame with silence, flushing the encoder delay, and end
+    /// the stream with a page trimmed to the samples actually written
+    pub async fn finish(mut self) -> Result<()> {
+        let pre_skip = PRE_SKIP as usize * self.sample_rate as usize / GRANULE_RATE as usize;
+        let mut padding = pre_skip * self.channels as usize;
+        let frame_len = self.frame_len();
+        let remainder = (self.pending.len() + padding) % frame_len;
+        if remainder != 0 {
+            padding += frame_len - remainder;
+        }
+        self.pending.resize(self.pending.len() + padding, 0);
+        self.encode_pending().await?;
+
+        if let Some((packet, _)) = self.held.take() {
+            let granule = self.granule(self.samples_in) + PRE_SKIP as u64;
+            let page = self.page(&packet, granule, HEADER_TYPE_EOS);
+            self.file.write_all(&page).await?;
+        }
+        self.file.sync_all().await?;
+        Ok(())
+    }
+
+    /// One page holding the whole `packet`
+    fn page(&mut self, packet: &[u8], granule: u64, header_type: u8) -> Vec<u8> {
+        let mut lacing = vec![255u8; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+
+        let mut page = Vec::with_capacity(27 + lacing.len() + packet.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0); // version
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0u8; 4]); // checksum, filled in below
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(packet);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.sequence += 1;
+        page
+    }
+}
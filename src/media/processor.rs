@@ -1,9 +1,11 @@
-use super::INTERNAL_SAMPLERATE;
 use super::track::track_codec::TrackCodec;
+use super::{INTERNAL_SAMPLERATE, internal_samplerate_for_codec};
 use crate::event::{EventSender, SessionEvent};
 use crate::media::{AudioFrame, Samples, SourcePacket};
 use anyhow::Result;
+use audio_codec::CodecType;
 use std::any::Any;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
 pub trait Processor: Send + Sync + Any {
@@ -53,7 +55,8 @@ impl Samples {
 pub struct ProcessorChain {
     processors: Arc<Mutex<Vec<Box<dyn Processor>>>>,
     pub codec: TrackCodec,
-    sample_rate: u32,
+    sample_rate: Arc<AtomicU32>,
+    follow_codec: Arc<AtomicBool>,
     pub force_decode: bool,
 }
 
@@ -62,10 +65,32 @@ impl ProcessorChain {
         Self {
             processors: Arc::new(Mutex::new(Vec::new())),
             codec: TrackCodec::new(),
-            sample_rate: INTERNAL_SAMPLERATE,
+            sample_rate: Arc::new(AtomicU32::new(INTERNAL_SAMPLERATE)),
+            follow_codec: Arc::new(AtomicBool::new(false)),
             force_decode: true,
         }
     }
+
+    /// Rate frames are decoded/resampled to before reaching the processors
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Shared with clones already handed to running track tasks
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Let the track update the processing rate once its codec is negotiated
+    pub fn set_follow_codec(&self, follow: bool) {
+        self.follow_codec.store(follow, Ordering::Relaxed);
+    }
+
+    pub fn update_for_codec(&self, codec: CodecType) {
+        if self.follow_codec.load(Ordering::Relaxed) {
+            self.set_sample_rate(internal_samplerate_for_codec(codec));
+        }
+    }
     pub fn insert_processor(&mut self, processor: Box<dyn Processor>) {
         self.processors.lock().unwrap().insert(0, processor);
     }
@@ -85,11 +110,33 @@ impl ProcessorChain {
         processors.retain(|processor| !(processor.as_ref() as &dyn Any).is::<T>());
     }
 
+    /// Remove the processors of type `T` for which `keep` returns false
+    pub fn retain_processor<T: 'static>(&self, keep: impl Fn(&T) -> bool) {
+        let mut processors = self.processors.lock().unwrap();
+        processors.retain(
+            |processor| match (processor.as_ref() as &dyn Any).downcast_ref::<T>() {
+                Some(processor) => keep(processor),
+                None => true,
+            },
+        );
+    }
+
+    /// Apply `update` to every processor of type `T`
+    pub fn update_processor<T: 'static>(&self, mut update: impl FnMut(&mut T)) {
+        let mut processors = self.processors.lock().unwrap();
+        for processor in processors.iter_mut() {
+            if let Some(processor) = (processor.as_mut() as &mut dyn Any).downcast_mut::<T>() {
+                update(processor);
+            }
+        }
+    }
+
     pub fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
         let mut processors = self.processors.lock().unwrap();
         if !self.force_decode && processors.is_empty() {
             return Ok(());
         }
+        let sample_rate = self.sample_rate();
         match &mut frame.samples {
             Samples::RTP {
                 payload_type,
@@ -98,7 +145,7 @@ impl ProcessorChain {
             } => {
                 if TrackCodec::is_audio(*payload_type) {
                     let (decoded_sample_rate, channels, samples) =
-                        self.codec.decode(*payload_type, &payload, self.sample_rate);
+                        self.codec.decode(*payload_type, &payload, sample_rate);
                     let src_packet = SourcePacket {
                         sequence_number: *sequence_number,
                         payload_type: *payload_type,
@@ -114,14 +161,12 @@ impl ProcessorChain {
         }
 
         if let Samples::PCM { samples } = &mut frame.samples {
-            if frame.sample_rate != self.sample_rate {
-                let new_samples = self.codec.resample(
-                    std::mem::take(samples),
-                    frame.sample_rate,
-                    self.sample_rate,
-                );
+            if frame.sample_rate != sample_rate {
+                let new_samples =
+                    self.codec
+                        .resample(std::mem::take(samples), frame.sample_rate, sample_rate);
                 *samples = new_samples;
-                frame.sample_rate = self.sample_rate;
+                frame.sample_rate = sample_rate;
             }
             if frame.channels == 2 {
                 convert_to_mono(samples, 2);
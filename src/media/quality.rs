@@ -0,0 +1,208 @@
+use rustrtc::{StatsKind, StatsReport};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Link quality derived from the RTCP receiver reports the peer sends about our stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityStats {
+    pub jitter_ms: f64,
+    pub loss_pct: f64,
+    pub rtt_ms: Option<f64>,
+}
+
+impl QualityStats {
+    /// Extract quality stats from a stats report. `clock_rate` converts RTCP
+    /// jitter (in RTP timestamp units) into milliseconds. Returns `None` until
+    /// the peer has sent at least one report block.
+    pub fn from_report(report: &StatsReport, clock_rate: u32) -> Option<Self> {
+        let clock_rate = clock_rate.max(1) as f64;
+        let mut stats: Option<QualityStats> = None;
+        for entry in report
+            .entries
+            .iter()
+            .filter(|e| e.kind == StatsKind::RemoteInboundRtp)
+        {
+            let jitter = entry
+                .values
+                .get("jitter")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0);
+            let fraction_lost = entry
+                .values
+                .get("fractionLost")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0);
+            let rtt = entry.values.get("roundTripTime").and_then(|v| v.as_f64());
+
+            let current = QualityStats {
+                jitter_ms: jitter * 1000.0 / clock_rate,
+                loss_pct: fraction_lost * 100.0 / 256.0,
+                rtt_ms: rtt.map(|secs| (secs * 1000.0).max(0.0)),
+            };
+            stats = Some(match stats {
+                Some(prev) => prev.worst(&current),
+                None => current,
+            });
+        }
+        stats
+    }
+
+    fn worst(&self, other: &QualityStats) -> QualityStats {
+        QualityStats {
+            jitter_ms: self.jitter_ms.max(other.jitter_ms),
+            loss_pct: self.loss_pct.max(other.loss_pct),
+            rtt_ms: match (self.rtt_ms, other.rtt_ms) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+/// Worst-case quality observed over a call, written to the call record at hangup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualitySummary {
+    pub samples: u32,
+    pub max_jitter_ms: f64,
+    pub max_loss_pct: f64,
+    pub max_rtt_ms: Option<f64>,
+}
+
+impl QualitySummary {
+    pub fn update(&mut self, stats: &QualityStats) {
+        self.samples += 1;
+        self.max_jitter_ms = self.max_jitter_ms.max(stats.jitter_ms);
+        self.max_loss_pct = self.max_loss_pct.max(stats.loss_pct);
+        self.max_rtt_ms = match (self.max_rtt_ms, stats.rtt_ms) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// Watches quality stats for degradation that lasts at least `sustain`, firing once
+pub struct QualityMonitor {
+    max_loss_pct: f64,
+    max_jitter_ms: f64,
+    sustain: Duration,
+    degraded_since: Option<Instant>,
+    fired: bool,
+}
+
+impl QualityMonitor {
+    pub fn new(max_loss_pct: f64, max_jitter_ms: f64, sustain: Duration) -> Self {
+        Self {
+            max_loss_pct,
+            max_jitter_ms,
+            sustain,
+            degraded_since: None,
+            fired: false,
+        }
+    }
+
+    /// Record stats observed at `now`, returning true the first time
+    /// degradation has lasted for the sustain period
+    pub fn observe(&mut self, stats: &QualityStats, now: Instant) -> bool {
+        if self.fired {
+            return false;
+        }
+        if stats.loss_pct <= self.max_loss_pct && stats.jitter_ms <= self.max_jitter_ms {
+            self.degraded_since = None;
+            return false;
+        }
+        let since = *self.degraded_since.get_or_insert(now);
+        if now.duration_since(since) >= self.sustain {
+            self.fired = true;
+        }
+        self.fired
+    }
+}
+
+/// Codec change made because of poor link quality, written to the call record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodecAdaptation {
+    pub timestamp: u64,
+    pub from: Option<String>,
+    pub to: String,
+    pub stats: QualityStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustrtc::{StatsEntry, StatsId};
+    use serde_json::json;
+
+    fn remote_inbound(ssrc: u32, jitter: u32, fraction_lost: u8, rtt: Option<f64>) -> StatsEntry {
+        let mut entry = StatsEntry::new(
+            StatsId::new(format!("remote-inbound-rtp-{}", ssrc)),
+            StatsKind::RemoteInboundRtp,
+        )
+        .with_value("ssrc", json!(ssrc))
+        .with_value("fractionLost", json!(fraction_lost))
+        .with_value("jitter", json!(jitter));
+        if let Some(rtt) = rtt {
+            entry = entry.with_value("roundTripTime", json!(rtt));
+        }
+        entry
+    }
+
+    #[test]
+    fn test_quality_stats_from_report() {
+        let report = StatsReport::new(vec![remote_inbound(1, 160, 64, Some(0.12))]);
+        let stats = QualityStats::from_report(&report, 8000).expect("stats");
+        assert_eq!(stats.jitter_ms, 20.0);
+        assert_eq!(stats.loss_pct, 25.0);
+        assert_eq!(stats.rtt_ms, Some(120.0));
+
+        let empty = StatsReport::new(vec![]);
+        assert!(QualityStats::from_report(&empty, 8000).is_none());
+    }
+
+    #[test]
+    fn test_quality_summary_keeps_worst_values() {
+        let mut summary = QualitySummary::default();
+        let reports = [
+            StatsReport::new(vec![remote_inbound(1, 80, 0, None)]),
+            StatsReport::new(vec![remote_inbound(1, 400, 26, Some(0.3))]),
+            StatsReport::new(vec![remote_inbound(1, 160, 13, Some(0.05))]),
+        ];
+        for report in reports.iter() {
+            let stats = QualityStats::from_report(report, 8000).unwrap();
+            summary.update(&stats);
+        }
+        assert_eq!(summary.samples, 3);
+        assert_eq!(summary.max_jitter_ms, 50.0);
+        assert!((summary.max_loss_pct - 10.15625).abs() < 1e-9);
+        assert_eq!(summary.max_rtt_ms, Some(300.0));
+    }
+
+    #[test]
+    fn test_quality_monitor_fires_after_sustained_degradation() {
+        let mut monitor = QualityMonitor::new(10.0, 60.0, Duration::from_secs(5));
+        let start = Instant::now();
+        let bad = QualityStats {
+            jitter_ms: 20.0,
+            loss_pct: 30.0,
+            rtt_ms: None,
+        };
+        let good = QualityStats {
+            jitter_ms: 20.0,
+            loss_pct: 1.0,
+            rtt_ms: None,
+        };
+
+        assert!(!monitor.observe(&bad, start));
+        assert!(!monitor.observe(&bad, start + Duration::from_secs(3)));
+        // Recovering resets the period
+        assert!(!monitor.observe(&good, start + Duration::from_secs(4)));
+        assert!(!monitor.observe(&bad, start + Duration::from_secs(6)));
+        assert!(!monitor.observe(&bad, start + Duration::from_secs(10)));
+        assert!(monitor.observe(&bad, start + Duration::from_secs(11)));
+        // Fires only once
+        assert!(!monitor.observe(&bad, start + Duration::from_secs(20)));
+    }
+}
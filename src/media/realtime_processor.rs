@@ -5,6 +5,7 @@ use crate::{
     media::{AudioFrame, INTERNAL_SAMPLERATE, Samples, TrackId},
 };
 use anyhow::{Result, anyhow};
+use audio_codec::Resampler;
 use base64::{Engine as _, engine::general_purpose};
 use futures::{SinkExt, StreamExt};
 use serde_json::{Value, json};
@@ -15,6 +16,7 @@ use tracing::{debug, error, info};
 
 pub struct RealtimeProcessor {
     audio_tx: mpsc::UnboundedSender<Vec<i16>>,
+    resampler: Option<(u32, Resampler)>,
 }
 
 impl RealtimeProcessor {
@@ -42,14 +44,32 @@ impl RealtimeProcessor {
             }
         });
 
-        Ok(Self { audio_tx })
+        Ok(Self {
+            audio_tx,
+            resampler: None,
+        })
     }
 }
 
 impl Processor for RealtimeProcessor {
     fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
         if let Samples::PCM { samples } = &frame.samples {
-            if !samples.is_empty() {
+            if samples.is_empty() {
+                return Ok(());
+            }
+            // The realtime session is opened at INTERNAL_SAMPLERATE
+            if frame.sample_rate > 0 && frame.sample_rate != INTERNAL_SAMPLERATE {
+                if !matches!(&self.resampler, Some((rate, _)) if *rate == frame.sample_rate) {
+                    self.resampler = None;
+                }
+                let (_, resampler) = self.resampler.get_or_insert_with(|| {
+                    (
+                        frame.sample_rate,
+                        Resampler::new(frame.sample_rate as usize, INTERNAL_SAMPLERATE as usize),
+                    )
+                });
+                self.audio_tx.send(resampler.resample(samples)).ok();
+            } else {
                 self.audio_tx.send(samples.clone()).ok();
             }
         }
@@ -209,6 +229,8 @@ async fn run_realtime_loop(
                                     end_time: None,
                                     is_filler: None,
                                     confidence: None,
+                                    stable_text: None,
+                                    unstable_text: None,
                                 }).ok();
                             }
                         }
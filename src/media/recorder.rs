@@ -1,10 +1,13 @@
 use anyhow::{Result, anyhow};
-use audio_codec::{PcmBuf, samples_to_bytes};
+use audio_codec::{
+    CodecType, Decoder, Encoder, PcmBuf, Resampler, create_decoder, create_encoder,
+    samples_to_bytes,
+};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
         Mutex,
         atomic::{AtomicUsize, Ordering},
@@ -32,19 +35,32 @@ pub enum RecorderFormat {
     Pcmu,
     Pcma,
     G722,
+    /// Opus in an Ogg container, needs the `opus` feature
+    Ogg,
 }
 
 impl RecorderFormat {
     pub fn extension(&self) -> &'static str {
-        "wav"
+        match self {
+            RecorderFormat::Ogg => "ogg",
+            _ => "wav",
+        }
     }
 
     pub fn is_supported(&self) -> bool {
-        true
+        match self {
+            RecorderFormat::Ogg => cfg!(feature = "opus"),
+            _ => true,
+        }
     }
 
+    /// The format actually recorded, WAV when this one isn't built in
     pub fn effective(&self) -> RecorderFormat {
-        *self
+        if self.is_supported() {
+            *self
+        } else {
+            RecorderFormat::Wav
+        }
     }
 }
 
@@ -54,6 +70,183 @@ impl Default for RecorderFormat {
     }
 }
 
+/// What the recorder does when the call switches codec mid-recording (e.g. a
+/// re-INVITE from G722 to PCMU) and the new audio no longer matches the WAV header
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FormatChangePolicy {
+    /// Transcode the new audio to the format of the file header
+    #[default]
+    Resample,
+    /// Close the current file and continue in a new segment, see [`segment_path`]
+    Split,
+}
+
+/// Path of the `index`-th extra segment of a split recording: `call.wav` -> `call.1.wav`
+pub fn segment_path(file_path: &Path, index: usize) -> PathBuf {
+    let stem = file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match file_path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, index),
+    };
+    file_path.with_file_name(name)
+}
+
+/// Extra segments written next to `file_path` after format changes
+pub fn existing_segments(file_path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|index| segment_path(file_path, index))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+/// A file a finished recorder left behind
+#[derive(Debug, Clone)]
+pub struct RecordedFile {
+    pub path: String,
+    /// Index of the segment, see [`segment_path`]. `None` for the first file
+    pub segment: Option<usize>,
+    /// Encrypted at rest, `path` is the encrypted copy
+    pub encrypted: bool,
+}
+
+impl RecordedFile {
+    /// The recording at `file_path` and its extra segments, as written
+    pub fn list(file_path: &Path) -> Vec<RecordedFile> {
+        let first = file_path.exists().then(|| RecordedFile {
+            path: file_path.to_string_lossy().to_string(),
+            segment: None,
+            encrypted: false,
+        });
+        let segments = existing_segments(file_path)
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| RecordedFile {
+                path: path.to_string_lossy().to_string(),
+                segment: Some(i + 1),
+                encrypted: false,
+            });
+        first.into_iter().chain(segments).collect()
+    }
+}
+
+/// Payload types with a dedicated WAV header in [`Recorder`]
+fn has_wav_header(payload_type: u8) -> bool {
+    matches!(payload_type, 0 | 8 | 9 | 10 | 11)
+}
+
+/// Re-encodes RTP payloads of the codec the call switched to into the codec
+/// of the file header
+struct Transcoder {
+    decoder: Box<dyn Decoder>,
+    resampler: Option<Resampler>,
+    encoder: Box<dyn Encoder>,
+}
+
+impl Transcoder {
+    fn new(from: u8, to: u8) -> Result<Self> {
+        let from = CodecType::try_from(from)?;
+        let to = CodecType::try_from(to)?;
+        if !from.is_audio() || !to.is_audio() {
+            return Err(anyhow!("cannot transcode {:?} to {:?}", from, to));
+        }
+        let decoder = create_decoder(from);
+        let encoder = create_encoder(to);
+        let resampler = (decoder.sample_rate() != encoder.sample_rate()).then(|| {
+            Resampler::new(
+                decoder.sample_rate() as usize,
+                encoder.sample_rate() as usize,
+            )
+        });
+        Ok(Self {
+            decoder,
+            resampler,
+            encoder,
+        })
+    }
+
+    fn transcode(&mut self, payload: &[u8]) -> Vec<u8> {
+        let samples = self.decoder.decode(payload);
+        let samples = match self.resampler.as_mut() {
+            Some(resampler) => resampler.resample(&samples),
+            None => samples,
+        };
+        self.encoder.encode(&samples)
+    }
+}
+
+/// Decodes the RTP payloads of one payload type to mono PCM at the rate of an
+/// Ogg/Opus recording
+#[cfg(feature = "opus")]
+enum RtpDecoder {
+    Codec {
+        decoder: Box<dyn Decoder>,
+        resampler: Option<Resampler>,
+    },
+    /// Big-endian L16 at 44.1kHz, `stereo` for payload type 10
+    L16 { stereo: bool, resampler: Resampler },
+    /// Not an audio payload, dropped
+    Skip,
+}
+
+#[cfg(feature = "opus")]
+impl RtpDecoder {
+    fn new(payload_type: u8, sample_rate: u32) -> Result<Self> {
+        if matches!(payload_type, 10 | 11) {
+            return Ok(RtpDecoder::L16 {
+                stereo: payload_type == 10,
+                resampler: Resampler::new(44100, sample_rate as usize),
+            });
+        }
+        let codec = CodecType::try_from(payload_type)?;
+        if !codec.is_audio() {
+            return Err(anyhow!("cannot record {:?} to ogg", codec));
+        }
+        let decoder = create_decoder(codec);
+        let resampler = (decoder.sample_rate() != sample_rate)
+            .then(|| Resampler::new(decoder.sample_rate() as usize, sample_rate as usize));
+        Ok(RtpDecoder::Codec { decoder, resampler })
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> PcmBuf {
+        match self {
+            RtpDecoder::Codec { decoder, resampler } => {
+                let samples = decoder.decode(payload);
+                let samples = if decoder.channels() == 2 {
+                    downmix(&samples)
+                } else {
+                    samples
+                };
+                match resampler.as_mut() {
+                    Some(resampler) => resampler.resample(&samples),
+                    None => samples,
+                }
+            }
+            RtpDecoder::L16 { stereo, resampler } => {
+                let samples: PcmBuf = payload
+                    .chunks_exact(2)
+                    .map(|b| i16::from_be_bytes([b[0], b[1]]))
+                    .collect();
+                let samples = if *stereo { downmix(&samples) } else { samples };
+                resampler.resample(&samples)
+            }
+            RtpDecoder::Skip => Vec::new(),
+        }
+    }
+}
+
+/// Average interleaved stereo down to mono
+#[cfg(feature = "opus")]
+fn downmix(samples: &[i16]) -> PcmBuf {
+    samples
+        .chunks_exact(2)
+        .map(|lr| ((lr[0] as i32 + lr[1] as i32) / 2) as i16)
+        .collect()
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
@@ -66,6 +259,21 @@ pub struct RecorderOption {
     pub ptime: u32,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub format: Option<RecorderFormat>,
+    /// Handling of a codec change in raw RTP recordings, default `resample`.
+    /// Mixed PCM recordings are always resampled to `samplerate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_format_change: Option<FormatChangePolicy>,
+    /// Encrypt the finished recording with this key, taken from the server config only
+    #[serde(skip)]
+    pub encryption_key: Option<String>,
+    /// Keep the caller on the left channel and the outbound audio (TTS, played
+    /// files) on the right one. Otherwise tracks take the channels in the
+    /// order they start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stereo: Option<bool>,
+    /// Track recorded on the right channel with `stereo`, the call's server side track
+    #[serde(skip)]
+    pub outbound_track_id: Option<String>,
 }
 
 impl RecorderOption {
@@ -106,6 +314,10 @@ impl Default for RecorderOption {
             samplerate: 16000,
             ptime: 200,
             format: None,
+            on_format_change: None,
+            encryption_key: None,
+            stereo: None,
+            outbound_track_id: None,
         }
     }
 }
@@ -119,6 +331,8 @@ pub struct Recorder {
     channels: Mutex<HashMap<String, usize>>,
     stereo_buf: Mutex<PcmBuf>,
     mono_buf: Mutex<PcmBuf>,
+    /// Per track resampler for PCM frames not at `option.samplerate`
+    resamplers: Mutex<HashMap<String, (u32, Resampler)>>,
 }
 
 impl Recorder {
@@ -136,6 +350,7 @@ impl Recorder {
             channels: Mutex::new(HashMap::new()),
             stereo_buf: Mutex::new(Vec::new()),
             mono_buf: Mutex::new(Vec::new()),
+            resamplers: Mutex::new(HashMap::new()),
         }
     }
 
@@ -213,18 +428,145 @@ impl Recorder {
             None => return Ok(()),
         };
 
+        #[cfg(feature = "opus")]
+        if self.option.resolved_format(RecorderFormat::Wav) == RecorderFormat::Ogg {
+            return self
+                .process_recording_ogg(file_path, receiver, first_frame)
+                .await;
+        }
+
         if let Samples::RTP { .. } = first_frame.samples {
             return self
                 .process_recording_rtp(file_path, receiver, first_frame)
                 .await;
         }
 
-        let _requested_format = self.option.format.unwrap_or(RecorderFormat::Wav);
-
         self.process_recording_wav(file_path, receiver, first_frame)
             .await
     }
 
+    /// Rate of Ogg/Opus recordings: `samplerate` when Opus encodes it
+    /// directly, else 48kHz
+    #[cfg(feature = "opus")]
+    fn ogg_sample_rate(&self) -> u32 {
+        if crate::media::ogg::is_supported_rate(self.option.samplerate) {
+            self.option.samplerate
+        } else {
+            48000
+        }
+    }
+
+    /// Encode the recording to Ogg/Opus. RTP payloads are decoded to PCM and
+    /// recorded in mono, mixed PCM frames keep one channel per track.
+    #[cfg(feature = "opus")]
+    async fn process_recording_ogg(
+        &self,
+        file_path: &Path,
+        mut receiver: UnboundedReceiver<AudioFrame>,
+        first_frame: AudioFrame,
+    ) -> Result<()> {
+        use crate::media::ogg::OggOpusWriter;
+
+        let sample_rate = self.ogg_sample_rate();
+        let file = self.create_output_file(file_path).await?;
+
+        if let Samples::RTP { .. } = first_frame.samples {
+            let mut writer = OggOpusWriter::create(file, sample_rate, 1).await?;
+            let mut decoders: HashMap<u8, RtpDecoder> = HashMap::new();
+            let mut next_frame = Some(first_frame);
+            loop {
+                let frame = match next_frame.take() {
+                    Some(frame) => frame,
+                    None => match receiver.recv().await {
+                        Some(frame) => frame,
+                        None => break,
+                    },
+                };
+                let Samples::RTP {
+                    payload_type,
+                    payload,
+                    ..
+                } = frame.samples
+                else {
+                    continue;
+                };
+                if !decoders.contains_key(&payload_type) {
+                    match RtpDecoder::new(payload_type, sample_rate) {
+                        Ok(decoder) => {
+                            decoders.insert(payload_type, decoder);
+                        }
+                        Err(e) => {
+                            warn!(session_id = self.session_id, "recorder: {}", e);
+                            decoders.insert(payload_type, RtpDecoder::Skip);
+                        }
+                    }
+                }
+                let samples = decoders
+                    .get_mut(&payload_type)
+                    .map(|decoder| decoder.decode(&payload))
+                    .unwrap_or_default();
+                writer.write(&samples).await?;
+            }
+            return writer.finish().await;
+        }
+
+        let mut writer = OggOpusWriter::create(file, sample_rate, 2).await?;
+        self.append_frame(first_frame).await.ok();
+
+        // `append_frame` resamples to `samplerate`, which Opus may not take
+        let mut resamplers = (sample_rate != self.option.samplerate).then(|| {
+            (
+                Resampler::new(self.option.samplerate as usize, sample_rate as usize),
+                Resampler::new(self.option.samplerate as usize, sample_rate as usize),
+            )
+        });
+        let chunk_size = (self.option.samplerate / 1000 * self.option.ptime) as usize;
+        info!(
+            session_id = self.session_id,
+            format = "ogg",
+            "Recording to {} ptime: {}ms chunk_size: {}",
+            file_path.display(),
+            self.option.ptime,
+            chunk_size
+        );
+
+        let mut interval = IntervalStream::new(tokio::time::interval(Duration::from_millis(
+            self.option.ptime as u64,
+        )));
+        loop {
+            let (mono_buf, stereo_buf) = select! {
+                Some(frame) = receiver.recv() => {
+                    self.append_frame(frame).await.ok();
+                    continue;
+                }
+                _ = interval.next() => self.pop(chunk_size).await,
+                _ = self.cancel_token.cancelled() => break,
+            };
+            Self::write_ogg_chunk(&mut writer, resamplers.as_mut(), mono_buf, stereo_buf).await?;
+        }
+
+        let (mono_buf, stereo_buf) = self.pop(usize::MAX).await;
+        Self::write_ogg_chunk(&mut writer, resamplers.as_mut(), mono_buf, stereo_buf).await?;
+        writer.finish().await
+    }
+
+    #[cfg(feature = "opus")]
+    async fn write_ogg_chunk(
+        writer: &mut crate::media::ogg::OggOpusWriter,
+        resamplers: Option<&mut (Resampler, Resampler)>,
+        mono_buf: PcmBuf,
+        stereo_buf: PcmBuf,
+    ) -> Result<()> {
+        let (mono_buf, mut stereo_buf) = match resamplers {
+            Some((mono, stereo)) => (mono.resample(&mono_buf), stereo.resample(&stereo_buf)),
+            None => (mono_buf, stereo_buf),
+        };
+        stereo_buf.resize(mono_buf.len(), 0);
+        writer
+            .write(&Self::mix_buffers(&mono_buf, &stereo_buf))
+            .await
+    }
+
     fn ensure_parent_dir(&self, file_path: &Path) -> Result<()> {
         if let Some(parent) = file_path.parent() {
             if !parent.exists() {
@@ -269,38 +611,84 @@ impl Recorder {
         mut receiver: UnboundedReceiver<AudioFrame>,
         first_frame: AudioFrame,
     ) -> Result<()> {
-        let (payload_type, mut file) =
-            if let Samples::RTP { payload_type, .. } = &first_frame.samples {
-                let file = self.create_output_file(file_path).await?;
-                (*payload_type, file)
-            } else {
-                return Err(anyhow!("Invalid frame type for RTP recording"));
+        let mut header_pt = match &first_frame.samples {
+            Samples::RTP { payload_type, .. } => *payload_type,
+            _ => return Err(anyhow!("Invalid frame type for RTP recording")),
+        };
+        let mut file = self.create_output_file(file_path).await?;
+        self.update_wav_header(&mut file, Some(header_pt)).await?;
+
+        let policy = self.option.on_format_change.unwrap_or_default();
+        let mut source_pt = header_pt;
+        let mut transcoder: Option<Transcoder> = None;
+        let mut segment = 0;
+        let mut next_frame = Some(first_frame);
+        loop {
+            let frame = match next_frame.take() {
+                Some(frame) => frame,
+                None => match receiver.recv().await {
+                    Some(frame) => frame,
+                    None => break,
+                },
+            };
+            let Samples::RTP {
+                payload_type,
+                payload,
+                ..
+            } = frame.samples
+            else {
+                continue;
             };
 
-        self.update_wav_header(&mut file, Some(payload_type))
-            .await?;
+            if payload_type != source_pt && has_wav_header(payload_type) {
+                let mut split = policy == FormatChangePolicy::Split;
+                if !split {
+                    transcoder = None;
+                    if payload_type != header_pt {
+                        match Transcoder::new(payload_type, header_pt) {
+                            Ok(t) => transcoder = Some(t),
+                            Err(e) => {
+                                warn!(
+                                    session_id = self.session_id,
+                                    "recorder: {}, starting a new segment", e
+                                );
+                                split = true;
+                            }
+                        }
+                    }
+                }
+                if split {
+                    self.update_wav_header(&mut file, Some(header_pt)).await?;
+                    file.sync_all().await?;
+                    segment += 1;
+                    let path = segment_path(file_path, segment);
+                    file = self.create_output_file(&path).await?;
+                    self.samples_written.store(0, Ordering::SeqCst);
+                    header_pt = payload_type;
+                    transcoder = None;
+                    self.update_wav_header(&mut file, Some(header_pt)).await?;
+                }
+                info!(
+                    session_id = self.session_id,
+                    from = source_pt,
+                    to = payload_type,
+                    ?policy,
+                    segment,
+                    "recorder: codec changed"
+                );
+                source_pt = payload_type;
+            }
 
-        if let Samples::RTP { payload, .. } = first_frame.samples {
+            let payload = match transcoder.as_mut() {
+                Some(transcoder) if payload_type == source_pt => transcoder.transcode(&payload),
+                _ => payload,
+            };
             file.write_all(&payload).await?;
             self.samples_written
                 .fetch_add(payload.len(), Ordering::SeqCst);
         }
 
-        loop {
-            match receiver.recv().await {
-                Some(frame) => {
-                    if let Samples::RTP { payload, .. } = frame.samples {
-                        file.write_all(&payload).await?;
-                        self.samples_written
-                            .fetch_add(payload.len(), Ordering::SeqCst);
-                    }
-                }
-                None => break,
-            }
-        }
-
-        self.update_wav_header(&mut file, Some(payload_type))
-            .await?;
+        self.update_wav_header(&mut file, Some(header_pt)).await?;
 
         file.sync_all().await?;
 
@@ -351,6 +739,13 @@ impl Recorder {
     }
 
     fn get_channel_index(&self, track_id: &str) -> usize {
+        if self.option.stereo.unwrap_or(false) {
+            return if self.option.outbound_track_id.as_deref() == Some(track_id) {
+                1
+            } else {
+                0
+            };
+        }
         let mut channels = self.channels.lock().unwrap();
         if let Some(&channel_idx) = channels.get(track_id) {
             channel_idx % 2
@@ -368,7 +763,7 @@ impl Recorder {
     }
 
     async fn append_frame(&self, frame: AudioFrame) -> Result<()> {
-        let buffer = match frame.samples {
+        let mut buffer = match frame.samples {
             Samples::PCM { samples } => samples,
             _ => return Ok(()), // ignore non-PCM frames
         };
@@ -377,6 +772,23 @@ impl Recorder {
             return Ok(());
         }
 
+        if frame.sample_rate > 0 && frame.sample_rate != self.option.samplerate {
+            let mut resamplers = self.resamplers.lock().unwrap();
+            let entry = resamplers.entry(frame.track_id.clone()).or_insert_with(|| {
+                (
+                    frame.sample_rate,
+                    Resampler::new(frame.sample_rate as usize, self.option.samplerate as usize),
+                )
+            });
+            if entry.0 != frame.sample_rate {
+                *entry = (
+                    frame.sample_rate,
+                    Resampler::new(frame.sample_rate as usize, self.option.samplerate as usize),
+                );
+            }
+            buffer = entry.1.resample(&buffer);
+        }
+
         let channel_idx = self.get_channel_index(&frame.track_id);
         match channel_idx {
             0 => {
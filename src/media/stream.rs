@@ -1,20 +1,23 @@
 use crate::event::{EventSender, SessionEvent};
-use crate::media::dtmf::DtmfDetector;
+use crate::media::dtmf::{DtmfDetector, generate_dtmf_tone};
 use crate::media::volume_control::HoldProcessor;
 use crate::media::{AudioFrame, Samples, TrackId};
 use crate::media::{
+    barge::{BARGE_SAMPLERATE, BARGE_TRACK_ID, BargeMixer},
+    monitor::{MonitorProcessor, MonitorSender},
     processor::Processor,
-    recorder::{Recorder, RecorderOption},
+    recorder::{RecordedFile, Recorder, RecorderOption},
     track::{Track, TrackPacketReceiver, TrackPacketSender},
 };
 use anyhow::Result;
+use audio_codec::PcmBuf;
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use tokio::task::JoinHandle;
 use tokio::{
     select,
-    sync::{Mutex, mpsc},
+    sync::{Mutex, broadcast, mpsc, oneshot},
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
@@ -31,11 +34,20 @@ pub struct MediaStream {
     packet_receiver: Mutex<Option<TrackPacketReceiver>>,
     recorder_sender: mpsc::UnboundedSender<AudioFrame>,
     recorder_receiver: Mutex<Option<mpsc::UnboundedReceiver<AudioFrame>>>,
-    recorder_handle: Mutex<Option<JoinHandle<()>>>,
+    /// Files of the running recorder, sent once it finished
+    recorder_files: Mutex<Option<oneshot::Receiver<Vec<RecordedFile>>>>,
+    monitor_sender: MonitorSender,
+    /// Whether the tracks copy their frames to `monitor_sender`, only once
+    /// something subscribed, so calls nobody watches don't pay for it.
+    /// Guarded by the `tracks` lock
+    monitor_attached: AtomicBool,
+    barge: Mutex<Option<BargeMixer>>,
 }
 
 const CALLEE_TRACK_ID: &str = "callee-track";
 const QUEUE_HOLD_TRACK_ID: &str = "queue-hold-track";
+/// Source of the frames of inband DTMF tones
+const DTMF_TRACK_ID: &str = "dtmf-track";
 
 pub struct MediaStreamBuilder {
     cancel_token: Option<CancellationToken>,
@@ -86,7 +98,10 @@ impl MediaStreamBuilder {
             packet_receiver: Mutex::new(Some(track_packet_receiver)),
             recorder_sender,
             recorder_receiver: Mutex::new(Some(recorder_receiver)),
-            recorder_handle: Mutex::new(None),
+            recorder_files: Mutex::new(None),
+            monitor_sender: broadcast::channel(64).0,
+            monitor_attached: AtomicBool::new(false),
+            barge: Mutex::new(None),
         }
     }
 }
@@ -118,16 +133,85 @@ impl MediaStream {
         self.cancel_token.cancel()
     }
 
-    pub async fn cleanup(&self) -> Result<()> {
+    /// Stop the stream and wait for the recorder to finish. Returns the files
+    /// it wrote, after encryption, or `None` without a recorder that finished.
+    pub async fn cleanup(&self) -> Result<Option<Vec<RecordedFile>>> {
         self.cancel_token.cancel();
-        if let Some(recorder_handle) = self.recorder_handle.lock().await.take() {
-            if let Ok(Ok(_)) = tokio::time::timeout(Duration::from_secs(30), recorder_handle).await
-            {
+        let Some(recorder_files) = self.recorder_files.lock().await.take() else {
+            return Ok(None);
+        };
+        match tokio::time::timeout(Duration::from_secs(30), recorder_files).await {
+            Ok(Ok(files)) => {
                 info!(session_id = self.id, "recorder stopped");
-            } else {
+                Ok(Some(files))
+            }
+            _ => {
                 warn!(session_id = self.id, "recorder timeout");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Receive the decoded frames of every track, for live monitoring and
+    /// media answer supervision. The tracks start copying their frames on
+    /// the first subscription
+    pub async fn subscribe_monitor(&self) -> broadcast::Receiver<AudioFrame> {
+        let mut tracks = self.tracks.lock().await;
+        if !self.monitor_attached.swap(true, Ordering::Relaxed) {
+            for (track, _) in tracks.values_mut() {
+                track
+                    .insert_processor(Box::new(MonitorProcessor::new(self.monitor_sender.clone())));
             }
         }
+        self.monitor_sender.subscribe()
+    }
+
+    /// Let a supervisor join the call, their audio is mixed into what every
+    /// track is sent until [`MediaStream::leave_barge`]. One supervisor at a time
+    pub async fn join_barge(&self) -> Result<()> {
+        let mut barge = self.barge.lock().await;
+        if barge.is_some() {
+            anyhow::bail!("a supervisor already joined {}", self.id);
+        }
+        *barge = Some(BargeMixer::new());
+        Ok(())
+    }
+
+    pub async fn leave_barge(&self) {
+        self.barge.lock().await.take();
+    }
+
+    /// Send the joined supervisor's audio, mono PCM at [`BARGE_SAMPLERATE`],
+    /// to every track of the call
+    pub async fn send_barge_audio(&self, samples: PcmBuf) -> Result<()> {
+        let mut barge = self.barge.lock().await;
+        let Some(mixer) = barge.as_mut() else {
+            anyhow::bail!("no supervisor joined {}", self.id);
+        };
+        let mut tracks = self.tracks.lock().await;
+        let idle = mixer.push(tracks.keys(), &samples);
+        let frame = AudioFrame {
+            track_id: BARGE_TRACK_ID.to_string(),
+            samples: Samples::PCM { samples },
+            timestamp: crate::media::get_timestamp(),
+            sample_rate: BARGE_SAMPLERATE,
+            channels: 1,
+            src_packet: None,
+        };
+        for track_id in idle {
+            let Some((track, _)) = tracks.get_mut(&track_id) else {
+                continue;
+            };
+            if let Err(e) = track.send_packet(&frame).await {
+                warn!(
+                    id = track_id,
+                    "media_stream: Failed to send barge audio: {}", e
+                );
+            }
+        }
+        if self.monitor_sender.receiver_count() > 0 {
+            self.monitor_sender.send(frame).ok();
+        }
         Ok(())
     }
 
@@ -136,6 +220,22 @@ impl MediaStream {
         self.start_recorder().await.ok();
     }
 
+    /// Start recording mid-call, tapping the tracks that are already running
+    pub async fn attach_recorder(&self, recorder_config: RecorderOption) {
+        {
+            let mut recorder_option = self.recorder_option.lock().await;
+            if recorder_option.is_none() {
+                for (track, _) in self.tracks.lock().await.values_mut() {
+                    track.insert_processor(Box::new(RecorderProcessor::new(
+                        self.recorder_sender.clone(),
+                    )));
+                }
+            }
+            *recorder_option = Some(recorder_config);
+        }
+        self.start_recorder().await.ok();
+    }
+
     pub async fn remove_track(&self, id: &TrackId, graceful: bool) {
         let track_entry = { self.tracks.lock().await.remove(id) };
         if let Some((track, _)) = track_entry {
@@ -220,10 +320,14 @@ impl MediaStream {
             Ok(_) => {
                 info!(session_id = self.id, track_id = track.id(), "track started");
                 let track_id = track.id().clone();
-                self.tracks
-                    .lock()
-                    .await
-                    .insert(track_id.clone(), (track, DtmfDetector::new()));
+                let mut tracks = self.tracks.lock().await;
+                if self.monitor_attached.load(Ordering::Relaxed) {
+                    track.insert_processor(Box::new(MonitorProcessor::new(
+                        self.monitor_sender.clone(),
+                    )));
+                }
+                tracks.insert(track_id.clone(), (track, DtmfDetector::new()));
+                drop(tracks);
                 self.event_sender
                     .send(SessionEvent::TrackStart {
                         track_id,
@@ -292,6 +396,67 @@ impl MediaStream {
         }
     }
 
+    pub async fn send_data(&self, track_id: &TrackId, label: &str, data: &str) -> Result<()> {
+        match self.tracks.lock().await.get(track_id) {
+            Some((track, _)) => track.send_data(label, data).await,
+            None => Err(anyhow::anyhow!("Track {} not found", track_id)),
+        }
+    }
+
+    /// Play `digits` as inband tones to `track_id`, each `duration_ms` long
+    /// and `gap_ms` apart. The tones are generated at the rate of the track's
+    /// negotiated codec, so the encoder sends them without resampling
+    pub async fn send_dtmf_inband(
+        &self,
+        track_id: &TrackId,
+        digits: &str,
+        duration_ms: u32,
+        gap_ms: u32,
+    ) -> Result<()> {
+        let (sample_rate, ptime) = match self.tracks.lock().await.get(track_id) {
+            Some((track, _)) => (
+                track
+                    .negotiated_codec()
+                    .map(|codec| codec.samplerate())
+                    .unwrap_or(track.config().samplerate),
+                track.config().ptime,
+            ),
+            None => anyhow::bail!("Track {} not found", track_id),
+        };
+        let mut samples = PcmBuf::new();
+        for digit in digits.chars() {
+            let Some(tone) = generate_dtmf_tone(digit, sample_rate, duration_ms) else {
+                anyhow::bail!("'{}' is not a DTMF key", digit);
+            };
+            samples.extend(tone);
+            samples.extend(std::iter::repeat_n(
+                0,
+                sample_rate as usize * gap_ms as usize / 1000,
+            ));
+        }
+
+        let frame_len = (sample_rate as usize * ptime.as_millis() as usize / 1000).max(1);
+        let mut ticker = tokio::time::interval(ptime);
+        for chunk in samples.chunks(frame_len) {
+            ticker.tick().await;
+            let frame = AudioFrame {
+                track_id: DTMF_TRACK_ID.to_string(),
+                samples: Samples::PCM {
+                    samples: chunk.to_vec(),
+                },
+                timestamp: crate::media::get_timestamp(),
+                sample_rate,
+                channels: 1,
+                src_packet: None,
+            };
+            match self.tracks.lock().await.get_mut(track_id) {
+                Some((track, _)) => track.send_packet(&frame).await?,
+                None => anyhow::bail!("Track {} stopped while sending dtmf", track_id),
+            }
+        }
+        Ok(())
+    }
+
     pub async fn suppress_forwarding(&self, track_id: &TrackId) {
         self.suppressed_sources
             .lock()
@@ -312,6 +477,35 @@ impl MediaStream {
         }
     }
 
+    pub async fn retain_processor<T: 'static>(
+        &self,
+        track_id: &TrackId,
+        keep: impl Fn(&T) -> bool,
+    ) -> Result<()> {
+        if let Some((track, _)) = self.tracks.lock().await.get_mut(track_id) {
+            track.as_mut().processor_chain().retain_processor::<T>(keep);
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Track {} not found", track_id))
+        }
+    }
+
+    pub async fn update_processor<T: 'static>(
+        &self,
+        track_id: &TrackId,
+        update: impl FnMut(&mut T),
+    ) -> Result<()> {
+        if let Some((track, _)) = self.tracks.lock().await.get_mut(track_id) {
+            track
+                .as_mut()
+                .processor_chain()
+                .update_processor::<T>(update);
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Track {} not found", track_id))
+        }
+    }
+
     pub async fn append_processor(
         &self,
         track_id: &TrackId,
@@ -372,28 +566,56 @@ impl MediaStream {
                 "start recorder",
             );
 
-            let recorder_handle = crate::spawn(async move {
+            let (files_sender, files_receiver) = oneshot::channel();
+            crate::spawn(async move {
                 let recorder_file = recorder_option.recorder_file.clone();
+                let encryption_key = recorder_option.encryption_key.clone();
                 let recorder =
                     Recorder::new(cancel_token, session_id_clone.clone(), recorder_option);
-                match recorder
-                    .process_recording(Path::new(&recorder_file), recorder_receiver)
+                let recorder_file = Path::new(&recorder_file);
+                if let Err(e) = recorder
+                    .process_recording(recorder_file, recorder_receiver)
                     .await
                 {
-                    Ok(_) => {}
-                    Err(e) => {
-                        warn!(
-                            session_id = session_id_clone,
-                            "Failed to process recorder: {}", e
-                        );
+                    warn!(
+                        session_id = session_id_clone,
+                        "Failed to process recorder: {}", e
+                    );
+                    files_sender.send(RecordedFile::list(recorder_file)).ok();
+                    return;
+                }
+                let mut files = RecordedFile::list(recorder_file);
+                if let Some(key) = encryption_key {
+                    for file in files.iter_mut() {
+                        match Self::encrypt_recording(Path::new(&file.path), &key).await {
+                            Ok(encrypted) => {
+                                file.path = encrypted;
+                                file.encrypted = true;
+                            }
+                            Err(e) => {
+                                warn!(
+                                    session_id = session_id_clone,
+                                    "Failed to encrypt recording: {}", e
+                                );
+                            }
+                        }
                     }
                 }
+                files_sender.send(files).ok();
             });
-            *self.recorder_handle.lock().await = Some(recorder_handle);
+            *self.recorder_files.lock().await = Some(files_receiver);
         }
         Ok(())
     }
 
+    /// Encrypt the recording at `path` in place, returning the encrypted path
+    async fn encrypt_recording(path: &Path, key: &str) -> Result<String> {
+        let key = crate::media::encryption::parse_key(key)?;
+        let encrypted = crate::media::encryption::encrypt_file(path, &key).await?;
+        info!(path = encrypted, "recording encrypted");
+        Ok(encrypted)
+    }
+
     async fn handle_forward_track(&self, mut packet_receiver: TrackPacketReceiver) {
         let event_sender = self.event_sender.clone();
         while let Some(packet) = packet_receiver.recv().await {
@@ -403,6 +625,8 @@ impl MediaStream {
                     .await
                     .contains(&packet.track_id)
             };
+            // Lock order matches `send_barge_audio`
+            let mut barge = self.barge.lock().await;
             // Process the packet with each track
             for (track, dtmf_detector) in self.tracks.lock().await.values_mut() {
                 if track.id() == &packet.track_id {
@@ -433,7 +657,15 @@ impl MediaStream {
                 if packet.track_id == QUEUE_HOLD_TRACK_ID && track.id() == CALLEE_TRACK_ID {
                     continue;
                 }
-                if let Err(e) = track.send_packet(&packet).await {
+                let result = match barge.as_mut() {
+                    Some(mixer) => {
+                        let mut mixed = packet.clone();
+                        mixer.mix(track.id(), &mut mixed);
+                        track.send_packet(&mixed).await
+                    }
+                    None => track.send_packet(&packet).await,
+                };
+                if let Err(e) = result {
                     warn!(
                         id = track.id(),
                         "media_stream: Failed to send packet to track: {}", e
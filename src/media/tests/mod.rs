@@ -5,6 +5,7 @@ mod perf_analysis;
 mod perf_rtp_recorder;
 mod recorder;
 mod recorder_rtp;
+mod samplerate;
 mod stream;
 mod tts_track;
 mod webrtc_track;
@@ -406,3 +406,61 @@ async fn test_recorder_200ms_timing() -> Result<()> {
     println!("200ms timing test completed successfully");
     Ok(())
 }
+
+/// With `stereo` the caller is recorded on the left channel and the server
+/// side track on the right, whichever starts first, and the quiet side is
+/// padded with silence
+#[tokio::test]
+async fn test_recorder_stereo_routes_by_direction() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let file_path = temp_dir.path().join("test_stereo.wav");
+    let file_path_clone = file_path.clone();
+    let config = RecorderOption {
+        stereo: Some(true),
+        outbound_track_id: Some("server-side-track".to_string()),
+        ..Default::default()
+    };
+    let recorder = Arc::new(Recorder::new(
+        CancellationToken::new(),
+        "test_stereo".to_string(),
+        config,
+    ));
+    let (tx, rx) = mpsc::unbounded_channel();
+    let recorder_clone = recorder.clone();
+    let recorder_handle =
+        tokio::spawn(async move { recorder_clone.process_recording(&file_path_clone, rx).await });
+
+    let frame = |track_id: &str, value: Sample, len: usize| AudioFrame {
+        track_id: track_id.to_string(),
+        samples: Samples::PCM {
+            samples: vec![value; len],
+        },
+        sample_rate: 16000,
+        channels: 1,
+        ..Default::default()
+    };
+    // The outbound track starts first, and the caller keeps talking after it
+    tx.send(frame("server-side-track", 2000, 1600))?;
+    tx.send(frame("caller", 1000, 3200))?;
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    recorder.stop_recording()?;
+    recorder_handle.await??;
+
+    let mut reader = hound::WavReader::open(&file_path)?;
+    assert_eq!(reader.spec().channels, 2);
+    let samples: Vec<Sample> = reader.samples::<i16>().collect::<Result<_, _>>()?;
+    let pairs: Vec<(Sample, Sample)> = samples.chunks_exact(2).map(|s| (s[0], s[1])).collect();
+    assert!(pairs.iter().all(|(left, _)| matches!(left, 0 | 1000)));
+    assert!(pairs.iter().all(|(_, right)| matches!(right, 0 | 2000)));
+    assert_eq!(pairs.iter().filter(|(left, _)| *left == 1000).count(), 3200);
+    assert_eq!(
+        pairs.iter().filter(|(_, right)| *right == 2000).count(),
+        1600
+    );
+    assert!(
+        pairs.contains(&(1000, 0)),
+        "the outbound channel should be silent while only the caller talks"
+    );
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use crate::media::{
     AudioFrame, Samples,
-    recorder::{Recorder, RecorderOption},
+    recorder::{FormatChangePolicy, Recorder, RecorderOption, segment_path},
 };
 use anyhow::Result;
 use std::sync::Arc;
@@ -196,3 +196,207 @@ async fn test_recorder_rtp_pcm_l16() -> Result<()> {
 
     Ok(())
 }
+
+/// Record one second of G722 followed by one second of PCMU, as after a
+/// re-INVITE switching codecs mid-call
+async fn record_codec_change(
+    file_path: &std::path::Path,
+    policy: FormatChangePolicy,
+) -> Result<()> {
+    let config = RecorderOption {
+        on_format_change: Some(policy),
+        ..Default::default()
+    };
+    let recorder = Arc::new(Recorder::new(
+        CancellationToken::new(),
+        "test_codec_change".to_string(),
+        config,
+    ));
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let recorder_clone = recorder.clone();
+    let file_path_clone = file_path.to_path_buf();
+    let handle =
+        tokio::spawn(async move { recorder_clone.process_recording(&file_path_clone, rx).await });
+
+    // 20ms frames of a 440Hz tone
+    let tone = |rate: u32, offset: usize| -> Vec<i16> {
+        let len = (rate / 50) as usize;
+        (0..len)
+            .map(|i| {
+                let t = (offset * len + i) as f32 / rate as f32;
+                ((t * 440.0 * 2.0 * std::f32::consts::PI).sin() * 8000.0) as i16
+            })
+            .collect()
+    };
+    let mut g722 = audio_codec::create_encoder(audio_codec::CodecType::G722);
+    let mut pcmu = audio_codec::create_encoder(audio_codec::CodecType::PCMU);
+    for i in 0..100u16 {
+        let (payload_type, payload, sample_rate) = if i < 50 {
+            (9, g722.encode(&tone(16000, i as usize)), 16000)
+        } else {
+            (0, pcmu.encode(&tone(8000, i as usize)), 8000)
+        };
+        tx.send(AudioFrame {
+            track_id: "track1".to_string(),
+            samples: Samples::RTP {
+                sequence_number: i,
+                payload_type,
+                payload,
+            },
+            timestamp: i as u64 * 20,
+            sample_rate,
+            channels: 1,
+            ..Default::default()
+        })?;
+    }
+    drop(tx);
+    handle.await??;
+    Ok(())
+}
+
+/// (format tag, sample rate, playback duration in seconds) of a WAV file,
+/// checking the header matches the data actually written
+fn wav_info(path: &std::path::Path) -> (u16, u32, f64) {
+    let data = std::fs::read(path).unwrap();
+    assert_eq!(&data[0..4], b"RIFF");
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+    let data_size = u32_at(40) as usize;
+    assert_eq!(data_size, data.len() - 44, "header data size mismatch");
+    let bytes_per_sec = u32_at(28);
+    (
+        u16_at(20),
+        u32_at(24),
+        data_size as f64 / bytes_per_sec as f64,
+    )
+}
+
+#[tokio::test]
+async fn test_recorder_rtp_codec_change_resample() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let file_path = temp_dir.path().join("test_resample.wav");
+    record_codec_change(&file_path, FormatChangePolicy::Resample).await?;
+
+    let (format_tag, sample_rate, duration) = wav_info(&file_path);
+    assert_eq!(format_tag, 0x0064);
+    assert_eq!(sample_rate, 16000);
+    // The PCMU half is transcoded to G722 and keeps its real duration
+    assert!(
+        (duration - 2.0).abs() < 0.05,
+        "recording plays {}s instead of 2s",
+        duration
+    );
+    assert!(!segment_path(&file_path, 1).exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recorder_rtp_codec_change_split() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let file_path = temp_dir.path().join("test_split.wav");
+    record_codec_change(&file_path, FormatChangePolicy::Split).await?;
+
+    let (format_tag, sample_rate, duration) = wav_info(&file_path);
+    assert_eq!((format_tag, sample_rate), (0x0064, 16000));
+    assert!((duration - 1.0).abs() < 0.01);
+
+    let segment = segment_path(&file_path, 1);
+    assert_eq!(segment, temp_dir.path().join("test_split.1.wav"));
+    let (format_tag, sample_rate, duration) = wav_info(&segment);
+    assert_eq!((format_tag, sample_rate), (0x0007, 8000));
+    assert!((duration - 1.0).abs() < 0.01);
+    Ok(())
+}
+
+/// Split an Ogg stream into `(header_type, granule, packet)` pages, checking
+/// each page's CRC
+#[cfg(feature = "opus")]
+fn ogg_pages(data: &[u8]) -> Vec<(u8, u64, Vec<u8>)> {
+    let mut pages = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        assert_eq!(&data[pos..pos + 4], b"OggS");
+        let segments = data[pos + 26] as usize;
+        let lacing = &data[pos + 27..pos + 27 + segments];
+        let body_len: usize = lacing.iter().map(|&l| l as usize).sum();
+        let end = pos + 27 + segments + body_len;
+
+        let mut page = data[pos..end].to_vec();
+        let crc = u32::from_le_bytes(page[22..26].try_into().unwrap());
+        page[22..26].fill(0);
+        let mut expected = 0u32;
+        for &byte in &page {
+            expected ^= (byte as u32) << 24;
+            for _ in 0..8 {
+                expected = if expected & 0x8000_0000 != 0 {
+                    (expected << 1) ^ 0x04c1_1db7
+                } else {
+                    expected << 1
+                };
+            }
+        }
+        assert_eq!(crc, expected, "page at {} has a bad checksum", pos);
+
+        let granule = u64::from_le_bytes(data[pos + 6..pos + 14].try_into().unwrap());
+        pages.push((data[pos + 5], granule, data[end - body_len..end].to_vec()));
+        pos = end;
+    }
+    pages
+}
+
+#[cfg(feature = "opus")]
+#[tokio::test]
+async fn test_recorder_rtp_ogg() -> Result<()> {
+    use crate::media::recorder::RecorderFormat;
+
+    let temp_dir = tempdir()?;
+    let file_path = temp_dir.path().join("test_ogg.ogg");
+    let config = RecorderOption {
+        format: Some(RecorderFormat::Ogg),
+        ..Default::default()
+    };
+    let recorder = Arc::new(Recorder::new(
+        CancellationToken::new(),
+        "test_ogg".to_string(),
+        config,
+    ));
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let recorder_clone = recorder.clone();
+    let file_path_clone = file_path.clone();
+    let handle =
+        tokio::spawn(async move { recorder_clone.process_recording(&file_path_clone, rx).await });
+
+    // 1s of PCMU
+    for i in 0..50u16 {
+        tx.send(AudioFrame {
+            track_id: "track1".to_string(),
+            samples: Samples::RTP {
+                sequence_number: i,
+                payload_type: 0,
+                payload: vec![0xffu8; 160],
+            },
+            timestamp: i as u64 * 20,
+            sample_rate: 8000,
+            channels: 1,
+            ..Default::default()
+        })?;
+    }
+    drop(tx);
+    handle.await??;
+
+    let pages = ogg_pages(&std::fs::read(&file_path)?);
+    assert!(pages.len() > 2, "expected audio pages after the headers");
+    let (header_type, _, head) = &pages[0];
+    assert_eq!(*header_type, 0x02, "first page should begin the stream");
+    assert!(head.starts_with(b"OpusHead"));
+    assert_eq!(head[9], 1, "RTP is recorded in mono");
+    assert!(pages[1].2.starts_with(b"OpusTags"));
+
+    let (header_type, granule, _) = pages.last().unwrap();
+    assert_eq!(*header_type, 0x04, "last page should end the stream");
+    // 1s at 48kHz plus the pre-skip
+    assert_eq!(*granule, 48000 + 312);
+    Ok(())
+}
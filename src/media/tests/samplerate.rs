@@ -0,0 +1,152 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::processor::{Processor, ProcessorChain};
+use crate::media::track::track_codec::TrackCodec;
+use crate::media::vad::{VADOption, VadProcessor};
+use crate::media::{
+    AudioFrame, INTERNAL_SAMPLERATE, Samples, internal_samplerate_for_codec,
+    resolve_internal_samplerate,
+};
+use anyhow::Result;
+use audio_codec::CodecType;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Records the rate and length of every frame reaching the processors
+struct CaptureProcessor {
+    frames: Arc<Mutex<Vec<(u32, usize)>>>,
+}
+
+impl Processor for CaptureProcessor {
+    fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        if let Samples::PCM { samples } = &frame.samples {
+            self.frames
+                .lock()
+                .unwrap()
+                .push((frame.sample_rate, samples.len()));
+        }
+        Ok(())
+    }
+}
+
+fn tone(sample_rate: u32, offset: usize, len: usize, amplitude: f32) -> Vec<i16> {
+    (0..len)
+        .map(|i| {
+            let t = (offset + i) as f32 / sample_rate as f32;
+            (amplitude * (2.0 * std::f32::consts::PI * 440.0 * t).sin()) as i16
+        })
+        .collect()
+}
+
+/// Feed 400ms of tone followed by 200ms of silence as 20ms RTP packets through a
+/// chain following `codec`, returning the captured frames and the VAD silence event.
+fn run_call(codec: CodecType, payload_type: u8, wire_rate: u32) -> (u32, Vec<(u32, usize)>, usize) {
+    let mut chain = ProcessorChain::new(INTERNAL_SAMPLERATE);
+    chain.set_follow_codec(true);
+    chain.update_for_codec(codec);
+    let samplerate = chain.sample_rate();
+
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let vad = VadProcessor::create_nop(
+        CancellationToken::new(),
+        event_sender.clone(),
+        VADOption::default(),
+    )
+    .unwrap();
+    chain.append_processor(vad);
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    chain.append_processor(Box::new(CaptureProcessor {
+        frames: frames.clone(),
+    }));
+
+    let mut encoder = TrackCodec::new();
+    let packet_len = (wire_rate / 50) as usize;
+    for n in 0..30 {
+        let amplitude = if n < 20 { 8000.0 } else { 0.0 };
+        let pcm = AudioFrame {
+            samples: Samples::PCM {
+                samples: tone(wire_rate, n * packet_len, packet_len, amplitude),
+            },
+            sample_rate: wire_rate,
+            channels: 1,
+            ..Default::default()
+        };
+        let (_, payload) = encoder.encode(payload_type, pcm);
+        let mut frame = AudioFrame {
+            track_id: "caller".to_string(),
+            samples: Samples::RTP {
+                sequence_number: n as u16,
+                payload_type,
+                payload,
+            },
+            timestamp: (n * 20) as u64,
+            sample_rate: wire_rate,
+            channels: 1,
+            ..Default::default()
+        };
+        chain.process_frame(&mut frame).unwrap();
+    }
+
+    let mut silence_samples = 0;
+    while let Ok(event) = event_receiver.try_recv() {
+        if let SessionEvent::Silence {
+            samples: Some(samples),
+            ..
+        } = event
+        {
+            silence_samples = samples.len();
+        }
+    }
+    let frames = frames.lock().unwrap().clone();
+    (samplerate, frames, silence_samples)
+}
+
+#[test]
+fn test_resolve_internal_samplerate() {
+    assert_eq!(internal_samplerate_for_codec(CodecType::PCMU), 8000);
+    assert_eq!(internal_samplerate_for_codec(CodecType::G722), 16000);
+    assert_eq!(resolve_internal_samplerate(0, CodecType::PCMA), Some(8000));
+    assert_eq!(
+        resolve_internal_samplerate(48000, CodecType::PCMA),
+        Some(48000)
+    );
+    assert_eq!(resolve_internal_samplerate(22050, CodecType::PCMA), None);
+}
+
+#[test]
+fn test_pcmu_call_processes_at_8k() {
+    let (samplerate, frames, silence_samples) = run_call(CodecType::PCMU, 0, 8000);
+    assert_eq!(samplerate, 8000);
+    assert_eq!(frames.len(), 30);
+    assert!(
+        frames
+            .iter()
+            .all(|(rate, len)| *rate == 8000 && *len == 160)
+    );
+    // VAD still runs at its own 16k rate: ~420ms of buffered speech
+    assert!(
+        (silence_samples as i64 - 420 * 16).abs() <= 640,
+        "silence samples {}",
+        silence_samples
+    );
+}
+
+#[cfg(feature = "opus")]
+#[test]
+fn test_opus_call_processes_at_48k() {
+    let (samplerate, frames, _) = run_call(CodecType::Opus, 111, 48000);
+    assert_eq!(samplerate, 48000);
+    assert_eq!(frames.len(), 30);
+    assert!(
+        frames
+            .iter()
+            .all(|(rate, len)| *rate == 48000 && *len == 960)
+    );
+}
+
+#[test]
+fn test_chain_ignores_codec_without_follow() {
+    let chain = ProcessorChain::new(INTERNAL_SAMPLERATE);
+    chain.update_for_codec(CodecType::PCMU);
+    assert_eq!(chain.sample_rate(), INTERNAL_SAMPLERATE);
+}
@@ -25,6 +25,7 @@ pub struct TestTrack {
     sender: Option<TrackPacketSender>,
     processor_chain: ProcessorChain,
     received_packets: Arc<Mutex<Vec<AudioFrame>>>,
+    echo: bool,
 }
 
 impl TestTrack {
@@ -35,8 +36,19 @@ impl TestTrack {
             sender: None,
             processor_chain: ProcessorChain::new(16000),
             received_packets: Arc::new(Mutex::new(Vec::new())),
+            echo: true,
         }
     }
+
+    /// Only record the packets the track is sent
+    pub fn without_echo(mut self) -> Self {
+        self.echo = false;
+        self
+    }
+
+    pub fn received_packets(&self) -> Arc<Mutex<Vec<AudioFrame>>> {
+        self.received_packets.clone()
+    }
 }
 
 #[async_trait]
@@ -78,6 +90,9 @@ impl Track for TestTrack {
             let mut received = self.received_packets.lock().await;
             received.push(packet.clone());
         }
+        if !self.echo {
+            return Ok(());
+        }
 
         // Clone and process the packet
         let mut packet_clone = packet.clone();
@@ -472,3 +487,168 @@ async fn test_remove_processor_from_nonexistent_track() -> Result<()> {
 
     Ok(())
 }
+
+/// A joined supervisor is heard by both parties: on its own while nobody
+/// else is sent audio, mixed in otherwise, and not at all once they left
+#[tokio::test]
+async fn test_barge_audio_reaches_caller_and_agent() -> Result<()> {
+    let event_sender = crate::event::create_event_sender();
+    let stream = Arc::new(MediaStreamBuilder::new(event_sender).build());
+    let caller = TestTrack::new("caller".to_string()).without_echo();
+    let agent = TestTrack::new("agent".to_string()).without_echo();
+    let caller_received = caller.received_packets();
+    let agent_received = agent.received_packets();
+    stream.update_track(Box::new(caller), None).await;
+    stream.update_track(Box::new(agent), None).await;
+
+    let stream_clone = stream.clone();
+    let handle = tokio::spawn(async move {
+        stream_clone.serve().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    fn frame(track_id: &str, level: i16) -> AudioFrame {
+        AudioFrame {
+            track_id: track_id.to_string(),
+            samples: Samples::PCM {
+                samples: vec![level; 320],
+            },
+            sample_rate: 16000,
+            channels: 1,
+            ..Default::default()
+        }
+    }
+    // Each party sends one frame
+    async fn talk(packet_sender: &TrackPacketSender) {
+        packet_sender.send(frame("caller", 100)).unwrap();
+        packet_sender.send(frame("agent", 200)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    stream.join_barge().await?;
+    assert!(
+        stream.join_barge().await.is_err(),
+        "one supervisor at a time"
+    );
+    stream.send_barge_audio(vec![1000; 320]).await?;
+    talk(&stream.packet_sender).await;
+    stream.send_barge_audio(vec![1000; 320]).await?;
+    talk(&stream.packet_sender).await;
+    stream.leave_barge().await;
+    assert!(stream.send_barge_audio(vec![1000; 320]).await.is_err());
+    talk(&stream.packet_sender).await;
+
+    let levels = |received: Vec<AudioFrame>| {
+        received
+            .iter()
+            .map(|frame| match &frame.samples {
+                Samples::PCM { samples } => {
+                    assert!(samples.iter().all(|s| *s == samples[0]));
+                    samples[0]
+                }
+                _ => panic!("expected PCM"),
+            })
+            .collect::<Vec<_>>()
+    };
+    // Supervisor alone, the other party, the other party with the supervisor, the other party
+    assert_eq!(
+        levels(caller_received.lock().await.clone()),
+        vec![1000, 200, 1200, 200]
+    );
+    assert_eq!(
+        levels(agent_received.lock().await.clone()),
+        vec![1000, 100, 1100, 100]
+    );
+
+    handle.abort();
+    Ok(())
+}
+
+/// Cleanup waits for the recorder and hands back the encrypted file it left
+#[tokio::test]
+async fn test_stream_cleanup_returns_encrypted_recording() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let file_path = temp_dir.path().join("encrypted_recording.wav");
+    let stream = Arc::new(
+        MediaStreamBuilder::new(crate::event::create_event_sender())
+            .with_recorder_config(RecorderOption {
+                recorder_file: file_path.to_string_lossy().to_string(),
+                encryption_key: Some(
+                    "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
+                ),
+                ..Default::default()
+            })
+            .build(),
+    );
+    let track = Box::new(TestTrack::new("test1".to_string()));
+    let track_id = track.id().clone();
+    stream.update_track(track, None).await;
+
+    let stream_clone = stream.clone();
+    tokio::spawn(async move {
+        stream_clone.serve().await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stream
+        .packet_sender
+        .send(AudioFrame {
+            track_id,
+            timestamp: 1000,
+            samples: Samples::PCM {
+                samples: vec![3000; 320],
+            },
+            sample_rate: 16000,
+            channels: 1,
+            ..Default::default()
+        })
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let files = stream.cleanup().await?.expect("recorder should finish");
+    assert_eq!(files.len(), 1);
+    assert!(files[0].encrypted);
+    assert_eq!(
+        files[0].path,
+        format!(
+            "{}.{}",
+            file_path.to_string_lossy(),
+            crate::media::encryption::ENCRYPTED_EXTENSION
+        )
+    );
+    assert!(std::path::Path::new(&files[0].path).exists());
+    assert!(!file_path.exists());
+    Ok(())
+}
+
+/// Inband DTMF reaches the track at its own rate, in ptime sized frames
+#[tokio::test]
+async fn test_stream_sends_dtmf_at_track_rate() -> Result<()> {
+    for sample_rate in [8000u32, 48000] {
+        let stream = MediaStreamBuilder::new(crate::event::create_event_sender()).build();
+        let mut track = TestTrack::new("caller".to_string()).without_echo();
+        track.config = TrackConfig::default().with_sample_rate(sample_rate);
+        let received = track.received_packets();
+        stream.update_track(Box::new(track), None).await;
+
+        stream
+            .send_dtmf_inband(&"caller".to_string(), "5", 100, 60)
+            .await?;
+
+        let received = received.lock().await;
+        assert!(received.iter().all(|f| f.sample_rate == sample_rate));
+        let samples: Vec<i16> = received
+            .iter()
+            .flat_map(|f| match &f.samples {
+                Samples::PCM { samples } => samples.clone(),
+                _ => vec![],
+            })
+            .collect();
+        // 160ms of tone and gap in 20ms frames
+        assert_eq!(received.len(), 8, "at {}Hz", sample_rate);
+        assert_eq!(samples.len(), sample_rate as usize * 160 / 1000);
+        let tone_len = sample_rate as usize / 10;
+        assert!(samples[..tone_len].iter().any(|s| *s != 0));
+        assert!(samples[tone_len..].iter().all(|s| *s == 0));
+    }
+    Ok(())
+}
@@ -1,9 +1,19 @@
-use crate::media::track::{
-    Track, TrackConfig,
-    rtc::{RtcTrack, RtcTrackConfig},
+use crate::event::SessionEvent;
+use crate::media::{
+    AudioFrame, Samples,
+    negotiate::{SIP_SDP_FILTER, sip_sdp_filter},
+    track::{
+        Track, TrackConfig,
+        rtc::{RtcTrack, RtcTrackConfig},
+        track_codec::TrackCodec,
+    },
 };
 use anyhow::Result;
+use audio_codec::CodecType;
 use rustrtc::TransportMode;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 
 #[tokio::test]
@@ -42,3 +52,176 @@ async fn test_rtp_track_creation() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_sip_answer_is_filtered() -> Result<()> {
+    let offer = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 40000 RTP/AVP 0 101\r\na=rtpmap:0 PCMU/8000\r\na=rtpmap:101 telephone-event/8000\r\na=fmtp:101 0-16\r\na=ptime:20\r\na=sendrecv\r\n";
+    let rtc_config = RtcTrackConfig {
+        mode: TransportMode::Rtp,
+        sdp_filter: sip_sdp_filter(),
+        ..Default::default()
+    };
+    let mut track = RtcTrack::new(
+        CancellationToken::new(),
+        "test-sip-answer".to_string(),
+        TrackConfig::default(),
+        rtc_config,
+    );
+
+    let answer = track.handshake(offer.to_string(), None).await?;
+    let attributes: Vec<&str> = answer
+        .lines()
+        .filter_map(|l| l.strip_prefix("a="))
+        .map(|a| a.split(':').next().unwrap_or_default())
+        .collect();
+    assert!(attributes.contains(&"rtpmap"));
+    for attribute in attributes {
+        assert!(
+            !SIP_SDP_FILTER.contains(&attribute),
+            "unexpected a={} in SIP answer:\n{}",
+            attribute,
+            answer
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_configured_opus_fmtp() -> Result<()> {
+    const FMTP: &str = "maxaveragebitrate=24000;useinbandfec=1";
+    let rtc_config = RtcTrackConfig {
+        mode: TransportMode::Rtp,
+        codecs: vec![CodecType::Opus, CodecType::PCMU],
+        preferred_codec: Some(CodecType::Opus),
+        codec_fmtp: HashMap::from([("opus".to_string(), FMTP.to_string())]),
+        ..Default::default()
+    };
+    let mut track = RtcTrack::new(
+        CancellationToken::new(),
+        "test-opus-fmtp".to_string(),
+        TrackConfig::default(),
+        rtc_config.clone(),
+    );
+    track.create().await?;
+    let offer = track.local_description().await?;
+    assert!(
+        offer.contains(&format!("a=fmtp:111 {}", FMTP)),
+        "offer:\n{}",
+        offer
+    );
+
+    // Answers carry our parameters, not the offerer's
+    let remote = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 40000 RTP/AVP 111 0\r\na=rtpmap:111 opus/48000/2\r\na=fmtp:111 minptime=10;useinbandfec=0\r\na=rtpmap:0 PCMU/8000\r\na=sendrecv\r\n";
+    let mut track = RtcTrack::new(
+        CancellationToken::new(),
+        "test-opus-fmtp-answer".to_string(),
+        TrackConfig::default(),
+        rtc_config,
+    );
+    let answer = track.handshake(remote.to_string(), None).await?;
+    assert!(
+        answer.contains(&format!("a=fmtp:111 {}", FMTP)),
+        "answer:\n{}",
+        answer
+    );
+
+    // The encoder keeps to the bitrate cap
+    let frame = || AudioFrame {
+        samples: Samples::PCM {
+            samples: (0..960)
+                .map(|i| ((i * 7919 % 2000) as i16 - 1000) * 8)
+                .collect(),
+        },
+        sample_rate: 48000,
+        channels: 1,
+        ..Default::default()
+    };
+    let mut default_codec = TrackCodec::new();
+    let mut capped_codec = TrackCodec::new();
+    capped_codec.set_fmtp(CodecType::Opus, FMTP);
+    let (_, default_payload) = default_codec.encode(111, frame());
+    let (_, capped_payload) = capped_codec.encode(111, frame());
+    // 24kbps for 20ms
+    assert!(
+        !capped_payload.is_empty() && capped_payload.len() <= 60,
+        "{} bytes",
+        capped_payload.len()
+    );
+    assert!(default_payload.len() > capped_payload.len());
+    Ok(())
+}
+
+fn data_channel_track(id: &str) -> RtcTrack {
+    let rtc_config = RtcTrackConfig {
+        mode: TransportMode::WebRtc,
+        ice_servers: Some(vec![]),
+        bind_ip: Some("127.0.0.1".to_string()),
+        data_channel: true,
+        ..Default::default()
+    };
+    RtcTrack::new(
+        CancellationToken::new(),
+        id.to_string(),
+        TrackConfig::default(),
+        rtc_config,
+    )
+}
+
+async fn recv_data(events: &mut broadcast::Receiver<SessionEvent>) -> Result<(String, String)> {
+    loop {
+        if let SessionEvent::DataChannel { label, data, .. } = events.recv().await? {
+            return Ok((label, data));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_data_channel_round_trip() -> Result<()> {
+    let mut offerer = data_channel_track("offerer");
+    let mut answerer = data_channel_track("answerer");
+
+    offerer.create().await?;
+    offerer.local_description().await?;
+    let pc = offerer.peer_connection.clone().unwrap();
+    pc.wait_for_gathering_complete().await;
+    let offer = pc.local_description().unwrap().to_sdp_string();
+    assert!(offer.contains("m=application"));
+
+    let answer = answerer.handshake(offer, None).await?;
+    offerer.update_remote_description(&answer).await?;
+
+    let (offerer_events, mut offerer_rx) = broadcast::channel(16);
+    let (answerer_events, mut answerer_rx) = broadcast::channel(16);
+    let (packet_sender, _packet_receiver) = mpsc::unbounded_channel();
+    offerer.start(offerer_events, packet_sender.clone()).await?;
+    answerer.start(answerer_events, packet_sender).await?;
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        // The offer's channel can be used once DTLS and SCTP are up
+        loop {
+            if offerer.send_data("data", "hello").await.is_ok() {
+                let received =
+                    tokio::time::timeout(Duration::from_millis(200), recv_data(&mut answerer_rx));
+                if let Ok(received) = received.await {
+                    assert_eq!(received?, ("data".to_string(), "hello".to_string()));
+                    break;
+                }
+            } else {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }
+
+        answerer.send_data("data", "hi").await?;
+        assert_eq!(
+            recv_data(&mut offerer_rx).await?,
+            ("data".to_string(), "hi".to_string())
+        );
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+
+    assert!(offerer.send_data("other", "hello").await.is_err());
+    offerer.stop().await?;
+    answerer.stop().await?;
+    Ok(())
+}
@@ -65,6 +65,10 @@ pub trait Track: Send + Sync {
     fn id(&self) -> &TrackId;
     fn config(&self) -> &TrackConfig;
     fn processor_chain(&mut self) -> &mut ProcessorChain;
+    /// Primary audio codec agreed with the remote side, if any
+    fn negotiated_codec(&self) -> Option<CodecType> {
+        None
+    }
     fn insert_processor(&mut self, processor: Box<dyn Processor>) {
         self.processor_chain().insert_processor(processor);
     }
@@ -87,4 +91,12 @@ pub trait Track: Send + Sync {
         self.stop().await
     }
     async fn send_packet(&mut self, packet: &AudioFrame) -> Result<()>;
+    /// Send a text message on the data channel `label`, WebRTC tracks only
+    async fn send_data(&self, label: &str, _data: &str) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "track {} has no data channel {}",
+            self.id(),
+            label
+        ))
+    }
 }
@@ -3,7 +3,9 @@ use crate::{
     event::{EventSender, SessionEvent},
     media::AudioFrame,
     media::{
+        negotiate::{codec_fmtp, filter_sdp_attributes},
         processor::ProcessorChain,
+        quality::QualityStats,
         track::{Track, TrackConfig, TrackId, TrackPacketSender},
     },
 };
@@ -13,15 +15,17 @@ use audio_codec::CodecType;
 use bytes::Bytes;
 use futures::{FutureExt, StreamExt, stream::FuturesUnordered};
 use rustrtc::{
-    AudioCapability, IceServer, MediaKind, PeerConnection, PeerConnectionEvent,
+    AudioCapability, DataChannelEvent, IceServer, MediaKind, PeerConnection, PeerConnectionEvent,
     PeerConnectionState, RtcConfiguration, RtpCodecParameters, SdpType, TransportMode,
     config::MediaCapabilities,
     media::{
         MediaStreamTrack, SampleStreamSource, frame::AudioFrame as RtcAudioFrame, sample_track,
         track::SampleStreamTrack,
     },
+    transports::sctp::DataChannel,
 };
 use std::{
+    collections::HashMap,
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -29,6 +33,11 @@ use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
+/// Label of the data channel opened with our own offer
+pub const DEFAULT_DATA_CHANNEL_LABEL: &str = "data";
+
+type DataChannels = Arc<Mutex<HashMap<String, Arc<DataChannel>>>>;
+
 #[derive(Clone)]
 pub struct RtcTrackConfig {
     pub mode: TransportMode,
@@ -40,6 +49,14 @@ pub struct RtcTrackConfig {
     pub codecs: Vec<CodecType>,
     pub payload_type: Option<u8>,
     pub enable_latching: Option<bool>,
+    /// SDP attributes left out of the descriptions we hand to the remote peer
+    pub sdp_filter: Vec<String>,
+    /// Open a data channel with our offer and surface messages on the
+    /// channels the remote peer opens, WebRTC only
+    pub data_channel: bool,
+    /// `a=fmtp` parameters per codec name, offered and answered in place of
+    /// the defaults. Our encoder follows the peer's parameters instead
+    pub codec_fmtp: HashMap<String, String>,
 }
 
 impl Default for RtcTrackConfig {
@@ -54,6 +71,9 @@ impl Default for RtcTrackConfig {
             codecs: Vec::new(),
             payload_type: None,
             enable_latching: None,
+            sdp_filter: Vec::new(),
+            data_channel: false,
+            codec_fmtp: HashMap::new(),
         }
     }
 }
@@ -64,6 +84,7 @@ pub struct RtcTrack {
     rtc_config: RtcTrackConfig,
     processor_chain: ProcessorChain,
     packet_sender: Arc<Mutex<Option<TrackPacketSender>>>,
+    event_sender: Arc<Mutex<Option<EventSender>>>,
     cancel_token: CancellationToken,
     local_source: Option<Arc<SampleStreamSource>>,
     encoder: TrackCodec,
@@ -75,6 +96,7 @@ pub struct RtcTrack {
     last_packet_time: Option<Instant>,
     last_remote_sdp: Option<String>,
     need_marker: bool,
+    data_channels: DataChannels,
 }
 
 impl RtcTrack {
@@ -91,6 +113,7 @@ impl RtcTrack {
             rtc_config,
             processor_chain,
             packet_sender: Arc::new(Mutex::new(None)),
+            event_sender: Arc::new(Mutex::new(None)),
             cancel_token,
             local_source: None,
             encoder: TrackCodec::new(),
@@ -102,6 +125,7 @@ impl RtcTrack {
             last_packet_time: None,
             last_remote_sdp: None,
             need_marker: false,
+            data_channels: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -123,9 +147,73 @@ impl RtcTrack {
             .peer_connection
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("No PeerConnection"))?;
+        if self.data_channel_enabled() {
+            let mut data_channels = self.data_channels.lock().await;
+            if data_channels.is_empty() {
+                let dc = pc.create_data_channel(DEFAULT_DATA_CHANNEL_LABEL, None)?;
+                data_channels.insert(dc.label.clone(), dc.clone());
+                Self::spawn_data_channel_reader(
+                    dc,
+                    self.track_id.clone(),
+                    self.event_sender.clone(),
+                    self.data_channels.clone(),
+                    self.cancel_token.clone(),
+                );
+            }
+        }
         let offer = pc.create_offer().await?;
         pc.set_local_description(offer.clone())?;
-        Ok(offer.to_sdp_string())
+        Ok(filter_sdp_attributes(
+            &offer.to_sdp_string(),
+            &self.rtc_config.sdp_filter,
+        ))
+    }
+
+    fn data_channel_enabled(&self) -> bool {
+        self.rtc_config.data_channel && self.rtc_config.mode != TransportMode::Rtp
+    }
+
+    /// Forward the messages of `dc` as [`SessionEvent::DataChannel`] until it closes
+    fn spawn_data_channel_reader(
+        dc: Arc<DataChannel>,
+        track_id: TrackId,
+        event_sender: Arc<Mutex<Option<EventSender>>>,
+        data_channels: DataChannels,
+        cancel_token: CancellationToken,
+    ) {
+        crate::spawn(async move {
+            debug!(track_id=%track_id, label=%dc.label, id=dc.id, "data channel reader started");
+            loop {
+                let event = tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    event = dc.recv() => event,
+                };
+                match event {
+                    Some(DataChannelEvent::Message(data)) => {
+                        if let Some(sender) = event_sender.lock().await.as_ref() {
+                            sender
+                                .send(SessionEvent::DataChannel {
+                                    track_id: track_id.clone(),
+                                    timestamp: crate::media::get_timestamp(),
+                                    label: dc.label.clone(),
+                                    data: String::from_utf8_lossy(&data).into_owned(),
+                                })
+                                .ok();
+                        }
+                    }
+                    Some(DataChannelEvent::Close) | None => break,
+                    Some(_) => {}
+                }
+            }
+            let mut data_channels = data_channels.lock().await;
+            if data_channels
+                .get(&dc.label)
+                .is_some_and(|current| Arc::ptr_eq(current, &dc))
+            {
+                data_channels.remove(&dc.label);
+            }
+            debug!(track_id=%track_id, label=%dc.label, "data channel reader ended");
+        });
     }
 
     pub async fn create(&mut self) -> Result<()> {
@@ -159,9 +247,11 @@ impl RtcTrack {
             .enable_latching
             .unwrap_or_else(|| self.rtc_config.mode == TransportMode::Rtp);
 
-        if !self.rtc_config.codecs.is_empty() {
+        if !self.rtc_config.codecs.is_empty() || !self.rtc_config.codec_fmtp.is_empty() {
             let mut caps = MediaCapabilities::default();
-            caps.audio.clear();
+            if !self.rtc_config.codecs.is_empty() {
+                caps.audio.clear();
+            }
 
             for codec in &self.rtc_config.codecs {
                 let cap = match codec {
@@ -175,6 +265,11 @@ impl RtcTrack {
                 };
                 caps.audio.push(cap);
             }
+            for cap in caps.audio.iter_mut() {
+                if let Some(fmtp) = codec_fmtp(&self.rtc_config.codec_fmtp, &cap.codec_name) {
+                    cap.fmtp = Some(fmtp.to_string());
+                }
+            }
             config.media_capabilities = Some(caps);
         }
 
@@ -223,11 +318,17 @@ impl RtcTrack {
     ) {
         let cancel_token = self.cancel_token.clone();
         let packet_sender = self.packet_sender.clone();
+        let event_sender = self.event_sender.clone();
+        let clock_rate = CodecType::try_from(default_payload_type)
+            .map(|c| c.clock_rate())
+            .unwrap_or(8000);
         let pc_event = pc.clone();
         let pc_stats = pc.clone();
         let pc_state = pc.clone();
         let track_id_log = track_id.clone();
         let is_webrtc = self.rtc_config.mode != TransportMode::Rtp;
+        let data_channel_enabled = self.data_channel_enabled();
+        let data_channels = self.data_channels.clone();
 
         crate::spawn(async move {
             info!(track_id=%track_id_log, "RtcTrack event/stats loop started");
@@ -262,7 +363,21 @@ impl RtcTrack {
                         };
                         debug!(track_id=%track_id_log, "Received PeerConnectionEvent #{}: {}", event_count, event_type);
 
-                        if let PeerConnectionEvent::Track(transceiver) = event {
+                        if let PeerConnectionEvent::DataChannel(dc) = event {
+                            if !data_channel_enabled {
+                                debug!(track_id=%track_id_log, label=%dc.label, "data channel not enabled, ignoring");
+                                continue;
+                            }
+                            info!(track_id=%track_id_log, label=%dc.label, "Remote data channel opened");
+                            data_channels.lock().await.insert(dc.label.clone(), dc.clone());
+                            Self::spawn_data_channel_reader(
+                                dc,
+                                track_id_log.clone(),
+                                event_sender.clone(),
+                                data_channels.clone(),
+                                cancel_token.clone(),
+                            );
+                        } else if let PeerConnectionEvent::Track(transceiver) = event {
                             if let Some(receiver) = transceiver.receiver() {
                                 let track = receiver.track();
                                 info!(track_id=%track_id_log, "New track received (SSRC latching complete)");
@@ -286,6 +401,17 @@ impl RtcTrack {
                         match pc_stats.get_stats().await {
                             Ok(stats) => {
                                 info!(track_id=%track_id_log, %stats, "RTCP Stats");
+                                if let Some(quality) = QualityStats::from_report(&stats, clock_rate) {
+                                    if let Some(sender) = event_sender.lock().await.as_ref() {
+                                        sender.send(SessionEvent::QualityStats {
+                                            track_id: track_id_log.clone(),
+                                            timestamp: crate::media::get_timestamp(),
+                                            jitter_ms: quality.jitter_ms,
+                                            loss_pct: quality.loss_pct,
+                                            rtt_ms: quality.rtt_ms,
+                                        }).ok();
+                                    }
+                                }
                             }
                             Err(e) => {
                                 debug!(track_id=%track_id_log, "Failed to get stats: {:?}", e);
@@ -475,6 +601,30 @@ impl RtcTrack {
                 }
             }
 
+            // The peer's fmtp is what it wants to receive, so it shapes our encoder
+            for format in &media.formats {
+                let Some(codec) = format
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(|pt| self.encoder.payload_type_map.get(&pt).cloned())
+                else {
+                    continue;
+                };
+                let fmtp = media
+                    .attributes
+                    .iter()
+                    .filter(|attr| attr.key == "fmtp")
+                    .filter_map(|attr| attr.value.as_deref())
+                    .find_map(|value| {
+                        value
+                            .split_once(' ')
+                            .filter(|(pt, _)| *pt == format.as_str())
+                            .map(|(_, params)| params)
+                    })
+                    .unwrap_or_default();
+                self.encoder.set_fmtp(codec, fmtp);
+            }
+
             // Negotiate primary audio codec
             let mut negotiated = None;
 
@@ -531,6 +681,7 @@ impl RtcTrack {
             if let Some((pt, codec)) = negotiated {
                 info!(track_id=%self.track_id, "Negotiated primary audio PT {} ({:?})", pt, codec);
                 self.payload_type = Some(pt);
+                self.processor_chain.update_for_codec(codec);
             }
         }
         Ok(())
@@ -645,6 +796,14 @@ impl Track for RtcTrack {
     fn processor_chain(&mut self) -> &mut ProcessorChain {
         &mut self.processor_chain
     }
+    fn negotiated_codec(&self) -> Option<CodecType> {
+        let pt = self.payload_type?;
+        self.encoder
+            .payload_type_map
+            .get(&pt)
+            .cloned()
+            .or_else(|| CodecType::try_from(pt).ok())
+    }
 
     async fn handshake(&mut self, offer: String, _: Option<Duration>) -> Result<String> {
         info!(track_id=%self.track_id, "rtc handshake start");
@@ -688,7 +847,10 @@ impl Track for RtcTrack {
             .local_description()
             .ok_or(anyhow::anyhow!("No local description"))?;
 
-        Ok(final_answer.to_sdp_string())
+        Ok(filter_sdp_attributes(
+            &final_answer.to_sdp_string(),
+            &self.rtc_config.sdp_filter,
+        ))
     }
 
     async fn update_remote_description(&mut self, answer: &String) -> Result<()> {
@@ -705,6 +867,7 @@ impl Track for RtcTrack {
         packet_sender: TrackPacketSender,
     ) -> Result<()> {
         *self.packet_sender.lock().await = Some(packet_sender.clone());
+        *self.event_sender.lock().await = Some(event_sender.clone());
         let token_clone = self.cancel_token.clone();
         let event_sender_clone = event_sender.clone();
         let track_id = self.track_id.clone();
@@ -735,6 +898,22 @@ impl Track for RtcTrack {
         Ok(())
     }
 
+    async fn send_data(&self, label: &str, data: &str) -> Result<()> {
+        let pc = self
+            .peer_connection
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No PeerConnection"))?;
+        let channel_id = self
+            .data_channels
+            .lock()
+            .await
+            .get(label)
+            .map(|dc| dc.id)
+            .ok_or_else(|| anyhow::anyhow!("data channel {} not open", label))?;
+        pc.send_text(channel_id, data).await?;
+        Ok(())
+    }
+
     async fn send_packet(&mut self, packet: &AudioFrame) -> Result<()> {
         let packet = packet.clone();
 
@@ -908,6 +1087,41 @@ mod tests {
         assert_eq!(track.get_payload_type(), 111);
     }
 
+    #[test]
+    #[cfg(feature = "opus")]
+    fn test_remote_opus_fmtp_caps_encoder() {
+        let mut track = RtcTrack::new(
+            CancellationToken::new(),
+            "test-remote-fmtp".to_string(),
+            TrackConfig::default(),
+            RtcTrackConfig::default(),
+        );
+        let mut encoded_len = |sdp: &str| {
+            track
+                .parse_sdp_payload_types(rustrtc::SdpType::Offer, sdp)
+                .expect("parse offer");
+            let frame = AudioFrame {
+                samples: crate::media::Samples::PCM {
+                    samples: (0..960)
+                        .map(|i| ((i * 7919 % 2000) as i16 - 1000) * 8)
+                        .collect(),
+                },
+                sample_rate: 48000,
+                channels: 1,
+                ..Default::default()
+            };
+            track.encoder.encode(111, frame).1.len()
+        };
+
+        let offer = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 1234 RTP/AVP 111\r\na=rtpmap:111 opus/48000/2\r\n";
+        let capped = offer.to_string() + "a=fmtp:111 maxaveragebitrate=24000\r\n";
+        let capped_len = encoded_len(&capped);
+        // 24kbps for 20ms
+        assert!(capped_len > 0 && capped_len <= 60, "{} bytes", capped_len);
+        // A later offer without the cap lifts it
+        assert!(encoded_len(offer) > capped_len);
+    }
+
     #[tokio::test]
     async fn test_rtp_mode_handshake_spawns_handler() {
         use rustrtc::TransportMode;
@@ -8,6 +8,8 @@ use audio_codec::{
 };
 use std::collections::HashMap;
 
+#[cfg(feature = "opus")]
+use crate::media::negotiate::parse_fmtp;
 use audio_codec::g729::{G729Decoder, G729Encoder};
 #[cfg(feature = "opus")]
 use audio_codec::opus::{OpusDecoder, OpusEncoder};
@@ -28,6 +30,9 @@ pub struct TrackCodec {
     opus_encoder: Option<OpusEncoder>,
     #[cfg(feature = "opus")]
     opus_decoder: Option<OpusDecoder>,
+    /// The peer's Opus `a=fmtp` parameters the encoder is created with
+    #[cfg(feature = "opus")]
+    opus_fmtp: HashMap<String, String>,
 
     resampler: Option<Resampler>,
     resampler_in_rate: u32,
@@ -39,6 +44,10 @@ impl Clone for TrackCodec {
     fn clone(&self) -> Self {
         let mut new = Self::new();
         new.payload_type_map = self.payload_type_map.clone();
+        #[cfg(feature = "opus")]
+        {
+            new.opus_fmtp = self.opus_fmtp.clone();
+        }
         new
     }
 }
@@ -67,6 +76,8 @@ impl TrackCodec {
             opus_encoder: None,
             #[cfg(feature = "opus")]
             opus_decoder: None,
+            #[cfg(feature = "opus")]
+            opus_fmtp: HashMap::new(),
             resampler: None,
             resampler_in_rate: 0,
             resampler_out_rate: 0,
@@ -78,6 +89,43 @@ impl TrackCodec {
         self.payload_type_map.insert(pt, codec);
     }
 
+    /// Configure the encoder of `codec` from the peer's `a=fmtp` parameters,
+    /// which describe what the peer wants to receive (RFC 7587 §7.1). Opus
+    /// honors `maxaveragebitrate`, `cbr` and `stereo`. `useinbandfec` only
+    /// matters in our own SDP, where it says we decode FEC: the encoder has
+    /// no setting for sending it.
+    #[cfg_attr(not(feature = "opus"), allow(unused_variables))]
+    pub fn set_fmtp(&mut self, codec: CodecType, fmtp: &str) {
+        #[cfg(feature = "opus")]
+        if codec == CodecType::Opus {
+            let fmtp = parse_fmtp(fmtp);
+            if fmtp != self.opus_fmtp {
+                self.opus_fmtp = fmtp;
+                self.opus_encoder = None;
+            }
+        }
+    }
+
+    #[cfg(feature = "opus")]
+    fn create_opus_encoder(fmtp: &HashMap<String, String>) -> OpusEncoder {
+        let channels = match fmtp.get("stereo").map(String::as_str) {
+            Some("0") => 1,
+            _ => 2,
+        };
+        let mut encoder = OpusEncoder::new(48000, channels);
+        if let Some(bitrate) = fmtp
+            .get("maxaveragebitrate")
+            .and_then(|bitrate| bitrate.parse::<i32>().ok())
+        {
+            // RFC 7587 range
+            encoder.set_bitrate(bitrate.clamp(6000, 510000));
+        }
+        if let Some(cbr) = fmtp.get("cbr") {
+            encoder.set_cbr(cbr == "1");
+        }
+        encoder
+    }
+
     pub fn is_audio(payload_type: u8) -> bool {
         match payload_type {
             0 | 8 | 9 | 18 | 111 => true,
@@ -206,7 +254,7 @@ impl TrackCodec {
                     #[cfg(feature = "opus")]
                     111 => self
                         .opus_encoder
-                        .get_or_insert_with(OpusEncoder::new_default)
+                        .get_or_insert_with(|| Self::create_opus_encoder(&self.opus_fmtp))
                         .encode(&pcm),
                     _ => samples_to_bytes(&pcm),
                 };
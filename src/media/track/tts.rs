@@ -7,7 +7,7 @@ use crate::{
     },
     synthesis::{
         Subtitle, SynthesisClient, SynthesisCommand, SynthesisCommandReceiver,
-        SynthesisCommandSender, SynthesisEvent, bytes_size_to_duration,
+        SynthesisCommandSender, SynthesisEvent, bytes_size_to_duration, chunk,
     },
 };
 use anyhow::{Result, anyhow};
@@ -107,6 +107,29 @@ pub fn strip_emoji_chars(text: &str) -> String {
         .collect()
 }
 
+/// Split a command whose text is longer than `max_text_length` into one
+/// command per chunk. Non streaming chunks get their own sequence numbers and
+/// so play back to back like separate commands.
+fn split_cmd(cmd: &SynthesisCommand) -> Vec<SynthesisCommand> {
+    let max_text_length = match cmd.option.max_text_length {
+        Some(max) if !cmd.base64 && cmd.text.chars().count() > max => max,
+        _ => return vec![cmd.clone()],
+    };
+    let chunks = chunk::split_text(&cmd.text, max_text_length);
+    if chunks.len() <= 1 {
+        return vec![cmd.clone()];
+    }
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| SynthesisCommand {
+            text,
+            cache_key: cmd.cache_key.as_ref().map(|key| format!("{}-{}", key, i)),
+            ..cmd.clone()
+        })
+        .collect()
+}
+
 impl TtsTask {
     async fn run(mut self) -> Result<()> {
         let mut stream;
@@ -384,8 +407,10 @@ impl TtsTask {
                         if cmd.option.session_id.is_none() {
                             cmd.option.session_id = Some(self.session_id.clone());
                         }
-                        self.handle_cmd(cmd, cmd_seq).await;
-                        cmd_seq.as_mut().map(|seq| *seq += 1);
+                        for cmd in split_cmd(cmd) {
+                            self.handle_cmd(&cmd, cmd_seq).await;
+                            cmd_seq.as_mut().map(|seq| *seq += 1);
+                        }
                     }
 
                     // set finished if command sender is exhausted or end_of_stream is true
@@ -665,6 +690,28 @@ impl TtsTask {
                     entry.subtitles.extend(subtitles);
                 });
             }
+            Ok(SynthesisEvent::Throttled { waited_ms }) => {
+                debug!(
+                    session_id = %self.session_id,
+                    track_id = %self.track_id,
+                    play_id = ?self.play_id,
+                    cmd_seq = ?cmd_seq,
+                    waited_ms,
+                    "tts request throttled by provider concurrency limit"
+                );
+                self.event_sender
+                    .send(SessionEvent::Metrics {
+                        timestamp: crate::media::get_timestamp(),
+                        key: format!("throttled.tts.{}", self.client.provider()),
+                        data: serde_json::json!({
+                                "playId": self.play_id,
+                                "cmdSeq": cmd_seq,
+                                "waited": waited_ms,
+                        }),
+                        duration: waited_ms as u32,
+                    })
+                    .ok();
+            }
             Ok(SynthesisEvent::Finished) => {
                 let entry = self.metadatas.entry(assume_seq).or_default();
                 let duration = (crate::media::get_timestamp() - entry.recv_time) as u32;
@@ -1026,12 +1073,14 @@ mod tests {
 
     struct MockClient {
         stream_rx: Option<mpsc::UnboundedReceiver<(Option<usize>, Result<SynthesisEvent>)>>,
+        requests: Arc<std::sync::Mutex<Vec<(Option<usize>, String)>>>,
     }
 
     impl MockClient {
         fn new(rx: mpsc::UnboundedReceiver<(Option<usize>, Result<SynthesisEvent>)>) -> Self {
             Self {
                 stream_rx: Some(rx),
+                requests: Default::default(),
             }
         }
     }
@@ -1049,10 +1098,14 @@ mod tests {
         }
         async fn synthesize(
             &mut self,
-            _text: &str,
-            _cmd_seq: Option<usize>,
+            text: &str,
+            cmd_seq: Option<usize>,
             _option: Option<crate::synthesis::SynthesisOption>,
         ) -> Result<()> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((cmd_seq, text.to_string()));
             Ok(())
         }
         async fn stop(&mut self) -> Result<()> {
@@ -2009,4 +2062,105 @@ mod tests {
 
         cancel_token.cancel();
     }
+
+    #[tokio::test]
+    async fn test_long_text_split_into_gapless_chunks() {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        let (session_event_tx, _session_event_rx) = broadcast::channel(10);
+
+        let client = MockClient::new(event_rx);
+        let requests = client.requests.clone();
+        let sample_rate = 8000;
+
+        let task = TtsTask {
+            play_id: Some("long_text".to_string()),
+            track_id: "track_long_text".to_string(),
+            session_id: "session_long_text".to_string(),
+            client: Box::new(client),
+            command_rx: cmd_rx,
+            event_sender: session_event_tx,
+            packet_sender: packet_tx,
+            cancel_token: CancellationToken::new(),
+            processor_chain: ProcessorChain::new(sample_rate),
+            cache_enabled: false,
+            sample_rate,
+            ptime: Duration::from_millis(20),
+            cache_buffer: BytesMut::new(),
+            emit_q: VecDeque::new(),
+            metadatas: HashMap::new(),
+            cur_seq: 0,
+            streaming: false,
+            graceful: Arc::new(AtomicBool::new(false)),
+            ssrc: 8888,
+            buffering_state: None,
+            min_buffer_size: 0,
+            max_buffer_wait: Duration::from_millis(500),
+        };
+        let handle = tokio::spawn(task.run());
+
+        let option = crate::synthesis::SynthesisOption {
+            max_text_length: Some(40),
+            ..Default::default()
+        };
+        cmd_tx
+            .send(SynthesisCommand {
+                text:
+                    "This is the first sentence. Here comes the second one. And this is the last."
+                        .to_string(),
+                play_id: Some("long_text".to_string()),
+                end_of_stream: true,
+                option,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut waited = 0;
+        while requests.lock().unwrap().len() < 3 && waited < 100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            waited += 1;
+        }
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                (Some(0), "This is the first sentence.".to_string()),
+                (Some(1), "Here comes the second one.".to_string()),
+                (Some(2), "And this is the last.".to_string()),
+            ]
+        );
+
+        // 800 samples of a distinct value per chunk
+        for seq in 0..3usize {
+            let value = (seq + 1) as u8;
+            event_tx
+                .send((
+                    Some(seq),
+                    Ok(SynthesisEvent::AudioChunk(Bytes::from(vec![value; 1600]))),
+                ))
+                .unwrap();
+            event_tx
+                .send((Some(seq), Ok(SynthesisEvent::Finished)))
+                .unwrap();
+        }
+        drop(event_tx);
+
+        let mut played = Vec::new();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            while let Some(frame) = packet_rx.recv().await {
+                if let Samples::PCM { samples } = frame.samples {
+                    played.extend(samples);
+                }
+            }
+        })
+        .await
+        .expect("tts task should finish");
+        handle.await.unwrap().unwrap();
+
+        let expected: Vec<i16> = [0x0101i16, 0x0202, 0x0303]
+            .iter()
+            .flat_map(|v| std::iter::repeat_n(*v, 800))
+            .collect();
+        assert_eq!(played, expected, "chunks should play back to back");
+    }
 }
@@ -4,19 +4,19 @@ use crate::{
     media::AudioFrame,
     media::Samples,
     media::TrackId,
+    media::audio_queue::{AudioQueueReceiver, AudioQueueSender},
     media::processor::ProcessorChain,
 };
 use anyhow::Result;
 use async_trait::async_trait;
 use audio_codec::bytes_to_samples;
-use bytes::Bytes;
 use std::{sync::Mutex, time::Duration};
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
-pub type WebsocketBytesSender = tokio::sync::mpsc::UnboundedSender<Bytes>;
-pub type WebsocketBytesReceiver = tokio::sync::mpsc::UnboundedReceiver<Bytes>;
+pub type WebsocketBytesSender = AudioQueueSender;
+pub type WebsocketBytesReceiver = AudioQueueReceiver;
 
 pub struct WebsocketTrack {
     track_id: TrackId,
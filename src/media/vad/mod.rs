@@ -2,6 +2,7 @@ use crate::event::{EventSender, SessionEvent};
 use crate::media::processor::Processor;
 use crate::media::{AudioFrame, PcmBuf, Samples};
 use anyhow::Result;
+use audio_codec::Resampler;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::any::Any;
@@ -109,6 +110,7 @@ struct VadProcessorInner {
     triggered_event_sent: bool,
     current_speech_start: Option<u64>,
     temp_end: Option<u64>,
+    resampler: Option<(u32, Resampler)>,
 }
 pub struct VadProcessor {
     inner: VadProcessorInner,
@@ -125,7 +127,38 @@ impl VadProcessorInner {
             _ => return Ok(()),
         };
 
-        let samples_cloned = samples.to_owned();
+        // The engine runs at option.samplerate, calls may process at 8k or 48k
+        if frame.sample_rate > 0 && frame.sample_rate != self.option.samplerate {
+            if !matches!(&self.resampler, Some((rate, _)) if *rate == frame.sample_rate) {
+                self.resampler = None;
+            }
+            let target_rate = self.option.samplerate;
+            let (_, resampler) = self.resampler.get_or_insert_with(|| {
+                (
+                    frame.sample_rate,
+                    Resampler::new(frame.sample_rate as usize, target_rate as usize),
+                )
+            });
+            let mut vad_frame = AudioFrame {
+                track_id: frame.track_id.clone(),
+                samples: Samples::PCM {
+                    samples: resampler.resample(samples),
+                },
+                timestamp: frame.timestamp,
+                sample_rate: self.option.samplerate,
+                channels: frame.channels,
+                ..Default::default()
+            };
+            return self.process_vad_frame(&mut vad_frame);
+        }
+        self.process_vad_frame(frame)
+    }
+
+    fn process_vad_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        let samples_cloned = match &frame.samples {
+            Samples::PCM { samples } => samples.to_owned(),
+            _ => return Ok(()),
+        };
         let results = self.vad.process(frame);
         for (is_speaking, timestamp) in results {
             if is_speaking || self.triggered {
@@ -287,6 +320,7 @@ impl VadProcessor {
             triggered: false,
             current_speech_start: None,
             temp_end: None,
+            resampler: None,
         };
         Ok(Self { inner })
     }
@@ -0,0 +1,350 @@
+use super::processor::Processor;
+use crate::media::{AudioFrame, Samples};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum WatermarkKind {
+    /// A short sine burst
+    #[default]
+    Tone,
+    /// A pseudo-random noise burst, found by correlating with the sequence of `seed`
+    SpreadSpectrum,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WatermarkOption {
+    pub enabled: Option<bool>,
+    pub kind: Option<WatermarkKind>,
+    /// Time from the start of one mark to the next, default 5000ms
+    pub interval_ms: Option<u32>,
+    /// Length of each mark, default 200ms
+    pub duration_ms: Option<u32>,
+    /// Peak level of the mark in dBFS, default -45
+    pub level_dbfs: Option<f32>,
+    /// Frequency of the tone, default 3000Hz, kept below the Nyquist frequency
+    pub frequency_hz: Option<f32>,
+    /// Seed of the spread-spectrum sequence, default 1
+    pub seed: Option<u32>,
+}
+
+impl WatermarkOption {
+    pub fn merge(&mut self, other: &WatermarkOption) {
+        if self.enabled.is_none() {
+            self.enabled = other.enabled;
+        }
+        if self.kind.is_none() {
+            self.kind = other.kind;
+        }
+        if self.interval_ms.is_none() {
+            self.interval_ms = other.interval_ms;
+        }
+        if self.duration_ms.is_none() {
+            self.duration_ms = other.duration_ms;
+        }
+        if self.level_dbfs.is_none() {
+            self.level_dbfs = other.level_dbfs;
+        }
+        if self.frequency_hz.is_none() {
+            self.frequency_hz = other.frequency_hz;
+        }
+        if self.seed.is_none() {
+            self.seed = other.seed;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+fn dbfs_to_amplitude(dbfs: f32) -> f32 {
+    32768.0 * 10f32.powf(dbfs / 20.0)
+}
+
+/// Fade in and out over 5ms at each end of a mark, so it doesn't click
+const FADE_MS: u64 = 5;
+
+/// Mixes a low-level mark into outgoing agent audio at a fixed cadence.
+/// Unlike ambiance the mark is not meant to be heard, only found again in
+/// recordings of the call. Marks fall on the call's clock, see `starting_at`,
+/// so the cadence holds across the tracks of one call.
+pub struct WatermarkProcessor {
+    kind: WatermarkKind,
+    interval_ms: u64,
+    duration_ms: u64,
+    amplitude: f32,
+    frequency_hz: f32,
+    seed: u32,
+    start_ms: u64,
+    position: Option<u64>,
+}
+
+impl WatermarkProcessor {
+    pub fn new(option: &WatermarkOption) -> Self {
+        let interval_ms = option.interval_ms.unwrap_or(5000).max(1) as u64;
+        Self {
+            kind: option.kind.unwrap_or_default(),
+            interval_ms,
+            duration_ms: (option.duration_ms.unwrap_or(200) as u64).min(interval_ms),
+            amplitude: dbfs_to_amplitude(option.level_dbfs.unwrap_or(-45.0).min(0.0)),
+            frequency_hz: option.frequency_hz.unwrap_or(3000.0),
+            seed: option.seed.unwrap_or(1),
+            start_ms: 0,
+            position: None,
+        }
+    }
+
+    /// Start this many milliseconds into the call, for a track replacing an
+    /// earlier one
+    pub fn starting_at(mut self, elapsed_ms: u64) -> Self {
+        self.start_ms = elapsed_ms;
+        self
+    }
+
+    /// The ±1 chip of the spread-spectrum sequence at `offset`, a hash of the
+    /// seed and offset so a track starting mid-mark still lines up
+    fn chip(&self, offset: u64) -> f32 {
+        let mut x = (((self.seed as u64) << 32) | offset).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        if x & 1 == 0 { 1.0 } else { -1.0 }
+    }
+
+    /// The mark's value `offset` samples into a mark of `length` samples
+    fn mark(&self, offset: u64, length: u64, sample_rate: u64) -> f32 {
+        let fade = (FADE_MS * sample_rate / 1000).min(length / 2).max(1);
+        let envelope = (offset.min(length - 1 - offset) as f32 / fade as f32).min(1.0);
+        let value = match self.kind {
+            WatermarkKind::Tone => {
+                let frequency = self.frequency_hz.min(sample_rate as f32 * 0.45);
+                let t = offset as f32 / sample_rate as f32;
+                (2.0 * std::f32::consts::PI * frequency * t).sin()
+            }
+            WatermarkKind::SpreadSpectrum => self.chip(offset),
+        };
+        value * envelope * self.amplitude
+    }
+}
+
+impl Processor for WatermarkProcessor {
+    fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        let samples = match &mut frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        if frame.sample_rate == 0 {
+            return Ok(());
+        }
+        let sample_rate = frame.sample_rate as u64;
+        let channels = frame.channels.max(1) as usize;
+        let interval = (self.interval_ms * sample_rate / 1000).max(1);
+        let length = self.duration_ms * sample_rate / 1000;
+        let mut position = self.position.unwrap_or(self.start_ms * sample_rate / 1000);
+
+        for slot in samples.chunks_mut(channels) {
+            let offset = position % interval;
+            position += 1;
+            if offset >= length {
+                continue;
+            }
+            let value = self.mark(offset, length, sample_rate);
+            for sample in slot.iter_mut() {
+                *sample = (*sample as f32 + value).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+        self.position = Some(position);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 16000;
+
+    /// 20ms frames of a vowel-like mix of harmonics, or silence
+    fn frames(seconds: usize, amplitude: f32) -> Vec<AudioFrame> {
+        (0..seconds * 50)
+            .map(|n| {
+                let samples = (0..320)
+                    .map(|i| {
+                        let t = (n * 320 + i) as f32 / RATE as f32;
+                        let voice: f32 = [220.0, 440.0, 660.0, 880.0]
+                            .iter()
+                            .map(|f| (2.0 * std::f32::consts::PI * f * t).sin())
+                            .sum();
+                        (amplitude * voice / 4.0) as i16
+                    })
+                    .collect();
+                AudioFrame {
+                    samples: Samples::PCM { samples },
+                    sample_rate: RATE as u32,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn process(option: &WatermarkOption, frames: &[AudioFrame]) -> Vec<i16> {
+        let mut watermark = WatermarkProcessor::new(option);
+        let mut output = Vec::new();
+        for frame in frames {
+            let mut frame = frame.clone();
+            watermark.process_frame(&mut frame).unwrap();
+            if let Samples::PCM { samples } = frame.samples {
+                output.extend(samples);
+            }
+        }
+        output
+    }
+
+    fn pcm(frames: &[AudioFrame]) -> Vec<i16> {
+        frames
+            .iter()
+            .flat_map(|frame| match &frame.samples {
+                Samples::PCM { samples } => samples.clone(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_tone_marks_at_interval() {
+        let option = WatermarkOption {
+            interval_ms: Some(1000),
+            duration_ms: Some(100),
+            ..Default::default()
+        };
+        let output = process(&option, &frames(3, 0.0));
+
+        let marked: Vec<usize> = output
+            .chunks(RATE / 100)
+            .enumerate()
+            .filter(|(_, chunk)| chunk.iter().any(|s| *s != 0))
+            .map(|(n, _)| n * 10)
+            .collect();
+        let expected: Vec<usize> = [0, 1000, 2000]
+            .iter()
+            .flat_map(|start| (*start..start + 100).step_by(10))
+            .collect();
+        assert_eq!(marked, expected);
+
+        let peak = output.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        let level = dbfs_to_amplitude(-45.0);
+        assert!((peak as f32) <= level + 1.0, "{} vs {}", peak, level);
+        assert!((peak as f32) > level * 0.9, "{} vs {}", peak, level);
+    }
+
+    /// First difference, which keeps the wideband mark and suppresses most of
+    /// the low-frequency voice
+    fn highpass(samples: &[i16]) -> Vec<f32> {
+        samples
+            .windows(2)
+            .map(|w| w[1] as f32 - w[0] as f32)
+            .collect()
+    }
+
+    /// Correlation of `signal` at `start` with `reference`, relative to a perfect match
+    fn correlate(signal: &[f32], start: usize, reference: &[f32]) -> f32 {
+        let sum: f32 = reference
+            .iter()
+            .zip(&signal[start..])
+            .map(|(r, s)| r * s)
+            .sum();
+        sum / reference.iter().map(|r| r * r).sum::<f32>()
+    }
+
+    #[test]
+    fn test_spread_spectrum_found_under_speech() {
+        let option = WatermarkOption {
+            kind: Some(WatermarkKind::SpreadSpectrum),
+            interval_ms: Some(1000),
+            duration_ms: Some(200),
+            seed: Some(42),
+            ..Default::default()
+        };
+        let speech = frames(3, 8000.0);
+        let input = pcm(&speech);
+        let output = highpass(&process(&option, &speech));
+        let reference = highpass(&process(&option, &frames(1, 0.0))[..RATE / 5]);
+
+        for second in 0..3 {
+            let found = correlate(&output, second * RATE, &reference);
+            assert!(found > 0.5, "mark at {}s: {}", second, found);
+            let between = correlate(&output, second * RATE + RATE / 2, &reference);
+            assert!(between.abs() < 0.2, "no mark at {}.5s: {}", second, between);
+        }
+
+        // A different seed doesn't match
+        let other = WatermarkOption {
+            seed: Some(7),
+            ..option.clone()
+        };
+        let other = highpass(&process(&other, &frames(1, 0.0)));
+        let cross = correlate(&other, 0, &reference);
+        assert!(cross.abs() < 0.2, "{}", cross);
+
+        // The mark stays well below the speech, so it doesn't get in the way
+        // of intelligibility
+        let output = process(&option, &speech);
+        let signal: Vec<f32> = input.iter().map(|s| *s as f32).collect();
+        let added: Vec<f32> = output
+            .iter()
+            .zip(&input)
+            .map(|(o, i)| *o as f32 - *i as f32)
+            .collect();
+        let snr_db = 20.0 * (rms(&signal) / rms(&added)).log10();
+        assert!(snr_db > 25.0, "{}dB", snr_db);
+    }
+
+    #[test]
+    fn test_later_track_keeps_the_cadence() {
+        let option = WatermarkOption {
+            kind: Some(WatermarkKind::SpreadSpectrum),
+            interval_ms: Some(1000),
+            ..Default::default()
+        };
+        let whole = process(&option, &frames(2, 0.0));
+
+        // A track replacing the first one 1.1s into the call, mid-mark
+        let mut watermark = WatermarkProcessor::new(&option).starting_at(1100);
+        let mut tail = Vec::new();
+        for mut frame in frames(1, 0.0).into_iter().take(15) {
+            watermark.process_frame(&mut frame).unwrap();
+            tail.extend(pcm(std::slice::from_ref(&frame)));
+        }
+        let start = RATE * 11 / 10;
+        assert_eq!(tail, whole[start..start + tail.len()]);
+        assert!(tail.iter().any(|s| *s != 0));
+    }
+
+    #[test]
+    fn test_interleaved_channels_get_the_same_mark() {
+        let mut watermark = WatermarkProcessor::new(&WatermarkOption::default());
+        let mut frame = AudioFrame {
+            samples: Samples::PCM {
+                samples: vec![0; 640],
+            },
+            sample_rate: RATE as u32,
+            channels: 2,
+            ..Default::default()
+        };
+        watermark.process_frame(&mut frame).unwrap();
+        let Samples::PCM { samples } = &frame.samples else {
+            unreachable!()
+        };
+        assert!(samples.iter().any(|s| *s != 0));
+        for pair in samples.chunks(2) {
+            assert_eq!(pair[0], pair[1]);
+        }
+    }
+}
@@ -1,6 +1,69 @@
+use crate::config::HttpClientConfig;
 use anyhow::Result;
 use get_if_addrs::get_if_addrs;
 use std::net::IpAddr;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+/// HTTP client shared by all outbound requests, see [`configure_http_client`]
+static HTTP_CLIENT: LazyLock<RwLock<reqwest::Client>> =
+    LazyLock::new(|| RwLock::new(reqwest::Client::new()));
+
+/// The shared HTTP client. Cloning is cheap and reuses its connection pool.
+pub fn http_client() -> reqwest::Client {
+    HTTP_CLIENT.read().unwrap().clone()
+}
+
+/// Configured `timeout_secs`, applied per request by [`with_request_timeout`]
+static HTTP_TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
+
+/// Replace the shared HTTP client with one built from `config`
+pub fn configure_http_client(config: &HttpClientConfig) -> Result<()> {
+    let client = build_http_client(config)?;
+    *HTTP_CLIENT.write().unwrap() = client;
+    *HTTP_TIMEOUT.write().unwrap() = config.timeout_secs.map(Duration::from_secs);
+    Ok(())
+}
+
+/// Apply the configured total timeout to a one-shot request. Streaming
+/// requests (LLM and TTS streams) skip it so long responses are not cut off.
+pub fn with_request_timeout(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match *HTTP_TIMEOUT.read().unwrap() {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
+
+pub fn build_http_client(config: &HttpClientConfig) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = &config.proxy {
+        let mut proxy = reqwest::Proxy::all(proxy)?;
+        if let Some(no_proxy) = &config.no_proxy {
+            proxy = proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+        }
+        builder = builder.proxy(proxy);
+    }
+    if let Some(secs) = config.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(path) = &config.ca_bundle {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("failed to read ca bundle {}: {}", path, e))?;
+        builder = builder.tls_certs_merge(reqwest::Certificate::from_pem_bundle(&pem)?);
+    }
+    if let Some(max) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(secs) = config.pool_idle_timeout_secs {
+        builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+    }
+    builder = match config.http2 {
+        Some(true) => builder.http2_prior_knowledge(),
+        Some(false) => builder.http1_only(),
+        None => builder,
+    };
+    Ok(builder.build()?)
+}
 
 pub fn get_first_non_loopback_interface() -> Result<IpAddr> {
     for i in get_if_addrs()? {
@@ -8,6 +8,10 @@ use async_trait::async_trait;
 pub trait DialogueHandler: Send + Sync {
     async fn on_start(&mut self) -> Result<Vec<Command>>;
     async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>>;
+    /// `skipped` events were lost because the handler fell behind
+    async fn on_lagged(&mut self, _skipped: u64) -> Result<Vec<Command>> {
+        Ok(vec![])
+    }
     async fn get_history(&self) -> Vec<ChatMessage>;
     async fn summarize(&mut self, prompt: &str) -> Result<String>;
 }
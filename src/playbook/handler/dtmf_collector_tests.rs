@@ -1,6 +1,7 @@
 use super::*;
 use crate::call::Command;
 use crate::event::SessionEvent;
+use crate::playbook::harness::{asr_final, dtmf, scored_asr_final, track_start};
 use anyhow::Result;
 use futures::Stream;
 use std::collections::HashMap;
@@ -63,6 +64,8 @@ fn create_phone_collector() -> super::super::DtmfCollectorConfig {
         }),
         retry_times: Some(3),
         interruptible: Some(false),
+        on_complete_url: None,
+        accept_speech: None,
     }
 }
 
@@ -78,6 +81,25 @@ fn create_code_collector() -> super::super::DtmfCollectorConfig {
         validation: None,
         retry_times: Some(2),
         interruptible: Some(false),
+        on_complete_url: None,
+        accept_speech: None,
+    }
+}
+
+fn create_account_collector() -> super::super::DtmfCollectorConfig {
+    super::super::DtmfCollectorConfig {
+        description: Some("4 to 8 digit account number".to_string()),
+        digits: None,
+        min_digits: Some(4),
+        max_digits: Some(8),
+        finish_key: Some("#".to_string()),
+        timeout: Some(30),
+        inter_digit_timeout: Some(5),
+        validation: None,
+        retry_times: Some(2),
+        interruptible: Some(false),
+        on_complete_url: None,
+        accept_speech: None,
     }
 }
 
@@ -237,6 +259,223 @@ async fn test_collector_auto_complete_at_max_digits() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_collector_variable_length_completes_on_finish_key() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("account".to_string(), create_account_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("account", "account_no");
+
+    for digit in "12345".chars() {
+        let commands = handler.handle_collector_digit(&digit.to_string()).await?;
+        assert!(commands.is_empty());
+    }
+    assert!(handler.is_collecting());
+
+    let commands = handler.handle_collector_digit("#").await?;
+    assert!(!handler.is_collecting());
+    assert!(!commands.is_empty());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'account_no': 12345]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_variable_length_retries_when_short() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("account".to_string(), create_account_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("account", "account_no");
+
+    for digit in "123#".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+
+    // Too short: the collector restarts and says why
+    assert!(handler.is_collecting());
+    let state = handler.collector_state.as_ref().unwrap();
+    assert_eq!(state.retry_count, 1);
+    assert_eq!(state.buffer, "");
+    assert!(
+        !handler
+            .history
+            .iter()
+            .any(|m| m.content.contains("completed"))
+    );
+
+    // The retry accepts a long enough entry
+    for digit in "1234#".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+    assert!(!handler.is_collecting());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'account_no': 1234]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_variable_length_auto_completes_at_max_digits() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("account".to_string(), create_account_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("account", "account_no");
+
+    for digit in "1234567".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+    assert!(handler.is_collecting());
+
+    // The 8th digit fills the collector, no finish key needed
+    let commands = handler.handle_collector_digit("8").await?;
+    assert!(!handler.is_collecting());
+    assert!(!commands.is_empty());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'account_no': 12345678]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_variable_length_rejects_digits_beyond_max() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("account".to_string(), create_account_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("account", "account_no");
+
+    // Nine keys in one burst: the collector completes at eight and the
+    // ninth is not part of the value
+    handler.on_event(&dtmf("123456789")).await?;
+
+    assert!(!handler.is_collecting());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'account_no': 12345678]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_fixed_length_ignores_digits_beyond_max() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("phone".to_string(), create_phone_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("phone", "user_phone");
+
+    // A fixed-length collector with a finish key waits for the key once full
+    for digit in "138001380009".chars() {
+        let commands = handler.handle_collector_digit(&digit.to_string()).await?;
+        assert!(commands.is_empty());
+    }
+    assert_eq!(
+        handler.collector_state.as_ref().unwrap().buffer,
+        "13800138000"
+    );
+
+    handler.handle_collector_digit("#").await?;
+    assert!(!handler.is_collecting());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'user_phone': 13800138000]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_replays_prompt_on_early_finish_key() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("account".to_string(), create_account_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    let mut buffer = String::from(
+        "<collect type=\"account\" var=\"account_no\" prompt=\"Enter your account number, then press pound\" />",
+    );
+    handler
+        .extract_streaming_commands(&mut buffer, "test-play-id", false)
+        .await;
+    assert!(handler.is_collecting());
+
+    for digit in "123".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+    let commands = handler.handle_collector_digit("#").await?;
+
+    // Too short: the prompt is spoken again and collection starts over
+    assert!(handler.is_collecting());
+    let state = handler.collector_state.as_ref().unwrap();
+    assert_eq!(state.buffer, "");
+    assert_eq!(state.retry_count, 1);
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(
+        &commands[0],
+        Command::Tts { text, .. } if text == "Enter your account number, then press pound"
+    ));
+
+    for digit in "12345#".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+    assert!(!handler.is_collecting());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'account_no': 12345]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_early_finish_key_fails_after_retries() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("account".to_string(), create_account_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("account", "account_no");
+    handler.collector_state.as_mut().unwrap().prompt = Some("Enter your account number".into());
+
+    // retry_times is 2: two replays, then the collection fails
+    for _ in 0..2 {
+        handler.handle_collector_digit("1").await?;
+        handler.handle_collector_digit("#").await?;
+        assert!(handler.is_collecting());
+    }
+    handler.handle_collector_digit("1").await?;
+    handler.handle_collector_digit("#").await?;
+
+    assert!(!handler.is_collecting());
+    assert!(handler.history.iter().any(|m| {
+        m.content
+            .starts_with("[DTMF collection failed for 'account_no'")
+    }));
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_collector_validation_success() -> Result<()> {
     let mut collectors = HashMap::new();
@@ -273,6 +512,50 @@ async fn test_collector_validation_success() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_collector_posts_value_to_webhook() -> Result<()> {
+    use axum::{Json, Router, routing::post};
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let app = Router::new().route(
+        "/collected",
+        post(move |Json(body): Json<serde_json::Value>| async move {
+            tx.send(body).ok();
+            "ok"
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/collected", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let mut phone = create_phone_collector();
+    phone.on_complete_url = Some(url);
+    let mut collectors = HashMap::new();
+    collectors.insert("phone".to_string(), phone);
+    let mut handler = create_test_handler(Some(collectors));
+
+    handler.start_collector("phone", "user_phone");
+    // A failed attempt is not reported
+    for digit in "123#".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+    for digit in "13812345678#".chars() {
+        handler.handle_collector_digit(&digit.to_string()).await?;
+    }
+    assert!(!handler.is_collecting());
+
+    let body = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+        .await?
+        .expect("webhook should be called");
+    assert_eq!(body["var_name"], "user_phone");
+    assert_eq!(body["value"], "13812345678");
+    assert!(body["call_id"].is_null());
+    assert!(rx.try_recv().is_err());
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_collector_validation_failure_with_retry() -> Result<()> {
     let mut collectors = HashMap::new();
@@ -438,6 +721,76 @@ async fn test_collector_on_event_dtmf_routing() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_collector_restarts_when_events_lost() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("code".to_string(), create_code_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("code", "verification_code");
+    handler.on_event(&dtmf("12")).await?;
+
+    // Keys may have been among the lost events, so the caller starts over
+    let commands = handler.on_lagged(3).await?;
+    assert!(matches!(commands.as_slice(), [Command::Tts { .. }]));
+    let state = handler.collector_state.as_ref().unwrap();
+    assert!(state.buffer.is_empty());
+    assert_eq!(state.retry_count, 0);
+
+    // Without a collection there is nothing to resync
+    handler.collector_state = None;
+    assert!(handler.on_lagged(3).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_dtmf_burst_keeps_order() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("code".to_string(), create_code_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("code", "verification_code");
+
+    // Six key presses delivered back to back, the middle two in one event
+    let mut commands = Vec::new();
+    for digit in ["9", "0", "12", "7", "3"] {
+        commands.extend(handler.on_event(&dtmf(digit)).await?);
+    }
+
+    assert!(!handler.is_collecting());
+    assert!(!commands.is_empty());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'verification_code': 901273]")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_burst_finish_key_in_same_event() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("phone".to_string(), create_phone_collector());
+
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("phone", "user_phone");
+
+    handler.on_event(&dtmf("13812345678#")).await?;
+
+    assert!(!handler.is_collecting());
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.content == "[DTMF collection completed for 'user_phone': 13812345678]")
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_collector_on_event_ignores_asr() -> Result<()> {
     let mut collectors = HashMap::new();
@@ -457,6 +810,7 @@ async fn test_collector_on_event_ignores_asr() -> Result<()> {
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -502,6 +856,60 @@ async fn test_collector_on_event_allows_hangup() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_collector_prompt_interrupted_by_digit() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert(
+        "code".to_string(),
+        super::super::DtmfCollectorConfig {
+            interruptible: Some(true),
+            ..create_code_collector()
+        },
+    );
+    let mut handler = create_test_handler(Some(collectors));
+    let event_sender = crate::event::create_event_sender();
+    let mut event_rx = event_sender.subscribe();
+    handler.set_event_sender(event_sender);
+    handler.start_collector("code", "verification_code");
+
+    // The prompt starts playing, then the caller presses a key over it
+    handler.on_event(&track_start()).await?;
+    let commands = handler.on_event(&dtmf("4")).await?;
+    assert!(matches!(commands.first(), Some(Command::Interrupt { .. })));
+    match event_rx.try_recv()? {
+        SessionEvent::CollectInterrupted { var_name, .. } => {
+            assert_eq!(var_name, "verification_code")
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+    // Collection goes on with the key that cut the prompt short
+    assert_eq!(handler.collector_state.as_ref().unwrap().buffer, "4");
+
+    // Only reported once per prompt
+    let commands = handler.on_event(&dtmf("2")).await?;
+    assert!(commands.is_empty());
+    assert!(event_rx.try_recv().is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_prompt_not_interrupted_when_not_interruptible() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("code".to_string(), create_code_collector());
+    let mut handler = create_test_handler(Some(collectors));
+    let event_sender = crate::event::create_event_sender();
+    let mut event_rx = event_sender.subscribe();
+    handler.set_event_sender(event_sender);
+    handler.start_collector("code", "verification_code");
+
+    handler.on_event(&track_start()).await?;
+    let commands = handler.on_event(&dtmf("4")).await?;
+    assert!(commands.is_empty());
+    assert!(event_rx.try_recv().is_err());
+    assert_eq!(handler.collector_state.as_ref().unwrap().buffer, "4");
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_extract_collect_command_from_stream() {
     let mut collectors = HashMap::new();
@@ -639,3 +1047,290 @@ dtmfCollectors:
     assert_eq!(code.digits, Some(6));
     assert_eq!(code.finish_key, None); // Not specified
 }
+
+fn tts_texts(commands: &[Command]) -> Vec<String> {
+    commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::Tts { text, .. } => Some(text.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_low_confidence_falls_back_to_dtmf_menu() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert(
+        "menu".to_string(),
+        super::super::DtmfCollectorConfig {
+            digits: Some(1),
+            ..Default::default()
+        },
+    );
+    let mut handler = create_test_handler(Some(collectors));
+    handler.set_low_confidence_fallback(Some(super::super::LowConfidenceFallbackConfig {
+        min_confidence: Some(0.6),
+        max_turns: Some(2),
+        prompt: "Press 1 for billing, 2 for support".to_string(),
+        collector: Some("menu".to_string()),
+        var_name: None,
+    }));
+
+    // Below the threshold, still answered normally
+    let commands = handler
+        .on_event(&scored_asr_final("bill", Some(0.3)))
+        .await?;
+    assert!(tts_texts(&commands).iter().any(|t| t.contains("Hello")));
+    assert!(!handler.is_collecting());
+
+    // Threshold reached: the menu is offered and digits are collected
+    let commands = handler
+        .on_event(&scored_asr_final("bil", Some(0.2)))
+        .await?;
+    assert_eq!(
+        tts_texts(&commands),
+        vec!["Press 1 for billing, 2 for support".to_string()]
+    );
+    assert!(handler.is_collecting());
+    assert_eq!(
+        handler.collector_state.as_ref().unwrap().var_name,
+        "menu_choice"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_confident_result_resets_low_confidence_count() -> Result<()> {
+    let mut handler = create_test_handler(None);
+    handler.set_low_confidence_fallback(Some(super::super::LowConfidenceFallbackConfig {
+        prompt: "Press 1 for billing".to_string(),
+        ..Default::default()
+    }));
+
+    handler
+        .on_event(&scored_asr_final("bill", Some(0.3)))
+        .await?;
+    // Confident and unscored results don't count as low
+    handler
+        .on_event(&scored_asr_final("billing", Some(0.9)))
+        .await?;
+    handler.on_event(&asr_final("billing")).await?;
+    let commands = handler
+        .on_event(&scored_asr_final("bil", Some(0.3)))
+        .await?;
+    assert!(tts_texts(&commands).iter().any(|t| t.contains("Hello")));
+    assert!(!handler.dtmf_to_llm);
+
+    // Without a collector, keys are routed to the LLM after the menu
+    let commands = handler
+        .on_event(&scored_asr_final("bil", Some(0.1)))
+        .await?;
+    assert_eq!(
+        tts_texts(&commands),
+        vec!["Press 1 for billing".to_string()]
+    );
+    assert!(handler.menu_keys_to_llm);
+    assert!(!handler.is_collecting());
+
+    // Only the key answering the menu goes to the LLM
+    let commands = handler.on_event(&dtmf("1")).await?;
+    assert!(tts_texts(&commands).iter().any(|t| t.contains("Hello")));
+    assert!(!handler.menu_keys_to_llm);
+    assert!(handler.on_event(&dtmf("2")).await?.is_empty());
+    assert!(!handler.dtmf_to_llm);
+    Ok(())
+}
+
+/// Start the `code` collector and let it time out with no key pressed
+async fn time_out_collector(handler: &mut LlmHandler) -> Result<Vec<Command>> {
+    handler.start_collector("code", "verification_code");
+    if let Some(state) = &mut handler.collector_state {
+        state.start_time = std::time::Instant::now() - std::time::Duration::from_secs(60);
+    }
+    handler.check_collector_timeout().await
+}
+
+#[tokio::test]
+async fn test_repeated_no_input_hangs_up() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("code".to_string(), create_code_collector());
+    let mut handler = create_test_handler(Some(collectors));
+    handler.set_no_input_hangup(Some(super::super::NoInputHangupConfig {
+        max_failures: Some(3),
+        prompt: None,
+    }));
+
+    for _ in 0..2 {
+        let commands = time_out_collector(&mut handler).await?;
+        assert!(!commands.iter().any(|c| matches!(c, Command::Hangup { .. })));
+    }
+
+    // A collection failing after its retries counts too
+    handler.start_collector("code", "verification_code");
+    if let Some(state) = &mut handler.collector_state {
+        state.retry_count = 2;
+        state.buffer = "123".to_string();
+        state.last_digit_time = std::time::Instant::now() - std::time::Duration::from_secs(10);
+    }
+    let commands = handler.check_collector_timeout().await?;
+    assert!(!handler.is_collecting());
+    match commands.as_slice() {
+        [
+            Command::Hangup {
+                reason, initiator, ..
+            },
+        ] => {
+            assert_eq!(reason.as_deref(), Some("Max no input reached"));
+            assert_eq!(initiator.as_deref(), Some("system"));
+        }
+        other => panic!("expected a hangup, got {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_input_hangup_speaks_prompt() -> Result<()> {
+    let mut collectors = HashMap::new();
+    collectors.insert("code".to_string(), create_code_collector());
+    let mut handler = create_test_handler(Some(collectors));
+    handler.set_no_input_hangup(Some(super::super::NoInputHangupConfig {
+        max_failures: Some(1),
+        prompt: Some("We didn't receive any input. Goodbye.".to_string()),
+    }));
+
+    let commands = time_out_collector(&mut handler).await?;
+    assert_eq!(
+        tts_texts(&commands),
+        vec!["We didn't receive any input. Goodbye.".to_string()]
+    );
+    assert!(matches!(
+        commands[0],
+        Command::Tts {
+            auto_hangup: Some(true),
+            ..
+        }
+    ));
+    Ok(())
+}
+
+fn create_spoken_phone_collector() -> super::super::DtmfCollectorConfig {
+    super::super::DtmfCollectorConfig {
+        finish_key: None,
+        accept_speech: Some(true),
+        ..create_phone_collector()
+    }
+}
+
+fn spoken_phone_handler() -> LlmHandler {
+    let mut collectors = HashMap::new();
+    collectors.insert("phone".to_string(), create_spoken_phone_collector());
+    let mut handler = create_test_handler(Some(collectors));
+    handler.start_collector("phone", "user_phone");
+    handler
+}
+
+fn collected(handler: &LlmHandler, value: &str) -> bool {
+    let expected = format!("[DTMF collection completed for 'user_phone': {}]", value);
+    handler
+        .history
+        .iter()
+        .any(|msg| msg.role == "system" && msg.content == expected)
+}
+
+#[tokio::test]
+async fn test_collector_accepts_spoken_english_digits() -> Result<()> {
+    let mut handler = spoken_phone_handler();
+
+    handler
+        .on_event(&asr_final(
+            "One three eight, one two three four, five six seven eight.",
+        ))
+        .await?;
+
+    assert!(!handler.is_collecting());
+    assert!(collected(&handler, "13812345678"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_accepts_spoken_chinese_digits() -> Result<()> {
+    let mut handler = spoken_phone_handler();
+
+    handler.on_event(&asr_final("我的手机号是幺三八")).await?;
+    assert_eq!(handler.collector_state.as_ref().unwrap().buffer, "138");
+    handler.on_event(&asr_final("一二三四，五六七八。")).await?;
+
+    assert!(!handler.is_collecting());
+    assert!(collected(&handler, "13812345678"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_mixes_speech_and_keypad() -> Result<()> {
+    let mut handler = spoken_phone_handler();
+
+    handler.on_event(&dtmf("1")).await?;
+    handler.on_event(&dtmf("3")).await?;
+    handler.on_event(&asr_final("eight double one")).await?;
+    // Speech without digits leaves the buffer alone
+    handler.on_event(&asr_final("hold on")).await?;
+    assert_eq!(handler.collector_state.as_ref().unwrap().buffer, "13811");
+    handler.on_event(&dtmf("2345")).await?;
+    handler.on_event(&asr_final("六六")).await?;
+
+    assert!(!handler.is_collecting());
+    assert!(collected(&handler, "13811234566"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collector_spoken_start_over_clears_buffer() -> Result<()> {
+    let mut handler = spoken_phone_handler();
+
+    handler.on_event(&dtmf("139")).await?;
+    handler.on_event(&asr_final("Sorry, start over")).await?;
+    assert!(handler.is_collecting());
+    assert_eq!(handler.collector_state.as_ref().unwrap().buffer, "");
+
+    // The new number may follow the request in the same utterance
+    handler
+        .on_event(&asr_final("重新输入，一三八一二三四"))
+        .await?;
+    assert_eq!(handler.collector_state.as_ref().unwrap().buffer, "1381234");
+    handler.on_event(&asr_final("five six seven eight")).await?;
+
+    assert!(!handler.is_collecting());
+    assert!(collected(&handler, "13812345678"));
+    Ok(())
+}
+
+#[test]
+fn test_spoken_digits() {
+    use super::spoken_digits::{after_clear_command, spoken_digits};
+
+    assert_eq!(spoken_digits("double five, triple oh"), "55000");
+    assert_eq!(spoken_digits("uno dos tres"), "123");
+    assert_eq!(spoken_digits("fünf null acht"), "508");
+    assert_eq!(spoken_digits("my number is 138-0013"), "1380013");
+    assert_eq!(spoken_digits("１３８"), "138");
+    assert_eq!(spoken_digits("nine pound"), "9#");
+    assert_eq!(spoken_digits("hello there"), "");
+    assert_eq!(spoken_digits("一三八"), "138");
+    assert_eq!(spoken_digits("五"), "5");
+    // Everyday words that happen to be keys
+    assert_eq!(spoken_digits("oh, sorry, five"), "5");
+    assert_eq!(spoken_digits("five oh two"), "502");
+    assert_eq!(spoken_digits("un moment"), "");
+    assert_eq!(spoken_digits("等一下，我看看"), "");
+    assert_eq!(spoken_digits("我要两个"), "");
+
+    assert_eq!(after_clear_command("Clear it"), Some(" it".to_string()));
+    assert_eq!(after_clear_command("one two"), None);
+    assert_eq!(after_clear_command("that's unclear"), None);
+    assert_eq!(after_clear_command("all cleared, one two"), None);
+    assert_eq!(
+        after_clear_command("请重新输入一三八"),
+        Some("一三八".to_string())
+    );
+}
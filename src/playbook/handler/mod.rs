@@ -9,7 +9,7 @@ use reqwest::Client;
 use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 #[cfg(test)]
 mod tests;
@@ -30,6 +30,8 @@ static RE_HTTP: Lazy<Regex> = Lazy::new(|| {
 static RE_COLLECT: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r#"<collect\s+type="([^"]+)"\s+var="([^"]+)"(?:\s+prompt="([^"]*)")?\s*/>"#).unwrap()
 });
+static RE_INJECTION_VAR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{(query|result|source|summary)\}").unwrap());
 static RE_SENTENCE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)[.!?。！？\n]\s*").unwrap());
 static FILLERS: Lazy<std::collections::HashSet<String>> = Lazy::new(|| {
     let mut s = std::collections::HashSet::new();
@@ -53,12 +55,15 @@ static FILLERS: Lazy<std::collections::HashSet<String>> = Lazy::new(|| {
 });
 
 use super::ChatMessage;
+use super::FollowUpExhausted;
+use super::GreetingMode;
 use super::InterruptionStrategy;
 use super::LlmConfig;
 use super::dialogue::DialogueHandler;
 
 pub mod provider;
 pub mod rag;
+pub mod spoken_digits;
 pub mod types;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -78,6 +83,10 @@ pub use rag::*;
 pub use types::*;
 
 const MAX_RAG_ATTEMPTS: usize = 3;
+/// Latest messages kept verbatim when the history is summarized
+const HISTORY_KEEP_RECENT: usize = 6;
+/// Separates the playbook prompt from the rolling summary in the system message
+const SUMMARY_MARKER: &str = "\n\n[Previous Context Summary]: ";
 
 /// Runtime state for an active DTMF digit collection session
 #[derive(Debug, Clone)]
@@ -96,6 +105,41 @@ pub struct CollectorState {
     pub last_digit_time: std::time::Instant,
     /// Number of retries attempted
     pub retry_count: u32,
+    /// Prompt spoken when the collector started, replayed when the finish key
+    /// comes before `min_digits`
+    pub prompt: Option<String>,
+}
+
+impl CollectorState {
+    /// Fewest digits the collection accepts, `digits` taking precedence
+    fn min_digits(&self) -> u32 {
+        self.config.digits.or(self.config.min_digits).unwrap_or(0)
+    }
+
+    /// Whether the buffer holds as many digits as the collector takes
+    fn is_full(&self) -> bool {
+        self.config
+            .digits
+            .or(self.config.max_digits)
+            .is_some_and(|max| self.buffer.len() >= max as usize)
+    }
+}
+
+/// Greeting for `language` from a `{lang: greeting}` map, matched exactly,
+/// then ignoring case, then by primary subtag (`es-MX` picks `es`)
+fn greeting_for_language(greetings: &HashMap<String, String>, language: &str) -> Option<String> {
+    let find = |key: &str| {
+        greetings
+            .iter()
+            .find(|(lang, _)| lang.eq_ignore_ascii_case(key))
+            .map(|(_, greeting)| greeting)
+    };
+    let primary = language.split(['-', '_']).next().unwrap_or(language);
+    greetings
+        .get(language)
+        .or_else(|| find(language))
+        .or_else(|| find(primary))
+        .cloned()
 }
 
 pub struct LlmHandler {
@@ -122,6 +166,37 @@ pub struct LlmHandler {
     sip_config: Option<crate::SipOption>,
     /// Active DTMF digit collector state (None when not collecting)
     collector_state: Option<CollectorState>,
+    use_interim_asr: bool,
+    /// Latest interim transcript of the current utterance
+    interim_text: Option<String>,
+    /// Interim transcript already answered on EOU, used to skip the duplicate final
+    interim_committed: Option<String>,
+    dtmf_to_llm: bool,
+    /// Language of the text normalization applied before TTS, if enabled
+    tts_normalization: Option<String>,
+    /// Language picking the greeting from `greetings`
+    greeting_language: Option<String>,
+    low_confidence_fallback: Option<super::LowConfidenceFallbackConfig>,
+    /// Consecutive ASR finals below the fallback's confidence threshold
+    low_confidence_turns: u32,
+    /// Keys go to the LLM until the low-confidence menu, offered without a
+    /// collector, is answered by a key or a confident result
+    menu_keys_to_llm: bool,
+    no_input_hangup: Option<super::NoInputHangupConfig>,
+    /// Collector timeouts and failed collections so far in the call
+    collector_failures: u32,
+    /// LLM requests, replies, tool calls and RAG lookups so far, when
+    /// `save_llm_trace` is on
+    llm_trace: Option<Vec<serde_json::Value>>,
+    /// History messages already in the trace, each request step only adds
+    /// the ones after them
+    traced_messages: usize,
+    /// Tokens used so far in the call, and in the current turn
+    usage: LlmUsage,
+    turn_usage: LlmUsage,
+    /// Caller speech over our playback that hasn't met the barge-in
+    /// thresholds yet: when it started and the most words heard
+    barge_in: Option<(std::time::Instant, usize)>,
 }
 
 impl LlmHandler {
@@ -135,9 +210,10 @@ impl LlmHandler {
         initial_scene_id: Option<String>,
         sip_config: Option<crate::SipOption>,
     ) -> Self {
+        let provider = create_llm_provider(&config);
         Self::with_provider(
             config,
-            Arc::new(DefaultLlmProvider::new()),
+            provider,
             Arc::new(NoopRagRetriever),
             interruption,
             global_follow_up_config,
@@ -162,13 +238,16 @@ impl LlmHandler {
         sip_config: Option<crate::SipOption>,
     ) -> Self {
         let mut history = Vec::new();
-        let system_prompt = Self::build_system_prompt(&config, None, dtmf_collectors.as_ref());
+        let system_prompt =
+            Self::build_system_prompt(&config, None, dtmf_collectors.as_ref(), None);
 
         history.push(ChatMessage {
             role: "system".to_string(),
             content: system_prompt,
         });
 
+        let llm_trace = (config.save_llm_trace == Some(true)).then(Vec::new);
+
         Self {
             config,
             interruption_config: interruption,
@@ -189,19 +268,51 @@ impl LlmHandler {
             call: None,
             scenes,
             current_scene_id: initial_scene_id,
-            client: Client::new(),
+            client: crate::net_tool::http_client(),
             sip_config,
             collector_state: None,
+            use_interim_asr: false,
+            interim_text: None,
+            interim_committed: None,
+            dtmf_to_llm: false,
+            tts_normalization: None,
+            greeting_language: None,
+            low_confidence_fallback: None,
+            low_confidence_turns: 0,
+            menu_keys_to_llm: false,
+            no_input_hangup: None,
+            collector_failures: 0,
+            llm_trace,
+            traced_messages: 0,
+            usage: LlmUsage::default(),
+            turn_usage: LlmUsage::default(),
+            barge_in: None,
         }
     }
 
+    /// Tokens used by the LLM requests of the call so far
+    pub fn usage(&self) -> LlmUsage {
+        self.usage
+    }
+
+    /// The system prompt, with the `{{ var }}` placeholders of the base
+    /// prompt rendered from `vars` when given
     fn build_system_prompt(
         config: &LlmConfig,
         scene_prompt: Option<&str>,
         dtmf_collectors: Option<&HashMap<String, super::DtmfCollectorConfig>>,
+        vars: Option<&HashMap<String, serde_json::Value>>,
     ) -> String {
         let base_prompt =
             scene_prompt.unwrap_or_else(|| config.prompt.as_deref().unwrap_or_default());
+        let base_prompt = match vars {
+            Some(vars) => super::render_prompt(
+                base_prompt,
+                vars,
+                config.blank_unknown_vars.unwrap_or(false),
+            ),
+            None => base_prompt.to_string(),
+        };
         let mut features_prompt = String::new();
 
         if let Some(features) = &config.features {
@@ -348,18 +459,15 @@ impl LlmHandler {
                 "DTMF collector overall timeout ({}s) for var={}",
                 timeout_secs, state.var_name
             );
-            let var_name = state.var_name.clone();
-            let buffer = state.buffer.clone();
-            let collector_type = state.collector_type.clone();
-            let config = state.config.clone();
-            let retry_count = state.retry_count;
-            self.collector_state = None;
-
-            if !buffer.is_empty() {
+            let state = self.collector_state.take().unwrap();
+            if !state.buffer.is_empty() {
                 // Try to validate what we have
-                return self
-                    .do_finish_collection(buffer, var_name, collector_type, config, retry_count)
-                    .await;
+                return self.do_finish_collection(state).await;
+            }
+            let var_name = state.var_name;
+
+            if let Some(commands) = self.check_no_input_hangup().await {
+                return Ok(commands);
             }
 
             // Nothing collected - notify LLM
@@ -381,41 +489,112 @@ impl LlmHandler {
                 "DTMF collector inter-digit timeout ({}s) for var={}, buffer={}",
                 inter_digit_timeout_secs, state.var_name, state.buffer
             );
-            let buffer = state.buffer.clone();
-            let var_name = state.var_name.clone();
-            let collector_type = state.collector_type.clone();
-            let config = state.config.clone();
-            let retry_count = state.retry_count;
-            self.collector_state = None;
-            return self
-                .do_finish_collection(buffer, var_name, collector_type, config, retry_count)
-                .await;
+            return self.finish_collector().await;
         }
 
         Ok(vec![])
     }
 
+    /// Apply the keys of a DTMF event received while collecting in arrival
+    /// order. An event may carry several keys when they were sent in a burst;
+    /// each one is handled on its own so none is lost or merged.
+    async fn handle_collector_input(&mut self, digits: &str) -> Result<Vec<Command>> {
+        let mut keys = digits.chars().filter(|c| !c.is_whitespace());
+        let mut commands = Vec::new();
+        while self.collector_state.is_some() {
+            let Some(key) = keys.next() else {
+                break;
+            };
+            commands.extend(self.handle_collector_digit(&key.to_string()).await?);
+        }
+        let discarded = keys.count();
+        if discarded > 0 {
+            info!(
+                "DTMF collector: discarding {} keys received after collection ended",
+                discarded
+            );
+        }
+        Ok(commands)
+    }
+
+    /// Start the collection over when events were lost, since the keys
+    /// collected so far may miss some. The caller is asked to enter them again
+    fn resync_collector(&mut self, skipped: u64) -> Option<Command> {
+        let state = self.collector_state.as_mut()?;
+        warn!(
+            "DTMF collector: {} events lost, restarting collection for var={}, dropping '{}'",
+            skipped, state.var_name, state.buffer
+        );
+        state.buffer.clear();
+        state.last_digit_time = std::time::Instant::now();
+        Some(self.create_tts_command(
+            "Some keys were missed, please enter them again.".to_string(),
+            None,
+            None,
+        ))
+    }
+
+    /// Apply the digits spoken in a transcript as key presses, after
+    /// emptying the buffer when the caller asks to start over. Speech
+    /// without digits is ignored
+    async fn handle_collector_speech(&mut self, text: &str) -> Result<Vec<Command>> {
+        let clear = spoken_digits::after_clear_command(text);
+        let digits = spoken_digits::spoken_digits(clear.as_deref().unwrap_or(text));
+        if clear.is_none() && digits.is_empty() {
+            debug!("DTMF collector: no digits in speech '{}'", text);
+            return Ok(vec![]);
+        }
+
+        let mut commands: Vec<Command> = self.interrupt_collector_prompt().into_iter().collect();
+        if let (Some(_), Some(state)) = (&clear, self.collector_state.as_mut()) {
+            info!(
+                "DTMF collector: caller asked to start over, dropping '{}'",
+                state.buffer
+            );
+            state.buffer.clear();
+            state.last_digit_time = std::time::Instant::now();
+        }
+        info!("DTMF collector: spoken digits '{}' from '{}'", digits, text);
+        commands.extend(self.handle_collector_input(&digits).await?);
+        Ok(commands)
+    }
+
     /// Handle a DTMF digit while in collector mode
     async fn handle_collector_digit(&mut self, digit: &str) -> Result<Vec<Command>> {
         let state = self.collector_state.as_mut().unwrap();
 
         // Check if it's the finish key
-        if let Some(ref finish_key) = state.config.finish_key.clone() {
-            if digit == finish_key {
-                info!("DTMF collector: finish key '{}' received", digit);
-                let buffer = state.buffer.clone();
-                let var_name = state.var_name.clone();
-                let collector_type = state.collector_type.clone();
-                let config = state.config.clone();
-                let retry_count = state.retry_count;
-                self.collector_state = None;
-                return self
-                    .do_finish_collection(buffer, var_name, collector_type, config, retry_count)
-                    .await;
-            }
-        }
-
-        // Append digit to buffer
+        if state.config.finish_key.as_deref() == Some(digit) {
+            info!("DTMF collector: finish key '{}' received", digit);
+            // Too early: ask again, as long as retries are left
+            let min = state.min_digits();
+            let too_early = (state.buffer.len() as u32) < min
+                && state.retry_count < state.config.retry_times.unwrap_or(3);
+            if let Some(prompt) = state.prompt.clone().filter(|_| too_early) {
+                info!(
+                    "DTMF collector: finish key after {} of {} digits, replaying the prompt",
+                    state.buffer.len(),
+                    min
+                );
+                let now = std::time::Instant::now();
+                state.buffer.clear();
+                state.retry_count += 1;
+                state.start_time = now;
+                state.last_digit_time = now;
+                return Ok(vec![self.create_tts_command(prompt, None, None)]);
+            }
+            return self.finish_collector().await;
+        }
+
+        // A full buffer only waits for the finish key, further digits are dropped
+        if state.is_full() {
+            info!(
+                "DTMF collector: rejecting digit '{}', buffer '{}' is full",
+                digit, state.buffer
+            );
+            return Ok(vec![]);
+        }
+
         state.buffer.push_str(digit);
         state.last_digit_time = std::time::Instant::now();
 
@@ -424,41 +603,40 @@ impl LlmHandler {
             digit, state.buffer
         );
 
-        // Check if we've reached the required digit count
-        let effective_max = state.config.digits.or(state.config.max_digits);
-
-        if let Some(max) = effective_max {
-            if state.buffer.len() >= max as usize {
-                // If no finish_key is configured, auto-complete at max digits
-                if state.config.finish_key.is_none() {
-                    info!("DTMF collector: reached max digits ({})", max);
-                    let buffer = state.buffer.clone();
-                    let var_name = state.var_name.clone();
-                    let collector_type = state.collector_type.clone();
-                    let config = state.config.clone();
-                    let retry_count = state.retry_count;
-                    self.collector_state = None;
-                    return self
-                        .do_finish_collection(buffer, var_name, collector_type, config, retry_count)
-                        .await;
-                }
-            }
+        // A fixed `digits` collector with a finish key waits for the key, a
+        // variable-length (`min_digits`/`max_digits`) one completes as soon
+        // as it is full
+        let waits_for_key = state.config.digits.is_some() && state.config.finish_key.is_some();
+        if state.is_full() && !waits_for_key {
+            info!("DTMF collector: buffer '{}' is full", state.buffer);
+            return self.finish_collector().await;
         }
 
         Ok(vec![])
     }
 
-    /// Internal: finish collection with full state available
-    async fn do_finish_collection(
-        &mut self,
-        buffer: String,
-        var_name: String,
-        collector_type: String,
-        config: super::DtmfCollectorConfig,
-        retry_count: u32,
-    ) -> Result<Vec<Command>> {
+    /// End the active collection and validate what was collected
+    async fn finish_collector(&mut self) -> Result<Vec<Command>> {
+        match self.collector_state.take() {
+            Some(state) => self.do_finish_collection(state).await,
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Internal: validate the collected digits, then store them or retry
+    async fn do_finish_collection(&mut self, state: CollectorState) -> Result<Vec<Command>> {
+        let min = state.min_digits();
+        let CollectorState {
+            collector_type,
+            var_name,
+            config,
+            buffer,
+            retry_count,
+            prompt,
+            ..
+        } = state;
+
         // Validate min digits
-        let min = config.digits.or(config.min_digits).unwrap_or(0);
         if min > 0 && (buffer.len() as u32) < min {
             return self
                 .retry_or_fail(
@@ -466,6 +644,7 @@ impl LlmHandler {
                     config,
                     retry_count,
                     var_name,
+                    prompt,
                     &format!("Expected at least {} digits, got {}", min, buffer.len()),
                 )
                 .await;
@@ -480,7 +659,7 @@ impl LlmHandler {
                         .clone()
                         .unwrap_or_else(|| "Input format is incorrect".to_string());
                     return self
-                        .retry_or_fail(collector_type, config, retry_count, var_name, &msg)
+                        .retry_or_fail(collector_type, config, retry_count, var_name, prompt, &msg)
                         .await;
                 }
             }
@@ -498,6 +677,9 @@ impl LlmHandler {
             extras.insert(var_name.clone(), serde_json::Value::String(buffer.clone()));
             state.extras = Some(extras);
         }
+        if let Some(url) = &config.on_complete_url {
+            self.notify_collection_complete(url, &var_name, &buffer);
+        }
 
         // Notify LLM of the result
         self.history.push(ChatMessage {
@@ -509,6 +691,28 @@ impl LlmHandler {
         self.generate_response().await
     }
 
+    /// POST a collected value to the collector's `on_complete_url` without
+    /// holding up the conversation
+    fn notify_collection_complete(&self, url: &str, var_name: &str, value: &str) {
+        let call_id = self.call.as_ref().map(|call| call.session_id.clone());
+        let body = json!({
+            "var_name": var_name,
+            "value": value,
+            "call_id": call_id,
+        });
+        let request = self.client.post(url).json(&body);
+        let url = url.to_string();
+        crate::spawn(async move {
+            match request.send().await {
+                Ok(res) if !res.status().is_success() => {
+                    warn!("DTMF collector webhook {} returned {}", url, res.status());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("DTMF collector webhook {} failed: {}", url, e),
+            }
+        });
+    }
+
     /// Retry collection or fail after max retries
     async fn retry_or_fail(
         &mut self,
@@ -516,6 +720,7 @@ impl LlmHandler {
         config: super::DtmfCollectorConfig,
         retry_count: u32,
         var_name: String,
+        prompt: Option<String>,
         reason: &str,
     ) -> Result<Vec<Command>> {
         let max_retries = config.retry_times.unwrap_or(3);
@@ -525,6 +730,9 @@ impl LlmHandler {
                 "DTMF collector: max retries ({}) reached for var '{}'",
                 max_retries, var_name
             );
+            if let Some(commands) = self.check_no_input_hangup().await {
+                return Ok(commands);
+            }
             self.history.push(ChatMessage {
                 role: "system".to_string(),
                 content: format!(
@@ -553,6 +761,7 @@ impl LlmHandler {
             start_time: now,
             last_digit_time: now,
             retry_count: retry_count + 1,
+            prompt,
         });
 
         // Play error message
@@ -565,6 +774,35 @@ impl LlmHandler {
         Ok(vec![self.create_tts_command(error_msg, None, None)])
     }
 
+    /// Count a collector that timed out without input or failed for good, and
+    /// end the call once `noInputHangup.maxFailures` is reached
+    async fn check_no_input_hangup(&mut self) -> Option<Vec<Command>> {
+        let config = self.no_input_hangup.clone()?;
+        self.collector_failures += 1;
+        let max_failures = config.max_failures.unwrap_or(3);
+        if self.collector_failures < max_failures {
+            return None;
+        }
+        info!(
+            "DTMF collectors failed {} times in the call, hanging up",
+            self.collector_failures
+        );
+        self.is_hanging_up = true;
+        if let Some(prompt) = config.prompt {
+            self.history.push(ChatMessage {
+                role: "assistant".to_string(),
+                content: prompt.clone(),
+            });
+            return Some(vec![self.create_tts_command(prompt, None, Some(true))]);
+        }
+        let headers = self.render_sip_headers().await;
+        Some(vec![Command::Hangup {
+            reason: Some("Max no input reached".to_string()),
+            initiator: Some("system".to_string()),
+            headers,
+        }])
+    }
+
     /// Start a DTMF collector from an LLM-generated <collect> command
     fn start_collector(&mut self, collector_type: &str, var_name: &str) -> bool {
         let config = match &self.dtmf_collectors {
@@ -590,6 +828,7 @@ impl LlmHandler {
             start_time: now,
             last_digit_time: now,
             retry_count: 0,
+            prompt: None,
         });
 
         info!(
@@ -599,6 +838,33 @@ impl LlmHandler {
         true
     }
 
+    /// Stop the prompt playing over an interruptible collector when the
+    /// caller answers early, emitting `CollectInterrupted` for it
+    fn interrupt_collector_prompt(&mut self) -> Option<Command> {
+        let state = self
+            .collector_state
+            .as_ref()
+            .filter(|s| s.config.interruptible.unwrap_or(false))?;
+        if !self.is_speaking {
+            return None;
+        }
+        info!(
+            "DTMF collector: prompt interrupted by input for var={}",
+            state.var_name
+        );
+        if let Some(sender) = &self.event_sender {
+            let _ = sender.send(SessionEvent::CollectInterrupted {
+                timestamp: crate::media::get_timestamp(),
+                var_name: state.var_name.clone(),
+            });
+        }
+        self.stop_speaking();
+        Some(Command::Interrupt {
+            graceful: Some(true),
+            fade_out_ms: self.interruption_config.volume_fade_ms,
+        })
+    }
+
     /// Returns true if currently in DTMF digit collection mode
     pub fn is_collecting(&self) -> bool {
         self.collector_state.is_some()
@@ -650,13 +916,51 @@ impl LlmHandler {
         }
     }
 
-    /// Get current extras (variables) from call_state for dynamic template rendering.
+    /// Get current extras (variables) from call_state for dynamic template rendering,
+    /// along with the call's caller, callee and call_id unless extras set them.
     async fn get_current_extras(&self) -> HashMap<String, serde_json::Value> {
-        if let Some(call) = &self.call {
-            let state = call.call_state.read().await;
-            state.extras.clone().unwrap_or_default()
-        } else {
-            HashMap::new()
+        let Some(call) = &self.call else {
+            return HashMap::new();
+        };
+        let state = call.call_state.read().await;
+        let mut vars = state.extras.clone().unwrap_or_default();
+        if let Some(option) = &state.option {
+            for (key, value) in [
+                (super::BUILTIN_CALLER, &option.caller),
+                (super::BUILTIN_CALLEE, &option.callee),
+            ] {
+                if let Some(value) = value {
+                    vars.entry(key.to_string())
+                        .or_insert_with(|| serde_json::Value::String(value.clone()));
+                }
+            }
+        }
+        vars.entry(super::BUILTIN_CALL_ID.to_string())
+            .or_insert_with(|| serde_json::Value::String(call.session_id.clone()));
+        vars
+    }
+
+    /// Render the call variables into the system prompt, once the call is known
+    async fn render_system_prompt(&mut self) {
+        if self.call.is_none() {
+            return;
+        }
+        let vars = self.get_current_extras().await;
+        let scene_prompt = self
+            .current_scene_id
+            .as_ref()
+            .and_then(|id| self.scenes.get(id))
+            .and_then(|scene| scene.raw_prompt.clone());
+        let system_prompt = Self::build_system_prompt(
+            &self.config,
+            scene_prompt.as_deref(),
+            self.dtmf_collectors.as_ref(),
+            Some(&vars),
+        );
+        if let Some(first_msg) = self.history.get_mut(0) {
+            if first_msg.role == "system" {
+                first_msg.content = system_prompt;
+            }
         }
     }
 
@@ -681,6 +985,7 @@ impl LlmHandler {
                 &self.config,
                 Some(&rendered_prompt),
                 self.dtmf_collectors.as_ref(),
+                None,
             );
             if let Some(first_msg) = self.history.get_mut(0) {
                 if first_msg.role == "system" {
@@ -717,13 +1022,70 @@ impl LlmHandler {
         self.current_scene_id.clone()
     }
 
-    pub fn set_call(&mut self, call: crate::call::ActiveCallRef) {
+    pub fn set_use_interim_asr(&mut self, enabled: bool) {
+        self.use_interim_asr = enabled;
+    }
+
+    pub fn set_dtmf_to_llm(&mut self, enabled: bool) {
+        self.dtmf_to_llm = enabled;
+    }
+
+    pub fn set_low_confidence_fallback(
+        &mut self,
+        fallback: Option<super::LowConfidenceFallbackConfig>,
+    ) {
+        self.low_confidence_fallback = fallback;
+        self.low_confidence_turns = 0;
+        self.menu_keys_to_llm = false;
+    }
+
+    pub fn set_no_input_hangup(&mut self, config: Option<super::NoInputHangupConfig>) {
+        self.no_input_hangup = config;
+        self.collector_failures = 0;
+    }
+
+    /// Normalize numbers, times and currency in `language` before synthesis
+    pub fn set_text_normalization(&mut self, language: Option<String>) {
+        self.tts_normalization = language;
+    }
+
+    fn normalize_for_tts(&self, text: String) -> String {
+        match &self.tts_normalization {
+            Some(language) => crate::synthesis::normalize::normalize_text(&text, language),
+            None => text,
+        }
+    }
+
+    pub async fn set_call(&mut self, call: crate::call::ActiveCallRef) {
+        let var = self
+            .config
+            .greeting_language_var
+            .as_deref()
+            .unwrap_or("lang");
+        let language = {
+            let state = call.call_state.read().await;
+            state
+                .extras
+                .as_ref()
+                .and_then(|extras| extras.get(var))
+                .and_then(|value| value.as_str())
+                .map(|s| s.to_string())
+        };
+        if language.is_some() {
+            self.greeting_language = language;
+        }
         self.call = Some(call);
     }
 
+    /// Language used to pick the greeting from `greetings`, normally resolved
+    /// from the call variables by `set_call`
+    pub fn set_greeting_language(&mut self, language: Option<String>) {
+        self.greeting_language = language;
+    }
+
     pub fn set_event_sender(&mut self, sender: crate::event::EventSender) {
         self.event_sender = Some(sender.clone());
-        if let Some(greeting) = &self.config.greeting {
+        if let Some(greeting) = self.static_greeting() {
             let _ = sender.send(crate::event::SessionEvent::AddHistory {
                 sender: Some("system".to_string()),
                 timestamp: crate::media::get_timestamp(),
@@ -733,9 +1095,42 @@ impl LlmHandler {
         }
     }
 
-    fn send_debug_event(&self, key: &str, data: serde_json::Value) {
+    /// The configured greeting for the call's language, else the default one,
+    /// unless the opening turn is left to the LLM
+    fn static_greeting(&self) -> Option<String> {
+        if self.config.greeting_mode == Some(GreetingMode::Llm) {
+            return None;
+        }
+        self.greeting_language
+            .as_deref()
+            .zip(self.config.greetings.as_ref())
+            .and_then(|(language, greetings)| greeting_for_language(greetings, language))
+            .or_else(|| self.config.greeting.clone())
+    }
+
+    /// Add a step to the trace when `save_llm_trace` is on. A request step
+    /// carries the history messages added since the previous one
+    fn trace_step(&mut self, key: &str, timestamp: u64, data: &serde_json::Value) {
+        let Some(trace) = &mut self.llm_trace else {
+            return;
+        };
+        let mut step = json!({
+            "step": key,
+            "timestamp": timestamp,
+            "data": data,
+        });
+        if key == "llm_call_start" {
+            let start = self.traced_messages.min(self.history.len());
+            step["messages"] = json!(self.history[start..]);
+            self.traced_messages = self.history.len();
+        }
+        trace.push(step);
+    }
+
+    fn send_debug_event(&mut self, key: &str, data: serde_json::Value) {
+        let timestamp = crate::media::get_timestamp();
+        self.trace_step(key, timestamp, &data);
         if let Some(sender) = &self.event_sender {
-            let timestamp = crate::media::get_timestamp();
             if key == "llm_response" {
                 if let Some(text) = data.get("response").and_then(|v| v.as_str()) {
                     let _ = sender.send(crate::event::SessionEvent::AddHistory {
@@ -757,8 +1152,93 @@ impl LlmHandler {
         }
     }
 
-    async fn call_llm(&self) -> Result<String> {
-        self.provider.call(&self.config, &self.history).await
+    /// Non-streaming request, traced but not reported as debug events
+    async fn call_llm(&mut self) -> Result<String> {
+        let start_time = crate::media::get_timestamp();
+        self.trace_step(
+            "llm_call_start",
+            start_time,
+            &json!({ "history_length": self.history.len() }),
+        );
+        let (response, usage) = self
+            .provider
+            .call_with_usage(&self.config, &self.history)
+            .await?;
+        self.add_usage(usage.unwrap_or_else(|| LlmUsage::estimate(&self.history, &response)));
+        let end_time = crate::media::get_timestamp();
+        self.trace_step(
+            "llm_response",
+            end_time,
+            &json!({
+                "response": response,
+                "duration": end_time - start_time,
+            }),
+        );
+        Ok(response)
+    }
+
+    /// Send `history` to the provider, counting the tokens used
+    /// Plain text completion of `history`, for summaries. Sent without JSON
+    /// mode or native tools even when the conversation uses them
+    async fn complete(&mut self, history: &[ChatMessage]) -> Result<String> {
+        let config = LlmConfig {
+            json_mode: None,
+            function_calling: None,
+            ..self.config.clone()
+        };
+        let (response, usage) = self.provider.call_with_usage(&config, history).await?;
+        self.add_usage(usage.unwrap_or_else(|| LlmUsage::estimate(history, &response)));
+        Ok(response)
+    }
+
+    fn add_usage(&mut self, usage: LlmUsage) {
+        self.usage.add(usage);
+        self.turn_usage.add(usage);
+    }
+
+    /// Emit an `llm_usage` metrics event with the call's running total, and
+    /// the current turn's usage unless it is the final one
+    fn send_usage_event(&mut self, is_final: bool) {
+        let mut data = json!({ "total": self.usage.to_json() });
+        if is_final {
+            data["final"] = json!(true);
+        } else {
+            data["turn"] = self.turn_usage.to_json();
+        }
+        self.turn_usage = LlmUsage::default();
+        if let Some(sender) = &self.event_sender {
+            let _ = sender.send(crate::event::SessionEvent::Metrics {
+                timestamp: crate::media::get_timestamp(),
+                key: "llm_usage".to_string(),
+                duration: 0,
+                data,
+            });
+        }
+    }
+
+    /// Store the call's token totals in the call extras for the call record
+    async fn save_usage(&self) {
+        let Some(call) = &self.call else {
+            return;
+        };
+        let mut state = call.call_state.write().await;
+        state
+            .extras
+            .get_or_insert_with(HashMap::new)
+            .insert("llm_usage".to_string(), self.usage.to_json());
+    }
+
+    /// Store the trace in the call extras at hangup, so it is saved with the
+    /// call record
+    async fn save_llm_trace(&self) {
+        let (Some(trace), Some(call)) = (&self.llm_trace, &self.call) else {
+            return;
+        };
+        let mut state = call.call_state.write().await;
+        state
+            .extras
+            .get_or_insert_with(HashMap::new)
+            .insert("llm_trace".to_string(), json!(trace));
     }
 
     fn create_tts_command(
@@ -783,7 +1263,7 @@ impl LlmHandler {
         }
 
         Command::Tts {
-            text,
+            text: self.normalize_for_tts(text),
             speaker: None,
             play_id: Some(play_id),
             auto_hangup,
@@ -797,6 +1277,14 @@ impl LlmHandler {
     }
 
     async fn generate_response(&mut self) -> Result<Vec<Command>> {
+        let result = self.stream_response().await;
+        self.send_usage_event(false);
+        self.save_usage().await;
+        result
+    }
+
+    async fn stream_response(&mut self) -> Result<Vec<Command>> {
+        self.apply_rolling_summary().await;
         let start_time = crate::media::get_timestamp();
         let play_id = uuid::Uuid::new_v4().to_string();
 
@@ -821,6 +1309,10 @@ impl LlmHandler {
         let mut is_json_mode = false;
         let mut checked_json_mode = false;
         let mut first_token_time = None;
+        let mut reported_usage = None;
+        let mut tool_calls = Vec::new();
+        let mut dispatched = false;
+        let pipelining = self.config.sentence_pipelining.unwrap_or(true);
 
         while let Some(chunk_result) = stream.next().await {
             let event = match chunk_result {
@@ -835,6 +1327,12 @@ impl LlmHandler {
                 LlmStreamEvent::Reasoning(text) => {
                     full_reasoning.push_str(&text);
                 }
+                LlmStreamEvent::Usage(usage) => {
+                    reported_usage = Some(usage);
+                }
+                LlmStreamEvent::ToolCalls(tools) => {
+                    tool_calls.extend(tools);
+                }
                 LlmStreamEvent::Content(chunk) => {
                     if first_token_time.is_none() && !chunk.trim().is_empty() {
                         first_token_time = Some(crate::media::get_timestamp());
@@ -853,10 +1351,12 @@ impl LlmHandler {
                         }
                     }
 
-                    if checked_json_mode && !is_json_mode {
+                    // Dispatch every completed sentence while the model keeps generating
+                    if pipelining && checked_json_mode && !is_json_mode {
                         let extracted = self
                             .extract_streaming_commands(&mut buffer, &play_id, false)
                             .await;
+                        dispatched |= !extracted.is_empty();
                         for cmd in extracted {
                             if let Some(call) = &self.call {
                                 let _ = call.enqueue_command(cmd).await;
@@ -869,6 +1369,10 @@ impl LlmHandler {
             }
         }
 
+        self.add_usage(
+            reported_usage.unwrap_or_else(|| LlmUsage::estimate(&self.history, &full_content)),
+        );
+
         // Send debug event - LLM response received
         let end_time = crate::media::get_timestamp();
         self.send_debug_event(
@@ -880,33 +1384,64 @@ impl LlmHandler {
                 "duration": end_time - start_time,
                 "ttfb": first_token_time.map(|t| t - start_time).unwrap_or(0),
                 "playId": play_id,
+                "tool_calls": tool_calls,
             }),
         );
 
         if is_json_mode {
-            self.interpret_response(full_content).await
-        } else {
-            let extracted = self
-                .extract_streaming_commands(&mut buffer, &play_id, true)
-                .await;
-            for cmd in extracted {
-                if let Some(call) = &self.call {
-                    let _ = call.enqueue_command(cmd).await;
-                } else {
-                    commands.push(cmd);
+            let raw = match parse_structured_response(&full_content) {
+                Some(structured) if !tool_calls.is_empty() => {
+                    tool_calls.extend(structured.tools.unwrap_or_default());
+                    tool_call_reply(structured.text.as_deref(), &tool_calls)
                 }
+                _ => full_content,
+            };
+            return self.interpret_response(raw).await;
+        }
+        if !tool_calls.is_empty() && !dispatched {
+            // Nothing was spoken yet, the text goes with the tools like a
+            // structured reply so a hangup waits for it
+            let text = (!full_content.trim().is_empty()).then_some(full_content);
+            return self
+                .interpret_response(tool_call_reply(text.as_deref(), &tool_calls))
+                .await;
+        }
+
+        // Part of the reply is already playing, a hangup ends the call once it
+        // is finished, like a `<hangup/>` written in the text
+        if tool_calls
+            .iter()
+            .any(|tool| matches!(tool, ToolInvocation::Hangup { .. }))
+        {
+            buffer.push_str("<hangup/>");
+            tool_calls.retain(|tool| !matches!(tool, ToolInvocation::Hangup { .. }));
+        }
+        let extracted = self
+            .extract_streaming_commands(&mut buffer, &play_id, true)
+            .await;
+        for cmd in extracted {
+            if let Some(call) = &self.call {
+                let _ = call.enqueue_command(cmd).await;
+            } else {
+                commands.push(cmd);
             }
-            if !full_content.trim().is_empty() {
-                self.history.push(ChatMessage {
-                    role: "assistant".to_string(),
-                    content: full_content,
-                });
-                self.last_robot_msg_at = Some(std::time::Instant::now());
-                self.is_speaking = true;
-                self.last_tts_start_at = Some(std::time::Instant::now());
-            }
-            Ok(commands)
         }
+        if !full_content.trim().is_empty() {
+            self.history.push(ChatMessage {
+                role: "assistant".to_string(),
+                content: full_content,
+            });
+            self.last_robot_msg_at = Some(std::time::Instant::now());
+            self.is_speaking = true;
+            self.last_tts_start_at = Some(std::time::Instant::now());
+        }
+        if !tool_calls.is_empty() {
+            commands.extend(
+                self.interpret_response(tool_call_reply(None, &tool_calls))
+                    .await?,
+            );
+        }
+        Ok(commands)
     }
 
     async fn extract_streaming_commands(
@@ -926,7 +1461,7 @@ impl LlmHandler {
             let set_var_pos = RE_SET_VAR.captures(buffer);
             let http_pos = RE_HTTP.captures(buffer);
             let collect_pos = RE_COLLECT.captures(buffer);
-            let sentence_pos = RE_SENTENCE.find(buffer);
+            let sentence_pos = find_sentence_end(buffer);
 
             // Find the first occurrence
             let mut positions: Vec<(usize, CommandKind)> = Vec::new();
@@ -1072,11 +1607,30 @@ impl LlmHandler {
                                 None,
                             ));
                         }
-                        commands.push(Command::Refer {
+                        let refer = ToolInvocation::Refer {
                             caller: String::new(),
-                            callee,
+                            callee: callee.clone(),
                             options: None,
-                        });
+                        };
+                        match self.validate_tool_invocation(&refer) {
+                            Some(problem) => {
+                                warn!("Rejected tool invocation: {}", problem);
+                                self.send_debug_event(
+                                    "tool_rejected",
+                                    json!({ "reason": problem }),
+                                );
+                                // The model learns why on its next turn
+                                self.history.push(ChatMessage {
+                                    role: "system".to_string(),
+                                    content: format!("Tool call rejected: {}", problem),
+                                });
+                            }
+                            None => commands.push(Command::Refer {
+                                caller: String::new(),
+                                callee,
+                                options: None,
+                            }),
+                        }
                         buffer.drain(..mat.end());
                     }
                     CommandKind::Play => {
@@ -1126,6 +1680,7 @@ impl LlmHandler {
                                 &self.config,
                                 Some(&rendered_prompt),
                                 self.dtmf_collectors.as_ref(),
+                                None,
                             );
                             if let Some(first_msg) = self.history.get_mut(0) {
                                 if first_msg.role == "system" {
@@ -1156,14 +1711,17 @@ impl LlmHandler {
                         }
 
                         // Play the collector prompt if provided
-                        if let Some(p) = prompt {
-                            if !p.trim().is_empty() {
-                                commands.push(self.create_tts_command(p, None, None));
-                            }
+                        let prompt = prompt.filter(|p| !p.trim().is_empty());
+                        if let Some(p) = prompt.clone() {
+                            commands.push(self.create_tts_command(p, None, None));
                         }
 
                         // Start the collector
-                        if !self.start_collector(&collector_type, &var_name) {
+                        if self.start_collector(&collector_type, &var_name) {
+                            if let Some(state) = self.collector_state.as_mut() {
+                                state.prompt = prompt;
+                            }
+                        } else {
                             // Collector type not found, notify LLM
                             self.history.push(ChatMessage {
                                 role: "system".to_string(),
@@ -1276,7 +1834,7 @@ impl LlmHandler {
         auto_hangup: Option<bool>,
     ) -> Command {
         Command::Tts {
-            text,
+            text: self.normalize_for_tts(text),
             speaker: None,
             play_id: Some(play_id),
             auto_hangup,
@@ -1312,9 +1870,11 @@ impl LlmHandler {
 
                 let headers = self.render_sip_headers().await;
 
+                // The model may name whoever asked to end the call, but the
+                // hangup itself is always the agent's
                 tool_commands.push(Command::Hangup {
                     reason: reason.clone(),
-                    initiator: initiator.clone(),
+                    initiator: Some("ai".to_string()),
                     headers,
                 });
                 Ok(false)
@@ -1386,6 +1946,36 @@ impl LlmHandler {
         }
     }
 
+    /// Check tool arguments before they become commands, returning why the
+    /// invocation is rejected
+    fn validate_tool_invocation(&self, tool: &ToolInvocation) -> Option<String> {
+        match tool {
+            ToolInvocation::Refer { caller, callee, .. } => {
+                let max_len = self.config.max_tool_uri_length.unwrap_or(256);
+                if caller.is_empty() {
+                    validate_sip_uri("callee", callee, max_len).err()
+                } else {
+                    validate_sip_uri("caller", caller, max_len)
+                        .and_then(|_| validate_sip_uri("callee", callee, max_len))
+                        .err()
+                }
+            }
+            ToolInvocation::Hangup {
+                reason: Some(reason),
+                ..
+            } => {
+                let max_len = self.config.max_hangup_reason_length.unwrap_or(128);
+                (reason.chars().count() > max_len).then(|| {
+                    format!(
+                        "hangup reason is longer than {} characters, use a short reason",
+                        max_len
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+
     async fn render_sip_headers(&self) -> Option<HashMap<String, String>> {
         let hangup_template = self.sip_config.as_ref()?.hangup_headers.as_ref()?;
         let call = self.call.as_ref()?;
@@ -1447,7 +2037,29 @@ impl LlmHandler {
             }),
         );
 
-        let rag_result = self.rag_retriever.retrieve(query).await?;
+        let timeout_ms = self.config.rag.as_ref().and_then(|rag| rag.timeout_ms);
+        let rag_result = match timeout_ms {
+            Some(ms) => match tokio::time::timeout(
+                std::time::Duration::from_millis(ms),
+                self.rag_retriever.retrieve(query),
+            )
+            .await
+            {
+                Ok(result) => result?,
+                Err(_) => {
+                    warn!("RAG retrieval for {} timed out after {}ms", query, ms);
+                    self.send_debug_event(
+                        "rag_timeout",
+                        json!({
+                            "query": query,
+                            "timeoutMs": ms,
+                        }),
+                    );
+                    "no results (retrieval timed out)".to_string()
+                }
+            },
+            None => self.rag_retriever.retrieve(query).await?,
+        };
 
         self.send_debug_event(
             "rag_result",
@@ -1460,13 +2072,33 @@ impl LlmHandler {
         let summary = if let Some(source) = source {
             format!("[{}] {}", source, rag_result)
         } else {
-            rag_result
+            rag_result.clone()
         };
 
-        self.history.push(ChatMessage {
-            role: "system".to_string(),
-            content: format!("RAG result for {}: {}", query, summary),
-        });
+        let rag_config = self.config.rag.as_ref();
+        // A "tool" message must answer a tool call, which this one doesn't
+        let role = match rag_config.and_then(|rag| rag.injection_role.as_deref()) {
+            Some(role @ ("system" | "user" | "assistant")) => role.to_string(),
+            Some(role) => {
+                warn!("unsupported RAG injection role {}, using system", role);
+                "system".to_string()
+            }
+            None => "system".to_string(),
+        };
+        // Rendered in one pass, so placeholders inside the retrieved text are
+        // kept as they are
+        let content = match rag_config.and_then(|rag| rag.injection_template.as_ref()) {
+            Some(template) => RE_INJECTION_VAR
+                .replace_all(template, |caps: &regex::Captures| match &caps[1] {
+                    "query" => query.to_string(),
+                    "source" => source.clone().unwrap_or_default(),
+                    "summary" => summary.clone(),
+                    _ => rag_result.clone(),
+                })
+                .into_owned(),
+            None => format!("RAG result for {}: {}", query, summary),
+        };
+        self.history.push(ChatMessage { role, content });
 
         Ok(())
     }
@@ -1493,7 +2125,11 @@ impl LlmHandler {
             }),
         );
 
-        let mut req = self.client.request(method, url);
+        let timeout_ms = self.config.http_tool_timeout_ms.unwrap_or(10000);
+        let mut req = self
+            .client
+            .request(method, url)
+            .timeout(std::time::Duration::from_millis(timeout_ms));
         if let Some(body) = body {
             req = req.json(body);
         }
@@ -1514,9 +2150,17 @@ impl LlmHandler {
             }
             Err(e) => {
                 warn!("HTTP tool failed: {}", e);
+                let content = if e.is_timeout() {
+                    format!(
+                        "HTTP tool failed: {} {} timed out after {}ms",
+                        method_str, url, timeout_ms
+                    )
+                } else {
+                    format!("HTTP tool failed: {}", e)
+                };
                 self.history.push(ChatMessage {
                     role: "system".to_string(),
-                    content: format!("HTTP tool failed: {}", e),
+                    content,
                 });
             }
         }
@@ -1524,22 +2168,94 @@ impl LlmHandler {
         Ok(())
     }
 
-    async fn handle_asr_final(&mut self, text: &str) -> Result<Vec<Command>> {
+    async fn handle_asr_final(
+        &mut self,
+        text: &str,
+        confidence: Option<f32>,
+    ) -> Result<Vec<Command>> {
         if text.trim().is_empty() {
             return Ok(vec![]);
         }
+        if let Some(commands) = self.check_low_confidence(confidence) {
+            return Ok(commands);
+        }
+
+        self.interim_text = None;
+        if let Some(committed) = self.interim_committed.take() {
+            if committed.trim() == text.trim() {
+                info!("ASR final matches answered interim result, skipping");
+                self.last_asr_final_at = Some(std::time::Instant::now());
+                return Ok(vec![]);
+            }
+        }
 
         self.apply_context_repair(text);
-        self.apply_rolling_summary().await;
 
         self.last_asr_final_at = Some(std::time::Instant::now());
         self.last_interaction_at = std::time::Instant::now();
-        self.is_speaking = false;
+        self.stop_speaking();
         self.consecutive_follow_ups = 0;
 
         self.generate_response().await
     }
 
+    /// Count consecutive low-confidence finals and, once `maxTurns` is reached,
+    /// speak the DTMF menu instead of answering. Results without a confidence
+    /// score leave the count untouched.
+    fn check_low_confidence(&mut self, confidence: Option<f32>) -> Option<Vec<Command>> {
+        let fallback = self.low_confidence_fallback.clone()?;
+        let confidence = confidence?;
+        if confidence >= fallback.min_confidence.unwrap_or(0.5) {
+            self.low_confidence_turns = 0;
+            self.menu_keys_to_llm = false;
+            return None;
+        }
+        self.low_confidence_turns += 1;
+        if self.low_confidence_turns < fallback.max_turns.unwrap_or(2) {
+            return None;
+        }
+        self.low_confidence_turns = 0;
+        info!(
+            "ASR confidence {} low for {} turns, falling back to DTMF menu",
+            confidence,
+            fallback.max_turns.unwrap_or(2)
+        );
+
+        self.interim_text = None;
+        self.interim_committed = None;
+        self.last_asr_final_at = Some(std::time::Instant::now());
+        self.last_interaction_at = std::time::Instant::now();
+        self.stop_speaking();
+        self.consecutive_follow_ups = 0;
+        self.history.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: fallback.prompt.clone(),
+        });
+
+        let started = fallback.collector.as_deref().is_some_and(|collector| {
+            let var_name = fallback.var_name.as_deref().unwrap_or("menu_choice");
+            self.start_collector(collector, var_name)
+        });
+        if let Some(state) = self.collector_state.as_mut().filter(|_| started) {
+            state.prompt = Some(fallback.prompt.clone());
+        }
+        if !started {
+            self.menu_keys_to_llm = true;
+        }
+        Some(vec![self.create_tts_command(fallback.prompt, None, None)])
+    }
+
+    /// Feed a standalone DTMF digit to the LLM as user input so it can route menus.
+    async fn handle_dtmf_input(&mut self, digit: &str) -> Result<Vec<Command>> {
+        self.history.push(ChatMessage {
+            role: "user".to_string(),
+            content: format!("[DTMF: {}]", digit),
+        });
+        self.last_interaction_at = std::time::Instant::now();
+        self.consecutive_follow_ups = 0;
+        self.generate_response().await
+    }
+
     fn apply_context_repair(&mut self, text: &str) {
         let enable_repair = self
             .config
@@ -1587,6 +2303,11 @@ impl LlmHandler {
         }
     }
 
+    /// Fold the oldest turns into the system prompt once the history passes
+    /// `summary_limit` (with the `rolling_summary` feature) or
+    /// `max_history_messages`. A new summary replaces the previous one, which
+    /// is handed to the model so nothing it covered is lost. When the summary
+    /// fails the oldest turns are dropped instead, so the history stays bounded.
     async fn apply_rolling_summary(&mut self) {
         let enable_summary = self
             .config
@@ -1594,54 +2315,70 @@ impl LlmHandler {
             .as_ref()
             .map(|f| f.contains(&"rolling_summary".to_string()))
             .unwrap_or(false);
-
-        if !enable_summary {
-            return;
-        }
-
         let summary_limit = self.config.summary_limit.unwrap_or(20);
-        if self.history.len() <= summary_limit {
-            return;
+        let mut keep_recent = HISTORY_KEEP_RECENT;
+        // The history length allowed by whichever limit was passed
+        let mut limit = usize::MAX;
+        if enable_summary && self.history.len() > summary_limit + keep_recent {
+            limit = summary_limit + keep_recent;
         }
-
-        info!("Rolling Summary: History limit reached. Triggering background summary.");
-        let keep_recent = 6;
-        if self.history.len() <= summary_limit + keep_recent
-            || self.history.len() <= keep_recent + 1
-        {
+        if let Some(max_messages) = self.config.max_history_messages {
+            if self.history.len() > max_messages {
+                // The system prompt takes one of the slots
+                keep_recent = keep_recent.min(max_messages.saturating_sub(1)).max(1);
+                limit = limit.min(max_messages);
+            }
+        }
+        if limit == usize::MAX || self.history.len() <= keep_recent + 1 {
             return;
         }
+        info!("Rolling Summary: History limit reached. Triggering background summary.");
 
         let split_idx = self.history.len() - keep_recent;
-        let to_summarize = self.history[1..split_idx].to_vec();
-        let recent = self.history[split_idx..].to_vec();
+        let (base_prompt, previous_summary) =
+            match self.history[0].content.split_once(SUMMARY_MARKER) {
+                Some((base, summary)) => (base.to_string(), Some(summary.to_string())),
+                None => (self.history[0].content.clone(), None),
+            };
 
         let summary_prompt =
             "Summarize the above conversation so far, focusing on key details and user intent.";
-        let mut summary_req_history = to_summarize;
+        let mut summary_req_history = Vec::new();
+        if let Some(previous_summary) = previous_summary {
+            summary_req_history.push(ChatMessage {
+                role: "system".to_string(),
+                content: format!(
+                    "Summary of the conversation before this: {}",
+                    previous_summary
+                ),
+            });
+        }
+        summary_req_history.extend_from_slice(&self.history[1..split_idx]);
         summary_req_history.push(ChatMessage {
             role: "user".to_string(),
             content: summary_prompt.to_string(),
         });
 
-        match self.provider.call(&self.config, &summary_req_history).await {
+        match self.complete(&summary_req_history).await {
             Ok(summary) => {
-                let mut new_history = Vec::new();
-                if let Some(sys) = self.history.first() {
-                    let mut new_sys = sys.clone();
-                    new_sys.content.push_str("\n\n[Previous Context Summary]: ");
-                    new_sys.content.push_str(&summary);
-                    new_history.push(new_sys);
-                }
-                new_history.extend(recent);
-                self.history = new_history;
+                self.history[0].content = format!("{}{}{}", base_prompt, SUMMARY_MARKER, summary);
+                self.history.drain(1..split_idx);
+                self.traced_messages = 0;
                 info!(
                     "Rolling Summary: Applied summary. New history len: {}",
                     self.history.len()
                 );
             }
             Err(e) => {
-                warn!("Rolling Summary failed: {}", e);
+                // Keep the system prompt and the latest turns that fit
+                let limit = limit.max(keep_recent + 1);
+                let drop = self.history.len().saturating_sub(limit);
+                self.history.drain(1..1 + drop);
+                self.traced_messages = 0;
+                warn!(
+                    "Rolling Summary failed, dropped {} oldest messages: {}",
+                    drop, e
+                );
             }
         }
     }
@@ -1691,14 +2428,57 @@ impl LlmHandler {
             }
         }
 
+        if !self.barge_in_sustained(event) {
+            return None;
+        }
+
         info!("Smart interruption detected, stopping playback");
-        self.is_speaking = false;
+        self.stop_speaking();
         Some(Command::Interrupt {
             graceful: Some(true),
             fade_out_ms: self.interruption_config.volume_fade_ms,
         })
     }
 
+    /// Our playback ended, so caller speech tracked against it no longer
+    /// counts towards the next barge-in
+    fn stop_speaking(&mut self) {
+        self.is_speaking = false;
+        self.barge_in = None;
+    }
+
+    /// Whether the caller's speech has lasted `min_duration_ms` and reached
+    /// `min_words`, tracked across events until it does. Speech that stops
+    /// first is dropped on the next silence or final transcript
+    fn barge_in_sustained(&mut self, event: &SessionEvent) -> bool {
+        let config = &self.interruption_config;
+        if config.strategy == InterruptionStrategy::Vad {
+            return true;
+        }
+        let min_duration_ms = config.min_duration_ms.unwrap_or(0);
+        let min_words = config.min_words.unwrap_or(0);
+        if min_duration_ms == 0 && min_words == 0 {
+            return true;
+        }
+
+        let (started_at, words) = self
+            .barge_in
+            .get_or_insert_with(|| (std::time::Instant::now(), 0));
+        if let SessionEvent::AsrDelta { text, .. } = event {
+            *words = (*words).max(count_words(text));
+        }
+        let sustained =
+            started_at.elapsed().as_millis() >= min_duration_ms as u128 && *words >= min_words;
+        if !sustained {
+            debug!(
+                words = *words,
+                elapsed_ms = started_at.elapsed().as_millis() as u64,
+                "barge-in below threshold"
+            );
+        }
+        sustained
+    }
+
     async fn handle_silence(&mut self) -> Result<Vec<Command>> {
         let follow_up_config = if let Some(scene_id) = &self.current_scene_id {
             self.scenes
@@ -1720,6 +2500,10 @@ impl LlmHandler {
         }
 
         if self.consecutive_follow_ups >= config.max_count {
+            if config.on_max == FollowUpExhausted::Wait {
+                debug!("Max follow-up count reached, waiting for the caller");
+                return Ok(vec![]);
+            }
             info!("Max follow-up count reached, hanging up");
             let headers = self.render_sip_headers().await;
             return Ok(vec![Command::Hangup {
@@ -1805,6 +2589,17 @@ impl LlmHandler {
             let mut rerun_for_rag = false;
             if let Some(tools) = structured.tools {
                 for tool in tools {
+                    if let Some(problem) = self.validate_tool_invocation(&tool) {
+                        warn!("Rejected tool invocation: {}", problem);
+                        self.send_debug_event("tool_rejected", json!({ "reason": problem }));
+                        self.history.push(ChatMessage {
+                            role: "system".to_string(),
+                            content: format!("Tool call rejected: {}", problem),
+                        });
+                        // let the model correct itself or explain to the caller
+                        rerun_for_rag = true;
+                        continue;
+                    }
                     let needs_rerun = self
                         .handle_tool_invocation(tool, &mut tool_commands)
                         .await?;
@@ -1853,11 +2648,62 @@ impl LlmHandler {
     }
 }
 
+/// The next sentence boundary in `buffer`, skipping decimal points such as
+/// "$12.50" (and a trailing "12." that may still be followed by digits)
+fn find_sentence_end(buffer: &str) -> Option<regex::Match<'_>> {
+    RE_SENTENCE.find_iter(buffer).find(|m| {
+        let bytes = buffer.as_bytes();
+        let is_decimal = bytes[m.start()] == b'.'
+            && m.start() > 0
+            && bytes[m.start() - 1].is_ascii_digit()
+            && bytes
+                .get(m.start() + 1)
+                .is_none_or(|next| next.is_ascii_digit());
+        !is_decimal
+    })
+}
+
+fn validate_sip_uri(field: &str, value: &str, max_len: usize) -> std::result::Result<(), String> {
+    if value.len() > max_len {
+        return Err(format!(
+            "refer {} is longer than {} characters",
+            field, max_len
+        ));
+    }
+    let valid = !value.chars().any(|c| c.is_whitespace() || c.is_control())
+        && rsip::Uri::try_from(value).is_ok_and(|uri| {
+            matches!(
+                uri.scheme,
+                Some(rsip::Scheme::Sip) | Some(rsip::Scheme::Sips)
+            ) && !uri.host_with_port.host.to_string().is_empty()
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "refer {} \"{}\" is not a valid SIP URI, expected e.g. sip:1001@example.com",
+            field, value
+        ))
+    }
+}
+
 fn parse_structured_response(raw: &str) -> Option<StructuredResponse> {
     let payload = extract_json_block(raw)?;
     serde_json::from_str(payload).ok()
 }
 
+/// Words in `text`, counting each CJK (non-ASCII) character as one word
+fn count_words(text: &str) -> usize {
+    text.split_whitespace()
+        .map(
+            |word| match word.chars().filter(|c| !c.is_ascii()).count() {
+                0 => 1,
+                n => n,
+            },
+        )
+        .sum()
+}
+
 fn is_likely_filler(text: &str) -> bool {
     let trimmed = text.trim().to_lowercase();
     FILLERS.contains(&trimmed)
@@ -1893,6 +2739,7 @@ fn extract_json_block(raw: &str) -> Option<&str> {
 impl DialogueHandler for LlmHandler {
     async fn on_start(&mut self) -> Result<Vec<Command>> {
         self.last_tts_start_at = Some(std::time::Instant::now());
+        self.render_system_prompt().await;
 
         let mut commands = Vec::new();
 
@@ -1910,9 +2757,15 @@ impl DialogueHandler for LlmHandler {
             }
         }
 
-        if let Some(greeting) = &self.config.greeting {
+        if let Some(greeting) = self.static_greeting() {
+            if self.config.greeting_mode == Some(GreetingMode::StaticThenLlm) {
+                self.history.push(ChatMessage {
+                    role: "assistant".to_string(),
+                    content: greeting.clone(),
+                });
+            }
             self.is_speaking = true;
-            commands.push(self.create_tts_command(greeting.clone(), None, None));
+            commands.push(self.create_tts_command(greeting, None, None));
             return Ok(commands);
         }
 
@@ -1922,19 +2775,31 @@ impl DialogueHandler for LlmHandler {
     }
 
     async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
+        match event {
+            SessionEvent::Hangup { .. } => {
+                self.send_usage_event(true);
+                self.save_llm_trace().await;
+            }
+            SessionEvent::Silence { .. } | SessionEvent::AsrFinal { .. } => self.barge_in = None,
+            _ => {}
+        }
+
         // When in DTMF collection mode, only handle DTMF events and track lifecycle
         if self.collector_state.is_some() {
             match event {
                 SessionEvent::Dtmf { digit, .. } => {
                     info!("DTMF received (collecting): {}", digit);
-                    return self.handle_collector_digit(digit).await;
+                    let mut commands: Vec<Command> =
+                        self.interrupt_collector_prompt().into_iter().collect();
+                    commands.extend(self.handle_collector_input(digit).await?);
+                    return Ok(commands);
                 }
                 SessionEvent::Silence { .. } => {
                     // Check collector timeout on silence events
                     return self.check_collector_timeout().await;
                 }
                 SessionEvent::TrackEnd { .. } => {
-                    self.is_speaking = false;
+                    self.stop_speaking();
                     return Ok(vec![]);
                 }
                 SessionEvent::TrackStart { .. } => {
@@ -1945,6 +2810,14 @@ impl DialogueHandler for LlmHandler {
                     // Allow hangup to pass through
                     self.collector_state = None;
                 }
+                SessionEvent::AsrFinal { text, .. }
+                    if self
+                        .collector_state
+                        .as_ref()
+                        .is_some_and(|s| s.config.accept_speech.unwrap_or(false)) =>
+                {
+                    return self.handle_collector_speech(text).await;
+                }
                 // Ignore ASR/Speaking/Eou during collection (not interruptible by default)
                 SessionEvent::AsrFinal { .. }
                 | SessionEvent::AsrDelta { .. }
@@ -1958,6 +2831,17 @@ impl DialogueHandler for LlmHandler {
                     if !interruptible {
                         return Ok(vec![]);
                     }
+                    let barge_in = if matches!(
+                        event,
+                        SessionEvent::Speaking { .. } | SessionEvent::AsrDelta { .. }
+                    ) {
+                        self.interrupt_collector_prompt()
+                    } else {
+                        None
+                    };
+                    if let Some(interrupt) = barge_in {
+                        return Ok(vec![interrupt]);
+                    }
                     // If interruptible, fall through to normal handling
                 }
                 _ => return Ok(vec![]),
@@ -1969,12 +2853,26 @@ impl DialogueHandler for LlmHandler {
                 info!("DTMF received: {}", digit);
                 if let Some(action) = self.get_dtmf_action(digit) {
                     self.handle_dtmf_action(action).await
+                } else if self.dtmf_to_llm || self.menu_keys_to_llm {
+                    self.menu_keys_to_llm = false;
+                    self.handle_dtmf_input(digit).await
                 } else {
                     Ok(vec![])
                 }
             }
-            SessionEvent::AsrFinal { text, .. } => self.handle_asr_final(text).await,
+            SessionEvent::AsrFinal {
+                text, confidence, ..
+            } => self.handle_asr_final(text, *confidence).await,
             SessionEvent::AsrDelta { is_filler, .. } | SessionEvent::Speaking { is_filler, .. } => {
+                if self.use_interim_asr {
+                    if let SessionEvent::AsrDelta { text, .. } = event {
+                        if !is_filler.unwrap_or(false) && !text.trim().is_empty() {
+                            self.interim_text = Some(text.clone());
+                            self.last_interaction_at = std::time::Instant::now();
+                            self.consecutive_follow_ups = 0;
+                        }
+                    }
+                }
                 Ok(self
                     .check_interruption(event, is_filler)
                     .into_iter()
@@ -1983,6 +2881,10 @@ impl DialogueHandler for LlmHandler {
             SessionEvent::Eou { completed, .. } => {
                 if *completed && !self.is_speaking {
                     info!("EOU detected, triggering early response");
+                    if let Some(text) = self.interim_text.take() {
+                        self.apply_context_repair(&text);
+                        self.interim_committed = Some(text);
+                    }
                     self.generate_response().await
                 } else {
                     Ok(vec![])
@@ -1994,7 +2896,7 @@ impl DialogueHandler for LlmHandler {
                 Ok(vec![])
             }
             SessionEvent::TrackEnd { .. } => {
-                self.is_speaking = false;
+                self.stop_speaking();
                 self.is_hanging_up = false;
                 self.last_interaction_at = std::time::Instant::now();
                 Ok(vec![])
@@ -2006,6 +2908,10 @@ impl DialogueHandler for LlmHandler {
         }
     }
 
+    async fn on_lagged(&mut self, skipped: u64) -> Result<Vec<Command>> {
+        Ok(self.resync_collector(skipped).into_iter().collect())
+    }
+
     async fn get_history(&self) -> Vec<ChatMessage> {
         self.history.clone()
     }
@@ -2018,6 +2924,9 @@ impl DialogueHandler for LlmHandler {
             content: prompt.to_string(),
         });
 
-        self.provider.call(&self.config, &summary_history).await
+        let summary = self.complete(&summary_history).await;
+        // Summaries run once the call is over, after its last save
+        self.save_usage().await;
+        summary
     }
 }
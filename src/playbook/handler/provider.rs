@@ -12,11 +12,192 @@ use super::types::ToolInvocation;
 pub enum LlmStreamEvent {
     Content(String),
     Reasoning(String),
+    /// Tokens used by the whole reply, when the provider reports them
+    Usage(LlmUsage),
+    /// Tools the model called natively, sent once the reply is complete
+    ToolCalls(Vec<ToolInvocation>),
+}
+
+/// OpenAI `tools` definitions of the built-in tools, sent when
+/// `functionCalling` is enabled
+pub fn tool_definitions() -> serde_json::Value {
+    json!([
+        {
+            "type": "function",
+            "function": {
+                "name": "hangup",
+                "description": "End the call once the reply has been spoken",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "reason": { "type": "string", "description": "Short reason for ending the call" }
+                    }
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "refer",
+                "description": "Transfer the call to another party",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "callee": { "type": "string", "description": "SIP URI to transfer to, e.g. sip:1001@example.com" }
+                    },
+                    "required": ["callee"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "rag",
+                "description": "Look up the knowledge base before answering",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": { "type": "string", "description": "What to look up" },
+                        "source": { "type": "string", "description": "Knowledge base to search, when there are several" }
+                    },
+                    "required": ["query"]
+                }
+            }
+        }
+    ])
+}
+
+/// Map a native tool call to its invocation, `None` for an unknown tool or
+/// arguments that don't fit it
+pub fn parse_tool_call(name: &str, arguments: &str) -> Option<ToolInvocation> {
+    let mut args = match serde_json::from_str::<serde_json::Value>(arguments) {
+        Ok(serde_json::Value::Object(args)) => args,
+        _ if arguments.trim().is_empty() => serde_json::Map::new(),
+        _ => return None,
+    };
+    args.insert("name".to_string(), json!(name));
+    if name == "refer" {
+        args.entry("caller").or_insert_with(|| json!(""));
+    }
+    serde_json::from_value(serde_json::Value::Object(args)).ok()
+}
+
+/// Parse an OpenAI `tool_calls` array, skipping calls that don't map to a tool
+pub fn parse_tool_calls(tool_calls: &serde_json::Value) -> Vec<ToolInvocation> {
+    let Some(calls) = tool_calls.as_array() else {
+        return Vec::new();
+    };
+    calls
+        .iter()
+        .filter_map(|call| {
+            let name = call["function"]["name"].as_str()?;
+            let arguments = call["function"]["arguments"].as_str().unwrap_or_default();
+            let tool = parse_tool_call(name, arguments);
+            if tool.is_none() {
+                tracing::warn!(name, arguments, "ignoring unsupported tool call");
+            }
+            tool
+        })
+        .collect()
+}
+
+/// The structured reply `text` plus `tools`, as a JSON-mode reply would carry them
+pub fn tool_call_reply(text: Option<&str>, tools: &[ToolInvocation]) -> String {
+    json!({ "text": text, "tools": tools }).to_string()
+}
+
+/// Merge a streamed `tool_calls` delta into the calls received so far
+fn push_tool_call_delta(calls: &mut Vec<serde_json::Value>, delta: &serde_json::Value) {
+    for part in delta.as_array().into_iter().flatten() {
+        let index = part["index"].as_u64().map_or(calls.len(), |i| i as usize);
+        // Calls are numbered in order, so only the next one may be opened;
+        // a delta for any other index is dropped instead of padding the list
+        if index == calls.len() {
+            calls.push(json!({ "function": { "name": "", "arguments": "" } }));
+        }
+        let Some(call) = calls.get_mut(index) else {
+            continue;
+        };
+        let function = &mut call["function"];
+        for key in ["name", "arguments"] {
+            if let Some(text) = part["function"][key].as_str() {
+                let merged = format!("{}{}", function[key].as_str().unwrap_or_default(), text);
+                function[key] = json!(merged);
+            }
+        }
+    }
+}
+
+/// Tokens billed for LLM requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LlmUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Some of the counts are estimates, the provider reported no usage
+    pub estimated: bool,
+}
+
+impl LlmUsage {
+    /// Parse an OpenAI style `usage` object
+    pub fn from_openai(usage: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            prompt_tokens: usage.get("prompt_tokens")?.as_u64()?,
+            completion_tokens: usage
+                .get("completion_tokens")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0),
+            estimated: false,
+        })
+    }
+
+    /// Rough usage of a request whose provider reported none
+    pub fn estimate(history: &[ChatMessage], response: &str) -> Self {
+        Self {
+            prompt_tokens: history.iter().map(|m| estimate_tokens(&m.content)).sum(),
+            completion_tokens: estimate_tokens(response),
+            estimated: true,
+        }
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: LlmUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.estimated |= other.estimated;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "total_tokens": self.total_tokens(),
+            "estimated": self.estimated,
+        })
+    }
+}
+
+/// About four characters per token for ASCII text, one per other character
+/// (CJK text is roughly a token per character)
+fn estimate_tokens(text: &str) -> u64 {
+    let ascii = text.chars().filter(char::is_ascii).count() as u64;
+    let other = text.chars().count() as u64 - ascii;
+    ascii.div_ceil(4) + other
 }
 
 #[async_trait]
 pub trait LlmProvider: Send + Sync {
     async fn call(&self, config: &LlmConfig, history: &[ChatMessage]) -> Result<String>;
+    /// Like `call`, also returning the tokens used when the provider reports them
+    async fn call_with_usage(
+        &self,
+        config: &LlmConfig,
+        history: &[ChatMessage],
+    ) -> Result<(String, Option<LlmUsage>)> {
+        Ok((self.call(config, history).await?, None))
+    }
     async fn call_stream(
         &self,
         config: &LlmConfig,
@@ -47,7 +228,28 @@ pub struct DefaultLlmProvider {
 impl DefaultLlmProvider {
     pub fn new() -> Self {
         Self {
-            client: Client::new(),
+            client: crate::net_tool::http_client(),
+        }
+    }
+
+    /// Offer the built-in tools as native functions when `functionCalling` is on
+    fn apply_tools(body: &mut serde_json::Value, config: &LlmConfig) {
+        if config.function_calling.unwrap_or(false) {
+            body["tools"] = tool_definitions();
+            body["tool_choice"] = json!("auto");
+        }
+    }
+
+    /// Add the configured sampling parameters, leaving unset ones to the provider
+    fn apply_sampling(body: &mut serde_json::Value, config: &LlmConfig) {
+        if let Some(temperature) = config.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = config.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = config.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
         }
     }
 }
@@ -55,6 +257,14 @@ impl DefaultLlmProvider {
 #[async_trait]
 impl LlmProvider for DefaultLlmProvider {
     async fn call(&self, config: &LlmConfig, history: &[ChatMessage]) -> Result<String> {
+        Ok(self.call_with_usage(config, history).await?.0)
+    }
+
+    async fn call_with_usage(
+        &self,
+        config: &LlmConfig,
+        history: &[ChatMessage],
+    ) -> Result<(String, Option<LlmUsage>)> {
         let mut url = config
             .base_url
             .clone()
@@ -69,16 +279,22 @@ impl LlmProvider for DefaultLlmProvider {
             url = format!("{}/chat/completions", url.trim_end_matches('/'));
         }
 
-        let body = json!({
+        let mut body = json!({
             "model": model,
             "messages": history,
         });
+        if config.json_mode.unwrap_or(false) {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
+        Self::apply_tools(&mut body, config);
+        Self::apply_sampling(&mut body, config);
 
-        let res = self
+        let request = self
             .client
             .post(&url)
             .header("Authorization", format!("Bearer {}", api_key))
-            .json(&body)
+            .json(&body);
+        let res = crate::net_tool::with_request_timeout(request)
             .send()
             .await?;
 
@@ -87,12 +303,18 @@ impl LlmProvider for DefaultLlmProvider {
         }
 
         let json: serde_json::Value = res.json().await?;
-        let content = json["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or_else(|| anyhow!("Invalid LLM response"))?
-            .to_string();
+        let message = &json["choices"][0]["message"];
+        let content = message["content"].as_str();
+        let tools = parse_tool_calls(&message["tool_calls"]);
+        let content = if !tools.is_empty() {
+            tool_call_reply(content, &tools)
+        } else {
+            content
+                .ok_or_else(|| anyhow!("Invalid LLM response"))?
+                .to_string()
+        };
 
-        Ok(content)
+        Ok((content, LlmUsage::from_openai(&json["usage"])))
     }
 
     async fn call_stream(
@@ -114,11 +336,22 @@ impl LlmProvider for DefaultLlmProvider {
             url = format!("{}/chat/completions", url.trim_end_matches('/'));
         }
 
-        let body = json!({
+        let mut body = json!({
             "model": model,
             "messages": history,
             "stream": true,
         });
+        if config
+            .stream_usage
+            .unwrap_or_else(|| url.starts_with("https://api.openai.com/"))
+        {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if config.json_mode.unwrap_or(false) {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
+        Self::apply_tools(&mut body, config);
+        Self::apply_sampling(&mut body, config);
 
         let res = self
             .client
@@ -135,6 +368,7 @@ impl LlmProvider for DefaultLlmProvider {
         let stream = res.bytes_stream();
         let s = async_stream::stream! {
             let mut buffer = String::new();
+            let mut tool_calls = Vec::new();
             for await chunk in stream {
                 match chunk {
                     Ok(bytes) => {
@@ -149,6 +383,10 @@ impl LlmProvider for DefaultLlmProvider {
                                     break;
                                 }
                                 if let Ok(json) = serde_json::from_str::<serde_json::Value>(data) {
+                                    // Sent in a last chunk without choices
+                                    if let Some(usage) = LlmUsage::from_openai(&json["usage"]) {
+                                        yield Ok(LlmStreamEvent::Usage(usage));
+                                    }
                                     if let Some(delta) = json["choices"][0].get("delta") {
                                          if let Some(thinking) = delta.get("reasoning_content").and_then(|v| v.as_str()) {
                                              yield Ok(LlmStreamEvent::Reasoning(thinking.to_string()));
@@ -156,6 +394,215 @@ impl LlmProvider for DefaultLlmProvider {
                                          if let Some(content) = delta.get("content").and_then(|v| v.as_str()) {
                                              yield Ok(LlmStreamEvent::Content(content.to_string()));
                                          }
+                                         if let Some(calls) = delta.get("tool_calls") {
+                                             push_tool_call_delta(&mut tool_calls, calls);
+                                         }
+                                    }
+                                }
+                            }
+                            buffer.drain(..=line_end);
+                        }
+                    }
+                    Err(e) => yield Err(anyhow!(e)),
+                }
+            }
+            if !tool_calls.is_empty() {
+                yield Ok(LlmStreamEvent::ToolCalls(parse_tool_calls(&json!(tool_calls))));
+            }
+        };
+
+        Ok(Box::pin(s))
+    }
+}
+
+/// Select the provider implementation for `config.provider`.
+pub fn create_llm_provider(config: &LlmConfig) -> std::sync::Arc<dyn LlmProvider> {
+    match config.provider.as_str() {
+        "gemini" => std::sync::Arc::new(GeminiLlmProvider::new()),
+        _ => std::sync::Arc::new(DefaultLlmProvider::new()),
+    }
+}
+
+/// Google Gemini `generateContent` API
+pub struct GeminiLlmProvider {
+    client: Client,
+}
+
+impl GeminiLlmProvider {
+    pub fn new() -> Self {
+        Self {
+            client: crate::net_tool::http_client(),
+        }
+    }
+
+    fn endpoint(config: &LlmConfig, method: &str) -> String {
+        let base_url = config
+            .base_url
+            .clone()
+            .unwrap_or_else(|| "https://generativelanguage.googleapis.com/v1beta".to_string());
+        let model = config
+            .model
+            .clone()
+            .unwrap_or_else(|| "gemini-2.0-flash".to_string());
+        format!(
+            "{}/models/{}:{}",
+            base_url.trim_end_matches('/'),
+            model.trim_start_matches("models/"),
+            method
+        )
+    }
+
+    /// Build the request for `config`, asking for a JSON response when `json_mode` is set.
+    pub fn build_config_request(config: &LlmConfig, history: &[ChatMessage]) -> serde_json::Value {
+        let mut body = Self::build_request(history);
+        let mut generation = serde_json::Map::new();
+        if config.json_mode.unwrap_or(false) {
+            generation.insert("responseMimeType".to_string(), json!("application/json"));
+        }
+        if let Some(temperature) = config.temperature {
+            generation.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(top_p) = config.top_p {
+            generation.insert("topP".to_string(), json!(top_p));
+        }
+        if let Some(max_tokens) = config.max_tokens {
+            generation.insert("maxOutputTokens".to_string(), json!(max_tokens));
+        }
+        if !generation.is_empty() {
+            body["generationConfig"] = serde_json::Value::Object(generation);
+        }
+        body
+    }
+
+    /// Map chat history into Gemini's `contents`, moving system messages into `system_instruction`.
+    pub fn build_request(history: &[ChatMessage]) -> serde_json::Value {
+        let mut system = Vec::new();
+        let mut contents: Vec<serde_json::Value> = Vec::new();
+        for msg in history {
+            let role = match msg.role.as_str() {
+                "system" => {
+                    system.push(msg.content.as_str());
+                    continue;
+                }
+                "assistant" => "model",
+                _ => "user",
+            };
+            // Gemini expects alternating turns, merge consecutive messages of the same role
+            if let Some(last) = contents.last_mut() {
+                if last["role"] == role {
+                    if let Some(parts) = last["parts"].as_array_mut() {
+                        parts.push(json!({ "text": msg.content }));
+                        continue;
+                    }
+                }
+            }
+            contents.push(json!({
+                "role": role,
+                "parts": [{ "text": msg.content }],
+            }));
+        }
+
+        let mut body = json!({ "contents": contents });
+        if !system.is_empty() {
+            body["system_instruction"] = json!({
+                "parts": [{ "text": system.join("\n\n") }],
+            });
+        }
+        body
+    }
+
+    /// Extract answer text and thought summaries from a `generateContent` response.
+    pub fn parse_response(json: &serde_json::Value) -> Result<(String, String)> {
+        if let Some(error) = json.get("error") {
+            return Err(anyhow!("Gemini request failed: {}", error));
+        }
+        let parts = json["candidates"][0]["content"]["parts"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let mut text = String::new();
+        let mut thought = String::new();
+        for part in parts {
+            if let Some(t) = part["text"].as_str() {
+                if part["thought"].as_bool().unwrap_or(false) {
+                    thought.push_str(t);
+                } else {
+                    text.push_str(t);
+                }
+            }
+        }
+        Ok((text, thought))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiLlmProvider {
+    async fn call(&self, config: &LlmConfig, history: &[ChatMessage]) -> Result<String> {
+        let url = Self::endpoint(config, "generateContent");
+        let api_key = config.api_key.clone().unwrap_or_default();
+
+        let request = self
+            .client
+            .post(&url)
+            .header("x-goog-api-key", api_key)
+            .json(&Self::build_config_request(config, history));
+        let res = crate::net_tool::with_request_timeout(request)
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(anyhow!("LLM request failed: {}", res.status()));
+        }
+
+        let json: serde_json::Value = res.json().await?;
+        let (text, _) = Self::parse_response(&json)?;
+        Ok(text)
+    }
+
+    async fn call_stream(
+        &self,
+        config: &LlmConfig,
+        history: &[ChatMessage],
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let url = format!(
+            "{}?alt=sse",
+            Self::endpoint(config, "streamGenerateContent")
+        );
+        let api_key = config.api_key.clone().unwrap_or_default();
+
+        let res = self
+            .client
+            .post(&url)
+            .header("x-goog-api-key", api_key)
+            .json(&Self::build_config_request(config, history))
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(anyhow!("LLM request failed: {}", res.status()));
+        }
+
+        let stream = res.bytes_stream();
+        let s = async_stream::stream! {
+            let mut buffer = String::new();
+            for await chunk in stream {
+                match chunk {
+                    Ok(bytes) => {
+                        buffer.push_str(&String::from_utf8_lossy(&bytes));
+                        while let Some(line_end) = buffer.find('\n') {
+                            let line = buffer[..line_end].trim();
+                            if let Some(data) = line.strip_prefix("data:") {
+                                if let Ok(json) = serde_json::from_str::<serde_json::Value>(data.trim()) {
+                                    match GeminiLlmProvider::parse_response(&json) {
+                                        Ok((text, thought)) => {
+                                            if !thought.is_empty() {
+                                                yield Ok(LlmStreamEvent::Reasoning(thought));
+                                            }
+                                            if !text.is_empty() {
+                                                yield Ok(LlmStreamEvent::Content(text));
+                                            }
+                                        }
+                                        Err(e) => yield Err(e),
                                     }
                                 }
                             }
@@ -0,0 +1,223 @@
+//! Reading keypad input out of a transcript, for DTMF collectors that accept
+//! speech: "one three eight", "一三八" or "uno tres ocho" all give "138".
+
+/// Spoken words for each key, English, Spanish, French and German
+const DIGIT_WORDS: &[(&str, char)] = &[
+    ("zero", '0'),
+    ("cero", '0'),
+    ("zéro", '0'),
+    ("null", '0'),
+    ("one", '1'),
+    ("uno", '1'),
+    ("une", '1'),
+    ("eins", '1'),
+    ("two", '2'),
+    ("dos", '2'),
+    ("deux", '2'),
+    ("zwei", '2'),
+    ("three", '3'),
+    ("tres", '3'),
+    ("trois", '3'),
+    ("drei", '3'),
+    ("four", '4'),
+    ("cuatro", '4'),
+    ("quatre", '4'),
+    ("vier", '4'),
+    ("five", '5'),
+    ("cinco", '5'),
+    ("cinq", '5'),
+    ("fünf", '5'),
+    ("six", '6'),
+    ("seis", '6'),
+    ("sechs", '6'),
+    ("seven", '7'),
+    ("siete", '7'),
+    ("sept", '7'),
+    ("sieben", '7'),
+    ("eight", '8'),
+    ("ocho", '8'),
+    ("huit", '8'),
+    ("acht", '8'),
+    ("nine", '9'),
+    ("nueve", '9'),
+    ("neuf", '9'),
+    ("neun", '9'),
+    ("pound", '#'),
+    ("hash", '#'),
+    ("star", '*'),
+];
+
+/// Keys that are also everyday words ("oh, sorry", "un moment"), only read
+/// next to other keys
+const LOOSE_DIGIT_WORDS: &[(&str, char)] = &[("oh", '0'), ("un", '1')];
+
+/// Words repeating the key that follows, as in "double five"
+const REPEAT_WORDS: &[(&str, usize)] = &[("double", 2), ("triple", 3)];
+
+/// Phrases asking to empty the keys entered so far
+const CLEAR_PHRASES: &[&str] = &[
+    "start over",
+    "clear",
+    "reset",
+    "delete",
+    "重新输入",
+    "重新",
+    "重来",
+    "清除",
+    "清空",
+    "删除",
+    "borrar",
+    "effacer",
+    "recommencer",
+    "löschen",
+];
+
+/// A CJK numeral, which is also part of many words ("一下", "两个",
+/// "星期"), so only read next to other keys or in an utterance that is
+/// mostly keys
+fn cjk_digit(c: char) -> Option<char> {
+    Some(match c {
+        '零' | '〇' | '洞' => '0',
+        '一' | '幺' | '壹' => '1',
+        '二' | '两' | '贰' => '2',
+        '三' | '叁' => '3',
+        '四' | '肆' => '4',
+        '五' | '伍' => '5',
+        '六' | '陆' => '6',
+        '七' | '拐' | '柒' => '7',
+        '八' | '捌' => '8',
+        '九' | '勾' | '玖' => '9',
+        '井' => '#',
+        '星' => '*',
+        _ => return None,
+    })
+}
+
+fn fullwidth_digit(c: char) -> Option<char> {
+    match c {
+        '０'..='９' => char::from_digit(c as u32 - '０' as u32, 10),
+        _ => None,
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{9fff}' | '\u{ff00}'..='\u{ffef}')
+}
+
+/// A letter or digit of a space separated language
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() && !is_cjk(c)
+}
+
+enum Token {
+    Key(char),
+    LooseWord(char),
+    LooseCjk(char),
+    Repeat(usize),
+    /// Any other word or CJK character
+    Word,
+    /// Punctuation, ending a run of keys
+    Break,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    fn word_token(word: &str) -> Token {
+        if let Some((_, key)) = DIGIT_WORDS.iter().find(|(w, _)| *w == word) {
+            Token::Key(*key)
+        } else if let Some((_, key)) = LOOSE_DIGIT_WORDS.iter().find(|(w, _)| *w == word) {
+            Token::LooseWord(*key)
+        } else if let Some((_, n)) = REPEAT_WORDS.iter().find(|(w, _)| *w == word) {
+            Token::Repeat(*n)
+        } else {
+            Token::Word
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_alphabetic() && !is_cjk(c) {
+            word.extend(c.to_lowercase());
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(word_token(&word));
+            word.clear();
+        }
+        if c.is_ascii_digit() || c == '#' || c == '*' {
+            tokens.push(Token::Key(c));
+        } else if let Some(key) = fullwidth_digit(c) {
+            tokens.push(Token::Key(key));
+        } else if let Some(key) = cjk_digit(c) {
+            tokens.push(Token::LooseCjk(key));
+        } else if c.is_alphanumeric() {
+            tokens.push(Token::Word);
+        } else if !c.is_whitespace() {
+            tokens.push(Token::Break);
+        }
+    }
+    tokens
+}
+
+/// The keys spoken in `text`, in order. Digits written as numbers are kept,
+/// other words are skipped. Keys that are also everyday words only count in
+/// a run of two or more keys, CJK numerals also when most of `text` is keys
+pub fn spoken_digits(text: &str) -> String {
+    let tokens = tokenize(text);
+    let is_key = |token: &Token| {
+        matches!(
+            token,
+            Token::Key(_) | Token::LooseWord(_) | Token::LooseCjk(_) | Token::Repeat(_)
+        )
+    };
+    let keys = tokens.iter().filter(|&t| is_key(t)).count();
+    let words = tokens.iter().filter(|t| matches!(t, Token::Word)).count();
+    let mostly_keys = keys > words;
+
+    let mut digits = String::new();
+    let mut repeat = 1;
+    for run in tokens.split(|t| !is_key(t)) {
+        let in_run = run.len() >= 2;
+        for token in run {
+            let key = match *token {
+                Token::Key(key) => Some(key),
+                Token::LooseWord(key) => in_run.then_some(key),
+                Token::LooseCjk(key) => (in_run || mostly_keys).then_some(key),
+                Token::Repeat(n) => {
+                    repeat = n;
+                    continue;
+                }
+                Token::Word | Token::Break => None,
+            };
+            if let Some(key) = key {
+                for _ in 0..repeat {
+                    digits.push(key);
+                }
+            }
+            repeat = 1;
+        }
+    }
+    digits
+}
+
+/// When `text` asks to start over, the part of it after the request, which
+/// may already hold the new keys. Phrases in space separated languages must
+/// be whole words, so "cleared" or "unclear" don't match
+pub fn after_clear_command(text: &str) -> Option<String> {
+    let lower = text.to_lowercase();
+    let lower = lower.as_str();
+    CLEAR_PHRASES
+        .iter()
+        .flat_map(|phrase| {
+            lower.match_indices(phrase).filter_map(move |(pos, _)| {
+                let end = pos + phrase.len();
+                let starts_word = phrase.chars().next().is_some_and(is_word_char);
+                let ends_word = phrase.chars().next_back().is_some_and(is_word_char);
+                let before = lower[..pos].chars().next_back().is_some_and(is_word_char);
+                let after = lower[end..].chars().next().is_some_and(is_word_char);
+                (!(starts_word && before) && !(ends_word && after)).then_some(end)
+            })
+        })
+        .max()
+        .map(|end| lower[end..].to_string())
+}
@@ -74,7 +74,7 @@ fn test_build_system_prompt_with_features() {
         ..Default::default()
     };
 
-    let prompt = LlmHandler::build_system_prompt(&config, None, None);
+    let prompt = LlmHandler::build_system_prompt(&config, None, None, None);
     assert!(prompt.contains("Base prompt"));
     assert!(prompt.contains("### Enhanced Capabilities:"));
     // This is the content of intent_clarification.zh.md
@@ -92,7 +92,7 @@ fn test_build_system_prompt_missing_feature() {
     };
 
     // Should not crash, just warn and omit the feature
-    let prompt = LlmHandler::build_system_prompt(&config, None, None);
+    let prompt = LlmHandler::build_system_prompt(&config, None, None, None);
     assert!(prompt.contains("Base prompt"));
     assert!(!prompt.contains("Enhanced Capabilities"));
 }
@@ -106,7 +106,7 @@ fn test_build_system_prompt_en() {
         ..Default::default()
     };
 
-    let prompt = LlmHandler::build_system_prompt(&config, None, None);
+    let prompt = LlmHandler::build_system_prompt(&config, None, None, None);
     assert!(prompt.contains("If the user's intent is unclear"));
 }
 
@@ -145,6 +145,7 @@ async fn handler_applies_tool_instructions() -> Result<()> {
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -170,17 +171,21 @@ async fn handler_applies_tool_instructions() -> Result<()> {
 }
 
 #[tokio::test]
-async fn handler_requeries_after_rag() -> Result<()> {
-    let rag_instruction = r#"{"tools": [{"name": "rag", "query": "policy"}]}"#;
+async fn handler_rejects_invalid_refer_target() -> Result<()> {
+    let response = r#"{
+        "text": "Transferring you now",
+        "tools": [
+            {"name": "refer", "caller": "", "callee": "the sales team <ext 5>"}
+        ]
+    }"#;
     let provider = Arc::new(TestProvider::new(vec![
-        rag_instruction.to_string(),
-        "Final answer".to_string(),
+        response.to_string(),
+        "Sorry, I can't transfer you right now.".to_string(),
     ]));
-    let rag = Arc::new(RecordingRag::new());
     let mut handler = LlmHandler::with_provider(
         LlmConfig::default(),
         provider,
-        rag.clone(),
+        Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
         None,
         HashMap::new(),
@@ -191,47 +196,42 @@ async fn handler_requeries_after_rag() -> Result<()> {
     );
 
     let event = SessionEvent::AsrFinal {
-        track_id: "track-2".to_string(),
+        track_id: "track-1".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "reep".to_string(),
+        text: "put me through to sales".to_string(),
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
 
     let commands = handler.on_event(&event).await?;
+    assert!(
+        !commands
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Refer { .. }))
+    );
     assert!(matches!(
         commands.get(0),
-        Some(Command::Tts {
-            text,
-            wait_input_timeout: Some(timeout),
-            ..
-        }) if text == "Final answer" && *timeout == 10000
+        Some(Command::Tts { text, .. }) if text == "Sorry, I can't transfer you right now."
     ));
-    assert_eq!(rag.recorded_queries(), vec!["policy".to_string()]);
+    assert!(handler.history.iter().any(|msg| msg.role == "system"
+        && msg.content.contains("refer callee")
+        && msg.content.contains("not a valid SIP URI")));
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_full_dialogue_flow() -> Result<()> {
-    let responses = vec![
-        "Hello! How can I help you today?".to_string(),
-        r#"{"text": "I can help with that. Anything else?", "waitInputTimeout": 5000}"#.to_string(),
-        r#"{"text": "Goodbye!", "tools": [{"name": "hangup", "reason": "completed"}]}"#.to_string(),
-    ];
-
-    let provider = Arc::new(TestProvider::new(responses));
-    let config = LlmConfig {
-        greeting: Some("Welcome to the voice assistant.".to_string()),
-        ..Default::default()
-    };
-
+async fn handler_normalizes_text_before_tts() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec![
+        "That's $12.50 at 3:00pm. See you then!".to_string(),
+    ]));
     let mut handler = LlmHandler::with_provider(
-        config,
+        LlmConfig::default(),
         provider,
         Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
@@ -242,103 +242,59 @@ async fn test_full_dialogue_flow() -> Result<()> {
         None,
         None,
     );
+    handler.set_text_normalization(Some("en".to_string()));
 
-    // 1. Start the dialogue
-    let commands = handler.on_start().await?;
-    assert_eq!(commands.len(), 1);
-    if let Command::Tts { text, .. } = &commands[0] {
-        assert_eq!(text, "Welcome to the voice assistant.");
-    } else {
-        panic!("Expected Tts command");
-    }
-
-    // 2. User says something
     let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
+        track_id: "track-1".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "I need help".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
-    let commands = handler.on_event(&event).await?;
-    // "Hello! How can I help you today?" -> split into two + EOS
-    assert_eq!(commands.len(), 3);
-    if let Command::Tts { text, .. } = &commands[0] {
-        assert!(text.contains("Hello"));
-    } else {
-        panic!("Expected Tts command");
-    }
-
-    // 3. User says something else
-    let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 1,
-        start_time: None,
-        end_time: None,
-        text: "Tell me a joke".to_string(),
+        text: "how much is it".to_string(),
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
-    let commands = handler.on_event(&event).await?;
-    assert_eq!(commands.len(), 1);
-    if let Command::Tts {
-        text,
-        wait_input_timeout,
-        ..
-    } = &commands[0]
-    {
-        assert_eq!(text, "I can help with that. Anything else?");
-        assert_eq!(*wait_input_timeout, Some(5000));
-    } else {
-        panic!("Expected Tts command");
-    }
 
-    // 4. User says goodbye
-    let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 2,
-        start_time: None,
-        end_time: None,
-        text: "That's all, thanks".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
     let commands = handler.on_event(&event).await?;
-    // Should have Tts with auto_hangup
-    assert_eq!(commands.len(), 1);
-
-    let has_tts_hangup = commands.iter().any(|c| {
-        matches!(
-            c,
-            Command::Tts {
-                text,
-                auto_hangup: Some(true),
-                ..
-            } if text == "Goodbye!"
-        )
-    });
-
-    assert!(has_tts_hangup);
+    let texts: Vec<&str> = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::Tts { text, .. } if !text.is_empty() => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        texts,
+        vec![
+            "That's twelve dollars and fifty cents at three PM. ",
+            "See you then!"
+        ]
+    );
+    // The conversation history keeps what the model actually said
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|msg| msg.content == "That's $12.50 at 3:00pm. See you then!")
+    );
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_xml_tools_and_sentence_splitting() -> Result<()> {
-    let responses = vec!["Hello! <refer to=\"sip:123\"/> How are you? <hangup/>".to_string()];
-    let provider = Arc::new(TestProvider::new(responses));
+async fn handler_requeries_after_rag() -> Result<()> {
+    let rag_instruction = r#"{"tools": [{"name": "rag", "query": "policy"}]}"#;
+    let provider = Arc::new(TestProvider::new(vec![
+        rag_instruction.to_string(),
+        "Final answer".to_string(),
+    ]));
+    let rag = Arc::new(RecordingRag::new());
     let mut handler = LlmHandler::with_provider(
         LlmConfig::default(),
         provider,
-        Arc::new(NoopRagRetriever),
+        rag.clone(),
         crate::playbook::InterruptionConfig::default(),
         None,
         HashMap::new(),
@@ -349,77 +305,54 @@ async fn test_xml_tools_and_sentence_splitting() -> Result<()> {
     );
 
     let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
+        track_id: "track-2".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "hi".to_string(),
+        text: "reep".to_string(),
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
 
     let commands = handler.on_event(&event).await?;
-
-    // Expected commands:
-    // 1. TTS "Hello! "
-    // 2. Refer "sip:123"
-    // 3. TTS " How are you? "
-    // 4. Empty TTS with auto_hangup (no separate Hangup command anymore)
-    assert_eq!(commands.len(), 4);
-
-    if let Command::Tts {
-        text,
-        play_id: pid1,
-        ..
-    } = &commands[0]
-    {
-        assert!(text.contains("Hello"));
-        assert!(pid1.is_some());
-
-        if let Command::Refer { callee, .. } = &commands[1] {
-            assert_eq!(callee, "sip:123");
-        } else {
-            panic!("Expected Refer");
-        }
-
-        if let Command::Tts {
+    assert!(matches!(
+        commands.get(0),
+        Some(Command::Tts {
             text,
-            play_id: pid2,
-            ..
-        } = &commands[2]
-        {
-            assert!(text.contains("How are you"));
-            assert_eq!(*pid1, *pid2); // Same play_id
-        } else {
-            panic!("Expected Tts");
-        }
-
-        if let Command::Tts {
-            auto_hangup: Some(true),
-            end_of_stream: Some(true),
+            wait_input_timeout: Some(timeout),
             ..
-        } = &commands[3]
-        {
-            // Good - auto_hangup TTS instead of separate Hangup command
-        } else {
-            panic!("Expected Tts with auto_hangup and end_of_stream");
-        }
-    } else {
-        panic!("Expected Tts");
-    }
+        }) if text == "Final answer" && *timeout == 10000
+    ));
+    assert_eq!(rag.recorded_queries(), vec!["policy".to_string()]);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_interruption_logic() -> Result<()> {
-    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
+async fn handler_injects_rag_result_with_template() -> Result<()> {
+    let rag_instruction =
+        r#"{"tools": [{"name": "rag", "query": "refund policy", "source": "faq"}]}"#;
+    let provider = Arc::new(TestProvider::new(vec![
+        rag_instruction.to_string(),
+        "Refunds take five days".to_string(),
+    ]));
+    let config = LlmConfig {
+        rag: Some(crate::playbook::RagConfig {
+            injection_role: Some("user".to_string()),
+            injection_template: Some(
+                "<context source=\"{source}\" query=\"{query}\">{result}</context>".to_string(),
+            ),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
     let mut handler = LlmHandler::with_provider(
-        LlmConfig::default(),
+        config,
         provider,
-        Arc::new(NoopRagRetriever),
+        Arc::new(RecordingRag::new()),
         crate::playbook::InterruptionConfig::default(),
         None,
         HashMap::new(),
@@ -429,58 +362,60 @@ async fn test_interruption_logic() -> Result<()> {
         None,
     );
 
-    // 1. Trigger a response
     let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
+        track_id: "track-rag-template".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "hello".to_string(),
+        text: "how do refunds work".to_string(),
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
-    handler.on_event(&event).await?;
-    assert!(handler.is_speaking);
-
-    // Sleep to bypass the 800ms interruption guard
-    tokio::time::sleep(std::time::Duration::from_millis(850)).await;
 
-    // 2. Simulate user starting to speak (AsrDelta)
-    let event = SessionEvent::AsrDelta {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 0,
-        start_time: None,
-        end_time: None,
-        text: "I...".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
-    let commands = handler.on_event(&event).await?;
-    assert_eq!(commands.len(), 1);
-    assert!(matches!(commands[0], Command::Interrupt { .. }));
-    assert!(!handler.is_speaking);
+    handler.on_event(&event).await?;
+    let injected = handler
+        .history
+        .iter()
+        .find(|msg| msg.content.starts_with("<context"))
+        .expect("rag result should be injected");
+    assert_eq!(injected.role, "user");
+    assert_eq!(
+        injected.content,
+        "<context source=\"faq\" query=\"refund policy\">retrieved refund policy</context>"
+    );
+    assert!(
+        !handler
+            .history
+            .iter()
+            .any(|msg| msg.content.starts_with("RAG result for"))
+    );
 
     Ok(())
 }
 
+/// Placeholders inside the query or the retrieved text are not substituted
+/// again, and a "tool" role falls back to "system"
 #[tokio::test]
-async fn test_rag_iteration_limit() -> Result<()> {
-    // Provider that always returns a RAG tool call
-    let rag_instruction = r#"{"tools": [{"name": "rag", "query": "endless"}]}"#;
+async fn handler_renders_rag_template_once() -> Result<()> {
+    let rag_instruction =
+        r#"{"tools": [{"name": "rag", "query": "{source} codes", "source": "faq"}]}"#;
     let provider = Arc::new(TestProvider::new(vec![
         rag_instruction.to_string(),
-        rag_instruction.to_string(),
-        rag_instruction.to_string(),
-        rag_instruction.to_string(),
-        "Should not reach here".to_string(),
+        "Here are the codes".to_string(),
     ]));
-
+    let config = LlmConfig {
+        rag: Some(crate::playbook::RagConfig {
+            injection_role: Some("tool".to_string()),
+            injection_template: Some("<context query=\"{query}\">{result}</context>".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
     let mut handler = LlmHandler::with_provider(
-        LlmConfig::default(),
+        config,
         provider,
         Arc::new(RecordingRag::new()),
         crate::playbook::InterruptionConfig::default(),
@@ -493,178 +428,264 @@ async fn test_rag_iteration_limit() -> Result<()> {
     );
 
     let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
+        track_id: "track-rag-once".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "loop".to_string(),
+        text: "which codes are there".to_string(),
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
 
-    let commands = handler.on_event(&event).await?;
-    // After 3 attempts (MAX_RAG_ATTEMPTS), it should stop and return the last raw response
-    assert_eq!(commands.len(), 1);
-    if let Command::Tts { text, .. } = &commands[0] {
-        assert_eq!(text, rag_instruction);
-    }
+    handler.on_event(&event).await?;
+    let injected = handler
+        .history
+        .iter()
+        .find(|msg| msg.content.starts_with("<context"))
+        .expect("rag result should be injected");
+    assert_eq!(injected.role, "system");
+    assert_eq!(
+        injected.content,
+        "<context query=\"{source} codes\">retrieved {source} codes</context>"
+    );
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_follow_up_logic() -> Result<()> {
-    use std::time::Duration;
-
-    // 1. Setup handler with follow-up config
-    let follow_up_config = super::super::FollowUpConfig {
-        timeout: 100, // 100ms for testing
-        max_count: 2,
-    };
+async fn handler_renders_call_variables_into_system_prompt() -> Result<()> {
+    use crate::app::AppStateBuilder;
+    use crate::call::{ActiveCall, ActiveCallType};
+    use crate::config::Config;
+    use crate::media::track::TrackConfig;
+    use tokio_util::sync::CancellationToken;
 
-    // Provider that returns responses for follow-ups
-    let provider = Arc::new(TestProvider::new(vec![
-        "Follow up 1".to_string(),
-        "Follow up 2".to_string(),
-        "Response to user".to_string(),
-    ]));
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await?;
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        CancellationToken::new(),
+        "test-prompt-vars".to_string(),
+        app_state.invitation.clone(),
+        app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
+    {
+        let mut state = active_call.call_state.write().await;
+        state.option = Some(crate::CallOption {
+            caller: Some("sip:1001@example.com".to_string()),
+            ..Default::default()
+        });
+        state
+            .extras
+            .get_or_insert_with(HashMap::new)
+            .insert("customer_name".to_string(), serde_json::json!("Ana"));
+    }
 
+    let config = LlmConfig {
+        greeting: Some("Hi".to_string()),
+        prompt: Some(
+            "Customer {{ customer_name }} calling from {{ caller }} on call {{ call_id }}. \
+             Plan: {{ plan }}"
+                .to_string(),
+        ),
+        ..Default::default()
+    };
     let mut handler = LlmHandler::with_provider(
-        LlmConfig::default(),
-        provider,
+        config,
+        Arc::new(TestProvider::new(vec![])),
         Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
-        Some(follow_up_config),
+        None,
         HashMap::new(),
         None,
         None,
         None,
         None,
     );
+    handler.set_call(active_call.clone()).await;
+    handler.on_start().await?;
 
-    // 2. Simulate initial interaction end
-    handler.last_interaction_at = std::time::Instant::now();
-    handler.is_speaking = false;
+    let system_prompt = &handler.history[0].content;
+    assert!(
+        system_prompt.starts_with(
+            "Customer Ana calling from sip:1001@example.com on call test-prompt-vars. \
+             Plan: {{ plan }}"
+        ),
+        "{}",
+        system_prompt
+    );
+    Ok(())
+}
 
-    // 3. Silence < timeout
-    let event = SessionEvent::Silence {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        start_time: 0,
-        duration: 50,
-        samples: None,
-    };
-    let commands = handler.on_event(&event).await?;
-    assert!(commands.is_empty(), "Should not trigger if < timeout");
+#[tokio::test]
+async fn handler_saves_llm_trace_in_call_extras() -> Result<()> {
+    use crate::app::AppStateBuilder;
+    use crate::call::{ActiveCall, ActiveCallType};
+    use crate::config::Config;
+    use crate::media::track::TrackConfig;
+    use tokio_util::sync::CancellationToken;
 
-    // 4. Silence >= timeout
-    tokio::time::sleep(Duration::from_millis(110)).await;
-    // We need to act as if time passed. logic uses last_interaction_at.elapsed()
-    // Wait, handler.last_interaction_at was set to now(). Sleep ensures elapsed() > 100ms.
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await?;
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        CancellationToken::new(),
+        "test-llm-trace".to_string(),
+        app_state.invitation.clone(),
+        app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
 
-    let event = SessionEvent::Silence {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        start_time: 0,
-        duration: 100,
-        samples: None,
+    let provider = Arc::new(TestProvider::new(vec![
+        r#"{"tools": [{"name": "rag", "query": "opening hours"}]}"#.to_string(),
+        "We open at nine".to_string(),
+    ]));
+    let config = LlmConfig {
+        save_llm_trace: Some(true),
+        ..Default::default()
     };
-    let commands = handler.on_event(&event).await?;
-    assert_eq!(commands.len(), 1, "Should trigger follow-up 1");
-    if let Command::Tts { text, .. } = &commands[0] {
-        assert_eq!(text, "Follow up 1");
-    }
-    assert_eq!(handler.consecutive_follow_ups, 1);
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider,
+        Arc::new(RecordingRag::new()),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    handler.call = Some(active_call.clone());
 
-    // 4b. Simulate bot finishing speaking Follow up 1
-    // generate_response sets is_speaking = true. We need to clear it.
-    let event = SessionEvent::TrackEnd {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        play_id: None,
-        duration: 100,
-        ssrc: 0,
-    };
-    handler.on_event(&event).await?;
+    handler.handle_asr_final("when do you open", None).await?;
     assert!(
-        !handler.is_speaking,
-        "Bot should not be speaking after TrackEnd"
+        active_call
+            .call_state
+            .read()
+            .await
+            .extras
+            .as_ref()
+            .is_none_or(|extras| !extras.contains_key("llm_trace")),
+        "trace is only written at hangup"
     );
+    handler
+        .on_event(&SessionEvent::Hangup {
+            track_id: "test-llm-trace".to_string(),
+            timestamp: 0,
+            reason: None,
+            initiator: None,
+            start_time: String::new(),
+            hangup_time: String::new(),
+            answer_time: None,
+            ringing_time: None,
+            from: None,
+            to: None,
+            extra: None,
+            refer: None,
+        })
+        .await?;
 
-    // Wait again for timeout
-    tokio::time::sleep(Duration::from_millis(110)).await;
-    let event = SessionEvent::Silence {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        start_time: 0,
-        duration: 100,
-        samples: None,
-    };
-    let commands = handler.on_event(&event).await?;
-    assert_eq!(commands.len(), 1, "Should trigger follow-up 2");
-    if let Command::Tts { text, .. } = &commands[0] {
-        assert_eq!(text, "Follow up 2");
-    }
-    assert_eq!(handler.consecutive_follow_ups, 2);
-
-    // 5b. Simulate bot finishing speaking Follow up 2
-    let event = SessionEvent::TrackEnd {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        play_id: None,
-        duration: 100,
-        ssrc: 0,
-    };
-    handler.on_event(&event).await?;
+    let state = active_call.call_state.read().await;
+    let trace = state
+        .extras
+        .as_ref()
+        .and_then(|extras| extras.get("llm_trace"))
+        .and_then(|trace| trace.as_array())
+        .expect("trace saved in the call extras");
+    let steps: Vec<&str> = trace
+        .iter()
+        .map(|step| step["step"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            "llm_call_start",
+            "llm_response",
+            "tool_invocation",
+            "rag_result",
+            "llm_call_start",
+            "llm_response",
+        ]
+    );
+    let messages = trace[0]["messages"].as_array().unwrap();
+    assert_eq!(messages.last().unwrap()["content"], "when do you open");
+    // The second request only carries what was added after the first
+    let messages = trace[4]["messages"].as_array().unwrap();
+    assert!(messages.iter().all(|m| m["content"] != "when do you open"));
+    assert!(!messages.is_empty());
+    assert_eq!(trace[2]["data"]["tool"], "Rag");
+    assert_eq!(trace[3]["data"]["result"], "retrieved opening hours");
+    assert_eq!(trace[5]["data"]["response"], "We open at nine");
+    Ok(())
+}
 
-    // 6. Max count reached
-    tokio::time::sleep(Duration::from_millis(110)).await;
-    let event = SessionEvent::Silence {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        start_time: 0,
-        duration: 100,
-        samples: None,
-    };
-    let commands = handler.on_event(&event).await?;
-    assert_eq!(commands.len(), 1, "Should hangup after max count");
-    assert!(matches!(commands[0], Command::Hangup { .. }));
+/// Replies with a fixed text and an OpenAI style usage block
+struct UsageProvider;
 
-    handler.consecutive_follow_ups = 2; // Artificially set high
-    let event = SessionEvent::AsrFinal {
-        track_id: "t1".to_string(),
-        timestamp: 0,
-        index: 0,
-        start_time: None,
-        end_time: None,
-        text: "User speaks".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
+#[async_trait]
+impl LlmProvider for UsageProvider {
+    async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+        Ok("Sure.".to_string())
+    }
 
-    let _ = handler.on_event(&event).await?;
-    assert_eq!(
-        handler.consecutive_follow_ups, 0,
-        "Should reset count on AsrFinal"
-    );
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let usage = LlmUsage::from_openai(&serde_json::json!({
+            "prompt_tokens": 120,
+            "completion_tokens": 8,
+            "total_tokens": 128,
+        }))
+        .unwrap();
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content("Sure.".to_string()));
+            yield Ok(LlmStreamEvent::Usage(usage));
+        };
+        Ok(Box::pin(s))
+    }
+}
 
-    Ok(())
+fn usage_events(rx: &mut crate::event::EventReceiver) -> Vec<serde_json::Value> {
+    std::iter::from_fn(|| rx.try_recv().ok())
+        .filter_map(|event| match event {
+            SessionEvent::Metrics { key, data, .. } if key == "llm_usage" => Some(data),
+            _ => None,
+        })
+        .collect()
 }
 
 #[tokio::test]
-async fn test_interruption_protection_period() -> Result<()> {
-    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
-    let mut config = crate::playbook::InterruptionConfig::default();
-    config.ignore_first_ms = Some(800);
-
+async fn handler_accumulates_llm_usage() -> Result<()> {
     let mut handler = LlmHandler::with_provider(
         LlmConfig::default(),
-        provider,
+        Arc::new(UsageProvider),
         Arc::new(NoopRagRetriever),
-        config,
+        crate::playbook::InterruptionConfig::default(),
         None,
         HashMap::new(),
         None,
@@ -672,54 +693,59 @@ async fn test_interruption_protection_period() -> Result<()> {
         None,
         None,
     );
+    let (event_sender, mut rx) = tokio::sync::broadcast::channel(64);
+    handler.set_event_sender(event_sender);
 
-    // 1. Trigger a response
-    let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 0,
-        start_time: None,
-        end_time: None,
-        text: "hello".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
-    handler.on_event(&event).await?;
-    assert!(handler.is_speaking);
+    handler.handle_asr_final("book a table", None).await?;
+    handler.handle_asr_final("for two", None).await?;
 
-    // 2. Simulate user starting to speak immediately (AsrDelta)
-    let event = SessionEvent::AsrDelta {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 0,
-        start_time: None,
-        end_time: None,
-        text: "I...".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
-    let commands = handler.on_event(&event).await?;
-    // Should be ignored due to protection period
-    assert_eq!(commands.len(), 0);
-    assert!(handler.is_speaking);
+    assert_eq!(
+        handler.usage(),
+        LlmUsage {
+            prompt_tokens: 240,
+            completion_tokens: 16,
+            estimated: false,
+        }
+    );
+    let events = usage_events(&mut rx);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["turn"]["total_tokens"], 128);
+    assert_eq!(events[1]["turn"]["prompt_tokens"], 120);
+    assert_eq!(events[1]["total"]["prompt_tokens"], 240);
+    assert_eq!(events[1]["total"]["completion_tokens"], 16);
+    assert_eq!(events[1]["total"]["total_tokens"], 256);
 
+    handler
+        .on_event(&SessionEvent::Hangup {
+            track_id: "track-usage".to_string(),
+            timestamp: 0,
+            reason: None,
+            initiator: None,
+            start_time: String::new(),
+            hangup_time: String::new(),
+            answer_time: None,
+            ringing_time: None,
+            from: None,
+            to: None,
+            extra: None,
+            refer: None,
+        })
+        .await?;
+    let events = usage_events(&mut rx);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["final"], true);
+    assert_eq!(events[0]["total"]["total_tokens"], 256);
     Ok(())
 }
 
 #[tokio::test]
-async fn test_interruption_filler_word() -> Result<()> {
-    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
-    let mut config = crate::playbook::InterruptionConfig::default();
-    config.filler_word_filter = Some(true);
-    config.ignore_first_ms = Some(0); // Disable protection period for this test
-
+async fn handler_estimates_usage_without_provider_usage() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Twelve chars".to_string()]));
     let mut handler = LlmHandler::with_provider(
         LlmConfig::default(),
         provider,
         Arc::new(NoopRagRetriever),
-        config,
+        crate::playbook::InterruptionConfig::default(),
         None,
         HashMap::new(),
         None,
@@ -728,68 +754,90 @@ async fn test_interruption_filler_word() -> Result<()> {
         None,
     );
 
-    // 1. Trigger a response
-    let event = SessionEvent::AsrFinal {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 0,
-        start_time: None,
-        end_time: None,
-        text: "hello".to_string(),
-        is_filler: None,
-        confidence: None,
-        task_id: None,
-    };
-    handler.on_event(&event).await?;
-    assert!(handler.is_speaking);
+    handler.handle_asr_final("hello", None).await?;
 
-    // Sleep to bypass the 500ms stale event guard
-    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    let usage = handler.usage();
+    assert!(usage.estimated);
+    assert_eq!(usage.completion_tokens, 3);
+    assert!(usage.prompt_tokens > 0);
+    Ok(())
+}
 
-    // 2. Simulate user saying "uh" (filler)
-    let event = SessionEvent::AsrDelta {
-        track_id: "test".to_string(),
-        timestamp: 0,
-        index: 0,
-        start_time: None,
-        end_time: None,
-        text: "uh".to_string(),
-        is_filler: Some(true),
-        confidence: None,
-        task_id: None,
+struct SlowRag;
+
+#[async_trait]
+impl RagRetriever for SlowRag {
+    async fn retrieve(&self, query: &str) -> Result<String> {
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        Ok(format!("retrieved {}", query))
+    }
+}
+
+#[tokio::test]
+async fn handler_proceeds_after_rag_timeout() -> Result<()> {
+    let rag_instruction = r#"{"tools": [{"name": "rag", "query": "policy"}]}"#;
+    let provider = Arc::new(TestProvider::new(vec![
+        rag_instruction.to_string(),
+        "Sorry, I could not find that".to_string(),
+    ]));
+    let config = LlmConfig {
+        rag: Some(crate::playbook::RagConfig {
+            timeout_ms: Some(50),
+            ..Default::default()
+        }),
+        ..Default::default()
     };
-    let commands = handler.on_event(&event).await?;
-    // Should be ignored
-    assert_eq!(commands.len(), 0);
-    assert!(handler.is_speaking);
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider,
+        Arc::new(SlowRag),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
 
-    // 3. Simulate user saying "Wait" (not filler)
-    let event = SessionEvent::AsrDelta {
-        track_id: "test".to_string(),
+    let event = SessionEvent::AsrFinal {
+        track_id: "track-rag-timeout".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "Wait".to_string(),
-        is_filler: Some(false),
+        text: "what is the policy".to_string(),
+        is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
-    let commands = handler.on_event(&event).await?;
-    // Should trigger interruption
-    assert_eq!(commands.len(), 1);
-    assert!(matches!(commands[0], Command::Interrupt { .. }));
+
+    let commands =
+        tokio::time::timeout(std::time::Duration::from_secs(2), handler.on_event(&event)).await??;
+    assert!(matches!(
+        commands.get(0),
+        Some(Command::Tts { text, .. }) if text == "Sorry, I could not find that"
+    ));
+    assert!(handler.history.iter().any(
+        |msg| msg.role == "system" && msg.content.contains("RAG result for policy: no results")
+    ));
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_eou_early_response() -> Result<()> {
+async fn test_greeting_mode_static_then_llm() -> Result<()> {
     let provider = Arc::new(TestProvider::new(vec![
-        "End of Utterance response".to_string(),
+        "Sure, let me check your order.".to_string(),
     ]));
+    let config = LlmConfig {
+        greeting: Some("Hi, this is Acme support.".to_string()),
+        greeting_mode: Some(GreetingMode::StaticThenLlm),
+        ..Default::default()
+    };
     let mut handler = LlmHandler::with_provider(
-        LlmConfig::default(),
+        config,
         provider,
         Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
@@ -801,29 +849,59 @@ async fn test_eou_early_response() -> Result<()> {
         None,
     );
 
-    // 1. Receive EOU
-    let event = SessionEvent::Eou {
+    // The opener is spoken right away without waiting for the model
+    let commands = handler.on_start().await?;
+    assert!(matches!(
+        commands.as_slice(),
+        [Command::Tts { text, .. }] if text == "Hi, this is Acme support."
+    ));
+
+    let event = SessionEvent::AsrFinal {
         track_id: "test".to_string(),
         timestamp: 0,
-        completed: true,
-        interrupt_point: None,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "Where is my order?".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
     };
     let commands = handler.on_event(&event).await?;
-    assert_eq!(commands.len(), 1);
-    if let Command::Tts { text, .. } = &commands[0] {
-        assert_eq!(text, "End of Utterance response");
-    } else {
-        panic!("Expected Tts");
-    }
+    assert!(commands.iter().any(
+        |c| matches!(c, Command::Tts { text, .. } if text.contains("let me check your order"))
+    ));
 
+    // The model saw the opener as its own first turn
+    let turns: Vec<(&str, &str)> = handler
+        .history
+        .iter()
+        .filter(|m| m.role != "system")
+        .map(|m| (m.role.as_str(), m.content.as_str()))
+        .collect();
+    assert_eq!(
+        &turns[..2],
+        &[
+            ("assistant", "Hi, this is Acme support."),
+            ("user", "Where is my order?")
+        ]
+    );
     Ok(())
 }
 
 #[tokio::test]
-async fn test_summary_and_history() -> Result<()> {
-    let provider = Arc::new(TestProvider::new(vec!["Test summary".to_string()]));
+async fn test_greeting_mode_llm_ignores_static_greeting() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec![
+        "Good evening, how can I help?".to_string(),
+    ]));
+    let config = LlmConfig {
+        greeting: Some("Hi, this is Acme support.".to_string()),
+        greeting_mode: Some(GreetingMode::Llm),
+        ..Default::default()
+    };
     let mut handler = LlmHandler::with_provider(
-        LlmConfig::default(),
+        config,
         provider,
         Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
@@ -835,36 +913,113 @@ async fn test_summary_and_history() -> Result<()> {
         None,
     );
 
-    // Add some history
-    handler.history.push(ChatMessage {
-        role: "user".to_string(),
-        content: "Hello".to_string(),
-    });
-    handler.history.push(ChatMessage {
-        role: "assistant".to_string(),
-        content: "Hi there".to_string(),
-    });
+    let commands = handler.on_start().await?;
+    assert!(
+        commands
+            .iter()
+            .any(|c| matches!(c, Command::Tts { text, .. } if text.contains("Good evening")))
+    );
+    assert!(
+        !commands
+            .iter()
+            .any(|c| matches!(c, Command::Tts { text, .. } if text.contains("Acme")))
+    );
+    Ok(())
+}
 
-    let history = handler.get_history().await;
-    assert_eq!(history.len(), 3);
+#[tokio::test]
+async fn test_greeting_picked_by_call_language() -> Result<()> {
+    use crate::app::AppStateBuilder;
+    use crate::call::{ActiveCall, ActiveCallType};
+    use crate::config::Config;
+    use crate::media::track::TrackConfig;
+    use tokio_util::sync::CancellationToken;
 
-    let summary = handler.summarize("Summarize this").await?;
-    assert_eq!(summary, "Test summary");
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await?;
+
+    let greetings = HashMap::from([
+        (
+            "es".to_string(),
+            "¡Hola! ¿En qué puedo ayudarle?".to_string(),
+        ),
+        (
+            "fr".to_string(),
+            "Bonjour, comment puis-je vous aider ?".to_string(),
+        ),
+    ]);
+    let greeting_for = |lang: Option<&str>| {
+        let extras =
+            lang.map(|lang| HashMap::from([("lang".to_string(), serde_json::json!(lang))]));
+        let call = Arc::new(ActiveCall::new(
+            ActiveCallType::Sip,
+            CancellationToken::new(),
+            format!("test-greeting-{}", lang.unwrap_or("default")),
+            app_state.invitation.clone(),
+            app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            extras,
+            None,
+        ));
+        let mut handler = LlmHandler::with_provider(
+            LlmConfig {
+                greeting: Some("Hello, how can I help?".to_string()),
+                greetings: Some(greetings.clone()),
+                ..Default::default()
+            },
+            Arc::new(TestProvider::new(vec![])),
+            Arc::new(NoopRagRetriever),
+            crate::playbook::InterruptionConfig::default(),
+            None,
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        async move {
+            handler.set_call(call).await;
+            match handler.on_start().await?.as_slice() {
+                [Command::Tts { text, .. }] => Ok::<_, anyhow::Error>(text.clone()),
+                other => Err(anyhow!("unexpected commands: {:?}", other)),
+            }
+        }
+    };
 
+    assert_eq!(
+        greeting_for(Some("es")).await?,
+        "¡Hola! ¿En qué puedo ayudarle?"
+    );
+    // Regional variants fall back to their language
+    assert_eq!(
+        greeting_for(Some("fr-CA")).await?,
+        "Bonjour, comment puis-je vous aider ?"
+    );
+    assert_eq!(greeting_for(Some("de")).await?, "Hello, how can I help?");
+    assert_eq!(greeting_for(None).await?, "Hello, how can I help?");
     Ok(())
 }
 
 #[tokio::test]
-async fn test_rolling_summary() -> Result<()> {
+async fn test_full_dialogue_flow() -> Result<()> {
     let responses = vec![
-        "This is the summary of previous conversation.".to_string(),
-        "Response to user input after summary.".to_string(),
+        "Hello! How can I help you today?".to_string(),
+        r#"{"text": "I can help with that. Anything else?", "waitInputTimeout": 5000}"#.to_string(),
+        r#"{"text": "Goodbye!", "tools": [{"name": "hangup", "reason": "completed"}]}"#.to_string(),
     ];
-    let provider = Arc::new(TestProvider::new(responses));
 
-    let mut config = LlmConfig::default();
-    config.features = Some(vec!["rolling_summary".to_string()]);
-    config.summary_limit = Some(4);
+    let provider = Arc::new(TestProvider::new(responses));
+    let config = LlmConfig {
+        greeting: Some("Welcome to the voice assistant.".to_string()),
+        ..Default::default()
+    };
 
     let mut handler = LlmHandler::with_provider(
         config,
@@ -879,67 +1034,1305 @@ async fn test_rolling_summary() -> Result<()> {
         None,
     );
 
-    for i in 1..=12 {
-        let role = if i % 2 == 1 { "user" } else { "assistant" };
-        handler.history.push(ChatMessage {
-            role: role.to_string(),
-            content: format!("Message {}", i),
-        });
+    // 1. Start the dialogue
+    let commands = handler.on_start().await?;
+    assert_eq!(commands.len(), 1);
+    if let Command::Tts { text, .. } = &commands[0] {
+        assert_eq!(text, "Welcome to the voice assistant.");
+    } else {
+        panic!("Expected Tts command");
     }
+
+    // 2. User says something
     let event = SessionEvent::AsrFinal {
         track_id: "test".to_string(),
         timestamp: 0,
         index: 0,
         start_time: None,
         end_time: None,
-        text: "Trigger summary".to_string(),
+        text: "I need help".to_string(),
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
-
     let commands = handler.on_event(&event).await?;
-
+    // "Hello! How can I help you today?" -> split into two + EOS
+    assert_eq!(commands.len(), 3);
     if let Command::Tts { text, .. } = &commands[0] {
-        assert_eq!(text, "Response to user input after summary.");
+        assert!(text.contains("Hello"));
     } else {
-        panic!("Expected Tts");
+        panic!("Expected Tts command");
     }
 
-    assert_eq!(handler.history.len(), 8);
+    // 3. User says something else
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 1,
+        start_time: None,
+        end_time: None,
+        text: "Tell me a joke".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert_eq!(commands.len(), 1);
+    if let Command::Tts {
+        text,
+        wait_input_timeout,
+        ..
+    } = &commands[0]
+    {
+        assert_eq!(text, "I can help with that. Anything else?");
+        assert_eq!(*wait_input_timeout, Some(5000));
+    } else {
+        panic!("Expected Tts command");
+    }
 
-    // Verify system prompt contains summary
-    let system_msg = &handler.history[0];
-    assert_eq!(system_msg.role, "system");
-    assert!(
-        system_msg
-            .content
-            .contains("[Previous Context Summary]: This is the summary of previous conversation.")
-    );
+    // 4. User says goodbye
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 2,
+        start_time: None,
+        end_time: None,
+        text: "That's all, thanks".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    // Should have Tts with auto_hangup
+    assert_eq!(commands.len(), 1);
 
-    assert_eq!(
-        handler.history.last().unwrap().content,
-        "Response to user input after summary."
-    );
-    assert_eq!(
-        handler.history[handler.history.len() - 2].content,
-        "Trigger summary"
-    );
-    assert_eq!(
-        handler.history[handler.history.len() - 3].content,
-        "Message 12"
-    );
+    let has_tts_hangup = commands.iter().any(|c| {
+        matches!(
+            c,
+            Command::Tts {
+                text,
+                auto_hangup: Some(true),
+                ..
+            } if text == "Goodbye!"
+        )
+    });
+
+    assert!(has_tts_hangup);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xml_tools_and_sentence_splitting() -> Result<()> {
+    let responses = vec!["Hello! <refer to=\"sip:123\"/> How are you? <hangup/>".to_string()];
+    let provider = Arc::new(TestProvider::new(responses));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "hi".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+
+    let commands = handler.on_event(&event).await?;
+
+    // Expected commands:
+    // 1. TTS "Hello! "
+    // 2. Refer "sip:123"
+    // 3. TTS " How are you? "
+    // 4. Empty TTS with auto_hangup (no separate Hangup command anymore)
+    assert_eq!(commands.len(), 4);
+
+    if let Command::Tts {
+        text,
+        play_id: pid1,
+        ..
+    } = &commands[0]
+    {
+        assert!(text.contains("Hello"));
+        assert!(pid1.is_some());
+
+        if let Command::Refer { callee, .. } = &commands[1] {
+            assert_eq!(callee, "sip:123");
+        } else {
+            panic!("Expected Refer");
+        }
+
+        if let Command::Tts {
+            text,
+            play_id: pid2,
+            ..
+        } = &commands[2]
+        {
+            assert!(text.contains("How are you"));
+            assert_eq!(*pid1, *pid2); // Same play_id
+        } else {
+            panic!("Expected Tts");
+        }
+
+        if let Command::Tts {
+            auto_hangup: Some(true),
+            end_of_stream: Some(true),
+            ..
+        } = &commands[3]
+        {
+            // Good - auto_hangup TTS instead of separate Hangup command
+        } else {
+            panic!("Expected Tts with auto_hangup and end_of_stream");
+        }
+    } else {
+        panic!("Expected Tts");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interruption_logic() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    // 1. Trigger a response
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "hello".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    handler.on_event(&event).await?;
+    assert!(handler.is_speaking);
+
+    // Sleep to bypass the 800ms interruption guard
+    tokio::time::sleep(std::time::Duration::from_millis(850)).await;
+
+    // 2. Simulate user starting to speak (AsrDelta)
+    let event = SessionEvent::AsrDelta {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "I...".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        stable_text: None,
+        unstable_text: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(commands[0], Command::Interrupt { .. }));
+    assert!(!handler.is_speaking);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rag_iteration_limit() -> Result<()> {
+    // Provider that always returns a RAG tool call
+    let rag_instruction = r#"{"tools": [{"name": "rag", "query": "endless"}]}"#;
+    let provider = Arc::new(TestProvider::new(vec![
+        rag_instruction.to_string(),
+        rag_instruction.to_string(),
+        rag_instruction.to_string(),
+        rag_instruction.to_string(),
+        "Should not reach here".to_string(),
+    ]));
+
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(RecordingRag::new()),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "loop".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+
+    let commands = handler.on_event(&event).await?;
+    // After 3 attempts (MAX_RAG_ATTEMPTS), it should stop and return the last raw response
+    assert_eq!(commands.len(), 1);
+    if let Command::Tts { text, .. } = &commands[0] {
+        assert_eq!(text, rag_instruction);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_follow_up_logic() -> Result<()> {
+    use std::time::Duration;
+
+    // 1. Setup handler with follow-up config
+    let follow_up_config = super::super::FollowUpConfig {
+        timeout: 100, // 100ms for testing
+        max_count: 2,
+        ..Default::default()
+    };
+
+    // Provider that returns responses for follow-ups
+    let provider = Arc::new(TestProvider::new(vec![
+        "Follow up 1".to_string(),
+        "Follow up 2".to_string(),
+        "Response to user".to_string(),
+    ]));
+
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        Some(follow_up_config),
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    // 2. Simulate initial interaction end
+    handler.last_interaction_at = std::time::Instant::now();
+    handler.is_speaking = false;
+
+    // 3. Silence < timeout
+    let event = SessionEvent::Silence {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        start_time: 0,
+        duration: 50,
+        samples: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert!(commands.is_empty(), "Should not trigger if < timeout");
+
+    // 4. Silence >= timeout
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    // We need to act as if time passed. logic uses last_interaction_at.elapsed()
+    // Wait, handler.last_interaction_at was set to now(). Sleep ensures elapsed() > 100ms.
+
+    let event = SessionEvent::Silence {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        start_time: 0,
+        duration: 100,
+        samples: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert_eq!(commands.len(), 1, "Should trigger follow-up 1");
+    if let Command::Tts { text, .. } = &commands[0] {
+        assert_eq!(text, "Follow up 1");
+    }
+    assert_eq!(handler.consecutive_follow_ups, 1);
+
+    // 4b. Simulate bot finishing speaking Follow up 1
+    // generate_response sets is_speaking = true. We need to clear it.
+    let event = SessionEvent::TrackEnd {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        play_id: None,
+        duration: 100,
+        ssrc: 0,
+    };
+    handler.on_event(&event).await?;
+    assert!(
+        !handler.is_speaking,
+        "Bot should not be speaking after TrackEnd"
+    );
+
+    // Wait again for timeout
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    let event = SessionEvent::Silence {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        start_time: 0,
+        duration: 100,
+        samples: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert_eq!(commands.len(), 1, "Should trigger follow-up 2");
+    if let Command::Tts { text, .. } = &commands[0] {
+        assert_eq!(text, "Follow up 2");
+    }
+    assert_eq!(handler.consecutive_follow_ups, 2);
+
+    // 5b. Simulate bot finishing speaking Follow up 2
+    let event = SessionEvent::TrackEnd {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        play_id: None,
+        duration: 100,
+        ssrc: 0,
+    };
+    handler.on_event(&event).await?;
+
+    // 6. Max count reached
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    let event = SessionEvent::Silence {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        start_time: 0,
+        duration: 100,
+        samples: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert_eq!(commands.len(), 1, "Should hangup after max count");
+    assert!(matches!(commands[0], Command::Hangup { .. }));
+
+    handler.consecutive_follow_ups = 2; // Artificially set high
+    let event = SessionEvent::AsrFinal {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "User speaks".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+
+    let _ = handler.on_event(&event).await?;
+    assert_eq!(
+        handler.consecutive_follow_ups, 0,
+        "Should reset count on AsrFinal"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_follow_up_waits_after_max() -> Result<()> {
+    use std::time::Duration;
+
+    let follow_up_config = super::super::FollowUpConfig {
+        timeout: 100,
+        max_count: 1,
+        on_max: super::super::FollowUpExhausted::Wait,
+    };
+    let provider = Arc::new(TestProvider::new(vec![
+        "Are you still there?".to_string(),
+        "Response to user".to_string(),
+    ]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider.clone(),
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        Some(follow_up_config),
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    handler.last_interaction_at = std::time::Instant::now();
+    handler.is_speaking = false;
+
+    let silence = SessionEvent::Silence {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        start_time: 0,
+        duration: 100,
+        samples: None,
+    };
+    let track_end = SessionEvent::TrackEnd {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        play_id: None,
+        duration: 100,
+        ssrc: 0,
+    };
+
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    let commands = handler.on_event(&silence).await?;
+    assert_eq!(commands.len(), 1, "Should trigger the only follow-up");
+    handler.on_event(&track_end).await?;
+
+    // Further silence neither re-queries the LLM nor hangs up
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        let commands = handler.on_event(&silence).await?;
+        assert!(commands.is_empty(), "Should wait silently: {:?}", commands);
+    }
+    assert_eq!(provider.responses.lock().unwrap().len(), 1);
+    assert_eq!(handler.consecutive_follow_ups, 1);
+
+    // The caller speaking resumes the conversation
+    let event = SessionEvent::AsrFinal {
+        track_id: "t1".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "Sorry, I'm back".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    handler.on_event(&event).await?;
+    assert_eq!(handler.consecutive_follow_ups, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interruption_protection_period() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
+    let mut config = crate::playbook::InterruptionConfig::default();
+    config.ignore_first_ms = Some(800);
+
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        config,
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    // 1. Trigger a response
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "hello".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    handler.on_event(&event).await?;
+    assert!(handler.is_speaking);
+
+    // 2. Simulate user starting to speak immediately (AsrDelta)
+    let event = SessionEvent::AsrDelta {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "I...".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        stable_text: None,
+        unstable_text: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    // Should be ignored due to protection period
+    assert_eq!(commands.len(), 0);
+    assert!(handler.is_speaking);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interruption_filler_word() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
+    let mut config = crate::playbook::InterruptionConfig::default();
+    config.filler_word_filter = Some(true);
+    config.ignore_first_ms = Some(0); // Disable protection period for this test
+
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        config,
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    // 1. Trigger a response
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "hello".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    handler.on_event(&event).await?;
+    assert!(handler.is_speaking);
+
+    // Sleep to bypass the 500ms stale event guard
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+
+    // 2. Simulate user saying "uh" (filler)
+    let event = SessionEvent::AsrDelta {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "uh".to_string(),
+        is_filler: Some(true),
+        confidence: None,
+        task_id: None,
+        stable_text: None,
+        unstable_text: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    // Should be ignored
+    assert_eq!(commands.len(), 0);
+    assert!(handler.is_speaking);
+
+    // 3. Simulate user saying "Wait" (not filler)
+    let event = SessionEvent::AsrDelta {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "Wait".to_string(),
+        is_filler: Some(false),
+        confidence: None,
+        task_id: None,
+        stable_text: None,
+        unstable_text: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    // Should trigger interruption
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(commands[0], Command::Interrupt { .. }));
+
+    Ok(())
+}
+
+fn asr_delta(text: &str) -> SessionEvent {
+    SessionEvent::AsrDelta {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: text.to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        stable_text: None,
+        unstable_text: None,
+    }
+}
+
+/// A handler speaking its reply, with barge-in thresholds of 3 words and 100ms
+async fn speaking_handler_with_barge_in_threshold() -> Result<LlmHandler> {
+    let provider = Arc::new(TestProvider::new(vec!["Some long response".to_string()]));
+    let config = crate::playbook::InterruptionConfig {
+        ignore_first_ms: Some(0),
+        min_words: Some(3),
+        min_duration_ms: Some(100),
+        ..Default::default()
+    };
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        config,
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    handler
+        .on_event(&SessionEvent::AsrFinal {
+            track_id: "test".to_string(),
+            timestamp: 0,
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: "hello".to_string(),
+            is_filler: None,
+            confidence: None,
+            task_id: None,
+            raw_text: None,
+        })
+        .await?;
+    assert!(handler.is_speaking);
+    // Past the 500ms stale event guard
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    Ok(handler)
+}
+
+#[tokio::test]
+async fn test_stray_delta_below_barge_in_threshold() -> Result<()> {
+    let mut handler = speaking_handler_with_barge_in_threshold().await?;
+
+    assert!(handler.on_event(&asr_delta("cough")).await?.is_empty());
+    handler
+        .on_event(&SessionEvent::Silence {
+            track_id: "test".to_string(),
+            timestamp: 0,
+            start_time: 0,
+            duration: 0,
+            samples: None,
+        })
+        .await?;
+
+    // The next noise starts over instead of adding to the stray delta
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    assert!(
+        handler
+            .on_event(&asr_delta("tv noise here"))
+            .await?
+            .is_empty()
+    );
+    assert!(handler.is_speaking);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sustained_speech_interrupts_once() -> Result<()> {
+    let mut handler = speaking_handler_with_barge_in_threshold().await?;
+
+    assert!(handler.on_event(&asr_delta("wait")).await?.is_empty());
+    // Enough words, not yet long enough
+    assert!(
+        handler
+            .on_event(&asr_delta("wait I need"))
+            .await?
+            .is_empty()
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+
+    let commands = handler.on_event(&asr_delta("wait I need to")).await?;
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(commands[0], Command::Interrupt { .. }));
+    assert!(!handler.is_speaking);
+
+    let commands = handler
+        .on_event(&asr_delta("wait I need to change"))
+        .await?;
+    assert!(
+        !commands
+            .iter()
+            .any(|c| matches!(c, Command::Interrupt { .. }))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_barge_in_starts_over_with_next_playback() -> Result<()> {
+    let mut handler = speaking_handler_with_barge_in_threshold().await?;
+
+    assert!(
+        handler
+            .on_event(&asr_delta("wait I need"))
+            .await?
+            .is_empty()
+    );
+    handler
+        .on_event(&SessionEvent::TrackEnd {
+            track_id: "test".to_string(),
+            timestamp: 0,
+            play_id: None,
+            duration: 100,
+            ssrc: 0,
+        })
+        .await?;
+    handler
+        .on_event(&SessionEvent::TrackStart {
+            track_id: "test".to_string(),
+            timestamp: 0,
+            play_id: None,
+        })
+        .await?;
+    assert!(handler.is_speaking);
+
+    // Speech heard over the previous playback doesn't count towards this one
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    assert!(
+        handler
+            .on_event(&asr_delta("wait I need"))
+            .await?
+            .is_empty()
+    );
+    assert!(handler.is_speaking);
+    Ok(())
+}
+
+#[test]
+fn test_count_words() {
+    assert_eq!(count_words("wait I need"), 3);
+    assert_eq!(count_words("  "), 0);
+    assert_eq!(count_words("等一下"), 3);
+    assert_eq!(count_words("ok 好的"), 3);
+}
+
+#[tokio::test]
+async fn test_eou_early_response() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec![
+        "End of Utterance response".to_string(),
+    ]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    // 1. Receive EOU
+    let event = SessionEvent::Eou {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        completed: true,
+        interrupt_point: None,
+    };
+    let commands = handler.on_event(&event).await?;
+    assert_eq!(commands.len(), 1);
+    if let Command::Tts { text, .. } = &commands[0] {
+        assert_eq!(text, "End of Utterance response");
+    } else {
+        panic!("Expected Tts");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_summary_and_history() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Test summary".to_string()]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    // Add some history
+    handler.history.push(ChatMessage {
+        role: "user".to_string(),
+        content: "Hello".to_string(),
+    });
+    handler.history.push(ChatMessage {
+        role: "assistant".to_string(),
+        content: "Hi there".to_string(),
+    });
+
+    let history = handler.get_history().await;
+    assert_eq!(history.len(), 3);
+
+    let summary = handler.summarize("Summarize this").await?;
+    assert_eq!(summary, "Test summary");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rolling_summary() -> Result<()> {
+    let responses = vec![
+        "This is the summary of previous conversation.".to_string(),
+        "Response to user input after summary.".to_string(),
+    ];
+    let provider = Arc::new(TestProvider::new(responses));
+
+    let mut config = LlmConfig::default();
+    config.features = Some(vec!["rolling_summary".to_string()]);
+    config.summary_limit = Some(4);
+
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    for i in 1..=12 {
+        let role = if i % 2 == 1 { "user" } else { "assistant" };
+        handler.history.push(ChatMessage {
+            role: role.to_string(),
+            content: format!("Message {}", i),
+        });
+    }
+    let event = SessionEvent::AsrFinal {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "Trigger summary".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+
+    let commands = handler.on_event(&event).await?;
+
+    if let Command::Tts { text, .. } = &commands[0] {
+        assert_eq!(text, "Response to user input after summary.");
+    } else {
+        panic!("Expected Tts");
+    }
+
+    assert_eq!(handler.history.len(), 8);
+
+    // Verify system prompt contains summary
+    let system_msg = &handler.history[0];
+    assert_eq!(system_msg.role, "system");
+    assert!(
+        system_msg
+            .content
+            .contains("[Previous Context Summary]: This is the summary of previous conversation.")
+    );
+
+    assert_eq!(
+        handler.history.last().unwrap().content,
+        "Response to user input after summary."
+    );
+    assert_eq!(
+        handler.history[handler.history.len() - 2].content,
+        "Trigger summary"
+    );
+    assert_eq!(
+        handler.history[handler.history.len() - 3].content,
+        "Message 12"
+    );
+
+    Ok(())
+}
+
+/// Answers summary requests with a numbered summary, failing them all with
+/// `fail`, and records every history sent for a reply. Each summary request
+/// must carry the previous summary.
+struct SummarizingProvider {
+    fail: bool,
+    summaries: Mutex<usize>,
+    requests: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+#[async_trait]
+impl LlmProvider for SummarizingProvider {
+    async fn call(&self, config: &LlmConfig, history: &[ChatMessage]) -> Result<String> {
+        assert!(config.json_mode.is_none(), "summary sent in JSON mode");
+        assert!(config.function_calling.is_none(), "summary sent with tools");
+        if self.fail {
+            return Err(anyhow::anyhow!("summary unavailable"));
+        }
+        let mut summaries = self.summaries.lock().unwrap();
+        if *summaries > 0 {
+            assert!(
+                history[0]
+                    .content
+                    .ends_with(&format!("Summary {}", summaries))
+            );
+        }
+        *summaries += 1;
+        Ok(format!("Summary {}", summaries))
+    }
+
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        history: &[ChatMessage],
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        self.requests.lock().unwrap().push(history.to_vec());
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content("Noted.".to_string()));
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[tokio::test]
+async fn test_max_history_messages_bounds_context() -> Result<()> {
+    let provider = Arc::new(SummarizingProvider {
+        fail: false,
+        summaries: Mutex::new(0),
+        requests: Mutex::new(Vec::new()),
+    });
+    let config = LlmConfig {
+        prompt: Some("You book dental appointments.".to_string()),
+        max_history_messages: Some(10),
+        json_mode: Some(true),
+        function_calling: Some(true),
+        ..Default::default()
+    };
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider.clone(),
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let system_prompt = handler.history[0].clone();
+
+    for turn in 1..=30 {
+        handler
+            .handle_asr_final(&format!("Turn {}", turn), None)
+            .await?;
+    }
+
+    let requests = provider.requests.lock().unwrap();
+    assert_eq!(requests.len(), 30);
+    for history in requests.iter() {
+        assert!(history.len() <= 10, "sent {} messages", history.len());
+        assert_eq!(history[0].role, "system");
+        assert!(history[0].content.starts_with(&system_prompt.content));
+    }
+
+    // The latest request carries the summary and the recent turns verbatim
+    let last = requests.last().unwrap();
+    assert!(
+        last[0]
+            .content
+            .contains("[Previous Context Summary]: Summary ")
+    );
+    // Each summary replaces the previous one
+    assert_eq!(
+        last[0]
+            .content
+            .matches("[Previous Context Summary]")
+            .count(),
+        1
+    );
+    assert_eq!(last.last().unwrap().content, "Turn 30");
+    assert_eq!(last[last.len() - 3].content, "Turn 29");
+    assert!(*provider.summaries.lock().unwrap() > 1);
+    assert!(handler.history.len() <= 11);
+    Ok(())
+}
+
+/// A failed summary drops the oldest turns so the limit still holds
+#[tokio::test]
+async fn test_max_history_messages_trims_history_when_summary_fails() -> Result<()> {
+    let provider = Arc::new(SummarizingProvider {
+        fail: true,
+        summaries: Mutex::new(0),
+        requests: Mutex::new(Vec::new()),
+    });
+    let config = LlmConfig {
+        max_history_messages: Some(4),
+        ..Default::default()
+    };
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider.clone(),
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    for turn in 1..=4 {
+        handler
+            .handle_asr_final(&format!("Turn {}", turn), None)
+            .await?;
+    }
+
+    let last = provider.requests.lock().unwrap().last().unwrap().clone();
+    assert_eq!(last.len(), 4);
+    assert_eq!(last[0].role, "system");
+    assert_eq!(last[1].content, "Turn 3");
+    assert_eq!(last[3].content, "Turn 4");
+    assert_eq!(handler.history.len(), 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_set_var_extraction() {
+    let config = LlmConfig::default();
+    let interruption = crate::playbook::InterruptionConfig::default();
+    let provider = Arc::new(TestProvider::new(vec![]));
+    let rag = Arc::new(RecordingRag::new());
+
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider,
+        rag,
+        interruption,
+        None,
+        std::collections::HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    let mut buffer = "Hello <set_var key=\"foo\" value=\"bar\" /> world".to_string();
+    let cmds = handler
+        .extract_streaming_commands(&mut buffer, "test_p", false)
+        .await;
+
+    // It should have extracted "Hello " as TTS command
+    assert_eq!(cmds.len(), 1);
+    if let Command::Tts { text, .. } = &cmds[0] {
+        assert_eq!(text, "Hello ");
+    } else {
+        panic!("Expected TTS command");
+    }
+
+    // The buffer should now start with " world" (leading space might be kept or consumed depending on regex?)
+    // The regex for SetVar consumes the tag.
+    // "Hello " was drained. Tag was drained.
+    // Buffer should contain " world".
+    assert_eq!(buffer, " world");
+}
+
+#[tokio::test]
+async fn test_multiple_set_vars() {
+    let config = LlmConfig::default();
+    let interruption = crate::playbook::InterruptionConfig::default();
+    let provider = Arc::new(TestProvider::new(vec![]));
+    let rag = Arc::new(RecordingRag::new());
+
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider,
+        rag,
+        interruption,
+        None,
+        std::collections::HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+
+    let mut buffer =
+        "<set_var key=\"k1\" value=\"v1\" /><set_var key=\"k2\" value=\"v2\" />".to_string();
+    // First extraction
+    let cmds = handler
+        .extract_streaming_commands(&mut buffer, "test_p", false)
+        .await;
+    assert_eq!(cmds.len(), 0); // No text prefix
+
+    // Should have consumed first tag.
+    // Loop in extract_streaming_commands?
+    // extract_streaming_commands loop:
+    // loop {
+    //    find first occurrence
+    //    if match, process and drain, then CONTINUE loop?
+    // }
+    // Let's check extract_streaming_commands loop logic.
+}
+
+#[tokio::test]
+async fn test_set_var_updates_state() {
+    use crate::app::AppStateBuilder;
+    use crate::call::{ActiveCall, ActiveCallType};
+    use crate::config::Config;
+    use crate::media::track::TrackConfig;
+    use tokio_util::sync::CancellationToken;
+
+    let config = crate::playbook::LlmConfig::default();
+    let interruption = crate::playbook::InterruptionConfig::default();
+    let provider = Arc::new(TestProvider::new(vec![]));
+    let rag = Arc::new(RecordingRag::new());
+
+    // Setup ActiveCall
+    // Use a random port or default config to avoid binding conflicts if any
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await
+        .expect("Failed to build app state");
+
+    let cancel_token = CancellationToken::new();
+    let session_id = "test-session-set-var".to_string();
+    let track_config = TrackConfig::default();
+
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        cancel_token,
+        session_id,
+        app_state.invitation.clone(),
+        app_state.clone(),
+        track_config,
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
 
-    Ok(())
+    let mut handler = LlmHandler::with_provider(
+        config,
+        provider,
+        rag,
+        interruption,
+        None, // global_follow_up
+        std::collections::HashMap::new(),
+        None, // dtmf
+        None, // dtmf_collectors
+        None, // initial_scene_id
+        None, // sip_config
+    );
+    handler.call = Some(active_call.clone());
+
+    // Initial buffer with set_var
+    let mut buffer = "<set_var key=\"my_key\" value=\"my_val\" />".to_string();
+
+    // We called extract_streaming_commands. It should process the tag and update state.
+    handler
+        .extract_streaming_commands(&mut buffer, "p_id", false)
+        .await;
+
+    // Check strict equality of buffer (should be drained) or at least the tag removed
+    assert!(
+        buffer.is_empty(),
+        "Buffer should be empty after processing set_var, got: '{}'",
+        buffer
+    );
+
+    // Check ActiveCall state
+    let state = active_call.call_state.read().await;
+    let extras = state
+        .extras
+        .as_ref()
+        .expect("extras should be initialized/set");
+
+    assert_eq!(
+        extras.get("my_key").unwrap(),
+        &serde_json::Value::String("my_val".to_string()),
+        "Variable my_key should be set to my_val"
+    );
 }
 
 #[tokio::test]
-async fn test_set_var_extraction() {
-    let config = LlmConfig::default();
+async fn test_set_var_with_sip_headers() {
+    use crate::app::AppStateBuilder;
+    use crate::call::{ActiveCall, ActiveCallType};
+    use crate::config::Config;
+    use crate::media::track::TrackConfig;
+    use tokio_util::sync::CancellationToken;
+
+    let config = crate::playbook::LlmConfig::default();
     let interruption = crate::playbook::InterruptionConfig::default();
     let provider = Arc::new(TestProvider::new(vec![]));
     let rag = Arc::new(RecordingRag::new());
 
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await
+        .expect("Failed to build app state");
+
+    let cancel_token = CancellationToken::new();
+    let session_id = "test-session-sip-headers".to_string();
+    let track_config = TrackConfig::default();
+
+    // Initialize with some extracted headers
+    let mut initial_extras = std::collections::HashMap::new();
+    initial_extras.insert("X-CID".to_string(), serde_json::json!("123456"));
+
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        cancel_token,
+        session_id,
+        app_state.invitation.clone(),
+        app_state.clone(),
+        track_config,
+        None,
+        false,
+        None,
+        Some(initial_extras),
+        None,
+    ));
+
     let mut handler = LlmHandler::with_provider(
         config,
         provider,
@@ -952,34 +2345,80 @@ async fn test_set_var_extraction() {
         None,
         None,
     );
+    handler.call = Some(active_call.clone());
 
-    let mut buffer = "Hello <set_var key=\"foo\" value=\"bar\" /> world".to_string();
-    let cmds = handler
-        .extract_streaming_commands(&mut buffer, "test_p", false)
+    // Simulate LLM setting _hangup_headers for BYE
+    let mut buffer = r#"<set_var key="_hangup_headers" value='{"X-Hangup-Reason":"completed","X-Duration":"120"}' />"#.to_string();
+
+    handler
+        .extract_streaming_commands(&mut buffer, "p_id", true)
         .await;
 
-    // It should have extracted "Hello " as TTS command
-    assert_eq!(cmds.len(), 1);
-    if let Command::Tts { text, .. } = &cmds[0] {
-        assert_eq!(text, "Hello ");
-    } else {
-        panic!("Expected TTS command");
-    }
+    assert!(
+        buffer.is_empty(),
+        "Buffer should be empty, got: '{}'",
+        buffer
+    );
 
-    // The buffer should now start with " world" (leading space might be kept or consumed depending on regex?)
-    // The regex for SetVar consumes the tag.
-    // "Hello " was drained. Tag was drained.
-    // Buffer should contain " world".
-    assert_eq!(buffer, " world");
+    let state = active_call.call_state.read().await;
+    let extras = state.extras.as_ref().unwrap();
+
+    // Verify original header still exists
+    assert_eq!(extras.get("X-CID").unwrap(), &serde_json::json!("123456"));
+
+    // Verify new _hangup_headers was set
+    assert!(extras.contains_key("_hangup_headers"));
+    let headers_value = extras.get("_hangup_headers").unwrap();
+    assert_eq!(
+        headers_value,
+        &serde_json::Value::String(
+            r#"{"X-Hangup-Reason":"completed","X-Duration":"120"}"#.to_string()
+        )
+    );
 }
 
 #[tokio::test]
-async fn test_multiple_set_vars() {
-    let config = LlmConfig::default();
+async fn test_http_command_in_stream() {
+    use crate::app::AppStateBuilder;
+    use crate::call::{ActiveCall, ActiveCallType};
+    use crate::config::Config;
+    use crate::media::track::TrackConfig;
+    use tokio_util::sync::CancellationToken;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let config = crate::playbook::LlmConfig::default();
     let interruption = crate::playbook::InterruptionConfig::default();
     let provider = Arc::new(TestProvider::new(vec![]));
     let rag = Arc::new(RecordingRag::new());
 
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await
+        .expect("Failed to build app state");
+
+    let cancel_token = CancellationToken::new();
+    let session_id = "test-session-http".to_string();
+    let track_config = TrackConfig::default();
+
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        cancel_token,
+        session_id,
+        app_state.invitation.clone(),
+        app_state.clone(),
+        track_config,
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
+
     let mut handler = LlmHandler::with_provider(
         config,
         provider,
@@ -992,40 +2431,56 @@ async fn test_multiple_set_vars() {
         None,
         None,
     );
+    handler.call = Some(active_call.clone());
 
-    let mut buffer =
-        "<set_var key=\"k1\" value=\"v1\" /><set_var key=\"k2\" value=\"v2\" />".to_string();
-    // First extraction
-    let cmds = handler
-        .extract_streaming_commands(&mut buffer, "test_p", false)
+    // Start mock server
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/data"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "status": "success",
+            "data": "test_value"
+        })))
+        .mount(&mock_server)
         .await;
-    assert_eq!(cmds.len(), 0); // No text prefix
 
-    // Should have consumed first tag.
-    // Loop in extract_streaming_commands?
-    // extract_streaming_commands loop:
-    // loop {
-    //    find first occurrence
-    //    if match, process and drain, then CONTINUE loop?
-    // }
-    // Let's check extract_streaming_commands loop logic.
+    let url = format!("{}/api/data", mock_server.uri());
+    let mut buffer = format!(r#"Check this <http url="{}" />"#, url);
+
+    let initial_history_len = handler.history.len();
+
+    handler
+        .extract_streaming_commands(&mut buffer, "p_id", true)
+        .await;
+
+    // History should have system message with HTTP response
+    assert!(
+        handler.history.len() > initial_history_len,
+        "History should grow after HTTP call"
+    );
+
+    let last_msg = handler.history.last().unwrap();
+    assert_eq!(last_msg.role, "system");
+    assert!(last_msg.content.contains("HTTP GET"));
+    assert!(last_msg.content.contains("200"));
+    assert!(last_msg.content.contains("test_value"));
 }
 
 #[tokio::test]
-async fn test_set_var_updates_state() {
+async fn test_http_command_post_with_body() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
     use tokio_util::sync::CancellationToken;
+    use wiremock::matchers::{body_string, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     let config = crate::playbook::LlmConfig::default();
     let interruption = crate::playbook::InterruptionConfig::default();
     let provider = Arc::new(TestProvider::new(vec![]));
     let rag = Arc::new(RecordingRag::new());
 
-    // Setup ActiveCall
-    // Use a random port or default config to avoid binding conflicts if any
     let mut app_config = Config::default();
     app_config.udp_port = 0;
 
@@ -1036,7 +2491,7 @@ async fn test_set_var_updates_state() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-set-var".to_string();
+    let session_id = "test-session-http-post".to_string();
     let track_config = TrackConfig::default();
 
     let active_call = Arc::new(ActiveCall::new(
@@ -1058,46 +2513,42 @@ async fn test_set_var_updates_state() {
         provider,
         rag,
         interruption,
-        None, // global_follow_up
+        None,
         std::collections::HashMap::new(),
-        None, // dtmf
-        None, // dtmf_collectors
-        None, // initial_scene_id
-        None, // sip_config
+        None,
+        None,
+        None,
+        None,
     );
     handler.call = Some(active_call.clone());
 
-    // Initial buffer with set_var
-    let mut buffer = "<set_var key=\"my_key\" value=\"my_val\" />".to_string();
-
-    // We called extract_streaming_commands. It should process the tag and update state.
-    handler
-        .extract_streaming_commands(&mut buffer, "p_id", false)
+    // Start mock server
+    let mock_server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/submit"))
+        .and(body_string("test_payload"))
+        .respond_with(ResponseTemplate::new(201).set_body_string("Created"))
+        .mount(&mock_server)
         .await;
 
-    // Check strict equality of buffer (should be drained) or at least the tag removed
-    assert!(
-        buffer.is_empty(),
-        "Buffer should be empty after processing set_var, got: '{}'",
-        buffer
+    let url = format!("{}/api/submit", mock_server.uri());
+    let mut buffer = format!(
+        r#"Submitting <http url="{}" method="POST" body="test_payload" />"#,
+        url
     );
 
-    // Check ActiveCall state
-    let state = active_call.call_state.read().await;
-    let extras = state
-        .extras
-        .as_ref()
-        .expect("extras should be initialized/set");
+    handler
+        .extract_streaming_commands(&mut buffer, "p_id", true)
+        .await;
 
-    assert_eq!(
-        extras.get("my_key").unwrap(),
-        &serde_json::Value::String("my_val".to_string()),
-        "Variable my_key should be set to my_val"
-    );
+    let last_msg = handler.history.last().unwrap();
+    assert_eq!(last_msg.role, "system");
+    assert!(last_msg.content.contains("HTTP POST"));
+    assert!(last_msg.content.contains("201"));
 }
 
 #[tokio::test]
-async fn test_set_var_with_sip_headers() {
+async fn test_multiple_commands_in_sequence() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
@@ -1119,13 +2570,9 @@ async fn test_set_var_with_sip_headers() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-sip-headers".to_string();
+    let session_id = "test-session-multi".to_string();
     let track_config = TrackConfig::default();
 
-    // Initialize with some extracted headers
-    let mut initial_extras = std::collections::HashMap::new();
-    initial_extras.insert("X-CID".to_string(), serde_json::json!("123456"));
-
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
         cancel_token,
@@ -1136,7 +2583,7 @@ async fn test_set_var_with_sip_headers() {
         None,
         false,
         None,
-        Some(initial_extras),
+        None,
         None,
     ));
 
@@ -1154,45 +2601,32 @@ async fn test_set_var_with_sip_headers() {
     );
     handler.call = Some(active_call.clone());
 
-    // Simulate LLM setting _hangup_headers for BYE
-    let mut buffer = r#"<set_var key="_hangup_headers" value='{"X-Hangup-Reason":"completed","X-Duration":"120"}' />"#.to_string();
+    // Test combining set_var and normal text
+    let mut buffer =
+        r#"Hello <set_var key="user_name" value="Alice" /> nice to meet you!"#.to_string();
 
-    handler
+    let commands = handler
         .extract_streaming_commands(&mut buffer, "p_id", true)
         .await;
 
-    assert!(
-        buffer.is_empty(),
-        "Buffer should be empty, got: '{}'",
-        buffer
-    );
+    // Should generate TTS commands
+    assert!(!commands.is_empty());
 
+    // Check state was updated
     let state = active_call.call_state.read().await;
     let extras = state.extras.as_ref().unwrap();
-
-    // Verify original header still exists
-    assert_eq!(extras.get("X-CID").unwrap(), &serde_json::json!("123456"));
-
-    // Verify new _hangup_headers was set
-    assert!(extras.contains_key("_hangup_headers"));
-    let headers_value = extras.get("_hangup_headers").unwrap();
     assert_eq!(
-        headers_value,
-        &serde_json::Value::String(
-            r#"{"X-Hangup-Reason":"completed","X-Duration":"120"}"#.to_string()
-        )
+        extras.get("user_name").unwrap(),
+        &serde_json::Value::String("Alice".to_string())
     );
 }
-
 #[tokio::test]
-async fn test_http_command_in_stream() {
+async fn test_set_var_individual_sip_header() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
     use tokio_util::sync::CancellationToken;
-    use wiremock::matchers::{method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     let config = crate::playbook::LlmConfig::default();
     let interruption = crate::playbook::InterruptionConfig::default();
@@ -1209,7 +2643,7 @@ async fn test_http_command_in_stream() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-http".to_string();
+    let session_id = "test-session-individual-header".to_string();
     let track_config = TrackConfig::default();
 
     let active_call = Arc::new(ActiveCall::new(
@@ -1240,50 +2674,51 @@ async fn test_http_command_in_stream() {
     );
     handler.call = Some(active_call.clone());
 
-    // Start mock server
-    let mock_server = MockServer::start().await;
-    Mock::given(method("GET"))
-        .and(path("/api/data"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-            "status": "success",
-            "data": "test_value"
-        })))
-        .mount(&mock_server)
+    // Set individual SIP headers using set_var
+    let mut buffer = r#"<set_var key="X-Call-Status" value="answered" />"#.to_string();
+    handler
+        .extract_streaming_commands(&mut buffer, "p1", true)
         .await;
 
-    let url = format!("{}/api/data", mock_server.uri());
-    let mut buffer = format!(r#"Check this <http url="{}" />"#, url);
-
-    let initial_history_len = handler.history.len();
-
+    let mut buffer2 = r#"<set_var key="X-Call-Duration" value="120" />"#.to_string();
     handler
-        .extract_streaming_commands(&mut buffer, "p_id", true)
+        .extract_streaming_commands(&mut buffer2, "p2", true)
         .await;
 
-    // History should have system message with HTTP response
-    assert!(
-        handler.history.len() > initial_history_len,
-        "History should grow after HTTP call"
-    );
+    let state = active_call.call_state.read().await;
+    let extras = state.extras.as_ref().unwrap();
 
-    let last_msg = handler.history.last().unwrap();
-    assert_eq!(last_msg.role, "system");
-    assert!(last_msg.content.contains("HTTP GET"));
-    assert!(last_msg.content.contains("200"));
-    assert!(last_msg.content.contains("test_value"));
+    // Both headers should be set
+    assert_eq!(
+        extras.get("X-Call-Status").unwrap(),
+        &serde_json::json!("answered")
+    );
+    assert_eq!(
+        extras.get("X-Call-Duration").unwrap(),
+        &serde_json::json!("120")
+    );
 }
 
 #[tokio::test]
-async fn test_http_command_post_with_body() {
+async fn test_bye_headers_with_all_variables() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
+    use std::collections::HashMap as StdHashMap;
     use tokio_util::sync::CancellationToken;
-    use wiremock::matchers::{body_string, method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    let config = crate::playbook::LlmConfig::default();
+    let mut sip_config = crate::SipOption::default();
+    let mut hangup_headers = StdHashMap::new();
+    hangup_headers.insert("X-Call-Result".to_string(), "{{ call_result }}".to_string());
+    hangup_headers.insert(
+        "X-Customer-ID".to_string(),
+        "{{ sip[\"X-CID\"] }}".to_string(),
+    );
+    hangup_headers.insert("X-Agent-Name".to_string(), "{{ agent_name }}".to_string());
+    sip_config.hangup_headers = Some(hangup_headers);
+
+    let llm_config = crate::playbook::LlmConfig::default();
     let interruption = crate::playbook::InterruptionConfig::default();
     let provider = Arc::new(TestProvider::new(vec![]));
     let rag = Arc::new(RecordingRag::new());
@@ -1298,9 +2733,17 @@ async fn test_http_command_post_with_body() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-http-post".to_string();
+    let session_id = "test-session-bye-headers".to_string();
     let track_config = TrackConfig::default();
 
+    // Initialize with mixed variables - SIP headers and regular variables
+    let mut initial_extras = std::collections::HashMap::new();
+    initial_extras.insert("X-CID".to_string(), serde_json::json!("CUSTOMER-123"));
+    initial_extras.insert("call_result".to_string(), serde_json::json!("successful"));
+    initial_extras.insert("agent_name".to_string(), serde_json::json!("Alice"));
+    // Mark X-CID as SIP header
+    initial_extras.insert("_sip_header_keys".to_string(), serde_json::json!(["X-CID"]));
+
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
         cancel_token,
@@ -1311,12 +2754,12 @@ async fn test_http_command_post_with_body() {
         None,
         false,
         None,
-        None,
+        Some(initial_extras),
         None,
     ));
 
     let mut handler = LlmHandler::with_provider(
-        config,
+        llm_config,
         provider,
         rag,
         interruption,
@@ -1325,44 +2768,47 @@ async fn test_http_command_post_with_body() {
         None,
         None,
         None,
-        None,
+        Some(sip_config),
     );
     handler.call = Some(active_call.clone());
 
-    // Start mock server
-    let mock_server = MockServer::start().await;
-    Mock::given(method("POST"))
-        .and(path("/api/submit"))
-        .and(body_string("test_payload"))
-        .respond_with(ResponseTemplate::new(201).set_body_string("Created"))
-        .mount(&mock_server)
-        .await;
-
-    let url = format!("{}/api/submit", mock_server.uri());
-    let mut buffer = format!(
-        r#"Submitting <http url="{}" method="POST" body="test_payload" />"#,
-        url
-    );
+    // Render BYE headers
+    let rendered_headers = handler.render_sip_headers().await;
 
-    handler
-        .extract_streaming_commands(&mut buffer, "p_id", true)
-        .await;
+    assert!(rendered_headers.is_some());
+    let headers = rendered_headers.unwrap();
 
-    let last_msg = handler.history.last().unwrap();
-    assert_eq!(last_msg.role, "system");
-    assert!(last_msg.content.contains("HTTP POST"));
-    assert!(last_msg.content.contains("201"));
+    // Verify all variables were accessible
+    assert_eq!(headers.get("X-Call-Result").unwrap(), "successful");
+    assert_eq!(headers.get("X-Customer-ID").unwrap(), "CUSTOMER-123");
+    assert_eq!(headers.get("X-Agent-Name").unwrap(), "Alice");
 }
 
 #[tokio::test]
-async fn test_multiple_commands_in_sequence() {
+async fn test_bye_headers_with_unset_variables() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
+    use std::collections::HashMap as StdHashMap;
     use tokio_util::sync::CancellationToken;
 
-    let config = crate::playbook::LlmConfig::default();
+    // Test case: user defines hangup_headers with variables that are NOT set yet
+    // This simulates the user's problem in the screenshot
+    let llm_config = crate::playbook::LlmConfig::default();
+
+    let mut sip_config = crate::SipOption::default();
+    let mut hangup_headers = StdHashMap::new();
+    hangup_headers.insert(
+        "X-Hangupreason".to_string(),
+        "{{ hangupreason }}".to_string(),
+    );
+    hangup_headers.insert(
+        "X-Skillgroupid".to_string(),
+        "{{ skillgroupid }}".to_string(),
+    );
+    sip_config.hangup_headers = Some(hangup_headers);
+
     let interruption = crate::playbook::InterruptionConfig::default();
     let provider = Arc::new(TestProvider::new(vec![]));
     let rag = Arc::new(RecordingRag::new());
@@ -1377,9 +2823,12 @@ async fn test_multiple_commands_in_sequence() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-multi".to_string();
+    let session_id = "test-session-unset-vars".to_string();
     let track_config = TrackConfig::default();
 
+    // Initialize WITHOUT setting hangupreason and skillgroupid
+    let initial_extras = StdHashMap::new();
+
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
         cancel_token,
@@ -1390,55 +2839,72 @@ async fn test_multiple_commands_in_sequence() {
         None,
         false,
         None,
-        None,
+        Some(initial_extras),
         None,
     ));
 
     let mut handler = LlmHandler::with_provider(
-        config,
+        llm_config,
         provider,
         rag,
         interruption,
         None,
-        std::collections::HashMap::new(),
-        None,
+        StdHashMap::new(),
         None,
         None,
         None,
+        Some(sip_config),
     );
     handler.call = Some(active_call.clone());
 
-    // Test combining set_var and normal text
-    let mut buffer =
-        r#"Hello <set_var key="user_name" value="Alice" /> nice to meet you!"#.to_string();
+    // Render BYE headers - this should handle missing variables gracefully
+    let rendered_headers = handler.render_sip_headers().await;
 
-    let commands = handler
-        .extract_streaming_commands(&mut buffer, "p_id", true)
-        .await;
+    assert!(rendered_headers.is_some());
+    let headers = rendered_headers.unwrap();
 
-    // Should generate TTS commands
-    assert!(!commands.is_empty());
+    // When variables are not set, MiniJinja renders empty strings
+    println!("Rendered headers when variables not set: {:?}", headers);
 
-    // Check state was updated
-    let state = active_call.call_state.read().await;
-    let extras = state.extras.as_ref().unwrap();
-    assert_eq!(
-        extras.get("user_name").unwrap(),
-        &serde_json::Value::String("Alice".to_string())
-    );
+    // This is the BUG: MiniJinja renders undefined variables as empty strings!
+    assert_eq!(headers.get("X-Hangupreason").unwrap(), "");
+    assert_eq!(headers.get("X-Skillgroupid").unwrap(), "");
 }
+
 #[tokio::test]
-async fn test_set_var_individual_sip_header() {
+async fn test_set_var_then_bye_headers() {
+    // Test the complete flow: set_var in streaming, then render BYE headers
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
+    use std::collections::HashMap as StdHashMap;
     use tokio_util::sync::CancellationToken;
 
-    let config = crate::playbook::LlmConfig::default();
-    let interruption = crate::playbook::InterruptionConfig::default();
-    let provider = Arc::new(TestProvider::new(vec![]));
+    let llm_config = crate::playbook::LlmConfig {
+        provider: "test".to_string(),
+        ..Default::default()
+    };
+
+    let mut sip_config = crate::SipOption::default();
+    let mut hangup_headers = StdHashMap::new();
+    hangup_headers.insert(
+        "X-Hangupreason".to_string(),
+        "{{ hangupreason }}".to_string(),
+    );
+    hangup_headers.insert(
+        "X-Skillgroupid".to_string(),
+        "{{ skillgroupid }}".to_string(),
+    );
+    sip_config.hangup_headers = Some(hangup_headers);
+
+    // LLM will return a response with set_var commands
+    let provider = Arc::new(TestProvider::new(vec![
+        r#"好的，我帮您转接人工 <set_var key="hangupreason" value="Transfer"/> <set_var key="skillgroupid" value="7084rx000003"/> <hangup/>"#.to_string(),
+    ]));
+
     let rag = Arc::new(RecordingRag::new());
+    let interruption = crate::playbook::InterruptionConfig::default();
 
     let mut app_config = Config::default();
     app_config.udp_port = 0;
@@ -1450,9 +2916,11 @@ async fn test_set_var_individual_sip_header() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-individual-header".to_string();
+    let session_id = "test-session-set-var-flow".to_string();
     let track_config = TrackConfig::default();
 
+    let initial_extras = StdHashMap::new();
+
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
         cancel_token,
@@ -1463,51 +2931,110 @@ async fn test_set_var_individual_sip_header() {
         None,
         false,
         None,
-        None,
+        Some(initial_extras),
         None,
     ));
 
     let mut handler = LlmHandler::with_provider(
-        config,
+        llm_config,
         provider,
         rag,
         interruption,
         None,
-        std::collections::HashMap::new(),
-        None,
+        StdHashMap::new(),
         None,
         None,
         None,
+        Some(sip_config),
     );
     handler.call = Some(active_call.clone());
 
-    // Set individual SIP headers using set_var
-    let mut buffer = r#"<set_var key="X-Call-Status" value="answered" />"#.to_string();
-    handler
-        .extract_streaming_commands(&mut buffer, "p1", true)
-        .await;
+    // Simulate the dialogue flow
+    let commands = handler.generate_response().await.unwrap();
 
-    let mut buffer2 = r#"<set_var key="X-Call-Duration" value="120" />"#.to_string();
-    handler
-        .extract_streaming_commands(&mut buffer2, "p2", true)
-        .await;
+    // Process the streaming response which includes set_var commands
+    // The commands should include TTS and eventually trigger set_var processing
+    println!("Generated commands: {:?}", commands);
 
+    // Now check if variables were set in state
     let state = active_call.call_state.read().await;
-    let extras = state.extras.as_ref().unwrap();
+    if let Some(extras) = &state.extras {
+        println!("Extras after generate_response: {:?}", extras);
 
-    // Both headers should be set
-    assert_eq!(
-        extras.get("X-Call-Status").unwrap(),
-        &serde_json::json!("answered")
+        // Check if set_var worked
+        if let Some(reason) = extras.get("hangupreason") {
+            assert_eq!(reason.as_str().unwrap(), "Transfer");
+        } else {
+            println!("WARNING: hangupreason not found in extras!");
+        }
+
+        if let Some(skill) = extras.get("skillgroupid") {
+            assert_eq!(skill.as_str().unwrap(), "7084rx000003");
+        } else {
+            println!("WARNING: skillgroupid not found in extras!");
+        }
+    } else {
+        println!("WARNING: extras is None!");
+    }
+    drop(state);
+
+    // Now render BYE headers
+    let rendered_headers = handler.render_sip_headers().await;
+
+    assert!(rendered_headers.is_some());
+    let headers = rendered_headers.unwrap();
+
+    println!("Rendered BYE headers: {:?}", headers);
+
+    // The headers should now contain the set values
+    assert_eq!(headers.get("X-Hangupreason").unwrap(), "Transfer");
+    assert_eq!(headers.get("X-Skillgroupid").unwrap(), "7084rx000003");
+}
+
+#[tokio::test]
+async fn test_streaming_chunks_with_incomplete_set_var() {
+    // Test case: streaming output splits <set_var> tag across multiple chunks
+    // This simulates what we see in the user's screenshot
+
+    let content =
+        r#"好的，我帮您转接人工 <set_var key="hangupreason" value="Transfer"/> <hangup/>"#;
+
+    // Simulate the tag being split in the middle (incomplete buffer state)
+    let incomplete_buffer1 = r#"好的，我帮您转接人工 <set_var key="hangupreason" value="Transfer"#;
+    // Test that incomplete tags are NOT matched
+    assert!(
+        super::RE_SET_VAR.captures(incomplete_buffer1).is_none(),
+        "Incomplete set_var should not be matched"
     );
-    assert_eq!(
-        extras.get("X-Call-Duration").unwrap(),
-        &serde_json::json!("120")
+
+    // Test that complete tags ARE matched
+    assert!(
+        super::RE_SET_VAR.captures(content).is_some(),
+        "Complete set_var should be matched"
     );
+
+    // Test critical scenario: hangup appears before set_var is complete
+    let bad_order = r#"好的，我帮您转接 <hangup/> <set_var key="hangupreason" value="Transfer"/>"#;
+
+    let hangup_match = super::RE_HANGUP.find(bad_order);
+    let setvar_match = super::RE_SET_VAR.captures(bad_order);
+
+    if let (Some(h), Some(s)) = (hangup_match, setvar_match) {
+        println!(
+            "Hangup position: {}, SetVar position: {}",
+            h.start(),
+            s.get(0).unwrap().start()
+        );
+        assert!(
+            h.start() < s.get(0).unwrap().start(),
+            "BUG: hangup appears before set_var!"
+        );
+    }
 }
 
 #[tokio::test]
-async fn test_bye_headers_with_all_variables() {
+async fn test_hangup_before_set_var_still_works() {
+    // Test the new behavior: set_var after hangup should still be processed
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
@@ -1515,20 +3042,30 @@ async fn test_bye_headers_with_all_variables() {
     use std::collections::HashMap as StdHashMap;
     use tokio_util::sync::CancellationToken;
 
+    let llm_config = crate::playbook::LlmConfig {
+        provider: "test".to_string(),
+        ..Default::default()
+    };
+
     let mut sip_config = crate::SipOption::default();
     let mut hangup_headers = StdHashMap::new();
-    hangup_headers.insert("X-Call-Result".to_string(), "{{ call_result }}".to_string());
     hangup_headers.insert(
-        "X-Customer-ID".to_string(),
-        "{{ sip[\"X-CID\"] }}".to_string(),
+        "X-Hangupreason".to_string(),
+        "{{ hangupreason }}".to_string(),
+    );
+    hangup_headers.insert(
+        "X-Skillgroupid".to_string(),
+        "{{ skillgroupid }}".to_string(),
     );
-    hangup_headers.insert("X-Agent-Name".to_string(), "{{ agent_name }}".to_string());
     sip_config.hangup_headers = Some(hangup_headers);
 
-    let llm_config = crate::playbook::LlmConfig::default();
-    let interruption = crate::playbook::InterruptionConfig::default();
-    let provider = Arc::new(TestProvider::new(vec![]));
+    // LLM returns hangup BEFORE set_var (wrong order, but should still work now!)
+    let provider = Arc::new(TestProvider::new(vec![
+        r#"好的，我帮您转接 <hangup/> <set_var key="hangupreason" value="Transfer"/> <set_var key="skillgroupid" value="7084rx000003"/>"#.to_string(),
+    ]));
+
     let rag = Arc::new(RecordingRag::new());
+    let interruption = crate::playbook::InterruptionConfig::default();
 
     let mut app_config = Config::default();
     app_config.udp_port = 0;
@@ -1540,16 +3077,10 @@ async fn test_bye_headers_with_all_variables() {
         .expect("Failed to build app state");
 
     let cancel_token = CancellationToken::new();
-    let session_id = "test-session-bye-headers".to_string();
+    let session_id = "test-session-hangup-before-setvar".to_string();
     let track_config = TrackConfig::default();
 
-    // Initialize with mixed variables - SIP headers and regular variables
-    let mut initial_extras = std::collections::HashMap::new();
-    initial_extras.insert("X-CID".to_string(), serde_json::json!("CUSTOMER-123"));
-    initial_extras.insert("call_result".to_string(), serde_json::json!("successful"));
-    initial_extras.insert("agent_name".to_string(), serde_json::json!("Alice"));
-    // Mark X-CID as SIP header
-    initial_extras.insert("_sip_header_keys".to_string(), serde_json::json!(["X-CID"]));
+    let initial_extras = StdHashMap::new();
 
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
@@ -1571,7 +3102,7 @@ async fn test_bye_headers_with_all_variables() {
         rag,
         interruption,
         None,
-        std::collections::HashMap::new(),
+        StdHashMap::new(),
         None,
         None,
         None,
@@ -1579,67 +3110,76 @@ async fn test_bye_headers_with_all_variables() {
     );
     handler.call = Some(active_call.clone());
 
+    // Generate response
+    let commands = handler.generate_response().await.unwrap();
+
+    println!("Generated commands: {:?}", commands);
+
+    // Check if variables were set DESPITE hangup coming first
+    let state = active_call.call_state.read().await;
+    if let Some(extras) = &state.extras {
+        println!("Extras after generate_response: {:?}", extras);
+
+        assert_eq!(
+            extras.get("hangupreason").and_then(|v| v.as_str()),
+            Some("Transfer"),
+            "hangupreason should be set even though hangup came first"
+        );
+
+        assert_eq!(
+            extras.get("skillgroupid").and_then(|v| v.as_str()),
+            Some("7084rx000003"),
+            "skillgroupid should be set even though hangup came first"
+        );
+    } else {
+        panic!("extras should not be None!");
+    }
+    drop(state);
+
     // Render BYE headers
     let rendered_headers = handler.render_sip_headers().await;
 
     assert!(rendered_headers.is_some());
     let headers = rendered_headers.unwrap();
 
-    // Verify all variables were accessible
-    assert_eq!(headers.get("X-Call-Result").unwrap(), "successful");
-    assert_eq!(headers.get("X-Customer-ID").unwrap(), "CUSTOMER-123");
-    assert_eq!(headers.get("X-Agent-Name").unwrap(), "Alice");
-}
+    println!("Rendered BYE headers: {:?}", headers);
 
+    // The headers should now contain the set values
+    assert_eq!(headers.get("X-Hangupreason").unwrap(), "Transfer");
+    assert_eq!(headers.get("X-Skillgroupid").unwrap(), "7084rx000003");
+}
 #[tokio::test]
-async fn test_bye_headers_with_unset_variables() {
+async fn test_dynamic_scene_prompt_rendering() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
-    use std::collections::HashMap as StdHashMap;
-    use tokio_util::sync::CancellationToken;
-
-    // Test case: user defines hangup_headers with variables that are NOT set yet
-    // This simulates the user's problem in the screenshot
-    let llm_config = crate::playbook::LlmConfig::default();
-
-    let mut sip_config = crate::SipOption::default();
-    let mut hangup_headers = StdHashMap::new();
-    hangup_headers.insert(
-        "X-Hangupreason".to_string(),
-        "{{ hangupreason }}".to_string(),
-    );
-    hangup_headers.insert(
-        "X-Skillgroupid".to_string(),
-        "{{ skillgroupid }}".to_string(),
-    );
-    sip_config.hangup_headers = Some(hangup_headers);
-
-    let interruption = crate::playbook::InterruptionConfig::default();
-    let provider = Arc::new(TestProvider::new(vec![]));
-    let rag = Arc::new(RecordingRag::new());
-
-    let mut app_config = Config::default();
-    app_config.udp_port = 0;
+    use crate::playbook::Scene;
 
+    let mut config = Config::default();
+    config.udp_port = 0;
     let app_state = AppStateBuilder::new()
-        .with_config(app_config)
+        .with_config(config)
         .build()
         .await
         .expect("Failed to build app state");
 
-    let cancel_token = CancellationToken::new();
-    let session_id = "test-session-unset-vars".to_string();
+    let cancel_token = tokio_util::sync::CancellationToken::new();
+    let session_id = "test-dynamic-prompt".to_string();
     let track_config = TrackConfig::default();
 
-    // Initialize WITHOUT setting hangupreason and skillgroupid
-    let initial_extras = StdHashMap::new();
+    // Initialize extras with SIP headers and built-in variables
+    let mut initial_extras = std::collections::HashMap::new();
+    initial_extras.insert("X-Jobid".to_string(), serde_json::json!("JOB-456"));
+    initial_extras.insert(
+        "_sip_header_keys".to_string(),
+        serde_json::json!(["X-Jobid"]),
+    );
 
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
         cancel_token,
-        session_id,
+        session_id.clone(),
         app_state.invitation.clone(),
         app_state.clone(),
         track_config,
@@ -1650,539 +3190,1072 @@ async fn test_bye_headers_with_unset_variables() {
         None,
     ));
 
+    // Create scenes with raw_prompt templates
+    let mut scenes = std::collections::HashMap::new();
+    scenes.insert(
+        "greeting".to_string(),
+        Scene {
+            id: "greeting".to_string(),
+            raw_prompt: Some(
+                "您好，客户意图：{{ intent }}\nJob ID：{{ sip[\"X-Jobid\"] }}".to_string(),
+            ),
+            prompt: "您好，客户意图：\nJob ID：JOB-456".to_string(),
+            ..Default::default()
+        },
+    );
+    scenes.insert(
+        "detail".to_string(),
+        Scene {
+            id: "detail".to_string(),
+            raw_prompt: Some("处理意图：{{ intent }}\n会话：{{ session_id }}".to_string()),
+            prompt: "处理意图：\n会话：".to_string(),
+            ..Default::default()
+        },
+    );
+
+    let llm_config = LlmConfig {
+        provider: "mock".to_string(),
+        prompt: Some("初始prompt".to_string()),
+        ..Default::default()
+    };
+
+    let provider = Arc::new(TestProvider::new(vec!["好的".to_string()]));
     let mut handler = LlmHandler::with_provider(
         llm_config,
         provider,
-        rag,
-        interruption,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
         None,
-        StdHashMap::new(),
+        scenes,
         None,
         None,
+        Some("greeting".to_string()),
         None,
-        Some(sip_config),
     );
     handler.call = Some(active_call.clone());
 
-    // Render BYE headers - this should handle missing variables gracefully
-    let rendered_headers = handler.render_sip_headers().await;
+    // Simulate set_var: LLM sets intent during conversation
+    {
+        let mut state = active_call.call_state.write().await;
+        let mut extras = state.extras.take().unwrap_or_default();
+        extras.insert(
+            "intent".to_string(),
+            serde_json::Value::String("买零食".to_string()),
+        );
+        state.extras = Some(extras);
+    }
 
-    assert!(rendered_headers.is_some());
-    let headers = rendered_headers.unwrap();
+    // Now switch to greeting scene, it should dynamically render with the latest variables
+    let commands = handler.switch_to_scene("greeting", false).await.unwrap();
+    // The commands should be empty since greeting scene has no play url
+    assert!(commands.is_empty());
 
-    // When variables are not set, MiniJinja renders empty strings
-    println!("Rendered headers when variables not set: {:?}", headers);
+    // Check that the system prompt was dynamically rendered with intent
+    let system_msg = &handler.history[0];
+    assert_eq!(system_msg.role, "system");
+    assert!(
+        system_msg.content.contains("客户意图：买零食"),
+        "System prompt should contain dynamically rendered intent, got: {}",
+        system_msg.content
+    );
+    assert!(
+        system_msg.content.contains("Job ID：JOB-456"),
+        "System prompt should contain SIP header value, got: {}",
+        system_msg.content
+    );
 
-    // This is the BUG: MiniJinja renders undefined variables as empty strings!
-    assert_eq!(headers.get("X-Hangupreason").unwrap(), "");
-    assert_eq!(headers.get("X-Skillgroupid").unwrap(), "");
+    // Switch to detail scene, should also render dynamically
+    let _ = handler.switch_to_scene("detail", false).await.unwrap();
+    let system_msg = &handler.history[0];
+    assert!(
+        system_msg.content.contains("处理意图：买零食"),
+        "Detail scene should have rendered intent, got: {}",
+        system_msg.content
+    );
+    // Built-in session_id should be available
+    assert!(
+        system_msg
+            .content
+            .contains(&format!("会话：{}", session_id)),
+        "Detail scene should have session_id, got: {}",
+        system_msg.content
+    );
 }
 
 #[tokio::test]
-async fn test_set_var_then_bye_headers() {
-    // Test the complete flow: set_var in streaming, then render BYE headers
+async fn test_dynamic_prompt_with_builtin_vars() {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
-    use std::collections::HashMap as StdHashMap;
-    use tokio_util::sync::CancellationToken;
-
-    let llm_config = crate::playbook::LlmConfig {
-        provider: "test".to_string(),
-        ..Default::default()
-    };
-
-    let mut sip_config = crate::SipOption::default();
-    let mut hangup_headers = StdHashMap::new();
-    hangup_headers.insert(
-        "X-Hangupreason".to_string(),
-        "{{ hangupreason }}".to_string(),
-    );
-    hangup_headers.insert(
-        "X-Skillgroupid".to_string(),
-        "{{ skillgroupid }}".to_string(),
-    );
-    sip_config.hangup_headers = Some(hangup_headers);
-
-    // LLM will return a response with set_var commands
-    let provider = Arc::new(TestProvider::new(vec![
-        r#"好的，我帮您转接人工 <set_var key="hangupreason" value="Transfer"/> <set_var key="skillgroupid" value="7084rx000003"/> <hangup/>"#.to_string(),
-    ]));
-
-    let rag = Arc::new(RecordingRag::new());
-    let interruption = crate::playbook::InterruptionConfig::default();
-
-    let mut app_config = Config::default();
-    app_config.udp_port = 0;
+    use crate::playbook::{BUILTIN_CALL_TYPE, BUILTIN_SESSION_ID, BUILTIN_START_TIME, Scene};
 
+    let mut config = Config::default();
+    config.udp_port = 0;
     let app_state = AppStateBuilder::new()
-        .with_config(app_config)
+        .with_config(config)
         .build()
         .await
         .expect("Failed to build app state");
 
-    let cancel_token = CancellationToken::new();
-    let session_id = "test-session-set-var-flow".to_string();
+    let cancel_token = tokio_util::sync::CancellationToken::new();
+    let session_id = "session-builtin-test".to_string();
     let track_config = TrackConfig::default();
 
-    let initial_extras = StdHashMap::new();
-
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
         cancel_token,
-        session_id,
+        session_id.clone(),
         app_state.invitation.clone(),
         app_state.clone(),
         track_config,
         None,
         false,
         None,
-        Some(initial_extras),
+        None,
         None,
     ));
 
+    // Verify built-in variables were injected
+    {
+        let state = active_call.call_state.read().await;
+        let extras = state.extras.as_ref().expect("extras should exist");
+        assert_eq!(
+            extras.get(BUILTIN_SESSION_ID).and_then(|v| v.as_str()),
+            Some("session-builtin-test"),
+        );
+        assert_eq!(
+            extras.get(BUILTIN_CALL_TYPE).and_then(|v| v.as_str()),
+            Some("sip"),
+        );
+        assert!(extras.get(BUILTIN_START_TIME).is_some());
+    }
+
+    // Create a scene that uses built-in variables
+    let mut scenes = std::collections::HashMap::new();
+    scenes.insert(
+        "main".to_string(),
+        Scene {
+            id: "main".to_string(),
+            raw_prompt: Some("会话：{{ session_id }}\n类型：{{ call_type }}".to_string()),
+            prompt: "会话：\n类型：".to_string(),
+            ..Default::default()
+        },
+    );
+
+    let llm_config = LlmConfig {
+        provider: "mock".to_string(),
+        prompt: Some("test".to_string()),
+        ..Default::default()
+    };
+
+    let provider = Arc::new(TestProvider::new(vec![]));
     let mut handler = LlmHandler::with_provider(
         llm_config,
         provider,
-        rag,
-        interruption,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
         None,
-        StdHashMap::new(),
+        scenes,
         None,
         None,
+        Some("main".to_string()),
         None,
-        Some(sip_config),
     );
     handler.call = Some(active_call.clone());
 
-    // Simulate the dialogue flow
-    let commands = handler.generate_response().await.unwrap();
+    let _ = handler.switch_to_scene("main", false).await.unwrap();
+    let system_msg = &handler.history[0];
+    assert!(
+        system_msg
+            .content
+            .contains(&format!("会话：{}", session_id)),
+        "Should contain built-in session_id, got: {}",
+        system_msg.content
+    );
+    assert!(
+        system_msg.content.contains("类型：sip"),
+        "Should contain built-in call_type, got: {}",
+        system_msg.content
+    );
+}
 
-    // Process the streaming response which includes set_var commands
-    // The commands should include TTS and eventually trigger set_var processing
-    println!("Generated commands: {:?}", commands);
+fn interim_event(text: &str) -> SessionEvent {
+    SessionEvent::AsrDelta {
+        track_id: "track-interim".to_string(),
+        index: 0,
+        timestamp: 0,
+        start_time: None,
+        end_time: None,
+        text: text.to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        stable_text: None,
+        unstable_text: None,
+    }
+}
 
-    // Now check if variables were set in state
-    let state = active_call.call_state.read().await;
-    if let Some(extras) = &state.extras {
-        println!("Extras after generate_response: {:?}", extras);
+fn eou_event() -> SessionEvent {
+    SessionEvent::Eou {
+        track_id: "track-interim".to_string(),
+        timestamp: 0,
+        completed: true,
+        interrupt_point: None,
+    }
+}
 
-        // Check if set_var worked
-        if let Some(reason) = extras.get("hangupreason") {
-            assert_eq!(reason.as_str().unwrap(), "Transfer");
-        } else {
-            println!("WARNING: hangupreason not found in extras!");
-        }
+#[tokio::test]
+async fn test_interim_asr_answers_on_eou() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Sure, booking now".to_string()]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    handler.set_use_interim_asr(true);
 
-        if let Some(skill) = extras.get("skillgroupid") {
-            assert_eq!(skill.as_str().unwrap(), "7084rx000003");
-        } else {
-            println!("WARNING: skillgroupid not found in extras!");
-        }
-    } else {
-        println!("WARNING: extras is None!");
-    }
-    drop(state);
+    handler.on_event(&interim_event("book a table")).await?;
+    let commands = handler.on_event(&eou_event()).await?;
+    assert!(matches!(
+        commands.get(0),
+        Some(Command::Tts { text, .. }) if text == "Sure, booking now"
+    ));
+    assert!(
+        handler
+            .history
+            .iter()
+            .any(|m| m.role == "user" && m.content == "book a table")
+    );
 
-    // Now render BYE headers
-    let rendered_headers = handler.render_sip_headers().await;
+    // The final for the same utterance must not trigger a second response
+    let final_event = SessionEvent::AsrFinal {
+        track_id: "track-interim".to_string(),
+        timestamp: 0,
+        index: 0,
+        start_time: None,
+        end_time: None,
+        text: "book a table".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    };
+    let commands = handler.on_event(&final_event).await?;
+    assert!(commands.is_empty());
+    Ok(())
+}
 
-    assert!(rendered_headers.is_some());
-    let headers = rendered_headers.unwrap();
+#[tokio::test]
+async fn test_interim_asr_disabled_by_default() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec!["Hello".to_string()]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
 
-    println!("Rendered BYE headers: {:?}", headers);
+    handler.on_event(&interim_event("book a table")).await?;
+    handler.on_event(&eou_event()).await?;
+    assert!(
+        !handler
+            .history
+            .iter()
+            .any(|m| m.role == "user" && m.content == "book a table")
+    );
+    Ok(())
+}
 
-    // The headers should now contain the set values
-    assert_eq!(headers.get("X-Hangupreason").unwrap(), "Transfer");
-    assert_eq!(headers.get("X-Skillgroupid").unwrap(), "7084rx000003");
+fn dtmf_event(digit: &str) -> SessionEvent {
+    SessionEvent::Dtmf {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        digit: digit.to_string(),
+    }
 }
 
 #[tokio::test]
-async fn test_streaming_chunks_with_incomplete_set_var() {
-    // Test case: streaming output splits <set_var> tag across multiple chunks
-    // This simulates what we see in the user's screenshot
-
-    let content =
-        r#"好的，我帮您转接人工 <set_var key="hangupreason" value="Transfer"/> <hangup/>"#;
+async fn test_dtmf_injected_into_llm_history() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec![
+        "Connecting you to sales.".to_string(),
+    ]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    handler.set_dtmf_to_llm(true);
 
-    // Simulate the tag being split in the middle (incomplete buffer state)
-    let incomplete_buffer1 = r#"好的，我帮您转接人工 <set_var key="hangupreason" value="Transfer"#;
-    // Test that incomplete tags are NOT matched
+    let commands = handler.on_event(&dtmf_event("1")).await?;
     assert!(
-        super::RE_SET_VAR.captures(incomplete_buffer1).is_none(),
-        "Incomplete set_var should not be matched"
+        handler
+            .history
+            .iter()
+            .any(|m| m.role == "user" && m.content == "[DTMF: 1]")
     );
+    assert!(matches!(
+        commands.first(),
+        Some(Command::Tts { text, .. }) if text.contains("sales")
+    ));
+    Ok(())
+}
 
-    // Test that complete tags ARE matched
-    assert!(
-        super::RE_SET_VAR.captures(content).is_some(),
-        "Complete set_var should be matched"
+#[tokio::test]
+async fn test_dtmf_not_injected_by_default() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec![]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
     );
 
-    // Test critical scenario: hangup appears before set_var is complete
-    let bad_order = r#"好的，我帮您转接 <hangup/> <set_var key="hangupreason" value="Transfer"/>"#;
+    let commands = handler.on_event(&dtmf_event("1")).await?;
+    assert!(commands.is_empty());
+    assert!(!handler.history.iter().any(|m| m.content.contains("DTMF")));
+    Ok(())
+}
 
-    let hangup_match = super::RE_HANGUP.find(bad_order);
-    let setvar_match = super::RE_SET_VAR.captures(bad_order);
+#[test]
+fn test_gemini_request_mapping() {
+    let history = vec![
+        ChatMessage {
+            role: "system".to_string(),
+            content: "You are a receptionist.".to_string(),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: "Hi".to_string(),
+        },
+        ChatMessage {
+            role: "assistant".to_string(),
+            content: "Hello, how can I help?".to_string(),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: "Book a table".to_string(),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: "for two".to_string(),
+        },
+    ];
+    let body = GeminiLlmProvider::build_request(&history);
+    assert_eq!(
+        body["system_instruction"]["parts"][0]["text"],
+        "You are a receptionist."
+    );
+    let contents = body["contents"].as_array().unwrap();
+    assert_eq!(contents.len(), 3);
+    assert_eq!(contents[0]["role"], "user");
+    assert_eq!(contents[1]["role"], "model");
+    assert_eq!(contents[1]["parts"][0]["text"], "Hello, how can I help?");
+    assert_eq!(contents[2]["parts"].as_array().unwrap().len(), 2);
+}
 
-    if let (Some(h), Some(s)) = (hangup_match, setvar_match) {
-        println!(
-            "Hangup position: {}, SetVar position: {}",
-            h.start(),
-            s.get(0).unwrap().start()
-        );
-        assert!(
-            h.start() < s.get(0).unwrap().start(),
-            "BUG: hangup appears before set_var!"
-        );
-    }
+#[test]
+fn test_gemini_response_parsing() {
+    let json = serde_json::json!({
+        "candidates": [{
+            "content": {
+                "role": "model",
+                "parts": [
+                    { "text": "User wants a table.", "thought": true },
+                    { "text": "Sure, " },
+                    { "text": "for what time?" }
+                ]
+            },
+            "finishReason": "STOP"
+        }]
+    });
+    let (text, thought) = GeminiLlmProvider::parse_response(&json).unwrap();
+    assert_eq!(text, "Sure, for what time?");
+    assert_eq!(thought, "User wants a table.");
+
+    let error = serde_json::json!({ "error": { "code": 400, "message": "bad key" } });
+    assert!(GeminiLlmProvider::parse_response(&error).is_err());
 }
 
 #[tokio::test]
-async fn test_hangup_before_set_var_still_works() {
-    // Test the new behavior: set_var after hangup should still be processed
-    use crate::app::AppStateBuilder;
-    use crate::call::{ActiveCall, ActiveCallType};
-    use crate::config::Config;
-    use crate::media::track::TrackConfig;
-    use std::collections::HashMap as StdHashMap;
-    use tokio_util::sync::CancellationToken;
+async fn test_json_mode_requests_json_response() -> Result<()> {
+    use axum::{Json, Router, routing::post};
+
+    let bodies = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+    let recorded = bodies.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move |Json(body): Json<serde_json::Value>| async move {
+            let json_mode = body.get("response_format").is_some();
+            recorded.lock().unwrap().push(body);
+            let content = if json_mode {
+                r#"{"text": "Your table is booked.", "tools": [{"name": "hangup", "reason": "done"}]}"#
+            } else {
+                "Your table is booked."
+            };
+            Json(serde_json::json!({
+                "choices": [{ "message": { "role": "assistant", "content": content } }]
+            }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}/v1", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
 
-    let llm_config = crate::playbook::LlmConfig {
-        provider: "test".to_string(),
+    let history = vec![ChatMessage {
+        role: "user".to_string(),
+        content: "Book a table".to_string(),
+    }];
+    let provider = DefaultLlmProvider::new();
+    let mut config = LlmConfig {
+        base_url: Some(base_url),
         ..Default::default()
     };
 
-    let mut sip_config = crate::SipOption::default();
-    let mut hangup_headers = StdHashMap::new();
-    hangup_headers.insert(
-        "X-Hangupreason".to_string(),
-        "{{ hangupreason }}".to_string(),
-    );
-    hangup_headers.insert(
-        "X-Skillgroupid".to_string(),
-        "{{ skillgroupid }}".to_string(),
-    );
-    sip_config.hangup_headers = Some(hangup_headers);
-
-    // LLM returns hangup BEFORE set_var (wrong order, but should still work now!)
-    let provider = Arc::new(TestProvider::new(vec![
-        r#"好的，我帮您转接 <hangup/> <set_var key="hangupreason" value="Transfer"/> <set_var key="skillgroupid" value="7084rx000003"/>"#.to_string(),
-    ]));
+    let text = provider.call(&config, &history).await?;
+    assert!(parse_structured_response(&text).is_none());
 
-    let rag = Arc::new(RecordingRag::new());
-    let interruption = crate::playbook::InterruptionConfig::default();
-
-    let mut app_config = Config::default();
-    app_config.udp_port = 0;
+    config.json_mode = Some(true);
+    let raw = provider.call(&config, &history).await?;
+    let structured = parse_structured_response(&raw).expect("json mode returns structured reply");
+    assert_eq!(structured.text.as_deref(), Some("Your table is booked."));
+    assert!(matches!(
+        structured.tools.as_deref(),
+        Some([ToolInvocation::Hangup { .. }])
+    ));
 
-    let app_state = AppStateBuilder::new()
-        .with_config(app_config)
-        .build()
-        .await
-        .expect("Failed to build app state");
+    let bodies = bodies.lock().unwrap();
+    assert!(bodies[0].get("response_format").is_none());
+    assert_eq!(bodies[1]["response_format"]["type"], "json_object");
+    Ok(())
+}
 
-    let cancel_token = CancellationToken::new();
-    let session_id = "test-session-hangup-before-setvar".to_string();
-    let track_config = TrackConfig::default();
+#[tokio::test]
+async fn test_sampling_params_sent_to_provider() -> Result<()> {
+    use axum::{Json, Router, routing::post};
+
+    let bodies = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+    let recorded = bodies.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move |Json(body): Json<serde_json::Value>| async move {
+            recorded.lock().unwrap().push(body);
+            Json(serde_json::json!({
+                "choices": [{ "message": { "role": "assistant", "content": "Hello" } }]
+            }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}/v1", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
 
-    let initial_extras = StdHashMap::new();
+    let history = vec![ChatMessage {
+        role: "user".to_string(),
+        content: "Hi".to_string(),
+    }];
+    let provider = DefaultLlmProvider::new();
+    let mut config = LlmConfig {
+        base_url: Some(base_url),
+        ..Default::default()
+    };
+    provider.call(&config, &history).await?;
+
+    config.temperature = Some(0.0);
+    config.max_tokens = Some(64);
+    provider.call(&config, &history).await?;
+    // The streaming request carries them too
+    provider.call_stream(&config, &history).await?;
+
+    let bodies = bodies.lock().unwrap();
+    assert!(bodies[0].get("temperature").is_none());
+    assert!(bodies[0].get("max_tokens").is_none());
+    for body in &bodies[1..] {
+        assert_eq!(body["temperature"], 0.0);
+        assert_eq!(body["max_tokens"], 64);
+        assert!(body.get("top_p").is_none());
+    }
+    assert_eq!(bodies.len(), 3);
+    Ok(())
+}
 
-    let active_call = Arc::new(ActiveCall::new(
-        ActiveCallType::Sip,
-        cancel_token,
-        session_id,
-        app_state.invitation.clone(),
-        app_state.clone(),
-        track_config,
-        None,
-        false,
-        None,
-        Some(initial_extras),
-        None,
-    ));
+#[tokio::test]
+async fn handler_maps_native_tool_calls_to_commands() -> Result<()> {
+    use axum::{Json, Router, response::IntoResponse, routing::post};
+
+    let bodies = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+    let recorded = bodies.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move |Json(body): Json<serde_json::Value>| async move {
+            let stream = body["stream"].as_bool().unwrap_or(false);
+            recorded.lock().unwrap().push(body);
+            if !stream {
+                return Json(serde_json::json!({
+                    "choices": [{ "message": {
+                        "role": "assistant",
+                        "content": "Goodbye!",
+                        "tool_calls": [{
+                            "id": "call_2",
+                            "type": "function",
+                            "function": { "name": "hangup", "arguments": "{\"reason\": \"done\"}" }
+                        }]
+                    }}]
+                }))
+                .into_response();
+            }
+            // The refer call is streamed in pieces, the way OpenAI sends it
+            let deltas = [
+                serde_json::json!([{ "index": 0, "id": "call_1", "type": "function",
+                    "function": { "name": "refer", "arguments": "" } }]),
+                serde_json::json!([{ "index": 0, "function": { "arguments": "{\"callee\": \"sip:1001@" } }]),
+                serde_json::json!([{ "index": 0, "function": { "arguments": "example.com\"}" } }]),
+                // An index past the next call is dropped rather than padded up to
+                serde_json::json!([{ "index": u32::MAX, "function": { "name": "refer", "arguments": "{\"callee\": \"sip:9@example.com\"}" } }]),
+            ];
+            let mut sse = String::new();
+            for delta in deltas {
+                let chunk = serde_json::json!({ "choices": [{ "delta": { "tool_calls": delta } }] });
+                sse.push_str(&format!("data: {}\n\n", chunk));
+            }
+            sse.push_str("data: [DONE]\n\n");
+            sse.into_response()
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}/v1", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
 
+    let config = LlmConfig {
+        base_url: Some(base_url),
+        function_calling: Some(true),
+        ..Default::default()
+    };
     let mut handler = LlmHandler::with_provider(
-        llm_config,
-        provider,
-        rag,
-        interruption,
+        config.clone(),
+        Arc::new(DefaultLlmProvider::new()),
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
         None,
-        StdHashMap::new(),
         None,
         None,
         None,
-        Some(sip_config),
     );
-    handler.call = Some(active_call.clone());
-
-    // Generate response
-    let commands = handler.generate_response().await.unwrap();
 
-    println!("Generated commands: {:?}", commands);
-
-    // Check if variables were set DESPITE hangup coming first
-    let state = active_call.call_state.read().await;
-    if let Some(extras) = &state.extras {
-        println!("Extras after generate_response: {:?}", extras);
+    let commands = handler.generate_response().await?;
+    assert!(
+        commands.iter().any(|c| matches!(
+            c,
+            Command::Refer { callee, .. } if callee == "sip:1001@example.com"
+        )),
+        "{:?}",
+        commands
+    );
+    assert_eq!(
+        commands
+            .iter()
+            .filter(|c| matches!(c, Command::Refer { .. }))
+            .count(),
+        1,
+        "{:?}",
+        commands
+    );
 
-        assert_eq!(
-            extras.get("hangupreason").and_then(|v| v.as_str()),
-            Some("Transfer"),
-            "hangupreason should be set even though hangup came first"
-        );
+    // A non-streamed reply carries its text and tools as a structured reply
+    let raw = DefaultLlmProvider::new()
+        .call(&config, handler.get_history_ref())
+        .await?;
+    let commands = handler.interpret_response(raw).await?;
+    assert!(matches!(
+        commands.as_slice(),
+        [Command::Tts { text, auto_hangup: Some(true), .. }] if text == "Goodbye!"
+    ));
 
-        assert_eq!(
-            extras.get("skillgroupid").and_then(|v| v.as_str()),
-            Some("7084rx000003"),
-            "skillgroupid should be set even though hangup came first"
-        );
-    } else {
-        panic!("extras should not be None!");
-    }
-    drop(state);
+    // Without `functionCalling` no tools are offered
+    DefaultLlmProvider::new()
+        .call(
+            &LlmConfig {
+                function_calling: None,
+                ..config
+            },
+            handler.get_history_ref(),
+        )
+        .await?;
+
+    let bodies = bodies.lock().unwrap();
+    let names: Vec<&str> = bodies[0]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|tool| tool["function"]["name"].as_str())
+        .collect();
+    assert_eq!(names, ["hangup", "refer", "rag"]);
+    assert_eq!(bodies[0]["tool_choice"], "auto");
+    // Usage of streamed replies is only asked from api.openai.com by default
+    assert!(bodies[0].get("stream_options").is_none());
+    assert!(bodies[1].get("tools").is_some());
+    assert!(bodies[2].get("tools").is_none());
+    Ok(())
+}
 
-    // Render BYE headers
-    let rendered_headers = handler.render_sip_headers().await;
+#[test]
+fn test_gemini_sampling_params_in_generation_config() {
+    let config = LlmConfig {
+        provider: "gemini".to_string(),
+        json_mode: Some(true),
+        temperature: Some(0.2),
+        top_p: Some(0.5),
+        max_tokens: Some(128),
+        ..Default::default()
+    };
+    let body = GeminiLlmProvider::build_config_request(&config, &[]);
+    let generation = &body["generationConfig"];
+    assert_eq!(generation["responseMimeType"], "application/json");
+    assert_eq!(generation["topP"], 0.5);
+    assert_eq!(generation["maxOutputTokens"], 128);
+    assert!((generation["temperature"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+
+    let plain = GeminiLlmProvider::build_config_request(&LlmConfig::default(), &[]);
+    assert!(plain.get("generationConfig").is_none());
+}
 
-    assert!(rendered_headers.is_some());
-    let headers = rendered_headers.unwrap();
+/// Streams the first chunk, then holds the rest back until released
+struct GatedProvider {
+    first: String,
+    rest: String,
+    gate: Arc<tokio::sync::Notify>,
+}
 
-    println!("Rendered BYE headers: {:?}", headers);
+#[async_trait]
+impl LlmProvider for GatedProvider {
+    async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+        Ok(format!("{}{}", self.first, self.rest))
+    }
 
-    // The headers should now contain the set values
-    assert_eq!(headers.get("X-Hangupreason").unwrap(), "Transfer");
-    assert_eq!(headers.get("X-Skillgroupid").unwrap(), "7084rx000003");
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let first = self.first.clone();
+        let rest = self.rest.clone();
+        let gate = self.gate.clone();
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content(first));
+            gate.notified().await;
+            yield Ok(LlmStreamEvent::Content(rest));
+        };
+        Ok(Box::pin(s))
+    }
 }
-#[tokio::test]
-async fn test_dynamic_scene_prompt_rendering() {
+
+/// Run a two sentence reply whose second sentence is held back, returning the
+/// TTS texts sent before and after it is released
+async fn run_gated_reply(pipelining: Option<bool>) -> Result<(Vec<String>, Vec<String>)> {
     use crate::app::AppStateBuilder;
     use crate::call::{ActiveCall, ActiveCallType};
     use crate::config::Config;
     use crate::media::track::TrackConfig;
-    use crate::playbook::Scene;
-
-    let mut config = Config::default();
-    config.udp_port = 0;
-    let app_state = AppStateBuilder::new()
-        .with_config(config)
-        .build()
-        .await
-        .expect("Failed to build app state");
-
-    let cancel_token = tokio_util::sync::CancellationToken::new();
-    let session_id = "test-dynamic-prompt".to_string();
-    let track_config = TrackConfig::default();
-
-    // Initialize extras with SIP headers and built-in variables
-    let mut initial_extras = std::collections::HashMap::new();
-    initial_extras.insert("X-Jobid".to_string(), serde_json::json!("JOB-456"));
-    initial_extras.insert(
-        "_sip_header_keys".to_string(),
-        serde_json::json!(["X-Jobid"]),
-    );
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
 
+    let mut app_config = Config::default();
+    app_config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(app_config)
+        .build()
+        .await?;
     let active_call = Arc::new(ActiveCall::new(
         ActiveCallType::Sip,
-        cancel_token,
-        session_id.clone(),
+        CancellationToken::new(),
+        "test-sentence-pipelining".to_string(),
         app_state.invitation.clone(),
         app_state.clone(),
-        track_config,
+        TrackConfig::default(),
         None,
         false,
         None,
-        Some(initial_extras),
+        None,
         None,
     ));
+    let mut cmd_rx = active_call.new_receiver().cmd_receiver;
 
-    // Create scenes with raw_prompt templates
-    let mut scenes = std::collections::HashMap::new();
-    scenes.insert(
-        "greeting".to_string(),
-        Scene {
-            id: "greeting".to_string(),
-            raw_prompt: Some(
-                "您好，客户意图：{{ intent }}\nJob ID：{{ sip[\"X-Jobid\"] }}".to_string(),
-            ),
-            prompt: "您好，客户意图：\nJob ID：JOB-456".to_string(),
-            ..Default::default()
-        },
-    );
-    scenes.insert(
-        "detail".to_string(),
-        Scene {
-            id: "detail".to_string(),
-            raw_prompt: Some("处理意图：{{ intent }}\n会话：{{ session_id }}".to_string()),
-            prompt: "处理意图：\n会话：".to_string(),
+    let gate = Arc::new(tokio::sync::Notify::new());
+    let provider = Arc::new(GatedProvider {
+        first: "Hello there. How can".to_string(),
+        rest: " I help you today? Take your time.".to_string(),
+        gate: gate.clone(),
+    });
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig {
+            sentence_pipelining: pipelining,
             ..Default::default()
         },
-    );
-
-    let llm_config = LlmConfig {
-        provider: "mock".to_string(),
-        prompt: Some("初始prompt".to_string()),
-        ..Default::default()
-    };
-
-    let provider = Arc::new(TestProvider::new(vec!["好的".to_string()]));
-    let mut handler = LlmHandler::with_provider(
-        llm_config,
         provider,
         Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
         None,
-        scenes,
+        HashMap::new(),
+        None,
         None,
         None,
-        Some("greeting".to_string()),
         None,
     );
-    handler.call = Some(active_call.clone());
+    handler.set_call(active_call.clone()).await;
+
+    let reply = tokio::spawn(async move {
+        let event = SessionEvent::AsrFinal {
+            track_id: "track-1".to_string(),
+            timestamp: 0,
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: "hi".to_string(),
+            is_filler: None,
+            confidence: None,
+            task_id: None,
+            raw_text: None,
+        };
+        handler.on_event(&event).await
+    });
 
-    // Simulate set_var: LLM sets intent during conversation
-    {
-        let mut state = active_call.call_state.write().await;
-        let mut extras = state.extras.take().unwrap_or_default();
-        extras.insert(
-            "intent".to_string(),
-            serde_json::Value::String("买零食".to_string()),
-        );
-        state.extras = Some(extras);
-    }
+    let before = collect_tts(&mut cmd_rx, Duration::from_millis(300)).await;
+    gate.notify_one();
+    reply.await??;
+    let after = collect_tts(&mut cmd_rx, Duration::from_millis(300)).await;
+    Ok((before, after))
+}
 
-    // Now switch to greeting scene, it should dynamically render with the latest variables
-    let commands = handler.switch_to_scene("greeting", false).await.unwrap();
-    // The commands should be empty since greeting scene has no play url
-    assert!(commands.is_empty());
+/// TTS texts received until no command arrives for `wait`
+async fn collect_tts(
+    cmd_rx: &mut crate::call::CommandReceiver,
+    wait: std::time::Duration,
+) -> Vec<String> {
+    let mut texts = Vec::new();
+    while let Ok(Ok(cmd)) = tokio::time::timeout(wait, cmd_rx.recv()).await {
+        match cmd {
+            Command::Tts { text, .. } if !text.is_empty() => texts.push(text),
+            _ => {}
+        }
+    }
+    texts
+}
 
-    // Check that the system prompt was dynamically rendered with intent
-    let system_msg = &handler.history[0];
-    assert_eq!(system_msg.role, "system");
-    assert!(
-        system_msg.content.contains("客户意图：买零食"),
-        "System prompt should contain dynamically rendered intent, got: {}",
-        system_msg.content
-    );
-    assert!(
-        system_msg.content.contains("Job ID：JOB-456"),
-        "System prompt should contain SIP header value, got: {}",
-        system_msg.content
-    );
+#[tokio::test]
+async fn handler_pipelines_sentences_to_tts() -> Result<()> {
+    let (before, after) = run_gated_reply(None).await?;
+    assert_eq!(before, vec!["Hello there. "]);
+    assert_eq!(after, vec!["How can I help you today? ", "Take your time."]);
+    Ok(())
+}
 
-    // Switch to detail scene, should also render dynamically
-    let _ = handler.switch_to_scene("detail", false).await.unwrap();
-    let system_msg = &handler.history[0];
-    assert!(
-        system_msg.content.contains("处理意图：买零食"),
-        "Detail scene should have rendered intent, got: {}",
-        system_msg.content
-    );
-    // Built-in session_id should be available
-    assert!(
-        system_msg
-            .content
-            .contains(&format!("会话：{}", session_id)),
-        "Detail scene should have session_id, got: {}",
-        system_msg.content
+#[tokio::test]
+async fn handler_waits_for_full_reply_without_pipelining() -> Result<()> {
+    let (before, after) = run_gated_reply(Some(false)).await?;
+    assert!(before.is_empty());
+    assert_eq!(
+        after,
+        vec![
+            "Hello there. ",
+            "How can I help you today? ",
+            "Take your time."
+        ]
     );
+    Ok(())
 }
 
-#[tokio::test]
-async fn test_dynamic_prompt_with_builtin_vars() {
-    use crate::app::AppStateBuilder;
-    use crate::call::{ActiveCall, ActiveCallType};
-    use crate::config::Config;
-    use crate::media::track::TrackConfig;
-    use crate::playbook::{BUILTIN_CALL_TYPE, BUILTIN_SESSION_ID, BUILTIN_START_TIME, Scene};
+/// Streams a reply as the given content chunks
+struct ChunkedProvider {
+    chunks: Vec<String>,
+}
 
-    let mut config = Config::default();
-    config.udp_port = 0;
-    let app_state = AppStateBuilder::new()
-        .with_config(config)
-        .build()
-        .await
-        .expect("Failed to build app state");
+#[async_trait]
+impl LlmProvider for ChunkedProvider {
+    async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+        Ok(self.chunks.concat())
+    }
 
-    let cancel_token = tokio_util::sync::CancellationToken::new();
-    let session_id = "session-builtin-test".to_string();
-    let track_config = TrackConfig::default();
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let chunks = self.chunks.clone();
+        let s = async_stream::stream! {
+            for chunk in chunks {
+                yield Ok(LlmStreamEvent::Content(chunk));
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
 
-    let active_call = Arc::new(ActiveCall::new(
-        ActiveCallType::Sip,
-        cancel_token,
-        session_id.clone(),
-        app_state.invitation.clone(),
-        app_state.clone(),
-        track_config,
+fn chunked_handler(chunks: &[&str]) -> LlmHandler {
+    let provider = Arc::new(ChunkedProvider {
+        chunks: chunks.iter().map(|c| c.to_string()).collect(),
+    });
+    LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
         None,
-        false,
         None,
         None,
         None,
+    )
+}
+
+#[tokio::test]
+async fn handler_rejects_invalid_streamed_refer_target() -> Result<()> {
+    let mut handler = chunked_handler(&[
+        "Transferring you now. ",
+        "<refer to=\"the sales team <ext 5>\"/>",
+    ]);
+
+    let commands = handler.generate_response().await?;
+    assert!(
+        !commands
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Refer { .. })),
+        "{:?}",
+        commands
+    );
+    assert!(handler.history.iter().any(|msg| msg.role == "system"
+        && msg.content.contains("refer callee")
+        && msg.content.contains("not a valid SIP URI")));
+
+    let mut handler = chunked_handler(&[
+        "Transferring you now. ",
+        "<refer to=\"sip:sales@example.com\"/>",
+    ]);
+    let commands = handler.generate_response().await?;
+    assert!(commands.iter().any(
+        |cmd| matches!(cmd, Command::Refer { callee, .. } if callee == "sip:sales@example.com")
     ));
+    Ok(())
+}
 
-    // Verify built-in variables were injected
-    {
-        let state = active_call.call_state.read().await;
-        let extras = state.extras.as_ref().expect("extras should exist");
-        assert_eq!(
-            extras.get(BUILTIN_SESSION_ID).and_then(|v| v.as_str()),
-            Some("session-builtin-test"),
-        );
-        assert_eq!(
-            extras.get(BUILTIN_CALL_TYPE).and_then(|v| v.as_str()),
-            Some("sip"),
-        );
-        assert!(extras.get(BUILTIN_START_TIME).is_some());
+#[tokio::test]
+async fn handler_streams_chunked_reply_as_one_tts_stream() -> Result<()> {
+    let mut handler = chunked_handler(&[
+        "Sure",
+        ", let me check",
+        " that for you. Your order",
+        " shipped yesterday! Anything",
+        " else",
+    ]);
+
+    let commands = handler.generate_response().await?;
+    let mut play_ids = Vec::new();
+    let mut texts = Vec::new();
+    for (i, cmd) in commands.iter().enumerate() {
+        let Command::Tts {
+            text,
+            play_id,
+            streaming,
+            end_of_stream,
+            ..
+        } = cmd
+        else {
+            panic!("unexpected command {:?}", cmd);
+        };
+        assert_eq!(*streaming, Some(true));
+        let last = i == commands.len() - 1;
+        assert_eq!(*end_of_stream, last.then_some(true), "command {}", i);
+        play_ids.push(play_id.clone().unwrap());
+        texts.push(text.clone());
     }
-
-    // Create a scene that uses built-in variables
-    let mut scenes = std::collections::HashMap::new();
-    scenes.insert(
-        "main".to_string(),
-        Scene {
-            id: "main".to_string(),
-            raw_prompt: Some("会话：{{ session_id }}\n类型：{{ call_type }}".to_string()),
-            prompt: "会话：\n类型：".to_string(),
-            ..Default::default()
-        },
+    assert_eq!(
+        texts,
+        vec![
+            "Sure, let me check that for you. ",
+            "Your order shipped yesterday! ",
+            "Anything else"
+        ]
     );
+    assert!(play_ids.iter().all(|id| id == &play_ids[0]));
+    Ok(())
+}
 
-    let llm_config = LlmConfig {
-        provider: "mock".to_string(),
-        prompt: Some("test".to_string()),
-        ..Default::default()
-    };
+#[tokio::test]
+async fn handler_buffers_chunked_json_reply() -> Result<()> {
+    let mut handler = chunked_handler(&[
+        "{\"text\": \"Thanks for calling.",
+        " Goodbye\", \"tools\": [{\"name\": ",
+        "\"hangup\", \"reason\": \"done\"}]}",
+    ]);
+
+    let commands = handler.generate_response().await?;
+    // Nothing is spoken until the whole JSON block has arrived
+    let tts_count = commands
+        .iter()
+        .filter(|cmd| matches!(cmd, Command::Tts { .. }))
+        .count();
+    assert_eq!(tts_count, 1, "{:?}", commands);
+    assert!(matches!(
+        commands.first(),
+        Some(Command::Tts {
+            text,
+            auto_hangup: Some(true),
+            ..
+        }) if text == "Thanks for calling. Goodbye"
+    ));
+    Ok(())
+}
 
-    let provider = Arc::new(TestProvider::new(vec![]));
+/// Replies in turn and records the history of every call
+struct HistoryProvider {
+    responses: Mutex<VecDeque<String>>,
+    histories: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+#[async_trait]
+impl LlmProvider for HistoryProvider {
+    async fn call(&self, _config: &LlmConfig, history: &[ChatMessage]) -> Result<String> {
+        self.histories.lock().unwrap().push(history.to_vec());
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow!("History provider ran out of responses"))
+    }
+
+    async fn call_stream(
+        &self,
+        config: &LlmConfig,
+        history: &[ChatMessage],
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let response = self.call(config, history).await?;
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content(response));
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+/// Ask for `path` on a local order service through the `http` tool, returning
+/// the final reply and the history the model saw when answering
+async fn run_http_tool(path: &str) -> Result<(Vec<Command>, Vec<ChatMessage>)> {
+    use axum::{Json, Router, routing::get};
+    use std::time::Duration;
+
+    let app = Router::new()
+        .route(
+            "/orders/42",
+            get(|| async { Json(serde_json::json!({ "status": "shipped" })) }),
+        )
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                "too late"
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}{}", listener.local_addr()?, path);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let provider = Arc::new(HistoryProvider {
+        responses: Mutex::new(VecDeque::from(vec![
+            format!(r#"{{"tools": [{{"name": "http", "url": "{}"}}]}}"#, url),
+            "Your order has shipped.".to_string(),
+        ])),
+        histories: Mutex::new(Vec::new()),
+    });
     let mut handler = LlmHandler::with_provider(
-        llm_config,
-        provider,
+        LlmConfig {
+            http_tool_timeout_ms: Some(200),
+            ..Default::default()
+        },
+        provider.clone(),
         Arc::new(NoopRagRetriever),
         crate::playbook::InterruptionConfig::default(),
         None,
-        scenes,
+        HashMap::new(),
+        None,
         None,
         None,
-        Some("main".to_string()),
         None,
     );
-    handler.call = Some(active_call.clone());
 
-    let _ = handler.switch_to_scene("main", false).await.unwrap();
-    let system_msg = &handler.history[0];
-    assert!(
-        system_msg
-            .content
-            .contains(&format!("会话：{}", session_id)),
-        "Should contain built-in session_id, got: {}",
-        system_msg.content
+    let commands = handler.generate_response().await?;
+    let histories = provider.histories.lock().unwrap();
+    assert_eq!(
+        histories.len(),
+        2,
+        "the model is asked again with the result"
     );
+    Ok((commands, histories[1].clone()))
+}
+
+fn tts_texts(commands: &[Command]) -> Vec<&str> {
+    commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            Command::Tts { text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn handler_feeds_http_tool_result_to_model() -> Result<()> {
+    let (commands, history) = run_http_tool("/orders/42").await?;
+    let result = history.last().unwrap();
+    assert_eq!(result.role, "system");
+    assert!(result.content.contains("200"), "{}", result.content);
+    assert!(result.content.contains("shipped"), "{}", result.content);
+    assert_eq!(tts_texts(&commands), vec!["Your order has shipped."]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn handler_reports_http_tool_timeout_to_model() -> Result<()> {
+    let (commands, history) = run_http_tool("/slow").await?;
+    let result = history.last().unwrap();
     assert!(
-        system_msg.content.contains("类型：sip"),
-        "Should contain built-in call_type, got: {}",
-        system_msg.content
+        result.content.contains("timed out after 200ms"),
+        "{}",
+        result.content
     );
+    assert_eq!(tts_texts(&commands), vec!["Your order has shipped."]);
+    Ok(())
 }
@@ -0,0 +1,104 @@
+use super::dialogue::DialogueHandler;
+use crate::call::Command;
+use crate::event::SessionEvent;
+use anyhow::Result;
+
+/// Drives a [`DialogueHandler`] with scripted session events, without a call or
+/// media, collecting the commands it returns in order.
+pub struct DialogueTestHarness<H: DialogueHandler> {
+    handler: H,
+    commands: Vec<Command>,
+}
+
+impl<H: DialogueHandler> DialogueTestHarness<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Run the handler's `on_start`, returning the commands it produced
+    pub async fn start(&mut self) -> Result<Vec<Command>> {
+        let commands = self.handler.on_start().await?;
+        self.commands.extend(commands.iter().cloned());
+        Ok(commands)
+    }
+
+    /// Feed one event to the handler, returning the commands it produced
+    pub async fn send(&mut self, event: SessionEvent) -> Result<Vec<Command>> {
+        let commands = self.handler.on_event(&event).await?;
+        self.commands.extend(commands.iter().cloned());
+        Ok(commands)
+    }
+
+    /// Start the handler and feed it `events` in order, returning every
+    /// command produced along the way
+    pub async fn replay(
+        &mut self,
+        events: impl IntoIterator<Item = SessionEvent>,
+    ) -> Result<Vec<Command>> {
+        let mut commands = self.start().await?;
+        for event in events {
+            commands.extend(self.send(event).await?);
+        }
+        Ok(commands)
+    }
+
+    /// All commands produced so far
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+}
+
+/// A final ASR result for `text`
+pub fn asr_final(text: &str) -> SessionEvent {
+    scored_asr_final(text, None)
+}
+
+/// A final ASR result for `text` with the recognizer's `confidence`
+pub fn scored_asr_final(text: &str, confidence: Option<f32>) -> SessionEvent {
+    let now = crate::media::get_timestamp();
+    SessionEvent::AsrFinal {
+        track_id: "harness".to_string(),
+        index: 0,
+        text: text.to_string(),
+        timestamp: now,
+        start_time: Some(now),
+        end_time: Some(now),
+        is_filler: None,
+        confidence,
+        task_id: None,
+        raw_text: None,
+    }
+}
+
+/// A DTMF key press
+pub fn dtmf(digit: &str) -> SessionEvent {
+    SessionEvent::Dtmf {
+        track_id: "harness".to_string(),
+        timestamp: crate::media::get_timestamp(),
+        digit: digit.to_string(),
+    }
+}
+
+/// Playback of a prompt starting on the call's track
+pub fn track_start() -> SessionEvent {
+    SessionEvent::TrackStart {
+        track_id: "harness".to_string(),
+        timestamp: crate::media::get_timestamp(),
+        play_id: None,
+    }
+}
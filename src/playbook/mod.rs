@@ -1,25 +1,21 @@
+use crate::config::expand_env_vars;
 use crate::media::recorder::RecorderOption;
 use crate::media::vad::VADOption;
 use crate::synthesis::SynthesisOption;
 use crate::transcription::TranscriptionOption;
-use crate::{EouOption, RealtimeOption, SipOption, media::ambiance::AmbianceOption};
+use crate::{
+    EouOption, RealtimeOption, SipOption,
+    media::{ambiance::AmbianceOption, loudness::LoudnessOption, watermark::WatermarkOption},
+};
 use anyhow::{Result, anyhow};
 use minijinja::Environment;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{collections::HashMap, path::Path};
 use tokio::fs;
 
-/// Expand environment variables in the format ${VAR_NAME}
-fn expand_env_vars(input: &str) -> String {
-    let re = regex::Regex::new(r"\$\{([^}]+)\}").unwrap();
-    re.replace_all(input, |caps: &regex::Captures| {
-        let var_name = &caps[1];
-        std::env::var(var_name).unwrap_or_else(|_| format!("${{{}}}", var_name))
-    })
-    .to_string()
-}
-
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum InterruptionStrategy {
@@ -34,7 +30,14 @@ pub enum InterruptionStrategy {
 #[serde(rename_all = "camelCase")]
 pub struct InterruptionConfig {
     pub strategy: InterruptionStrategy,
+    /// Deprecated and ignored, set `min_duration_ms` instead
     pub min_speech_ms: Option<u32>,
+    /// Caller speech must last this long before it interrupts playback.
+    /// Checked as ASR deltas arrive, so it is ignored by the `vad` strategy
+    pub min_duration_ms: Option<u32>,
+    /// Caller speech must reach this many words (CJK characters count one
+    /// each) in the ASR deltas before it interrupts playback
+    pub min_words: Option<usize>,
     pub filler_word_filter: Option<bool>,
     pub volume_fade_ms: Option<u32>,
     pub ignore_first_ms: Option<u32>,
@@ -49,17 +52,80 @@ pub struct PlaybookConfig {
     pub vad: Option<VADOption>,
     pub denoise: Option<bool>,
     pub ambiance: Option<AmbianceOption>,
+    pub output_loudness: Option<LoudnessOption>,
+    pub watermark: Option<WatermarkOption>,
     pub recorder: Option<RecorderOption>,
     pub extra: Option<HashMap<String, String>>,
     pub eou: Option<EouOption>,
     pub greeting: Option<String>,
+    /// Greetings keyed by language, picked by the call's language variable
+    pub greetings: Option<HashMap<String, String>>,
+    /// How the call opens, default `static` when a greeting is set
+    pub greeting_mode: Option<GreetingMode>,
     pub interruption: Option<InterruptionConfig>,
     pub dtmf: Option<HashMap<String, DtmfAction>>,
     pub dtmf_collectors: Option<HashMap<String, DtmfCollectorConfig>>,
     pub realtime: Option<RealtimeOption>,
     pub posthook: Option<PostHookConfig>,
+    /// Summarize the call with the LLM once it ends, for the call record and
+    /// an optional webhook
+    pub post_call_summary: Option<PostCallSummaryConfig>,
     pub follow_up: Option<FollowUpConfig>,
     pub sip: Option<SipOption>,
+    /// Let interim ASR results (AsrDelta) drive early responses, default false
+    pub use_interim_asr: Option<bool>,
+    /// Inject DTMF digits without a matching action or active collector into the
+    /// LLM history as user input (e.g. "[DTMF: 1]"), default false
+    pub dtmf_to_llm: Option<bool>,
+    /// Screen-pop callback POSTed when the call answers, overrides the global one
+    pub on_answer_url: Option<String>,
+    /// Offer a touch-tone menu after repeated low-confidence recognitions
+    pub low_confidence_fallback: Option<LowConfidenceFallbackConfig>,
+    /// Hang up once DTMF collectors keep timing out or failing across the call
+    pub no_input_hangup: Option<NoInputHangupConfig>,
+    /// Hold the greeting until the callee finished their opening "hello",
+    /// meant for outbound calls
+    pub greeting_gate: Option<GreetingGateConfig>,
+}
+
+/// Wait for the callee's first utterance to end before greeting, so the agent
+/// doesn't talk over an outbound callee picking up with "hello?"
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GreetingGateConfig {
+    /// Quiet after the callee's utterance before the greeting starts (default: 500)
+    pub silence_ms: Option<u64>,
+    /// Greet anyway once this long passed since answer, whether the callee
+    /// stayed silent or is still talking (default: 3000)
+    pub max_wait_ms: Option<u64>,
+}
+
+/// End the call with a caller who doesn't engage with DTMF collectors
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NoInputHangupConfig {
+    /// Collector timeouts without input and failed collections, counted
+    /// across the whole call, before hanging up (default: 3)
+    pub max_failures: Option<u32>,
+    /// Goodbye spoken before hanging up; without one the call ends right away
+    pub prompt: Option<String>,
+}
+
+/// Switch to DTMF input when ASR keeps returning low-confidence results
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LowConfidenceFallbackConfig {
+    /// Final results below this confidence count as low (default: 0.5)
+    pub min_confidence: Option<f32>,
+    /// Consecutive low-confidence results before falling back (default: 2)
+    pub max_turns: Option<u32>,
+    /// Menu spoken instead of answering, e.g. "Press 1 for billing, 2 for support"
+    pub prompt: String,
+    /// Collector from `dtmfCollectors` started after the prompt; without one,
+    /// keys go to the `dtmf` actions and then to the LLM
+    pub collector: Option<String>,
+    /// Variable the collected digits are stored in (default: "menu_choice")
+    pub var_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
@@ -67,6 +133,20 @@ pub struct PlaybookConfig {
 pub struct FollowUpConfig {
     pub timeout: u64,
     pub max_count: u32,
+    /// What to do once `max_count` follow-ups went unanswered, default `hangup`
+    #[serde(default)]
+    pub on_max: FollowUpExhausted,
+}
+
+/// Behaviour after the last silence follow-up got no answer
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FollowUpExhausted {
+    /// End the call
+    #[default]
+    Hangup,
+    /// Stay silent without querying the LLM until the caller speaks again
+    Wait,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -106,6 +186,41 @@ pub struct PostHookConfig {
     pub include_history: Option<bool>,
 }
 
+const DEFAULT_POST_CALL_SUMMARY_PROMPT: &str = "Summarize this call for the CRM: why the caller called, the outcome (disposition), and any action items with who owns them.";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PostCallSummaryConfig {
+    /// Default true when the section is present
+    pub enabled: Option<bool>,
+    /// Instruction sent to the LLM after the call transcript, by default
+    /// asking for the disposition and action items
+    pub prompt: Option<String>,
+    /// URL POSTed `{sessionId, summary, timestamp}` once the summary is
+    /// ready. The summary is always kept in the call record extras as
+    /// `post_call_summary`
+    pub webhook: Option<String>,
+    /// Give up on the summary after this many milliseconds, so a stalled
+    /// LLM does not hold the call record back (default: 30000)
+    pub timeout_ms: Option<u64>,
+}
+
+impl PostCallSummaryConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn prompt(&self) -> &str {
+        self.prompt
+            .as_deref()
+            .unwrap_or(DEFAULT_POST_CALL_SUMMARY_PROMPT)
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_ms.unwrap_or(30_000))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(tag = "action", rename_all = "lowercase")]
 pub enum DtmfAction {
@@ -146,8 +261,15 @@ pub struct DtmfCollectorConfig {
     pub validation: Option<DtmfValidation>,
     /// Max retry attempts when validation fails (default: 3)
     pub retry_times: Option<u32>,
-    /// Whether voice input (ASR) can interrupt collection (default: false)
+    /// Whether voice input (ASR) can interrupt collection (default: false).
+    /// Also lets a key press or speech cut the collector's prompt short
     pub interruptible: Option<bool>,
+    /// URL POSTed `{var_name, value, call_id}` when collection succeeds
+    pub on_complete_url: Option<String>,
+    /// Also take digits the caller speaks ("one three eight", "一三八"),
+    /// mixed freely with key presses; "clear" or "start over" empties the
+    /// digits entered so far (default: false)
+    pub accept_speech: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -159,13 +281,91 @@ pub struct LlmConfig {
     pub api_key: Option<String>,
     pub prompt: Option<String>,
     pub greeting: Option<String>,
+    /// Greetings keyed by language, e.g. `{es: "¡Hola!"}`. The call variable
+    /// named by `greeting_language_var` picks one, `greeting` is the fallback
+    pub greetings: Option<HashMap<String, String>>,
+    /// Call variable (extras or captured SIP header) holding the caller's
+    /// language (default: "lang")
+    pub greeting_language_var: Option<String>,
+    pub greeting_mode: Option<GreetingMode>,
     pub language: Option<String>,
     pub features: Option<Vec<String>>,
     pub repair_window_ms: Option<u64>,
     pub summary_limit: Option<usize>,
+    /// Summarize the oldest turns into the system prompt before a request
+    /// once the history holds more messages than this, like the rolling
+    /// summary. The latest turns are kept verbatim. Unbounded when unset
+    pub max_history_messages: Option<usize>,
+    /// Longest caller/callee URI accepted from a `refer` tool (default: 256)
+    pub max_tool_uri_length: Option<usize>,
+    /// Longest reason accepted from a `hangup` tool (default: 128)
+    pub max_hangup_reason_length: Option<usize>,
     /// Custom tool instructions. If not set, default tool instructions based on language will be used.
     /// Set this to override the built-in tool usage instructions completely.
     pub tool_instructions: Option<String>,
+    pub rag: Option<RagConfig>,
+    /// Give up on an `http` tool request after this many milliseconds and
+    /// tell the model it failed (default: 10000)
+    pub http_tool_timeout_ms: Option<u64>,
+    /// Ask the provider for a JSON object response (OpenAI `response_format`,
+    /// Gemini `responseMimeType`). Only enable when the prompt asks for the
+    /// structured JSON reply, free text is the default
+    pub json_mode: Option<bool>,
+    /// Offer the hangup, refer and rag tools as native OpenAI functions
+    /// (`tools`) and act on the model's `tool_calls`, instead of relying on
+    /// the tools written into the reply text. The text forms still work, for
+    /// providers without function calling (default: false)
+    pub function_calling: Option<bool>,
+    /// Speak each sentence as soon as the model completes it instead of
+    /// waiting for the whole reply (default: true)
+    pub sentence_pipelining: Option<bool>,
+    /// Sampling temperature, 0 for deterministic flows. Unset keeps the
+    /// provider default, as do `top_p` and `max_tokens`
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Most tokens generated for one reply
+    pub max_tokens: Option<u32>,
+    /// Blank `{{ var }}` placeholders of the prompt whose call variable has no
+    /// value, instead of leaving them as written (default: false)
+    pub blank_unknown_vars: Option<bool>,
+    /// Keep every LLM request, raw reply, tool call and RAG lookup of the
+    /// call in the call record extras under `llm_trace`, written at hangup.
+    /// Each request holds the messages added since the previous one, the
+    /// first the full prompt, so it is off by default
+    pub save_llm_trace: Option<bool>,
+    /// Ask for the token usage of streamed replies
+    /// (`stream_options.include_usage`). Some OpenAI compatible servers
+    /// reject the option, so it defaults to on only for api.openai.com, and
+    /// usage is estimated when the provider reports none
+    pub stream_usage: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GreetingMode {
+    /// Speak the static `greeting`, falls back to `llm` when none is set
+    #[default]
+    Static,
+    /// Let the LLM generate the opening turn
+    Llm,
+    /// Speak the static `greeting` right away and hand it to the LLM as its
+    /// own opening turn, so the first reply continues from it
+    StaticThenLlm,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RagConfig {
+    /// Abandon a retrieval that takes longer than this many milliseconds and
+    /// let the LLM continue without results. No limit when unset.
+    pub timeout_ms: Option<u64>,
+    /// Role of the history message carrying retrieved context: "system" (the
+    /// default), "user" or "assistant". Other roles fall back to "system"
+    pub injection_role: Option<String>,
+    /// Content of that message. `{query}`, `{result}` and `{source}` are
+    /// replaced, `{summary}` is the result prefixed with `[source]` when known.
+    /// Defaults to "RAG result for {query}: {summary}"
+    pub injection_template: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -194,18 +394,15 @@ pub const BUILTIN_CALL_TYPE: &str = "call_type";
 pub const BUILTIN_CALLER: &str = "caller";
 pub const BUILTIN_CALLEE: &str = "callee";
 pub const BUILTIN_START_TIME: &str = "start_time";
+pub const BUILTIN_CALL_ID: &str = "call_id";
 
-/// Render a scene prompt template dynamically using the current variables.
-/// This allows `set_var` values set during conversation to be used in scene prompts.
-///
-/// If `raw_prompt` is `None` or rendering fails, falls back to the pre-rendered `prompt`.
-pub fn render_scene_prompt(scene: &Scene, vars: &HashMap<String, serde_json::Value>) -> String {
-    let template = match &scene.raw_prompt {
-        Some(t) if t.contains("{{") => t,
-        _ => return scene.prompt.clone(),
-    };
+/// A `{{ ... }}` placeholder and the variable it starts with
+static RE_PROMPT_VAR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)[^}]*\}\}").unwrap());
 
-    let env = Environment::new();
+/// Template context of the call variables, with the extracted SIP headers
+/// gathered under `sip` and internal keys left out
+fn template_context(vars: &HashMap<String, Value>) -> HashMap<String, Value> {
     let mut context = vars.clone();
 
     // Build sip dictionary from _sip_header_keys (same logic as Playbook::render)
@@ -227,6 +424,46 @@ pub fn render_scene_prompt(scene: &Scene, vars: &HashMap<String, serde_json::Val
 
     // Remove internal keys from context
     context.retain(|k, _| !k.starts_with('_'));
+    context
+}
+
+/// Render the `{{ var }}` placeholders of a prompt with the call variables.
+/// Placeholders of variables without a value are kept as written, or blanked
+/// with `blank_unknown`. A template that fails to render is returned as is.
+pub fn render_prompt(template: &str, vars: &HashMap<String, Value>, blank_unknown: bool) -> String {
+    if !template.contains("{{") {
+        return template.to_string();
+    }
+    let context = template_context(vars);
+    let source = if blank_unknown {
+        std::borrow::Cow::Borrowed(template)
+    } else {
+        RE_PROMPT_VAR.replace_all(template, |caps: &regex::Captures| {
+            // Filters such as `default` handle the missing value themselves
+            if context.contains_key(&caps[1]) || caps[0].contains('|') {
+                caps[0].to_string()
+            } else {
+                format!("{{% raw %}}{}{{% endraw %}}", &caps[0])
+            }
+        })
+    };
+    Environment::new()
+        .render_str(&source, &context)
+        .unwrap_or_else(|_| template.to_string())
+}
+
+/// Render a scene prompt template dynamically using the current variables.
+/// This allows `set_var` values set during conversation to be used in scene prompts.
+///
+/// If `raw_prompt` is `None` or rendering fails, falls back to the pre-rendered `prompt`.
+pub fn render_scene_prompt(scene: &Scene, vars: &HashMap<String, serde_json::Value>) -> String {
+    let template = match &scene.raw_prompt {
+        Some(t) if t.contains("{{") => t,
+        _ => return scene.prompt.clone(),
+    };
+
+    let env = Environment::new();
+    let context = template_context(vars);
 
     match env.render_str(template, &context) {
         Ok(rendered) => rendered,
@@ -315,8 +552,10 @@ impl Playbook {
         let dtmf_regex =
             regex::Regex::new(r#"<dtmf\s+digit="([^"]+)"\s+action="([^"]+)"(?:\s+scene="([^"]+)")?(?:\s+target="([^"]+)")?\s*/>"#).unwrap();
         let play_regex = regex::Regex::new(r#"<play\s+file="([^"]+)"\s*/>"#).unwrap();
-        let followup_regex =
-            regex::Regex::new(r#"<followup\s+timeout="(\d+)"\s+max="(\d+)"\s*/>"#).unwrap();
+        let followup_regex = regex::Regex::new(
+            r#"<followup\s+timeout="(\d+)"\s+max="(\d+)"(?:\s+action="(hangup|wait)")?\s*/>"#,
+        )
+        .unwrap();
 
         let parse_scene = |id: String, content: String| -> Scene {
             let mut dtmf_map = HashMap::new();
@@ -356,7 +595,15 @@ impl Playbook {
             if let Some(cap) = followup_regex.captures(&content) {
                 let timeout = cap.get(1).unwrap().as_str().parse().unwrap_or(0);
                 let max_count = cap.get(2).unwrap().as_str().parse().unwrap_or(0);
-                follow_up = Some(FollowUpConfig { timeout, max_count });
+                let on_max = match cap.get(3).map(|m| m.as_str()) {
+                    Some("wait") => FollowUpExhausted::Wait,
+                    _ => FollowUpExhausted::Hangup,
+                };
+                follow_up = Some(FollowUpConfig {
+                    timeout,
+                    max_count,
+                    on_max,
+                });
             }
 
             // Remove dtmf and play tags from the content
@@ -456,10 +703,12 @@ impl Playbook {
 
 pub mod dialogue;
 pub mod handler;
+pub mod harness;
 pub mod runner;
 
 pub use dialogue::DialogueHandler;
 pub use handler::{LlmHandler, RagRetriever};
+pub use harness::DialogueTestHarness;
 pub use runner::PlaybookRunner;
 
 #[cfg(test)]
@@ -1163,6 +1412,27 @@ llm:
         assert!(rendered.contains("开始时间：2026-02-14T10:00:00Z"));
     }
 
+    #[test]
+    fn test_render_prompt_unknown_vars() {
+        let template =
+            "Hello {{ customer }}, order {{ order_id }}, tier {{ tier | default('basic') }}";
+        let mut vars = HashMap::new();
+        vars.insert("customer".to_string(), json!("Ana"));
+
+        assert_eq!(
+            render_prompt(template, &vars, false),
+            "Hello Ana, order {{ order_id }}, tier basic"
+        );
+        assert_eq!(
+            render_prompt(template, &vars, true),
+            "Hello Ana, order , tier basic"
+        );
+        assert_eq!(
+            render_prompt("No placeholders", &vars, false),
+            "No placeholders"
+        );
+    }
+
     #[test]
     fn test_render_scene_prompt_mixed_sip_and_set_var() {
         // Test mixed SIP headers and set_var variables in dynamic rendering
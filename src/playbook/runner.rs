@@ -1,17 +1,27 @@
 use crate::CallOption;
 use crate::call::{ActiveCallRef, Command};
-use crate::event::EventReceiver;
+use crate::event::{EventReceiver, SessionEvent};
 use anyhow::{Result, anyhow};
 use serde_json::json;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
 use tracing::{error, info, warn};
 
-use super::{Playbook, PlaybookConfig, dialogue::DialogueHandler, handler::LlmHandler};
+use super::{
+    InterruptionConfig, Playbook, PlaybookConfig, PostCallSummaryConfig, PostHookConfig,
+    dialogue::DialogueHandler, handler::LlmHandler,
+};
 
 pub struct PlaybookRunner {
     handler: Box<dyn DialogueHandler>,
     call: ActiveCallRef,
     config: PlaybookConfig,
     event_receiver: EventReceiver,
+    /// Events that arrived while the greeting gate was held, replayed to
+    /// the handler before new ones
+    pending_events: VecDeque<SessionEvent>,
 }
 
 impl PlaybookRunner {
@@ -26,12 +36,14 @@ impl PlaybookRunner {
             call,
             config,
             event_receiver,
+            pending_events: VecDeque::new(),
         }
     }
 
-    pub fn new(playbook: Playbook, call: ActiveCallRef) -> Result<Self> {
+    pub async fn new(playbook: Playbook, call: ActiveCallRef) -> Result<Self> {
         let event_receiver = call.event_sender.subscribe();
-        if let Ok(mut state) = call.call_state.try_write() {
+        {
+            let mut state = call.call_state.write().await;
             // Ensure option exists before applying config
             if state.option.is_none() {
                 state.option = Some(CallOption::default());
@@ -46,7 +58,16 @@ impl PlaybookRunner {
             if let Some(greeting) = playbook.config.greeting.clone() {
                 llm_config.greeting = Some(greeting);
             }
-            let interruption_config = playbook.config.interruption.clone().unwrap_or_default();
+            if let Some(greetings) = playbook.config.greetings.clone() {
+                llm_config.greetings = Some(greetings);
+            }
+            if let Some(greeting_mode) = playbook.config.greeting_mode {
+                llm_config.greeting_mode = Some(greeting_mode);
+            }
+            let interruption_config = resolve_interruption_config(
+                &playbook.config,
+                call.app_state.config().interruption.as_ref(),
+            );
             let dtmf_config = playbook.config.dtmf.clone();
             let dtmf_collectors = playbook.config.dtmf_collectors.clone();
 
@@ -60,9 +81,28 @@ impl PlaybookRunner {
                 playbook.initial_scene_id.clone(),
                 playbook.config.sip.clone(),
             );
-            // Set event sender for debugging
+            // Set the call first so the greeting announced with the event sender
+            // matches the caller's language
+            llm_handler.set_call(call.clone()).await;
             llm_handler.set_event_sender(call.event_sender.clone());
-            llm_handler.set_call(call.clone());
+            llm_handler.set_use_interim_asr(playbook.config.use_interim_asr.unwrap_or(false));
+            llm_handler.set_dtmf_to_llm(playbook.config.dtmf_to_llm.unwrap_or(false));
+            llm_handler
+                .set_low_confidence_fallback(playbook.config.low_confidence_fallback.clone());
+            llm_handler.set_no_input_hangup(playbook.config.no_input_hangup.clone());
+            if let Some(tts) = playbook
+                .config
+                .tts
+                .as_ref()
+                .filter(|tts| tts.normalize_text == Some(true))
+            {
+                let language = tts
+                    .language
+                    .clone()
+                    .or_else(|| playbook.config.llm.as_ref()?.language.clone())
+                    .unwrap_or_else(|| "zh".to_string());
+                llm_handler.set_text_normalization(Some(language));
+            }
             Box::new(llm_handler)
         } else {
             return Err(anyhow!(
@@ -75,9 +115,61 @@ impl PlaybookRunner {
             call,
             config: playbook.config,
             event_receiver,
+            pending_events: VecDeque::new(),
         })
     }
 
+    /// Wait for the callee's opening utterance to end, as configured by
+    /// `greetingGate`. Returns false when the call hung up meanwhile.
+    /// Events other than the callee's speech, like DTMF, are kept for the
+    /// handler
+    async fn wait_greeting_gate(&mut self) -> bool {
+        let Some(gate) = self.config.greeting_gate.clone() else {
+            return true;
+        };
+        let silence = Duration::from_millis(gate.silence_ms.unwrap_or(500));
+        let deadline = Instant::now() + Duration::from_millis(gate.max_wait_ms.unwrap_or(3000));
+        let mut heard = false;
+        let mut quiet_since: Option<Instant> = None;
+        loop {
+            let wake = match quiet_since {
+                Some(quiet_since) => (quiet_since + silence).min(deadline),
+                None => deadline,
+            };
+            let event = match tokio::time::timeout_at(wake, self.event_receiver.recv()).await {
+                Ok(Ok(event)) => event,
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return false,
+                Err(_) => {
+                    if quiet_since.is_some() && wake < deadline {
+                        info!("Callee finished their opening utterance, greeting");
+                    } else {
+                        info!("Greeting gate timed out, greeting");
+                    }
+                    return true;
+                }
+            };
+            match event {
+                SessionEvent::Speaking { .. } => {
+                    heard = true;
+                    quiet_since = None;
+                }
+                SessionEvent::Silence { .. } => {
+                    if heard {
+                        quiet_since = Some(Instant::now());
+                    }
+                }
+                SessionEvent::AsrFinal { .. } => {
+                    heard = true;
+                    quiet_since = Some(Instant::now());
+                }
+                SessionEvent::AsrDelta { .. } => {}
+                SessionEvent::Hangup { .. } => return false,
+                event => self.pending_events.push_back(event),
+            }
+        }
+    }
+
     pub async fn run(mut self) {
         info!(
             "PlaybookRunner started for session {}",
@@ -89,6 +181,7 @@ impl PlaybookRunner {
             state.answer_time.is_some()
         };
 
+        let mut gated = self.config.greeting_gate.is_none();
         if let Ok(commands) = self.handler.on_start().await {
             for cmd in commands {
                 let is_media = matches!(cmd, Command::Tts { .. } | Command::Play { .. });
@@ -111,6 +204,14 @@ impl PlaybookRunner {
                     }
                 }
 
+                if is_media && !gated {
+                    gated = true;
+                    if !self.wait_greeting_gate().await {
+                        info!("Call hung up before the greeting, stopping");
+                        return;
+                    }
+                }
+
                 if let Err(e) = self.call.enqueue_command(cmd).await {
                     error!("Failed to enqueue start command: {}", e);
                 }
@@ -134,7 +235,31 @@ impl PlaybookRunner {
             }
         }
 
-        while let Ok(event) = self.event_receiver.recv().await {
+        loop {
+            let received = match self.pending_events.pop_front() {
+                Some(event) => Ok(event),
+                None => self.event_receiver.recv().await,
+            };
+            let event = match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    // Keep the dialogue going when a burst outpaced the
+                    // handler, and let it resync what the lost events held
+                    warn!(
+                        session_id = self.call.session_id,
+                        skipped, "playbook event receiver lagged"
+                    );
+                    if let Ok(commands) = self.handler.on_lagged(skipped).await {
+                        for cmd in commands {
+                            if let Err(e) = self.call.enqueue_command(cmd).await {
+                                error!("Failed to enqueue command: {}", e);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
             if let Ok(commands) = self.handler.on_event(&event).await {
                 for cmd in commands {
                     if let Err(e) = self.call.enqueue_command(cmd).await {
@@ -151,69 +276,167 @@ impl PlaybookRunner {
             }
         }
 
-        // Post-hook logic
-        if let Some(posthook) = self.config.posthook.clone() {
-            let mut handler = self.handler;
-            let call = self.call.clone();
-            crate::spawn(async move {
-                info!("Executing posthook for session {}", call.session_id);
-
-                let summary = if let Some(summary_type) = &posthook.summary {
-                    match handler.summarize(summary_type.prompt()).await {
-                        Ok(s) => Some(s),
-                        Err(e) => {
-                            error!("Failed to generate summary: {}", e);
-                            None
-                        }
-                    }
-                } else {
-                    None
-                };
-
-                let history = if posthook.include_history.unwrap_or(true) {
-                    Some(handler.get_history().await)
-                } else {
-                    None
-                };
-
-                let payload = json!({
-                    "sessionId": call.session_id,
-                    "summary": summary,
-                    "history": history,
-                    "timestamp": chrono::Utc::now().to_rfc3339(),
-                });
-
-                let client = reqwest::Client::new();
-                let method = posthook
-                    .method
-                    .as_deref()
-                    .unwrap_or("POST")
-                    .parse::<reqwest::Method>()
-                    .unwrap_or(reqwest::Method::POST);
-
-                let mut request = client.request(method, &posthook.url).json(&payload);
-
-                if let Some(headers) = posthook.headers {
-                    for (k, v) in headers {
-                        request = request.header(k, v);
-                    }
-                }
+        let summary_config = self
+            .config
+            .post_call_summary
+            .clone()
+            .filter(|c| c.is_enabled());
+        let posthook = self.config.posthook.clone();
+        if summary_config.is_none() && posthook.is_none() {
+            return;
+        }
+        // The task keeps the call alive, so the summary lands in its call
+        // record, which is written when the call is dropped
+        let mut handler = self.handler;
+        let call = self.call.clone();
+        crate::spawn(async move {
+            if let Some(config) = summary_config {
+                post_call_summary(handler.as_mut(), &call, &config).await;
+            }
+            if let Some(posthook) = posthook {
+                send_posthook(handler.as_mut(), &call, posthook).await;
+            }
+        });
+    }
+}
 
-                match request.send().await {
-                    Ok(resp) => {
-                        if resp.status().is_success() {
-                            info!("Posthook sent successfully");
-                        } else {
-                            warn!("Posthook failed with status: {}", resp.status());
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to send posthook: {}", e);
-                    }
-                }
-            });
+/// Summarize the call transcript, keep the summary in the call extras as
+/// `post_call_summary` and POST it to the configured webhook. A summary that
+/// is a JSON document is kept as JSON
+async fn post_call_summary(
+    handler: &mut dyn DialogueHandler,
+    call: &ActiveCallRef,
+    config: &PostCallSummaryConfig,
+) {
+    info!(
+        "Generating post-call summary for session {}",
+        call.session_id
+    );
+    let summary =
+        match tokio::time::timeout(config.timeout(), handler.summarize(config.prompt())).await {
+            Ok(Ok(summary)) => summary,
+            Ok(Err(e)) => {
+                error!("Failed to generate post-call summary: {}", e);
+                return;
+            }
+            Err(_) => {
+                warn!(
+                    "Post-call summary for session {} timed out after {:?}",
+                    call.session_id,
+                    config.timeout()
+                );
+                return;
+            }
+        };
+    let summary = serde_json::from_str::<serde_json::Value>(summary.trim())
+        .ok()
+        .filter(|v| v.is_object() || v.is_array())
+        .unwrap_or(serde_json::Value::String(summary));
+
+    call.call_state
+        .write()
+        .await
+        .extras
+        .get_or_insert_with(Default::default)
+        .insert("post_call_summary".to_string(), summary.clone());
+
+    let Some(url) = &config.webhook else {
+        return;
+    };
+    let payload = json!({
+        "sessionId": call.session_id,
+        "summary": summary,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+    });
+    match crate::net_tool::with_request_timeout(
+        crate::net_tool::http_client().post(url).json(&payload),
+    )
+    .send()
+    .await
+    {
+        Ok(resp) if resp.status().is_success() => info!("Post-call summary sent"),
+        Ok(resp) => warn!(
+            "Post-call summary webhook failed with status: {}",
+            resp.status()
+        ),
+        Err(e) => error!("Failed to send post-call summary: {}", e),
+    }
+}
+
+async fn send_posthook(
+    handler: &mut dyn DialogueHandler,
+    call: &ActiveCallRef,
+    posthook: PostHookConfig,
+) {
+    info!("Executing posthook for session {}", call.session_id);
+
+    let summary = if let Some(summary_type) = &posthook.summary {
+        match handler.summarize(summary_type.prompt()).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                error!("Failed to generate summary: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let history = if posthook.include_history.unwrap_or(true) {
+        Some(handler.get_history().await)
+    } else {
+        None
+    };
+
+    let payload = json!({
+        "sessionId": call.session_id,
+        "summary": summary,
+        "history": history,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+    });
+
+    let client = crate::net_tool::http_client();
+    let method = posthook
+        .method
+        .as_deref()
+        .unwrap_or("POST")
+        .parse::<reqwest::Method>()
+        .unwrap_or(reqwest::Method::POST);
+
+    let mut request =
+        crate::net_tool::with_request_timeout(client.request(method, &posthook.url).json(&payload));
+
+    if let Some(headers) = posthook.headers {
+        for (k, v) in headers {
+            request = request.header(k, v);
         }
     }
+
+    match request.send().await {
+        Ok(resp) => {
+            if resp.status().is_success() {
+                info!("Posthook sent successfully");
+            } else {
+                warn!("Posthook failed with status: {}", resp.status());
+            }
+        }
+        Err(e) => {
+            error!("Failed to send posthook: {}", e);
+        }
+    }
+}
+
+/// The playbook's own interruption section, else the configured global
+/// defaults, else the built-in ones
+pub fn resolve_interruption_config(
+    config: &PlaybookConfig,
+    global: Option<&InterruptionConfig>,
+) -> InterruptionConfig {
+    config
+        .interruption
+        .clone()
+        .or_else(|| global.cloned())
+        .unwrap_or_default()
 }
 
 pub fn apply_playbook_config(option: &mut CallOption, config: &PlaybookConfig) {
@@ -240,6 +463,12 @@ pub fn apply_playbook_config(option: &mut CallOption, config: &PlaybookConfig) {
     if let Some(ambiance) = config.ambiance.clone() {
         option.ambiance = Some(ambiance);
     }
+    if let Some(output_loudness) = config.output_loudness.clone() {
+        option.output_loudness = Some(output_loudness);
+    }
+    if let Some(watermark) = config.watermark.clone() {
+        option.watermark = Some(watermark);
+    }
     if let Some(recorder) = config.recorder.clone() {
         option.recorder = Some(recorder);
     }
@@ -261,6 +490,9 @@ pub fn apply_playbook_config(option: &mut CallOption, config: &PlaybookConfig) {
     if let Some(sip) = config.sip.clone() {
         option.sip = Some(sip);
     }
+    if let Some(on_answer_url) = config.on_answer_url.clone() {
+        option.on_answer_url = Some(on_answer_url);
+    }
 }
 
 #[cfg(test)]
@@ -307,6 +539,46 @@ mod tests {
         assert!(option.eou.is_some());
     }
 
+    #[test]
+    fn interruption_config_falls_back_to_global_defaults() {
+        use super::super::InterruptionStrategy;
+
+        let global = InterruptionConfig {
+            strategy: InterruptionStrategy::Vad,
+            min_speech_ms: Some(400),
+            ignore_first_ms: Some(1200),
+            ..Default::default()
+        };
+
+        let inherited = resolve_interruption_config(&PlaybookConfig::default(), Some(&global));
+        assert_eq!(inherited.strategy, InterruptionStrategy::Vad);
+        assert_eq!(inherited.min_speech_ms, Some(400));
+        assert_eq!(inherited.ignore_first_ms, Some(1200));
+
+        let own = PlaybookConfig {
+            interruption: Some(InterruptionConfig {
+                strategy: InterruptionStrategy::Asr,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let kept = resolve_interruption_config(&own, Some(&global));
+        assert_eq!(kept.strategy, InterruptionStrategy::Asr);
+        assert_eq!(kept.min_speech_ms, None);
+
+        let builtin = resolve_interruption_config(&PlaybookConfig::default(), None);
+        assert_eq!(builtin.strategy, InterruptionStrategy::Both);
+        assert_eq!(builtin.min_speech_ms, None);
+    }
+
+    #[test]
+    fn interruption_config_ignores_older_min_speech_ms() {
+        let config: InterruptionConfig =
+            serde_json::from_str(r#"{"strategy": "both", "minSpeechMs": 300}"#).unwrap();
+        assert_eq!(config.min_speech_ms, Some(300));
+        assert_eq!(config.min_duration_ms, None);
+    }
+
     #[test]
     fn apply_playbook_config_propagates_api_key() {
         let mut option = CallOption::default();
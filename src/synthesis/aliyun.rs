@@ -1,4 +1,7 @@
-use super::{SynthesisClient, SynthesisOption, SynthesisType};
+use super::{
+    SynthesisClient, SynthesisOption, SynthesisType,
+    limiter::{self, throttle},
+};
 use crate::synthesis::SynthesisEvent;
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
@@ -314,10 +317,14 @@ impl SynthesisClient for StreamingClient {
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        // The connection holds a request slot for as long as it is open
+        let (permit, waited_ms) = limiter::acquire(&SynthesisType::Aliyun).await;
         let ws_stream = connect(self.task_id.clone(), self.option.clone()).await?;
         let (ws_sink, ws_source) = ws_stream.split();
         self.ws_sink.replace(ws_sink);
-        Ok(event_stream(ws_source).map(move |x| (None, x)).boxed())
+        Ok(limiter::hold(permit, waited_ms, event_stream(ws_source))
+            .map(move |x| (None, x))
+            .boxed())
     }
 
     async fn synthesize(
@@ -381,7 +388,7 @@ impl SynthesisClient for NonStreamingClient {
                 let task_id = Uuid::new_v4().to_string();
                 let text_clone = text.clone();
                 let task_id_clone = task_id.clone();
-                connect(task_id, option)
+                let events = connect(task_id, option)
                     .then(async move |res| match res {
                         Ok(mut ws_stream) => {
                             let continue_task_cmd =
@@ -400,7 +407,8 @@ impl SynthesisClient for NonStreamingClient {
                             stream::once(future::ready(Err(e.into()))).boxed()
                         }
                     })
-                    .flatten_stream()
+                    .flatten_stream();
+                throttle(SynthesisType::Aliyun, events)
                     .map(move |x| (cmd_seq, x))
                     .boxed()
             })
@@ -0,0 +1,51 @@
+//! Splitting of long text into several synthesis requests, so text longer
+//! than a provider's per-request limit can still be spoken in one command.
+
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', ';', '\n', '。', '！', '？', '；'];
+const CLAUSE_ENDS: &[char] = &[',', '，', '、', ':', '：', ' '];
+
+/// Split `text` into chunks of at most `max_chars` characters, breaking at
+/// sentence ends where possible. A sentence longer than `max_chars` is broken
+/// at the last clause end or space that fits, else mid-word.
+pub fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for sentence in text.split_inclusive(SENTENCE_ENDS) {
+        let len = sentence.chars().count();
+        if current.chars().count() + len <= max_chars {
+            current.push_str(sentence);
+            continue;
+        }
+        push_chunk(&mut chunks, &current);
+        current.clear();
+        if len <= max_chars {
+            current.push_str(sentence);
+            continue;
+        }
+        let mut rest = sentence;
+        while rest.chars().count() > max_chars {
+            let limit = rest
+                .char_indices()
+                .nth(max_chars)
+                .map(|(i, _)| i)
+                .unwrap_or(rest.len());
+            let cut = rest[..limit]
+                .rfind(CLAUSE_ENDS)
+                .map(|i| i + rest[i..].chars().next().map_or(1, char::len_utf8))
+                .unwrap_or(limit);
+            push_chunk(&mut chunks, &rest[..cut]);
+            rest = &rest[cut..];
+        }
+        current.push_str(rest);
+    }
+    push_chunk(&mut chunks, &current);
+    chunks
+}
+
+fn push_chunk(chunks: &mut Vec<String>, chunk: &str) {
+    let chunk = chunk.trim();
+    if !chunk.is_empty() {
+        chunks.push(chunk.to_string());
+    }
+}
@@ -1,4 +1,7 @@
-use crate::synthesis::{SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType};
+use crate::synthesis::{
+    SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType,
+    limiter::{self, throttle},
+};
 use anyhow::Result;
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -89,7 +92,7 @@ async fn chunked_stream(
         .as_ref()
         .ok_or_else(|| anyhow!("Deepegram tts: missing api key"))?;
     let payload = Payload { text };
-    let client = reqwest::Client::new();
+    let client = crate::net_tool::http_client();
     let resp = client
         .post(url)
         .header("Content-Type", "application/json")
@@ -117,7 +120,7 @@ impl SynthesisClient for RestClient {
             max_concurrent_tasks,
             move |(text, cmd_seq, cmd_option)| {
                 let option = client_option.merge_with(cmd_option);
-                chunked_stream(option, text)
+                let events = chunked_stream(option, text)
                     .map(move |res| match res {
                         Ok(stream) => stream
                             .map(move |res| res.map(|bytes| SynthesisEvent::AudioChunk(bytes)))
@@ -125,7 +128,8 @@ impl SynthesisClient for RestClient {
                             .boxed(),
                         Err(e) => stream::once(future::ready(Err(e))).boxed(),
                     })
-                    .flatten_stream()
+                    .flatten_stream();
+                throttle(SynthesisType::Deepgram, events)
                     .map(move |res| (cmd_seq, res))
                     .boxed()
             },
@@ -218,25 +222,26 @@ impl SynthesisClient for StreamingClient {
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        // The connection holds a request slot for as long as it is open
+        let (permit, waited_ms) = limiter::acquire(&SynthesisType::Deepgram).await;
         let (sink, source) = connect(self.option.clone()).await?.split();
         self.sink = Some(sink);
-        let stream = source
-            .filter_map(async move |message| match message {
-                Ok(Message::Binary(bytes)) => Some(Ok(SynthesisEvent::AudioChunk(bytes))),
-                Ok(Message::Text(text)) => {
-                    let event: Event =
-                        serde_json::from_str(&text).expect("Deepgram TTS API changed!");
-
-                    if let Event::Warning { description, code } = event {
-                        warn!("Deepgram TTS: warning: {}, {}", description, code);
-                    }
-
-                    None
+        let events = source.filter_map(async move |message| match message {
+            Ok(Message::Binary(bytes)) => Some(Ok(SynthesisEvent::AudioChunk(bytes))),
+            Ok(Message::Text(text)) => {
+                let event: Event = serde_json::from_str(&text).expect("Deepgram TTS API changed!");
+
+                if let Event::Warning { description, code } = event {
+                    warn!("Deepgram TTS: warning: {}, {}", description, code);
                 }
-                Ok(Message::Close(_)) => Some(Ok(SynthesisEvent::Finished)),
-                Err(e) => Some(Err(anyhow!("Deepgram TTS: websocket error: {:?}", e))),
-                _ => None,
-            })
+
+                None
+            }
+            Ok(Message::Close(_)) => Some(Ok(SynthesisEvent::Finished)),
+            Err(e) => Some(Err(anyhow!("Deepgram TTS: websocket error: {:?}", e))),
+            _ => None,
+        });
+        let stream = limiter::hold(permit, waited_ms, events)
             .map(|res| (None, res))
             .boxed();
         Ok(stream)
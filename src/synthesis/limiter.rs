@@ -0,0 +1,121 @@
+use super::{SynthesisEvent, SynthesisType};
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+    time::Instant,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+/// Request slots of one provider, shared across all calls
+struct Limit {
+    size: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+static LIMITS: LazyLock<RwLock<HashMap<String, Limit>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Configure the max number of in-flight requests per provider, keyed by provider
+/// name (e.g. "tencent", "aliyun"). Providers not listed are unlimited.
+///
+/// A provider that already had a limit keeps its slots and is resized, so
+/// requests in flight still count against the new limit.
+pub fn set_concurrency_limits(limits: &HashMap<String, usize>) {
+    let mut current = LIMITS.write().unwrap();
+    current.retain(|provider, _| limits.get(provider).is_some_and(|limit| *limit > 0));
+    for (provider, &size) in limits {
+        if size == 0 {
+            continue;
+        }
+        let Some(limit) = current.get_mut(provider) else {
+            let semaphore = Arc::new(Semaphore::new(size));
+            current.insert(provider.clone(), Limit { size, semaphore });
+            continue;
+        };
+        if size > limit.size {
+            limit.semaphore.add_permits(size - limit.size);
+        } else if size < limit.size {
+            // Slots in use are taken away as their requests finish
+            let excess = limit.size - size;
+            let pending = excess - limit.semaphore.forget_permits(excess);
+            if pending > 0 {
+                let semaphore = limit.semaphore.clone();
+                crate::spawn(async move {
+                    if let Ok(permits) = semaphore.acquire_many_owned(pending as u32).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+        limit.size = size;
+    }
+}
+
+fn semaphore_for(provider: &SynthesisType) -> Option<Arc<Semaphore>> {
+    LIMITS
+        .read()
+        .unwrap()
+        .get(&provider.to_string())
+        .map(|limit| limit.semaphore.clone())
+}
+
+/// Take a request slot of `provider`, waiting for one when all are in use.
+/// Returns the slot, none when the provider is unlimited, and how long it
+/// had to wait.
+pub async fn acquire(provider: &SynthesisType) -> (Option<OwnedSemaphorePermit>, Option<u64>) {
+    let Some(semaphore) = semaphore_for(provider) else {
+        return (None, None);
+    };
+    if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+        return (Some(permit), None);
+    }
+    let start = Instant::now();
+    let permit = semaphore.acquire_owned().await.ok();
+    let waited_ms = start.elapsed().as_millis() as u64;
+    debug!(%provider, waited_ms, "tts request throttled");
+    (permit, Some(waited_ms))
+}
+
+/// Hold `permit` for the whole lifetime of `inner`, emitting a `Throttled`
+/// event first when it had to be waited for
+pub fn hold<S>(
+    permit: Option<OwnedSemaphorePermit>,
+    waited_ms: Option<u64>,
+    inner: S,
+) -> impl Stream<Item = Result<SynthesisEvent>> + Send + 'static
+where
+    S: Stream<Item = Result<SynthesisEvent>> + Send + 'static,
+{
+    async_stream::stream! {
+        let _permit = permit;
+        if let Some(waited_ms) = waited_ms {
+            yield Ok(SynthesisEvent::Throttled { waited_ms });
+        }
+        let mut inner = std::pin::pin!(inner);
+        while let Some(item) = inner.next().await {
+            yield item;
+        }
+    }
+}
+
+/// Hold a request slot of `provider` for the whole lifetime of `inner`.
+/// Requests beyond the limit wait for a free slot instead of failing, and a
+/// `Throttled` event is emitted first when they had to wait.
+pub fn throttle<S>(
+    provider: SynthesisType,
+    inner: S,
+) -> impl Stream<Item = Result<SynthesisEvent>> + Send + 'static
+where
+    S: Stream<Item = Result<SynthesisEvent>> + Send + 'static,
+{
+    async_stream::stream! {
+        let (permit, waited_ms) = acquire(&provider).await;
+        let mut inner = std::pin::pin!(hold(permit, waited_ms, inner));
+        while let Some(item) = inner.next().await {
+            yield item;
+        }
+    }
+}
@@ -7,7 +7,10 @@ use std::collections::HashMap;
 use tokio::sync::mpsc;
 
 mod aliyun;
+pub mod chunk;
 mod deepgram;
+pub mod limiter;
+pub mod normalize;
 mod tencent_cloud;
 mod tencent_cloud_basic;
 
@@ -40,6 +43,8 @@ pub type SynthesisCommandReceiver = mpsc::UnboundedReceiver<SynthesisCommand>;
 pub enum SynthesisType {
     #[serde(rename = "tencent")]
     TencentCloud,
+    #[serde(rename = "tencent_basic")]
+    TencentCloudBasic,
     #[serde(rename = "aliyun")]
     Aliyun,
     #[serde(rename = "deepgram")]
@@ -55,6 +60,7 @@ impl std::fmt::Display for SynthesisType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SynthesisType::TencentCloud => write!(f, "tencent"),
+            SynthesisType::TencentCloudBasic => write!(f, "tencent_basic"),
             SynthesisType::Aliyun => write!(f, "aliyun"),
             SynthesisType::Deepgram => write!(f, "deepgram"),
             #[cfg(feature = "offline")]
@@ -72,6 +78,7 @@ impl<'de> Deserialize<'de> for SynthesisType {
         let value = String::deserialize(deserializer)?;
         match value.as_str() {
             "tencent" => Ok(SynthesisType::TencentCloud),
+            "tencent_basic" => Ok(SynthesisType::TencentCloudBasic),
             "aliyun" => Ok(SynthesisType::Aliyun),
             "deepgram" => Ok(SynthesisType::Deepgram),
             #[cfg(feature = "offline")]
@@ -106,6 +113,12 @@ pub struct SynthesisOption {
     pub extra: Option<HashMap<String, String>>,
     pub max_concurrent_tasks: Option<usize>,
     pub session_id: Option<String>,
+    /// Expand numbers, times, currency and abbreviations into spoken words
+    /// before synthesis, for providers without their own normalization
+    pub normalize_text: Option<bool>,
+    /// Split longer text at sentence boundaries into several requests, to
+    /// stay within the provider's per-request limit
+    pub max_text_length: Option<usize>,
 }
 
 impl SynthesisOption {
@@ -129,6 +142,8 @@ impl SynthesisOption {
                 extra: other.extra.or(self.extra.clone()),
                 max_concurrent_tasks: other.max_concurrent_tasks.or(self.max_concurrent_tasks),
                 session_id: other.session_id.or(self.session_id.clone()),
+                normalize_text: other.normalize_text.or(self.normalize_text),
+                max_text_length: other.max_text_length.or(self.max_text_length),
             }
         } else {
             self.clone()
@@ -143,6 +158,10 @@ pub enum SynthesisEvent {
     /// Progress information including completion status
     Subtitles(Vec<Subtitle>),
     Finished,
+    /// The request waited for a free provider slot before being sent
+    Throttled {
+        waited_ms: u64,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -221,6 +240,8 @@ impl Default for SynthesisOption {
             extra: None,
             max_concurrent_tasks: None,
             session_id: None,
+            normalize_text: None,
+            max_text_length: None,
         }
     }
 }
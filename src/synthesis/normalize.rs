@@ -0,0 +1,378 @@
+//! Text normalization before synthesis: numbers, times, currency and common
+//! abbreviations are expanded into the words a speaker would say, so TTS
+//! engines without their own normalization don't read "$12.50" symbol by symbol.
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+static RE_EN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"(?P<cur>[$€£])(?P<cur_int>\d{1,3}(?:,\d{3})+|\d+)(?:\.(?P<cur_frac>\d{1,2}))?\b",
+        r"|\b(?P<hour>\d{1,2}):(?P<min>[0-5]\d)(?:\s*(?P<ampm>[aApP])(?:\.[mM]\.|[mM]\b))?",
+        r"|(?P<pct>\d+(?:\.\d+)?)%",
+        r"|\b(?P<ord>\d+)(?:st|nd|rd|th)\b",
+        r"|(?P<dec_int>\d+)\.(?P<dec_frac>\d+)",
+        r"|(?P<int>\d{1,3}(?:,\d{3})+|\d+)",
+    ))
+    .unwrap()
+});
+
+static RE_EN_ABBR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(?:Dr|Mr|Mrs|Ms|vs|etc|e\.g|i\.e)\.|\s&\s").unwrap());
+
+static RE_ZH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"[¥￥](?P<cur_int>\d+)(?:\.(?P<cur_frac>\d{1,2}))?",
+        r"|(?P<hour>\d{1,2})[:：](?P<min>[0-5]\d)",
+        r"|(?P<pct>\d+(?:\.\d+)?)[%％]",
+        r"|(?P<year>\d{4})年",
+        r"|(?P<dec_int>\d+)\.(?P<dec_frac>\d+)",
+        r"|(?P<int>\d+)",
+    ))
+    .unwrap()
+});
+
+/// Normalize `text` for the given language ("en", "en-US", "zh", ...).
+/// Languages without rules are returned unchanged.
+pub fn normalize_text(text: &str, language: &str) -> String {
+    let lang = language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match lang.as_str() {
+        "en" => normalize_en(text),
+        "zh" => normalize_zh(text),
+        _ => text.to_string(),
+    }
+}
+
+fn normalize_en(text: &str) -> String {
+    let text = RE_EN_ABBR.replace_all(text, |caps: &Captures| {
+        match caps[0].trim() {
+            "Dr." => "Doctor",
+            "Mr." => "Mister",
+            "Mrs." => "Missus",
+            "Ms." => "Miz",
+            "vs." => "versus",
+            "etc." => "et cetera",
+            "e.g." => "for example",
+            "i.e." => "that is",
+            _ => " and ",
+        }
+        .to_string()
+    });
+    RE_EN
+        .replace_all(&text, |caps: &Captures| {
+            if let Some(symbol) = caps.name("cur") {
+                en_currency(symbol.as_str(), &caps["cur_int"], caps.name("cur_frac"))
+            } else if let Some(hour) = caps.name("hour") {
+                en_time(hour.as_str(), &caps["min"], caps.name("ampm"))
+            } else if let Some(pct) = caps.name("pct") {
+                format!("{} percent", en_number_str(pct.as_str()))
+            } else if let Some(ord) = caps.name("ord") {
+                en_ordinal(parse_int(ord.as_str()))
+            } else if let Some(int) = caps.name("dec_int") {
+                format!(
+                    "{} point {}",
+                    en_integer(int.as_str()),
+                    en_digits(&caps["dec_frac"])
+                )
+            } else {
+                en_integer(&caps["int"])
+            }
+        })
+        .into_owned()
+}
+
+fn en_currency(symbol: &str, int: &str, frac: Option<regex::Match>) -> String {
+    let (major, minor, minor_plural) = match symbol {
+        "€" => ("euro", "cent", "cents"),
+        "£" => ("pound", "penny", "pence"),
+        _ => ("dollar", "cent", "cents"),
+    };
+    let units = parse_int(int);
+    let cents = frac.map(|f| fraction_cents(f.as_str())).unwrap_or(0);
+    let units_words = format!(
+        "{} {}{}",
+        en_number(units),
+        major,
+        if units == 1 { "" } else { "s" }
+    );
+    let cents_words = format!(
+        "{} {}",
+        en_number(cents),
+        if cents == 1 { minor } else { minor_plural }
+    );
+    match (units, cents) {
+        (_, 0) => units_words,
+        (0, _) => cents_words,
+        _ => format!("{} and {}", units_words, cents_words),
+    }
+}
+
+fn en_time(hour: &str, min: &str, ampm: Option<regex::Match>) -> String {
+    let hour = parse_int(hour);
+    let minute = parse_int(min);
+    if hour > 23 {
+        return format!("{} {}", en_number(hour), en_integer(min));
+    }
+    let suffix = ampm.map(|m| {
+        if m.as_str().eq_ignore_ascii_case("a") {
+            " AM"
+        } else {
+            " PM"
+        }
+    });
+    let minutes = match minute {
+        0 if suffix.is_none() => " o'clock".to_string(),
+        0 => String::new(),
+        1..=9 => format!(" oh {}", en_number(minute)),
+        _ => format!(" {}", en_number(minute)),
+    };
+    format!(
+        "{}{}{}",
+        en_number(hour),
+        minutes,
+        suffix.unwrap_or_default()
+    )
+}
+
+/// Integers are read as numbers, but codes with a leading zero and long digit
+/// runs (phone numbers, order ids) are read digit by digit
+fn en_integer(s: &str) -> String {
+    if (s.len() > 1 && s.starts_with('0')) || (!s.contains(',') && s.len() > 6) {
+        en_digits(s)
+    } else {
+        en_number(parse_int(s))
+    }
+}
+
+fn en_number_str(s: &str) -> String {
+    match s.split_once('.') {
+        Some((int, frac)) => format!("{} point {}", en_integer(int), en_digits(frac)),
+        None => en_integer(s),
+    }
+}
+
+fn en_digits(s: &str) -> String {
+    s.chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ONES[d as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+fn en_number(n: u64) -> String {
+    if n < 20 {
+        return ONES[n as usize].to_string();
+    }
+    if n < 100 {
+        return match n % 10 {
+            0 => TENS[(n / 10) as usize].to_string(),
+            r => format!("{}-{}", TENS[(n / 10) as usize], ONES[r as usize]),
+        };
+    }
+    if n < 1000 {
+        return match n % 100 {
+            0 => format!("{} hundred", ONES[(n / 100) as usize]),
+            r => format!("{} hundred {}", ONES[(n / 100) as usize], en_number(r)),
+        };
+    }
+    let (scale, name) = [
+        (1_000_000_000_000, "trillion"),
+        (1_000_000_000, "billion"),
+        (1_000_000, "million"),
+        (1_000, "thousand"),
+    ]
+    .into_iter()
+    .find(|(scale, _)| n >= *scale)
+    .unwrap_or((1_000, "thousand"));
+    match n % scale {
+        0 => format!("{} {}", en_number(n / scale), name),
+        r => format!("{} {} {}", en_number(n / scale), name, en_number(r)),
+    }
+}
+
+fn en_ordinal(n: u64) -> String {
+    let words = en_number(n);
+    let (head, last) = match words.rfind(['-', ' ']) {
+        Some(pos) => words.split_at(pos + 1),
+        None => ("", words.as_str()),
+    };
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        w if w.ends_with('y') => format!("{}ieth", &w[..w.len() - 1]),
+        w => format!("{}th", w),
+    };
+    format!("{}{}", head, last)
+}
+
+fn normalize_zh(text: &str) -> String {
+    RE_ZH
+        .replace_all(text, |caps: &Captures| {
+            if let Some(int) = caps.name("cur_int") {
+                let yuan = parse_int(int.as_str());
+                let cents = caps
+                    .name("cur_frac")
+                    .map(|f| fraction_cents(f.as_str()))
+                    .unwrap_or(0);
+                let mut out = format!("{}元", zh_number(yuan));
+                if cents >= 10 {
+                    out.push_str(&format!("{}角", ZH_DIGITS[(cents / 10) as usize]));
+                }
+                if cents % 10 != 0 {
+                    out.push_str(&format!("{}分", ZH_DIGITS[(cents % 10) as usize]));
+                }
+                out
+            } else if let Some(hour) = caps.name("hour") {
+                let hour = parse_int(hour.as_str());
+                let minute = parse_int(&caps["min"]);
+                let hour = if hour == 2 {
+                    "两".to_string()
+                } else {
+                    zh_number(hour)
+                };
+                match minute {
+                    0 => format!("{}点", hour),
+                    1..=9 => format!("{}点零{}分", hour, zh_number(minute)),
+                    _ => format!("{}点{}分", hour, zh_number(minute)),
+                }
+            } else if let Some(pct) = caps.name("pct") {
+                format!("百分之{}", zh_number_str(pct.as_str()))
+            } else if let Some(year) = caps.name("year") {
+                format!("{}年", zh_digits(year.as_str()))
+            } else if let Some(int) = caps.name("dec_int") {
+                format!(
+                    "{}点{}",
+                    zh_integer(int.as_str()),
+                    zh_digits(&caps["dec_frac"])
+                )
+            } else {
+                zh_integer(&caps["int"])
+            }
+        })
+        .into_owned()
+}
+
+const ZH_DIGITS: [&str; 10] = ["零", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+fn zh_integer(s: &str) -> String {
+    if (s.len() > 1 && s.starts_with('0')) || s.len() > 8 {
+        zh_digits(s)
+    } else {
+        zh_number(parse_int(s))
+    }
+}
+
+fn zh_number_str(s: &str) -> String {
+    match s.split_once('.') {
+        Some((int, frac)) => format!("{}点{}", zh_integer(int), zh_digits(frac)),
+        None => zh_integer(s),
+    }
+}
+
+fn zh_digits(s: &str) -> String {
+    s.chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ZH_DIGITS[d as usize])
+        .collect()
+}
+
+fn zh_number(n: u64) -> String {
+    if n == 0 {
+        return ZH_DIGITS[0].to_string();
+    }
+    const SECTION_UNITS: [&str; 4] = ["", "万", "亿", "万亿"];
+    let mut sections = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        sections.push(rest % 10000);
+        rest /= 10000;
+    }
+    let mut out = String::new();
+    let mut need_zero = false;
+    for (idx, section) in sections.iter().enumerate().rev() {
+        if *section == 0 {
+            need_zero = !out.is_empty();
+            continue;
+        }
+        if need_zero || (!out.is_empty() && *section < 1000) {
+            out.push_str(ZH_DIGITS[0]);
+        }
+        need_zero = false;
+        out.push_str(&zh_section(*section));
+        out.push_str(SECTION_UNITS[idx.min(3)]);
+    }
+    // 10-19 are read as 十, 十一 ... rather than 一十
+    match out.strip_prefix("一十") {
+        Some(rest) => format!("十{}", rest),
+        None => out,
+    }
+}
+
+fn zh_section(n: u64) -> String {
+    const UNITS: [&str; 4] = ["千", "百", "十", ""];
+    let digits = [n / 1000, n / 100 % 10, n / 10 % 10, n % 10];
+    let mut out = String::new();
+    let mut pending_zero = false;
+    for (digit, unit) in digits.iter().zip(UNITS) {
+        if *digit == 0 {
+            pending_zero = !out.is_empty();
+            continue;
+        }
+        if pending_zero {
+            out.push_str(ZH_DIGITS[0]);
+            pending_zero = false;
+        }
+        out.push_str(ZH_DIGITS[*digit as usize]);
+        out.push_str(unit);
+    }
+    out
+}
+
+/// "5" after the decimal point means 50 cents, "05" means 5
+fn fraction_cents(frac: &str) -> u64 {
+    let cents = parse_int(frac);
+    if frac.len() == 1 { cents * 10 } else { cents }
+}
+
+fn parse_int(s: &str) -> u64 {
+    s.chars()
+        .filter(|c| c.is_ascii_digit())
+        .fold(0u64, |acc, c| {
+            acc.saturating_mul(10)
+                .saturating_add(c.to_digit(10).unwrap_or(0) as u64)
+        })
+}
@@ -1,4 +1,7 @@
-use super::{SynthesisClient, SynthesisOption, SynthesisType};
+use super::{
+    SynthesisClient, SynthesisOption, SynthesisType,
+    limiter::{self, throttle},
+};
 use crate::synthesis::{Subtitle, SynthesisEvent};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -286,11 +289,11 @@ impl SynthesisClient for RealTimeClient {
                 let session_id = Uuid::new_v4().to_string();
                 let option = client_option.merge_with(option);
                 let url = construct_request_url(&option, &session_id, Some(&text));
-                stream::once(connect_async(url))
-                    .flat_map(move |res| match res {
-                        Ok((ws_stream, _)) => ws_to_event_stream(ws_stream).boxed(),
-                        Err(e) => stream::once(future::ready(Err(e.into()))).boxed(),
-                    })
+                let events = stream::once(connect_async(url)).flat_map(move |res| match res {
+                    Ok((ws_stream, _)) => ws_to_event_stream(ws_stream).boxed(),
+                    Err(e) => stream::once(future::ready(Err(e.into()))).boxed(),
+                });
+                throttle(SynthesisType::TencentCloud, events)
                     .map(move |x| (cmd_seq, x))
                     .boxed()
             })
@@ -391,10 +394,12 @@ impl SynthesisClient for StreamingClient {
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        // The connection holds a request slot for as long as it is open
+        let (permit, waited_ms) = limiter::acquire(&SynthesisType::TencentCloud).await;
         let stream = self.connect().await?;
         let (ws_sink, ws_stream) = stream.split();
         self.sink = Some(ws_sink);
-        let stream = ws_to_event_stream(ws_stream)
+        let stream = limiter::hold(permit, waited_ms, ws_to_event_stream(ws_stream))
             .map(move |event| (None, event))
             .boxed();
         Ok(stream)
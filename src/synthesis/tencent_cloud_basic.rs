@@ -1,4 +1,4 @@
-use super::{SynthesisClient, SynthesisOption, SynthesisType};
+use super::{SynthesisClient, SynthesisOption, SynthesisType, limiter::throttle};
 use crate::synthesis::{SynthesisEvent, tencent_cloud::TencentSubtitle};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -120,7 +120,7 @@ fn construct_request_url(option: &SynthesisOption, session_id: &str, text: &str)
 #[async_trait]
 impl SynthesisClient for TencentCloudTtsBasicClient {
     fn provider(&self) -> SynthesisType {
-        SynthesisType::Other("tencent_basic".to_string())
+        SynthesisType::TencentCloudBasic
     }
 
     async fn start(
@@ -139,35 +139,38 @@ impl SynthesisClient for TencentCloudTtsBasicClient {
                 let option = client_option.merge_with(option);
                 let url = construct_request_url(&option, &session_id, &text);
                 // request tencent cloud tts
-                let fut = reqwest::get(url).then(async |res| {
-                    let resp = res?.json::<Response>().await?;
-                    if let Some(error) = resp.response.error {
-                        return Err(anyhow::anyhow!(
-                            "Tencent TTS error, code: {}, message: {}",
-                            error.code,
-                            error.message
-                        ));
-                    }
-                    let audio = BASE64_STANDARD.decode(resp.response.audio)?;
-                    Ok((audio, resp.response.subtitles))
-                });
+                let fut =
+                    crate::net_tool::with_request_timeout(crate::net_tool::http_client().get(url))
+                        .send()
+                        .then(async |res| {
+                            let resp = res?.json::<Response>().await?;
+                            if let Some(error) = resp.response.error {
+                                return Err(anyhow::anyhow!(
+                                    "Tencent TTS error, code: {}, message: {}",
+                                    error.code,
+                                    error.message
+                                ));
+                            }
+                            let audio = BASE64_STANDARD.decode(resp.response.audio)?;
+                            Ok((audio, resp.response.subtitles))
+                        });
 
                 // convert result to events
-                stream::once(fut)
-                    .flat_map(|res| match res {
-                        Ok((audio, subtitles)) => {
-                            let mut events = Vec::new();
-                            events.push(Ok(SynthesisEvent::AudioChunk(Bytes::from(audio))));
-                            if !subtitles.is_empty() {
-                                events.push(Ok(SynthesisEvent::Subtitles(
-                                    subtitles.iter().map(Into::into).collect(),
-                                )));
-                            }
-                            events.push(Ok(SynthesisEvent::Finished));
-                            stream::iter(events).boxed()
+                let events = stream::once(fut).flat_map(|res| match res {
+                    Ok((audio, subtitles)) => {
+                        let mut events = Vec::new();
+                        events.push(Ok(SynthesisEvent::AudioChunk(Bytes::from(audio))));
+                        if !subtitles.is_empty() {
+                            events.push(Ok(SynthesisEvent::Subtitles(
+                                subtitles.iter().map(Into::into).collect(),
+                            )));
                         }
-                        Err(e) => stream::once(future::ready(Err(e))).boxed(),
-                    })
+                        events.push(Ok(SynthesisEvent::Finished));
+                        stream::iter(events).boxed()
+                    }
+                    Err(e) => stream::once(future::ready(Err(e))).boxed(),
+                });
+                throttle(SynthesisType::TencentCloudBasic, events)
                     .map(move |x| (seq, x))
                     .boxed()
             })
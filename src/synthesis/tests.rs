@@ -30,6 +30,7 @@ async fn test_tts_basic(client: &mut dyn SynthesisClient) {
                 total_size += audio.len();
             }
             Ok(SynthesisEvent::Subtitles(_)) => {}
+            Ok(SynthesisEvent::Throttled { .. }) => {}
             Ok(SynthesisEvent::Finished) => {
                 finished = true;
             }
@@ -73,6 +74,7 @@ async fn test_multiple_tts_commands_streaming(client: &mut dyn SynthesisClient)
                 finished = true;
             }
             Ok(SynthesisEvent::Subtitles(_)) => {}
+            Ok(SynthesisEvent::Throttled { .. }) => {}
             Err(e) => {
                 tracing::error!("error during tts: {}", e);
                 error_occurred = true;
@@ -117,6 +119,7 @@ async fn test_multiple_tts_commands_non_streaming(client: &mut dyn SynthesisClie
                 finished_count += 1;
             }
             Ok(SynthesisEvent::Subtitles(_)) => {}
+            Ok(SynthesisEvent::Throttled { .. }) => {}
             Err(_) => {
                 error_occurred = true;
             }
@@ -254,3 +257,99 @@ async fn test_deepgram_tts() {
     test_tts_basic(streaming_client.as_mut()).await;
     test_multiple_tts_commands_streaming(streaming_client.as_mut()).await;
 }
+
+#[tokio::test]
+async fn test_tts_concurrency_limit_queues_requests() {
+    use crate::synthesis::{SynthesisType, limiter};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let provider = SynthesisType::Other("limit-test".to_string());
+    limiter::set_concurrency_limits(&HashMap::from([(provider.to_string(), 2)]));
+
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+    let mut tasks = Vec::new();
+    for _ in 0..6 {
+        let in_flight = in_flight.clone();
+        let max_in_flight = max_in_flight.clone();
+        let request = async_stream::stream! {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            yield Ok(SynthesisEvent::Finished);
+        };
+        let stream = limiter::throttle(provider.clone(), request);
+        tasks.push(tokio::spawn(stream.collect::<Vec<_>>()));
+    }
+
+    let mut finished = 0;
+    let mut throttled = 0;
+    for task in tasks {
+        for event in task.await.unwrap() {
+            match event {
+                Ok(SynthesisEvent::Finished) => finished += 1,
+                Ok(SynthesisEvent::Throttled { .. }) => throttled += 1,
+                Ok(_) => {}
+                Err(e) => panic!("request should queue, not fail: {}", e),
+            }
+        }
+    }
+    assert_eq!(finished, 6);
+    assert!(throttled >= 4, "throttled {}", throttled);
+    assert!(max_in_flight.load(Ordering::SeqCst) <= 2);
+
+    // Lowering the limit keeps counting the requests in flight
+    let (first, _) = limiter::acquire(&provider).await;
+    let (second, _) = limiter::acquire(&provider).await;
+    limiter::set_concurrency_limits(&HashMap::from([(provider.to_string(), 1)]));
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let blocked = tokio::time::timeout(Duration::from_millis(50), limiter::acquire(&provider));
+    assert!(blocked.await.is_err(), "one request is still in flight");
+    drop(second);
+    let (third, _) = tokio::time::timeout(Duration::from_millis(50), limiter::acquire(&provider))
+        .await
+        .expect("the slot should be free again");
+    assert!(third.is_some());
+}
+
+#[test]
+fn test_normalize_text() {
+    use crate::synthesis::normalize::normalize_text;
+
+    assert_eq!(
+        normalize_text("$12.50 at 3:00pm", "en"),
+        "twelve dollars and fifty cents at three PM"
+    );
+    assert_eq!(
+        normalize_text(
+            "Dr. Smith arrives on the 21st at 9:05, 45% done, call 0800123",
+            "en-US"
+        ),
+        "Doctor Smith arrives on the twenty-first at nine oh five, forty-five percent done, call zero eight zero zero one two three"
+    );
+    assert_eq!(
+        normalize_text("¥12.50，下午3:05，完成了85%，共10005人，2024年", "zh"),
+        "十二元五角，下午三点零五分，完成了百分之八十五，共一万零五人，二零二四年"
+    );
+    assert_eq!(normalize_text("Il coûte 12€", "fr"), "Il coûte 12€");
+}
+
+#[test]
+fn test_split_text() {
+    use crate::synthesis::chunk::split_text;
+
+    assert_eq!(
+        split_text("First one. Second one! Third?", 25),
+        vec!["First one. Second one!", "Third?"]
+    );
+    assert_eq!(
+        split_text("你好。今天天气很好，我们出去走走吧！", 8),
+        vec!["你好。", "今天天气很好，", "我们出去走走吧！"]
+    );
+    assert_eq!(split_text("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    assert_eq!(split_text("short", 100), vec!["short"]);
+}
@@ -1,4 +1,7 @@
-use super::{TranscriptionClient, TranscriptionOption, handle_wait_for_answer_with_audio_drop};
+use super::{
+    TranscriptionClient, TranscriptionOption, format::final_text,
+    handle_wait_for_answer_with_audio_drop,
+};
 use crate::{
     event::{EventSender, SessionEvent},
     media::{Sample, SourcePacket, TrackId},
@@ -295,6 +298,7 @@ impl AliyunAsrClient {
         }
 
         let track_id_for_recv = ctx.track_id.clone();
+        let option = ctx.option.clone();
         let track_id_for_send = ctx.track_id.clone();
         let aliyun_task_id_for_finish = aliyun_task_id.clone();
 
@@ -347,6 +351,7 @@ impl AliyunAsrClient {
                                 let text = sentence.text;
 
                                 let event = if sentence.sentence_end {
+                                    let (text, raw_text) = final_text(&option, text);
                                     SessionEvent::AsrFinal {
                                         track_id: track_id.clone(),
                                         index: sentence.sentence_id,
@@ -357,11 +362,15 @@ impl AliyunAsrClient {
                                         is_filler: None,
                                         confidence: None,
                                         task_id: None,
+                                        raw_text,
                                     }
                                 } else {
                                     SessionEvent::AsrDelta {
                                         track_id: track_id.clone(),
                                         index: sentence.sentence_id,
+                                        // No stability info from this provider, treat it all as unstable
+                                        unstable_text: Some(text.clone()),
+                                        stable_text: None,
                                         text,
                                         timestamp: crate::media::get_timestamp(),
                                         start_time: Some(sentence_start_time),
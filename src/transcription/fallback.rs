@@ -0,0 +1,104 @@
+use super::{TranscriptionClient, TranscriptionOption};
+use crate::event::{EventSender, SessionEvent};
+use crate::media::engine::FnCreateAsrClient;
+use crate::media::{Sample, SourcePacket, TrackId};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Everything needed to start the secondary recognizer of a track
+pub struct FallbackAsr {
+    pub creator: Arc<FnCreateAsrClient>,
+    pub track_id: TrackId,
+    pub cancel_token: CancellationToken,
+    pub option: TranscriptionOption,
+    pub event_sender: EventSender,
+}
+
+impl FallbackAsr {
+    /// Start the fallback recognizer, announcing the switch with an
+    /// `asr_fallback` metrics event
+    pub async fn start(self, from: &str, reason: &str) -> Result<Box<dyn TranscriptionClient>> {
+        let to = self
+            .option
+            .provider
+            .as_ref()
+            .map(|p| p.to_string())
+            .unwrap_or_default();
+        warn!(
+            track_id = self.track_id,
+            from, to, reason, "switching to fallback asr"
+        );
+        self.event_sender
+            .send(SessionEvent::Metrics {
+                timestamp: crate::media::get_timestamp(),
+                key: "asr_fallback".to_string(),
+                duration: 0,
+                data: json!({
+                    "trackId": self.track_id,
+                    "from": from,
+                    "to": to,
+                    "reason": reason,
+                }),
+            })
+            .ok();
+        (self.creator)(
+            self.track_id,
+            self.cancel_token,
+            self.option,
+            self.event_sender,
+        )
+        .await
+    }
+}
+
+/// Forwards audio to the primary recognizer until it fails, then starts the
+/// fallback one in its place. Audio arriving while the fallback connects is dropped.
+pub struct FallbackAsrClient {
+    provider: String,
+    active: Arc<Mutex<Option<Box<dyn TranscriptionClient>>>>,
+    fallback: Mutex<Option<FallbackAsr>>,
+}
+
+impl FallbackAsrClient {
+    pub fn new(
+        provider: String,
+        primary: Box<dyn TranscriptionClient>,
+        fallback: FallbackAsr,
+    ) -> Self {
+        Self {
+            provider,
+            active: Arc::new(Mutex::new(Some(primary))),
+            fallback: Mutex::new(Some(fallback)),
+        }
+    }
+}
+
+#[async_trait]
+impl TranscriptionClient for FallbackAsrClient {
+    fn send_audio(&self, samples: &[Sample], src_packet: Option<&SourcePacket>) -> Result<()> {
+        let mut active = self.active.lock().unwrap();
+        let Some(client) = active.as_ref() else {
+            return Ok(());
+        };
+        let Err(e) = client.send_audio(samples, src_packet) else {
+            return Ok(());
+        };
+        let Some(fallback) = self.fallback.lock().unwrap().take() else {
+            return Err(e);
+        };
+        *active = None;
+        let active_ref = self.active.clone();
+        let from = self.provider.clone();
+        crate::spawn(async move {
+            match fallback.start(&from, &e.to_string()).await {
+                Ok(client) => *active_ref.lock().unwrap() = Some(client),
+                Err(e) => warn!("failed to start fallback asr: {}", e),
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use super::TranscriptionOption;
+
+/// Opening words that make an unpunctuated sentence a question
+const QUESTION_WORDS: &[&str] = &[
+    "what", "why", "how", "when", "where", "who", "whom", "whose", "which", "can", "could",
+    "would", "should", "will", "shall", "may", "is", "are", "am", "was", "were", "do", "does",
+    "did", "have", "has",
+];
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}')
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Lightweight punctuation for recognizers that return bare lowercase text:
+/// sentence starts and the pronoun "I" are capitalized, and text without a
+/// closing mark gets a full stop, or a question mark when its last sentence
+/// opens with a question word. Chinese and Japanese text gets "。".
+/// Text that is already punctuated is left as it is.
+pub fn format_transcript(text: &str) -> String {
+    let mut words = Vec::new();
+    let mut sentence_start = true;
+    let mut question = false;
+    for word in text.split_whitespace() {
+        let bare = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        let formatted = if sentence_start || bare == "i" || bare.starts_with("i'") {
+            capitalize(word)
+        } else {
+            word.to_string()
+        };
+        if sentence_start && !bare.is_empty() {
+            question = QUESTION_WORDS.contains(&bare.as_str());
+            sentence_start = false;
+        }
+        if word.ends_with(['.', '?', '!', '。', '？', '！']) {
+            sentence_start = true;
+        }
+        words.push(formatted);
+    }
+
+    let mut out = words.join(" ");
+    match out.chars().last() {
+        Some(c) if is_cjk(c) => out.push('。'),
+        Some(c) if c.is_alphanumeric() => out.push(if question { '?' } else { '.' }),
+        _ => {}
+    }
+    out
+}
+
+/// `text` and `raw_text` of an `AsrFinal` from a recognizer with `option`.
+/// With `formatTranscripts` on, the text is formatted and the provider's text
+/// kept in `raw_text`; otherwise the text is passed on as it is.
+pub fn final_text(option: &TranscriptionOption, text: String) -> (String, Option<String>) {
+    if option.format_transcripts.unwrap_or(false) {
+        (format_transcript(&text), Some(text))
+    } else {
+        (text, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_transcript() {
+        assert_eq!(format_transcript("hello there"), "Hello there.");
+        assert_eq!(format_transcript("where is my order"), "Where is my order?");
+        assert_eq!(
+            format_transcript("ok. i think i'm done"),
+            "Ok. I think I'm done."
+        );
+        assert_eq!(format_transcript("Thanks, bye!"), "Thanks, bye!");
+        assert_eq!(format_transcript("  "), "");
+        assert_eq!(format_transcript("你好"), "你好。");
+    }
+
+    #[test]
+    fn test_final_text() {
+        let mut option = TranscriptionOption::default();
+        assert_eq!(
+            final_text(&option, "hello".to_string()),
+            ("hello".to_string(), None)
+        );
+        option.format_transcripts = Some(true);
+        assert_eq!(
+            final_text(&option, "hello".to_string()),
+            ("Hello.".to_string(), Some("hello".to_string()))
+        );
+    }
+}
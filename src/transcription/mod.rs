@@ -11,6 +11,8 @@ use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
 mod aliyun;
+pub mod fallback;
+pub mod format;
 mod tencent_cloud;
 
 #[cfg(feature = "offline")]
@@ -80,6 +82,11 @@ pub struct TranscriptionOption {
     pub endpoint: Option<String>,
     pub extra: Option<HashMap<String, String>>,
     pub start_when_answer: Option<bool>,
+    /// Capitalize and punctuate final transcripts before they reach the
+    /// dialogue and captions; recognizers apply it with [`format::final_text`]
+    pub format_transcripts: Option<bool>,
+    /// Secondary recognizer used when this one fails to start or errors mid-call
+    pub fallback: Option<Box<TranscriptionOption>>,
 }
 
 impl std::fmt::Display for TranscriptionType {
@@ -5,7 +5,7 @@ use crate::media::vad::{TinySilero, VADOption, VadEngine};
 use crate::media::{AudioFrame, INTERNAL_SAMPLERATE, Sample, Samples, TrackId};
 use crate::offline::get_offline_models;
 use crate::offline::sensevoice::{FeaturePipeline, FrontendConfig, language_id_from_code};
-use crate::transcription::{TranscriptionClient, TranscriptionOption};
+use crate::transcription::{TranscriptionClient, TranscriptionOption, format::final_text};
 use anyhow::{Result, anyhow};
 use audio_codec::Resampler;
 use std::{future::Future, pin::Pin, sync::Arc};
@@ -96,6 +96,7 @@ impl SensevoiceAsrClientBuilder {
                 event_sender,
                 input_rate,
                 vad_option,
+                option.clone(),
             ));
 
             let inner = SensevoiceAsrClientInner {
@@ -117,6 +118,7 @@ async fn process_stream(
     event_sender: EventSender,
     input_rate: u32,
     vad_option: VADOption,
+    option: TranscriptionOption,
 ) {
     // Buffer for accumulating audio samples (target rate 16000)
     let mut buffer: Vec<i16> = Vec::with_capacity(16000 * 10);
@@ -240,16 +242,18 @@ async fn process_stream(
                         info!(track_id = %track_id, text = %clean_text, elapsed_ms = %start_time.elapsed().as_millis(),
                              "SenseVoice transcription");
                         // Send event
+                        let (text, raw_text) = final_text(&option, clean_text.to_string());
                         let event = SessionEvent::AsrFinal {
                             track_id: track_id.clone(),
                             index: 0,
-                            text: clean_text.to_string(),
+                            text,
                             timestamp: crate::media::get_timestamp(),
                             start_time: None,
                             end_time: None,
                             is_filler: None,
                             confidence: Some(1.0),
                             task_id: None,
+                            raw_text,
                         };
 
                         if let Err(e) = event_sender.send(event) {
@@ -1,7 +1,8 @@
 use crate::event::{EventSender, SessionEvent};
 use crate::media::{SourcePacket, TrackId};
 use crate::transcription::{
-    TranscriptionClient, TranscriptionOption, handle_wait_for_answer_with_audio_drop,
+    TranscriptionClient, TranscriptionOption, format::final_text,
+    handle_wait_for_answer_with_audio_drop,
 };
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
@@ -42,6 +43,31 @@ pub struct TencentCloudAsrResult {
     pub word_list: Vec<TencentCloudAsrWord>,
 }
 
+impl TencentCloudAsrResult {
+    /// Split the interim text into the prefix made of words flagged stable and the
+    /// remaining unstable tail. Without a word list the whole text is unstable.
+    pub fn stability_split(&self) -> (Option<String>, String) {
+        let text = self.voice_text_str.as_str();
+        if self.word_list.is_empty() {
+            return (None, text.to_string());
+        }
+        let mut stable_end = 0;
+        for word in self.word_list.iter() {
+            if word.stable_flag == 0 {
+                break;
+            }
+            match text[stable_end..].find(word.word.as_str()) {
+                Some(pos) => stable_end += pos + word.word.len(),
+                None => break,
+            }
+        }
+        (
+            Some(text[..stable_end].to_string()),
+            text[stable_end..].to_string(),
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TencentCloudAsrWord {
     pub word: String,
@@ -187,6 +213,7 @@ impl TencentCloudAsrClientBuilder {
 
                 match TencentCloudAsrClient::handle_websocket_message(
                     track_id.clone(),
+                    inner.option.clone(),
                     ws_stream,
                     audio_rx,
                     event_sender.clone(),
@@ -352,6 +379,7 @@ impl TencentCloudAsrClientInner {
 impl TencentCloudAsrClient {
     async fn handle_websocket_message(
         track_id: TrackId,
+        option: TranscriptionOption,
         ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
         mut audio_rx: mpsc::UnboundedReceiver<Vec<u8>>,
         event_sender: EventSender,
@@ -384,21 +412,27 @@ impl TencentCloudAsrClient {
                                 };
                                 response.result.and_then(|result| {
                                     let event = if result.slice_type == 2 {
+                                        let (text, raw_text) =
+                                            final_text(&option, result.voice_text_str);
                                         SessionEvent::AsrFinal {
                                             track_id: track_id.clone(),
                                             index: result.index,
-                                            text: result.voice_text_str,
+                                            text,
                                             timestamp: crate::media::get_timestamp(),
                                             start_time: Some(begin_time + result.start_time as u64),
                                             end_time: Some(begin_time + result.end_time as u64),
                                             is_filler: None,
                                             confidence: None,
                                             task_id: response.task_id.take(),
+                                            raw_text,
                                         }
                                     } else {
+                                        let (stable_text, unstable_text) = result.stability_split();
                                         SessionEvent::AsrDelta {
                                             track_id: track_id.clone(),
                                             index: result.index,
+                                            stable_text,
+                                            unstable_text: Some(unstable_text),
                                             text: result.voice_text_str,
                                             timestamp: crate::media::get_timestamp(),
                                             start_time: Some(begin_time + result.start_time as u64),
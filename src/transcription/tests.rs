@@ -249,3 +249,39 @@ async fn test_aliyun_asr() {
         "Expected some transcription result from Aliyun ASR"
     );
 }
+
+#[test]
+fn test_tencent_delta_stability_split() {
+    use crate::transcription::tencent_cloud::TencentCloudAsrResponse;
+
+    let response: TencentCloudAsrResponse = serde_json::from_str(
+        r#"{
+            "code": 0,
+            "message": "success",
+            "result": {
+                "slice_type": 1,
+                "index": 0,
+                "start_time": 0,
+                "end_time": 1200,
+                "voice_text_str": "你好我想订",
+                "word_size": 3,
+                "word_list": [
+                    {"word": "你好", "start_time": 0, "end_time": 400, "stable_flag": 1},
+                    {"word": "我想", "start_time": 400, "end_time": 800, "stable_flag": 1},
+                    {"word": "订", "start_time": 800, "end_time": 1200, "stable_flag": 0}
+                ]
+            }
+        }"#,
+    )
+    .unwrap();
+    let result = response.result.unwrap();
+    let (stable, unstable) = result.stability_split();
+    assert_eq!(stable.as_deref(), Some("你好我想"));
+    assert_eq!(unstable, "订");
+
+    let mut no_words = result.clone();
+    no_words.word_list.clear();
+    let (stable, unstable) = no_words.stability_split();
+    assert!(stable.is_none());
+    assert_eq!(unstable, "你好我想订");
+}
@@ -2,7 +2,6 @@ use crate::{call::RoutingState, useragent::invitation::InvitationHandler};
 use anyhow::Result;
 use async_trait::async_trait;
 use chrono::Utc;
-use reqwest::Client;
 use rsip::prelude::{HasHeaders, HeadersExt};
 use rsipstack::dialog::server_dialog::ServerInviteDialog;
 use serde_json::json;
@@ -39,7 +38,7 @@ impl InvitationHandler for WebhookInvitationHandler {
         dialog: ServerInviteDialog,
         routing_state: Arc<RoutingState>,
     ) -> Result<()> {
-        let client = Client::new();
+        let client = crate::net_tool::http_client();
         let create_time = Utc::now().to_rfc3339();
 
         let invite_request = dialog.initial_request();
@@ -81,7 +80,10 @@ impl InvitationHandler for WebhookInvitationHandler {
         }
 
         let start_time = Instant::now();
-        match request.json(&payload).send().await {
+        match crate::net_tool::with_request_timeout(request.json(&payload))
+            .send()
+            .await
+        {
             Ok(response) => {
                 info!(
                     dialog_id,
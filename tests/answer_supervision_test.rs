@@ -0,0 +1,235 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::{AnswerSupervision, CallOption};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// How long the trunk stays silent after sending 200 OK
+const MEDIA_DELAY: Duration = Duration::from_millis(800);
+
+#[derive(Default, Clone, Copy)]
+struct TrunkTimes {
+    answered_at: Option<DateTime<Utc>>,
+    media_at: Option<DateTime<Utc>>,
+}
+
+fn headers<'a>(message: &'a str, name: &str) -> Vec<&'a str> {
+    let prefix = format!("{}:", name.to_ascii_lowercase());
+    message
+        .lines()
+        .filter(|l| l.to_ascii_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+fn response(request: &str, contact: &str, body: &str) -> String {
+    let mut out = "SIP/2.0 200 OK\r\n".to_string();
+    for name in ["Via", "From", "Call-ID", "CSeq"] {
+        for line in headers(request, name) {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    for line in headers(request, "To") {
+        out.push_str(line);
+        if !line.contains(";tag=") {
+            out.push_str(";tag=trunk");
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("Contact: <{}>\r\n", contact));
+    if !body.is_empty() {
+        out.push_str("Content-Type: application/sdp\r\n");
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out
+}
+
+/// The RTP address announced in an SDP offer
+fn offer_rtp_addr(sdp: &str) -> Option<SocketAddr> {
+    let ip = sdp
+        .lines()
+        .find_map(|l| l.strip_prefix("c=IN IP4 "))?
+        .trim();
+    let port = sdp
+        .lines()
+        .find_map(|l| l.strip_prefix("m=audio "))?
+        .split_whitespace()
+        .next()?;
+    format!("{}:{}", ip, port).parse().ok()
+}
+
+/// Answers the INVITE at once, then starts sending PCMU RTP after `MEDIA_DELAY`
+async fn run_trunk(socket: UdpSocket, rtp: UdpSocket, times: Arc<Mutex<TrunkTimes>>) {
+    let contact = format!("sip:trunk@{}", socket.local_addr().unwrap());
+    let answer = format!(
+        "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=sendrecv\r\n",
+        rtp.local_addr().unwrap().port()
+    );
+    let rtp = Arc::new(rtp);
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let reply = if message.starts_with("INVITE ") {
+            let offer = message
+                .split_once("\r\n\r\n")
+                .map(|(_, body)| body.to_string())
+                .unwrap_or_default();
+            if let Some(target) = offer_rtp_addr(&offer) {
+                let rtp = rtp.clone();
+                let times = times.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(MEDIA_DELAY).await;
+                    times.lock().unwrap().media_at = Some(Utc::now());
+                    let mut interval = tokio::time::interval(Duration::from_millis(20));
+                    for seq in 0u16..150 {
+                        interval.tick().await;
+                        let mut packet = vec![0x80, 0x00];
+                        packet.extend_from_slice(&seq.to_be_bytes());
+                        packet.extend_from_slice(&(seq as u32 * 160).to_be_bytes());
+                        packet.extend_from_slice(&0x1234_5678u32.to_be_bytes());
+                        packet.extend(std::iter::repeat(0xFFu8).take(160));
+                        if rtp.send_to(&packet, target).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            times.lock().unwrap().answered_at = Some(Utc::now());
+            response(&message, &contact, &answer)
+        } else if message.starts_with("BYE ") {
+            response(&message, &contact, "")
+        } else {
+            continue;
+        };
+        socket.send_to(reply.as_bytes(), peer).await.ok();
+    }
+}
+
+/// Place a call, keep it up past the first media and return the CDR answer
+/// time along with when the trunk answered and started sending media
+async fn call_with_supervision(
+    supervision: AnswerSupervision,
+) -> Result<(DateTime<Utc>, TrunkTimes)> {
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    let (cdr_tx, mut cdr_rx) = mpsc::unbounded_channel();
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_callrecord_sender(cdr_tx)
+        .build()
+        .await?;
+
+    let trunk = UdpSocket::bind("127.0.0.1:0").await?;
+    let rtp = UdpSocket::bind("127.0.0.1:0").await?;
+    let target = format!("sip:bob@{}", trunk.local_addr()?);
+    let times = Arc::new(Mutex::new(TrunkTimes::default()));
+    tokio::spawn(run_trunk(trunk, rtp, times.clone()));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-answer-supervision".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                callee: Some(target),
+                answer_supervision: Some(supervision),
+                ..Default::default()
+            },
+        })?;
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                if let SessionEvent::Answer { .. } = event {
+                    break;
+                }
+            }
+        })
+        .await?;
+        tokio::time::sleep(MEDIA_DELAY + Duration::from_millis(700)).await;
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+        let record = tokio::time::timeout(Duration::from_secs(5), cdr_rx.recv())
+            .await?
+            .expect("cdr should be sent");
+        Ok::<_, anyhow::Error>(record)
+    };
+
+    let record = tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    };
+    let answer_time = record.answer_time.expect("call should be answered");
+    let times = *times.lock().unwrap();
+    Ok((answer_time, times))
+}
+
+/// `signaling` supervision answers on 200 OK, before any media flows
+#[tokio::test]
+async fn test_signaling_supervision_answers_on_200_ok() -> Result<()> {
+    let (answer_time, times) = call_with_supervision(AnswerSupervision::Signaling).await?;
+    let answered_at = times.answered_at.expect("trunk should answer");
+    let media_at = times.media_at.expect("trunk should send media");
+
+    assert!(
+        (answer_time - answered_at).num_milliseconds().abs() < 300,
+        "answer_time {} should match 200 OK at {}",
+        answer_time,
+        answered_at
+    );
+    assert!(answer_time < media_at);
+    Ok(())
+}
+
+/// `media` supervision answers on the first RTP packet, not on 200 OK
+#[tokio::test]
+async fn test_media_supervision_answers_on_first_rtp() -> Result<()> {
+    let (answer_time, times) = call_with_supervision(AnswerSupervision::Media).await?;
+    let answered_at = times.answered_at.expect("trunk should answer");
+    let media_at = times.media_at.expect("trunk should send media");
+
+    assert!(
+        answer_time >= media_at,
+        "answer_time {} should not precede the first RTP at {}",
+        answer_time,
+        media_at
+    );
+    assert!(
+        (answer_time - media_at).num_milliseconds() < 300,
+        "answer_time {} should match the first RTP at {}",
+        answer_time,
+        media_at
+    );
+    assert!((answer_time - answered_at).num_milliseconds() >= 500);
+    Ok(())
+}
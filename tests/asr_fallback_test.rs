@@ -0,0 +1,214 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::{EventSender, SessionEvent};
+use active_call::media::engine::StreamEngine;
+use active_call::media::{Sample, SourcePacket, TrackId};
+use active_call::transcription::{TranscriptionClient, TranscriptionOption, TranscriptionType};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Emits an `AsrFinal` carrying its name every 5 chunks, and starts failing
+/// after `fail_after` chunks
+struct MockAsrClient {
+    name: &'static str,
+    fail_after: Option<usize>,
+    chunks: AtomicUsize,
+    track_id: TrackId,
+    event_sender: EventSender,
+}
+
+#[async_trait]
+impl TranscriptionClient for MockAsrClient {
+    fn send_audio(&self, _samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        let count = self.chunks.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.fail_after.is_some_and(|n| count > n) {
+            return Err(anyhow!("{} connection lost", self.name));
+        }
+        if count % 5 == 0 {
+            let now = active_call::media::get_timestamp();
+            self.event_sender
+                .send(SessionEvent::AsrFinal {
+                    track_id: self.track_id.clone(),
+                    index: count as u32,
+                    text: self.name.to_string(),
+                    timestamp: now,
+                    start_time: Some(now),
+                    end_time: Some(now),
+                    is_filler: None,
+                    confidence: None,
+                    task_id: None,
+                    raw_text: None,
+                })
+                .ok();
+        }
+        Ok(())
+    }
+}
+
+fn register_mock(engine: &mut StreamEngine, name: &'static str, fail_after: Option<usize>) {
+    engine.register_asr(
+        TranscriptionType::Other(name.to_string()),
+        Box::new(
+            move |track_id: TrackId,
+                  _token: CancellationToken,
+                  _option: TranscriptionOption,
+                  event_sender: EventSender| {
+                Box::pin(async move {
+                    Ok(Box::new(MockAsrClient {
+                        name,
+                        fail_after,
+                        chunks: AtomicUsize::new(0),
+                        track_id,
+                        event_sender,
+                    }) as Box<dyn TranscriptionClient>)
+                })
+            },
+        ),
+    );
+}
+
+fn register_broken(engine: &mut StreamEngine, name: &'static str) {
+    engine.register_asr(
+        TranscriptionType::Other(name.to_string()),
+        Box::new(
+            |_track_id: TrackId,
+             _token: CancellationToken,
+             _option: TranscriptionOption,
+             _event_sender: EventSender| {
+                Box::pin(async move {
+                    Err::<Box<dyn TranscriptionClient>, _>(anyhow!("missing credentials"))
+                })
+            },
+        ),
+    );
+}
+
+/// Run a call transcribed by `primary` with `backup` as fallback, returning the
+/// `asr_fallback` metrics data and the texts of the finals received
+async fn run_call(engine: StreamEngine, primary: &str) -> Result<(serde_json::Value, Vec<String>)> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(engine))
+        .build()
+        .await?;
+
+    let cancel_token = CancellationToken::new();
+    let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        format!("test-asr-fallback-{}", primary),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            asr: Some(TranscriptionOption {
+                provider: Some(TranscriptionType::Other(primary.to_string())),
+                fallback: Some(Box::new(TranscriptionOption {
+                    provider: Some(TranscriptionType::Other("backup".to_string())),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    })?;
+
+    // 20ms of 16k PCM per chunk
+    let pump_token = cancel_token.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(20));
+        while !pump_token.is_cancelled() {
+            interval.tick().await;
+            if audio_tx.send(Bytes::from(vec![0u8; 640])).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut switch = None;
+    let mut finals = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                SessionEvent::Metrics { key, data, .. } if key == "asr_fallback" => {
+                    switch = Some(data);
+                }
+                SessionEvent::AsrFinal { text, .. } => {
+                    let done = text == "backup";
+                    finals.push(text);
+                    if done {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    })
+    .await?;
+
+    cancel_token.cancel();
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    Ok((switch.expect("switch should be announced"), finals))
+}
+
+/// A primary recognizer that can't start is replaced by the fallback at once
+#[tokio::test]
+async fn test_fallback_asr_when_primary_fails_to_start() -> Result<()> {
+    let mut engine = StreamEngine::new();
+    register_broken(&mut engine, "broken");
+    register_mock(&mut engine, "backup", None);
+
+    let (switch, finals) = run_call(engine, "broken").await?;
+    assert_eq!(switch["from"], "broken");
+    assert_eq!(switch["to"], "backup");
+    assert!(
+        switch["reason"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("missing credentials")
+    );
+    assert_eq!(finals, vec!["backup".to_string()]);
+    Ok(())
+}
+
+/// A primary recognizer failing mid-call hands over to the fallback without
+/// dropping the call
+#[tokio::test]
+async fn test_fallback_asr_when_primary_fails_mid_call() -> Result<()> {
+    let mut engine = StreamEngine::new();
+    register_mock(&mut engine, "flaky", Some(12));
+    register_mock(&mut engine, "backup", None);
+
+    let (switch, finals) = run_call(engine, "flaky").await?;
+    assert_eq!(switch["from"], "flaky");
+    assert!(
+        switch["reason"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("connection lost")
+    );
+    assert_eq!(finals.first().map(String::as_str), Some("flaky"));
+    assert_eq!(finals.last().map(String::as_str), Some("backup"));
+    Ok(())
+}
@@ -28,6 +28,7 @@ async fn test_refer_option_pause_parent_asr_field() {
         asr: None,
         sip: None,
         call_id: None,
+        diversion: None,
     };
 
     assert_eq!(refer_option.pause_parent_asr, Some(true));
@@ -41,6 +42,7 @@ async fn test_refer_option_pause_parent_asr_field() {
         asr: None,
         sip: None,
         call_id: None,
+        diversion: None,
     };
 
     assert_eq!(refer_option_false.pause_parent_asr, Some(false));
@@ -55,6 +57,7 @@ async fn test_refer_option_pause_parent_asr_field() {
         asr: None,
         sip: None,
         call_id: None,
+        diversion: None,
     };
     assert_eq!(none_refer.pause_parent_asr, None);
 }
@@ -131,6 +134,7 @@ async fn test_refer_option_serialization() -> Result<()> {
         asr: None,
         sip: None,
         call_id: None,
+        diversion: None,
     };
 
     let json = serde_json::to_string(&refer_option)?;
@@ -0,0 +1,181 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::{EventSender, SessionEvent};
+use active_call::media::engine::StreamEngine;
+use active_call::media::{Sample, SourcePacket, TrackId};
+use active_call::transcription::{TranscriptionClient, TranscriptionOption, TranscriptionType};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Emits an `AsrFinal` carrying its name every 5 chunks, after `warmup` chunks
+struct MockAsrClient {
+    name: &'static str,
+    warmup: usize,
+    chunks: AtomicUsize,
+    track_id: TrackId,
+    event_sender: EventSender,
+}
+
+#[async_trait]
+impl TranscriptionClient for MockAsrClient {
+    fn send_audio(&self, _samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        let count = self.chunks.fetch_add(1, Ordering::Relaxed) + 1;
+        if count >= self.warmup && (count - self.warmup) % 5 == 0 {
+            let now = active_call::media::get_timestamp();
+            self.event_sender
+                .send(SessionEvent::AsrFinal {
+                    track_id: self.track_id.clone(),
+                    index: count as u32,
+                    text: self.name.to_string(),
+                    timestamp: now,
+                    start_time: Some(now),
+                    end_time: Some(now),
+                    is_filler: None,
+                    confidence: None,
+                    task_id: None,
+                    raw_text: None,
+                })
+                .ok();
+        }
+        Ok(())
+    }
+}
+
+fn register_mock(engine: &mut StreamEngine, name: &'static str, warmup: usize) {
+    engine.register_asr(
+        TranscriptionType::Other(name.to_string()),
+        Box::new(
+            move |track_id: TrackId,
+                  _token: CancellationToken,
+                  _option: TranscriptionOption,
+                  event_sender: EventSender| {
+                Box::pin(async move {
+                    Ok(Box::new(MockAsrClient {
+                        name,
+                        warmup,
+                        chunks: AtomicUsize::new(0),
+                        track_id,
+                        event_sender,
+                    }) as Box<dyn TranscriptionClient>)
+                })
+            },
+        ),
+    );
+}
+
+/// Swapping recognizers hands the audio over at once: the old recognizer stops
+/// transcribing, so the two never both produce results for the same speech.
+#[tokio::test]
+async fn test_swap_asr_hands_audio_over() -> Result<()> {
+    let mut engine = StreamEngine::new();
+    register_mock(&mut engine, "old", 5);
+    register_mock(&mut engine, "new", 15);
+
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(engine))
+        .build()
+        .await?;
+
+    let cancel_token = CancellationToken::new();
+    let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        "test-asr-swap".to_string(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            asr: Some(TranscriptionOption {
+                provider: Some(TranscriptionType::Other("old".to_string())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    })?;
+
+    // 20ms of 16k PCM per chunk
+    let pump_token = cancel_token.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(20));
+        while !pump_token.is_cancelled() {
+            interval.tick().await;
+            if audio_tx.send(Bytes::from(vec![0u8; 640])).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut finals: Vec<String> = Vec::new();
+    let mut swapped_at = None;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(event) = event_rx.recv().await {
+            if let SessionEvent::AsrFinal { text, .. } = event {
+                finals.push(text);
+                if swapped_at.is_none() {
+                    swapped_at = Some(finals.len());
+                    command_tx
+                        .send(Command::SwapAsr {
+                            option: TranscriptionOption {
+                                provider: Some(TranscriptionType::Other("new".to_string())),
+                                ..Default::default()
+                            },
+                            overlap_timeout: None,
+                        })
+                        .ok();
+                }
+                if finals.iter().filter(|text| *text == "new").count() >= 3 {
+                    break;
+                }
+            }
+        }
+    })
+    .await?;
+
+    command_tx.send(Command::Hangup {
+        reason: None,
+        initiator: None,
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+    // One old result may already be on its way when the swap lands
+    let swapped_at = swapped_at.unwrap();
+    let old_after_swap = finals[swapped_at..]
+        .iter()
+        .filter(|text| *text == "old")
+        .count();
+    assert!(
+        old_after_swap <= 1,
+        "old asr should stop transcribing at the swap: {:?}",
+        finals
+    );
+    let first_new = finals.iter().position(|text| text == "new").unwrap();
+    assert!(
+        finals[first_new..].iter().all(|text| text == "new"),
+        "old and new asr results interleaved: {:?}",
+        finals
+    );
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::ActiveCallType;
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::media::audio_queue::audio_queue;
+use anyhow::Result;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// A full queue drops its oldest frames and keeps the newest
+#[tokio::test]
+async fn test_audio_queue_drops_oldest() -> Result<()> {
+    let (sender, mut receiver) = audio_queue(3);
+    for n in 0u8..5 {
+        sender.push(Bytes::from(vec![n]));
+    }
+    assert_eq!(receiver.len(), 3);
+    assert_eq!(sender.dropped(), 2);
+    drop(sender);
+
+    let mut frames = Vec::new();
+    while let Some(frame) = receiver.recv().await {
+        frames.push(frame[0]);
+    }
+    assert_eq!(frames, vec![2, 3, 4]);
+    Ok(())
+}
+
+/// Audio flooding a call before its track exists stays bounded, and the
+/// dropped frames are reported
+#[tokio::test]
+async fn test_flooded_call_audio_is_bounded() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.max_audio_buffer_frames = Some(100);
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let cancel_token = CancellationToken::new();
+    let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (_command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        "test-audio-flood".to_string(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    // No invite yet, so nothing consumes the audio
+    for _ in 0..1000 {
+        audio_tx.send(Bytes::from(vec![0u8; 640]))?;
+    }
+
+    let mut total = 0;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                SessionEvent::Metrics { key, data, .. } if key == "audio_dropped" => {
+                    assert_eq!(data["capacity"], 100);
+                    total = data["total"].as_u64().unwrap_or_default();
+                    if total >= 900 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    })
+    .await?;
+    assert_eq!(total, 900);
+
+    cancel_token.cancel();
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    Ok(())
+}
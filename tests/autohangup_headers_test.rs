@@ -96,7 +96,7 @@ async fn test_autohangup_headers_stored_in_extras() -> Result<()> {
         Some(sip_option),
     );
 
-    handler.set_call(active_call.clone());
+    handler.set_call(active_call.clone()).await;
 
     // Act: Simulate user speech that triggers LLM response with <hangup/>
     let event = SessionEvent::AsrFinal {
@@ -109,6 +109,7 @@ async fn test_autohangup_headers_stored_in_extras() -> Result<()> {
         confidence: None,
         task_id: None,
         timestamp: 0,
+        raw_text: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -188,7 +189,7 @@ async fn test_autohangup_without_sip_config() -> Result<()> {
         None, // No SipOption
     );
 
-    handler.set_call(active_call.clone());
+    handler.set_call(active_call.clone()).await;
 
     // Act
     let event = SessionEvent::AsrFinal {
@@ -201,6 +202,7 @@ async fn test_autohangup_without_sip_config() -> Result<()> {
         confidence: None,
         task_id: None,
         timestamp: 0,
+        raw_text: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -291,7 +293,7 @@ async fn test_autohangup_headers_with_template_variables() -> Result<()> {
         Some(sip_option),
     );
 
-    handler.set_call(active_call.clone());
+    handler.set_call(active_call.clone()).await;
 
     // Act
     let event = SessionEvent::AsrFinal {
@@ -304,6 +306,7 @@ async fn test_autohangup_headers_with_template_variables() -> Result<()> {
         confidence: None,
         task_id: None,
         timestamp: 0,
+        raw_text: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -0,0 +1,161 @@
+use active_call::app::{AppState, AppStateBuilder};
+use active_call::config::{BargeConfig, Config};
+use active_call::handler::call_router;
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+
+async fn start_server(barge: Option<BargeConfig>) -> Result<(AppState, String)> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.barge = barge;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let router = call_router().with_state(app_state.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.ok();
+    });
+    let app_state_clone = app_state.clone();
+    tokio::spawn(async move {
+        app_state_clone.serve().await.ok();
+    });
+    Ok((app_state, format!("ws://{}", addr)))
+}
+
+async fn wait_for_call(app_state: &AppState, session_id: &str) -> Result<()> {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !app_state
+            .active_calls
+            .lock()
+            .unwrap()
+            .contains_key(session_id)
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+async fn barge_status(base_url: &str, query: &str) -> u16 {
+    match connect_async(format!("{}/call?{}", base_url, query)).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response.status().as_u16(),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => 101,
+    }
+}
+
+/// The caller hears a barging supervisor, and the call record notes the barge
+/// once they left
+#[tokio::test]
+async fn test_barge_supervisor_heard_by_caller() -> Result<()> {
+    let (app_state, base_url) = start_server(Some(BargeConfig {
+        token: Some("secret".to_string()),
+    }))
+    .await?;
+
+    let (call_ws, _) = connect_async(format!("{}/call?id=barged&dump=false", base_url)).await?;
+    let (mut call_tx, mut call_rx) = call_ws.split();
+    call_tx
+        .send(Message::Text(
+            serde_json::json!({"command": "invite", "option": {}})
+                .to_string()
+                .into(),
+        ))
+        .await?;
+    wait_for_call(&app_state, "barged").await?;
+
+    assert_eq!(
+        barge_status(&base_url, "barge=barged&token=wrong").await,
+        401
+    );
+    assert_eq!(
+        barge_status(&base_url, "barge=unknown&token=secret").await,
+        404
+    );
+
+    let (supervisor, _) = connect_async(format!(
+        "{}/call?barge=barged&token=secret&supervisor=alice",
+        base_url
+    ))
+    .await?;
+    let (mut supervisor_tx, _supervisor_rx) = supervisor.split();
+
+    // 20ms of 16k PCM at a constant level
+    let frame: Vec<u8> = std::iter::repeat(3000i16.to_le_bytes())
+        .take(320)
+        .flatten()
+        .collect();
+    let pump = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(20));
+        for _ in 0..25 {
+            interval.tick().await;
+            if supervisor_tx
+                .send(Message::Binary(frame.clone().into()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        supervisor_tx.close().await.ok();
+    });
+
+    let heard = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = call_rx.next().await {
+            if let Message::Binary(data) = message {
+                if data.iter().any(|b| *b != 0) {
+                    return true;
+                }
+            }
+        }
+        false
+    })
+    .await?;
+    assert!(heard, "caller should hear the supervisor");
+
+    pump.await?;
+    let active_call = app_state
+        .active_calls
+        .lock()
+        .unwrap()
+        .get("barged")
+        .cloned()
+        .expect("call should survive the supervisor leaving");
+    let left = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let barges = active_call
+                .call_state
+                .read()
+                .await
+                .extras
+                .as_ref()
+                .and_then(|extras| extras.get("barges").cloned());
+            if let Some(barges) = barges {
+                if barges[0].get("leftAt").is_some() {
+                    return barges;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    assert_eq!(left.as_array().map(|b| b.len()), Some(1));
+    assert_eq!(left[0]["supervisor"], "alice");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_barge_refused_when_disabled() -> Result<()> {
+    let (_app_state, base_url) = start_server(None).await?;
+    assert_eq!(barge_status(&base_url, "barge=any").await, 403);
+
+    // A barge section without a token is refused too
+    let (_app_state, base_url) = start_server(Some(BargeConfig { token: None })).await?;
+    assert_eq!(barge_status(&base_url, "barge=any").await, 403);
+    Ok(())
+}
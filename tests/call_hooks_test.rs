@@ -0,0 +1,120 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::{CallRecordConfig, Config};
+use active_call::media::engine::StreamEngine;
+use active_call::{CallOption, callrecord::CallRecordHangupReason};
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+async fn wait_for(calls: &Arc<Mutex<Vec<String>>>, name: &str) -> bool {
+    for _ in 0..200 {
+        if calls.lock().unwrap().iter().any(|c| c == name) {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_lifecycle_hooks_invoked_in_order() -> Result<()> {
+    let record_dir = tempfile::tempdir()?;
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.callrecord = Some(CallRecordConfig::Local {
+        root: record_dir.path().to_string_lossy().to_string(),
+        compress: None,
+    });
+
+    let calls = Arc::new(Mutex::new(Vec::<String>::new()));
+    let hangup_reason = Arc::new(Mutex::new(None));
+    let (c1, c2, c3, c4) = (calls.clone(), calls.clone(), calls.clone(), calls.clone());
+    let reason_ref = hangup_reason.clone();
+
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .on_call_created(move |ctx| {
+            let calls = c1.clone();
+            async move {
+                assert_eq!(ctx.session_id, "test-hooks");
+                calls.lock().unwrap().push("created".to_string());
+            }
+        })
+        .on_answered(move |_| {
+            let calls = c2.clone();
+            async move {
+                calls.lock().unwrap().push("answered".to_string());
+            }
+        })
+        .on_hangup(move |ctx| {
+            let calls = c3.clone();
+            let reason_ref = reason_ref.clone();
+            async move {
+                *reason_ref.lock().unwrap() = ctx.hangup_reason;
+                calls.lock().unwrap().push("hangup".to_string());
+            }
+        })
+        .on_cdr_saved(move |record| {
+            let calls = c4.clone();
+            async move {
+                assert_eq!(record.call_id, "test-hooks");
+                calls.lock().unwrap().push("cdr_saved".to_string());
+            }
+        })
+        .build()
+        .await?;
+
+    let session_id = "test-hooks".to_string();
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, _event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        session_id.clone(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    assert!(wait_for(&calls, "created").await);
+    assert!(!calls.lock().unwrap().contains(&"answered".to_string()));
+
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            codec: Some("pcmu".to_string()),
+            ..Default::default()
+        },
+    })?;
+    assert!(wait_for(&calls, "answered").await);
+    assert!(!calls.lock().unwrap().contains(&"hangup".to_string()));
+
+    command_tx.send(Command::Hangup {
+        reason: Some("by_client".to_string()),
+        initiator: Some("caller".to_string()),
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+    assert!(wait_for(&calls, "hangup").await);
+    assert!(wait_for(&calls, "cdr_saved").await);
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec!["created", "answered", "hangup", "cdr_saved"]
+    );
+    assert_eq!(
+        *hangup_reason.lock().unwrap(),
+        Some(CallRecordHangupReason::ByCaller)
+    );
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use active_call::app::{AppState, AppStateBuilder};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::handler::call_router;
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+
+const MONITOR_TOKEN: &str = "monitor-secret";
+
+async fn start_server(enable_monitor: bool) -> Result<(AppState, String)> {
+    start_server_with_token(enable_monitor, Some(MONITOR_TOKEN)).await
+}
+
+async fn start_server_with_token(
+    enable_monitor: bool,
+    monitor_token: Option<&str>,
+) -> Result<(AppState, String)> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.enable_monitor = Some(enable_monitor);
+    config.monitor_token = monitor_token.map(str::to_string);
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let router = call_router().with_state(app_state.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.ok();
+    });
+    let app_state_clone = app_state.clone();
+    tokio::spawn(async move {
+        app_state_clone.serve().await.ok();
+    });
+    Ok((app_state, format!("ws://{}", addr)))
+}
+
+async fn wait_for_call(app_state: &AppState, session_id: &str) -> Result<()> {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !app_state
+            .active_calls
+            .lock()
+            .unwrap()
+            .contains_key(session_id)
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// A monitor hears the caller's audio, while its commands are ignored and
+/// leaving doesn't end the call.
+#[tokio::test]
+async fn test_monitor_receives_audio_without_control() -> Result<()> {
+    let (app_state, base_url) = start_server(true).await?;
+
+    let (call_ws, _) = connect_async(format!("{}/call?id=monitored&dump=false", base_url)).await?;
+    let (mut call_tx, mut call_rx) = call_ws.split();
+    call_tx
+        .send(Message::Text(
+            serde_json::json!({"command": "invite", "option": {}})
+                .to_string()
+                .into(),
+        ))
+        .await?;
+    wait_for_call(&app_state, "monitored").await?;
+
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(Ok(message)) = call_rx.next().await {
+            if let Message::Text(text) = message {
+                if let Ok(event) = serde_json::from_str::<SessionEvent>(text.as_str()) {
+                    event_tx.send(event).ok();
+                }
+            }
+        }
+    });
+
+    // 20ms of 16k PCM at a constant level
+    let frame: Vec<u8> = std::iter::repeat(3000i16.to_le_bytes())
+        .take(320)
+        .flatten()
+        .collect();
+    let pump = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(20));
+        loop {
+            interval.tick().await;
+            if call_tx
+                .send(Message::Binary(frame.clone().into()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let (mut monitor, _) = connect_async(format!(
+        "{}/call?monitor=monitored&token={}",
+        base_url, MONITOR_TOKEN
+    ))
+    .await?;
+    let heard = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = monitor.next().await {
+            if let Message::Binary(data) = message {
+                assert_eq!(data.len(), 640);
+                if data
+                    .chunks_exact(2)
+                    .any(|s| i16::from_le_bytes([s[0], s[1]]) != 0)
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    })
+    .await?;
+    assert!(heard, "monitor should receive the call audio");
+
+    monitor
+        .send(Message::Text(
+            serde_json::json!({"command": "hangup"}).to_string().into(),
+        ))
+        .await?;
+    monitor.close(None).await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert!(
+        app_state
+            .active_calls
+            .lock()
+            .unwrap()
+            .contains_key("monitored"),
+        "call should survive the monitor"
+    );
+    while let Ok(event) = event_rx.try_recv() {
+        assert!(
+            !matches!(event, SessionEvent::Hangup { .. }),
+            "monitor must not be able to hang up the call"
+        );
+    }
+    assert!(!pump.is_finished());
+
+    pump.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_monitor_refused_when_disabled() -> Result<()> {
+    let (_app_state, base_url) = start_server(false).await?;
+    let result = connect_async(format!("{}/call?monitor=any", base_url)).await;
+    match result {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), 403);
+        }
+        other => panic!("expected 403, got {:?}", other.map(|_| ())),
+    }
+    Ok(())
+}
+
+async fn monitor_status(base_url: &str, query: &str) -> u16 {
+    match connect_async(format!("{}/call?monitor=any{}", base_url, query)).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response.status().as_u16(),
+        other => panic!("expected a refusal, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn test_monitor_requires_token() -> Result<()> {
+    let (_app_state, base_url) = start_server(true).await?;
+    assert_eq!(monitor_status(&base_url, "").await, 401);
+    assert_eq!(monitor_status(&base_url, "&token=wrong").await, 401);
+
+    // Enabled without a token is still refused
+    let (_app_state, base_url) = start_server_with_token(true, None).await?;
+    assert_eq!(
+        monitor_status(&base_url, &format!("&token={}", MONITOR_TOKEN)).await,
+        403
+    );
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use active_call::app::{AppState, AppStateBuilder};
+use active_call::config::{Config, InviteHandlerConfig};
+use anyhow::Result;
+use axum::{Router, routing::post};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Start a webhook server recording when each inbound call reaches it
+async fn start_webhook(hits: Arc<Mutex<Vec<Instant>>>) -> Result<String> {
+    let app = Router::new().route(
+        "/webhook",
+        post(move || async move {
+            hits.lock().unwrap().push(Instant::now());
+            "OK"
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/webhook", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    Ok(url)
+}
+
+async fn start_app(config_fn: impl FnOnce(&mut Config), webhook_url: String) -> Result<AppState> {
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    config.handler = Some(InviteHandlerConfig::Webhook {
+        url: Some(webhook_url),
+        urls: None,
+        method: Some("POST".to_string()),
+        headers: None,
+    });
+    config_fn(&mut config);
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+    let app_state_clone = app_state.clone();
+    tokio::spawn(async move {
+        app_state_clone.serve().await.ok();
+    });
+    Ok(app_state)
+}
+
+fn invite(target: SocketAddr, local: SocketAddr, n: usize) -> String {
+    let sdp = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 40000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=sendrecv\r\n";
+    format!(
+        "INVITE sip:bot@{target} SIP/2.0\r\n\
+         Via: SIP/2.0/UDP {local};branch=z9hG4bK-burst-{n}\r\n\
+         Max-Forwards: 70\r\n\
+         From: <sip:caller{n}@{local}>;tag=caller-{n}\r\n\
+         To: <sip:bot@{target}>\r\n\
+         Call-ID: burst-{n}@test\r\n\
+         CSeq: 1 INVITE\r\n\
+         Contact: <sip:caller{n}@{local}>\r\n\
+         Content-Type: application/sdp\r\n\
+         Content-Length: {}\r\n\r\n{sdp}",
+        sdp.len()
+    )
+}
+
+/// Send a burst of INVITEs, returning the socket that receives the responses
+async fn send_burst(app_state: &AppState, count: usize) -> Result<UdpSocket> {
+    let target: SocketAddr = app_state
+        .endpoint
+        .get_addrs()
+        .first()
+        .expect("sip listener")
+        .addr
+        .to_string()
+        .parse()?;
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let local = socket.local_addr()?;
+    for n in 0..count {
+        socket
+            .send_to(invite(target, local, n).as_bytes(), target)
+            .await?;
+    }
+    Ok(socket)
+}
+
+/// A burst of INVITEs is admitted at the configured rate, not all at once
+#[tokio::test]
+async fn test_invite_burst_admitted_at_configured_rate() -> Result<()> {
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    let webhook_url = start_webhook(hits.clone()).await?;
+    let app_state = start_app(
+        |config| {
+            config.max_calls_per_sec = Some(5.0);
+            config.call_burst = Some(1);
+            config.call_admission_timeout = Some("10s".to_string());
+        },
+        webhook_url,
+    )
+    .await?;
+
+    let _socket = send_burst(&app_state, 5).await?;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while hits.lock().unwrap().len() < 5 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+
+    let mut hits = hits.lock().unwrap().clone();
+    hits.sort();
+    for pair in hits.windows(2) {
+        let gap = pair[1] - pair[0];
+        assert!(
+            gap >= Duration::from_millis(150),
+            "calls admitted {:?} apart, expected about 200ms",
+            gap
+        );
+    }
+    assert!(hits[4] - hits[0] >= Duration::from_millis(750));
+    assert_eq!(
+        app_state
+            .total_throttled_calls
+            .load(std::sync::atomic::Ordering::Relaxed),
+        4
+    );
+    Ok(())
+}
+
+/// Calls that would wait longer than `call_admission_timeout` are refused with 503
+#[tokio::test]
+async fn test_invite_refused_past_admission_timeout() -> Result<()> {
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    let webhook_url = start_webhook(hits.clone()).await?;
+    let app_state = start_app(
+        |config| {
+            config.max_calls_per_sec = Some(1.0);
+            config.call_burst = Some(1);
+            config.call_admission_timeout = Some("100ms".to_string());
+        },
+        webhook_url,
+    )
+    .await?;
+
+    let socket = send_burst(&app_state, 3).await?;
+    let mut refused = 0;
+    let mut buf = vec![0u8; 8192];
+    let _ = tokio::time::timeout(Duration::from_secs(2), async {
+        while let Ok((n, _)) = socket.recv_from(&mut buf).await {
+            if String::from_utf8_lossy(&buf[..n]).starts_with("SIP/2.0 503") {
+                refused += 1;
+                if refused == 2 {
+                    break;
+                }
+            }
+        }
+    })
+    .await;
+
+    assert_eq!(refused, 2, "calls over the rate should be refused");
+    assert_eq!(hits.lock().unwrap().len(), 1);
+    Ok(())
+}
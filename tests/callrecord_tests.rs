@@ -7,6 +7,7 @@ use std::collections::HashMap;
 use std::io::Write;
 use std::sync::Arc;
 use tempfile::NamedTempFile;
+use tokio_util::sync::CancellationToken;
 
 #[tokio::test]
 async fn test_save_with_http_without_media() {
@@ -44,6 +45,10 @@ async fn test_save_with_http_without_media() {
         &headers,
         &with_media,
         &None, // keep_media_copy is irrelevant here
+        &None,
+        &None,
+        &None,
+        &CancellationToken::new(),
         &record,
     )
     .await;
@@ -106,6 +111,10 @@ async fn test_save_with_http_with_media() {
         &headers,
         &with_media,
         &None, // keep_media_copy is irrelevant here
+        &None,
+        &None,
+        &None,
+        &CancellationToken::new(),
         &record,
     )
     .await;
@@ -155,6 +164,10 @@ async fn test_save_with_http_with_custom_headers() {
         &Some(headers),
         &with_media,
         &None, // keep_media_copy is irrelevant here
+        &None,
+        &None,
+        &None,
+        &CancellationToken::new(),
         &record,
     )
     .await;
@@ -204,6 +217,10 @@ async fn test_save_with_s3_like_with_custom_headers() {
         &Some(headers),
         &with_media,
         &None,
+        &None,
+        &None,
+        &None,
+        &CancellationToken::new(),
         &record,
     )
     .await;
@@ -262,6 +279,11 @@ async fn test_save_with_s3_like_memory_store() {
         &endpoint,
         &with_media,
         &keep_media_copy,
+        &None,
+        &None,
+        &None,
+        &None,
+        &None,
         &record,
     )
     .await;
@@ -338,6 +360,11 @@ async fn test_save_with_s3_like_with_media() {
             &endpoint,
             &with_media,
             &keep_media_copy,
+            &None,
+            &None,
+            &None,
+            &None,
+            &None,
             &record,
         )
         .await;
@@ -351,3 +378,1231 @@ async fn test_save_with_s3_like_with_media() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_s3_upload_sends_sse_headers() {
+    use axum::{Router, http::HeaderMap, http::StatusCode};
+    use object_store::{ObjectStoreExt, PutPayload, path::Path as ObjectPath};
+
+    // Mock bucket that rejects unencrypted writes, like a policy requiring SSE-KMS
+    let captured: Arc<std::sync::Mutex<Vec<HeaderMap>>> = Default::default();
+    let captured_clone = captured.clone();
+    let app = Router::new().fallback(move |headers: HeaderMap| {
+        let captured = captured_clone.clone();
+        async move {
+            let encrypted = headers
+                .get("x-amz-server-side-encryption")
+                .map(|v| v == "aws:kms")
+                .unwrap_or(false);
+            captured.lock().unwrap().push(headers);
+            if encrypted {
+                (StatusCode::OK, [("ETag", "\"mock-etag\"")])
+            } else {
+                (StatusCode::FORBIDDEN, [("ETag", "\"\"")])
+            }
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let config: active_call::config::CallRecordConfig = toml::from_str(&format!(
+        r#"
+type = "s3"
+vendor = "minio"
+bucket = "secure-bucket"
+region = "us-east-1"
+access_key = "test"
+secret_key = "test"
+endpoint = "http://{}"
+root = "cdr"
+
+[sse]
+algorithm = "aws:kms"
+kms_key_id = "arn:aws:kms:us-east-1:123456789012:key/test-key"
+"#,
+        addr
+    ))
+    .unwrap();
+    let active_call::config::CallRecordConfig::S3 {
+        vendor,
+        bucket,
+        region,
+        access_key,
+        secret_key,
+        endpoint,
+        sse,
+        ..
+    } = config
+    else {
+        panic!("expected s3 config");
+    };
+
+    assert!(matches!(vendor, S3Vendor::Minio));
+    let builder = mock_s3_builder(&endpoint)
+        .with_bucket_name(bucket)
+        .with_region(region)
+        .with_access_key_id(access_key)
+        .with_secret_access_key(secret_key);
+    let store = apply_s3_sse(builder, sse.as_ref())
+        .unwrap()
+        .build()
+        .unwrap();
+    store
+        .put(
+            &ObjectPath::from("cdr/test.json"),
+            PutPayload::from(b"{}".to_vec()),
+        )
+        .await
+        .expect("upload to encrypted bucket should succeed");
+
+    let captured = captured.lock().unwrap();
+    let headers = captured.last().expect("request captured");
+    assert_eq!(
+        headers
+            .get("x-amz-server-side-encryption-aws-kms-key-id")
+            .and_then(|v| v.to_str().ok()),
+        Some("arn:aws:kms:us-east-1:123456789012:key/test-key")
+    );
+}
+
+/// A collector answering 502 twice gets the record on the third attempt, with
+/// the full multipart body each time
+#[tokio::test]
+async fn test_save_with_http_retries_transient_failures() {
+    use axum::{Router, http::StatusCode};
+
+    let bodies: Arc<std::sync::Mutex<Vec<String>>> = Default::default();
+    let bodies_clone = bodies.clone();
+    let app = Router::new().fallback(move |body: String| {
+        let bodies = bodies_clone.clone();
+        async move {
+            let mut bodies = bodies.lock().unwrap();
+            bodies.push(body);
+            if bodies.len() <= 2 {
+                (StatusCode::BAD_GATEWAY, "collector unavailable")
+            } else {
+                (StatusCode::OK, "stored")
+            }
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let mut media_file = NamedTempFile::new().unwrap();
+    media_file.write_all(b"fake audio content").unwrap();
+    media_file.flush().unwrap();
+    let record = CallRecord {
+        call_id: "test_call_retry".to_string(),
+        recorder: vec![CallRecordMedia {
+            track_id: "track_001".to_string(),
+            path: media_file.path().to_string_lossy().to_string(),
+            size: 18,
+            extra: None,
+        }],
+        ..Default::default()
+    };
+
+    let url = format!("http://{}/cdr", addr);
+    let result = CallRecordManager::save_with_http(
+        Arc::new(DefaultCallRecordFormatter::default()),
+        &url,
+        &None,
+        &Some(true),
+        &None,
+        &Some(3),
+        &Some(10),
+        &None,
+        &CancellationToken::new(),
+        &record,
+    )
+    .await;
+
+    assert_eq!(result.unwrap(), "HTTP upload successful: stored");
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(bodies.len(), 3);
+    for body in bodies.iter() {
+        assert!(body.contains("test_call_retry"));
+        assert!(body.contains("fake audio content"));
+    }
+}
+
+/// Retries give up once the retry budget is spent, and stop waiting as soon
+/// as the saver is cancelled
+#[tokio::test]
+async fn test_save_with_http_gives_up_and_honors_cancel() {
+    use axum::{Router, http::StatusCode};
+
+    let attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let attempts_clone = attempts.clone();
+    let app = Router::new().fallback(move || {
+        let attempts = attempts_clone.clone();
+        async move {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let url = format!("http://{}/cdr", addr);
+    let formatter: Arc<dyn CallRecordFormatter> = Arc::new(DefaultCallRecordFormatter::default());
+    let record = CallRecord::default();
+    let result = CallRecordManager::save_with_http(
+        formatter.clone(),
+        &url,
+        &None,
+        &None,
+        &None,
+        &Some(2),
+        &Some(10),
+        &None,
+        &CancellationToken::new(),
+        &record,
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(attempts.load(std::sync::atomic::Ordering::Relaxed), 3);
+
+    let cancel_token = CancellationToken::new();
+    cancel_token.cancel();
+    let started = std::time::Instant::now();
+    let result = CallRecordManager::save_with_http(
+        formatter,
+        &url,
+        &None,
+        &None,
+        &None,
+        &Some(5),
+        &Some(60_000),
+        &None,
+        &cancel_token,
+        &record,
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(attempts.load(std::sync::atomic::Ordering::Relaxed), 4);
+}
+
+/// A compressed call record is written with a `.gz` suffix and decompresses
+/// back to the formatted JSON
+#[tokio::test]
+async fn test_save_local_record_compressed_round_trip() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let dir = tempfile::tempdir().unwrap();
+    let formatter = Arc::new(DefaultCallRecordFormatter {
+        root: dir.path().to_string_lossy().to_string(),
+    });
+    let mut record = CallRecord {
+        call_type: ActiveCallType::Sip,
+        call_id: "test_gzip_call".to_string(),
+        start_time: Utc::now(),
+        end_time: Utc::now(),
+        caller: "+1234567890".to_string(),
+        callee: "+0987654321".to_string(),
+        status_code: 200,
+        hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        ..Default::default()
+    };
+
+    let file_name =
+        CallRecordManager::save_local_record(formatter.clone(), &Some(true), &mut record)
+            .await
+            .unwrap();
+    assert!(file_name.ends_with(".json.gz"), "file name: {}", file_name);
+    assert_eq!(file_name, formatter.format_stored_file_name(&record, true));
+
+    let mut json = String::new();
+    GzDecoder::new(std::fs::File::open(&file_name).unwrap())
+        .read_to_string(&mut json)
+        .unwrap();
+    assert_eq!(json, formatter.format(&record).unwrap());
+    let decoded: CallRecord = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.call_id, "test_gzip_call");
+
+    let plain = CallRecordManager::save_local_record(formatter.clone(), &None, &mut record)
+        .await
+        .unwrap();
+    assert!(plain.ends_with(".json"));
+    assert_eq!(
+        std::fs::read_to_string(plain).unwrap(),
+        formatter.format(&record).unwrap()
+    );
+}
+
+/// CSV records of the same day go to one file, with the header written once
+#[tokio::test]
+async fn test_save_local_record_appends_csv_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let formatter = Arc::new(CsvCallRecordFormatter {
+        root: dir.path().to_string_lossy().to_string(),
+    });
+    let start_time = Utc::now();
+    let mut first = CallRecord {
+        call_id: "csv_call_1".to_string(),
+        start_time,
+        end_time: start_time,
+        caller: "+1234567890".to_string(),
+        callee: "+0987654321".to_string(),
+        status_code: 200,
+        ..Default::default()
+    };
+    let mut second = CallRecord {
+        call_id: "csv_call_2".to_string(),
+        ..first.clone()
+    };
+
+    let first_file = CallRecordManager::save_local_record(formatter.clone(), &None, &mut first)
+        .await
+        .unwrap();
+    let second_file = CallRecordManager::save_local_record(formatter.clone(), &None, &mut second)
+        .await
+        .unwrap();
+    assert_eq!(first_file, second_file);
+    assert!(first_file.ends_with(".csv"), "file name: {}", first_file);
+
+    let content = std::fs::read_to_string(&first_file).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 3, "content: {}", content);
+    assert!(lines[0].starts_with("call_id,caller,callee,"));
+    assert!(lines[1].starts_with("csv_call_1,"));
+    assert!(lines[2].starts_with("csv_call_2,"));
+}
+
+/// Records saved at the same time to one CSV file get one header and whole rows
+#[tokio::test]
+async fn test_save_local_record_appends_csv_rows_concurrently() {
+    let dir = tempfile::tempdir().unwrap();
+    let formatter = Arc::new(CsvCallRecordFormatter {
+        root: dir.path().to_string_lossy().to_string(),
+    });
+    let start_time = Utc::now();
+    let mut saves = Vec::new();
+    for i in 0..20 {
+        let formatter = formatter.clone();
+        let mut record = CallRecord {
+            call_id: format!("csv_concurrent_{}", i),
+            start_time,
+            end_time: start_time,
+            caller: "+1234567890".to_string(),
+            callee: "+0987654321".to_string(),
+            status_code: 200,
+            ..Default::default()
+        };
+        saves.push(tokio::spawn(async move {
+            CallRecordManager::save_local_record(formatter, &None, &mut record).await
+        }));
+    }
+    let mut file_name = String::new();
+    for save in saves {
+        file_name = save.await.unwrap().unwrap();
+    }
+
+    let content = std::fs::read_to_string(&file_name).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 21, "content: {}", content);
+    assert!(lines[0].starts_with("call_id,caller,callee,"));
+    for line in &lines[1..] {
+        assert!(line.starts_with("csv_concurrent_"), "line: {}", line);
+        assert_eq!(line.split(',').count(), 9, "line: {}", line);
+    }
+}
+
+/// Object stores can't append, so each CSV record is uploaded as its own
+/// object carrying the header
+#[tokio::test]
+async fn test_s3_csv_record_uploaded_per_call() {
+    use axum::{Router, http::Method, http::StatusCode, http::Uri};
+
+    let puts: Arc<std::sync::Mutex<Vec<(String, String)>>> = Default::default();
+    let puts_clone = puts.clone();
+    let app = Router::new().fallback(move |method: Method, uri: Uri, body: axum::body::Bytes| {
+        let puts = puts_clone.clone();
+        async move {
+            if method == Method::PUT {
+                puts.lock().unwrap().push((
+                    uri.path().to_string(),
+                    String::from_utf8_lossy(&body).to_string(),
+                ));
+            }
+            (StatusCode::OK, [("ETag", "\"mock-etag\"")])
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let formatter = Arc::new(CsvCallRecordFormatter {
+        root: "cdr".to_string(),
+    });
+    let endpoint = format!("http://{}", addr);
+    for call_id in ["csv_s3_1", "csv_s3_2"] {
+        let record = CallRecord {
+            call_id: call_id.to_string(),
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            ..Default::default()
+        };
+        CallRecordManager::save_to_object_store(
+            mock_s3_store(&endpoint),
+            &endpoint,
+            formatter.clone(),
+            &None,
+            &Some(true),
+            &None,
+            &None,
+            &None,
+            &None,
+            &record,
+        )
+        .await
+        .unwrap();
+    }
+
+    let puts = puts.lock().unwrap();
+    assert_eq!(puts.len(), 2, "puts: {:?}", puts);
+    for ((path, body), call_id) in puts.iter().zip(["csv_s3_1", "csv_s3_2"]) {
+        assert!(
+            path.ends_with(&format!("/{}.csv", call_id)),
+            "path: {}",
+            path
+        );
+        let lines: Vec<&str> = body.lines().collect();
+        assert!(lines[0].starts_with("call_id,caller,callee,"));
+        assert!(lines[1].starts_with(call_id));
+    }
+}
+
+#[test]
+fn test_retention_removes_only_expired_files() {
+    use active_call::callrecord::retention::remove_expired_files;
+    use std::collections::HashSet;
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    let day_dir = dir.path().join("20240101");
+    std::fs::create_dir_all(&day_dir).unwrap();
+
+    let old_age = SystemTime::now() - Duration::from_secs(10 * 24 * 3600);
+    let touch = |path: &std::path::Path, modified: Option<SystemTime>| {
+        let file = std::fs::File::create(path).unwrap();
+        if let Some(modified) = modified {
+            file.set_modified(modified).unwrap();
+        }
+    };
+
+    let old_cdr = day_dir.join("20240101-120000_old-call.json");
+    let old_recording = dir.path().join("old-call.wav");
+    let active_recording = dir.path().join("live-call.wav");
+    let new_recording = dir.path().join("new-call.wav");
+    touch(&old_cdr, Some(old_age));
+    touch(&old_recording, Some(old_age));
+    touch(&active_recording, Some(old_age));
+    touch(&new_recording, None);
+
+    // Empty directories a writer may be about to fill are kept: one just
+    // created, and today's even when it is old
+    let new_dir = dir.path().join("20240102");
+    let today_dir = dir
+        .path()
+        .join(chrono::Local::now().format("%Y%m%d").to_string());
+    std::fs::create_dir_all(&new_dir).unwrap();
+    std::fs::create_dir_all(&today_dir).unwrap();
+    for old_dir in [&day_dir, &today_dir] {
+        std::fs::File::open(old_dir)
+            .unwrap()
+            .set_modified(old_age)
+            .unwrap();
+    }
+
+    let active: HashSet<String> = ["live-call".to_string()].into_iter().collect();
+    let removed = remove_expired_files(dir.path(), Duration::from_secs(7 * 24 * 3600), &active);
+
+    assert_eq!(removed, 2);
+    assert!(!old_cdr.exists());
+    assert!(!day_dir.exists(), "empty date directory should be removed");
+    assert!(new_dir.exists(), "recently created directory should be kept");
+    assert!(today_dir.exists(), "today's directory should be kept");
+    assert!(!old_recording.exists());
+    assert!(active_recording.exists(), "active call files must be kept");
+    assert!(new_recording.exists());
+}
+
+/// A tenant id set on the originated call ends up as a top-level CDR field
+#[tokio::test]
+async fn test_cdr_carries_tenant_from_call_extra() -> anyhow::Result<()> {
+    use active_call::app::AppStateBuilder;
+    use active_call::call::Command;
+    use active_call::config::{CallRecordConfig, Config};
+    use active_call::media::engine::StreamEngine;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    let record_dir = tempfile::tempdir()?;
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.callrecord = Some(CallRecordConfig::Local {
+        root: record_dir.path().to_string_lossy().to_string(),
+        compress: None,
+    });
+    let saved = Arc::new(tokio::sync::Notify::new());
+    let saved_ref = saved.clone();
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .on_cdr_saved(move |_| {
+            let saved = saved_ref.clone();
+            async move { saved.notify_one() }
+        })
+        .build()
+        .await?;
+
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, _event_rx) = mpsc::unbounded_channel();
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        "test-cdr-tenant".to_string(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            codec: Some("pcmu".to_string()),
+            extra: Some(HashMap::from([("tenant".to_string(), "acme".to_string())])),
+            ..Default::default()
+        },
+    })?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    command_tx.send(Command::Hangup {
+        reason: None,
+        initiator: None,
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    tokio::time::timeout(Duration::from_secs(5), saved.notified()).await?;
+
+    let mut dirs = vec![record_dir.path().to_path_buf()];
+    let mut cdr = None;
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "json") {
+                cdr = Some(path);
+            }
+        }
+    }
+    let cdr = cdr.expect("cdr file should be written");
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(cdr)?)?;
+    assert_eq!(json["tenant"], "acme");
+    Ok(())
+}
+
+#[test]
+fn test_answered_call_durations() {
+    let start_time = Utc::now();
+    let mut record = CallRecord {
+        call_id: "answered".to_string(),
+        start_time,
+        answer_time: Some(start_time + chrono::Duration::seconds(4)),
+        end_time: start_time + chrono::Duration::milliseconds(64_500),
+        status_code: 200,
+        ..Default::default()
+    };
+    assert_eq!(record.duration(), chrono::Duration::milliseconds(64_500));
+    assert_eq!(
+        record.billable_duration(),
+        Some(chrono::Duration::milliseconds(60_500))
+    );
+
+    record.update_durations();
+    let json = serde_json::to_value(&record).unwrap();
+    assert_eq!(json["durationMs"], 64_500);
+    assert_eq!(json["billableMs"], 60_500);
+
+    // A later change to the timestamps is reflected when serialized again
+    record.end_time = start_time + chrono::Duration::milliseconds(70_000);
+    let json = serde_json::to_value(&record).unwrap();
+    assert_eq!(json["durationMs"], 70_000);
+    assert_eq!(json["billableMs"], 66_000);
+
+    let parsed: CallRecord = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.duration_ms, Some(70_000));
+    assert_eq!(parsed.billable_ms, Some(66_000));
+}
+
+#[test]
+fn test_no_answer_call_is_not_billable() {
+    let start_time = Utc::now();
+    let record = CallRecord {
+        call_id: "no-answer".to_string(),
+        start_time,
+        end_time: start_time + chrono::Duration::seconds(30),
+        status_code: 480,
+        hangup_reason: Some(CallRecordHangupReason::NoAnswer),
+        ..Default::default()
+    };
+    assert_eq!(record.duration(), chrono::Duration::seconds(30));
+    assert_eq!(record.billable_duration(), None);
+
+    // Durations are filled in when serialized, without update_durations
+    let json = serde_json::to_value(&record).unwrap();
+    assert_eq!(json["durationMs"], 30_000);
+    assert!(json.get("billableMs").is_none());
+}
+
+#[test]
+fn test_billable_duration_clamps_clock_skew() {
+    let start_time = Utc::now();
+    let mut record = CallRecord {
+        call_id: "skewed".to_string(),
+        start_time,
+        answer_time: Some(start_time + chrono::Duration::seconds(10)),
+        end_time: start_time + chrono::Duration::seconds(8),
+        status_code: 200,
+        ..Default::default()
+    };
+    assert_eq!(record.billable_duration(), Some(chrono::Duration::zero()));
+
+    record.update_durations();
+    assert_eq!(record.billable_ms, Some(0));
+}
+
+/// Media files of one record upload in parallel up to the configured limit,
+/// and a failed upload doesn't stop the others. Without `verify_upload` the
+/// record is still saved, keeping the local copy of the failed file
+#[tokio::test]
+async fn test_save_with_s3_like_uploads_media_concurrently() {
+    use axum::{Router, http::Method, http::StatusCode, http::Uri};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+    let uploaded: Arc<std::sync::Mutex<Vec<String>>> = Default::default();
+    let (in_flight_clone, max_clone, uploaded_clone) =
+        (in_flight.clone(), max_in_flight.clone(), uploaded.clone());
+    let app = Router::new().fallback(move |method: Method, uri: Uri| {
+        let (in_flight, max_in_flight, uploaded) = (
+            in_flight_clone.clone(),
+            max_clone.clone(),
+            uploaded_clone.clone(),
+        );
+        async move {
+            let path = uri.path().to_string();
+            if method != Method::PUT || !path.ends_with(".wav") {
+                return (StatusCode::OK, [("ETag", "\"mock-etag\"")]);
+            }
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            if path.ends_with("broken.wav") {
+                return (StatusCode::FORBIDDEN, [("ETag", "\"\"")]);
+            }
+            uploaded.lock().unwrap().push(path);
+            (StatusCode::OK, [("ETag", "\"mock-etag\"")])
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut recorder = Vec::new();
+    for name in ["leg-0", "leg-1", "broken", "leg-2", "leg-3"] {
+        let path = dir.path().join(format!("{}.wav", name));
+        std::fs::write(&path, b"fake audio").unwrap();
+        recorder.push(CallRecordMedia {
+            track_id: name.to_string(),
+            path: path.to_string_lossy().to_string(),
+            size: 10,
+            extra: None,
+        });
+    }
+    let record = CallRecord {
+        call_id: "test_s3_concurrent".to_string(),
+        start_time: Utc::now(),
+        end_time: Utc::now(),
+        recorder: recorder.clone(),
+        ..Default::default()
+    };
+
+    let endpoint = format!("http://{}", addr);
+    let result = CallRecordManager::save_to_object_store(
+        mock_s3_store(&endpoint),
+        &endpoint,
+        Arc::new(DefaultCallRecordFormatter::default()),
+        &Some(true),
+        &Some(false),
+        &None,
+        &None,
+        &Some(2),
+        &None,
+        &record,
+    )
+    .await;
+
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    let uploaded = uploaded.lock().unwrap();
+    assert_eq!(uploaded.len(), 4, "uploaded: {:?}", uploaded);
+    for name in ["leg-0", "leg-1", "leg-2", "leg-3"] {
+        assert!(
+            uploaded
+                .iter()
+                .any(|p| p.ends_with(&format!("{}.wav", name))),
+            "{} not uploaded: {:?}",
+            name,
+            uploaded
+        );
+    }
+    // Only the failed file is kept so it can be uploaded again
+    for media in &recorder {
+        assert_eq!(
+            std::path::Path::new(&media.path).exists(),
+            media.track_id == "broken",
+            "{}",
+            media.path
+        );
+    }
+}
+
+/// Local media copies are removed once every upload succeeded
+#[tokio::test]
+async fn test_save_with_s3_like_removes_uploaded_media() {
+    use axum::{Router, http::StatusCode};
+
+    let app = Router::new().fallback(|| async { (StatusCode::OK, [("ETag", "\"mock-etag\"")]) });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("leg-0.wav");
+    std::fs::write(&path, b"fake audio").unwrap();
+    let record = CallRecord {
+        call_id: "test_s3_cleanup".to_string(),
+        start_time: Utc::now(),
+        end_time: Utc::now(),
+        recorder: vec![CallRecordMedia {
+            track_id: "leg-0".to_string(),
+            path: path.to_string_lossy().to_string(),
+            size: 10,
+            extra: None,
+        }],
+        ..Default::default()
+    };
+
+    let endpoint = format!("http://{}", addr);
+    CallRecordManager::save_to_object_store(
+        mock_s3_store(&endpoint),
+        &endpoint,
+        Arc::new(DefaultCallRecordFormatter::default()),
+        &Some(true),
+        &Some(false),
+        &None,
+        &None,
+        &None,
+        &None,
+        &record,
+    )
+    .await
+    .expect("record upload should succeed");
+    assert!(!path.exists());
+}
+
+/// Start a mock S3 bucket whose HEAD reports a truncated size for the call
+/// record until `truncated_heads` HEAD requests have been answered
+async fn start_truncating_bucket(
+    truncated_heads: usize,
+) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use axum::{
+        Router,
+        http::{HeaderMap, Method, StatusCode, Uri},
+        response::IntoResponse,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let record_puts = Arc::new(AtomicUsize::new(0));
+    let heads = Arc::new(AtomicUsize::new(0));
+    let stored_size = Arc::new(AtomicUsize::new(0));
+    let record_puts_clone = record_puts.clone();
+    let app = Router::new().fallback(move |method: Method, uri: Uri, body: axum::body::Bytes| {
+        let (record_puts, heads, stored_size) = (
+            record_puts_clone.clone(),
+            heads.clone(),
+            stored_size.clone(),
+        );
+        async move {
+            if !uri.path().ends_with(".json") {
+                return (StatusCode::OK, [("ETag", "\"mock-etag\"")]).into_response();
+            }
+            if method == Method::PUT {
+                record_puts.fetch_add(1, Ordering::SeqCst);
+                stored_size.store(body.len(), Ordering::SeqCst);
+                return (StatusCode::OK, [("ETag", "\"mock-etag\"")]).into_response();
+            }
+            let mut size = stored_size.load(Ordering::SeqCst);
+            if heads.fetch_add(1, Ordering::SeqCst) < truncated_heads {
+                size /= 2;
+            }
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Length", size.into());
+            headers.insert("ETag", "\"mock-etag\"".parse().unwrap());
+            headers.insert(
+                "Last-Modified",
+                "Mon, 01 Jan 2024 00:00:00 GMT".parse().unwrap(),
+            );
+            (StatusCode::OK, headers).into_response()
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    (format!("http://{}", addr), record_puts)
+}
+
+async fn save_verified_to_s3(endpoint: &String) -> anyhow::Result<String> {
+    let record = CallRecord {
+        call_id: "test_s3_verify".to_string(),
+        start_time: Utc::now(),
+        end_time: Utc::now(),
+        ..Default::default()
+    };
+    CallRecordManager::save_to_object_store(
+        mock_s3_store(endpoint),
+        endpoint,
+        Arc::new(DefaultCallRecordFormatter::default()),
+        &Some(false),
+        &None,
+        &None,
+        &None,
+        &None,
+        &Some(true),
+        &record,
+    )
+    .await
+}
+
+/// A call record stored truncated is detected by the HEAD check and uploaded again
+#[tokio::test]
+async fn test_s3_verify_upload_retries_truncated_object() {
+    let (endpoint, record_puts) = start_truncating_bucket(1).await;
+    let url = save_verified_to_s3(&endpoint)
+        .await
+        .expect("second upload should verify");
+    assert!(url.starts_with(&endpoint), "url: {}", url);
+    assert_eq!(record_puts.load(std::sync::atomic::Ordering::SeqCst), 2);
+}
+
+/// A call record that never verifies is reported as a failure, not a success
+#[tokio::test]
+async fn test_s3_verify_upload_fails_when_always_truncated() {
+    let (endpoint, record_puts) = start_truncating_bucket(usize::MAX).await;
+    assert!(save_verified_to_s3(&endpoint).await.is_err());
+    assert_eq!(record_puts.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+/// Start a mock S3 bucket taking multipart uploads of encrypted media. With
+/// `truncate`, HEAD reports half of the bytes that were written.
+async fn start_encrypted_media_bucket(truncate: bool) -> String {
+    use axum::{
+        Router,
+        extract::RawQuery,
+        http::{HeaderMap, Method, StatusCode, Uri},
+        response::IntoResponse,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    const INITIATED: &str = "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>";
+    const COMPLETED: &str =
+        "<CompleteMultipartUploadResult><ETag>\"mock-etag\"</ETag></CompleteMultipartUploadResult>";
+
+    let stored_size = Arc::new(AtomicUsize::new(0));
+    let app = Router::new().fallback(
+        move |method: Method, uri: Uri, RawQuery(query): RawQuery, body: axum::body::Bytes| {
+            let stored_size = stored_size.clone();
+            async move {
+                let query = query.unwrap_or_default();
+                if !uri.path().ends_with(".wav.age") {
+                    return (StatusCode::OK, [("ETag", "\"mock-etag\"")]).into_response();
+                }
+                match method {
+                    Method::POST if query.starts_with("uploads") => {
+                        (StatusCode::OK, INITIATED).into_response()
+                    }
+                    Method::PUT => {
+                        stored_size.fetch_add(body.len(), Ordering::SeqCst);
+                        (StatusCode::OK, [("ETag", "\"part-etag\"")]).into_response()
+                    }
+                    Method::POST => (StatusCode::OK, COMPLETED).into_response(),
+                    _ => {
+                        let mut size = stored_size.load(Ordering::SeqCst);
+                        if truncate {
+                            size /= 2;
+                        }
+                        let mut headers = HeaderMap::new();
+                        headers.insert("Content-Length", size.into());
+                        headers.insert("ETag", "\"mock-etag\"".parse().unwrap());
+                        headers.insert(
+                            "Last-Modified",
+                            "Mon, 01 Jan 2024 00:00:00 GMT".parse().unwrap(),
+                        );
+                        (StatusCode::OK, headers).into_response()
+                    }
+                }
+            }
+        },
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    format!("http://{}", addr)
+}
+
+/// Upload a record with one encrypted recording, returning the result and
+/// whether the local recording is still there
+async fn save_encrypted_media_to_s3(endpoint: &String) -> (anyhow::Result<String>, bool) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("leg-0.wav");
+    std::fs::write(&path, b"fake audio").unwrap();
+    let record = CallRecord {
+        call_id: "test_s3_encrypted_media".to_string(),
+        start_time: Utc::now(),
+        end_time: Utc::now(),
+        recorder: vec![CallRecordMedia {
+            track_id: "leg-0".to_string(),
+            path: path.to_string_lossy().to_string(),
+            size: 10,
+            extra: None,
+        }],
+        ..Default::default()
+    };
+    let encryption = active_call::config::EncryptionConfig {
+        recipient: age::x25519::Identity::generate().to_public().to_string(),
+    };
+    let result = CallRecordManager::save_to_object_store(
+        mock_s3_store(endpoint),
+        endpoint,
+        Arc::new(DefaultCallRecordFormatter::default()),
+        &Some(true),
+        &Some(false),
+        &None,
+        &Some(encryption),
+        &None,
+        &None,
+        &record,
+    )
+    .await;
+    (result, path.exists())
+}
+
+/// Encrypted media are checked against the bytes written before the local
+/// copy is deleted, a mismatch keeps it
+#[tokio::test]
+async fn test_s3_encrypted_media_verified_before_cleanup() {
+    let endpoint = start_encrypted_media_bucket(false).await;
+    let (result, kept) = save_encrypted_media_to_s3(&endpoint).await;
+    assert!(result.is_ok(), "{:?}", result);
+    assert!(!kept);
+
+    let endpoint = start_encrypted_media_bucket(true).await;
+    let (result, kept) = save_encrypted_media_to_s3(&endpoint).await;
+    assert!(result.is_ok(), "{:?}", result);
+    assert!(kept, "local recording should be kept for another upload");
+}
+
+/// A collector echoing a different checksum gets the record again
+#[tokio::test]
+async fn test_save_with_http_retries_checksum_mismatch() {
+    use axum::{Router, http::HeaderMap};
+
+    let checksums: Arc<std::sync::Mutex<Vec<String>>> = Default::default();
+    let checksums_clone = checksums.clone();
+    let app = Router::new().fallback(move |headers: HeaderMap| {
+        let checksums = checksums_clone.clone();
+        async move {
+            let sent = headers
+                .get(CALLLOG_CHECKSUM_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let mut checksums = checksums.lock().unwrap();
+            checksums.push(sent.clone());
+            // The first body arrives truncated
+            let received = if checksums.len() == 1 {
+                "0".repeat(64)
+            } else {
+                sent
+            };
+            ([(CALLLOG_CHECKSUM_HEADER, received)], "stored")
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let url = format!("http://{}/cdr", addr);
+    let result = CallRecordManager::save_with_http(
+        Arc::new(DefaultCallRecordFormatter::default()),
+        &url,
+        &None,
+        &None,
+        &None,
+        &Some(2),
+        &Some(10),
+        &Some(true),
+        &CancellationToken::new(),
+        &CallRecord {
+            call_id: "test_http_verify".to_string(),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(result.unwrap(), "HTTP upload successful: stored");
+    let checksums = checksums.lock().unwrap();
+    assert_eq!(checksums.len(), 2);
+    assert_eq!(checksums[0].len(), 64);
+    assert_eq!(checksums[0], checksums[1]);
+}
+
+/// A record the saver fails on is reported once to the save error hook
+#[tokio::test]
+async fn test_on_save_error_fires_once_for_failed_record() {
+    use active_call::config::CallRecordConfig;
+    use active_call::hooks::{CallHooks, CallRecordSaveError};
+    use std::future::Future;
+    use std::pin::Pin;
+
+    let saver: FnSaveCallRecord = Arc::new(Box::new(
+        |_: CancellationToken,
+         _: Arc<dyn CallRecordFormatter>,
+         _: Arc<CallRecordConfig>,
+         _: CallRecord|
+         -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> {
+            Box::pin(async { Err(anyhow::anyhow!("collector unreachable")) })
+        },
+    ));
+    let (error_tx, mut error_rx) = tokio::sync::mpsc::unbounded_channel();
+    let cancel_token = CancellationToken::new();
+    let mut manager = CallRecordManagerBuilder::new()
+        .with_cancel_token(cancel_token.clone())
+        .with_config(CallRecordConfig::Http {
+            url: "http://127.0.0.1:1/cdr".to_string(),
+            headers: None,
+            with_media: None,
+            keep_media_copy: None,
+            max_retries: None,
+            retry_backoff_ms: None,
+            verify_upload: None,
+        })
+        .with_saver(saver)
+        .with_on_save_error(Some(CallHooks::wrap(move |error: CallRecordSaveError| {
+            let error_tx = error_tx.clone();
+            async move {
+                error_tx.send(error).ok();
+            }
+        })))
+        .build();
+    let sender = manager.sender.clone();
+    tokio::spawn(async move { manager.serve().await });
+
+    sender
+        .send(CallRecord {
+            call_id: "test_save_error".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+    let error = tokio::time::timeout(std::time::Duration::from_secs(5), error_rx.recv())
+        .await
+        .expect("save error hook should fire")
+        .unwrap();
+    assert_eq!(error.call_id, "test_save_error");
+    assert_eq!(error.backend, "http");
+    assert!(error.error.contains("collector unreachable"));
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(300), error_rx.recv())
+            .await
+            .is_err(),
+        "hook should fire exactly once"
+    );
+    cancel_token.cancel();
+}
+
+/// Save records through a manager with `enrichment`, returning what the saver received
+async fn save_enriched(
+    enrichment: active_call::config::CdrEnrichmentConfig,
+    record: CallRecord,
+) -> CallRecord {
+    use active_call::config::CallRecordConfig;
+    use std::future::Future;
+    use std::pin::Pin;
+
+    let (saved_tx, mut saved_rx) = tokio::sync::mpsc::unbounded_channel();
+    let saver: FnSaveCallRecord = Arc::new(Box::new(
+        move |_: CancellationToken,
+              _: Arc<dyn CallRecordFormatter>,
+              _: Arc<CallRecordConfig>,
+              record: CallRecord|
+              -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> {
+            saved_tx.send(record).ok();
+            Box::pin(async { Ok(()) })
+        },
+    ));
+    let cancel_token = CancellationToken::new();
+    let mut manager = CallRecordManagerBuilder::new()
+        .with_cancel_token(cancel_token.clone())
+        .with_saver(saver)
+        .with_enrichment(Some(enrichment))
+        .build();
+    let sender = manager.sender.clone();
+    tokio::spawn(async move { manager.serve().await });
+
+    sender.send(record).unwrap();
+    let saved = tokio::time::timeout(std::time::Duration::from_secs(5), saved_rx.recv())
+        .await
+        .expect("record should be saved")
+        .unwrap();
+    cancel_token.cancel();
+    saved
+}
+
+#[tokio::test]
+async fn test_cdr_enriched_from_lookup() {
+    use active_call::config::CdrEnrichmentConfig;
+    use axum::{Json, Router, extract::Query, http::HeaderMap, routing::get};
+
+    let app = Router::new().route(
+        "/crm",
+        get(
+            |Query(params): Query<HashMap<String, String>>, headers: HeaderMap| async move {
+                assert_eq!(headers["x-api-key"], "secret");
+                Json(serde_json::json!({
+                    "customer_name": format!("Customer {}", params["caller"]),
+                    "account_tier": "gold",
+                    "test_key": "overwritten",
+                }))
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/crm", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let mut extras = HashMap::new();
+    extras.insert("test_key".to_string(), serde_json::json!("test_value"));
+    let saved = save_enriched(
+        CdrEnrichmentConfig {
+            url,
+            headers: Some(HashMap::from([(
+                "x-api-key".to_string(),
+                "secret".to_string(),
+            )])),
+            timeout_ms: None,
+        },
+        CallRecord {
+            call_id: "test_enriched".to_string(),
+            caller: "+1234567890".to_string(),
+            status_code: 200,
+            extras: Some(extras),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let extras = saved.extras.unwrap();
+    assert_eq!(extras["customer_name"], "Customer +1234567890");
+    assert_eq!(extras["account_tier"], "gold");
+    assert_eq!(extras["test_key"], "test_value", "call fields win");
+}
+
+#[tokio::test]
+async fn test_cdr_saved_when_enrichment_times_out() {
+    use active_call::config::CdrEnrichmentConfig;
+    use axum::{Json, Router, routing::get};
+
+    let app = Router::new().route(
+        "/crm",
+        get(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+            Json(serde_json::json!({ "account_tier": "gold" }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/crm", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let saved = save_enriched(
+        CdrEnrichmentConfig {
+            url,
+            headers: None,
+            timeout_ms: Some(100),
+        },
+        CallRecord {
+            call_id: "test_enrichment_timeout".to_string(),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(saved.call_id, "test_enrichment_timeout");
+    assert!(saved.extras.is_none());
+}
+
+/// S3 client for a mock server, which only speaks plain HTTP
+fn mock_s3_builder(endpoint: &str) -> object_store::aws::AmazonS3Builder {
+    object_store::aws::AmazonS3Builder::new()
+        .with_endpoint(endpoint)
+        .with_allow_http(true)
+        .with_virtual_hosted_style_request(false)
+}
+
+fn mock_s3_store(endpoint: &str) -> Arc<dyn object_store::ObjectStore> {
+    Arc::new(
+        mock_s3_builder(endpoint)
+            .with_bucket_name("test-bucket")
+            .with_region("us-east-1")
+            .with_access_key_id("test")
+            .with_secret_access_key("test")
+            .build()
+            .unwrap(),
+    )
+}
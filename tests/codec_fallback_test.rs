@@ -0,0 +1,207 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::{CallOption, CodecFallbackOption};
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const PCMA_ANSWER: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 41000 RTP/AVP 8\r\na=rtpmap:8 PCMA/8000\r\na=sendrecv\r\n";
+const PCMU_ANSWER: &str = "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 41000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=sendrecv\r\n";
+
+fn headers<'a>(message: &'a str, name: &str) -> Vec<&'a str> {
+    let prefix = format!("{}:", name.to_ascii_lowercase());
+    message
+        .lines()
+        .filter(|l| l.to_ascii_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+/// Build a response to `request` echoing the transaction headers
+fn response(request: &str, status: &str, contact: &str, body: &str) -> String {
+    let mut out = format!("SIP/2.0 {}\r\n", status);
+    for name in ["Via", "From", "Call-ID", "CSeq"] {
+        for line in headers(request, name) {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    for line in headers(request, "To") {
+        out.push_str(line);
+        if !line.contains(";tag=") {
+            out.push_str(";tag=trunk");
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("Contact: <{}>\r\n", contact));
+    if !body.is_empty() {
+        out.push_str("Content-Type: application/sdp\r\n");
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out
+}
+
+/// A trunk that picks PCMA for the call and accepts whatever a re-INVITE
+/// narrows it to, recording the body of each INVITE
+async fn run_trunk(socket: UdpSocket, invites: Arc<Mutex<Vec<String>>>) {
+    let contact = format!("sip:trunk@{}", socket.local_addr().unwrap());
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let method = message.split(' ').next().unwrap_or_default().to_string();
+        let body = message
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        let reply = match method.as_str() {
+            "INVITE" => {
+                let first = {
+                    let mut invites = invites.lock().unwrap();
+                    invites.push(body);
+                    invites.len() == 1
+                };
+                let answer = if first { PCMA_ANSWER } else { PCMU_ANSWER };
+                response(&message, "200 OK", &contact, answer)
+            }
+            "BYE" => response(&message, "200 OK", &contact, ""),
+            _ => continue,
+        };
+        socket.send_to(reply.as_bytes(), peer).await.ok();
+    }
+}
+
+/// Sustained packet loss reported for a SIP call renegotiates it to the
+/// fallback codec, announced with a `codec_fallback` metrics event
+#[tokio::test]
+async fn test_sustained_loss_renegotiates_codec() -> Result<()> {
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let trunk = UdpSocket::bind("127.0.0.1:0").await?;
+    let target = format!("sip:bob@{}", trunk.local_addr()?);
+    let invites = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_trunk(trunk, invites.clone()));
+
+    let session_id = "test-codec-fallback";
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            session_id.to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                callee: Some(target),
+                codec_fallback: Some(CodecFallbackOption {
+                    max_loss_pct: Some(5.0),
+                    sustain_secs: Some(1),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        })?;
+
+        let mut fallback = None;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    SessionEvent::Answer { .. } => {
+                        let call = app_state
+                            .active_calls
+                            .lock()
+                            .unwrap()
+                            .get(session_id)
+                            .cloned()
+                            .expect("call should be active");
+                        // Report 20% loss every 200ms, as RTCP receiver reports would
+                        tokio::spawn(async move {
+                            for _ in 0..20 {
+                                call.event_sender
+                                    .send(SessionEvent::QualityStats {
+                                        track_id: call.session_id.clone(),
+                                        timestamp: active_call::media::get_timestamp(),
+                                        jitter_ms: 10.0,
+                                        loss_pct: 20.0,
+                                        rtt_ms: None,
+                                    })
+                                    .ok();
+                                tokio::time::sleep(Duration::from_millis(200)).await;
+                            }
+                        });
+                    }
+                    SessionEvent::Metrics { key, data, .. } if key == "codec_fallback" => {
+                        fallback = Some(data);
+                        break;
+                    }
+                    SessionEvent::Hangup { .. } | SessionEvent::Reject { .. } => break,
+                    _ => {}
+                }
+            }
+        })
+        .await?;
+        let fallback = fallback.expect("codec fallback should be announced");
+        assert_eq!(fallback["from"], "PCMA");
+        assert_eq!(fallback["to"], "PCMU");
+        assert_eq!(fallback["lossPct"], 20.0);
+
+        let adaptation = {
+            let call = app_state
+                .active_calls
+                .lock()
+                .unwrap()
+                .get(session_id)
+                .cloned()
+                .expect("call should be active");
+            call.call_state.read().await.codec_adaptation.clone()
+        };
+        let adaptation = adaptation.expect("codec change should be recorded");
+        assert_eq!(adaptation.to, "PCMU");
+        assert_eq!(adaptation.stats.loss_pct, 20.0);
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    }
+
+    let invites = invites.lock().unwrap().clone();
+    assert_eq!(invites.len(), 2, "invites: {:?}", invites);
+    let reoffer = invites[1]
+        .lines()
+        .find(|line| line.starts_with("m=audio "))
+        .unwrap_or_default()
+        .to_string();
+    let payload_types: Vec<&str> = reoffer.split_whitespace().skip(3).collect();
+    assert_eq!(payload_types.first(), Some(&"0"), "re-offer: {}", reoffer);
+    assert!(!payload_types.contains(&"8"), "re-offer: {}", reoffer);
+    Ok(())
+}
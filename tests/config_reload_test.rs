@@ -0,0 +1,106 @@
+use active_call::app::AppStateBuilder;
+use active_call::config::{Config, RecordingPolicy};
+use active_call::synthesis::{SynthesisEvent, SynthesisType, limiter};
+use anyhow::Result;
+use futures::{StreamExt, stream};
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn config_toml(rtp_start_port: u16) -> String {
+    format!(
+        "addr = \"127.0.0.1\"\nudp_port = 0\nrtp_start_port = {}\nrtp_end_port = 40000\n",
+        rtp_start_port
+    )
+}
+
+/// Saving the file yields the re-parsed config, skipping contents that don't
+/// parse or validate
+#[tokio::test]
+async fn test_watch_yields_valid_changes() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, config_toml(20000))?;
+    let updates = Config::watch(&path.to_string_lossy())?;
+    let mut updates = std::pin::pin!(updates);
+
+    std::fs::write(&path, config_toml(30000))?;
+    let reloaded = tokio::time::timeout(Duration::from_secs(5), updates.next())
+        .await?
+        .expect("config should be yielded");
+    assert_eq!(reloaded.rtp_start_port, Some(30000));
+
+    std::fs::write(&path, "udp_port = ")?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    // Start port above the end port
+    std::fs::write(&path, config_toml(50000))?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    std::fs::write(&path, config_toml(31000))?;
+    let reloaded = tokio::time::timeout(Duration::from_secs(5), updates.next())
+        .await?
+        .expect("config should be yielded");
+    assert_eq!(reloaded.rtp_start_port, Some(31000));
+    Ok(())
+}
+
+/// Reloadable settings are swapped in, restart-only ones keep their value
+#[tokio::test]
+async fn test_reload_config_applies_reloadable_settings() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+    let before = app_state.config();
+
+    let mut reloaded = Config::default();
+    reloaded.udp_port = 5070;
+    reloaded.rtp_start_port = Some(30000);
+    reloaded.recording = Some(RecordingPolicy {
+        path: Some("/tmp/reloaded-recorders".to_string()),
+        ..Default::default()
+    });
+    app_state.reload_config(reloaded);
+
+    let after = app_state.config();
+    assert_eq!(after.rtp_start_port, Some(30000));
+    assert_eq!(after.recorder_path(), "/tmp/reloaded-recorders");
+    assert_eq!(after.udp_port, 0);
+    // Snapshots taken before the reload are left untouched
+    assert_eq!(before.rtp_start_port, Config::default().rtp_start_port);
+    Ok(())
+}
+
+/// TTS concurrency limits from a reloaded file apply to the next requests
+#[tokio::test]
+async fn test_reload_config_applies_tts_concurrency() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let provider = SynthesisType::Other("reload-limit-test".to_string());
+    let mut reloaded = Config::default();
+    reloaded.tts_concurrency = Some(HashMap::from([(provider.to_string(), 1)]));
+    app_state.reload_config(reloaded);
+
+    // Hold the only slot with a request that never finishes
+    let mut held = Box::pin(limiter::throttle(provider.clone(), stream::pending()));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), held.next())
+            .await
+            .is_err()
+    );
+
+    let queued = tokio::spawn(
+        limiter::throttle(
+            provider,
+            stream::iter([Ok::<_, anyhow::Error>(SynthesisEvent::Finished)]),
+        )
+        .collect::<Vec<_>>(),
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    drop(held);
+    let events = tokio::time::timeout(Duration::from_secs(5), queued).await??;
+    assert!(matches!(
+        events.first(),
+        Some(Ok(SynthesisEvent::Throttled { .. }))
+    ));
+    Ok(())
+}
@@ -0,0 +1,626 @@
+use active_call::app::{AppState, AppStateBuilder};
+use active_call::call::{ActiveCallType, Command};
+use active_call::callrecord::CallRecord;
+use active_call::config::{CallRecordConfig, Config};
+use active_call::event::SessionEvent;
+use active_call::{CallOption, InviteRetryOption};
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const ANSWER_SDP: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 41000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+
+fn headers<'a>(message: &'a str, name: &str) -> Vec<&'a str> {
+    let prefix = format!("{}:", name.to_ascii_lowercase());
+    message
+        .lines()
+        .filter(|l| l.to_ascii_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+/// Build a response to `request` echoing the transaction headers
+fn response(request: &str, status: &str, contact: &str, body: &str) -> String {
+    let mut out = format!("SIP/2.0 {}\r\n", status);
+    for name in ["Via", "From", "Call-ID", "CSeq"] {
+        for line in headers(request, name) {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    for line in headers(request, "To") {
+        out.push_str(line);
+        if !line.contains(";tag=") {
+            out.push_str(";tag=trunk");
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("Contact: <{}>\r\n", contact));
+    if !body.is_empty() {
+        out.push_str("Content-Type: application/sdp\r\n");
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out
+}
+
+/// A bare UDP endpoint that either answers every INVITE or rings forever,
+/// recording the Call-ID of each INVITE in `call_ids`
+async fn run_trunk(
+    socket: UdpSocket,
+    answer: bool,
+    methods: Arc<Mutex<Vec<String>>>,
+    call_ids: Arc<Mutex<Vec<String>>>,
+) {
+    let contact = format!("sip:trunk@{}", socket.local_addr().unwrap());
+    let mut invite = None;
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let method = message.split(' ').next().unwrap_or_default().to_string();
+        methods.lock().unwrap().push(method.clone());
+        if method == "INVITE" {
+            if let Some(line) = headers(&message, "Call-ID").first() {
+                call_ids.lock().unwrap().push(line[8..].trim().to_string());
+            }
+        }
+        let replies = match method.as_str() {
+            "INVITE" if answer => vec![response(&message, "200 OK", &contact, ANSWER_SDP)],
+            "INVITE" => {
+                let ringing = response(&message, "180 Ringing", &contact, "");
+                invite = Some(message);
+                vec![ringing]
+            }
+            "CANCEL" => {
+                let mut replies = vec![response(&message, "200 OK", &contact, "")];
+                if let Some(invite) = &invite {
+                    replies.push(response(invite, "487 Request Terminated", &contact, ""));
+                }
+                replies
+            }
+            "BYE" => vec![response(&message, "200 OK", &contact, "")],
+            _ => vec![],
+        };
+        for reply in replies {
+            socket.send_to(reply.as_bytes(), peer).await.ok();
+        }
+    }
+}
+
+/// A bare UDP endpoint that rejects the first `failures` INVITEs with
+/// `failure` and answers the rest
+async fn run_flaky_trunk(
+    socket: UdpSocket,
+    failures: usize,
+    failure: &'static str,
+    methods: Arc<Mutex<Vec<String>>>,
+) {
+    let contact = format!("sip:trunk@{}", socket.local_addr().unwrap());
+    let mut invites = 0;
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let method = message.split(' ').next().unwrap_or_default().to_string();
+        methods.lock().unwrap().push(method.clone());
+        let reply = match method.as_str() {
+            "INVITE" => {
+                invites += 1;
+                if invites <= failures {
+                    response(&message, failure, &contact, "")
+                } else {
+                    response(&message, "200 OK", &contact, ANSWER_SDP)
+                }
+            }
+            "BYE" => response(&message, "200 OK", &contact, ""),
+            _ => continue,
+        };
+        socket.send_to(reply.as_bytes(), peer).await.ok();
+    }
+}
+
+async fn build_app_state(
+    record_dir: &std::path::Path,
+    saved: Arc<Mutex<Option<CallRecord>>>,
+) -> Result<AppState> {
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    config.callrecord = Some(CallRecordConfig::Local {
+        root: record_dir.to_string_lossy().to_string(),
+        compress: None,
+    });
+    AppStateBuilder::new()
+        .with_config(config)
+        .on_cdr_saved(move |record| {
+            let saved = saved.clone();
+            async move {
+                *saved.lock().unwrap() = Some(record);
+            }
+        })
+        .build()
+        .await
+}
+
+/// The first target rings out, the second answers: the call connects to the
+/// second target and the CDR lists both attempts.
+#[tokio::test]
+async fn test_dial_sequence_moves_on_after_ring_timeout() -> Result<()> {
+    let record_dir = tempfile::tempdir()?;
+    let saved = Arc::new(Mutex::new(None::<CallRecord>));
+    let app_state = build_app_state(record_dir.path(), saved.clone()).await?;
+
+    let mobile = UdpSocket::bind("127.0.0.1:0").await?;
+    let office = UdpSocket::bind("127.0.0.1:0").await?;
+    let mobile_target = format!("sip:mobile@{}", mobile.local_addr()?);
+    let office_target = format!("sip:office@{}", office.local_addr()?);
+    let mobile_methods = Arc::new(Mutex::new(Vec::new()));
+    let office_methods = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_trunk(
+        mobile,
+        false,
+        mobile_methods.clone(),
+        Default::default(),
+    ));
+    tokio::spawn(run_trunk(
+        office,
+        true,
+        office_methods.clone(),
+        Default::default(),
+    ));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-dial-sequence".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                dial_sequence: Some(vec![mobile_target.clone(), office_target.clone()]),
+                ring_timeout: Some(1),
+                ..Default::default()
+            },
+        })?;
+
+        let answered = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    SessionEvent::Answer { .. } => return true,
+                    SessionEvent::Hangup { .. } | SessionEvent::Reject { .. } => return false,
+                    _ => {}
+                }
+            }
+            false
+        })
+        .await?;
+        assert!(answered, "call should connect to the second target");
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+        for _ in 0..200 {
+            if saved.lock().unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    }
+
+    assert!(
+        mobile_methods
+            .lock()
+            .unwrap()
+            .contains(&"CANCEL".to_string())
+    );
+    assert!(office_methods.lock().unwrap().contains(&"ACK".to_string()));
+
+    let record = saved.lock().unwrap().take().expect("cdr should be saved");
+    assert_eq!(record.callee, office_target);
+    let attempts: Vec<_> = record
+        .hangup_messages
+        .iter()
+        .map(|m| (m.target.clone().unwrap_or_default(), m.code))
+        .collect();
+    assert_eq!(attempts, vec![(mobile_target, 408), (office_target, 200)]);
+    Ok(())
+}
+
+/// Both targets are rung at once: the desk phone answers, the ringing mobile
+/// gets CANCEL and the call is bridged to the desk phone.
+#[tokio::test]
+async fn test_dial_fork_bridges_first_answer() -> Result<()> {
+    let record_dir = tempfile::tempdir()?;
+    let saved = Arc::new(Mutex::new(None::<CallRecord>));
+    let app_state = build_app_state(record_dir.path(), saved.clone()).await?;
+
+    let mobile = UdpSocket::bind("127.0.0.1:0").await?;
+    let desk = UdpSocket::bind("127.0.0.1:0").await?;
+    let mobile_target = format!("sip:mobile@{}", mobile.local_addr()?);
+    let desk_target = format!("sip:desk@{}", desk.local_addr()?);
+    let mobile_methods = Arc::new(Mutex::new(Vec::new()));
+    let desk_methods = Arc::new(Mutex::new(Vec::new()));
+    let call_ids = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_trunk(
+        mobile,
+        false,
+        mobile_methods.clone(),
+        call_ids.clone(),
+    ));
+    tokio::spawn(run_trunk(
+        desk,
+        true,
+        desk_methods.clone(),
+        call_ids.clone(),
+    ));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-dial-fork".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                dial_fork: Some(vec![mobile_target.clone(), desk_target.clone()]),
+                ring_timeout: Some(10),
+                ..Default::default()
+            },
+        })?;
+
+        let answered = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    SessionEvent::Answer { .. } => return true,
+                    SessionEvent::Hangup { .. } | SessionEvent::Reject { .. } => return false,
+                    _ => {}
+                }
+            }
+            false
+        })
+        .await?;
+        assert!(answered, "call should be bridged to the answering target");
+
+        // The losing leg must not end the bridged call
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(
+            !handler.is_finished(),
+            "cancelling the other leg should keep the call up"
+        );
+        assert!(!desk_methods.lock().unwrap().contains(&"BYE".to_string()));
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+        for _ in 0..200 {
+            if saved.lock().unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    }
+
+    assert!(
+        mobile_methods
+            .lock()
+            .unwrap()
+            .contains(&"CANCEL".to_string())
+    );
+    assert!(desk_methods.lock().unwrap().contains(&"ACK".to_string()));
+    // Each forked INVITE is a call of its own
+    let call_ids = call_ids.lock().unwrap().clone();
+    assert_eq!(call_ids.len(), 2, "{:?}", call_ids);
+    assert_ne!(call_ids[0], call_ids[1]);
+
+    let record = saved.lock().unwrap().take().expect("cdr should be saved");
+    assert_eq!(record.callee, desk_target);
+    let legs: Vec<_> = record
+        .hangup_messages
+        .iter()
+        .map(|m| (m.target.clone().unwrap_or_default(), m.code))
+        .collect();
+    assert_eq!(legs, vec![(mobile_target, 487), (desk_target, 200)]);
+    Ok(())
+}
+
+/// Two targets answer at the same time: exactly one is bridged and the other
+/// is released, either cancelled or hung up with BYE.
+#[tokio::test]
+async fn test_dial_fork_simultaneous_answers() -> Result<()> {
+    let record_dir = tempfile::tempdir()?;
+    let saved = Arc::new(Mutex::new(None::<CallRecord>));
+    let app_state = build_app_state(record_dir.path(), saved.clone()).await?;
+
+    let first = UdpSocket::bind("127.0.0.1:0").await?;
+    let second = UdpSocket::bind("127.0.0.1:0").await?;
+    let first_target = format!("sip:first@{}", first.local_addr()?);
+    let second_target = format!("sip:second@{}", second.local_addr()?);
+    let first_methods = Arc::new(Mutex::new(Vec::new()));
+    let second_methods = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_trunk(
+        first,
+        true,
+        first_methods.clone(),
+        Default::default(),
+    ));
+    tokio::spawn(run_trunk(
+        second,
+        true,
+        second_methods.clone(),
+        Default::default(),
+    ));
+
+    let released = |methods: &Arc<Mutex<Vec<String>>>| {
+        let methods = methods.lock().unwrap();
+        methods.iter().any(|m| m == "BYE" || m == "CANCEL")
+    };
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-dial-fork-race".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                dial_fork: Some(vec![first_target.clone(), second_target.clone()]),
+                ..Default::default()
+            },
+        })?;
+
+        let mut answers = 0;
+        let _ = tokio::time::timeout(Duration::from_secs(3), async {
+            while let Some(event) = event_rx.recv().await {
+                if let SessionEvent::Answer { .. } = event {
+                    answers += 1;
+                }
+            }
+        })
+        .await;
+        assert_eq!(answers, 1, "only one leg should be bridged");
+        assert!(
+            released(&first_methods) != released(&second_methods),
+            "exactly one leg should be released before hangup"
+        );
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+        for _ in 0..200 {
+            if saved.lock().unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    }
+
+    let record = saved.lock().unwrap().take().expect("cdr should be saved");
+    let winners: Vec<_> = record
+        .hangup_messages
+        .iter()
+        .filter(|m| m.code == 200 && m.reason.is_none())
+        .map(|m| m.target.clone().unwrap_or_default())
+        .collect();
+    assert_eq!(winners, vec![record.callee.clone()]);
+    Ok(())
+}
+
+/// Dial `target` with `invite_retry`, returning whether the call was answered,
+/// and the INVITEs the target got and the saved CDR once it ended
+async fn dial_with_retry(
+    session_id: &str,
+    target: &str,
+    invite_retry: InviteRetryOption,
+    methods: &Arc<Mutex<Vec<String>>>,
+) -> Result<(bool, usize, CallRecord)> {
+    let record_dir = tempfile::tempdir()?;
+    let saved = Arc::new(Mutex::new(None::<CallRecord>));
+    let app_state = build_app_state(record_dir.path(), saved.clone()).await?;
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            session_id.to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                callee: Some(target.to_string()),
+                invite_retry: Some(invite_retry),
+                ..Default::default()
+            },
+        })?;
+
+        let answered = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    SessionEvent::Answer { .. } => return true,
+                    SessionEvent::Hangup { .. } | SessionEvent::Reject { .. } => return false,
+                    _ => {}
+                }
+            }
+            false
+        })
+        .await?;
+
+        command_tx
+            .send(Command::Hangup {
+                reason: None,
+                initiator: None,
+                headers: None,
+            })
+            .ok();
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+        for _ in 0..200 {
+            if saved.lock().unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok::<bool, anyhow::Error>(answered)
+    };
+
+    let answered = tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    };
+    let invites = methods
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|m| *m == "INVITE")
+        .count();
+    let record = saved.lock().unwrap().take().expect("cdr should be saved");
+    Ok((answered, invites, record))
+}
+
+/// A 503 is retried and the second INVITE connects the call; the CDR lists
+/// both attempts
+#[tokio::test]
+async fn test_invite_retried_after_503() -> Result<()> {
+    let trunk = UdpSocket::bind("127.0.0.1:0").await?;
+    let target = format!("sip:trunk@{}", trunk.local_addr()?);
+    let methods = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_flaky_trunk(
+        trunk,
+        1,
+        "503 Service Unavailable",
+        methods.clone(),
+    ));
+
+    let (answered, invites, record) = dial_with_retry(
+        "test-invite-retry",
+        &target,
+        InviteRetryOption {
+            delay_ms: Some(50),
+            ..Default::default()
+        },
+        &methods,
+    )
+    .await?;
+    assert!(answered, "call should connect after the retry");
+    assert_eq!(invites, 2);
+    let attempts: Vec<_> = record
+        .hangup_messages
+        .iter()
+        .map(|m| (m.target.clone().unwrap_or_default(), m.code))
+        .collect();
+    assert_eq!(attempts, vec![(target.clone(), 503), (target, 200)]);
+    Ok(())
+}
+
+/// A 4xx other than 408/480 is never retried, even when listed
+#[tokio::test]
+async fn test_invite_not_retried_on_busy() -> Result<()> {
+    let trunk = UdpSocket::bind("127.0.0.1:0").await?;
+    let target = format!("sip:trunk@{}", trunk.local_addr()?);
+    let methods = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_flaky_trunk(trunk, 1, "486 Busy Here", methods.clone()));
+
+    let (answered, invites, record) = dial_with_retry(
+        "test-invite-no-retry",
+        &target,
+        InviteRetryOption {
+            codes: Some(vec![486, 503]),
+            delay_ms: Some(50),
+            ..Default::default()
+        },
+        &methods,
+    )
+    .await?;
+    assert!(!answered);
+    assert_eq!(invites, 1);
+    assert_eq!(record.status_code, 486);
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use active_call::call::Command;
+use active_call::event::SessionEvent;
+use active_call::playbook::{
+    ChatMessage, DialogueHandler, DialogueTestHarness, InterruptionConfig, LlmConfig,
+    handler::{LlmHandler, LlmProvider, LlmStreamEvent, RagRetriever},
+    harness::{asr_final, dtmf},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Answers every recognized phrase by saying it back, and hangs up on `#`
+struct EchoHandler {
+    heard: Vec<String>,
+}
+
+#[async_trait]
+impl DialogueHandler for EchoHandler {
+    async fn on_start(&mut self) -> Result<Vec<Command>> {
+        Ok(vec![tts("ready")])
+    }
+
+    async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
+        match event {
+            SessionEvent::AsrFinal { text, .. } => {
+                self.heard.push(text.clone());
+                Ok(vec![tts(text)])
+            }
+            SessionEvent::Dtmf { digit, .. } if digit == "#" => Ok(vec![Command::Hangup {
+                reason: Some("done".to_string()),
+                initiator: None,
+                headers: None,
+            }]),
+            _ => Ok(vec![]),
+        }
+    }
+
+    async fn get_history(&self) -> Vec<ChatMessage> {
+        vec![]
+    }
+
+    async fn summarize(&mut self, _prompt: &str) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+fn tts(text: &str) -> Command {
+    Command::Tts {
+        text: text.to_string(),
+        speaker: None,
+        play_id: None,
+        auto_hangup: None,
+        streaming: None,
+        end_of_stream: None,
+        option: None,
+        wait_input_timeout: None,
+        base64: None,
+        cache_key: None,
+    }
+}
+
+fn spoken(commands: &[Command]) -> Vec<String> {
+    commands
+        .iter()
+        .filter_map(|command| match command {
+            Command::Tts { text, .. } if !text.is_empty() => Some(text.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The harness runs `on_start`, then every scripted event, and returns the
+/// commands in the order they were produced
+#[tokio::test]
+async fn test_harness_replays_events_in_order() -> Result<()> {
+    let mut harness = DialogueTestHarness::new(EchoHandler { heard: vec![] });
+    let commands = harness
+        .replay([asr_final("hello"), dtmf("1"), asr_final("bye"), dtmf("#")])
+        .await?;
+
+    assert_eq!(commands.len(), 4);
+    assert_eq!(spoken(&commands), vec!["ready", "hello", "bye"]);
+    assert!(matches!(commands.last(), Some(Command::Hangup { .. })));
+    assert_eq!(harness.commands().len(), 4);
+
+    let handler = harness.into_inner();
+    assert_eq!(handler.heard, vec!["hello", "bye"]);
+    Ok(())
+}
+
+struct ScriptedProvider;
+
+#[async_trait]
+impl LlmProvider for ScriptedProvider {
+    async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+        Ok("Your order has shipped.".to_string())
+    }
+
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<std::pin::Pin<Box<dyn futures::Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content("Your order has shipped.".to_string()));
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+struct NoopRag;
+
+#[async_trait]
+impl RagRetriever for NoopRag {
+    async fn retrieve(&self, _query: &str) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+/// The built-in LLM handler can be exercised the same way
+#[tokio::test]
+async fn test_harness_drives_llm_handler() -> Result<()> {
+    let handler = LlmHandler::with_provider(
+        LlmConfig {
+            greeting: Some("Hi, how can I help?".to_string()),
+            ..Default::default()
+        },
+        Arc::new(ScriptedProvider),
+        Arc::new(NoopRag),
+        InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let mut harness = DialogueTestHarness::new(handler);
+
+    let greeting = harness.start().await?;
+    assert_eq!(spoken(&greeting), vec!["Hi, how can I help?"]);
+
+    let reply = harness.send(asr_final("Where is my order?")).await?;
+    assert_eq!(spoken(&reply).concat(), "Your order has shipped.");
+
+    let history = harness.handler().get_history().await;
+    assert!(
+        history
+            .iter()
+            .any(|m| m.role == "user" && m.content == "Where is my order?")
+    );
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use active_call::app::{AppState, AppStateBuilder};
+use active_call::config::Config;
+use active_call::handler::call_router;
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+
+async fn start_server() -> Result<(AppState, String)> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let router = call_router().with_state(app_state.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.ok();
+    });
+    Ok((app_state, format!("ws://{}", addr)))
+}
+
+/// Run a call that is invited and hung up, returning the names of the events
+/// the client received
+async fn run_call(base_url: &str, query: &str) -> Result<Vec<String>> {
+    let (ws, _) = connect_async(format!("{}/call?{}", base_url, query)).await?;
+    let (mut tx, mut rx) = ws.split();
+    tx.send(Message::Text(
+        serde_json::json!({"command": "invite", "option": {}})
+            .to_string()
+            .into(),
+    ))
+    .await?;
+    // 20ms of 16k PCM, so the call produces audio related events
+    for _ in 0..25 {
+        tx.send(Message::Binary(vec![0u8; 640].into())).await?;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    tx.send(Message::Text(
+        serde_json::json!({"command": "hangup"}).to_string().into(),
+    ))
+    .await?;
+
+    let mut names = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = rx.next().await {
+            if let Message::Text(text) = message {
+                let event: serde_json::Value = serde_json::from_str(text.as_str())?;
+                let name = event["event"].as_str().unwrap_or_default().to_string();
+                let done = name == "hangup";
+                names.push(name);
+                if done {
+                    break;
+                }
+            }
+        }
+        anyhow::Ok(())
+    })
+    .await;
+    Ok(names)
+}
+
+/// Clients subscribed to a set of events receive only those
+#[tokio::test]
+async fn test_call_events_filtered_by_subscription() -> Result<()> {
+    let (_app_state, base_url) = start_server().await?;
+
+    let all = run_call(&base_url, "id=unfiltered&dump=false").await?;
+    assert!(all.iter().any(|name| name == "answer"));
+    assert_eq!(all.last().map(String::as_str), Some("hangup"));
+
+    let filtered = run_call(&base_url, "id=filtered&dump=false&events=asrfinal,hangup").await?;
+    assert_eq!(filtered.last().map(String::as_str), Some("hangup"));
+    for name in &filtered {
+        assert!(
+            name == "asrFinal" || name == "hangup",
+            "unsubscribed event {} delivered",
+            name
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::{Config, EventWebhookConfig};
+use active_call::event::SessionEvent;
+use active_call::event_webhook::EventWebhook;
+use anyhow::Result;
+use axum::Router;
+use axum::http::StatusCode;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Start a server that fails the first `failures` requests with a 503 and
+/// accepts the rest, recording every request body
+async fn start_webhook_server(failures: usize) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    let app = Router::new().fallback(move |body: String| {
+        let received = received_clone.clone();
+        async move {
+            let mut received = received.lock().unwrap();
+            received.push(serde_json::from_str(&body).unwrap_or_default());
+            if received.len() <= failures {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            }
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    (format!("http://{}", addr), received)
+}
+
+fn webhook_config(url: String, dead_letter_path: &std::path::Path) -> EventWebhookConfig {
+    EventWebhookConfig {
+        url,
+        max_retries: Some(3),
+        retry_delay_ms: Some(10),
+        dead_letter_path: Some(dead_letter_path.to_string_lossy().to_string()),
+        ..Default::default()
+    }
+}
+
+/// Poll `check` until it holds or a few seconds have passed
+async fn wait_for(check: impl Fn() -> bool) -> bool {
+    for _ in 0..100 {
+        if check() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+/// An event whose first deliveries fail is retried until it is accepted
+#[tokio::test]
+async fn test_event_webhook_retries_until_delivered() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let dead_letter = dir.path().join("dead_letter.jsonl");
+    let (url, received) = start_webhook_server(2).await;
+    let webhook = EventWebhook::new(
+        webhook_config(url, &dead_letter),
+        false,
+        CancellationToken::new(),
+    );
+
+    webhook.send(
+        "call-1",
+        "hangup",
+        json!({"caller": "sip:alice@example.com"}),
+    );
+    webhook.close("call-1");
+
+    assert!(wait_for(|| received.lock().unwrap().len() >= 3).await);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 3, "two failures, then delivered");
+    for payload in &received {
+        assert_eq!(payload["event"], "hangup");
+        assert_eq!(payload["call_id"], "call-1");
+        assert_eq!(payload["data"]["caller"], "sip:alice@example.com");
+    }
+    assert!(!dead_letter.exists());
+    Ok(())
+}
+
+/// An event still failing after the last retry lands in the dead letter file
+#[tokio::test]
+async fn test_event_webhook_dead_letters_after_retries() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let dead_letter = dir.path().join("webhooks").join("dead_letter.jsonl");
+    let (url, received) = start_webhook_server(usize::MAX).await;
+    let webhook = EventWebhook::new(
+        webhook_config(url, &dead_letter),
+        false,
+        CancellationToken::new(),
+    );
+
+    webhook.send("call-2", "hangup", json!({}));
+    webhook.close("call-2");
+
+    assert!(wait_for(|| dead_letter.exists()).await, "no dead letter");
+    assert_eq!(
+        received.lock().unwrap().len(),
+        4,
+        "first attempt and 3 retries"
+    );
+    let content = std::fs::read_to_string(&dead_letter)?;
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["attempts"], 4);
+    assert_eq!(lines[0]["error"], "status 503");
+    assert_eq!(lines[0]["payload"]["event"], "hangup");
+    assert_eq!(lines[0]["payload"]["call_id"], "call-2");
+    Ok(())
+}
+
+/// Events still being retried at shutdown are saved and delivered after the
+/// next start
+#[tokio::test]
+async fn test_event_webhook_resumes_pending_after_shutdown() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let dead_letter = dir.path().join("dead_letter.jsonl");
+    let pending = dir.path().join("pending.jsonl");
+    let (url, received) = start_webhook_server(usize::MAX).await;
+    let mut config = webhook_config(url, &dead_letter);
+    config.retry_delay_ms = Some(60_000);
+    config.pending_path = Some(pending.to_string_lossy().to_string());
+    let webhook = EventWebhook::new(config.clone(), false, CancellationToken::new());
+
+    webhook.send("call-3", "created", json!({}));
+    webhook.send("call-3", "hangup", json!({}));
+    webhook.close("call-3");
+    assert!(wait_for(|| !received.lock().unwrap().is_empty()).await);
+    webhook.shutdown().await;
+
+    let content = std::fs::read_to_string(&pending)?;
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let events: Vec<&str> = lines
+        .iter()
+        .map(|payload| payload["event"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(events, vec!["created", "hangup"]);
+    assert!(!dead_letter.exists());
+
+    let (url, received) = start_webhook_server(0).await;
+    config.url = url;
+    let webhook = EventWebhook::new(config, false, CancellationToken::new());
+    assert_eq!(webhook.resume_pending().await?, 2);
+    assert!(!pending.exists());
+    assert!(wait_for(|| received.lock().unwrap().len() >= 2).await);
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received[0]["event"], "created");
+    assert_eq!(received[1]["event"], "hangup");
+    assert!(
+        received
+            .iter()
+            .all(|payload| payload["call_id"] == "call-3")
+    );
+    Ok(())
+}
+
+/// A call reports its lifecycle events in order
+#[tokio::test]
+async fn test_call_lifecycle_events_in_order() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (url, received) = start_webhook_server(0).await;
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.event_webhook = Some(webhook_config(url, &dir.path().join("dead_letter.jsonl")));
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        "test-event-webhook".to_string(),
+        app_state,
+        cancel_token,
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+    command_tx.send(Command::Invite {
+        option: CallOption::default(),
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = event_rx.recv().await {
+            if matches!(event, SessionEvent::Answer { .. }) {
+                break;
+            }
+        }
+    })
+    .await?;
+    command_tx.send(Command::Hangup {
+        reason: None,
+        initiator: None,
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+    assert!(wait_for(|| received.lock().unwrap().len() >= 3).await);
+    let received = received.lock().unwrap().clone();
+    let events: Vec<&str> = received
+        .iter()
+        .map(|payload| payload["event"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(events, vec!["created", "answered", "hangup"]);
+    assert!(
+        received
+            .iter()
+            .all(|payload| payload["call_id"] == "test-event-webhook")
+    );
+    Ok(())
+}
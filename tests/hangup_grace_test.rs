@@ -0,0 +1,94 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::ActiveCallType;
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::media::engine::StreamEngine;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Events emitted shortly after the call is torn down must still reach the client
+/// when `hangup_grace_period` is configured.
+#[tokio::test]
+async fn test_events_delivered_during_hangup_grace() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.hangup_grace_period = Some("500ms".to_string());
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .build()
+        .await?;
+
+    let session_id = "test-hangup-grace".to_string();
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (_command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        session_id.clone(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    let call = {
+        let mut found = None;
+        for _ in 0..100 {
+            found = app_state
+                .active_calls
+                .lock()
+                .unwrap()
+                .get(&session_id)
+                .cloned();
+            if found.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        found.expect("call should be registered")
+    };
+
+    // Simulate a component that reports its final metrics after hangup
+    let late_sender = call.event_sender.clone();
+    let call_token = call.cancel_token.clone();
+    tokio::spawn(async move {
+        call_token.cancelled().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        late_sender
+            .send(SessionEvent::Metrics {
+                timestamp: 0,
+                key: "final".to_string(),
+                duration: 0,
+                data: serde_json::json!({}),
+            })
+            .ok();
+    });
+    drop(call);
+
+    cancel_token.cancel();
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+
+    let mut got_final = false;
+    while let Ok(event) = event_rx.try_recv() {
+        if let SessionEvent::Metrics { key, .. } = event {
+            if key == "final" {
+                got_final = true;
+            }
+        }
+    }
+    assert!(
+        got_final,
+        "late event should be forwarded within the grace period"
+    );
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use active_call::app::{AppState, AppStateBuilder};
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::{EventSender, SessionEvent};
+use active_call::media::engine::StreamEngine;
+use active_call::media::{Sample, SourcePacket, TrackId};
+use active_call::transcription::{TranscriptionClient, TranscriptionOption, TranscriptionType};
+use active_call::{CallOption, HoldAsrMode};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Counts the chunks it is fed
+struct CountingAsrClient {
+    chunks: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl TranscriptionClient for CountingAsrClient {
+    fn send_audio(&self, _samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        self.chunks.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Chunks fed to any recognizer, and the number of recognizers created
+#[derive(Clone, Default)]
+struct Counters {
+    chunks: Arc<AtomicUsize>,
+    created: Arc<AtomicUsize>,
+}
+
+fn counting_engine(counters: &Counters) -> StreamEngine {
+    let mut engine = StreamEngine::new();
+    let counters = counters.clone();
+    engine.register_asr(
+        TranscriptionType::Other("counting".to_string()),
+        Box::new(
+            move |_track_id: TrackId,
+                  _token: CancellationToken,
+                  _option: TranscriptionOption,
+                  _event_sender: EventSender| {
+                counters.created.fetch_add(1, Ordering::Relaxed);
+                let chunks = counters.chunks.clone();
+                Box::pin(async move {
+                    Ok(Box::new(CountingAsrClient { chunks }) as Box<dyn TranscriptionClient>)
+                })
+            },
+        ),
+    );
+    engine
+}
+
+async fn wait_for_chunks(counters: &Counters, above: usize) -> Result<usize> {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let chunks = counters.chunks.load(Ordering::Relaxed);
+            if chunks > above {
+                return chunks;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .map_err(Into::into)
+}
+
+fn set_hold(app_state: &AppState, session_id: &str, on_hold: bool) {
+    let call = app_state
+        .active_calls
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .cloned()
+        .expect("call should be active");
+    call.event_sender
+        .send(SessionEvent::Hold {
+            track_id: session_id.to_string(),
+            timestamp: active_call::media::get_timestamp(),
+            on_hold,
+        })
+        .ok();
+}
+
+/// Hold and resume a websocket call fed with audio, returning the chunks
+/// seen by ASR before, during and after the hold
+async fn run_hold(mode: HoldAsrMode, counters: &Counters) -> Result<(usize, usize, usize)> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(counting_engine(counters)))
+        .build()
+        .await?;
+
+    let session_id = format!("test-hold-asr-{:?}", mode);
+    let cancel_token = CancellationToken::new();
+    let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, _event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        session_id.clone(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            asr: Some(TranscriptionOption {
+                provider: Some(TranscriptionType::Other("counting".to_string())),
+                ..Default::default()
+            }),
+            hold_asr: Some(mode),
+            ..Default::default()
+        },
+    })?;
+
+    // 20ms of 16k PCM per chunk
+    let pump_token = cancel_token.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(20));
+        while !pump_token.is_cancelled() {
+            interval.tick().await;
+            if audio_tx.send(Bytes::from(vec![0u8; 640])).is_err() {
+                break;
+            }
+        }
+    });
+
+    wait_for_chunks(counters, 5).await?;
+    set_hold(&app_state, &session_id, true);
+    // Let the hold take effect before counting
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let before_hold = counters.chunks.load(Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let during_hold = counters.chunks.load(Ordering::Relaxed) - before_hold;
+
+    set_hold(&app_state, &session_id, false);
+    let resumed = counters.chunks.load(Ordering::Relaxed);
+    let after_hold = wait_for_chunks(counters, resumed + 5).await? - resumed;
+
+    command_tx.send(Command::Hangup {
+        reason: None,
+        initiator: None,
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    Ok((before_hold, during_hold, after_hold))
+}
+
+/// The default mode keeps feeding ASR with silence during hold
+#[tokio::test]
+async fn test_hold_keeps_feeding_asr_by_default() -> Result<()> {
+    let counters = Counters::default();
+    let (_, during_hold, _) = run_hold(HoldAsrMode::Silence, &counters).await?;
+    assert!(during_hold > 0, "asr should keep receiving frames on hold");
+    assert_eq!(counters.created.load(Ordering::Relaxed), 1);
+    Ok(())
+}
+
+/// `pause_asr` stops feeding the same recognizer during hold and resumes it after
+#[tokio::test]
+async fn test_pause_asr_stops_frames_during_hold() -> Result<()> {
+    let counters = Counters::default();
+    let (before_hold, during_hold, after_hold) = run_hold(HoldAsrMode::PauseAsr, &counters).await?;
+    assert!(before_hold > 0);
+    assert_eq!(during_hold, 0, "asr should receive no frames on hold");
+    assert!(after_hold > 0, "asr should receive frames after resume");
+    assert_eq!(counters.created.load(Ordering::Relaxed), 1);
+    Ok(())
+}
+
+/// `teardown_asr` drops the recognizer on hold and starts a new one on resume
+#[tokio::test]
+async fn test_teardown_asr_restarts_recognizer_after_hold() -> Result<()> {
+    let counters = Counters::default();
+    let (_, during_hold, after_hold) = run_hold(HoldAsrMode::TeardownAsr, &counters).await?;
+    assert_eq!(during_hold, 0, "asr should receive no frames on hold");
+    assert!(after_hold > 0, "asr should receive frames after resume");
+    assert_eq!(counters.created.load(Ordering::Relaxed), 2);
+    Ok(())
+}
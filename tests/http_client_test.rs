@@ -0,0 +1,92 @@
+use active_call::config::HttpClientConfig;
+use active_call::net_tool::{
+    build_http_client, configure_http_client, http_client, with_request_timeout,
+};
+use anyhow::Result;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// With a proxy configured, requests are sent to it in absolute form instead
+/// of connecting to the target host.
+#[tokio::test]
+async fn test_requests_go_through_proxy() -> Result<()> {
+    let proxy = TcpListener::bind("127.0.0.1:0").await?;
+    let proxy_addr = proxy.local_addr()?;
+    let received = tokio::spawn(async move {
+        let (mut socket, _) = proxy.accept().await?;
+        let mut buf = vec![0u8; 4096];
+        let n = socket.read(&mut buf).await?;
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+            .await?;
+        Ok::<_, anyhow::Error>(String::from_utf8_lossy(&buf[..n]).to_string())
+    });
+
+    let client = build_http_client(&HttpClientConfig {
+        proxy: Some(format!("http://{}", proxy_addr)),
+        ..Default::default()
+    })?;
+    let response = client
+        .post("http://cdr.example.invalid/upload")
+        .body("{}")
+        .send()
+        .await?;
+    assert_eq!(response.text().await?, "ok");
+
+    let request = received.await??;
+    assert!(
+        request.starts_with("POST http://cdr.example.invalid/upload HTTP/1.1\r\n"),
+        "proxy got: {}",
+        request
+    );
+    Ok(())
+}
+
+/// A one-shot request to a server that never answers fails after the
+/// configured timeout.
+#[tokio::test]
+async fn test_request_timeout_is_honored() -> Result<()> {
+    let server = TcpListener::bind("127.0.0.1:0").await?;
+    let server_addr = server.local_addr()?;
+    tokio::spawn(async move {
+        let (_socket, _) = server.accept().await?;
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        Ok::<_, anyhow::Error>(())
+    });
+
+    configure_http_client(&HttpClientConfig {
+        timeout_secs: Some(1),
+        ..Default::default()
+    })?;
+    let start = Instant::now();
+    let result = with_request_timeout(http_client().get(format!("http://{}/slow", server_addr)))
+        .send()
+        .await;
+    let elapsed = start.elapsed();
+
+    let error = result.expect_err("request should time out");
+    assert!(error.is_timeout(), "unexpected error: {}", error);
+    assert!(
+        elapsed >= Duration::from_millis(900) && elapsed < Duration::from_secs(5),
+        "timed out after {:?}",
+        elapsed
+    );
+    Ok(())
+}
+
+#[test]
+fn test_http_client_config() {
+    let config: HttpClientConfig = toml::from_str(
+        r#"
+proxy = "http://proxy.corp:3128"
+no_proxy = "localhost,.internal"
+timeout_secs = 30
+http2 = false
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.proxy.as_deref(), Some("http://proxy.corp:3128"));
+    assert_eq!(config.timeout_secs, Some(30));
+    assert!(build_http_client(&config).is_ok());
+}
@@ -0,0 +1,113 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::{Config, MissingPlaybookAction};
+use active_call::event::SessionEvent;
+use active_call::media::engine::StreamEngine;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+fn write_prompt(path: &std::path::Path) {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for i in 0..3200 {
+        writer.write_sample(((i % 32) as i16 - 16) * 200).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// A call whose playbook is missing plays the configured prompt after answer
+/// and then hangs up instead of sitting in silence.
+#[tokio::test]
+async fn test_missing_playbook_plays_prompt_and_hangs_up() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let prompt = dir.path().join("unavailable.wav");
+    write_prompt(&prompt);
+
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.missing_playbook_action = Some(MissingPlaybookAction::Prompt {
+        prompt: prompt.to_string_lossy().to_string(),
+    });
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .build()
+        .await?;
+
+    let session_id = "test-missing-playbook".to_string();
+    app_state
+        .pending_playbooks
+        .lock()
+        .await
+        .insert(session_id.clone(), "does-not-exist.md".to_string());
+
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        session_id.clone(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            codec: Some("pcmu".to_string()),
+            ..Default::default()
+        },
+    })?;
+
+    // The call must end on its own once the prompt finished
+    tokio::time::timeout(Duration::from_secs(10), handler).await??;
+
+    let mut got_error = false;
+    let mut prompt_started = false;
+    let mut got_hangup = false;
+    while let Ok(event) = event_rx.try_recv() {
+        match event {
+            SessionEvent::Error { sender, .. } if sender == "playbook" => got_error = true,
+            SessionEvent::TrackStart { .. } => prompt_started = true,
+            SessionEvent::Hangup { .. } => got_hangup = true,
+            _ => {}
+        }
+    }
+    assert!(got_error, "client should be told the playbook failed");
+    assert!(prompt_started, "fallback prompt should play");
+    assert!(got_hangup, "call should hang up after the prompt");
+    Ok(())
+}
+
+#[test]
+fn test_missing_playbook_action_config() {
+    let action: MissingPlaybookAction = toml::from_str(
+        r#"
+type = "fallback"
+playbook = "default.md"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        action,
+        MissingPlaybookAction::Fallback {
+            playbook: Some("default.md".to_string())
+        }
+    );
+}
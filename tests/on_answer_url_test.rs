@@ -0,0 +1,131 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use anyhow::Result;
+use axum::Router;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Start a server recording every request body, returning its base URL
+async fn start_callback_server() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_clone = received.clone();
+    let app = Router::new().fallback(move |body: String| {
+        let received = received_clone.clone();
+        async move {
+            received
+                .lock()
+                .unwrap()
+                .push(serde_json::from_str(&body).unwrap_or_default());
+            "ok"
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    (format!("http://{}", addr), received)
+}
+
+/// Answer a websocket call, wait a moment for the callback, then hang up
+async fn answer_call(config: Config, session_id: &str, option: CallOption) -> Result<()> {
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        session_id.to_string(),
+        app_state,
+        cancel_token,
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+    command_tx.send(Command::Invite { option })?;
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = event_rx.recv().await {
+            if matches!(event, SessionEvent::Answer { .. }) {
+                break;
+            }
+        }
+    })
+    .await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    command_tx.send(Command::Hangup {
+        reason: None,
+        initiator: None,
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    Ok(())
+}
+
+/// Answering POSTs the caller details once to the global `on_answer_url`
+#[tokio::test]
+async fn test_answer_posts_to_on_answer_url_once() -> Result<()> {
+    let (url, received) = start_callback_server().await;
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.on_answer_url = Some(format!("{}/screen-pop", url));
+
+    answer_call(
+        config,
+        "test-on-answer-url",
+        CallOption {
+            caller: Some("sip:alice@example.com".to_string()),
+            callee: Some("sip:support@example.com".to_string()),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1, "received: {:?}", received);
+    let payload = &received[0];
+    assert_eq!(payload["call_id"], "test-on-answer-url");
+    assert_eq!(payload["caller"], "sip:alice@example.com");
+    assert_eq!(payload["callee"], "sip:support@example.com");
+    assert!(payload["answer_time"].is_string());
+    assert!(payload["extras"].is_object());
+    Ok(())
+}
+
+/// A call's own `onAnswerUrl` wins over the global one
+#[tokio::test]
+async fn test_call_on_answer_url_overrides_global() -> Result<()> {
+    let (global_url, global_received) = start_callback_server().await;
+    let (call_url, call_received) = start_callback_server().await;
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.on_answer_url = Some(global_url);
+
+    answer_call(
+        config,
+        "test-call-on-answer-url",
+        CallOption {
+            caller: Some("sip:bob@example.com".to_string()),
+            on_answer_url: Some(call_url),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    assert!(global_received.lock().unwrap().is_empty());
+    let call_received = call_received.lock().unwrap();
+    assert_eq!(call_received.len(), 1);
+    assert_eq!(call_received[0]["caller"], "sip:bob@example.com");
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::{CallOption, SipOption};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+/// Build a `486 Busy Here` for `request` echoing the transaction headers
+fn busy(request: &str) -> String {
+    let mut out = "SIP/2.0 486 Busy Here\r\n".to_string();
+    for line in request.lines() {
+        let name = line
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if matches!(name.as_str(), "via" | "from" | "call-id" | "cseq") {
+            out.push_str(line);
+            out.push_str("\r\n");
+        } else if name == "to" {
+            out.push_str(line);
+            out.push_str(";tag=proxy\r\n");
+        }
+    }
+    out.push_str("Content-Length: 0\r\n\r\n");
+    out
+}
+
+/// A proxy that rejects the first INVITE it receives, handing it to the test
+async fn run_proxy(socket: UdpSocket, invite: oneshot::Sender<String>) {
+    let mut invite = Some(invite);
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        if !message.starts_with("INVITE ") {
+            continue;
+        }
+        socket.send_to(busy(&message).as_bytes(), peer).await.ok();
+        if let Some(invite) = invite.take() {
+            invite.send(message).ok();
+        }
+    }
+}
+
+/// Originate a call to `callee` and return the INVITE seen by `proxy`
+async fn invite_through_proxy(
+    config: Config,
+    proxy: UdpSocket,
+    session_id: &str,
+    option: CallOption,
+) -> Result<String> {
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+    let (invite_tx, invite_rx) = oneshot::channel();
+    tokio::spawn(run_proxy(proxy, invite_tx));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            session_id.to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+        command_tx.send(Command::Invite { option })?;
+
+        let invite = tokio::time::timeout(Duration::from_secs(10), invite_rx).await??;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = event_rx.recv().await {
+                if matches!(
+                    event,
+                    SessionEvent::Reject { .. } | SessionEvent::Hangup { .. }
+                ) {
+                    break;
+                }
+            }
+        })
+        .await
+        .ok();
+        cancel_token.cancel();
+        tokio::time::timeout(Duration::from_secs(5), handler)
+            .await
+            .ok();
+        Ok::<String, anyhow::Error>(invite)
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res,
+    }
+}
+
+fn route_headers(message: &str) -> Vec<&str> {
+    message
+        .lines()
+        .filter(|l| l.to_ascii_lowercase().starts_with("route:"))
+        .collect()
+}
+
+/// With a global outbound proxy the INVITE goes to the proxy, keeping the real
+/// target in the Request-URI and routing through the proxy with `lr`
+#[tokio::test]
+async fn test_invite_sent_through_outbound_proxy() -> Result<()> {
+    let proxy = UdpSocket::bind("127.0.0.1:0").await?;
+    let proxy_addr = proxy.local_addr()?;
+
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    config.outbound_proxy = Some(proxy_addr.to_string());
+
+    let invite = invite_through_proxy(
+        config,
+        proxy,
+        "test-outbound-proxy",
+        CallOption {
+            caller: Some("sip:alice@127.0.0.1".to_string()),
+            callee: Some("sip:bob@carrier.example.com".to_string()),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    assert!(
+        invite.starts_with("INVITE sip:bob@carrier.example.com SIP/2.0"),
+        "invite: {}",
+        invite
+    );
+    let routes = route_headers(&invite);
+    assert_eq!(routes.len(), 1, "invite: {}", invite);
+    assert!(
+        routes[0].contains(&format!("sip:{};lr", proxy_addr)),
+        "route: {}",
+        routes[0]
+    );
+    Ok(())
+}
+
+/// A call's own `sip.outbound_proxy` wins over the global one
+#[tokio::test]
+async fn test_call_outbound_proxy_overrides_global() -> Result<()> {
+    let proxy = UdpSocket::bind("127.0.0.1:0").await?;
+    let proxy_addr = proxy.local_addr()?;
+
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    config.outbound_proxy = Some("sip:unused-proxy.example.com".to_string());
+
+    let invite = invite_through_proxy(
+        config,
+        proxy,
+        "test-call-outbound-proxy",
+        CallOption {
+            caller: Some("sip:alice@127.0.0.1".to_string()),
+            callee: Some("sip:bob@carrier.example.com".to_string()),
+            sip: Some(SipOption {
+                outbound_proxy: Some(format!("sip:{}", proxy_addr)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let routes = route_headers(&invite);
+    assert_eq!(routes.len(), 1, "invite: {}", invite);
+    assert!(
+        routes[0].contains(&format!("sip:{};lr", proxy_addr)),
+        "route: {}",
+        routes[0]
+    );
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Local `play` paths outside `play_allowed_roots` are refused with a 403 error
+/// event, while files under an allowed root still play.
+#[tokio::test]
+async fn test_play_rejects_paths_outside_allowed_roots() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.play_allowed_roots = Some(vec!["fixtures".to_string()]);
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let cancel_token = CancellationToken::new();
+    let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        "test-play-path".to_string(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+
+    command_tx.send(Command::Invite {
+        option: CallOption::default(),
+    })?;
+
+    for (url, play_id) in [
+        ("../../etc/passwd", "traversal"),
+        ("fixtures/../Cargo.toml", "outside"),
+        ("/etc/hostname", "absolute"),
+        ("fixtures/sample.wav", "allowed"),
+    ] {
+        command_tx.send(Command::Play {
+            url: url.to_string(),
+            play_id: Some(play_id.to_string()),
+            auto_hangup: None,
+            wait_input_timeout: None,
+        })?;
+    }
+
+    let mut rejected = Vec::new();
+    let allowed_started = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                SessionEvent::Error {
+                    sender,
+                    error,
+                    code,
+                    ..
+                } if sender == "play" => {
+                    assert_eq!(code, Some(403));
+                    rejected.push(error);
+                }
+                SessionEvent::TrackStart { play_id, .. }
+                    if play_id.as_deref() == Some("allowed") =>
+                {
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        false
+    })
+    .await?;
+
+    assert!(allowed_started, "file under an allowed root should play");
+    assert_eq!(rejected.len(), 3, "rejected: {:?}", rejected);
+    assert!(rejected[0].contains("../../etc/passwd"));
+    assert!(rejected[1].contains("fixtures/../Cargo.toml"));
+    assert!(rejected[2].contains("/etc/hostname"));
+
+    cancel_token.cancel();
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    Ok(())
+}
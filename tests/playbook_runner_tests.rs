@@ -1,17 +1,19 @@
 use active_call::app::AppStateBuilder;
-use active_call::call::{ActiveCall, ActiveCallType, Command};
+use active_call::call::{ActiveCall, ActiveCallType, Command, CommandReceiver};
+use active_call::callrecord::CallRecordHangupReason;
 use active_call::config::Config;
 use active_call::event::SessionEvent;
 use active_call::media::engine::StreamEngine;
 use active_call::media::track::TrackConfig;
 use active_call::playbook::{
-    ChatMessage, LlmConfig, PlaybookConfig, PlaybookRunner,
-    handler::{LlmHandler, LlmProvider, LlmStreamEvent, RagRetriever},
+    ChatMessage, GreetingGateConfig, LlmConfig, PlaybookConfig, PlaybookRunner,
+    PostCallSummaryConfig, PostHookConfig,
+    handler::{LlmHandler, LlmProvider, LlmStreamEvent, LlmUsage, RagRetriever},
 };
 use anyhow::Result;
 use async_trait::async_trait;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio_util::sync::CancellationToken;
 
 struct MockLlmProvider {
@@ -153,6 +155,7 @@ async fn test_playbook_run_flow() -> Result<()> {
         is_filler: None,
         confidence: None,
         task_id: None,
+        raw_text: None,
     };
 
     // Send event
@@ -533,3 +536,569 @@ async fn test_playbook_media_wait_flow() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_llm_hangup_attributed_to_system() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .build()
+        .await?;
+
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        CancellationToken::new(),
+        "test-llm-hangup".to_string(),
+        app_state.invitation.clone(),
+        app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
+    let receiver = active_call.new_receiver();
+    let serving_call = active_call.clone();
+    tokio::spawn(async move { serving_call.serve(receiver).await });
+
+    // The model names the remote party, the CDR must still blame the agent
+    let provider = Arc::new(MockLlmProvider {
+        response: r#"{"tools": [{"name": "hangup", "reason": "done", "initiator": "callee"}]}"#
+            .to_string(),
+    });
+    let llm_handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRag),
+        active_call::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let runner = PlaybookRunner::with_handler(
+        Box::new(llm_handler),
+        active_call.clone(),
+        PlaybookConfig::default(),
+    );
+    tokio::spawn(async move {
+        runner.run().await;
+    });
+
+    active_call.event_sender.send(SessionEvent::Answer {
+        track_id: "test-llm-hangup".to_string(),
+        timestamp: 0,
+        sdp: "".to_string(),
+        refer: None,
+    })?;
+    active_call.event_sender.send(SessionEvent::AsrFinal {
+        track_id: "test-llm-hangup".to_string(),
+        timestamp: 100,
+        index: 1,
+        start_time: None,
+        end_time: None,
+        text: "That's all, thanks".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    })?;
+
+    let reason = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            if let Some(reason) = active_call.call_state.read().await.hangup_reason.clone() {
+                return reason;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    assert_eq!(reason, CallRecordHangupReason::BySystem);
+    Ok(())
+}
+
+/// Greets on start and records the events it is handed
+struct GreetingHandler {
+    events: Arc<Mutex<Vec<SessionEvent>>>,
+}
+
+#[async_trait]
+impl active_call::playbook::DialogueHandler for GreetingHandler {
+    async fn on_start(&mut self) -> Result<Vec<Command>> {
+        Ok(vec![Command::Tts {
+            text: "Hi, this is the clinic".to_string(),
+            speaker: None,
+            play_id: None,
+            auto_hangup: None,
+            streaming: None,
+            end_of_stream: None,
+            option: None,
+            wait_input_timeout: None,
+            base64: None,
+            cache_key: None,
+        }])
+    }
+    async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(vec![])
+    }
+    async fn get_history(&self) -> Vec<ChatMessage> {
+        vec![]
+    }
+    async fn summarize(&mut self, _prompt: &str) -> Result<String> {
+        Ok("".to_string())
+    }
+}
+
+async fn answered_gated_call(
+    session_id: &str,
+    gate: GreetingGateConfig,
+) -> Result<(
+    Arc<ActiveCall>,
+    CommandReceiver,
+    Arc<Mutex<Vec<SessionEvent>>>,
+)> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .build()
+        .await?;
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        CancellationToken::new(),
+        session_id.to_string(),
+        app_state.invitation.clone(),
+        app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
+    active_call.call_state.write().await.answer_time = Some(chrono::Utc::now());
+    let cmd_rx = active_call.new_receiver().cmd_receiver;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let runner = PlaybookRunner::with_handler(
+        Box::new(GreetingHandler {
+            events: events.clone(),
+        }),
+        active_call.clone(),
+        PlaybookConfig {
+            greeting_gate: Some(gate),
+            ..Default::default()
+        },
+    );
+    tokio::spawn(async move {
+        runner.run().await;
+    });
+    Ok((active_call, cmd_rx, events))
+}
+
+#[tokio::test]
+async fn test_greeting_gate_waits_for_callee_utterance() -> Result<()> {
+    let (active_call, mut cmd_rx, _) = answered_gated_call(
+        "test-greeting-gate",
+        GreetingGateConfig {
+            silence_ms: Some(100),
+            max_wait_ms: Some(5000),
+        },
+    )
+    .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    active_call.event_sender.send(SessionEvent::Speaking {
+        track_id: "test-greeting-gate".to_string(),
+        timestamp: 100,
+        start_time: 100,
+        is_filler: None,
+        confidence: None,
+    })?;
+    // Held while the callee says hello
+    let held = tokio::time::timeout(std::time::Duration::from_millis(300), cmd_rx.recv()).await;
+    assert!(held.is_err(), "greeting must wait for the callee");
+
+    active_call.event_sender.send(SessionEvent::Silence {
+        track_id: "test-greeting-gate".to_string(),
+        timestamp: 600,
+        start_time: 500,
+        duration: 100,
+        samples: None,
+    })?;
+    let cmd = tokio::time::timeout(std::time::Duration::from_millis(1000), cmd_rx.recv())
+        .await
+        .expect("greeting after the callee's pause")?;
+    match cmd {
+        Command::Tts { text, .. } => assert_eq!(text, "Hi, this is the clinic"),
+        _ => panic!("Expected TTS command, got {:?}", cmd),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_greeting_gate_times_out_without_speech() -> Result<()> {
+    let (_active_call, mut cmd_rx, _) = answered_gated_call(
+        "test-greeting-gate-timeout",
+        GreetingGateConfig {
+            silence_ms: Some(100),
+            max_wait_ms: Some(400),
+        },
+    )
+    .await?;
+
+    let held = tokio::time::timeout(std::time::Duration::from_millis(200), cmd_rx.recv()).await;
+    assert!(held.is_err(), "greeting must wait up to maxWaitMs");
+    let cmd = tokio::time::timeout(std::time::Duration::from_millis(1000), cmd_rx.recv())
+        .await
+        .expect("greeting after maxWaitMs")?;
+    assert!(matches!(cmd, Command::Tts { .. }));
+    Ok(())
+}
+
+/// Keys pressed while the greeting is held still reach the handler
+#[tokio::test]
+async fn test_greeting_gate_keeps_dtmf() -> Result<()> {
+    let (active_call, mut cmd_rx, events) = answered_gated_call(
+        "test-greeting-gate-dtmf",
+        GreetingGateConfig {
+            silence_ms: Some(100),
+            max_wait_ms: Some(400),
+        },
+    )
+    .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    active_call.event_sender.send(SessionEvent::Dtmf {
+        track_id: "test-greeting-gate-dtmf".to_string(),
+        timestamp: 100,
+        digit: "1".to_string(),
+    })?;
+    let cmd = tokio::time::timeout(std::time::Duration::from_millis(1000), cmd_rx.recv())
+        .await
+        .expect("greeting after maxWaitMs")?;
+    assert!(matches!(cmd, Command::Tts { .. }));
+
+    let handled = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        loop {
+            let dtmf = events
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|e| matches!(e, SessionEvent::Dtmf { digit, .. } if digit == "1"));
+            if dtmf {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+    assert!(handled.is_ok(), "dtmf during the greeting gate was dropped");
+    Ok(())
+}
+
+/// Streams `response` to the caller and answers non-streamed requests, the
+/// post-call summary, with `summary`, recording what they were sent
+struct SummaryProvider {
+    response: String,
+    summary: String,
+    summary_requests: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+#[async_trait]
+impl LlmProvider for SummaryProvider {
+    async fn call(&self, _config: &LlmConfig, history: &[ChatMessage]) -> Result<String> {
+        self.summary_requests.lock().unwrap().push(history.to_vec());
+        Ok(self.summary.clone())
+    }
+
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<std::pin::Pin<Box<dyn futures::Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let response = self.response.clone();
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content(response));
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[tokio::test]
+async fn test_post_call_summary_saved_in_call_record() -> Result<()> {
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .build()
+        .await?;
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        CancellationToken::new(),
+        "test-post-call-summary".to_string(),
+        app_state.invitation.clone(),
+        app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
+    let mut cmd_rx = active_call.new_receiver().cmd_receiver;
+
+    let provider = Arc::new(SummaryProvider {
+        response: "Your order is cancelled.".to_string(),
+        summary: r#"{"disposition": "cancelled", "actionItems": ["refund the order"]}"#.to_string(),
+        summary_requests: Mutex::new(Vec::new()),
+    });
+    let llm_handler = LlmHandler::with_provider(
+        LlmConfig {
+            greeting: Some("Hello".to_string()),
+            ..Default::default()
+        },
+        provider.clone(),
+        Arc::new(NoopRag),
+        active_call::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let playbook_config = PlaybookConfig {
+        post_call_summary: Some(PostCallSummaryConfig {
+            prompt: Some("Give the disposition as JSON".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let runner =
+        PlaybookRunner::with_handler(Box::new(llm_handler), active_call.clone(), playbook_config);
+    let join_handle = tokio::spawn(runner.run());
+
+    active_call.event_sender.send(SessionEvent::Answer {
+        track_id: "track1".to_string(),
+        timestamp: 0,
+        sdp: "".to_string(),
+        refer: None,
+    })?;
+    assert!(matches!(cmd_rx.recv().await, Ok(Command::Tts { .. })));
+
+    active_call.event_sender.send(SessionEvent::AsrFinal {
+        track_id: "track1".to_string(),
+        timestamp: 100,
+        index: 1,
+        start_time: None,
+        end_time: None,
+        text: "Please cancel my order".to_string(),
+        is_filler: None,
+        confidence: None,
+        task_id: None,
+        raw_text: None,
+    })?;
+    assert!(matches!(cmd_rx.recv().await, Ok(Command::Tts { .. })));
+
+    active_call.event_sender.send(SessionEvent::Hangup {
+        track_id: "track1".to_string(),
+        timestamp: 200,
+        reason: None,
+        initiator: None,
+        start_time: "".to_string(),
+        hangup_time: "".to_string(),
+        answer_time: None,
+        ringing_time: None,
+        from: None,
+        to: None,
+        extra: None,
+        refer: None,
+    })?;
+    join_handle.await?;
+
+    // The summary is written by a task spawned once the runner stops
+    let summary = tokio::time::timeout(std::time::Duration::from_secs(2), async {
+        loop {
+            let extras = active_call.call_state.read().await.extras.clone();
+            if let Some(summary) = extras.and_then(|e| e.get("post_call_summary").cloned()) {
+                return summary;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    assert_eq!(summary["disposition"], "cancelled");
+    assert_eq!(summary["actionItems"][0], "refund the order");
+
+    let record = active_call.get_callrecord().expect("call record");
+    let extras = record.extras.unwrap_or_default();
+    assert_eq!(extras.get("post_call_summary").cloned(), Some(summary));
+
+    // The summary was asked for over the call transcript
+    let requests = provider.summary_requests.lock().unwrap();
+    let request = requests.last().expect("summary request");
+
+    // Its tokens are counted in the call's usage, which without them would
+    // be below what the summary request alone takes
+    let summary_usage = LlmUsage::estimate(request, r#"{"disposition": "cancelled"}"#);
+    let usage = extras.get("llm_usage").expect("llm usage");
+    assert!(
+        usage["prompt_tokens"].as_u64().unwrap_or(0) >= summary_usage.prompt_tokens,
+        "{}",
+        usage
+    );
+    assert!(
+        request
+            .iter()
+            .any(|m| m.role == "user" && m.content == "Please cancel my order")
+    );
+    assert!(
+        request
+            .iter()
+            .any(|m| m.role == "assistant" && m.content == "Your order is cancelled.")
+    );
+    assert_eq!(
+        request.last().map(|m| m.content.as_str()),
+        Some("Give the disposition as JSON")
+    );
+    Ok(())
+}
+
+/// Streams `response` to the caller and never answers a non-streamed request
+struct StalledSummaryProvider;
+
+#[async_trait]
+impl LlmProvider for StalledSummaryProvider {
+    async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+        futures::future::pending().await
+    }
+
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<std::pin::Pin<Box<dyn futures::Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content("Goodbye.".to_string()));
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[tokio::test]
+async fn test_post_call_summary_gives_up_after_timeout() -> Result<()> {
+    let (posthook_tx, mut posthook_rx) = tokio::sync::mpsc::unbounded_channel();
+    let router = axum::Router::new().route(
+        "/posthook",
+        axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let posthook_tx = posthook_tx.clone();
+            async move {
+                posthook_tx.send(body).ok();
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let posthook_url = format!("http://{}/posthook", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.ok();
+    });
+
+    let mut config = Config::default();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::new()))
+        .build()
+        .await?;
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::Sip,
+        CancellationToken::new(),
+        "test-post-call-summary-timeout".to_string(),
+        app_state.invitation.clone(),
+        app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+        None,
+    ));
+    let mut cmd_rx = active_call.new_receiver().cmd_receiver;
+
+    let llm_handler = LlmHandler::with_provider(
+        LlmConfig {
+            greeting: Some("Hello".to_string()),
+            ..Default::default()
+        },
+        Arc::new(StalledSummaryProvider),
+        Arc::new(NoopRag),
+        active_call::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let playbook_config = PlaybookConfig {
+        post_call_summary: Some(PostCallSummaryConfig {
+            timeout_ms: Some(100),
+            ..Default::default()
+        }),
+        posthook: Some(PostHookConfig {
+            url: posthook_url,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let runner =
+        PlaybookRunner::with_handler(Box::new(llm_handler), active_call.clone(), playbook_config);
+    let join_handle = tokio::spawn(runner.run());
+
+    active_call.event_sender.send(SessionEvent::Answer {
+        track_id: "track1".to_string(),
+        timestamp: 0,
+        sdp: "".to_string(),
+        refer: None,
+    })?;
+    assert!(matches!(cmd_rx.recv().await, Ok(Command::Tts { .. })));
+
+    active_call.event_sender.send(SessionEvent::Hangup {
+        track_id: "track1".to_string(),
+        timestamp: 100,
+        reason: None,
+        initiator: None,
+        start_time: "".to_string(),
+        hangup_time: "".to_string(),
+        answer_time: None,
+        ringing_time: None,
+        from: None,
+        to: None,
+        extra: None,
+        refer: None,
+    })?;
+    join_handle.await?;
+
+    // The stalled summary is dropped and the posthook after it still goes out
+    let posthook = tokio::time::timeout(std::time::Duration::from_secs(2), posthook_rx.recv())
+        .await?
+        .expect("posthook sent");
+    assert_eq!(posthook["sessionId"], "test-post-call-summary-timeout");
+    let extras = active_call.call_state.read().await.extras.clone();
+    assert!(
+        extras
+            .unwrap_or_default()
+            .get("post_call_summary")
+            .is_none()
+    );
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::callrecord::CallRecord;
+use active_call::config::{CallRecordConfig, Config, RecordingPolicy};
+use active_call::event::SessionEvent;
+use active_call::media::recorder::RecorderOption;
+use active_call::{CallOption, ConsentMode, RecordingConsentOption};
+use anyhow::Result;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Run a recorded websocket call fed with audio, pressing `digit` after the
+/// answer, and return its call record
+async fn run_call(
+    session_id: &str,
+    consent: Option<RecordingConsentOption>,
+    digit: Option<&str>,
+) -> Result<CallRecord> {
+    let dir = tempfile::tempdir()?;
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.recording = Some(RecordingPolicy {
+        path: Some(dir.path().join("recorders").to_string_lossy().to_string()),
+        ..Default::default()
+    });
+    config.callrecord = Some(CallRecordConfig::Local {
+        root: dir.path().join("cdr").to_string_lossy().to_string(),
+        compress: None,
+    });
+    let (record_tx, mut record_rx) = mpsc::unbounded_channel();
+    let app_state = AppStateBuilder::new()
+        .with_config(config)
+        .on_cdr_saved(move |record| {
+            let record_tx = record_tx.clone();
+            async move {
+                record_tx.send(record).ok();
+            }
+        })
+        .build()
+        .await?;
+
+    let cancel_token = CancellationToken::new();
+    let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+        ActiveCallType::WebSocket,
+        session_id.to_string(),
+        app_state.clone(),
+        cancel_token.clone(),
+        audio_rx,
+        None,
+        false,
+        0,
+        command_rx,
+        event_tx,
+    ));
+    command_tx.send(Command::Invite {
+        option: CallOption {
+            recorder: Some(RecorderOption::default()),
+            recording_consent: consent,
+            ..Default::default()
+        },
+    })?;
+
+    // 20ms of 16k PCM per chunk
+    let pump_token = cancel_token.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(20));
+        while !pump_token.is_cancelled() {
+            interval.tick().await;
+            if audio_tx.send(Bytes::from(vec![1u8; 640])).is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = event_rx.recv().await {
+            if matches!(event, SessionEvent::Answer { .. }) {
+                break;
+            }
+        }
+    })
+    .await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    if let Some(digit) = digit {
+        let call = app_state
+            .active_calls
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .cloned()
+            .expect("call should be active");
+        call.event_sender
+            .send(SessionEvent::Dtmf {
+                track_id: session_id.to_string(),
+                timestamp: active_call::media::get_timestamp(),
+                digit: digit.to_string(),
+            })
+            .ok();
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    command_tx.send(Command::Hangup {
+        reason: None,
+        initiator: None,
+        headers: None,
+    })?;
+    tokio::time::timeout(Duration::from_secs(5), handler).await??;
+    let record = tokio::time::timeout(Duration::from_secs(5), record_rx.recv())
+        .await?
+        .expect("call record should be saved");
+    Ok(record)
+}
+
+fn consent(mode: ConsentMode) -> Option<RecordingConsentOption> {
+    Some(RecordingConsentOption {
+        consent_mode: mode,
+        ..Default::default()
+    })
+}
+
+fn consent_outcome(record: &CallRecord) -> (String, String) {
+    let consent = &record.extras.as_ref().expect("extras")["recordingConsent"];
+    (
+        consent["consentMode"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        consent["outcome"].as_str().unwrap_or_default().to_string(),
+    )
+}
+
+/// `none` records from the start without asking
+#[tokio::test]
+async fn test_consent_none_records_immediately() -> Result<()> {
+    let record = run_call("test-consent-none", consent(ConsentMode::None), None).await?;
+    assert!(!record.recorder.is_empty(), "call should be recorded");
+    assert_eq!(
+        consent_outcome(&record),
+        ("none".to_string(), "not_required".to_string())
+    );
+    Ok(())
+}
+
+/// `announce` records once the notice has been played
+#[tokio::test]
+async fn test_consent_announce_records_after_notice() -> Result<()> {
+    let record = run_call(
+        "test-consent-announce",
+        consent(ConsentMode::Announce),
+        None,
+    )
+    .await?;
+    assert!(!record.recorder.is_empty(), "call should be recorded");
+    assert_eq!(
+        consent_outcome(&record),
+        ("announce".to_string(), "announced".to_string())
+    );
+    Ok(())
+}
+
+/// `explicit` records after the caller presses the accept digit
+#[tokio::test]
+async fn test_consent_explicit_granted_records() -> Result<()> {
+    let record = run_call(
+        "test-consent-granted",
+        consent(ConsentMode::Explicit),
+        Some("1"),
+    )
+    .await?;
+    assert!(!record.recorder.is_empty(), "call should be recorded");
+    assert_eq!(
+        consent_outcome(&record),
+        ("explicit".to_string(), "granted".to_string())
+    );
+    Ok(())
+}
+
+/// `explicit` never records when the caller declines
+#[tokio::test]
+async fn test_consent_explicit_declined_does_not_record() -> Result<()> {
+    let record = run_call(
+        "test-consent-declined",
+        consent(ConsentMode::Explicit),
+        Some("2"),
+    )
+    .await?;
+    assert!(record.recorder.is_empty(), "call should not be recorded");
+    assert_eq!(
+        consent_outcome(&record),
+        ("explicit".to_string(), "declined".to_string())
+    );
+    Ok(())
+}
+
+/// `explicit` without an answer in time counts as declined
+#[tokio::test]
+async fn test_consent_explicit_timeout_does_not_record() -> Result<()> {
+    let record = run_call(
+        "test-consent-timeout",
+        Some(RecordingConsentOption {
+            consent_mode: ConsentMode::Explicit,
+            timeout_secs: Some(0),
+            ..Default::default()
+        }),
+        None,
+    )
+    .await?;
+    assert!(record.recorder.is_empty(), "call should not be recorded");
+    assert_eq!(
+        consent_outcome(&record),
+        ("explicit".to_string(), "timed_out".to_string())
+    );
+    Ok(())
+}
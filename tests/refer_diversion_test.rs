@@ -0,0 +1,229 @@
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use active_call::{CallOption, DiversionHeader, DiversionOption, ReferOption};
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const ANSWER_SDP: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 41000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n";
+
+fn headers<'a>(message: &'a str, name: &str) -> Vec<&'a str> {
+    let prefix = format!("{}:", name.to_ascii_lowercase());
+    message
+        .lines()
+        .filter(|l| l.to_ascii_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+/// Build a response to `request` echoing the transaction headers
+fn response(request: &str, status: &str, contact: &str, body: &str) -> String {
+    let mut out = format!("SIP/2.0 {}\r\n", status);
+    for name in ["Via", "From", "Call-ID", "CSeq"] {
+        for line in headers(request, name) {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    for line in headers(request, "To") {
+        out.push_str(line);
+        if !line.contains(";tag=") {
+            out.push_str(";tag=trunk");
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("Contact: <{}>\r\n", contact));
+    if !body.is_empty() {
+        out.push_str("Content-Type: application/sdp\r\n");
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out
+}
+
+/// A bare UDP endpoint answering every INVITE and keeping them
+async fn run_trunk(socket: UdpSocket, invites: Arc<Mutex<Vec<String>>>) {
+    let contact = format!("sip:trunk@{}", socket.local_addr().unwrap());
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let reply = match message.split(' ').next().unwrap_or_default() {
+            "INVITE" => {
+                invites.lock().unwrap().push(message.clone());
+                response(&message, "200 OK", &contact, ANSWER_SDP)
+            }
+            "BYE" => response(&message, "200 OK", &contact, ""),
+            _ => continue,
+        };
+        socket.send_to(reply.as_bytes(), peer).await.ok();
+    }
+}
+
+/// Call `2000` on one trunk, transfer it to an agent on another and return
+/// the dialed number and the INVITE the agent received
+async fn transfer(
+    refer_diversion: Option<DiversionOption>,
+    diversion: Option<DiversionOption>,
+) -> Result<(String, String)> {
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    config.refer_diversion = refer_diversion;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let pstn = UdpSocket::bind("127.0.0.1:0").await?;
+    let agent = UdpSocket::bind("127.0.0.1:0").await?;
+    let dialed = format!("sip:2000@{}", pstn.local_addr()?);
+    let agent_target = format!("sip:agent@{}", agent.local_addr()?);
+    let agent_invites = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_trunk(pstn, Arc::new(Mutex::new(Vec::new()))));
+    tokio::spawn(run_trunk(agent, agent_invites.clone()));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-refer-diversion".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                callee: Some(dialed.clone()),
+                ..Default::default()
+            },
+        })?;
+
+        let answered = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    SessionEvent::Answer { .. } => return true,
+                    SessionEvent::Hangup { .. } | SessionEvent::Reject { .. } => return false,
+                    _ => {}
+                }
+            }
+            false
+        })
+        .await?;
+        assert!(answered, "call should be answered");
+
+        command_tx.send(Command::Refer {
+            caller: "sip:alice@127.0.0.1".to_string(),
+            callee: agent_target.clone(),
+            options: Some(ReferOption {
+                denoise: None,
+                timeout: Some(5),
+                moh: None,
+                asr: None,
+                auto_hangup: Some(false),
+                sip: None,
+                call_id: None,
+                pause_parent_asr: None,
+                diversion,
+            }),
+        })?;
+
+        for _ in 0..500 {
+            if !agent_invites.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    }
+
+    let invite = agent_invites
+        .lock()
+        .unwrap()
+        .first()
+        .cloned()
+        .expect("the agent should be invited");
+    Ok((dialed, invite))
+}
+
+/// The global `refer_diversion` applies to refers without options of their
+/// own, like the ones playbooks send.
+#[tokio::test]
+async fn test_transfer_invite_carries_diversion() -> Result<()> {
+    let (dialed, invite) = transfer(Some(DiversionOption::default()), None).await?;
+
+    let diversion = headers(&invite, "Diversion");
+    assert_eq!(
+        diversion,
+        vec![format!(
+            "Diversion: <{}>;reason=unconditional;counter=1",
+            dialed
+        )]
+    );
+    assert!(headers(&invite, "History-Info").is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_invite_carries_history_info() -> Result<()> {
+    let diversion = DiversionOption {
+        header: Some(DiversionHeader::HistoryInfo),
+        ..Default::default()
+    };
+    let (dialed, invite) = transfer(None, Some(diversion)).await?;
+
+    let history = headers(&invite, "History-Info");
+    assert_eq!(history.len(), 1, "{}", invite);
+    assert!(
+        history[0].contains(&format!("<{}>;index=1,", dialed)),
+        "{}",
+        history[0]
+    );
+    assert!(history[0].contains(";index=1.1;mp=1"), "{}", history[0]);
+    assert!(headers(&invite, "Diversion").is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_without_diversion_config() -> Result<()> {
+    let (_, invite) = transfer(None, None).await?;
+    assert!(headers(&invite, "Diversion").is_empty());
+    assert!(headers(&invite, "History-Info").is_empty());
+    Ok(())
+}
+
+/// A reason outside RFC 5806 is not written into the INVITE
+#[tokio::test]
+async fn test_transfer_rejects_invalid_diversion_reason() -> Result<()> {
+    let diversion = DiversionOption {
+        reason: Some("unconditional;counter=9>\r\nX-Injected: yes".to_string()),
+        ..Default::default()
+    };
+    let (_, invite) = transfer(None, Some(diversion)).await?;
+    assert!(headers(&invite, "Diversion").is_empty(), "{}", invite);
+    assert!(headers(&invite, "X-Injected").is_empty(), "{}", invite);
+    Ok(())
+}
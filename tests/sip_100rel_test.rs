@@ -0,0 +1,182 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+const EARLY_MEDIA_SDP: &str = "v=0\r\n\
+o=- 1 1 IN IP4 127.0.0.1\r\n\
+s=-\r\n\
+c=IN IP4 127.0.0.1\r\n\
+t=0 0\r\n\
+m=audio 40000 RTP/AVP 0\r\n\
+a=rtpmap:0 PCMU/8000\r\n\
+a=sendrecv\r\n";
+
+fn header_name(line: &str) -> String {
+    line.split(':')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Build a response to `request` echoing the transaction headers, tagging the
+/// To header unless the request is already in the dialog
+fn response(request: &str, status: &str, headers: &[String], body: &str) -> String {
+    let mut out = format!("SIP/2.0 {}\r\n", status);
+    for line in request.lines() {
+        let name = header_name(line);
+        if matches!(name.as_str(), "via" | "from" | "call-id" | "cseq") {
+            out.push_str(line);
+            out.push_str("\r\n");
+        } else if name == "to" {
+            out.push_str(line);
+            if !line.contains("tag=") {
+                out.push_str(";tag=uas");
+            }
+            out.push_str("\r\n");
+        }
+    }
+    for header in headers {
+        out.push_str(header);
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out
+}
+
+/// A callee that answers the first INVITE with a reliable 183 carrying SDP,
+/// then rejects the call once the 183 has been PRACKed, handing the INVITE
+/// and the PRACK to the test
+async fn run_callee(socket: UdpSocket, received: oneshot::Sender<(String, String)>) {
+    let contact = format!("Contact: <sip:uas@{}>", socket.local_addr().unwrap());
+    let mut received = Some(received);
+    let mut invite: Option<String> = None;
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        if message.starts_with("INVITE ") && invite.is_none() {
+            let progress = response(
+                &message,
+                "183 Session Progress",
+                &[
+                    contact.clone(),
+                    "Require: 100rel".to_string(),
+                    "RSeq: 1".to_string(),
+                    "Content-Type: application/sdp".to_string(),
+                ],
+                EARLY_MEDIA_SDP,
+            );
+            socket.send_to(progress.as_bytes(), peer).await.ok();
+            invite = Some(message);
+        } else if message.starts_with("PRACK ") {
+            let ok = response(&message, "200 OK", &[], "");
+            socket.send_to(ok.as_bytes(), peer).await.ok();
+            let Some(invite) = invite.as_ref() else {
+                continue;
+            };
+            let busy = response(invite, "486 Busy Here", &[], "");
+            socket.send_to(busy.as_bytes(), peer).await.ok();
+            if let Some(received) = received.take() {
+                received.send((invite.clone(), message)).ok();
+            }
+        }
+    }
+}
+
+fn header_value<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message
+        .lines()
+        .find(|line| header_name(line) == name)
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, value)| value.trim())
+}
+
+/// With `enable_100rel` the INVITE offers 100rel, the reliable 183 is PRACKed
+/// and its SDP starts early media
+#[tokio::test]
+async fn test_reliable_183_is_pracked() -> Result<()> {
+    let callee = UdpSocket::bind("127.0.0.1:0").await?;
+    let callee_addr = callee.local_addr()?;
+
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    config.enable_100rel = Some(true);
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+    let (received_tx, received_rx) = oneshot::channel();
+    tokio::spawn(run_callee(callee, received_tx));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-100rel".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                callee: Some(format!("sip:bob@{}", callee_addr)),
+                ..Default::default()
+            },
+        })?;
+
+        let early_media = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = event_rx.recv().await {
+                if let SessionEvent::Ringing { early_media, .. } = event {
+                    return early_media;
+                }
+            }
+            false
+        })
+        .await?;
+        let (invite, prack) = tokio::time::timeout(Duration::from_secs(10), received_rx).await??;
+
+        cancel_token.cancel();
+        tokio::time::timeout(Duration::from_secs(5), handler)
+            .await
+            .ok();
+        Ok::<_, anyhow::Error>((early_media, invite, prack))
+    };
+
+    let (early_media, invite, prack) = tokio::select! {
+        _ = app_state_run.serve() => Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res,
+    }?;
+
+    assert!(early_media, "183 SDP should start early media");
+    assert!(
+        header_value(&invite, "supported").is_some_and(|v| v.contains("100rel")),
+        "invite: {}",
+        invite
+    );
+    let invite_cseq = header_value(&invite, "cseq")
+        .and_then(|v| v.split_whitespace().next())
+        .unwrap_or_default();
+    assert_eq!(
+        header_value(&prack, "rack"),
+        Some(format!("1 {} INVITE", invite_cseq).as_str()),
+        "prack: {}",
+        prack
+    );
+    Ok(())
+}
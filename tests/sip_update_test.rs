@@ -0,0 +1,360 @@
+use active_call::CallOption;
+use active_call::app::AppStateBuilder;
+use active_call::call::{ActiveCallType, Command};
+use active_call::config::Config;
+use active_call::event::SessionEvent;
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+const ANSWER_SDP: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 41000 RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=sendrecv\r\n";
+
+fn headers<'a>(message: &'a str, name: &str) -> Vec<&'a str> {
+    let prefix = format!("{}:", name.to_ascii_lowercase());
+    message
+        .lines()
+        .filter(|l| l.to_ascii_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+/// Build a response to `request` echoing the transaction headers
+fn response(request: &str, status: &str, contact: &str, allow: Option<&str>, body: &str) -> String {
+    let mut out = format!("SIP/2.0 {}\r\n", status);
+    for name in ["Via", "From", "Call-ID", "CSeq"] {
+        for line in headers(request, name) {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    for line in headers(request, "To") {
+        out.push_str(line);
+        if !line.contains(";tag=") {
+            out.push_str(";tag=trunk");
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("Contact: <{}>\r\n", contact));
+    if let Some(allow) = allow {
+        out.push_str(&format!("Allow: {}\r\n", allow));
+    }
+    if !body.is_empty() {
+        out.push_str("Content-Type: application/sdp\r\n");
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out
+}
+
+/// Value of the first `name` header of `message`
+fn header_value(message: &str, name: &str) -> String {
+    headers(message, name)
+        .first()
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, value)| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// The trunk's own hold offer, sent in the dialog of our re-INVITE `request`
+/// so the two cross
+fn crossing_invite(request: &str, local: SocketAddr) -> String {
+    let contact = header_value(request, "Contact");
+    let uri = contact
+        .trim_start_matches('<')
+        .split(['>', ';'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let body = ANSWER_SDP.replace("a=sendrecv", "a=sendonly");
+    format!(
+        "INVITE {uri} SIP/2.0\r\nVia: SIP/2.0/UDP {local};branch=z9hG4bKglare\r\nMax-Forwards: 70\r\nFrom: {}\r\nTo: {}\r\nCall-ID: {}\r\nCSeq: 1 INVITE\r\nContact: <sip:trunk@{local}>\r\nContent-Type: application/sdp\r\nContent-Length: {}\r\n\r\n{body}",
+        header_value(request, "To"),
+        header_value(request, "From"),
+        header_value(request, "Call-ID"),
+        body.len(),
+    )
+}
+
+/// ACK for the final non-2xx `response` to our crossing `invite`
+fn ack(invite: &str, response: &str) -> String {
+    let request_line = invite.lines().next().unwrap_or_default();
+    format!(
+        "{}\r\n{}\r\nMax-Forwards: 70\r\n{}\r\n{}\r\n{}\r\nCSeq: 1 ACK\r\nContent-Length: 0\r\n\r\n",
+        request_line.replacen("INVITE", "ACK", 1),
+        headers(invite, "Via")[0],
+        headers(invite, "From")[0],
+        headers(response, "To")[0],
+        headers(invite, "Call-ID")[0],
+    )
+}
+
+/// A bare UDP endpoint answering every offer, recording each request's method
+/// and body, and the status line of each response it gets. With `glare` it
+/// crosses our hold offer with one of its own and refuses ours with 491 once
+/// we answered its offer.
+async fn run_trunk(
+    socket: UdpSocket,
+    allow: Option<&str>,
+    glare: bool,
+    requests: Arc<Mutex<Vec<(String, String)>>>,
+) {
+    let local = socket.local_addr().unwrap();
+    let contact = format!("sip:trunk@{}", local);
+    let mut crossing: Option<(String, String)> = None;
+    let mut buf = vec![0u8; 8192];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let mut method = message.split(' ').next().unwrap_or_default().to_string();
+        if method == "SIP/2.0" {
+            method = message.lines().next().unwrap_or_default().to_string();
+        }
+        let body = message
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        requests
+            .lock()
+            .unwrap()
+            .push((method.clone(), body.clone()));
+        let reply = match method.as_str() {
+            "INVITE" | "UPDATE" if glare && crossing.is_none() && body.contains("a=sendonly") => {
+                let invite = crossing_invite(&message, local);
+                socket.send_to(invite.as_bytes(), peer).await.ok();
+                let refusal = response(&message, "491 Request Pending", &contact, allow, "");
+                crossing = Some((invite, refusal));
+                continue;
+            }
+            "INVITE" | "UPDATE" => {
+                let answer = if body.contains("a=sendonly") {
+                    ANSWER_SDP.replace("a=sendrecv", "a=recvonly")
+                } else {
+                    ANSWER_SDP.to_string()
+                };
+                response(&message, "200 OK", &contact, allow, &answer)
+            }
+            "BYE" => response(&message, "200 OK", &contact, None, ""),
+            status if status.starts_with("SIP/2.0 1") => continue,
+            status if status.starts_with("SIP/2.0") => {
+                let Some((invite, refusal)) = &crossing else {
+                    continue;
+                };
+                socket
+                    .send_to(ack(invite, &message).as_bytes(), peer)
+                    .await
+                    .ok();
+                refusal.clone()
+            }
+            _ => continue,
+        };
+        socket.send_to(reply.as_bytes(), peer).await.ok();
+    }
+}
+
+/// The session version of an SDP's `o=` line
+fn origin_version(sdp: &str) -> u64 {
+    sdp.lines()
+        .find_map(|line| line.strip_prefix("o="))
+        .and_then(|origin| origin.split(' ').nth(2))
+        .and_then(|version| version.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Place a call to a trunk, hold and resume it, and return what the trunk
+/// received. With `renegotiate` the media is offered again before the hold
+async fn hold_and_resume(
+    allow: Option<&'static str>,
+    glare: bool,
+    renegotiate: bool,
+) -> Result<Vec<(String, String)>> {
+    let mut config = Config::default();
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = 0;
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let trunk = UdpSocket::bind("127.0.0.1:0").await?;
+    let target = format!("sip:bob@{}", trunk.local_addr()?);
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(run_trunk(trunk, allow, glare, requests.clone()));
+
+    let app_state_run = app_state.clone();
+    let test_logic = async {
+        let cancel_token = CancellationToken::new();
+        let (_audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+
+        let handler = tokio::spawn(active_call::handler::handler::call_handler_core(
+            ActiveCallType::Sip,
+            "test-sip-hold".to_string(),
+            app_state.clone(),
+            cancel_token.clone(),
+            audio_rx,
+            None,
+            false,
+            0,
+            command_rx,
+            event_tx,
+        ));
+
+        command_tx.send(Command::Invite {
+            option: CallOption {
+                caller: Some("sip:alice@127.0.0.1".to_string()),
+                callee: Some(target),
+                ..Default::default()
+            },
+        })?;
+
+        let mut holds = Vec::new();
+        tokio::time::timeout(Duration::from_secs(15), async {
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    SessionEvent::Answer { .. } => {
+                        if renegotiate {
+                            command_tx.send(Command::Renegotiate {}).ok();
+                        }
+                        command_tx.send(Command::Hold {}).ok()
+                    }
+                    SessionEvent::Hold { on_hold, .. } => {
+                        holds.push(on_hold);
+                        if !on_hold {
+                            break;
+                        }
+                        command_tx.send(Command::Unhold {}).ok()
+                    }
+                    SessionEvent::Hangup { .. } | SessionEvent::Reject { .. } => break,
+                    _ => None,
+                };
+            }
+        })
+        .await?;
+        assert_eq!(holds, vec![true, false]);
+
+        command_tx.send(Command::Hangup {
+            reason: None,
+            initiator: None,
+            headers: None,
+        })?;
+        tokio::time::timeout(Duration::from_secs(5), handler).await??;
+        Ok::<(), anyhow::Error>(())
+    };
+
+    tokio::select! {
+        _ = app_state_run.serve() => return Err(anyhow::anyhow!("app state stopped unexpectedly")),
+        res = test_logic => res?,
+    }
+    let requests = requests.lock().unwrap().clone();
+    Ok(requests)
+}
+
+/// A peer advertising `Allow: UPDATE` is put on hold and resumed with UPDATE,
+/// without answering a second INVITE.
+#[tokio::test]
+async fn test_hold_uses_update_when_allowed() -> Result<()> {
+    let requests = hold_and_resume(
+        Some("INVITE, ACK, BYE, CANCEL, OPTIONS, UPDATE"),
+        false,
+        false,
+    )
+    .await?;
+
+    let updates: Vec<&String> = requests
+        .iter()
+        .filter(|(method, _)| method == "UPDATE")
+        .map(|(_, body)| body)
+        .collect();
+    assert_eq!(updates.len(), 2, "requests: {:?}", requests);
+    assert!(updates[0].contains("a=sendonly"));
+    assert!(updates[1].contains("a=sendrecv"));
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|(method, _)| method == "INVITE")
+            .count(),
+        1
+    );
+    Ok(())
+}
+
+/// Without `Allow: UPDATE` the hold offer falls back to a re-INVITE.
+#[tokio::test]
+async fn test_hold_falls_back_to_reinvite() -> Result<()> {
+    let requests = hold_and_resume(None, false, false).await?;
+
+    let invites: Vec<&String> = requests
+        .iter()
+        .filter(|(method, _)| method == "INVITE")
+        .map(|(_, body)| body)
+        .collect();
+    assert_eq!(invites.len(), 3, "requests: {:?}", requests);
+    assert!(invites[1].contains("a=sendonly"));
+    assert!(invites[2].contains("a=sendrecv"));
+    assert!(!requests.iter().any(|(method, _)| method == "UPDATE"));
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|(method, _)| method == "ACK")
+            .count(),
+        3
+    );
+    Ok(())
+}
+
+/// When the trunk's hold offer crosses ours, we refuse its offer with 491,
+/// back off after its 491 to ours and retry with a fresh offer, and the hold
+/// still goes through.
+#[tokio::test]
+async fn test_hold_retries_after_reinvite_glare() -> Result<()> {
+    let requests = hold_and_resume(None, true, false).await?;
+
+    assert!(
+        requests
+            .iter()
+            .any(|(status, _)| status.starts_with("SIP/2.0 491")),
+        "requests: {:?}",
+        requests
+    );
+    let invites: Vec<&String> = requests
+        .iter()
+        .filter(|(method, _)| method == "INVITE")
+        .map(|(_, body)| body)
+        .collect();
+    assert_eq!(invites.len(), 4, "requests: {:?}", requests);
+    assert!(invites[1].contains("a=sendonly"));
+    // The retry is a new offer, with a higher origin version
+    assert!(invites[2].contains("a=sendonly"));
+    assert!(origin_version(invites[2]) > origin_version(invites[1]));
+    assert!(origin_version(invites[3]) > origin_version(invites[2]));
+    assert!(invites[3].contains("a=sendrecv"));
+    Ok(())
+}
+
+/// Renegotiating offers the unchanged media as a new SDP version, through the
+/// same UPDATE path as hold.
+#[tokio::test]
+async fn test_renegotiate_offers_new_version() -> Result<()> {
+    let requests = hold_and_resume(
+        Some("INVITE, ACK, BYE, CANCEL, OPTIONS, UPDATE"),
+        false,
+        true,
+    )
+    .await?;
+
+    let invite = requests
+        .iter()
+        .find(|(method, _)| method == "INVITE")
+        .map(|(_, body)| body)
+        .expect("initial invite");
+    let updates: Vec<&String> = requests
+        .iter()
+        .filter(|(method, _)| method == "UPDATE")
+        .map(|(_, body)| body)
+        .collect();
+    assert_eq!(updates.len(), 3, "requests: {:?}", requests);
+    assert!(!updates[0].contains("a=sendonly"));
+    assert!(origin_version(updates[0]) > origin_version(invite));
+    assert!(updates[1].contains("a=sendonly"));
+    assert!(origin_version(updates[1]) > origin_version(updates[0]));
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use active_call::event::{EventSender, SessionEvent, create_event_sender};
+use active_call::media::engine::StreamEngine;
+use active_call::media::{Sample, SourcePacket, TrackId};
+use active_call::playbook::{
+    ChatMessage, DialogueHandler, DialogueTestHarness, InterruptionConfig, LlmConfig,
+    handler::{LlmHandler, LlmProvider, LlmStreamEvent, RagRetriever},
+};
+use active_call::transcription::{
+    TranscriptionClient, TranscriptionOption, TranscriptionType, format::final_text,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Returns the same unpunctuated lowercase final for every chunk of audio
+struct LowercaseAsrClient {
+    track_id: TrackId,
+    option: TranscriptionOption,
+    event_sender: EventSender,
+}
+
+#[async_trait]
+impl TranscriptionClient for LowercaseAsrClient {
+    fn send_audio(&self, _samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        let now = active_call::media::get_timestamp();
+        let (text, raw_text) = final_text(&self.option, "where is my order".to_string());
+        self.event_sender
+            .send(SessionEvent::AsrFinal {
+                track_id: self.track_id.clone(),
+                index: 0,
+                text,
+                timestamp: now,
+                start_time: Some(now),
+                end_time: Some(now),
+                is_filler: None,
+                confidence: None,
+                task_id: None,
+                raw_text,
+            })
+            .ok();
+        Ok(())
+    }
+}
+
+/// Transcribe one chunk of audio, returning the final that reaches the session
+async fn transcribe(format_transcripts: Option<bool>) -> Result<SessionEvent> {
+    transcribe_with(TranscriptionOption {
+        provider: Some(TranscriptionType::Other("lowercase".to_string())),
+        format_transcripts,
+        ..Default::default()
+    })
+    .await
+}
+
+async fn transcribe_with(option: TranscriptionOption) -> Result<SessionEvent> {
+    let mut engine = StreamEngine::new();
+    engine.register_asr(
+        TranscriptionType::Other("lowercase".to_string()),
+        Box::new(
+            |track_id: TrackId,
+             _token: CancellationToken,
+             option: TranscriptionOption,
+             event_sender: EventSender| {
+                Box::pin(async move {
+                    Ok(Box::new(LowercaseAsrClient {
+                        track_id,
+                        option,
+                        event_sender,
+                    }) as Box<dyn TranscriptionClient>)
+                })
+            },
+        ),
+    );
+
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let cancel_token = CancellationToken::new();
+    let client = engine
+        .create_asr_client(
+            "caller".to_string(),
+            cancel_token.clone(),
+            option,
+            event_sender,
+        )
+        .await?;
+    client.send_audio(&[0; 320], None)?;
+
+    let event = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            match event_receiver.recv().await? {
+                event @ SessionEvent::AsrFinal { .. } => return Ok::<_, anyhow::Error>(event),
+                _ => continue,
+            }
+        }
+    })
+    .await??;
+    cancel_token.cancel();
+    Ok(event)
+}
+
+struct OkProvider;
+
+#[async_trait]
+impl LlmProvider for OkProvider {
+    async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+        Ok("Let me check.".to_string())
+    }
+
+    async fn call_stream(
+        &self,
+        _config: &LlmConfig,
+        _history: &[ChatMessage],
+    ) -> Result<std::pin::Pin<Box<dyn futures::Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+        let s = async_stream::stream! {
+            yield Ok(LlmStreamEvent::Content("Let me check.".to_string()));
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+struct NoopRag;
+
+#[async_trait]
+impl RagRetriever for NoopRag {
+    async fn retrieve(&self, _query: &str) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+/// With `formatTranscripts` the final is punctuated before the LLM sees it,
+/// and the provider's text is kept in `rawText`
+#[tokio::test]
+async fn test_formatted_transcript_reaches_llm_history() -> Result<()> {
+    let event = transcribe(Some(true)).await?;
+    let SessionEvent::AsrFinal { text, raw_text, .. } = &event else {
+        panic!("expected asrFinal, got {:?}", event);
+    };
+    assert_eq!(text, "Where is my order?");
+    assert_eq!(raw_text.as_deref(), Some("where is my order"));
+
+    let handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        Arc::new(OkProvider),
+        Arc::new(NoopRag),
+        InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let mut harness = DialogueTestHarness::new(handler);
+    harness.send(event).await?;
+
+    let history = harness.handler().get_history().await;
+    assert!(
+        history
+            .iter()
+            .any(|m| m.role == "user" && m.content == "Where is my order?"),
+        "history: {:?}",
+        history
+    );
+    Ok(())
+}
+
+/// Without the option the transcript is passed on untouched
+#[tokio::test]
+async fn test_transcript_unformatted_by_default() -> Result<()> {
+    let event = transcribe(None).await?;
+    let SessionEvent::AsrFinal { text, raw_text, .. } = &event else {
+        panic!("expected asrFinal, got {:?}", event);
+    };
+    assert_eq!(text, "where is my order");
+    assert_eq!(*raw_text, None);
+    Ok(())
+}
+
+/// A fallback recognizer formats like the one it stands in for
+#[tokio::test]
+async fn test_fallback_transcript_formatted() -> Result<()> {
+    let event = transcribe_with(TranscriptionOption {
+        provider: Some(TranscriptionType::Other("missing".to_string())),
+        format_transcripts: Some(true),
+        fallback: Some(Box::new(TranscriptionOption {
+            provider: Some(TranscriptionType::Other("lowercase".to_string())),
+            ..Default::default()
+        })),
+        ..Default::default()
+    })
+    .await?;
+    let SessionEvent::AsrFinal { text, raw_text, .. } = &event else {
+        panic!("expected asrFinal, got {:?}", event);
+    };
+    assert_eq!(text, "Where is my order?");
+    assert_eq!(raw_text.as_deref(), Some("where is my order"));
+    Ok(())
+}
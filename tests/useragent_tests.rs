@@ -596,3 +596,93 @@ async fn test_webhook_unavailable_immediate_reject() -> Result<()> {
     bob_token.cancel();
     test_result
 }
+
+#[tokio::test]
+async fn test_outgoing_call_uses_configured_caller_id() -> Result<()> {
+    let alice_ua_arc = create_simple_useragent("127.0.0.1".to_string()).await?;
+    let alice_token = alice_ua_arc.token.clone();
+    let alice_ua_run = alice_ua_arc.clone();
+
+    // A bare UDP socket stands in for the trunk so the raw INVITE can be inspected
+    let trunk = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let trunk_addr = trunk.local_addr()?;
+
+    let test_logic = async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let option = active_call::CallOption {
+            callee: Some(format!("sip:bob@{}", trunk_addr)),
+            sip: Some(active_call::SipOption {
+                from: Some("+15551234567@campaign.example.com".to_string()),
+                p_asserted_identity: Some("sip:+15551234567@campaign.example.com".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let invite_option = option.build_invite_option()?;
+
+        let invitation = alice_ua_arc.invitation.clone();
+        tokio::spawn(async move {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            invitation.invite(invite_option, tx).await.ok();
+        });
+
+        let mut buf = vec![0u8; 4096];
+        let (n, _) =
+            tokio::time::timeout(Duration::from_secs(5), trunk.recv_from(&mut buf)).await??;
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        assert!(
+            message.starts_with("INVITE "),
+            "unexpected message: {}",
+            message
+        );
+
+        let header = |name: &str| {
+            message
+                .lines()
+                .find(|l| {
+                    l.to_ascii_lowercase()
+                        .starts_with(&format!("{}:", name.to_ascii_lowercase()))
+                })
+                .map(|l| l.to_string())
+        };
+        let from = header("From").expect("From header");
+        assert!(
+            from.contains("sip:+15551234567@campaign.example.com"),
+            "From header: {}",
+            from
+        );
+        let pai = header("P-Asserted-Identity").expect("P-Asserted-Identity header");
+        assert!(
+            pai.contains("<sip:+15551234567@campaign.example.com>"),
+            "{}",
+            pai
+        );
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let test_result = tokio::select! {
+        _ = alice_ua_run.serve() => Err(anyhow::anyhow!("Alice stopped unexpectedly")),
+        res = test_logic => res,
+    };
+
+    alice_token.cancel();
+    test_result
+}
+
+#[test]
+fn test_invalid_caller_id_is_rejected() {
+    let option = active_call::CallOption {
+        callee: Some("sip:bob@127.0.0.1".to_string()),
+        sip: Some(active_call::SipOption {
+            from: Some("sip:@".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert!(option.build_invite_option().is_err());
+
+    let sip: active_call::SipOption =
+        serde_json::from_str(r#"{"caller_id": "sip:1000@example.com"}"#).unwrap();
+    assert_eq!(sip.from.as_deref(), Some("sip:1000@example.com"));
+}